@@ -0,0 +1,128 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn default_poll_interval() -> u64 {
+    10
+}
+
+fn default_settle_time() -> u64 {
+    60
+}
+
+fn default_control_socket() -> String {
+    crate::process::DEFAULT_CONTROL_SOCKET.to_owned()
+}
+
+/// When to capture; every `every` seconds a capture which lasts for `duration` seconds is started.
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+pub struct Schedule {
+    pub every: u64,
+    pub duration: u64
+}
+
+/// Which of the finished captures are kept; whatever goes over any of the limits is deleted, oldest first.
+#[derive(Clone, PartialEq, Default, Debug, Deserialize)]
+pub struct Retention {
+    #[serde(default)]
+    pub max_files: Option< usize >,
+    /// In seconds.
+    #[serde(default)]
+    pub max_age: Option< u64 >,
+    /// In bytes.
+    #[serde(default)]
+    pub max_total_size: Option< u64 >
+}
+
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+pub struct Service {
+    pub name: String,
+    /// A file with the PID of the service.
+    #[serde(default)]
+    pub pid_file: Option< PathBuf >,
+    /// The name of the service's process, as in `/proc/<pid>/comm`; used if there's no `pid_file`.
+    #[serde(default)]
+    pub process_name: Option< String >,
+    /// Where the service writes its data files, i.e. the directory of its `MEMORY_PROFILER_OUTPUT`.
+    pub output_dir: PathBuf,
+    /// The service's `MEMORY_PROFILER_CONTROL_SOCKET`; `%p` is replaced with its PID.
+    #[serde(default = "default_control_socket")]
+    pub control_socket: String,
+    /// If not specified the service is captured continuously.
+    #[serde(default)]
+    pub schedule: Option< Schedule >,
+    #[serde(default)]
+    pub retention: Retention,
+    /// A directory to which the finished captures are copied, or the `tcp://<host>:<port>` address
+    /// of a `memory-profiler-cli server --listen` to which they're streamed.
+    #[serde(default)]
+    pub sync_to: Option< String >
+}
+
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+pub struct Config {
+    /// How often the services are checked, in seconds.
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+    /// For how many seconds a data file can't change before it's considered finished.
+    #[serde(default = "default_settle_time")]
+    pub settle_time: u64,
+    pub services: Vec< Service >
+}
+
+impl Config {
+    pub fn parse( input: &str ) -> Result< Self, String > {
+        let config: Config = serde_json::from_str( input ).map_err( |error| error.to_string() )?;
+        for service in &config.services {
+            if service.pid_file.is_none() && service.process_name.is_none() {
+                return Err( format!( "service '{}' needs either a 'pid_file' or a 'process_name'", service.name ) );
+            }
+
+            if let Some( ref target ) = service.sync_to {
+                if target.contains( "://" ) && !target.starts_with( "tcp://" ) {
+                    return Err( format!( "service '{}' has an unsupported 'sync_to'; it has to be either a directory or a 'tcp://' address", service.name ) );
+                }
+            }
+
+            if let Some( ref schedule ) = service.schedule {
+                if schedule.duration == 0 || schedule.duration > schedule.every {
+                    return Err( format!( "service '{}' has an invalid schedule; the 'duration' has to be between 1 and 'every'", service.name ) );
+                }
+            }
+        }
+
+        Ok( config )
+    }
+
+    pub fn load( path: &Path ) -> Result< Self, io::Error > {
+        let input = fs::read_to_string( path )?;
+        Self::parse( &input ).map_err( |error| {
+            io::Error::new( io::ErrorKind::InvalidData, format!( "failed to parse {:?}: {}", path, error ) )
+        })
+    }
+}
+
+#[test]
+fn test_parse_config() {
+    let config = Config::parse( r#"{
+        "services": [
+            {
+                "name": "api",
+                "process_name": "api-server",
+                "output_dir": "/var/lib/captures/api",
+                "schedule": { "every": 3600, "duration": 300 },
+                "retention": { "max_files": 10 },
+                "sync_to": "tcp://central:8200"
+            }
+        ]
+    }"# ).unwrap();
+
+    assert_eq!( config.poll_interval, 10 );
+    assert_eq!( config.services[ 0 ].schedule, Some( Schedule { every: 3600, duration: 300 } ) );
+    assert_eq!( config.services[ 0 ].retention, Retention { max_files: Some( 10 ), .. Retention::default() } );
+    assert_eq!( config.services[ 0 ].control_socket, "/tmp/memory-profiler-%p.sock" );
+
+    assert!( Config::parse( r#"{ "services": [ { "name": "api", "output_dir": "/tmp" } ] }"# ).is_err() );
+    assert!( Config::parse( r#"{ "services": [ { "name": "api", "pid_file": "/run/api.pid", "output_dir": "/tmp", "schedule": { "every": 10, "duration": 20 } } ] }"# ).is_err() );
+    assert!( Config::parse( r#"{ "services": [ { "name": "api", "pid_file": "/run/api.pid", "output_dir": "/tmp", "sync_to": "http://central/" } ] }"# ).is_err() );
+}
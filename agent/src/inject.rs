@@ -0,0 +1,90 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/*
+    Loading the profiler into a process which is already running is done through gdb, which calls into the target:
+
+        setenv( "MEMORY_PROFILER_...", ... )      for every option which we want the profiler to pick up
+        dlopen( "/tmp/libmemory_profiler.so" )    or `__libc_dlopen_mode` on older glibc versions without `libdl`
+        memory_profiler_attach()                  which hooks the allocation functions of the loaded libraries
+
+    The allocations made before the attach are not seen by the profiler, and neither are the allocations
+    from the libraries which are loaded afterwards.
+*/
+
+/// Where the profiler is copied into the target's filesystem.
+const INJECTED_LIBRARY_PATH: &str = "/tmp/libmemory_profiler.so";
+
+/// Whether the profiler is already loaded into the process.
+pub fn is_loaded( pid: u32 ) -> bool {
+    fs::read_to_string( format!( "/proc/{}/maps", pid ) )
+        .map( |maps| maps.lines().any( |line| line.ends_with( "/libmemory_profiler.so" ) ) )
+        .unwrap_or( false )
+}
+
+fn quote( value: &str ) -> String {
+    let mut output = String::with_capacity( value.len() + 2 );
+    output.push( '"' );
+    for ch in value.chars() {
+        if ch == '"' || ch == '\\' {
+            output.push( '\\' );
+        }
+        output.push( ch );
+    }
+    output.push( '"' );
+    output
+}
+
+fn gdb_commands( environment: &[(String, String)] ) -> Vec< String > {
+    let mut commands: Vec< _ > = environment.iter().map( |(key, value)| {
+        format!( "call (int) setenv({}, {}, 1)", quote( key ), quote( value ) )
+    }).collect();
+
+    // RTLD_NOW; the `__RTLD_DLOPEN` flag is required by `__libc_dlopen_mode`.
+    commands.push( format!( "call (void*) dlopen({}, 2)", quote( INJECTED_LIBRARY_PATH ) ) );
+    commands.push( format!( "call (void*) __libc_dlopen_mode({}, 0x80000002)", quote( INJECTED_LIBRARY_PATH ) ) );
+    commands.push( "call (int) memory_profiler_attach()".to_owned() );
+    commands
+}
+
+/// Loads the profiler into a running process, with the given `MEMORY_PROFILER_*` variables set in its environment.
+///
+/// This needs `gdb` and the permission to `ptrace` the target (e.g. `CAP_SYS_PTRACE`).
+pub fn inject( pid: u32, library: &Path, environment: &[(String, String)] ) -> io::Result< () > {
+    if is_loaded( pid ) {
+        return Ok(());
+    }
+
+    // The library has to be visible from within the target's mount namespace.
+    let root = Path::new( &format!( "/proc/{}/root", pid ) ).to_owned();
+    let path = root.join( INJECTED_LIBRARY_PATH.trim_start_matches( '/' ) );
+    let temporary_path = path.with_file_name( ".libmemory_profiler.so.tmp" );
+    fs::copy( library, &temporary_path )?;
+    fs::rename( &temporary_path, &path )?;
+
+    let mut command = Command::new( "gdb" );
+    command.args( &[ "--batch", "--nx", "-p", &pid.to_string() ] );
+    for gdb_command in gdb_commands( environment ) {
+        command.arg( "-ex" ).arg( gdb_command );
+    }
+
+    let output = command.output().map_err( |error| io::Error::new( error.kind(), format!( "failed to run gdb: {}", error ) ) )?;
+    debug!( "gdb output: {}", String::from_utf8_lossy( &output.stdout ) );
+
+    if !is_loaded( pid ) {
+        let stderr = String::from_utf8_lossy( &output.stderr );
+        return Err( io::Error::new( io::ErrorKind::Other, format!( "the profiler wasn't loaded into PID {}: {}", pid, stderr.trim() ) ) );
+    }
+
+    info!( "Injected the profiler into PID {}", pid );
+    Ok(())
+}
+
+#[test]
+fn test_gdb_commands() {
+    let commands = gdb_commands( &[ ("MEMORY_PROFILER_METADATA_POD".to_owned(), "a \"b\" \\c".to_owned()) ] );
+    assert_eq!( commands[ 0 ], r#"call (int) setenv("MEMORY_PROFILER_METADATA_POD", "a \"b\" \\c", 1)"# );
+    assert_eq!( commands.last().unwrap(), "call (int) memory_profiler_attach()" );
+}
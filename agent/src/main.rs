@@ -0,0 +1,354 @@
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_derive;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+mod config;
+mod inject;
+mod process;
+mod retention;
+mod sidecar;
+mod sync;
+
+use crate::config::{Config, Schedule, Service};
+use crate::process::Command as ControlCommand;
+use crate::retention::{Capture, select_expired};
+use crate::sidecar::{SidecarOptions, Target};
+use crate::sync::Uploader;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Action {
+    Enable,
+    Disable,
+    Nothing
+}
+
+#[derive(Default)]
+struct ServiceState {
+    pid: Option< u32 >,
+    capturing_since: Option< u64 >,
+    next_start: u64,
+    is_missing_profiler_reported: bool
+}
+
+fn next_action( schedule: Option< &Schedule >, capturing_since: Option< u64 >, next_start: u64, now: u64 ) -> Action {
+    match (schedule, capturing_since) {
+        (None, None) => Action::Enable,
+        (None, Some( _ )) => Action::Nothing,
+        (Some( schedule ), Some( start )) if now >= start + schedule.duration => Action::Disable,
+        (Some( _ ), None) if now >= next_start => Action::Enable,
+        _ => Action::Nothing
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since( UNIX_EPOCH ).map( |duration| duration.as_secs() ).unwrap_or( 0 )
+}
+
+fn find_pid( service: &Service ) -> Option< u32 > {
+    if let Some( ref path ) = service.pid_file {
+        let pid: u32 = fs::read_to_string( path ).ok()?.trim().parse().ok()?;
+        return if process::is_running( pid ) { Some( pid ) } else { None };
+    }
+
+    process::find_by_name( service.process_name.as_ref()? )
+}
+
+fn list_captures( directory: &Path ) -> Vec< Capture > {
+    let entries = match fs::read_dir( directory ) {
+        Ok( entries ) => entries,
+        Err( error ) => {
+            warn!( "Failed to list {:?}: {}", directory, error );
+            return Vec::new();
+        }
+    };
+
+    entries.filter_map( |entry| {
+        let path = entry.ok()?.path();
+        if path.extension()? != "dat" {
+            return None;
+        }
+
+        let metadata = fs::metadata( &path ).ok()?;
+        let modified = metadata.modified().ok()?.duration_since( UNIX_EPOCH ).ok()?.as_secs();
+        Some( Capture { path, size: metadata.len(), modified } )
+    }).collect()
+}
+
+fn with_suffix( path: &Path, suffix: &str ) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push( suffix );
+    path.into()
+}
+
+fn poll_service( config: &Config, service: &Service, uploader: &Uploader, state: &mut ServiceState, now: u64 ) {
+    let pid = find_pid( service );
+    if pid != state.pid {
+        match pid {
+            Some( pid ) => info!( "Service '{}' is running with PID {}", service.name, pid ),
+            None => info!( "Service '{}' is not running", service.name )
+        }
+
+        // A freshly started service has the profiling disabled (with `MEMORY_PROFILER_DISABLE_BY_DEFAULT=1`).
+        *state = ServiceState { pid, next_start: now, .. ServiceState::default() };
+    }
+
+    if let Some( pid ) = pid {
+        match process::control( pid, &service.control_socket, ControlCommand::Status ) {
+            Ok( is_enabled ) => {
+                state.is_missing_profiler_reported = false;
+
+                // Someone else could have toggled the profiling in the meantime.
+                if is_enabled != state.capturing_since.is_some() {
+                    state.capturing_since = if is_enabled { Some( now ) } else { None };
+                }
+
+                let (command, expected) = match next_action( service.schedule.as_ref(), state.capturing_since, state.next_start, now ) {
+                    Action::Enable => (ControlCommand::Start, true),
+                    Action::Disable => (ControlCommand::Stop, false),
+                    Action::Nothing => (ControlCommand::Status, is_enabled)
+                };
+
+                if command != ControlCommand::Status {
+                    match process::control( pid, &service.control_socket, command ) {
+                        Ok( is_enabled ) if is_enabled == expected => {
+                            if is_enabled {
+                                info!( "Started capturing service '{}'", service.name );
+                                state.capturing_since = Some( now );
+                                state.next_start = now + service.schedule.as_ref().map( |schedule| schedule.every ).unwrap_or( 0 );
+                            } else {
+                                info!( "Stopped capturing service '{}'", service.name );
+                                state.capturing_since = None;
+                            }
+                        },
+                        Ok( _ ) => warn!( "Service '{}' (PID {}) didn't change its profiling state", service.name, pid ),
+                        Err( error ) => warn!( "Failed to control service '{}' (PID {}): {}", service.name, pid, error )
+                    }
+                }
+            },
+            Err( error ) => {
+                if !state.is_missing_profiler_reported {
+                    warn!(
+                        "Service '{}' (PID {}) can't be controlled; is it running under the memory profiler with MEMORY_PROFILER_CONTROL_SOCKET set? ({})",
+                        service.name,
+                        pid,
+                        error
+                    );
+                    state.is_missing_profiler_reported = true;
+                }
+            }
+        }
+    }
+
+    let mut captures = list_captures( &service.output_dir );
+    if state.capturing_since.is_some() {
+        // The newest file is the one which is being written to right now.
+        if let Some( index ) = (0..captures.len()).max_by_key( |&index| captures[ index ].modified ) {
+            captures.remove( index );
+        }
+    }
+
+    captures.retain( |capture| now.saturating_sub( capture.modified ) >= config.settle_time );
+
+    if let Some( ref target ) = service.sync_to {
+        for capture in &captures {
+            let marker = with_suffix( &capture.path, ".synced" );
+            if !marker.exists() {
+                uploader.enqueue( target, &capture.path, marker );
+            }
+        }
+    }
+
+    for capture in select_expired( captures, &service.retention, now ) {
+        if uploader.is_pending( &capture.path ) {
+            continue;
+        }
+
+        if service.sync_to.is_some() && !with_suffix( &capture.path, ".synced" ).exists() {
+            warn!( "Deleting {:?} even though it wasn't synced yet", capture.path );
+        } else {
+            info!( "Deleting {:?}", capture.path );
+        }
+
+        if let Err( error ) = fs::remove_file( &capture.path ) {
+            warn!( "Failed to delete {:?}: {}", capture.path, error );
+        }
+
+        let _ = fs::remove_file( with_suffix( &capture.path, ".idx" ) );
+        let _ = fs::remove_file( with_suffix( &capture.path, ".synced" ) );
+    }
+}
+
+const USAGE: &str = "\
+usage: memory-profiler-agent <config.json>
+       memory-profiler-agent sidecar (--pid <pid> | --container-id <id> | --process-name <name>) --output-dir <dir> [--listen <address>] [--token-file <path>] [--control-socket <path>] [--inject <library>]
+       memory-profiler-agent install [--library <path>] <dir>";
+
+enum Command {
+    Agent( PathBuf ),
+    Sidecar( SidecarOptions ),
+    Install { library: Option< PathBuf >, directory: PathBuf }
+}
+
+fn parse_args< I: IntoIterator< Item = String > >( args: I ) -> Result< Command, String > {
+    let mut args = args.into_iter();
+    let first = args.next().ok_or_else( || "missing arguments".to_owned() )?;
+    let mut options: Vec< (String, String) > = Vec::new();
+    let mut positional = Vec::new();
+    while let Some( arg ) = args.next() {
+        if arg.starts_with( "--" ) {
+            let value = args.next().ok_or_else( || format!( "missing value for '{}'", arg ) )?;
+            options.push( (arg, value) );
+        } else {
+            positional.push( arg );
+        }
+    }
+
+    let mut take = |name: &str| -> Option< String > {
+        let index = options.iter().position( |(key, _)| key == name )?;
+        Some( options.remove( index ).1 )
+    };
+
+    let command = match first.as_str() {
+        "sidecar" => {
+            let target = match (take( "--pid" ), take( "--container-id" ), take( "--process-name" )) {
+                (Some( pid ), None, None) => Target::Pid( pid.parse().map_err( |_| format!( "invalid PID: '{}'", pid ) )? ),
+                (None, Some( id ), None) => Target::ContainerId( id ),
+                (None, None, Some( name )) => Target::ProcessName( name ),
+                _ => return Err( "exactly one of '--pid', '--container-id' or '--process-name' has to be specified".to_owned() )
+            };
+
+            let output_dir = take( "--output-dir" ).ok_or_else( || "missing '--output-dir'".to_owned() )?.into();
+            let listen = take( "--listen" ).unwrap_or_else( || "127.0.0.1:8090".to_owned() );
+            let token_file = take( "--token-file" ).map( PathBuf::from );
+            let control_socket = take( "--control-socket" ).unwrap_or_else( || process::DEFAULT_CONTROL_SOCKET.to_owned() );
+            let inject = take( "--inject" ).map( PathBuf::from );
+            if !positional.is_empty() {
+                return Err( format!( "unexpected argument: '{}'", positional[ 0 ] ) );
+            }
+
+            Command::Sidecar( SidecarOptions { target, output_dir, listen, control_socket, token_file, inject } )
+        },
+        "install" => {
+            let library = take( "--library" ).map( PathBuf::from );
+            if positional.len() != 1 {
+                return Err( "expected exactly one directory".to_owned() );
+            }
+
+            Command::Install { library, directory: positional.pop().unwrap().into() }
+        },
+        _ => {
+            if !positional.is_empty() {
+                return Err( format!( "unexpected argument: '{}'", positional[ 0 ] ) );
+            }
+
+            Command::Agent( first.into() )
+        }
+    };
+
+    if let Some( (key, _) ) = options.first() {
+        return Err( format!( "unknown option: '{}'", key ) );
+    }
+
+    Ok( command )
+}
+
+fn exit_with_error( error: impl std::fmt::Display ) -> ! {
+    error!( "{}", error );
+    std::process::exit( 1 );
+}
+
+fn main() {
+    if env::var( "RUST_LOG" ).is_err() {
+        env::set_var( "RUST_LOG", "info" );
+    }
+
+    env_logger::init();
+
+    let path = match parse_args( env::args().skip( 1 ) ) {
+        Ok( Command::Agent( path ) ) => path,
+        Ok( Command::Sidecar( options ) ) => {
+            sidecar::run( options ).unwrap_or_else( |error| exit_with_error( error ) );
+            return;
+        },
+        Ok( Command::Install { library, directory } ) => {
+            let library = library.map( Ok ).unwrap_or_else( sidecar::default_library_path ).unwrap_or_else( |error| exit_with_error( error ) );
+            sidecar::install( &library, &directory ).unwrap_or_else( |error| exit_with_error( error ) );
+            return;
+        },
+        Err( error ) => {
+            eprintln!( "error: {}\n{}", error, USAGE );
+            std::process::exit( 1 );
+        }
+    };
+
+    let config = Config::load( &path ).unwrap_or_else( |error| exit_with_error( error ) );
+    let mut states: Vec< ServiceState > = config.services.iter().map( |_| ServiceState::default() ).collect();
+    let uploader = Uploader::new();
+    loop {
+        let now = now();
+        for (service, state) in config.services.iter().zip( states.iter_mut() ) {
+            poll_service( &config, service, &uploader, state, now );
+        }
+
+        thread::sleep( Duration::from_secs( config.poll_interval.max( 1 ) ) );
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let parse = |args: &[&str]| parse_args( args.iter().map( |arg| arg.to_string() ) );
+    match parse( &[ "agent.json" ] ) {
+        Ok( Command::Agent( path ) ) => assert_eq!( path, PathBuf::from( "agent.json" ) ),
+        _ => panic!()
+    }
+
+    match parse( &[ "sidecar", "--container-id", "4f3c2a", "--output-dir", "/captures" ] ) {
+        Ok( Command::Sidecar( options ) ) => assert_eq!( options, SidecarOptions {
+            target: Target::ContainerId( "4f3c2a".to_owned() ),
+            output_dir: "/captures".into(),
+            listen: "127.0.0.1:8090".to_owned(),
+            control_socket: "/tmp/memory-profiler-%p.sock".to_owned(),
+            token_file: None,
+            inject: None
+        }),
+        _ => panic!()
+    }
+
+    match parse( &[ "sidecar", "--pid", "42", "--output-dir", "/captures", "--listen", "0.0.0.0:8090", "--token-file", "/secrets/token", "--inject", "/profiler/libmemory_profiler.so" ] ) {
+        Ok( Command::Sidecar( options ) ) => {
+            assert_eq!( options.target, Target::Pid( 42 ) );
+            assert_eq!( options.token_file, Some( PathBuf::from( "/secrets/token" ) ) );
+            assert_eq!( options.inject, Some( PathBuf::from( "/profiler/libmemory_profiler.so" ) ) );
+        },
+        _ => panic!()
+    }
+
+    match parse( &[ "install", "/profiler" ] ) {
+        Ok( Command::Install { library: None, directory } ) => assert_eq!( directory, PathBuf::from( "/profiler" ) ),
+        _ => panic!()
+    }
+
+    assert!( parse( &[ "sidecar", "--pid", "1", "--process-name", "api", "--output-dir", "/captures" ] ).is_err() );
+    assert!( parse( &[ "sidecar", "--pid", "1" ] ).is_err() );
+    assert!( parse( &[ "sidecar", "--pid", "1", "--output-dir", "/captures", "--foo", "bar" ] ).is_err() );
+}
+
+#[test]
+fn test_next_action() {
+    let schedule = Schedule { every: 100, duration: 10 };
+    assert_eq!( next_action( None, None, 0, 50 ), Action::Enable );
+    assert_eq!( next_action( None, Some( 50 ), 0, 5000 ), Action::Nothing );
+
+    assert_eq!( next_action( Some( &schedule ), None, 100, 50 ), Action::Nothing );
+    assert_eq!( next_action( Some( &schedule ), None, 100, 100 ), Action::Enable );
+    assert_eq!( next_action( Some( &schedule ), Some( 100 ), 200, 105 ), Action::Nothing );
+    assert_eq!( next_action( Some( &schedule ), Some( 100 ), 200, 110 ), Action::Disable );
+    assert_eq!( next_action( Some( &schedule ), None, 200, 150 ), Action::Nothing );
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+fn pids() -> impl Iterator< Item = u32 > {
+    fs::read_dir( "/proc" ).into_iter().flatten()
+        .filter_map( |entry| entry.ok()?.file_name().to_str()?.parse::< u32 >().ok() )
+}
+
+pub fn is_running( pid: u32 ) -> bool {
+    Path::new( &format!( "/proc/{}", pid ) ).exists()
+}
+
+/// Finds the process with the given name (as in `/proc/<pid>/comm`); if there are several then the oldest one is picked.
+pub fn find_by_name( name: &str ) -> Option< u32 > {
+    pids()
+        .filter( |pid| {
+            fs::read_to_string( format!( "/proc/{}/comm", pid ) ).map( |comm| comm.trim() == name ).unwrap_or( false )
+        })
+        .min()
+}
+
+fn is_in_container( cgroup: &str, container_id: &str ) -> bool {
+    cgroup.lines().any( |line| {
+        let path = line.splitn( 3, ':' ).nth( 2 ).unwrap_or( "" );
+        path.rsplit( '/' ).next().map( |leaf| leaf.contains( container_id ) ).unwrap_or( false )
+    })
+}
+
+/// Finds the main process of a container, given its (possibly abbreviated) ID.
+pub fn find_by_container_id( container_id: &str ) -> Option< u32 > {
+    pids()
+        .filter( |pid| {
+            fs::read_to_string( format!( "/proc/{}/cgroup", pid ) ).map( |cgroup| is_in_container( &cgroup, container_id ) ).unwrap_or( false )
+        })
+        .min()
+}
+
+/// The default of the agent's `control_socket`, which is what the processes should have their `MEMORY_PROFILER_CONTROL_SOCKET` set to.
+pub const DEFAULT_CONTROL_SOCKET: &str = "/tmp/memory-profiler-%p.sock";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Command {
+    Status,
+    Start,
+    Stop
+}
+
+/// The path of a process' control socket, as seen from outside of its mount namespace.
+fn control_socket_path( pid: u32, pattern: &str ) -> PathBuf {
+    let path = pattern.replace( "%p", &pid.to_string() );
+    Path::new( &format!( "/proc/{}/root", pid ) ).join( path.trim_start_matches( '/' ) )
+}
+
+fn parse_reply( reply: &str ) -> io::Result< bool > {
+    match reply.trim() {
+        "enabled" => Ok( true ),
+        "disabled" => Ok( false ),
+        reply => Err( io::Error::new( io::ErrorKind::InvalidData, format!( "unexpected reply: '{}'", reply ) ) )
+    }
+}
+
+/// Sends a command to the profiler through its control socket (see `MEMORY_PROFILER_CONTROL_SOCKET`)
+/// and returns whether the profiling is enabled afterwards.
+///
+/// This fails if the process isn't running under the profiler.
+pub fn control( pid: u32, socket: &str, command: Command ) -> io::Result< bool > {
+    let mut stream = UnixStream::connect( control_socket_path( pid, socket ) )?;
+    stream.set_read_timeout( Some( Duration::from_secs( 5 ) ) )?;
+    stream.set_write_timeout( Some( Duration::from_secs( 5 ) ) )?;
+
+    let command = match command {
+        Command::Status => "status",
+        Command::Start => "start",
+        Command::Stop => "stop"
+    };
+
+    writeln!( stream, "{}", command )?;
+    let mut reply = String::new();
+    BufReader::new( stream ).read_line( &mut reply )?;
+    parse_reply( &reply )
+}
+
+#[test]
+fn test_is_in_container() {
+    let cgroup = "0::/kubepods.slice/kubepods-besteffort.slice/kubepods-besteffort-pod1a2b.slice/cri-containerd-4f3c2a9d8e7b.scope\n";
+    assert!( is_in_container( cgroup, "4f3c2a9d8e7b" ) );
+    assert!( is_in_container( cgroup, "4f3c2a" ) );
+    assert!( !is_in_container( cgroup, "1a2b" ) );
+
+    let cgroup = "12:memory:/kubepods/burstable/pod1a2b/4f3c2a9d8e7b\n11:cpu:/kubepods/burstable/pod1a2b/4f3c2a9d8e7b\n";
+    assert!( is_in_container( cgroup, "4f3c2a9d8e7b" ) );
+    assert!( !is_in_container( cgroup, "ffff" ) );
+}
+
+#[test]
+fn test_control_socket() {
+    assert_eq!( control_socket_path( 1234, DEFAULT_CONTROL_SOCKET ), PathBuf::from( "/proc/1234/root/tmp/memory-profiler-1234.sock" ) );
+    assert_eq!( parse_reply( "enabled\n" ).unwrap(), true );
+    assert_eq!( parse_reply( "disabled\n" ).unwrap(), false );
+    assert!( parse_reply( "error: unknown command 'foo'\n" ).is_err() );
+}
@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+
+use crate::config::Retention;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Capture {
+    pub path: PathBuf,
+    pub size: u64,
+    /// The last modification time, in seconds since the UNIX epoch.
+    pub modified: u64
+}
+
+/// Returns the captures which should be deleted, given the finished captures of a single service.
+pub fn select_expired( mut captures: Vec< Capture >, retention: &Retention, now: u64 ) -> Vec< Capture > {
+    // Newest first, so that whatever is over the limits is at the end.
+    captures.sort_by( |a, b| b.modified.cmp( &a.modified ).then_with( || b.path.cmp( &a.path ) ) );
+
+    let mut total_size = 0;
+    let mut expired = Vec::new();
+    for (index, capture) in captures.into_iter().enumerate() {
+        total_size += capture.size;
+        let is_expired =
+            retention.max_files.map( |max_files| index >= max_files ).unwrap_or( false ) ||
+            retention.max_age.map( |max_age| now.saturating_sub( capture.modified ) > max_age ).unwrap_or( false ) ||
+            retention.max_total_size.map( |max_total_size| total_size > max_total_size ).unwrap_or( false );
+
+        if is_expired {
+            expired.push( capture );
+        }
+    }
+
+    expired
+}
+
+#[test]
+fn test_select_expired() {
+    let capture = |name: &str, size: u64, modified: u64| Capture { path: name.into(), size, modified };
+    let captures = vec![
+        capture( "a.dat", 100, 1000 ),
+        capture( "b.dat", 100, 2000 ),
+        capture( "c.dat", 100, 3000 ),
+        capture( "d.dat", 100, 4000 )
+    ];
+
+    let names = |captures: Vec< Capture >| captures.into_iter().map( |capture| capture.path.to_str().unwrap().to_owned() ).collect::< Vec< _ > >();
+    assert_eq!( names( select_expired( captures.clone(), &Retention::default(), 5000 ) ), Vec::< String >::new() );
+    assert_eq!( names( select_expired( captures.clone(), &Retention { max_files: Some( 3 ), .. Retention::default() }, 5000 ) ), vec![ "a.dat" ] );
+    assert_eq!( names( select_expired( captures.clone(), &Retention { max_age: Some( 2500 ), .. Retention::default() }, 5000 ) ), vec![ "b.dat", "a.dat" ] );
+    assert_eq!( names( select_expired( captures.clone(), &Retention { max_total_size: Some( 250 ), .. Retention::default() }, 5000 ) ), vec![ "b.dat", "a.dat" ] );
+}
@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+use common::{METADATA_PREFIX, parse_metadata};
+use common::request::{is_token_valid, read_token};
+
+use crate::inject;
+use crate::process::{self, Command};
+
+/// How many requests are handled at the same time; the rest wait in a queue of the same size.
+const WORKER_COUNT: usize = 4;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Target {
+    Pid( u32 ),
+    ContainerId( String ),
+    ProcessName( String )
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SidecarOptions {
+    pub target: Target,
+    /// Where the target writes its data files; usually a volume which is shared with the target's container.
+    pub output_dir: PathBuf,
+    pub listen: String,
+    /// The target's `MEMORY_PROFILER_CONTROL_SOCKET`.
+    pub control_socket: String,
+    /// A file with the token which the requests have to carry in their `Authorization: Bearer <token>` header.
+    pub token_file: Option< PathBuf >,
+    /// The profiler which is injected into the target if it's not running under the profiler already.
+    pub inject: Option< PathBuf >
+}
+
+#[derive(Default)]
+struct State {
+    pid: Option< u32 >,
+    is_injection_attempted: bool,
+    capturing_since: Option< u64 >,
+    stop_at: Option< u64 >
+}
+
+#[derive(Serialize)]
+struct ResponseCapture {
+    name: String,
+    size: u64,
+    modified: u64
+}
+
+#[derive(Serialize)]
+struct ResponseStatus {
+    pid: Option< u32 >,
+    is_profiled: bool,
+    capturing: bool,
+    capturing_since: Option< u64 >,
+    stop_at: Option< u64 >,
+    metadata: BTreeMap< String, String >,
+    captures: Vec< ResponseCapture >
+}
+
+#[derive(PartialEq, Debug)]
+enum Route {
+    Status,
+    Start { duration: Option< u64 > },
+    Stop,
+    Capture( String ),
+    BadRequest,
+    NotFound
+}
+
+fn route( method: &str, target: &str ) -> Route {
+    let (path, query) = match target.find( '?' ) {
+        Some( index ) => (&target[ ..index ], &target[ index + 1.. ]),
+        None => (target, "")
+    };
+
+    match (method, path) {
+        ("GET", "/status") => Route::Status,
+        ("POST", "/start") => {
+            let mut duration = None;
+            for pair in query.split( '&' ).filter( |pair| !pair.is_empty() ) {
+                let mut iter = pair.splitn( 2, '=' );
+                match (iter.next(), iter.next()) {
+                    (Some( "duration" ), Some( value )) => match value.parse() {
+                        Ok( value ) => duration = Some( value ),
+                        Err( _ ) => return Route::BadRequest
+                    },
+                    _ => return Route::BadRequest
+                }
+            }
+
+            Route::Start { duration }
+        },
+        ("POST", "/stop") => Route::Stop,
+        ("GET", path) if path.starts_with( "/captures/" ) => {
+            let name = &path[ "/captures/".len().. ];
+            if name.is_empty() || name.contains( '/' ) || name.starts_with( '.' ) || !name.ends_with( ".dat" ) {
+                Route::NotFound
+            } else {
+                Route::Capture( name.to_owned() )
+            }
+        },
+        _ => Route::NotFound
+    }
+}
+
+fn resolve( target: &Target ) -> Option< u32 > {
+    match *target {
+        Target::Pid( pid ) => if process::is_running( pid ) { Some( pid ) } else { None },
+        Target::ContainerId( ref id ) => process::find_by_container_id( id ),
+        Target::ProcessName( ref name ) => process::find_by_name( name )
+    }
+}
+
+/// Reads the `MEMORY_PROFILER_METADATA_*` variables (e.g. the pod's name and namespace passed through the downward API)
+/// from the target's environment; these are also what ends up in its data files.
+///
+/// For an injected target these are the variables it was started with, not the ones which were set by the injection.
+fn read_metadata( pid: u32 ) -> BTreeMap< String, String > {
+    let environ = fs::read( format!( "/proc/{}/environ", pid ) ).unwrap_or_default();
+    environ.split( |&byte| byte == 0 ).filter_map( parse_metadata ).collect()
+}
+
+/// The options for a profiler which is injected into the target; the `MEMORY_PROFILER_METADATA_*` variables
+/// are taken from our own environment, so they can be set through the downward API on the sidecar's container.
+fn injected_environment( options: &SidecarOptions ) -> Vec< (String, String) > {
+    let mut environment = vec![
+        ("MEMORY_PROFILER_DISABLE_BY_DEFAULT".to_owned(), "1".to_owned()),
+        ("MEMORY_PROFILER_OUTPUT".to_owned(), options.output_dir.join( "memory-profiling_%e_%t_%p_%n.dat" ).to_string_lossy().into_owned()),
+        ("MEMORY_PROFILER_CONTROL_SOCKET".to_owned(), options.control_socket.clone())
+    ];
+
+    environment.extend( env::vars().filter( |(key, _)| key.starts_with( METADATA_PREFIX ) ) );
+    environment
+}
+
+fn status( options: &SidecarOptions, state: &State ) -> ResponseStatus {
+    let mut captures: Vec< _ > = crate::list_captures( &options.output_dir ).into_iter().filter_map( |capture| {
+        Some( ResponseCapture {
+            name: capture.path.file_name()?.to_str()?.to_owned(),
+            size: capture.size,
+            modified: capture.modified
+        })
+    }).collect();
+    captures.sort_by( |a, b| a.modified.cmp( &b.modified ).then_with( || a.name.cmp( &b.name ) ) );
+
+    ResponseStatus {
+        pid: state.pid,
+        is_profiled: state.pid.map( |pid| process::control( pid, &options.control_socket, Command::Status ).is_ok() ).unwrap_or( false ),
+        capturing: state.capturing_since.is_some(),
+        capturing_since: state.capturing_since,
+        stop_at: state.stop_at,
+        metadata: state.pid.map( read_metadata ).unwrap_or_default(),
+        captures
+    }
+}
+
+fn control( options: &SidecarOptions, pid: u32, command: Command, expected: bool ) -> Result< (), (u16, String) > {
+    match process::control( pid, &options.control_socket, command ) {
+        Ok( is_enabled ) if is_enabled == expected => Ok(()),
+        Ok( _ ) => Err( (500, format!( "the target (PID {}) didn't change its profiling state", pid )) ),
+        Err( error ) => Err( (500, format!( "the target (PID {}) can't be controlled; is it running under the memory profiler? ({})", pid, error )) )
+    }
+}
+
+fn start( options: &SidecarOptions, state: &mut State, duration: Option< u64 > ) -> Result< (), (u16, String) > {
+    let pid = state.pid.ok_or_else( || (503, "the target is not running".to_owned()) )?;
+    if state.capturing_since.is_some() {
+        return Err( (409, "already capturing".to_owned()) );
+    }
+
+    control( options, pid, Command::Start, true )?;
+
+    let now = crate::now();
+    info!( "Started capturing PID {}", pid );
+    state.capturing_since = Some( now );
+    state.stop_at = duration.map( |duration| now + duration );
+    Ok(())
+}
+
+fn stop( options: &SidecarOptions, state: &mut State ) -> Result< (), (u16, String) > {
+    let pid = state.pid.ok_or_else( || (503, "the target is not running".to_owned()) )?;
+    if state.capturing_since.is_none() {
+        return Err( (409, "not capturing".to_owned()) );
+    }
+
+    control( options, pid, Command::Stop, false )?;
+
+    info!( "Stopped capturing PID {}", pid );
+    state.capturing_since = None;
+    state.stop_at = None;
+    Ok(())
+}
+
+fn status_text( status: u16 ) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error"
+    }
+}
+
+fn respond( stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8] ) -> io::Result< () > {
+    write!(
+        stream,
+        "HTTP/1.0 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        status,
+        status_text( status ),
+        content_type,
+        body.len()
+    )?;
+    stream.write_all( body )
+}
+
+fn respond_with_status( stream: &mut TcpStream, options: &SidecarOptions, state: &State ) -> io::Result< () > {
+    let body = serde_json::to_vec( &status( options, state ) ).unwrap();
+    respond( stream, 200, "application/json", &body )
+}
+
+fn is_authorized( header: Option< &str >, token: &str ) -> bool {
+    match header.and_then( |header| header.strip_prefix( "Bearer " ) ) {
+        Some( given ) => is_token_valid( given.trim(), token ),
+        None => false
+    }
+}
+
+fn handle( mut stream: TcpStream, options: &SidecarOptions, token: Option< &str >, state: &Mutex< State > ) -> io::Result< () > {
+    stream.set_read_timeout( Some( Duration::from_secs( 30 ) ) )?;
+    stream.set_write_timeout( Some( Duration::from_secs( 30 ) ) )?;
+
+    let mut reader = BufReader::new( stream.try_clone()? ).take( 64 * 1024 );
+    let mut request_line = String::new();
+    reader.read_line( &mut request_line )?;
+
+    let mut authorization = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line( &mut line )? == 0 || line.trim().is_empty() {
+            break;
+        }
+
+        let mut iter = line.splitn( 2, ':' );
+        if let (Some( key ), Some( value )) = (iter.next(), iter.next()) {
+            if key.trim().eq_ignore_ascii_case( "authorization" ) {
+                authorization = Some( value.trim().to_owned() );
+            }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or( "" );
+    let target = parts.next().unwrap_or( "" );
+    debug!( "{} {}", method, target );
+
+    if let Some( token ) = token {
+        if !is_authorized( authorization.as_deref(), token ) {
+            return respond( &mut stream, 401, "text/plain", b"unauthorized" );
+        }
+    }
+
+    let result = match route( method, target ) {
+        Route::Status => Ok(()),
+        Route::Start { duration } => start( options, &mut state.lock().unwrap(), duration ),
+        Route::Stop => stop( options, &mut state.lock().unwrap() ),
+        Route::Capture( name ) => {
+            let path = options.output_dir.join( name );
+            return match File::open( &path ) {
+                Ok( mut fp ) => {
+                    write!(
+                        stream,
+                        "HTTP/1.0 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+                        fp.metadata()?.len()
+                    )?;
+                    io::copy( &mut fp, &mut stream ).map( |_| () )
+                },
+                Err( _ ) => respond( &mut stream, 404, "text/plain", b"no such capture" )
+            };
+        },
+        Route::BadRequest => Err( (400, "bad request".to_owned()) ),
+        Route::NotFound => Err( (404, "not found".to_owned()) )
+    };
+
+    match result {
+        Ok(()) => respond_with_status( &mut stream, options, &state.lock().unwrap() ),
+        Err( (status, message) ) => respond( &mut stream, status, "text/plain", message.as_bytes() )
+    }
+}
+
+fn try_inject( options: &SidecarOptions, library: &Path, pid: u32 ) {
+    if process::control( pid, &options.control_socket, Command::Status ).is_ok() {
+        return;
+    }
+
+    if let Err( error ) = inject::inject( pid, library, &injected_environment( options ) ) {
+        warn!( "Failed to inject the profiler into PID {}: {}", pid, error );
+    }
+}
+
+pub fn run( options: SidecarOptions ) -> io::Result< () > {
+    // Without a token the API is only reachable from within the pod (e.g. through `kubectl port-forward`).
+    let token = Arc::new( read_token( &options.listen, options.token_file.as_deref(), "--token-file" )? );
+    let options = Arc::new( options );
+    let state = Arc::new( Mutex::new( State::default() ) );
+    let listener = TcpListener::bind( &options.listen )?;
+    info!( "Listening on {}", options.listen );
+
+    let (sender, receiver) = mpsc::sync_channel::< TcpStream >( WORKER_COUNT );
+    let receiver = Arc::new( Mutex::new( receiver ) );
+    for _ in 0..WORKER_COUNT {
+        let options = options.clone();
+        let token = token.clone();
+        let state = state.clone();
+        let receiver = receiver.clone();
+        thread::spawn( move || loop {
+            let stream = match receiver.lock().unwrap().recv() {
+                Ok( stream ) => stream,
+                Err( _ ) => break
+            };
+
+            if let Err( error ) = handle( stream, &options, (*token).as_deref(), &state ) {
+                warn!( "Failed to handle a request: {}", error );
+            }
+        });
+    }
+
+    thread::spawn( move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok( stream ) => stream,
+                Err( error ) => {
+                    warn!( "Failed to accept a connection: {}", error );
+                    continue;
+                }
+            };
+
+            if let Err( TrySendError::Full( mut stream ) ) = sender.try_send( stream ) {
+                let _ = stream.set_write_timeout( Some( Duration::from_secs( 1 ) ) );
+                let _ = respond( &mut stream, 503, "text/plain", b"too many requests" );
+            }
+        }
+    });
+
+    loop {
+        let pid = resolve( &options.target );
+        let mut inject_into = None;
+        {
+            let mut state = state.lock().unwrap();
+            if pid != state.pid {
+                match pid {
+                    Some( pid ) => info!( "The target is running with PID {}", pid ),
+                    None => info!( "The target is not running" )
+                }
+
+                // A freshly started target has the profiling disabled (with `MEMORY_PROFILER_DISABLE_BY_DEFAULT=1`).
+                *state = State { pid, .. State::default() };
+            }
+
+            if let (Some( pid ), Some( library )) = (state.pid, options.inject.as_ref()) {
+                if !state.is_injection_attempted {
+                    state.is_injection_attempted = true;
+                    inject_into = Some( (pid, library) );
+                }
+            }
+        }
+
+        // This can take a while, so it's done without holding the lock.
+        if let Some( (pid, library) ) = inject_into {
+            try_inject( &options, library, pid );
+        }
+
+        {
+            let mut state = state.lock().unwrap();
+
+            // Someone else could have toggled the profiling in the meantime.
+            if let Some( pid ) = state.pid {
+                if let Ok( is_enabled ) = process::control( pid, &options.control_socket, Command::Status ) {
+                    if is_enabled != state.capturing_since.is_some() {
+                        state.capturing_since = if is_enabled { Some( crate::now() ) } else { None };
+                        state.stop_at = None;
+                    }
+                }
+            }
+
+            if state.stop_at.map( |stop_at| crate::now() >= stop_at ).unwrap_or( false ) {
+                if let Err( (_, message) ) = stop( &options, &mut state ) {
+                    warn!( "Failed to stop the capture: {}", message );
+                    state.stop_at = None;
+                }
+            }
+        }
+
+        thread::sleep( Duration::from_secs( 1 ) );
+    }
+}
+
+/// Copies the profiler into a directory, e.g. a volume which is shared with the container which is going to be profiled.
+pub fn install( library: &Path, directory: &Path ) -> io::Result< () > {
+    fs::create_dir_all( directory )?;
+
+    let temporary_path = directory.join( ".libmemory_profiler.so.tmp" );
+    let path = directory.join( "libmemory_profiler.so" );
+    fs::copy( library, &temporary_path )?;
+    fs::rename( &temporary_path, &path )?;
+    info!( "Installed {:?} into {:?}", library, path );
+    Ok(())
+}
+
+/// The profiler is expected to be installed right next to the agent.
+pub fn default_library_path() -> io::Result< PathBuf > {
+    let executable = env::current_exe()?;
+    Ok( executable.with_file_name( "libmemory_profiler.so" ) )
+}
+
+#[test]
+fn test_route() {
+    assert_eq!( route( "GET", "/status" ), Route::Status );
+    assert_eq!( route( "POST", "/start" ), Route::Start { duration: None } );
+    assert_eq!( route( "POST", "/start?duration=300" ), Route::Start { duration: Some( 300 ) } );
+    assert_eq!( route( "POST", "/start?duration=x" ), Route::BadRequest );
+    assert_eq!( route( "POST", "/start?foo=1" ), Route::BadRequest );
+    assert_eq!( route( "POST", "/stop" ), Route::Stop );
+    assert_eq!( route( "GET", "/stop" ), Route::NotFound );
+    assert_eq!( route( "GET", "/captures/memory-profiling_1.dat" ), Route::Capture( "memory-profiling_1.dat".to_owned() ) );
+    assert_eq!( route( "GET", "/captures/../etc/passwd.dat" ), Route::NotFound );
+    assert_eq!( route( "GET", "/captures/..dat" ), Route::NotFound );
+}
+
+#[test]
+fn test_is_authorized() {
+    assert!( is_authorized( Some( "Bearer secret" ), "secret" ) );
+    assert!( !is_authorized( Some( "Bearer secreT" ), "secret" ) );
+    assert!( !is_authorized( Some( "Bearer secret2" ), "secret" ) );
+    assert!( !is_authorized( Some( "Basic secret" ), "secret" ) );
+    assert!( !is_authorized( None, "secret" ) );
+}
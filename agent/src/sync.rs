@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use common::request::{BroadcastHeader, Response, PROTOCOL_VERSION};
+use common::speedy::Writable;
+use memory_profiler_capture::raw::parse_events;
+
+const TIMEOUT: Duration = Duration::from_secs( 30 );
+
+/// Sends a finished capture to a server started with `--listen`, just as if the profiled process was streaming it.
+///
+/// The server's token, if it needs one, is taken from `MEMORY_PROFILER_STREAM_TOKEN`.
+fn stream( address: &str, path: &Path ) -> io::Result< () > {
+    let (header, _) = parse_events( File::open( path )? )?;
+    let header = BroadcastHeader {
+        id: header.id,
+        initial_timestamp: header.initial_timestamp,
+        timestamp: header.timestamp,
+        wall_clock_secs: header.wall_clock_secs,
+        wall_clock_nsecs: header.wall_clock_nsecs,
+        pid: header.pid,
+        cmdline: header.cmdline,
+        executable: header.executable,
+        arch: header.arch,
+        listener_port: 0,
+        protocol_version: PROTOCOL_VERSION
+    };
+
+    let address = address.to_socket_addrs()?.next().ok_or_else( || io::Error::new( io::ErrorKind::NotFound, format!( "failed to resolve '{}'", address ) ) )?;
+    let stream = TcpStream::connect_timeout( &address, TIMEOUT )?;
+    stream.set_write_timeout( Some( TIMEOUT ) )?;
+
+    let mut stream = BufWriter::new( stream );
+    if let Ok( token ) = env::var( "MEMORY_PROFILER_STREAM_TOKEN" ) {
+        Response::Authenticate( token.into() ).write_to_stream( &mut stream )?;
+    }
+
+    Response::Start( header ).write_to_stream( &mut stream )?;
+
+    let mut fp = File::open( path )?;
+    let mut buffer = vec![ 0; 64 * 1024 ];
+    loop {
+        let count = fp.read( &mut buffer )?;
+        if count == 0 {
+            break;
+        }
+
+        Response::Data( (&buffer[ ..count ]).into() ).write_to_stream( &mut stream )?;
+    }
+
+    Response::Finished.write_to_stream( &mut stream )?;
+    stream.flush()
+}
+
+fn copy_to_directory( directory: &Path, path: &Path, filename: &str ) -> io::Result< () > {
+    fs::create_dir_all( directory )?;
+
+    // Copy under a temporary name first, so that nobody picks up a partial file.
+    let temporary_path = directory.join( format!( ".{}.tmp", filename ) );
+    fs::copy( path, &temporary_path )?;
+    fs::rename( &temporary_path, directory.join( filename ) )
+}
+
+/// Streams a finished capture to a server (if the `target` is a `tcp://` address), or copies it into a directory.
+pub fn sync( target: &str, path: &Path ) -> io::Result< () > {
+    if target.starts_with( "tcp://" ) {
+        return stream( &target[ "tcp://".len().. ], path );
+    }
+
+    let filename = path.file_name().and_then( |filename| filename.to_str() ).ok_or_else( || {
+        io::Error::new( io::ErrorKind::InvalidInput, format!( "invalid filename: {:?}", path ) )
+    })?;
+
+    copy_to_directory( Path::new( target ), path, filename )
+}
+
+struct Job {
+    target: String,
+    path: PathBuf,
+    /// Created once the capture was synced.
+    marker: PathBuf
+}
+
+/// Syncs the captures on a background thread, one at a time, so that a slow target doesn't hold up the polling.
+pub struct Uploader {
+    sender: Sender< Job >,
+    pending: Arc< Mutex< HashSet< PathBuf > > >
+}
+
+impl Uploader {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel::< Job >();
+        let pending = Arc::new( Mutex::new( HashSet::new() ) );
+        {
+            let pending = pending.clone();
+            thread::spawn( move || {
+                for job in receiver {
+                    match sync( &job.target, &job.path ) {
+                        Ok(()) => {
+                            info!( "Synced {:?} to '{}'", job.path, job.target );
+                            if let Err( error ) = fs::write( &job.marker, b"" ) {
+                                warn!( "Failed to create {:?}: {}", job.marker, error );
+                            }
+                        },
+                        Err( error ) => warn!( "Failed to sync {:?} to '{}': {}", job.path, job.target, error )
+                    }
+
+                    pending.lock().unwrap().remove( &job.path );
+                }
+            });
+        }
+
+        Uploader { sender, pending }
+    }
+
+    /// Queues a capture to be synced, unless it's already queued.
+    pub fn enqueue( &self, target: &str, path: &Path, marker: PathBuf ) {
+        if !self.pending.lock().unwrap().insert( path.to_owned() ) {
+            return;
+        }
+
+        let _ = self.sender.send( Job { target: target.to_owned(), path: path.to_owned(), marker } );
+    }
+
+    /// Whether the capture is queued or is being synced right now.
+    pub fn is_pending( &self, path: &Path ) -> bool {
+        self.pending.lock().unwrap().contains( path )
+    }
+}
@@ -0,0 +1,98 @@
+#ifndef MEMORY_PROFILER_CAPTURE_H
+#define MEMORY_PROFILER_CAPTURE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+    A capture is owned by the caller from a successful `memory_profiler_capture_open` until it's
+    passed to `memory_profiler_capture_close`, after which it (and every string and address array
+    which was returned for it) must not be used anymore. A single capture must not be used from
+    multiple threads at the same time, but different captures are independent.
+
+    Every function accepts NULL in place of a capture and fails (or returns NULL or zero) instead
+    of crashing; an internal error is reported as a failure too, so nothing ever unwinds into C.
+*/
+typedef struct memory_profiler_capture memory_profiler_capture;
+
+typedef enum {
+    MEMORY_PROFILER_EVENT_ALLOCATION = 1,
+    MEMORY_PROFILER_EVENT_REALLOCATION = 2,
+    MEMORY_PROFILER_EVENT_DEALLOCATION = 3,
+    MEMORY_PROFILER_EVENT_BACKTRACE = 4,
+    MEMORY_PROFILER_EVENT_MEMORY_MAP = 5,
+    MEMORY_PROFILER_EVENT_MEMORY_UNMAP = 6,
+    MEMORY_PROFILER_EVENT_MARKER = 7,
+    MEMORY_PROFILER_EVENT_WALL_CLOCK = 8,
+    MEMORY_PROFILER_EVENT_MEMORY_REMAP = 9
+} memory_profiler_event_kind;
+
+/*
+    A single event; which fields are set depends on the `kind`:
+
+      ALLOCATION:   timestamp, thread, pointer, size, backtrace, flags
+      REALLOCATION: timestamp, thread, old_pointer, pointer, size, backtrace, flags
+      DEALLOCATION: timestamp, thread, pointer, backtrace (only if `has_backtrace` is set)
+      BACKTRACE:    backtrace (the ID), addresses, address_count
+      MEMORY_MAP:   timestamp, thread, pointer, size (the length), backtrace
+      MEMORY_UNMAP: timestamp, thread, pointer, size (the length), backtrace
+      MARKER:       value
+      WALL_CLOCK:   timestamp, value (the seconds), size (the nanoseconds)
+      MEMORY_REMAP: timestamp, thread, old_pointer, value (the old length), pointer, size (the new length),
+                    flags (the `MREMAP_*` flags), backtrace
+
+    All of the timestamps are in microseconds of the profiled process' monotonic clock.
+    The `addresses` are only valid until the next call to `memory_profiler_capture_next`.
+*/
+typedef struct {
+    uint32_t kind;
+    uint32_t thread;
+    uint64_t timestamp;
+    uint64_t pointer;
+    uint64_t old_pointer;
+    uint64_t size;
+    uint64_t backtrace;
+    uint64_t value;
+    uint32_t flags;
+    uint32_t has_backtrace;
+    const uint64_t * addresses;
+    size_t address_count;
+} memory_profiler_event;
+
+/*
+    The `path` has to be a NUL-terminated UTF-8 string; it isn't used after this returns.
+    Returns NULL on failure; the error can be fetched with `memory_profiler_capture_last_error`.
+*/
+memory_profiler_capture * memory_profiler_capture_open( const char * path );
+
+/* Closing NULL does nothing; closing the same capture twice is undefined behavior. */
+void memory_profiler_capture_close( memory_profiler_capture * capture );
+
+/*
+    Overwrites the whole `event`, which has to point to memory owned by the caller.
+    Returns 1 if an event was read, 0 at the end of the capture (and on every call after that)
+    and -1 on failure, including when either of the arguments is NULL.
+*/
+int memory_profiler_capture_next( memory_profiler_capture * capture, memory_profiler_event * event );
+
+/* The strings are owned by the capture and are valid until it's closed; they're NULL for a NULL capture. */
+const char * memory_profiler_capture_executable( const memory_profiler_capture * capture );
+const char * memory_profiler_capture_architecture( const memory_profiler_capture * capture );
+uint32_t memory_profiler_capture_pid( const memory_profiler_capture * capture );
+uint64_t memory_profiler_capture_initial_timestamp( const memory_profiler_capture * capture );
+
+/*
+    The last error which happened on the current thread, or NULL; the string is owned by the library
+    and is valid until the next call of any of these functions on that thread.
+*/
+const char * memory_profiler_capture_last_error( void );
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,375 @@
+//! A C API for `memory-profiler-capture`; see `include/memory_profiler_capture.h`.
+
+// The safety requirements of every function are documented in the header.
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use libc::{c_char, c_int, size_t};
+
+use memory_profiler_capture::{Capture, Event};
+
+pub const MEMORY_PROFILER_EVENT_ALLOCATION: u32 = 1;
+pub const MEMORY_PROFILER_EVENT_REALLOCATION: u32 = 2;
+pub const MEMORY_PROFILER_EVENT_DEALLOCATION: u32 = 3;
+pub const MEMORY_PROFILER_EVENT_BACKTRACE: u32 = 4;
+pub const MEMORY_PROFILER_EVENT_MEMORY_MAP: u32 = 5;
+pub const MEMORY_PROFILER_EVENT_MEMORY_UNMAP: u32 = 6;
+pub const MEMORY_PROFILER_EVENT_MARKER: u32 = 7;
+pub const MEMORY_PROFILER_EVENT_WALL_CLOCK: u32 = 8;
+pub const MEMORY_PROFILER_EVENT_MEMORY_REMAP: u32 = 9;
+
+pub struct CaptureHandle {
+    capture: Capture,
+    executable: CString,
+    architecture: CString,
+    // Keeps the addresses of the last backtrace alive until the next event is read.
+    addresses: Vec< u64 >
+}
+
+#[repr(C)]
+pub struct RawEvent {
+    kind: u32,
+    thread: u32,
+    timestamp: u64,
+    pointer: u64,
+    old_pointer: u64,
+    size: u64,
+    backtrace: u64,
+    value: u64,
+    flags: u32,
+    has_backtrace: u32,
+    addresses: *const u64,
+    address_count: size_t
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell< Option< CString > > = RefCell::new( None );
+}
+
+fn set_last_error( error: String ) {
+    let error = CString::new( error.replace( '\0', "" ) ).unwrap();
+    LAST_ERROR.with( |last_error| *last_error.borrow_mut() = Some( error ) );
+}
+
+fn to_c_string( string: &str ) -> CString {
+    CString::new( string.replace( '\0', "" ) ).unwrap()
+}
+
+/// Runs the `callback`, returning `on_panic` if it panics, since unwinding into C is undefined behavior.
+fn guard< R >( on_panic: R, callback: impl FnOnce() -> R ) -> R {
+    match panic::catch_unwind( AssertUnwindSafe( callback ) ) {
+        Ok( value ) => value,
+        Err( payload ) => {
+            let message = payload.downcast_ref::< &str >().map( |message| message.to_string() )
+                .or_else( || payload.downcast_ref::< String >().cloned() )
+                .unwrap_or_else( || "unknown panic".into() );
+
+            set_last_error( format!( "internal error: {}", message ) );
+            on_panic
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn memory_profiler_capture_open( path: *const c_char ) -> *mut CaptureHandle {
+    if path.is_null() {
+        set_last_error( "the path is NULL".into() );
+        return ptr::null_mut();
+    }
+
+    let path = match CStr::from_ptr( path ).to_str() {
+        Ok( path ) => path,
+        Err( _ ) => {
+            set_last_error( "the path is not valid UTF-8".into() );
+            return ptr::null_mut();
+        }
+    };
+
+    guard( ptr::null_mut(), || match Capture::open( path ) {
+        Ok( capture ) => {
+            let handle = CaptureHandle {
+                executable: to_c_string( &capture.header().executable ),
+                architecture: to_c_string( &capture.header().architecture ),
+                capture,
+                addresses: Vec::new()
+            };
+
+            Box::into_raw( Box::new( handle ) )
+        },
+        Err( error ) => {
+            set_last_error( format!( "failed to open {:?}: {}", path, error ) );
+            ptr::null_mut()
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn memory_profiler_capture_close( handle: *mut CaptureHandle ) {
+    if !handle.is_null() {
+        guard( (), || drop( Box::from_raw( handle ) ) );
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn memory_profiler_capture_next( handle: *mut CaptureHandle, output: *mut RawEvent ) -> c_int {
+    if handle.is_null() || output.is_null() {
+        set_last_error( "the capture or the event is NULL".into() );
+        return -1;
+    }
+
+    let handle = &mut *handle;
+    let output = &mut *output;
+    guard( -1, || next_event( handle, output ) )
+}
+
+fn next_event( handle: &mut CaptureHandle, output: &mut RawEvent ) -> c_int {
+    loop {
+        let event = match handle.capture.next() {
+            None => return 0,
+            Some( Ok( event ) ) => event,
+            Some( Err( error ) ) => {
+                set_last_error( format!( "failed to read the next event: {}", error ) );
+                return -1;
+            }
+        };
+
+        *output = RawEvent {
+            kind: 0,
+            thread: 0,
+            timestamp: 0,
+            pointer: 0,
+            old_pointer: 0,
+            size: 0,
+            backtrace: 0,
+            value: 0,
+            flags: 0,
+            has_backtrace: 0,
+            addresses: ptr::null(),
+            address_count: 0
+        };
+
+        match event {
+            Event::Allocation { timestamp, thread, pointer, size, backtrace, flags } => {
+                output.kind = MEMORY_PROFILER_EVENT_ALLOCATION;
+                output.timestamp = timestamp;
+                output.thread = thread;
+                output.pointer = pointer;
+                output.size = size;
+                output.backtrace = backtrace;
+                output.has_backtrace = 1;
+                output.flags = flags;
+            },
+            Event::Reallocation { timestamp, thread, old_pointer, pointer, size, backtrace, flags } => {
+                output.kind = MEMORY_PROFILER_EVENT_REALLOCATION;
+                output.timestamp = timestamp;
+                output.thread = thread;
+                output.old_pointer = old_pointer;
+                output.pointer = pointer;
+                output.size = size;
+                output.backtrace = backtrace;
+                output.has_backtrace = 1;
+                output.flags = flags;
+            },
+            Event::Deallocation { timestamp, thread, pointer, backtrace } => {
+                output.kind = MEMORY_PROFILER_EVENT_DEALLOCATION;
+                output.timestamp = timestamp;
+                output.thread = thread;
+                output.pointer = pointer;
+                if let Some( backtrace ) = backtrace {
+                    output.backtrace = backtrace;
+                    output.has_backtrace = 1;
+                }
+            },
+            Event::Backtrace { id, addresses } => {
+                handle.addresses = addresses;
+                output.kind = MEMORY_PROFILER_EVENT_BACKTRACE;
+                output.backtrace = id;
+                output.has_backtrace = 1;
+                output.addresses = handle.addresses.as_ptr();
+                output.address_count = handle.addresses.len();
+            },
+            Event::MemoryMap { timestamp, thread, pointer, length, backtrace } => {
+                output.kind = MEMORY_PROFILER_EVENT_MEMORY_MAP;
+                output.timestamp = timestamp;
+                output.thread = thread;
+                output.pointer = pointer;
+                output.size = length;
+                output.backtrace = backtrace;
+                output.has_backtrace = 1;
+            },
+            Event::MemoryUnmap { timestamp, thread, pointer, length, backtrace } => {
+                output.kind = MEMORY_PROFILER_EVENT_MEMORY_UNMAP;
+                output.timestamp = timestamp;
+                output.thread = thread;
+                output.pointer = pointer;
+                output.size = length;
+                output.backtrace = backtrace;
+                output.has_backtrace = 1;
+            },
+            Event::MemoryRemap { timestamp, thread, old_pointer, old_length, pointer, length, flags, backtrace } => {
+                output.kind = MEMORY_PROFILER_EVENT_MEMORY_REMAP;
+                output.timestamp = timestamp;
+                output.thread = thread;
+                output.old_pointer = old_pointer;
+                output.value = old_length;
+                output.pointer = pointer;
+                output.size = length;
+                output.flags = flags;
+                output.backtrace = backtrace;
+                output.has_backtrace = 1;
+            },
+            Event::Marker { value } => {
+                output.kind = MEMORY_PROFILER_EVENT_MARKER;
+                output.value = value as u64;
+            },
+            Event::WallClock { timestamp, secs, nsecs } => {
+                output.kind = MEMORY_PROFILER_EVENT_WALL_CLOCK;
+                output.timestamp = timestamp;
+                output.value = secs;
+                output.size = nsecs;
+            },
+            // The maps, the binaries and the symbols aren't exposed through the C API.
+            _ => continue
+        }
+
+        return 1;
+    }
+}
+
+// None of the getters below can panic, so they don't need to be guarded.
+
+#[no_mangle]
+pub unsafe extern "C" fn memory_profiler_capture_executable( handle: *const CaptureHandle ) -> *const c_char {
+    handle.as_ref().map( |handle| handle.executable.as_ptr() ).unwrap_or( ptr::null() )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn memory_profiler_capture_architecture( handle: *const CaptureHandle ) -> *const c_char {
+    handle.as_ref().map( |handle| handle.architecture.as_ptr() ).unwrap_or( ptr::null() )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn memory_profiler_capture_pid( handle: *const CaptureHandle ) -> u32 {
+    handle.as_ref().map( |handle| handle.capture.header().pid ).unwrap_or( 0 )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn memory_profiler_capture_initial_timestamp( handle: *const CaptureHandle ) -> u64 {
+    handle.as_ref().map( |handle| handle.capture.header().initial_timestamp ).unwrap_or( 0 )
+}
+
+#[no_mangle]
+pub extern "C" fn memory_profiler_capture_last_error() -> *const c_char {
+    LAST_ERROR.with( |last_error| {
+        last_error.borrow().as_ref().map( |error| error.as_ptr() ).unwrap_or( ptr::null() )
+    })
+}
+
+#[cfg(test)]
+fn last_error() -> Option< String > {
+    let error = memory_profiler_capture_last_error();
+    if error.is_null() {
+        None
+    } else {
+        Some( unsafe { CStr::from_ptr( error ) }.to_str().unwrap().to_owned() )
+    }
+}
+
+#[test]
+fn test_event_layout() {
+    // Has to match `memory_profiler_event` from the header.
+    assert_eq!( std::mem::size_of::< RawEvent >(), 64 + 2 * std::mem::size_of::< usize >() );
+}
+
+#[test]
+fn test_capture_api() {
+    use common::Timestamp;
+    use common::chunked_stream::ChunkedWriter;
+    use common::event::{self, AllocBody, DataId, HeaderBody, HEADER_FLAG_IS_LITTLE_ENDIAN};
+
+    let header = HeaderBody {
+        id: DataId::new( 1, 2 ),
+        initial_timestamp: Timestamp::from_secs( 1 ),
+        timestamp: Timestamp::from_secs( 2 ),
+        wall_clock_secs: 1000,
+        wall_clock_nsecs: 0,
+        pid: 123,
+        cmdline: b"./a.out\0".to_vec(),
+        executable: b"/usr/bin/a.out".to_vec(),
+        arch: "x86_64".to_owned(),
+        flags: HEADER_FLAG_IS_LITTLE_ENDIAN,
+        pointer_size: 8
+    };
+
+    let allocation = AllocBody { pointer: 0x1000, size: 16, backtrace: 1, thread: 2, flags: 0, extra_usable_space: 0, preceding_free_space: 0 };
+    let mut writer = ChunkedWriter::new( Vec::new() ).unwrap();
+    writer.write_event( &event::Event::Header( header ) ).unwrap();
+    writer.write_event( &event::Event::Backtrace { id: 1, addresses: vec![ 0x10, 0x20 ].into() } ).unwrap();
+    writer.write_event( &event::Event::String { id: 0, string: "skipped".into() } ).unwrap();
+    writer.write_event( &event::Event::Alloc { timestamp: Timestamp::from_secs( 3 ), allocation } ).unwrap();
+    writer.write_event( &event::Event::Free { timestamp: Timestamp::from_secs( 4 ), pointer: 0x1000, backtrace: 99, thread: 2 } ).unwrap();
+    writer.write_event( &event::Event::Marker { value: 7 } ).unwrap();
+
+    let path = std::env::temp_dir().join( format!( "memory-profiler-capture-ffi-test-{}.dat", std::process::id() ) );
+    std::fs::write( &path, writer.finish().unwrap() ).unwrap();
+    let c_path = CString::new( path.to_str().unwrap() ).unwrap();
+
+    unsafe {
+        assert!( memory_profiler_capture_open( ptr::null() ).is_null() );
+        assert_eq!( last_error().unwrap(), "the path is NULL" );
+
+        let missing = CString::new( "/nonexistent/memory-profiling.dat" ).unwrap();
+        assert!( memory_profiler_capture_open( missing.as_ptr() ).is_null() );
+        assert!( last_error().unwrap().starts_with( "failed to open" ) );
+
+        let handle = memory_profiler_capture_open( c_path.as_ptr() );
+        let _ = std::fs::remove_file( &path );
+        assert!( !handle.is_null() );
+
+        assert_eq!( CStr::from_ptr( memory_profiler_capture_executable( handle ) ).to_str().unwrap(), "/usr/bin/a.out" );
+        assert_eq!( CStr::from_ptr( memory_profiler_capture_architecture( handle ) ).to_str().unwrap(), "x86_64" );
+        assert_eq!( memory_profiler_capture_pid( handle ), 123 );
+        assert_eq!( memory_profiler_capture_initial_timestamp( handle ), 1_000_000 );
+
+        let mut event: RawEvent = std::mem::zeroed();
+        assert_eq!( memory_profiler_capture_next( handle, &mut event ), 1 );
+        assert_eq!( event.kind, MEMORY_PROFILER_EVENT_BACKTRACE );
+        assert_eq!( event.backtrace, 1 );
+        assert_eq!( std::slice::from_raw_parts( event.addresses, event.address_count ), &[ 0x10, 0x20 ] );
+
+        assert_eq!( memory_profiler_capture_next( handle, &mut event ), 1 );
+        assert_eq!( event.kind, MEMORY_PROFILER_EVENT_ALLOCATION );
+        assert_eq!( (event.timestamp, event.thread, event.pointer, event.size, event.backtrace, event.has_backtrace), (3_000_000, 2, 0x1000, 16, 1, 1) );
+        assert!( event.addresses.is_null() );
+
+        assert_eq!( memory_profiler_capture_next( handle, &mut event ), 1 );
+        assert_eq!( event.kind, MEMORY_PROFILER_EVENT_DEALLOCATION );
+        assert_eq!( (event.timestamp, event.pointer, event.has_backtrace), (4_000_000, 0x1000, 0) );
+
+        assert_eq!( memory_profiler_capture_next( handle, &mut event ), 1 );
+        assert_eq!( event.kind, MEMORY_PROFILER_EVENT_MARKER );
+        assert_eq!( event.value, 7 );
+
+        assert_eq!( memory_profiler_capture_next( handle, &mut event ), 0 );
+        assert_eq!( memory_profiler_capture_next( handle, &mut event ), 0 );
+
+        assert_eq!( memory_profiler_capture_next( handle, ptr::null_mut() ), -1 );
+        assert_eq!( memory_profiler_capture_next( ptr::null_mut(), &mut event ), -1 );
+        assert!( memory_profiler_capture_executable( ptr::null() ).is_null() );
+        assert_eq!( memory_profiler_capture_pid( ptr::null() ), 0 );
+
+        memory_profiler_capture_close( handle );
+        memory_profiler_capture_close( ptr::null_mut() );
+    }
+}
+
+#[test]
+fn test_guard() {
+    assert_eq!( guard( -1, || 1 ), 1 );
+    assert_eq!( guard( -1, || panic!( "failure" ) ), -1 );
+    assert_eq!( last_error().unwrap(), "internal error: failure" );
+}
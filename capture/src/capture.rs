@@ -0,0 +1,455 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use common::event::{self, FramesInvalidated, HeaderBody, HEADER_FLAG_IS_LITTLE_ENDIAN};
+
+use crate::maps::{MapRegion, parse_maps};
+use crate::raw::{DataLoss, parse_events_with_data_loss};
+
+/// The basic information about the profiled process.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Header {
+    /// A unique ID of the data file, as a 32 character hex string.
+    pub id: String,
+    pub pid: u32,
+    pub executable: String,
+    pub cmdline: Vec< String >,
+    pub architecture: String,
+    pub pointer_size: u8,
+    pub is_little_endian: bool,
+    /// When the profiling started, in microseconds of the process' monotonic clock.
+    pub initial_timestamp: u64,
+    /// When the header was written, in microseconds of the process' monotonic clock.
+    pub timestamp: u64,
+    /// The wall clock time (as seconds and nanoseconds since the epoch) at `timestamp`.
+    pub wall_clock_secs: u64,
+    pub wall_clock_nsecs: u64
+}
+
+/// A single frame of a decoded address, from the symbols embedded in the data file.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SymbolFrame {
+    pub function: Option< String >,
+    /// The function's name before demangling.
+    pub raw_function: Option< String >,
+    pub source: Option< String >,
+    pub line: Option< u32 >,
+    pub column: Option< u32 >,
+    pub is_inline: bool
+}
+
+/// The frames an address resolves to, starting from the innermost one.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Symbol {
+    /// The address relative to the library, as if it was mapped at its file offsets.
+    pub address: u64,
+    pub frames: Vec< SymbolFrame >
+}
+
+/// All of the events are timestamped in microseconds of the profiled process' monotonic clock.
+///
+/// New kinds of events can be added in a minor release, so matches on this should always have a wildcard arm.
+#[non_exhaustive]
+#[derive(Clone, PartialEq, Debug)]
+pub enum Event {
+    Allocation {
+        timestamp: u64,
+        thread: u32,
+        pointer: u64,
+        size: u64,
+        /// The ID of a backtrace which was previously returned by an `Event::Backtrace`.
+        backtrace: u64,
+        flags: u32
+    },
+    Reallocation {
+        timestamp: u64,
+        thread: u32,
+        old_pointer: u64,
+        pointer: u64,
+        size: u64,
+        backtrace: u64,
+        flags: u32
+    },
+    Deallocation {
+        timestamp: u64,
+        thread: u32,
+        pointer: u64,
+        /// Only present when the backtrace of the deallocation was recorded.
+        backtrace: Option< u64 >
+    },
+    /// Always emitted before the first event which refers to the backtrace.
+    Backtrace {
+        id: u64,
+        /// The return addresses, starting from the innermost frame.
+        addresses: Vec< u64 >
+    },
+    MemoryMap {
+        timestamp: u64,
+        thread: u32,
+        pointer: u64,
+        length: u64,
+        backtrace: u64
+    },
+    MemoryUnmap {
+        timestamp: u64,
+        thread: u32,
+        pointer: u64,
+        length: u64,
+        backtrace: u64
+    },
+    /// A mapping was resized or moved with `mremap`.
+    MemoryRemap {
+        timestamp: u64,
+        thread: u32,
+        old_pointer: u64,
+        old_length: u64,
+        pointer: u64,
+        length: u64,
+        flags: u32,
+        backtrace: u64
+    },
+    /// A new snapshot of the process' memory maps, which replaces any previous one.
+    MemoryMaps {
+        timestamp: u64,
+        regions: Vec< MapRegion >
+    },
+    /// A copy of a binary loaded by the profiled process.
+    Binary {
+        path: String,
+        contents: Vec< u8 >
+    },
+    /// The symbols of a library, if they were embedded into the data file.
+    SymbolTable {
+        library: String,
+        build_id: Vec< u8 >,
+        symbols: Vec< Symbol >
+    },
+    Marker {
+        value: u32
+    },
+    WallClock {
+        timestamp: u64,
+        secs: u64,
+        nsecs: u64
+    }
+}
+
+fn lossy( bytes: &[u8] ) -> String {
+    String::from_utf8_lossy( bytes ).into_owned()
+}
+
+impl Header {
+    fn new( header: HeaderBody ) -> Self {
+        Header {
+            id: header.id.to_string(),
+            pid: header.pid,
+            executable: lossy( &header.executable ),
+            cmdline: header.cmdline.split( |&byte| byte == 0 ).filter( |arg| !arg.is_empty() ).map( lossy ).collect(),
+            architecture: header.arch,
+            pointer_size: header.pointer_size,
+            is_little_endian: header.flags & HEADER_FLAG_IS_LITTLE_ENDIAN != 0,
+            initial_timestamp: header.initial_timestamp.as_usecs(),
+            timestamp: header.timestamp.as_usecs(),
+            wall_clock_secs: header.wall_clock_secs,
+            wall_clock_nsecs: header.wall_clock_nsecs
+        }
+    }
+}
+
+/// A reader of a data file generated by the profiler.
+///
+/// ```no_run
+/// use memory_profiler_capture::{Capture, Event};
+///
+/// let capture = Capture::open( "memory-profiling_app_1583340812_19876.dat" )?;
+/// println!( "Profiled: {}", capture.header().executable );
+///
+/// let mut total = 0;
+/// for event in capture {
+///     if let Event::Allocation { size, .. } = event? {
+///         total += size;
+///     }
+/// }
+///
+/// println!( "Allocated {} bytes in total", total );
+/// # Ok::< (), std::io::Error >(())
+/// ```
+pub struct Capture {
+    header: Header,
+    events: Box< dyn Iterator< Item = io::Result< event::Event< 'static > > > + Send >,
+    data_loss: Arc< DataLoss >,
+    previous_backtrace_on_thread: HashMap< u32, Vec< u64 > >,
+    known_backtraces: HashSet< u64 >
+}
+
+impl Capture {
+    /// Opens a data file; encrypted data files are decrypted with the identities
+    /// from the file pointed to by `MEMORY_PROFILER_IDENTITY_FILE`.
+    pub fn open< P: AsRef< Path > >( path: P ) -> io::Result< Self > {
+        Self::from_reader( File::open( path )? )
+    }
+
+    /// Reads a data file from an arbitrary stream.
+    pub fn from_reader< R: Read + Send + 'static >( fp: R ) -> io::Result< Self > {
+        let (header, events, data_loss) = parse_events_with_data_loss( fp )?;
+        Ok( Capture {
+            header: Header::new( header ),
+            events: Box::new( events ),
+            data_loss,
+            previous_backtrace_on_thread: HashMap::new(),
+            known_backtraces: HashSet::new()
+        })
+    }
+
+    pub fn header( &self ) -> &Header {
+        &self.header
+    }
+
+    /// The number of damaged chunks of the data file which were skipped so far;
+    /// it's only final once all of the events were read.
+    pub fn lost_chunk_count( &self ) -> u64 {
+        self.data_loss.chunks()
+    }
+
+    /// The number of events in the damaged chunks which were skipped so far, as far as it's known.
+    pub fn lost_event_count( &self ) -> u64 {
+        self.data_loss.events()
+    }
+
+    fn expand_partial_backtrace( &mut self, thread: u32, frames_invalidated: FramesInvalidated, partial_addresses: impl Iterator< Item = u64 > ) -> Vec< u64 > {
+        let previous = self.previous_backtrace_on_thread.entry( thread ).or_insert( Vec::new() );
+        let addresses: Vec< u64 > = match frames_invalidated {
+            FramesInvalidated::All => partial_addresses.collect(),
+            FramesInvalidated::Some( frames_invalidated ) => {
+                partial_addresses.chain( previous.iter().cloned().skip( frames_invalidated as usize ) ).collect()
+            }
+        };
+
+        *previous = addresses.clone();
+        addresses
+    }
+
+    fn convert( &mut self, event: event::Event< 'static > ) -> Option< Event > {
+        let event = match event {
+            event::Event::Alloc { timestamp, allocation } |
+            event::Event::AllocEx { timestamp, allocation, .. } => Event::Allocation {
+                timestamp: timestamp.as_usecs(),
+                thread: allocation.thread,
+                pointer: allocation.pointer,
+                size: allocation.size,
+                backtrace: allocation.backtrace,
+                flags: allocation.flags
+            },
+            event::Event::Realloc { timestamp, old_pointer, allocation } |
+            event::Event::ReallocEx { timestamp, old_pointer, allocation, .. } => Event::Reallocation {
+                timestamp: timestamp.as_usecs(),
+                thread: allocation.thread,
+                old_pointer,
+                pointer: allocation.pointer,
+                size: allocation.size,
+                backtrace: allocation.backtrace,
+                flags: allocation.flags
+            },
+            event::Event::Free { timestamp, pointer, backtrace, thread } |
+            event::Event::FreeEx { timestamp, pointer, backtrace, thread, .. } => Event::Deallocation {
+                timestamp: timestamp.as_usecs(),
+                thread,
+                pointer,
+                backtrace: if self.known_backtraces.contains( &backtrace ) { Some( backtrace ) } else { None }
+            },
+            event::Event::Backtrace { id, addresses } => Event::Backtrace {
+                id,
+                addresses: addresses.into_owned()
+            },
+            event::Event::Backtrace32 { id, addresses } => Event::Backtrace {
+                id,
+                addresses: addresses.iter().map( |&address| address as u64 ).collect()
+            },
+            event::Event::PartialBacktrace { id, thread, frames_invalidated, addresses } => Event::Backtrace {
+                id,
+                addresses: self.expand_partial_backtrace( thread, frames_invalidated, addresses.iter().cloned() )
+            },
+            event::Event::PartialBacktrace32 { id, thread, frames_invalidated, addresses } => Event::Backtrace {
+                id,
+                addresses: self.expand_partial_backtrace( thread, frames_invalidated, addresses.iter().map( |&address| address as u64 ) )
+            },
+            event::Event::MemoryMap { timestamp, pointer, length, backtrace, thread, .. } => Event::MemoryMap {
+                timestamp: timestamp.as_usecs(),
+                thread,
+                pointer,
+                length,
+                backtrace
+            },
+            event::Event::MemoryUnmap { timestamp, pointer, length, backtrace, thread } => Event::MemoryUnmap {
+                timestamp: timestamp.as_usecs(),
+                thread,
+                pointer,
+                length,
+                backtrace
+            },
+            event::Event::MemoryRemap { timestamp, old_pointer, old_length, pointer, length, flags, backtrace, thread } => Event::MemoryRemap {
+                timestamp: timestamp.as_usecs(),
+                thread,
+                old_pointer,
+                old_length,
+                pointer,
+                length,
+                flags,
+                backtrace
+            },
+            event::Event::File { timestamp, path, contents } => {
+                if path == "/proc/self/maps" {
+                    Event::MemoryMaps {
+                        timestamp: timestamp.as_usecs(),
+                        regions: parse_maps( &String::from_utf8_lossy( &contents ) )
+                    }
+                } else if contents.starts_with( b"\x7FELF" ) {
+                    Event::Binary {
+                        path: path.into_owned(),
+                        contents: contents.into_owned()
+                    }
+                } else {
+                    return None;
+                }
+            },
+            event::Event::SymbolTable { build_id, library, strings, entries } => {
+                let string = |index: u32| strings.get( index as usize ).map( |string| string.clone().into_owned() );
+                let number = |value: u32| if value == 0xFFFFFFFF { None } else { Some( value ) };
+                let symbols = entries.into_iter().map( |entry| Symbol {
+                    address: entry.address,
+                    frames: entry.frames.into_iter().map( |frame| SymbolFrame {
+                        function: string( frame.function ),
+                        raw_function: string( frame.raw_function ),
+                        source: string( frame.source ),
+                        line: number( frame.line ),
+                        column: number( frame.column ),
+                        is_inline: frame.is_inline
+                    }).collect()
+                }).collect();
+
+                Event::SymbolTable {
+                    library: library.into_owned(),
+                    build_id: build_id.into_owned(),
+                    symbols
+                }
+            },
+            event::Event::Marker { value } => Event::Marker { value },
+            event::Event::WallClock { timestamp, sec, nsec } => Event::WallClock {
+                timestamp: timestamp.as_usecs(),
+                secs: sec,
+                nsecs: nsec
+            },
+            _ => return None
+        };
+
+        if let Event::Backtrace { id, .. } = &event {
+            self.known_backtraces.insert( *id );
+        }
+
+        Some( event )
+    }
+}
+
+impl Iterator for Capture {
+    type Item = io::Result< Event >;
+
+    fn next( &mut self ) -> Option< Self::Item > {
+        loop {
+            let event = match self.events.next()? {
+                Ok( event ) => event,
+                Err( error ) => return Some( Err( error ) )
+            };
+
+            if let Some( event ) = self.convert( event ) {
+                return Some( Ok( event ) );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_capture() {
+    use common::Timestamp;
+    use common::chunked_stream::ChunkedWriter;
+    use common::event::{AllocBody, DataId};
+
+    let header = HeaderBody {
+        id: DataId::new( 1, 2 ),
+        initial_timestamp: Timestamp::from_secs( 1 ),
+        timestamp: Timestamp::from_secs( 2 ),
+        wall_clock_secs: 1000,
+        wall_clock_nsecs: 500,
+        pid: 123,
+        cmdline: b"./a.out\0--verbose\0".to_vec(),
+        executable: b"/usr/bin/a.out".to_vec(),
+        arch: "x86_64".to_owned(),
+        flags: HEADER_FLAG_IS_LITTLE_ENDIAN,
+        pointer_size: 8
+    };
+
+    let allocation = |pointer, backtrace| AllocBody { pointer, size: 16, backtrace, thread: 1, flags: 0, extra_usable_space: 0, preceding_free_space: 0 };
+    let events = vec![
+        event::Event::Header( header ),
+        event::Event::File { timestamp: Timestamp::from_secs( 2 ), path: "/proc/self/maps".into(), contents: b"00400000-00452000 r-xp 00000000 08:02 173521 /usr/bin/a.out\n".to_vec().into() },
+        event::Event::String { id: 0, string: "ignored".into() },
+        event::Event::Backtrace { id: 1, addresses: vec![ 0x10, 0x20 ].into() },
+        event::Event::PartialBacktrace { id: 2, thread: 1, frames_invalidated: FramesInvalidated::All, addresses: vec![ 0x30, 0x40 ].into() },
+        event::Event::PartialBacktrace { id: 3, thread: 1, frames_invalidated: FramesInvalidated::Some( 1 ), addresses: vec![ 0x50 ].into() },
+        event::Event::Alloc { timestamp: Timestamp::from_secs( 3 ), allocation: allocation( 0x1000, 1 ) },
+        event::Event::Realloc { timestamp: Timestamp::from_secs( 4 ), old_pointer: 0x1000, allocation: allocation( 0x2000, 3 ) },
+        event::Event::Free { timestamp: Timestamp::from_secs( 5 ), pointer: 0x2000, backtrace: 2, thread: 1 },
+        event::Event::Free { timestamp: Timestamp::from_secs( 5 ), pointer: 0x3000, backtrace: 99, thread: 1 },
+        event::Event::Marker { value: 7 },
+        event::Event::WallClock { timestamp: Timestamp::from_secs( 6 ), sec: 1004, nsec: 0 }
+    ];
+
+    let mut writer = ChunkedWriter::new( Vec::new() ).unwrap();
+    for event in &events {
+        writer.write_event( event ).unwrap();
+    }
+
+    let input = writer.finish().unwrap();
+    let capture = Capture::from_reader( io::Cursor::new( input ) ).unwrap();
+    assert_eq!( *capture.header(), Header {
+        id: "00000000000000010000000000000002".to_owned(),
+        pid: 123,
+        executable: "/usr/bin/a.out".to_owned(),
+        cmdline: vec![ "./a.out".to_owned(), "--verbose".to_owned() ],
+        architecture: "x86_64".to_owned(),
+        pointer_size: 8,
+        is_little_endian: true,
+        initial_timestamp: 1_000_000,
+        timestamp: 2_000_000,
+        wall_clock_secs: 1000,
+        wall_clock_nsecs: 500
+    });
+
+    let events = capture.collect::< io::Result< Vec< _ > > >().unwrap();
+    assert_eq!( events, vec![
+        Event::MemoryMaps { timestamp: 2_000_000, regions: parse_maps( "00400000-00452000 r-xp 00000000 08:02 173521 /usr/bin/a.out\n" ) },
+        Event::Backtrace { id: 1, addresses: vec![ 0x10, 0x20 ] },
+        Event::Backtrace { id: 2, addresses: vec![ 0x30, 0x40 ] },
+        // The partial backtrace is expanded with the frames of the previous one from the same thread.
+        Event::Backtrace { id: 3, addresses: vec![ 0x50, 0x40 ] },
+        Event::Allocation { timestamp: 3_000_000, thread: 1, pointer: 0x1000, size: 16, backtrace: 1, flags: 0 },
+        Event::Reallocation { timestamp: 4_000_000, thread: 1, old_pointer: 0x1000, pointer: 0x2000, size: 16, backtrace: 3, flags: 0 },
+        Event::Deallocation { timestamp: 5_000_000, thread: 1, pointer: 0x2000, backtrace: Some( 2 ) },
+        // The backtraces of the deallocations which weren't recorded aren't exposed.
+        Event::Deallocation { timestamp: 5_000_000, thread: 1, pointer: 0x3000, backtrace: None },
+        Event::Marker { value: 7 },
+        Event::WallClock { timestamp: 6_000_000, secs: 1004, nsecs: 0 }
+    ]);
+}
+
+#[test]
+fn test_capture_without_header() {
+    use common::chunked_stream::ChunkedWriter;
+
+    let mut writer = ChunkedWriter::new( Vec::new() ).unwrap();
+    writer.write_event( &event::Event::Marker { value: 1 } ).unwrap();
+    assert!( Capture::from_reader( io::Cursor::new( writer.finish().unwrap() ) ).is_err() );
+    assert!( Capture::from_reader( io::Cursor::new( Vec::new() ) ).is_err() );
+}
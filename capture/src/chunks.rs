@@ -0,0 +1,98 @@
+use std::io::{self, Read};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use byteorder::{ReadBytesExt, LittleEndian};
+
+use common::chunked_stream::{CHUNK_HEADER_SIZE, ChunkContents, chunk_contents};
+
+/// Keeps track of the damaged chunks which had to be skipped.
+///
+/// It's shared with the reading thread, so it can be checked once the whole stream was read.
+#[derive(Default)]
+pub struct DataLoss {
+    chunks: AtomicU64,
+    bytes: AtomicU64,
+    events: AtomicU64
+}
+
+impl DataLoss {
+    pub(crate) fn add( &self, bytes: u64, events: u64 ) {
+        self.chunks.fetch_add( 1, Ordering::Relaxed );
+        self.bytes.fetch_add( bytes, Ordering::Relaxed );
+        self.events.fetch_add( events, Ordering::Relaxed );
+    }
+
+    /// The number of chunks which were skipped.
+    pub fn chunks( &self ) -> u64 {
+        self.chunks.load( Ordering::Relaxed )
+    }
+
+    /// The total size of the skipped chunks, in bytes.
+    pub fn bytes( &self ) -> u64 {
+        self.bytes.load( Ordering::Relaxed )
+    }
+
+    /// The number of events in the skipped chunks, as far as it's known.
+    pub fn events( &self ) -> u64 {
+        self.events.load( Ordering::Relaxed )
+    }
+
+    pub fn report( &self ) {
+        if self.chunks() > 0 {
+            warn!(
+                "The data file is damaged; skipped {} chunks with a total of {} bytes and {} events",
+                self.chunks(),
+                self.bytes(),
+                self.events()
+            );
+        }
+    }
+}
+
+/// Reads the next chunk of events, skipping any damaged ones; returns `None` at the end of the stream.
+pub fn read_chunk( fp: &mut impl io::Read, buffer: &mut Vec< u8 >, data_loss: &DataLoss ) -> Result< Option< (Vec< u8 >, bool) >, io::Error > {
+    loop {
+        let kind = match fp.read_u8() {
+            Ok( kind ) => kind,
+            Err( ref error ) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok( None ),
+            Err( error ) => return Err( error )
+        };
+
+        // A chunk cut short is what's left when the profiled process was killed in the middle of writing it.
+        let length = match fp.read_u32::< LittleEndian >() {
+            Ok( length ) => length as usize,
+            Err( ref error ) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                warn!( "The data file is truncated in the middle of a chunk header" );
+                data_loss.add( 1, 0 );
+                return Ok( None );
+            },
+            Err( error ) => return Err( error )
+        };
+
+        buffer.clear();
+        let read = fp.by_ref().take( length as u64 ).read_to_end( buffer )?;
+        if read != length {
+            warn!( "The data file is truncated; the last chunk has only {} out of {} bytes", read, length );
+            data_loss.add( (CHUNK_HEADER_SIZE + read) as u64, 0 );
+            return Ok( None );
+        }
+
+        let (offset, is_compressed) = match chunk_contents( kind, buffer )? {
+            ChunkContents::Compressed( offset ) => (offset, true),
+            ChunkContents::Uncompressed( offset ) => (offset, false),
+            ChunkContents::Metadata => continue,
+            ChunkContents::Damaged( event_count ) => {
+                warn!( "Skipping a damaged chunk of {} bytes with {} events", CHUNK_HEADER_SIZE + length, event_count );
+                data_loss.add( (CHUNK_HEADER_SIZE + length) as u64, event_count as u64 );
+                continue;
+            }
+        };
+
+        let mut chunk = mem::replace( buffer, Vec::new() );
+        if offset != 0 {
+            chunk.drain( ..offset );
+        }
+
+        return Ok( Some( (chunk, is_compressed) ) );
+    }
+}
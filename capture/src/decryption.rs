@@ -0,0 +1,77 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+#[cfg(feature = "encryption")]
+use std::{env, fs};
+
+/// The magic string every file encrypted with `age` starts with.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+/// The environment variable which points to a file with the identities used to decrypt the data.
+#[cfg(feature = "encryption")]
+const IDENTITY_FILE_VAR: &str = "MEMORY_PROFILER_IDENTITY_FILE";
+
+#[cfg(feature = "encryption")]
+fn invalid_data< E: ToString >( error: E ) -> io::Error {
+    io::Error::new( io::ErrorKind::InvalidData, error.to_string() )
+}
+
+#[cfg(feature = "encryption")]
+fn load_identities() -> io::Result< Vec< age::x25519::Identity > > {
+    let path = env::var_os( IDENTITY_FILE_VAR ).ok_or_else( || {
+        io::Error::new( io::ErrorKind::Other, format!( "the data file is encrypted; set {} to decrypt it", IDENTITY_FILE_VAR ) )
+    })?;
+
+    let contents = fs::read_to_string( &path )?;
+    let identities: Vec< age::x25519::Identity > = contents.lines()
+        .map( |line| line.trim() )
+        .filter( |line| !line.is_empty() && !line.starts_with( "#" ) )
+        .map( |line| line.parse().map_err( invalid_data ) )
+        .collect::< io::Result< _ > >()?;
+
+    if identities.is_empty() {
+        return Err( invalid_data( format!( "no identities found in {:?}", path ) ) );
+    }
+
+    Ok( identities )
+}
+
+/// Checks whether a given data file is encrypted.
+pub fn is_encrypted( path: &Path ) -> io::Result< bool > {
+    let mut prefix = Vec::with_capacity( AGE_MAGIC.len() );
+    File::open( path )?.take( AGE_MAGIC.len() as u64 ).read_to_end( &mut prefix )?;
+    Ok( prefix == AGE_MAGIC )
+}
+
+/// Wraps the data in a decrypting reader if it's encrypted; otherwise returns it as-is.
+pub(crate) fn decrypt_if_encrypted< F: Read + Send + 'static >( mut fp: F ) -> io::Result< Box< dyn Read + Send > > {
+    let mut prefix = Vec::with_capacity( AGE_MAGIC.len() );
+    (&mut fp).take( AGE_MAGIC.len() as u64 ).read_to_end( &mut prefix )?;
+
+    let is_encrypted = prefix == AGE_MAGIC;
+    let fp = io::Cursor::new( prefix ).chain( fp );
+    if !is_encrypted {
+        return Ok( Box::new( fp ) );
+    }
+
+    decrypt( fp )
+}
+
+#[cfg(feature = "encryption")]
+fn decrypt< F: Read + Send + 'static >( fp: F ) -> io::Result< Box< dyn Read + Send > > {
+    info!( "The data file is encrypted; decrypting..." );
+    let identities = load_identities()?;
+    let decryptor = match age::Decryptor::new( fp ).map_err( invalid_data )? {
+        age::Decryptor::Recipients( decryptor ) => decryptor,
+        _ => return Err( invalid_data( "data files encrypted with a passphrase are not supported" ) )
+    };
+
+    let fp = decryptor.decrypt( identities.iter().map( |identity| identity as &dyn age::Identity ) ).map_err( invalid_data )?;
+    Ok( Box::new( fp ) )
+}
+
+#[cfg(not(feature = "encryption"))]
+fn decrypt< F: Read + Send + 'static >( _fp: F ) -> io::Result< Box< dyn Read + Send > > {
+    Err( io::Error::new( io::ErrorKind::Other, "the data file is encrypted and the support for encryption wasn't compiled in" ) )
+}
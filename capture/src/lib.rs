@@ -0,0 +1,25 @@
+//! A reader for the data files generated by the memory profiler.
+//!
+//! The types exported from the root of this crate follow semver; the on-disk
+//! format itself is not stable, so the `raw` module, which exposes it directly,
+//! can change in any release.
+
+#[macro_use]
+extern crate log;
+
+mod chunks;
+#[cfg(not(target_arch = "wasm32"))]
+mod lz4_reader;
+#[cfg(target_arch = "wasm32")]
+#[path = "lz4_reader_sequential.rs"]
+mod lz4_reader;
+mod decryption;
+mod maps;
+mod capture;
+
+#[doc(hidden)]
+pub mod raw;
+
+pub use crate::capture::{Capture, Event, Header, Symbol, SymbolFrame};
+pub use crate::decryption::is_encrypted;
+pub use crate::maps::{MapRegion, parse_maps};
@@ -0,0 +1,208 @@
+use std::cmp::min;
+use std::io;
+use std::thread;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use lz4_compress;
+use parking_lot::Mutex;
+
+use common::chunked_stream::CHUNK_HEADER_SIZE;
+
+use crate::chunks::{DataLoss, read_chunk};
+
+/// Decompresses the data on multiple threads.
+pub struct Lz4Reader< F: io::Read + Send > {
+    phantom: PhantomData< F >,
+    output_rx: crossbeam_channel::Receiver< (u64, Vec< u8 >) >,
+    queue: Vec< (u64, Vec< u8 >) >,
+    counter: u64,
+    buffer: Vec< u8 >,
+    position: usize,
+    error: Arc< Mutex< Option< io::Error > > >,
+    data_loss: Arc< DataLoss >
+}
+
+/// Returns how many threads should be used to decompress the data, leaving one core for the consumer.
+fn decompression_thread_count() -> usize {
+    let cpu_count = unsafe { libc::sysconf( libc::_SC_NPROCESSORS_ONLN ) };
+    if cpu_count <= 1 {
+        return 1;
+    }
+
+    min( cpu_count as usize - 1, 16 )
+}
+
+impl< F: io::Read + Send + 'static > Lz4Reader< F > {
+    pub fn new( mut fp: F ) -> Self {
+        let thread_count = decompression_thread_count();
+        let (decompress_tx, decompress_rx) = crossbeam_channel::bounded( thread_count * 2 );
+        let (output_tx, output_rx) = crossbeam_channel::bounded( thread_count * 2 );
+        let error_arc = Arc::new( Mutex::new( None ) );
+        let error_arc_clone = error_arc.clone();
+        let data_loss = Arc::new( DataLoss::default() );
+        let data_loss_clone = data_loss.clone();
+
+        let output_tx_clone = output_tx.clone();
+        thread::spawn( move || {
+            let mut buffer = Vec::new();
+            let mut counter = 0;
+            loop {
+                let (chunk, is_compressed) = match read_chunk( &mut fp, &mut buffer, &data_loss_clone ) {
+                    Ok( Some( chunk ) ) => chunk,
+                    Ok( None ) => break,
+                    Err( ref error ) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                        warn!( "The data file is truncated; ignoring its incomplete last chunk" );
+                        break;
+                    },
+                    Err( error ) => {
+                        *error_arc_clone.lock() = Some( error );
+                        break;
+                    }
+                };
+
+                if is_compressed {
+                    if decompress_tx.send( (counter, chunk) ).is_err() {
+                        break;
+                    }
+                } else {
+                    if output_tx_clone.send( (counter, chunk) ).is_err() {
+                        break;
+                    }
+                }
+
+                counter += 1;
+            }
+
+            data_loss_clone.report();
+        });
+
+        for _ in 0..thread_count {
+            let decompress_rx = decompress_rx.clone();
+            let output_tx = output_tx.clone();
+            let data_loss = data_loss.clone();
+            thread::spawn( move || {
+                while let Ok( (counter, input) ) = decompress_rx.recv() {
+                    let mut output = Vec::new();
+                    if lz4_compress::decompress_into( &input, &mut output ).is_err() {
+                        // An empty chunk is still sent so that the reader doesn't wait for this one forever.
+                        warn!( "Skipping a chunk of {} bytes which failed to decompress", CHUNK_HEADER_SIZE + input.len() );
+                        data_loss.add( (CHUNK_HEADER_SIZE + input.len()) as u64, 0 );
+                        output.clear();
+                    }
+
+                    if output_tx.send( (counter, output) ).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        Lz4Reader {
+            phantom: PhantomData,
+            output_rx,
+            queue: Vec::new(),
+            counter: 0,
+            buffer: Vec::new(),
+            position: 0,
+            error: error_arc,
+            data_loss
+        }
+    }
+
+    /// Returns the statistics of the damaged chunks which were skipped so far.
+    pub fn data_loss( &self ) -> Arc< DataLoss > {
+        self.data_loss.clone()
+    }
+}
+
+impl< F: io::Read + Send > Lz4Reader< F > {
+    #[inline(always)]
+    fn read_cached( &mut self, buf: &mut [u8] ) -> usize {
+        let len = min( buf.len(), self.buffer.len() - self.position );
+        buf[ ..len ].copy_from_slice( &self.buffer[ self.position..self.position + len ] );
+        self.position += len;
+        len
+    }
+
+    #[inline(never)]
+    fn read_slow( &mut self, buf: &mut [u8] ) -> io::Result< usize > {
+        'outer: loop {
+            if self.buffer.len() - self.position > 0 {
+                return Ok( self.read_cached( buf ) );
+            }
+
+            let index = self.queue.iter().position( |(counter, _)| *counter == self.counter );
+            if let Some( index ) = index {
+                let (_, buffer) = self.queue.swap_remove( index );
+                self.buffer = buffer;
+                self.position = 0;
+                self.counter += 1;
+                continue;
+            }
+
+            loop {
+                let (counter, buffer) = match self.output_rx.recv() {
+                    Ok( (counter, buffer) ) => (counter, buffer),
+                    Err( .. ) => {
+                        if let Some( error ) = self.error.lock().take() {
+                            return Err( error );
+                        }
+
+                        return Ok( 0 );
+                    }
+                };
+
+                if counter == self.counter {
+                    self.buffer = buffer;
+                    self.position = 0;
+                    self.counter += 1;
+                    continue 'outer;
+                } else {
+                    self.queue.push( (counter, buffer) );
+                }
+            }
+        }
+    }
+
+    #[inline(never)]
+    fn read_exact_slow( &mut self, mut buf: &mut [u8] ) -> io::Result< () > {
+        while !buf.is_empty() {
+            match io::Read::read( self, buf ) {
+                Ok( 0 ) => break,
+                Ok( n ) => {
+                    let tmp = buf;
+                    buf = &mut tmp[n..];
+                }
+                Err( ref e ) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err( e ) => return Err( e )
+            }
+        }
+
+        if !buf.is_empty() {
+            Err( io::Error::new( io::ErrorKind::UnexpectedEof, "failed to fill whole buffer" ) )
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl< F: io::Read + Send > io::Read for Lz4Reader< F > {
+    #[inline(always)]
+    fn read( &mut self, buf: &mut [u8] ) -> io::Result< usize > {
+        if self.buffer.len() - self.position > 0 {
+            return Ok( self.read_cached( buf ) );
+        }
+
+        self.read_slow( buf )
+    }
+
+    #[inline(always)]
+    fn read_exact( &mut self, buf: &mut [u8] ) -> io::Result< () > {
+        if self.buffer.len() - self.position >= buf.len() {
+            self.read_cached( buf );
+            return Ok(());
+        }
+
+        self.read_exact_slow( buf )
+    }
+}
@@ -0,0 +1,86 @@
+use std::cmp::min;
+use std::io;
+use std::sync::Arc;
+use lz4_compress;
+
+use common::chunked_stream::CHUNK_HEADER_SIZE;
+
+use crate::chunks::{DataLoss, read_chunk};
+
+/// Decompresses the data on the calling thread, for targets which can't spawn threads.
+pub struct Lz4Reader< F: io::Read + Send > {
+    fp: F,
+    compressed_buffer: Vec< u8 >,
+    buffer: Vec< u8 >,
+    position: usize,
+    data_loss: Arc< DataLoss >,
+    done: bool
+}
+
+impl< F: io::Read + Send > Lz4Reader< F > {
+    pub fn new( fp: F ) -> Self {
+        Lz4Reader {
+            fp,
+            compressed_buffer: Vec::new(),
+            buffer: Vec::new(),
+            position: 0,
+            data_loss: Arc::new( DataLoss::default() ),
+            done: false
+        }
+    }
+
+    fn fill_buffer( &mut self ) -> io::Result< () > {
+        let (chunk, is_compressed) = match read_chunk( &mut self.fp, &mut self.compressed_buffer, &self.data_loss ) {
+            Ok( Some( chunk ) ) => chunk,
+            Ok( None ) => {
+                self.done = true;
+                self.data_loss.report();
+                return Ok(());
+            },
+            Err( ref error ) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                warn!( "The data file is truncated; ignoring its incomplete last chunk" );
+                self.done = true;
+                self.data_loss.report();
+                return Ok(());
+            },
+            Err( error ) => return Err( error )
+        };
+
+        self.position = 0;
+        if is_compressed {
+            self.buffer.clear();
+            // A chunk which fails to decompress is skipped, same as when decompressing on multiple threads.
+            if lz4_compress::decompress_into( &chunk, &mut self.buffer ).is_err() {
+                warn!( "Skipping a chunk of {} bytes which failed to decompress", CHUNK_HEADER_SIZE + chunk.len() );
+                self.data_loss.add( (CHUNK_HEADER_SIZE + chunk.len()) as u64, 0 );
+                self.buffer.clear();
+            }
+        } else {
+            self.buffer = chunk;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the statistics of the damaged chunks which were skipped so far.
+    pub fn data_loss( &self ) -> Arc< DataLoss > {
+        self.data_loss.clone()
+    }
+}
+
+impl< F: io::Read + Send > io::Read for Lz4Reader< F > {
+    fn read( &mut self, buf: &mut [u8] ) -> io::Result< usize > {
+        while self.position == self.buffer.len() {
+            if self.done {
+                return Ok( 0 );
+            }
+
+            self.fill_buffer()?;
+        }
+
+        let len = min( buf.len(), self.buffer.len() - self.position );
+        buf[ ..len ].copy_from_slice( &self.buffer[ self.position..self.position + len ] );
+        self.position += len;
+        Ok( len )
+    }
+}
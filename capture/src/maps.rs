@@ -0,0 +1,66 @@
+/// A single entry of the profiled process' `/proc/self/maps`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MapRegion {
+    pub start: u64,
+    pub end: u64,
+    pub is_readable: bool,
+    pub is_writable: bool,
+    pub is_executable: bool,
+    pub is_shared: bool,
+    pub file_offset: u64,
+    pub inode: u64,
+    /// The path of the mapped file, or a pseudo-name like `[heap]`; empty for anonymous mappings.
+    pub name: String
+}
+
+fn parse_line( line: &str ) -> Option< MapRegion > {
+    let mut fields = line.splitn( 6, ' ' );
+    let mut range = fields.next()?.splitn( 2, '-' );
+    let start = u64::from_str_radix( range.next()?, 16 ).ok()?;
+    let end = u64::from_str_radix( range.next()?, 16 ).ok()?;
+    let permissions = fields.next()?.as_bytes();
+    if permissions.len() != 4 {
+        return None;
+    }
+
+    let file_offset = u64::from_str_radix( fields.next()?, 16 ).ok()?;
+    let _device = fields.next()?;
+    let inode = fields.next()?.parse().ok()?;
+    let name = fields.next().unwrap_or( "" ).trim_start().to_owned();
+
+    Some( MapRegion {
+        start,
+        end,
+        is_readable: permissions[ 0 ] == b'r',
+        is_writable: permissions[ 1 ] == b'w',
+        is_executable: permissions[ 2 ] == b'x',
+        is_shared: permissions[ 3 ] == b's',
+        file_offset,
+        inode,
+        name
+    })
+}
+
+/// Parses the contents of `/proc/<pid>/maps`, skipping any malformed lines.
+pub fn parse_maps( contents: &str ) -> Vec< MapRegion > {
+    contents.lines().filter_map( parse_line ).collect()
+}
+
+#[test]
+fn test_parse_maps() {
+    let maps = parse_maps( concat!(
+        "00400000-00452000 r-xp 00000000 08:02 173521      /usr/bin/dbus-daemon\n",
+        "00e03000-00e24000 rw-p 00000000 00:00 0           [heap]\n",
+        "7f2b8c000000-7f2b8c021000 rw-p 00000000 00:00 0 \n",
+        "garbage\n"
+    ));
+
+    assert_eq!( maps.len(), 3 );
+    assert_eq!( maps[ 0 ].start, 0x400000 );
+    assert_eq!( maps[ 0 ].end, 0x452000 );
+    assert!( maps[ 0 ].is_executable && !maps[ 0 ].is_writable );
+    assert_eq!( maps[ 0 ].inode, 173521 );
+    assert_eq!( maps[ 0 ].name, "/usr/bin/dbus-daemon" );
+    assert_eq!( maps[ 1 ].name, "[heap]" );
+    assert_eq!( maps[ 2 ].name, "" );
+}
@@ -0,0 +1,204 @@
+use std::io::{self, Read, Seek};
+use std::ops::Range;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::vec;
+
+use common::event::{
+    Event,
+    HeaderBody
+};
+
+use common::chunked_stream::{ChunkIndex, read_chunk};
+use common::speedy::Readable;
+use common::Timestamp;
+use crate::decryption::decrypt_if_encrypted;
+pub use crate::chunks::DataLoss;
+use crate::lz4_reader::Lz4Reader;
+
+#[cfg(not(target_arch = "wasm32"))]
+const EVENT_BATCH_SIZE: usize = 4096;
+
+#[cfg(not(target_arch = "wasm32"))]
+type EventBatch = io::Result< Vec< Event< 'static > > >;
+
+/// Reads the events on a separate thread so that their decoding overlaps with their processing.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Iter {
+    rx: crossbeam_channel::Receiver< EventBatch >,
+    batch: vec::IntoIter< Event< 'static > >,
+    done: bool
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Iterator for Iter {
+    type Item = io::Result< Event< 'static > >;
+
+    #[inline]
+    fn next( &mut self ) -> Option< Self::Item > {
+        loop {
+            if let Some( event ) = self.batch.next() {
+                return Some( Ok( event ) );
+            }
+
+            if self.done {
+                return None;
+            }
+
+            match self.rx.recv() {
+                Ok( Ok( batch ) ) => {
+                    self.batch = batch.into_iter();
+                },
+                Ok( Err( err ) ) => {
+                    self.done = true;
+                    return Some( Err( err ) );
+                },
+                Err( _ ) => {
+                    self.done = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_events< T >( mut fp: Lz4Reader< T >, tx: crossbeam_channel::Sender< EventBatch > ) where T: Read + Send {
+    let mut batch = Vec::with_capacity( EVENT_BATCH_SIZE );
+    loop {
+        match Event::read_from_stream_unbuffered( &mut fp ) {
+            Ok( event ) => {
+                batch.push( event );
+                if batch.len() < EVENT_BATCH_SIZE {
+                    continue;
+                }
+
+                let batch = std::mem::replace( &mut batch, Vec::with_capacity( EVENT_BATCH_SIZE ) );
+                if tx.send( Ok( batch ) ).is_err() {
+                    return;
+                }
+            },
+            Err( err ) => {
+                let err: io::Error = err.into();
+                let _ = tx.send( Ok( batch ) );
+                if err.kind() != io::ErrorKind::UnexpectedEof {
+                    let _ = tx.send( Err( err ) );
+                }
+
+                return;
+            }
+        }
+    }
+}
+
+/// Reads the events on the calling thread, for targets which can't spawn threads.
+#[cfg(target_arch = "wasm32")]
+struct SequentialIter< T: Read + Send > {
+    fp: Lz4Reader< T >,
+    done: bool
+}
+
+#[cfg(target_arch = "wasm32")]
+impl< T: Read + Send > Iterator for SequentialIter< T > {
+    type Item = io::Result< Event< 'static > >;
+
+    fn next( &mut self ) -> Option< Self::Item > {
+        if self.done {
+            return None;
+        }
+
+        match Event::read_from_stream_unbuffered( &mut self.fp ) {
+            Ok( event ) => Some( Ok( event ) ),
+            Err( err ) => {
+                self.done = true;
+                let err: io::Error = err.into();
+                if err.kind() == io::ErrorKind::UnexpectedEof {
+                    None
+                } else {
+                    Some( Err( err ) )
+                }
+            }
+        }
+    }
+}
+
+/// Returns the header of the data and an iterator over its raw events.
+///
+/// The raw events mirror the on-disk format, which changes between the versions of the profiler.
+pub fn parse_events< T >( fp: T ) -> io::Result< (HeaderBody, impl Iterator< Item = io::Result< Event< 'static > > >) > where T: Read + Send + 'static {
+    let (header, iter, _) = parse_events_with_data_loss( fp )?;
+    Ok( (header, iter) )
+}
+
+/// Like `parse_events`, but also returns the statistics of the damaged chunks which had to be skipped,
+/// which are final once the iterator is exhausted.
+pub fn parse_events_with_data_loss< T >( fp: T ) -> io::Result< (HeaderBody, impl Iterator< Item = io::Result< Event< 'static > > >, Arc< DataLoss >) > where T: Read + Send + 'static {
+    let mut fp = Lz4Reader::new( decrypt_if_encrypted( fp )? );
+    let data_loss = fp.data_loss();
+
+    let event = Event::read_from_stream_unbuffered( &mut fp )?;
+    let header = match event {
+        Event::Header( header ) => {
+            header
+        },
+        _ => {
+            return Err( io::Error::new( io::ErrorKind::Other, "data file doesn't start with a proper header" ) );
+        }
+    };
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let iter = {
+        let (tx, rx) = crossbeam_channel::bounded( 16 );
+        thread::spawn( move || read_events( fp, tx ) );
+        Iter { rx, batch: Vec::new().into_iter(), done: false }
+    };
+
+    #[cfg(target_arch = "wasm32")]
+    let iter = SequentialIter { fp, done: false };
+
+    Ok( (header, iter, data_loss) )
+}
+
+/// Reads the events from the data chunks which overlap a given time range, using the seek index
+/// of a file in the second version of the format (which is rebuilt if the file doesn't have one).
+///
+/// Whole chunks are read, so some of the events can be from outside of the range, and the events
+/// which don't have a timestamp (e.g. the backtraces) are only there if they're in one of those chunks.
+/// Damaged chunks are skipped.
+pub fn parse_events_in_range< T >( fp: &mut T, range: Range< Timestamp > ) -> io::Result< Vec< Event< 'static > > > where T: Read + Seek {
+    let index = ChunkIndex::load( fp )?;
+    let mut events = Vec::new();
+    for entry in index.chunks_in_range( range ) {
+        let chunk = match read_chunk( fp, entry ) {
+            Ok( chunk ) => chunk,
+            Err( ref error ) if error.kind() == io::ErrorKind::InvalidData => {
+                warn!( "Skipping a damaged chunk at offset {}: {}", entry.offset, error );
+                continue;
+            },
+            Err( error ) => return Err( error )
+        };
+
+        let mut chunk = io::Cursor::new( chunk );
+        for _ in 0..entry.event_count {
+            events.push( Event::read_from_stream_unbuffered( &mut chunk )? );
+        }
+    }
+
+    Ok( events )
+}
+
+#[test]
+fn test_parse_events_in_range() {
+    use common::chunked_stream::ChunkedWriter;
+
+    let mut writer = ChunkedWriter::new( Vec::new() ).unwrap();
+    for secs in 0..100000 {
+        writer.write_event( &Event::WallClock { timestamp: Timestamp::from_secs( secs ), sec: 0, nsec: 0 } ).unwrap();
+    }
+
+    let mut fp = io::Cursor::new( writer.finish().unwrap() );
+    let events = parse_events_in_range( &mut fp, Timestamp::from_secs( 50000 )..Timestamp::from_secs( 50010 ) ).unwrap();
+    assert!( events.len() < 100000 );
+    assert!( events.iter().any( |event| *event == Event::WallClock { timestamp: Timestamp::from_secs( 50005 ), sec: 0, nsec: 0 } ) );
+}
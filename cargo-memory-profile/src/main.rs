@@ -0,0 +1,283 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+const USAGE: &str = "\
+Builds a Rust project and runs it under the memory profiler
+
+USAGE:
+    cargo memory-profile <run|test|bench> [OPTIONS] [CARGO_ARGS]... [-- <ARGS>...]
+
+OPTIONS:
+        --serve                   Starts the server with the gathered data files once the targets exit
+        --output-dir <DIR>        The directory into which the data files will be written [default: .]
+        --preload <PATH>          The path to `libmemory_profiler.so` [default: the one next to this binary]
+        --cli <PATH>              The path to `memory-profiler-cli` [default: the one next to this binary]
+    -h, --help                    Prints this message
+
+Every other argument before `--` is passed to cargo, and every one after it to the profiled targets.";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Mode {
+    Run,
+    Test,
+    Bench
+}
+
+#[derive(PartialEq, Debug)]
+struct Args {
+    mode: Mode,
+    serve: bool,
+    output_dir: PathBuf,
+    preload: Option< PathBuf >,
+    cli: Option< PathBuf >,
+    cargo_args: Vec< String >,
+    target_args: Vec< String >
+}
+
+fn parse_args( args: &[String] ) -> Result< Args, String > {
+    let mut args = args.iter();
+    let mode = match args.next().map( |arg| arg.as_str() ) {
+        Some( "run" ) => Mode::Run,
+        Some( "test" ) => Mode::Test,
+        Some( "bench" ) => Mode::Bench,
+        Some( arg ) => return Err( format!( "unknown command: '{}'", arg ) ),
+        None => return Err( "missing command".to_owned() )
+    };
+
+    let mut output = Args {
+        mode,
+        serve: false,
+        output_dir: PathBuf::from( "." ),
+        preload: None,
+        cli: None,
+        cargo_args: Vec::new(),
+        target_args: Vec::new()
+    };
+
+    while let Some( arg ) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else( || format!( "missing value for '{}'", arg ) );
+        match arg.as_str() {
+            "--serve" => output.serve = true,
+            "--output-dir" => output.output_dir = value()?.into(),
+            "--preload" => output.preload = Some( value()?.into() ),
+            "--cli" => output.cli = Some( value()?.into() ),
+            "--" => {
+                output.target_args.extend( args.by_ref().cloned() );
+                break;
+            },
+            _ => output.cargo_args.push( arg.clone() )
+        }
+    }
+
+    Ok( output )
+}
+
+/// Extracts the paths of the executables from cargo's `--message-format=json` output.
+fn parse_executables< R: BufRead >( fp: R, mode: Mode ) -> io::Result< Vec< PathBuf > > {
+    let mut executables = Vec::new();
+    for line in fp.lines() {
+        let line = line?;
+        let message: serde_json::Value = match serde_json::from_str( &line ) {
+            Ok( message ) => message,
+            Err( _ ) => continue
+        };
+
+        if message[ "reason" ] != "compiler-artifact" {
+            continue;
+        }
+
+        let executable = match message[ "executable" ].as_str() {
+            Some( executable ) => executable,
+            None => continue
+        };
+
+        let is_test = message[ "profile" ][ "test" ].as_bool().unwrap_or( false );
+        if (mode == Mode::Run) == is_test {
+            continue;
+        }
+
+        executables.push( PathBuf::from( executable ) );
+    }
+
+    Ok( executables )
+}
+
+fn build( args: &Args ) -> Result< Vec< PathBuf >, String > {
+    let cargo = env::var_os( "CARGO" ).unwrap_or_else( || "cargo".into() );
+    let mut command = Command::new( cargo );
+    match args.mode {
+        Mode::Run => command.arg( "build" ),
+        Mode::Test => command.args( &["test", "--no-run"] ),
+        Mode::Bench => command.args( &["bench", "--no-run"] )
+    };
+
+    command
+        .arg( "--message-format=json-render-diagnostics" )
+        .args( &args.cargo_args )
+        .stdout( Stdio::piped() );
+
+    let mut child = command.spawn().map_err( |error| format!( "failed to launch cargo: {}", error ) )?;
+    let executables = parse_executables( BufReader::new( child.stdout.take().unwrap() ), args.mode )
+        .map_err( |error| format!( "failed to read cargo's output: {}", error ) )?;
+
+    let status = child.wait().map_err( |error| format!( "failed to wait for cargo: {}", error ) )?;
+    if !status.success() {
+        return Err( "the build failed".to_owned() );
+    }
+
+    if args.mode == Mode::Run && executables.len() > 1 {
+        return Err( "the project has more than one binary; use `--bin` to pick one".to_owned() );
+    }
+
+    if executables.is_empty() {
+        return Err( "nothing to run".to_owned() );
+    }
+
+    Ok( executables )
+}
+
+fn next_to_current_exe( filename: &str ) -> Option< PathBuf > {
+    let path = env::current_exe().ok()?.parent()?.join( filename );
+    if path.exists() {
+        Some( path )
+    } else {
+        None
+    }
+}
+
+fn list_data_files( directory: &Path ) -> Vec< PathBuf > {
+    let entries = match fs::read_dir( directory ) {
+        Ok( entries ) => entries,
+        Err( _ ) => return Vec::new()
+    };
+
+    entries
+        .filter_map( |entry| entry.ok() )
+        .map( |entry| entry.path() )
+        .filter( |path| path.extension().map( |extension| extension == "dat" ).unwrap_or( false ) )
+        .collect()
+}
+
+fn run( args: Args ) -> Result< i32, String > {
+    let preload = match args.preload.clone().or_else( || next_to_current_exe( "libmemory_profiler.so" ) ) {
+        Some( preload ) => fs::canonicalize( &preload ).map_err( |error| format!( "failed to find {:?}: {}", preload, error ) )?,
+        None => return Err( "couldn't find 'libmemory_profiler.so'; use `--preload` to specify its path".to_owned() )
+    };
+
+    fs::create_dir_all( &args.output_dir ).map_err( |error| format!( "failed to create {:?}: {}", args.output_dir, error ) )?;
+    let output_dir = fs::canonicalize( &args.output_dir ).map_err( |error| format!( "failed to find {:?}: {}", args.output_dir, error ) )?;
+
+    let executables = build( &args )?;
+
+    // Anything which was already preloaded is kept.
+    let mut ld_preload = OsString::from( &preload );
+    if let Some( existing ) = env::var_os( "LD_PRELOAD" ) {
+        ld_preload.push( ":" );
+        ld_preload.push( existing );
+    }
+
+    let existing_files = list_data_files( &output_dir );
+    let mut exit_code = 0;
+    for executable in executables {
+        eprintln!( "     Running {:?} under the memory profiler", executable );
+        let mut command = Command::new( &executable );
+        command
+            .args( &args.target_args )
+            .env( "LD_PRELOAD", &ld_preload )
+            .env( "MEMORY_PROFILER_OUTPUT", output_dir.join( "memory-profiling_%e_%t_%p.dat" ) );
+
+        if args.mode == Mode::Bench {
+            command.arg( "--bench" );
+        }
+
+        let status = command.status().map_err( |error| format!( "failed to launch {:?}: {}", executable, error ) )?;
+        if !status.success() {
+            eprintln!( "warning: {:?} exited with {}", executable, status );
+            if exit_code == 0 {
+                exit_code = status.code().unwrap_or( 1 );
+            }
+        }
+    }
+
+    let mut data_files: Vec< _ > = list_data_files( &output_dir ).into_iter().filter( |path| !existing_files.contains( path ) ).collect();
+    data_files.sort();
+    if data_files.is_empty() {
+        return Err( "no data files were written; is the profiler working?".to_owned() );
+    }
+
+    for path in &data_files {
+        eprintln!( "     Written {:?}", path );
+    }
+
+    if args.serve {
+        let cli = args.cli.clone()
+            .or_else( || next_to_current_exe( "memory-profiler-cli" ) )
+            .unwrap_or_else( || "memory-profiler-cli".into() );
+
+        let status = Command::new( &cli ).arg( "server" ).args( &data_files ).status()
+            .map_err( |error| format!( "failed to launch {:?}: {}", cli, error ) )?;
+
+        return Ok( status.code().unwrap_or( 1 ) );
+    }
+
+    Ok( exit_code )
+}
+
+fn main() {
+    let mut args: Vec< String > = env::args().skip( 1 ).collect();
+
+    // When launched through cargo the name of the subcommand is passed as the first argument.
+    if args.first().map( |arg| arg.as_str() ) == Some( "memory-profile" ) {
+        args.remove( 0 );
+    }
+
+    if args.is_empty() || args.iter().take_while( |arg| *arg != "--" ).any( |arg| arg == "-h" || arg == "--help" ) {
+        println!( "{}", USAGE );
+        return;
+    }
+
+    let result = parse_args( &args ).and_then( run );
+    match result {
+        Ok( exit_code ) => process::exit( exit_code ),
+        Err( error ) => {
+            eprintln!( "error: {}", error );
+            process::exit( 1 );
+        }
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let args: Vec< String > = vec![ "test", "--release", "--serve", "--output-dir", "out", "-p", "foo", "--", "--nocapture" ]
+        .into_iter().map( |arg| arg.to_owned() ).collect();
+
+    assert_eq!( parse_args( &args ).unwrap(), Args {
+        mode: Mode::Test,
+        serve: true,
+        output_dir: "out".into(),
+        preload: None,
+        cli: None,
+        cargo_args: vec![ "--release".to_owned(), "-p".to_owned(), "foo".to_owned() ],
+        target_args: vec![ "--nocapture".to_owned() ]
+    });
+
+    assert!( parse_args( &[ "run".to_owned(), "--preload".to_owned() ] ).is_err() );
+    assert!( parse_args( &[ "build".to_owned() ] ).is_err() );
+}
+
+#[test]
+fn test_parse_executables() {
+    let output = r#"
+{"reason":"compiler-artifact","target":{"kind":["lib"]},"profile":{"test":false},"executable":null}
+{"reason":"compiler-artifact","target":{"kind":["bin"]},"profile":{"test":false},"executable":"/target/debug/foo"}
+{"reason":"compiler-artifact","target":{"kind":["bin"]},"profile":{"test":true},"executable":"/target/debug/deps/foo-1234"}
+{"reason":"build-finished","success":true}
+"#;
+
+    assert_eq!( parse_executables( output.as_bytes(), Mode::Run ).unwrap(), vec![ PathBuf::from( "/target/debug/foo" ) ] );
+    assert_eq!( parse_executables( output.as_bytes(), Mode::Test ).unwrap(), vec![ PathBuf::from( "/target/debug/deps/foo-1234" ) ] );
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Decides to which frame of a backtrace the allocated bytes are attributed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Attribution {
+    /// The innermost frame, even if it was inlined.
+    InnermostInline,
+    /// The innermost function which wasn't inlined, i.e. the one into which the innermost frames were inlined.
+    OutermostNonInline,
+    /// The innermost frame which isn't in any of the given libraries.
+    OutsideLibraries( Vec< String > )
+}
+
+impl Default for Attribution {
+    fn default() -> Self {
+        Attribution::InnermostInline
+    }
+}
+
+impl FromStr for Attribution {
+    type Err = String;
+    fn from_str( value: &str ) -> Result< Self, Self::Err > {
+        match value {
+            "innermost-inline" => Ok( Attribution::InnermostInline ),
+            "outermost-non-inline" => Ok( Attribution::OutermostNonInline ),
+            _ if value.starts_with( "outside:" ) => {
+                let libraries: Vec< String > = value[ "outside:".len().. ].split( ',' )
+                    .map( |library| library.trim() )
+                    .filter( |library| !library.is_empty() )
+                    .map( |library| library.to_owned() )
+                    .collect();
+
+                if libraries.is_empty() {
+                    return Err( "no libraries were specified after 'outside:'".to_owned() );
+                }
+
+                Ok( Attribution::OutsideLibraries( libraries ) )
+            },
+            _ => Err( format!( "invalid attribution '{}'; expected 'innermost-inline', 'outermost-non-inline' or 'outside:<library>,...'", value ) )
+        }
+    }
+}
+
+impl fmt::Display for Attribution {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            Attribution::InnermostInline => write!( fmt, "innermost-inline" ),
+            Attribution::OutermostNonInline => write!( fmt, "outermost-non-inline" ),
+            Attribution::OutsideLibraries( ref libraries ) => write!( fmt, "outside:{}", libraries.join( "," ) )
+        }
+    }
+}
+
+impl Attribution {
+    /// Checks whether a library given by its path is one of the libraries which should be skipped;
+    /// the libraries can be specified either by their full path or by their file name.
+    pub(crate) fn is_skipped_library( &self, path: &str ) -> bool {
+        match *self {
+            Attribution::OutsideLibraries( ref libraries ) => {
+                let file_name = path.rsplit( '/' ).next().unwrap_or( path );
+                libraries.iter().any( |library| library == path || library == file_name )
+            },
+            _ => false
+        }
+    }
+}
+
+#[test]
+fn test_attribution() {
+    assert_eq!( "innermost-inline".parse(), Ok( Attribution::InnermostInline ) );
+    assert_eq!( "outermost-non-inline".parse(), Ok( Attribution::OutermostNonInline ) );
+    assert_eq!( "outside:libstdc++.so.6, libc.so.6".parse(), Ok( Attribution::OutsideLibraries( vec![ "libstdc++.so.6".to_owned(), "libc.so.6".to_owned() ] ) ) );
+    assert!( "outside:".parse::< Attribution >().is_err() );
+    assert!( "foo".parse::< Attribution >().is_err() );
+
+    for attribution in &[ "innermost-inline", "outermost-non-inline", "outside:libstdc++.so.6,libc.so.6" ] {
+        assert_eq!( attribution.parse::< Attribution >().unwrap().to_string(), *attribution );
+    }
+
+    let attribution: Attribution = "outside:libstdc++.so.6".parse().unwrap();
+    assert!( attribution.is_skipped_library( "/usr/lib/libstdc++.so.6" ) );
+    assert!( !attribution.is_skipped_library( "/usr/lib/libc.so.6" ) );
+}
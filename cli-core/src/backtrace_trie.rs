@@ -0,0 +1,112 @@
+use ahash::AHashMap as HashMap;
+use smallvec::SmallVec;
+use speedy::{Readable, Writable, Context, Reader, Writer};
+
+use crate::data::FrameId;
+
+pub type FrameIds = SmallVec< [FrameId; 32] >;
+
+const ROOT: u32 = 0;
+
+#[derive(Copy, Clone, Debug)]
+struct Node {
+    parent: u32,
+    frame: u32
+}
+
+/// Stores the backtraces as paths in a prefix tree, starting from their outermost frame,
+/// so that the frames which many backtraces have in common are only stored once.
+#[derive(Clone, Debug)]
+pub struct BacktraceTrie {
+    nodes: Vec< Node >,
+    children: HashMap< (u32, u32), u32 >
+}
+
+impl Default for BacktraceTrie {
+    fn default() -> Self {
+        BacktraceTrie {
+            nodes: vec![ Node { parent: ROOT, frame: 0 } ],
+            children: HashMap::new()
+        }
+    }
+}
+
+impl BacktraceTrie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a backtrace, starting from its innermost frame, and returns the node which represents it.
+    pub fn insert< I >( &mut self, frame_ids: I ) -> u32 where I: IntoIterator< Item = FrameId >, I::IntoIter: DoubleEndedIterator {
+        let mut node = ROOT;
+        for frame_id in frame_ids.into_iter().rev() {
+            let nodes = &mut self.nodes;
+            node = *self.children.entry( (node, frame_id as u32) ).or_insert_with( || {
+                nodes.push( Node { parent: node, frame: frame_id as u32 } );
+                (nodes.len() - 1) as u32
+            });
+        }
+
+        node
+    }
+
+    /// Returns the frames of the backtrace represented by a given node, starting from its innermost frame.
+    pub fn get( &self, mut node: u32 ) -> FrameIds {
+        let mut frame_ids = FrameIds::new();
+        while node != ROOT {
+            let entry = self.nodes[ node as usize ];
+            frame_ids.push( entry.frame as FrameId );
+            node = entry.parent;
+        }
+
+        frame_ids
+    }
+
+    /// Frees the memory used to deduplicate the nodes; any backtraces inserted afterwards won't share them anymore.
+    pub fn shrink_to_fit( &mut self ) {
+        self.nodes.shrink_to_fit();
+        self.children = HashMap::new();
+    }
+}
+
+impl< 'a, C: Context > Readable< 'a, C > for BacktraceTrie {
+    fn read_from< R: Reader< 'a, C > >( reader: &mut R ) -> Result< Self, C::Error > {
+        let length = reader.read_u64()? as usize;
+        let mut nodes = Vec::with_capacity( length );
+        for _ in 0..length {
+            let parent = reader.read_u32()?;
+            let frame = reader.read_u32()?;
+            nodes.push( Node { parent, frame } );
+        }
+
+        Ok( BacktraceTrie { nodes, children: HashMap::new() } )
+    }
+}
+
+impl< C: Context > Writable< C > for BacktraceTrie {
+    fn write_to< W: ?Sized + Writer< C > >( &self, writer: &mut W ) -> Result< (), C::Error > {
+        writer.write_u64( self.nodes.len() as u64 )?;
+        for node in &self.nodes {
+            writer.write_u32( node.parent )?;
+            writer.write_u32( node.frame )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_backtrace_trie() {
+    let mut trie = BacktraceTrie::new();
+    let a = trie.insert( vec![ 3, 2, 1 ] );
+    let b = trie.insert( vec![ 4, 2, 1 ] );
+    let c = trie.insert( vec![ 3, 2, 1 ] );
+    let empty = trie.insert( vec![] );
+
+    assert_eq!( a, c );
+    assert_ne!( a, b );
+    assert_eq!( trie.nodes.len(), 5 );
+    assert_eq!( &trie.get( a )[..], &[3, 2, 1] );
+    assert_eq!( &trie.get( b )[..], &[4, 2, 1] );
+    assert_eq!( trie.get( empty ).len(), 0 );
+}
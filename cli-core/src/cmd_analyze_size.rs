@@ -2,7 +2,7 @@ use std::io::{self, Read};
 use std::collections::HashMap;
 use common::event::Event;
 use common::Timestamp;
-use crate::reader::parse_events;
+use memory_profiler_capture::raw::parse_events;
 
 fn format_count( count: usize ) -> String {
     if count < 1000 {
@@ -0,0 +1,307 @@
+use std::io::{self, BufRead, Write};
+
+use ahash::AHashMap as HashMap;
+use serde_json::{json, Value};
+
+use crate::data::{Data, StringId};
+
+/*
+    A JSON-RPC 2.0 server which uses the same framing as the Language Server Protocol, that is every message
+    is preceded by a `Content-Length: <length>` header and an empty line. It's meant to be launched by an editor
+    extension, which talks to it through its standard input and output.
+
+    Methods:
+
+        initialize                      returns the server's info and a short description of the loaded capture
+        memoryProfiler/line             { "path": "...", "line": <line> }
+                                        returns the statistics of the allocations attributed to the given line
+        memoryProfiler/file             { "path": "..." }
+                                        returns the statistics of every line of the given file which made any allocations
+        shutdown, exit                  as in LSP
+
+    The lines are numbered from 1. Every statistic has a `self` part, with the allocations whose innermost frame
+    (after the attribution is applied) is on the given line, and a `total` part, which also includes the allocations
+    made by whatever was called from that line.
+*/
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+#[derive(Clone, Default)]
+struct Stats {
+    allocated_count: u64,
+    allocated_size: u64,
+    leaked_count: u64,
+    leaked_size: u64
+}
+
+impl Stats {
+    fn add( &mut self, other: &Stats ) {
+        self.allocated_count += other.allocated_count;
+        self.allocated_size += other.allocated_size;
+        self.leaked_count += other.leaked_count;
+        self.leaked_size += other.leaked_size;
+    }
+
+    fn to_json( &self ) -> Value {
+        json!({
+            "allocated_count": self.allocated_count,
+            "allocated_size": self.allocated_size,
+            "leaked_count": self.leaked_count,
+            "leaked_size": self.leaked_size
+        })
+    }
+}
+
+#[derive(Clone, Default)]
+struct LineStats {
+    own: Stats,
+    total: Stats
+}
+
+impl LineStats {
+    fn add( &mut self, other: &LineStats ) {
+        self.own.add( &other.own );
+        self.total.add( &other.total );
+    }
+
+    fn to_json( &self ) -> Value {
+        json!({ "self": self.own.to_json(), "total": self.total.to_json() })
+    }
+}
+
+struct Index< 'a > {
+    data: &'a Data,
+    /// The source files of the capture grouped by their file names.
+    sources_by_filename: HashMap< &'a str, Vec< StringId > >,
+    lines_by_source: HashMap< StringId, HashMap< u32, LineStats > >
+}
+
+fn components( path: &str ) -> impl Iterator< Item = &str > {
+    path.rsplit( |ch| ch == '/' || ch == '\\' ).filter( |component| !component.is_empty() && *component != "." )
+}
+
+/// How many of the trailing path components are the same in both of the paths.
+fn common_suffix_length( a: &str, b: &str ) -> usize {
+    components( a ).zip( components( b ) ).take_while( |(a, b)| a == b ).count()
+}
+
+impl< 'a > Index< 'a > {
+    fn new( data: &'a Data ) -> Self {
+        let mut lines_by_source: HashMap< StringId, HashMap< u32, LineStats > > = HashMap::new();
+        let mut seen = Vec::new();
+        for (backtrace_id, backtrace) in data.all_backtraces() {
+            let group = data.get_group_statistics( backtrace_id );
+            if group.alloc_count == 0 {
+                continue;
+            }
+
+            let stats = Stats {
+                allocated_count: group.alloc_count,
+                allocated_size: group.alloc_size,
+                leaked_count: group.alloc_count - group.free_count,
+                leaked_size: group.alloc_size - group.free_size
+            };
+
+            let locations: Vec< _ > = backtrace.map( |(_, frame)| (frame.source(), frame.line()) ).collect();
+
+            // The backtrace starts from its outermost frame.
+            if let Some( &(Some( source ), Some( line )) ) = locations.last() {
+                lines_by_source.entry( source ).or_default().entry( line ).or_default().own.add( &stats );
+            }
+
+            // A recursive function shouldn't have its allocations counted more than once.
+            seen.clear();
+            for location in locations {
+                let location = match location {
+                    (Some( source ), Some( line )) => (source, line),
+                    _ => continue
+                };
+
+                if seen.contains( &location ) {
+                    continue;
+                }
+
+                seen.push( location );
+                lines_by_source.entry( location.0 ).or_default().entry( location.1 ).or_default().total.add( &stats );
+            }
+        }
+
+        let mut sources_by_filename: HashMap< &str, Vec< StringId > > = HashMap::new();
+        for &source in lines_by_source.keys() {
+            let path = data.interner().resolve( source ).unwrap();
+            if let Some( filename ) = components( path ).next() {
+                sources_by_filename.entry( filename ).or_default().push( source );
+            }
+        }
+
+        Index { data, sources_by_filename, lines_by_source }
+    }
+
+    /// The paths in the capture are usually from another machine (or are relative to where the binary was built),
+    /// so the ones which share the longest suffix with the given path are assumed to be the same file.
+    fn find_sources( &self, path: &str ) -> Vec< StringId > {
+        let filename = match components( path ).next() {
+            Some( filename ) => filename,
+            None => return Vec::new()
+        };
+
+        let candidates = match self.sources_by_filename.get( filename ) {
+            Some( candidates ) => candidates,
+            None => return Vec::new()
+        };
+
+        let score = |source: StringId| common_suffix_length( self.data.interner().resolve( source ).unwrap(), path );
+        let best = candidates.iter().map( |&source| score( source ) ).max().unwrap_or( 0 );
+        candidates.iter().cloned().filter( |&source| score( source ) == best ).collect()
+    }
+
+    fn lines( &self, path: &str ) -> HashMap< u32, LineStats > {
+        let mut output: HashMap< u32, LineStats > = HashMap::new();
+        for source in self.find_sources( path ) {
+            for (&line, stats) in &self.lines_by_source[ &source ] {
+                output.entry( line ).or_default().add( stats );
+            }
+        }
+
+        output
+    }
+}
+
+fn read_message( reader: &mut impl BufRead ) -> io::Result< Option< Vec< u8 > > > {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line( &mut line )? == 0 {
+            return Ok( None );
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length.is_none() {
+                // Tolerate stray empty lines between the messages.
+                continue;
+            }
+
+            break;
+        }
+
+        let index = line.find( ':' ).ok_or_else( || io::Error::new( io::ErrorKind::InvalidData, format!( "malformed header: '{}'", line ) ) )?;
+        if line[ ..index ].trim().eq_ignore_ascii_case( "Content-Length" ) {
+            let value = line[ index + 1.. ].trim().parse().map_err( |_| io::Error::new( io::ErrorKind::InvalidData, format!( "malformed header: '{}'", line ) ) )?;
+            length = Some( value );
+        }
+    }
+
+    let mut body = vec![ 0; length.unwrap() ];
+    reader.read_exact( &mut body )?;
+    Ok( Some( body ) )
+}
+
+fn write_message( output: &mut impl Write, message: &Value ) -> io::Result< () > {
+    let body = serde_json::to_vec( message )?;
+    write!( output, "Content-Length: {}\r\n\r\n", body.len() )?;
+    output.write_all( &body )?;
+    output.flush()
+}
+
+fn handle( index: &Index, method: &str, params: &Value ) -> Result< Value, (i64, String) > {
+    let path = || params.get( "path" ).and_then( |path| path.as_str() ).ok_or_else( || (INVALID_PARAMS, "missing 'path'".to_owned()) );
+    match method {
+        "initialize" => {
+            let data = index.data;
+            Ok( json!({
+                "capabilities": {},
+                "serverInfo": {
+                    "name": "memory-profiler",
+                    "version": env!( "CARGO_PKG_VERSION" )
+                },
+                "capture": {
+                    "id": format!( "{}", data.id() ),
+                    "executable": data.executable(),
+                    "pid": data.pid(),
+                    "elapsed": (data.last_timestamp() - data.initial_timestamp()).as_secs_f64()
+                }
+            }))
+        },
+        "memoryProfiler/line" => {
+            let path = path()?;
+            let line = params.get( "line" ).and_then( |line| line.as_u64() ).ok_or_else( || (INVALID_PARAMS, "missing 'line'".to_owned()) )?;
+            let stats = index.lines( path ).remove( &(line as u32) ).unwrap_or_default();
+            Ok( stats.to_json() )
+        },
+        "memoryProfiler/file" => {
+            let mut lines: Vec< _ > = index.lines( path()? ).into_iter().collect();
+            lines.sort_by_key( |&(line, _)| line );
+
+            let lines: Vec< _ > = lines.into_iter().map( |(line, stats)| {
+                let mut value = stats.to_json();
+                value[ "line" ] = line.into();
+                value
+            }).collect();
+
+            Ok( json!({ "lines": lines }) )
+        },
+        "shutdown" => Ok( Value::Null ),
+        _ => Err( (METHOD_NOT_FOUND, format!( "unknown method: '{}'", method )) )
+    }
+}
+
+/// Serves the per-line statistics of the given data through JSON-RPC until the client sends `exit`.
+pub fn editor_server( data: &Data, mut input: impl BufRead, mut output: impl Write ) -> Result< (), io::Error > {
+    let index = Index::new( data );
+    info!( "Ready; {} source files have allocations attributed to them", index.lines_by_source.len() );
+
+    while let Some( body ) = read_message( &mut input )? {
+        let request: Value = match serde_json::from_slice( &body ) {
+            Ok( request ) => request,
+            Err( error ) => {
+                write_message( &mut output, &json!({
+                    "jsonrpc": "2.0",
+                    "id": Value::Null,
+                    "error": { "code": PARSE_ERROR, "message": error.to_string() }
+                }))?;
+                continue;
+            }
+        };
+
+        let method = request.get( "method" ).and_then( |method| method.as_str() ).unwrap_or( "" );
+        if method == "exit" {
+            break;
+        }
+
+        let id = match request.get( "id" ) {
+            Some( id ) => id.clone(),
+            // Notifications (e.g. `initialized`) don't get a response.
+            None => continue
+        };
+
+        let response = match handle( &index, method, request.get( "params" ).unwrap_or( &Value::Null ) ) {
+            Ok( result ) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err( (code, message) ) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+        };
+
+        write_message( &mut output, &response )?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_read_message() {
+    let input = b"Content-Length: 2\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\n{}\r\ncontent-length: 4\r\n\r\nnull";
+    let mut input = io::Cursor::new( &input[..] );
+    assert_eq!( read_message( &mut input ).unwrap(), Some( b"{}".to_vec() ) );
+    assert_eq!( read_message( &mut input ).unwrap(), Some( b"null".to_vec() ) );
+    assert_eq!( read_message( &mut input ).unwrap(), None );
+}
+
+#[test]
+fn test_common_suffix_length() {
+    assert_eq!( common_suffix_length( "/build/project/src/lib.rs", "/home/user/project/src/lib.rs" ), 3 );
+    assert_eq!( common_suffix_length( "src/lib.rs", "/home/user/project/src/lib.rs" ), 2 );
+    assert_eq!( common_suffix_length( "./src/lib.rs", "C:\\project\\src\\lib.rs" ), 2 );
+    assert_eq!( common_suffix_length( "/registry/serde-1.0/src/lib.rs", "/home/user/project/src/lib.rs" ), 2 );
+    assert_eq!( common_suffix_length( "src/main.rs", "src/lib.rs" ), 0 );
+}
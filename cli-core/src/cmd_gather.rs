@@ -11,7 +11,17 @@ use std::mem;
 use chrono::prelude::*;
 use common::speedy::{Readable, Writable};
 
-use common::request::{PROTOCOL_VERSION, BroadcastHeader, Request, Response};
+use common::request::{
+    PROTOCOL_VERSION,
+    HANDSHAKE_PROTOCOL_VERSION,
+    CAPABILITY_STREAMING,
+    CAPABILITY_SUMMARY,
+    LEGACY_CAPABILITIES,
+    BroadcastHeader,
+    Handshake,
+    Request,
+    Response
+};
 use common::get_local_ips;
 use common::event::DataId;
 
@@ -93,44 +103,112 @@ fn client_loop( socket: TcpStream, mut fp: File, sigint: Sigint, mut ip_lock: Op
     Ok(())
 }
 
-fn connect< A: ToSocketAddrs >( target: A ) -> Result< (TcpStream, File, String), io::Error > {
+const CLIENT_CAPABILITIES: u64 = CAPABILITY_STREAMING | CAPABILITY_SUMMARY;
+
+fn read_start( socket: &TcpStream ) -> Result< BroadcastHeader, io::Error > {
+    match Response::read_from_stream_unbuffered( socket )? {
+        Response::Start( header ) => Ok( header ),
+        _ => Err( io::Error::new( io::ErrorKind::Other, "unexpected message" ) )
+    }
+}
+
+/// Connects to the embedded server of a profiled process and negotiates what both of the sides support.
+fn open< A: ToSocketAddrs >( target: A ) -> Result< (TcpStream, BroadcastHeader, Handshake), io::Error > {
     let socket = TcpStream::connect( target )?;
-    let target = socket.peer_addr().unwrap();
-    let response = Response::read_from_stream_unbuffered( &socket )?;
-    match response {
-        Response::Start( BroadcastHeader { pid, executable, arch, timestamp, initial_timestamp, .. } ) => {
-            let executable = String::from_utf8_lossy( &executable );
-            info!( "Connection established to {}:", target );
-            info!( "  Executable: {}", executable );
-            info!( "      Uptime: {}", ReadableDuration( timestamp.as_secs() - initial_timestamp.as_secs() ) );
-            info!( "         PID: {}", pid );
-            info!( "        Arch: {}", arch );
-
-            let basename: String = executable[ executable.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ].chars().map( |ch| {
-                if ch.is_alphanumeric() {
-                    ch
-                } else {
-                    '_'
-                }
-            }).collect();
+    let address = socket.peer_addr()?;
+    let header = read_start( &socket )?;
 
-            let now = Utc::now();
-            let filename = format!( "{}{:02}{:02}_{:02}{:02}{:02}_{:05}_{}.dat", now.year(), now.month(), now.day(), now.hour(), now.minute(), now.second(), pid, basename );
-            info!( "Gathering events to '{}'...", filename );
+    let handshake = Handshake {
+        protocol_version: HANDSHAKE_PROTOCOL_VERSION,
+        capabilities: CLIENT_CAPABILITIES
+    };
 
-            let fp = match File::create( &filename ) {
-                Ok( fp ) => fp,
-                Err( error ) => {
-                    error!( "Unable to create '{}': {}", filename, error );
-                    return Err( io::Error::new( io::ErrorKind::Other, "unable to create output file" ) );
-                }
+    Request::Hello( handshake.clone() ).write_to_stream( &socket )?;
+    socket.set_read_timeout( Some( Duration::from_secs( 5 ) ) )?;
+    let response = Response::read_from_stream_unbuffered( &socket );
+    socket.set_read_timeout( None )?;
+
+    match response {
+        Ok( Response::Hello( peer ) ) => {
+            let handshake = handshake.negotiate( &peer );
+            Ok( (socket, header, handshake) )
+        },
+        Ok( _ ) => Err( io::Error::new( io::ErrorKind::Other, "unexpected message" ) ),
+        Err( error ) => {
+            let error: io::Error = error.into();
+            match error.kind() {
+                ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => {},
+                _ => return Err( error )
+            }
+
+            // A server which predates the handshake drops the connection when it gets a request it doesn't know.
+            info!( "The server at {} doesn't support the handshake; reconnecting using the legacy protocol...", address );
+            let socket = TcpStream::connect( address )?;
+            let header = read_start( &socket )?;
+            let handshake = Handshake {
+                protocol_version: header.protocol_version,
+                capabilities: LEGACY_CAPABILITIES & CLIENT_CAPABILITIES
             };
 
-            Request::StartStreaming.write_to_stream( &socket )?;
+            Ok( (socket, header, handshake) )
+        }
+    }
+}
 
-            Ok( (socket, fp, filename) )
-        },
-        _ => return Err( io::Error::new( io::ErrorKind::Other, "unexpected message" ) )
+fn connect< A: ToSocketAddrs >( target: A ) -> Result< (TcpStream, File, String), io::Error > {
+    let (socket, header, handshake) = open( target )?;
+    let target = socket.peer_addr().unwrap();
+    let BroadcastHeader { pid, executable, arch, timestamp, initial_timestamp, .. } = header;
+    let executable = String::from_utf8_lossy( &executable );
+    info!( "Connection established to {}:", target );
+    info!( "  Executable: {}", executable );
+    info!( "      Uptime: {}", ReadableDuration( timestamp.as_secs() - initial_timestamp.as_secs() ) );
+    info!( "         PID: {}", pid );
+    info!( "        Arch: {}", arch );
+    info!( "    Protocol: {}", handshake.protocol_version );
+
+    if !handshake.has( CAPABILITY_STREAMING ) {
+        return Err( io::Error::new( io::ErrorKind::Other, "the profiled process doesn't support streaming" ) );
+    }
+
+    let basename: String = executable[ executable.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ].chars().map( |ch| {
+        if ch.is_alphanumeric() {
+            ch
+        } else {
+            '_'
+        }
+    }).collect();
+
+    let now = Utc::now();
+    let filename = format!( "{}{:02}{:02}_{:02}{:02}{:02}_{:05}_{}.dat", now.year(), now.month(), now.day(), now.hour(), now.minute(), now.second(), pid, basename );
+    info!( "Gathering events to '{}'...", filename );
+
+    let fp = match File::create( &filename ) {
+        Ok( fp ) => fp,
+        Err( error ) => {
+            error!( "Unable to create '{}': {}", filename, error );
+            return Err( io::Error::new( io::ErrorKind::Other, "unable to create output file" ) );
+        }
+    };
+
+    Request::StartStreaming.write_to_stream( &socket )?;
+
+    Ok( (socket, fp, filename) )
+}
+
+/// Asks the embedded server of a running process for a summary of its live allocations.
+pub fn live_summary< A: ToSocketAddrs >( target: A ) -> Result< String, io::Error > {
+    let (socket, _, handshake) = open( target )?;
+    if !handshake.has( CAPABILITY_SUMMARY ) {
+        return Err( io::Error::new( io::ErrorKind::Other, "the profiled process doesn't support summaries; it's probably profiled with an older version" ) );
+    }
+
+    socket.set_read_timeout( Some( Duration::from_secs( 5 ) ) )?;
+    Request::GetSummary.write_to_stream( &socket )?;
+    loop {
+        if let Response::Summary( summary ) = Response::read_from_stream_unbuffered( &socket )? {
+            return Ok( summary.into_owned() );
+        }
     }
 }
 
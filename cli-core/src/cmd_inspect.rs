@@ -0,0 +1,150 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::data::Data;
+use crate::frame::Frame;
+use crate::follower::Follower;
+use crate::live_allocations::LiveAllocations;
+use crate::symbol_sources::SymbolSources;
+
+/*
+    A line based protocol which is meant to be driven by a debugger (see `gdb/memory_profiler.py`).
+    Every line of the input is a single command, and every command gets a single line of JSON in response:
+
+        allocation <address>    the live allocation which contains the given address, along with its backtrace
+        summary                 the number and the total size of the live allocations
+
+    An error is reported as `{"error": "..."}`.
+
+    Before every command the data file is checked for new events, so the answers are only as fresh
+    as the data which the profiled process has managed to write out so far.
+*/
+
+#[derive(PartialEq, Debug)]
+enum Command {
+    Allocation( u64 ),
+    Summary
+}
+
+fn parse_address( text: &str ) -> Option< u64 > {
+    if text.starts_with( "0x" ) || text.starts_with( "0X" ) {
+        u64::from_str_radix( &text[ 2.. ], 16 ).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn parse_command( line: &str ) -> Result< Command, String > {
+    let mut parts = line.split_whitespace();
+    let command = match (parts.next(), parts.next()) {
+        (Some( "allocation" ), Some( address )) => {
+            let address = parse_address( address ).ok_or_else( || format!( "invalid address: '{}'", address ) )?;
+            Command::Allocation( address )
+        },
+        (Some( "allocation" ), None) => return Err( "missing address".to_owned() ),
+        (Some( "summary" ), None) => Command::Summary,
+        _ => return Err( format!( "unknown command: '{}'", line ) )
+    };
+
+    if parts.next().is_some() {
+        return Err( format!( "unexpected arguments: '{}'", line ) );
+    }
+
+    Ok( command )
+}
+
+fn frame_to_json( data: &Data, frame: &Frame ) -> Value {
+    let resolve = |id| data.interner().resolve( id ).unwrap();
+    json!({
+        "address": frame.address().raw(),
+        "library": frame.library().map( resolve ),
+        "function": frame.function().map( resolve ),
+        "source": frame.source().map( resolve ),
+        "line": frame.line(),
+        "is_inline": frame.is_inline()
+    })
+}
+
+fn execute( data: &Data, live: &LiveAllocations, command: Command ) -> Value {
+    match command {
+        Command::Allocation( address ) => {
+            let allocation = live.find( address ).map( |(pointer, size, id)| {
+                let allocation = data.get_allocation( id );
+                let mut backtrace: Vec< _ > = data.get_backtrace( allocation.backtrace ).map( |(_, frame)| frame_to_json( data, frame ) ).collect();
+                // Innermost frame first, just as in the debugger's own backtraces.
+                backtrace.reverse();
+
+                json!({
+                    "address": pointer,
+                    "size": size,
+                    "offset": address - pointer,
+                    "thread": allocation.thread,
+                    "timestamp": (allocation.timestamp - data.initial_timestamp()).as_secs_f64(),
+                    "backtrace_id": allocation.backtrace.raw(),
+                    "backtrace": backtrace
+                })
+            });
+
+            json!({ "address": address, "allocation": allocation })
+        },
+        Command::Summary => {
+            json!({
+                "pid": data.pid(),
+                "executable": data.executable(),
+                "live_count": live.len(),
+                "live_size": live.iter().map( |(_, size, _)| size ).sum::< u64 >(),
+                "elapsed": (data.last_timestamp() - data.initial_timestamp()).as_secs_f64()
+            })
+        }
+    }
+}
+
+/// Answers the commands read from `input` about a data file which is still being written to.
+pub fn inspect< P: AsRef< Path > >( path: P, symbol_sources: &SymbolSources, input: impl BufRead, mut output: impl Write ) -> Result< (), io::Error > {
+    let mut follower = Follower::new( path, symbol_sources )?;
+    let mut snapshot: Option< (Data, LiveAllocations) > = None;
+    for line in input.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let response = match parse_command( line ) {
+            Ok( command ) => {
+                if follower.poll()? != 0 || snapshot.is_none() {
+                    snapshot = follower.snapshot().map( |data| {
+                        let live = LiveAllocations::new( &data );
+                        (data, live)
+                    });
+                }
+
+                match snapshot {
+                    Some( (ref data, ref live) ) => execute( data, live, command ),
+                    None => json!({ "error": "the data file is still empty" })
+                }
+            },
+            Err( error ) => json!({ "error": error })
+        };
+
+        serde_json::to_writer( &mut output, &response )?;
+        output.write_all( b"\n" )?;
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_command() {
+    assert_eq!( parse_command( "allocation 0x7f00DEAD0000" ), Ok( Command::Allocation( 0x7f00dead0000 ) ) );
+    assert_eq!( parse_command( "allocation 4096" ), Ok( Command::Allocation( 4096 ) ) );
+    assert_eq!( parse_command( "  summary " ), Ok( Command::Summary ) );
+    assert!( parse_command( "allocation" ).is_err() );
+    assert!( parse_command( "allocation 0xzz" ).is_err() );
+    assert!( parse_command( "allocation 1 2" ).is_err() );
+    assert!( parse_command( "summary 1" ).is_err() );
+    assert!( parse_command( "backtrace" ).is_err() );
+}
@@ -0,0 +1,371 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use ahash::AHashMap as HashMap;
+use chrono::NaiveDateTime;
+
+use crate::data::{BacktraceId, Data, Timestamp};
+use crate::util::format_size;
+
+/// In which format the leak report is written.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ReportFormat {
+    Html,
+    Markdown
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+    fn from_str( value: &str ) -> Result< Self, Self::Err > {
+        match value {
+            "html" => Ok( ReportFormat::Html ),
+            "markdown" | "md" => Ok( ReportFormat::Markdown ),
+            _ => Err( format!( "invalid format '{}'; expected 'html' or 'markdown'", value ) )
+        }
+    }
+}
+
+/// The allocations from a single backtrace which were never deallocated.
+struct LeakedSite {
+    backtrace: BacktraceId,
+    count: u64,
+    size: u64,
+    min_size: u64,
+    max_size: u64,
+    first_allocation: Timestamp,
+    last_allocation: Timestamp
+}
+
+struct ReportFrame {
+    address: u64,
+    function: String,
+    location: Option< String >,
+    is_inline: bool
+}
+
+fn format_duration( timestamp: Timestamp ) -> String {
+    let secs = timestamp.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!( "{}h {}m {}s", hours, minutes, seconds )
+    } else if minutes > 0 {
+        format!( "{}m {}s", minutes, seconds )
+    } else {
+        format!( "{:.1}s", timestamp.as_usecs() as f64 / 1_000_000.0 )
+    }
+}
+
+fn escape_html( text: &str ) -> String {
+    let mut output = String::with_capacity( text.len() );
+    for ch in text.chars() {
+        match ch {
+            '<' => output.push_str( "&lt;" ),
+            '>' => output.push_str( "&gt;" ),
+            '&' => output.push_str( "&amp;" ),
+            '"' => output.push_str( "&quot;" ),
+            _ => output.push( ch )
+        }
+    }
+
+    output
+}
+
+fn escape_markdown_cell( text: &str ) -> String {
+    text.replace( '|', "\\|" ).replace( '`', "'" )
+}
+
+/// Returns a code fence which is longer than any run of backticks in the text, so that the text can't close it.
+fn markdown_fence( text: &str ) -> String {
+    let longest_run = text.split( |ch: char| ch != '`' ).map( |run| run.len() ).max().unwrap_or( 0 );
+    "`".repeat( std::cmp::max( longest_run + 1, 3 ) )
+}
+
+fn leaked_sites( data: &Data ) -> Vec< LeakedSite > {
+    let mut sites: HashMap< BacktraceId, LeakedSite > = HashMap::new();
+    for allocation in data.allocations().iter().filter( |allocation| !allocation.was_deallocated() ) {
+        let site = sites.entry( allocation.backtrace ).or_insert_with( || LeakedSite {
+            backtrace: allocation.backtrace,
+            count: 0,
+            size: 0,
+            min_size: u64::MAX,
+            max_size: 0,
+            first_allocation: allocation.timestamp,
+            last_allocation: allocation.timestamp
+        });
+
+        site.count += 1;
+        site.size += allocation.size;
+        site.min_size = std::cmp::min( site.min_size, allocation.size );
+        site.max_size = std::cmp::max( site.max_size, allocation.size );
+        site.first_allocation = std::cmp::min( site.first_allocation, allocation.timestamp );
+        site.last_allocation = std::cmp::max( site.last_allocation, allocation.timestamp );
+    }
+
+    let mut sites: Vec< _ > = sites.into_iter().map( |(_, site)| site ).collect();
+    sites.sort_by_key( |site| (std::cmp::Reverse( site.size ), site.backtrace.raw()) );
+    sites
+}
+
+/// Returns the frames of a backtrace, innermost first.
+fn report_frames( data: &Data, backtrace: BacktraceId ) -> Vec< ReportFrame > {
+    let resolve = |id| data.interner().resolve( id ).unwrap_or( "???" );
+    let mut frames: Vec< _ > = data.get_backtrace( backtrace ).map( |(_, frame)| {
+        let location = match (frame.source(), frame.line(), frame.library()) {
+            (Some( source ), Some( line ), _) => Some( format!( "{}:{}", resolve( source ), line ) ),
+            (_, _, Some( library )) => Some( resolve( library ).to_owned() ),
+            _ => None
+        };
+
+        ReportFrame {
+            address: frame.address().raw(),
+            function: frame.any_function().map( resolve ).unwrap_or( "???" ).to_owned(),
+            location,
+            is_inline: frame.is_inline()
+        }
+    }).collect();
+
+    frames.reverse();
+    frames
+}
+
+/// The innermost frame with a known function, which is what people usually call a site by.
+fn site_label( frames: &[ReportFrame] ) -> String {
+    let frame = match frames.iter().find( |frame| frame.function != "???" ).or( frames.first() ) {
+        Some( frame ) => frame,
+        None => return "<empty backtrace>".to_owned()
+    };
+
+    match frame.location {
+        Some( ref location ) => format!( "{} ({})", frame.function, location ),
+        None => frame.function.clone()
+    }
+}
+
+fn format_backtrace( frames: &[ReportFrame] ) -> String {
+    let mut output = String::new();
+    for (index, frame) in frames.iter().enumerate() {
+        let _ = write!( output, "#{:<3} 0x{:016X} {}{}", index, frame.address, if frame.is_inline { "[inlined] " } else { "" }, frame.function );
+        if let Some( ref location ) = frame.location {
+            let _ = write!( output, " at {}", location );
+        }
+        output.push( '\n' );
+    }
+
+    output
+}
+
+struct Summary {
+    rows: Vec< (&'static str, String) >,
+    leaked_count: u64,
+    leaked_size: u64
+}
+
+fn summary( data: &Data, sites: &[LeakedSite] ) -> Summary {
+    let leaked_count: u64 = sites.iter().map( |site| site.count ).sum();
+    let leaked_size: u64 = sites.iter().map( |site| site.size ).sum();
+    let started_at = NaiveDateTime::from_timestamp( data.initial_timestamp().as_secs() as i64, 0 );
+    let rows = vec![
+        ("Executable", data.executable().to_owned()),
+        ("PID", data.pid().to_string()),
+        ("Architecture", data.architecture().to_owned()),
+        ("Started at", started_at.format( "%Y-%m-%d %H:%M:%S UTC" ).to_string()),
+        ("Runtime", format_duration( data.last_timestamp() - data.initial_timestamp() )),
+        ("Allocated", format!( "{} in {} allocations", format_size( data.total_allocated() ), data.total_allocated_count() )),
+        ("Never freed", format!( "{} in {} allocations from {} backtraces", format_size( leaked_size ), leaked_count, sites.len() ))
+    ];
+
+    Summary { rows, leaked_count, leaked_size }
+}
+
+fn site_details( data: &Data, site: &LeakedSite ) -> String {
+    let sizes = if site.min_size == site.max_size {
+        format!( "every one of them {}", format_size( site.min_size ) )
+    } else {
+        format!( "from {} to {} each", format_size( site.min_size ), format_size( site.max_size ) )
+    };
+
+    format!(
+        "Sizes {}; allocated between {} and {} since the start.",
+        sizes,
+        format_duration( site.first_allocation - data.initial_timestamp() ),
+        format_duration( site.last_allocation - data.initial_timestamp() )
+    )
+}
+
+fn write_markdown( data: &Data, sites: &[LeakedSite], count: usize, mut output: impl Write ) -> io::Result< () > {
+    let summary = summary( data, sites );
+    writeln!( output, "# Memory leak report: {}", escape_markdown_cell( data.executable() ) )?;
+    writeln!( output )?;
+    writeln!( output, "| | |" )?;
+    writeln!( output, "|---|---|" )?;
+    for (key, value) in &summary.rows {
+        writeln!( output, "| {} | {} |", key, escape_markdown_cell( value ) )?;
+    }
+    writeln!( output )?;
+
+    if sites.is_empty() {
+        writeln!( output, "Every allocation was freed." )?;
+        return Ok(());
+    }
+
+    let shown = &sites[ ..std::cmp::min( count, sites.len() ) ];
+    writeln!( output, "## Top {} of {} leaking backtraces", shown.len(), sites.len() )?;
+    writeln!( output )?;
+    writeln!( output, "| # | Never freed | Allocations | % of leaked | Site |" )?;
+    writeln!( output, "|---|---|---|---|---|" )?;
+
+    let frames: Vec< _ > = shown.iter().map( |site| report_frames( data, site.backtrace ) ).collect();
+    for (index, (site, frames)) in shown.iter().zip( frames.iter() ).enumerate() {
+        writeln!(
+            output,
+            "| {} | {} | {} | {:.1}% | `{}` |",
+            index + 1,
+            format_size( site.size ),
+            site.count,
+            site.size as f64 * 100.0 / summary.leaked_size as f64,
+            escape_markdown_cell( &site_label( frames ) )
+        )?;
+    }
+
+    for (index, (site, frames)) in shown.iter().zip( frames.iter() ).enumerate() {
+        writeln!( output )?;
+        writeln!( output, "### {}. {} in {} allocations", index + 1, format_size( site.size ), site.count )?;
+        writeln!( output )?;
+        writeln!( output, "{}", site_details( data, site ) )?;
+        writeln!( output )?;
+        let backtrace = format_backtrace( frames );
+        let fence = markdown_fence( &backtrace );
+        writeln!( output, "{}", fence )?;
+        write!( output, "{}", backtrace )?;
+        writeln!( output, "{}", fence )?;
+    }
+
+    if summary.leaked_count > 0 && shown.len() < sites.len() {
+        let rest: u64 = sites[ shown.len().. ].iter().map( |site| site.size ).sum();
+        writeln!( output )?;
+        writeln!( output, "The remaining {} backtraces leaked {} in total.", sites.len() - shown.len(), format_size( rest ) )?;
+    }
+
+    Ok(())
+}
+
+const HTML_STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em auto; max-width: 70em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+th { background: #f0f0f0; }
+td.number { text-align: right; white-space: nowrap; }
+pre { background: #f6f6f6; padding: 0.6em; overflow-x: auto; }
+code, pre { font-size: 0.9em; }
+";
+
+fn write_html( data: &Data, sites: &[LeakedSite], count: usize, mut output: impl Write ) -> io::Result< () > {
+    let summary = summary( data, sites );
+    let title = format!( "Memory leak report: {}", escape_html( data.executable() ) );
+    writeln!( output, "<!DOCTYPE html>" )?;
+    writeln!( output, "<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>", title, HTML_STYLE )?;
+    writeln!( output, "<h1>{}</h1>", title )?;
+    writeln!( output, "<table>" )?;
+    for (key, value) in &summary.rows {
+        writeln!( output, "<tr><th>{}</th><td>{}</td></tr>", key, escape_html( value ) )?;
+    }
+    writeln!( output, "</table>" )?;
+
+    if sites.is_empty() {
+        writeln!( output, "<p>Every allocation was freed.</p>\n</body>\n</html>" )?;
+        return Ok(());
+    }
+
+    let shown = &sites[ ..std::cmp::min( count, sites.len() ) ];
+    let frames: Vec< _ > = shown.iter().map( |site| report_frames( data, site.backtrace ) ).collect();
+    writeln!( output, "<h2>Top {} of {} leaking backtraces</h2>", shown.len(), sites.len() )?;
+    writeln!( output, "<table>" )?;
+    writeln!( output, "<tr><th>#</th><th>Never freed</th><th>Allocations</th><th>% of leaked</th><th>Site</th></tr>" )?;
+    for (index, (site, frames)) in shown.iter().zip( frames.iter() ).enumerate() {
+        writeln!(
+            output,
+            "<tr><td class=\"number\"><a href=\"#site-{}\">{}</a></td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{:.1}%</td><td><code>{}</code></td></tr>",
+            index + 1,
+            index + 1,
+            format_size( site.size ),
+            site.count,
+            site.size as f64 * 100.0 / summary.leaked_size as f64,
+            escape_html( &site_label( frames ) )
+        )?;
+    }
+    writeln!( output, "</table>" )?;
+
+    for (index, (site, frames)) in shown.iter().zip( frames.iter() ).enumerate() {
+        writeln!( output, "<h3 id=\"site-{}\">{}. {} in {} allocations</h3>", index + 1, index + 1, format_size( site.size ), site.count )?;
+        writeln!( output, "<p>{}</p>", escape_html( &site_details( data, site ) ) )?;
+        writeln!( output, "<pre>{}</pre>", escape_html( &format_backtrace( frames ) ) )?;
+    }
+
+    if summary.leaked_count > 0 && shown.len() < sites.len() {
+        let rest: u64 = sites[ shown.len().. ].iter().map( |site| site.size ).sum();
+        writeln!( output, "<p>The remaining {} backtraces leaked {} in total.</p>", sites.len() - shown.len(), format_size( rest ) )?;
+    }
+
+    writeln!( output, "</body>\n</html>" )
+}
+
+/// Writes a self-contained report of the `count` backtraces whose allocations were never freed
+/// which hold the most memory.
+pub fn report( data: &Data, format: ReportFormat, count: usize, output: impl Write ) -> io::Result< () > {
+    let sites = leaked_sites( data );
+    match format {
+        ReportFormat::Html => write_html( data, &sites, count, output ),
+        ReportFormat::Markdown => write_markdown( data, &sites, count, output )
+    }
+}
+
+#[test]
+fn test_report() {
+    use crate::importer::{ImportWriter, ImportedFrame};
+
+    let frame = |address, function: &str| ImportedFrame {
+        address,
+        library: Some( "/usr/bin/app".to_owned() ),
+        function: Some( function.to_owned() ),
+        source: Some( "main.cpp".to_owned() ),
+        line: Some( address as u32 ),
+        .. ImportedFrame::default()
+    };
+
+    let mut input = Vec::new();
+    let mut writer = ImportWriter::new( &mut input, &[ "./app" ], Timestamp::from_secs( 1 ) ).unwrap();
+    let small = writer.backtrace( &[ frame( 0x10, "std::vector<int>::push_back" ), frame( 0x20, "main" ) ] ).unwrap();
+    let big = writer.backtrace( &[ frame( 0x30, "make_cache" ), frame( 0x20, "main" ) ] ).unwrap();
+    writer.allocate( Timestamp::from_secs( 1 ), 0x1000, 16, small ).unwrap();
+    writer.allocate( Timestamp::from_secs( 2 ), 0x2000, 2048, big ).unwrap();
+    writer.allocate( Timestamp::from_secs( 3 ), 0x3000, 4096, big ).unwrap();
+    writer.allocate( Timestamp::from_secs( 3 ), 0x4000, 100, small ).unwrap();
+    writer.deallocate( Timestamp::from_secs( 4 ), 0x4000 ).unwrap();
+    writer.finish().unwrap();
+
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( input ) ).unwrap();
+
+    let mut output = Vec::new();
+    report( &data, ReportFormat::Markdown, 1, &mut output ).unwrap();
+    let output = String::from_utf8( output ).unwrap();
+    assert!( output.contains( "| Never freed | 6.0 KiB in 3 allocations from 2 backtraces |" ) );
+    assert!( output.contains( "## Top 1 of 2 leaking backtraces" ) );
+    assert!( output.contains( "| 1 | 6.0 KiB | 2 | 99.7% | `make_cache (main.cpp:48)` |" ) );
+    assert!( output.contains( "#1   0x0000000000000020 main at main.cpp:32\n" ) );
+    assert!( output.contains( "The remaining 1 backtraces leaked 16 B in total." ) );
+
+    let mut output = Vec::new();
+    report( &data, ReportFormat::Html, 10, &mut output ).unwrap();
+    let output = String::from_utf8( output ).unwrap();
+    assert!( output.contains( "<code>std::vector&lt;int&gt;::push_back (main.cpp:16)</code>" ) );
+    assert!( !output.contains( "<int>" ) );
+}
+
+#[test]
+fn test_markdown_fence() {
+    assert_eq!( markdown_fence( "main at main.cpp:10" ), "```" );
+    assert_eq!( markdown_fence( "operator`` at a.cpp:1" ), "```" );
+    assert_eq!( markdown_fence( "a```b" ), "````" );
+    assert_eq!( markdown_fence( "`````" ), "``````" );
+}
@@ -0,0 +1,266 @@
+use std::cmp::min;
+use std::u64;
+
+use crate::data::{AllocationId, BacktraceId, Data};
+use crate::spill_vec::SpillVec;
+
+/// The fields of the allocations which are used by the most common filters, stored
+/// column by column so that the scans over them can be vectorized by the compiler.
+pub struct AllocationColumns {
+    pointers: SpillVec< u64 >,
+    sizes: SpillVec< u64 >,
+    timestamps: SpillVec< u64 >,
+    // `u64::MAX` for leaked allocations.
+    deallocation_timestamps: SpillVec< u64 >,
+    backtraces: SpillVec< u32 >,
+    markers: SpillVec< u32 >
+}
+
+/// The predicates which can be checked against the columns; all of the ranges are inclusive.
+#[derive(Clone, Debug)]
+pub struct ColumnFilter {
+    pub pointer: (u64, u64),
+    pub size: (u64, u64),
+    pub timestamp: (u64, u64),
+    pub deallocation_timestamp: (u64, u64),
+    /// The time between the allocation and the deallocation; leaked allocations are considered to live forever.
+    pub lifetime: (u64, u64),
+    pub marker: Option< u32 >,
+    /// Which backtraces are allowed, indexed by their raw IDs.
+    pub backtraces: Option< Vec< bool > >
+}
+
+impl Default for ColumnFilter {
+    fn default() -> Self {
+        ColumnFilter {
+            pointer: (0, u64::MAX),
+            size: (0, u64::MAX),
+            timestamp: (0, u64::MAX),
+            deallocation_timestamp: (0, u64::MAX),
+            lifetime: (0, u64::MAX),
+            marker: None,
+            backtraces: None
+        }
+    }
+}
+
+/// A set of allocations, stored as a bitmap.
+pub struct Selection {
+    words: Vec< u64 >,
+    length: usize
+}
+
+impl Selection {
+    #[inline]
+    pub fn contains( &self, id: AllocationId ) -> bool {
+        let index = id.raw() as usize;
+        index < self.length && self.words[ index / 64 ] & (1 << (index % 64)) != 0
+    }
+
+    pub fn count( &self ) -> usize {
+        self.words.iter().map( |word| word.count_ones() as usize ).sum()
+    }
+
+    /// Removes the allocations for which the callback returns `false`.
+    pub fn retain< F: FnMut( AllocationId ) -> bool >( &mut self, mut callback: F ) {
+        for (word_index, word) in self.words.iter_mut().enumerate() {
+            let mut remaining = *word;
+            while remaining != 0 {
+                let bit = remaining.trailing_zeros() as u64;
+                remaining &= remaining - 1;
+                if !callback( AllocationId::new( word_index as u64 * 64 + bit ) ) {
+                    *word &= !(1 << bit);
+                }
+            }
+        }
+    }
+
+    pub fn iter< 'a >( &'a self ) -> impl Iterator< Item = AllocationId > + 'a {
+        self.words.iter().enumerate().flat_map( |(word_index, &word)| {
+            let mut word = word;
+            std::iter::from_fn( move || {
+                if word == 0 {
+                    return None;
+                }
+
+                let bit = word.trailing_zeros() as u64;
+                word &= word - 1;
+                Some( AllocationId::new( word_index as u64 * 64 + bit ) )
+            })
+        })
+    }
+}
+
+#[inline(always)]
+fn in_range( value: u64, range: (u64, u64) ) -> bool {
+    // Non-short-circuiting so that there are no branches in the inner loop.
+    (value >= range.0) & (value <= range.1)
+}
+
+impl AllocationColumns {
+    pub fn new( data: &Data ) -> Self {
+        let count = data.allocations().len();
+        let mut columns = AllocationColumns {
+            pointers: SpillVec::with_capacity( count ),
+            sizes: SpillVec::with_capacity( count ),
+            timestamps: SpillVec::with_capacity( count ),
+            deallocation_timestamps: SpillVec::with_capacity( count ),
+            backtraces: SpillVec::with_capacity( count ),
+            markers: SpillVec::with_capacity( count )
+        };
+
+        for allocation in data.allocations() {
+            columns.pointers.push( allocation.pointer );
+            columns.sizes.push( allocation.size );
+            columns.timestamps.push( allocation.timestamp.as_usecs() );
+            columns.deallocation_timestamps.push( allocation.deallocation.as_ref().map( |deallocation| deallocation.timestamp.as_usecs() ).unwrap_or( u64::MAX ) );
+            columns.backtraces.push( allocation.backtrace.raw() );
+            columns.markers.push( allocation.marker );
+        }
+
+        columns
+    }
+
+    pub fn len( &self ) -> usize {
+        self.pointers.len()
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.pointers.is_empty()
+    }
+
+    /// Returns every allocation which satisfies all of the filter's predicates.
+    pub fn select( &self, filter: &ColumnFilter ) -> Selection {
+        let length = self.len();
+        let mut words = vec![ 0; (length + 63) / 64 ];
+        let marker = filter.marker.map( |marker| (marker, u32::MAX) ).unwrap_or( (0, 0) );
+
+        // The allocations are processed in chunks of 64, one for every bit of the output
+        // word, with the chunks cut out up front so that the bounds checks are hoisted.
+        for (word_index, word) in words.iter_mut().enumerate() {
+            let start = word_index * 64;
+            let end = min( start + 64, length );
+            let pointers = &self.pointers[ start..end ];
+            let sizes = &self.sizes[ start..end ];
+            let timestamps = &self.timestamps[ start..end ];
+            let deallocation_timestamps = &self.deallocation_timestamps[ start..end ];
+            let markers = &self.markers[ start..end ];
+
+            let mut bits = 0;
+            for index in 0..end - start {
+                let timestamp = timestamps[ index ];
+                let deallocation_timestamp = deallocation_timestamps[ index ];
+                let is_matched =
+                    in_range( pointers[ index ], filter.pointer ) &
+                    in_range( sizes[ index ], filter.size ) &
+                    in_range( timestamp, filter.timestamp ) &
+                    in_range( deallocation_timestamp, filter.deallocation_timestamp ) &
+                    in_range( deallocation_timestamp.wrapping_sub( timestamp ), filter.lifetime ) &
+                    // This is a no-op when there's no marker to match since `x & 0 == 0`.
+                    ((markers[ index ] & marker.1) == marker.0);
+
+                bits |= (is_matched as u64) << index;
+            }
+
+            *word = bits;
+        }
+
+        // This one needs a gather, so it's only done for the allocations which have passed everything else.
+        if let Some( ref allowed ) = filter.backtraces {
+            for (word_index, word) in words.iter_mut().enumerate() {
+                let mut remaining = *word;
+                while remaining != 0 {
+                    let bit = remaining.trailing_zeros() as usize;
+                    remaining &= remaining - 1;
+
+                    let backtrace = self.backtraces[ word_index * 64 + bit ] as usize;
+                    if !allowed.get( backtrace ).cloned().unwrap_or( false ) {
+                        *word &= !(1 << bit);
+                    }
+                }
+            }
+        }
+
+        Selection {
+            words,
+            length
+        }
+    }
+
+    /// Groups the selected allocations by their backtraces; the groups are sorted by the backtrace
+    /// and the allocations in every group by their IDs.
+    pub fn group_by_backtrace( &self, selection: &Selection ) -> Vec< (BacktraceId, Vec< AllocationId >) > {
+        let mut counts: Vec< u32 > = Vec::new();
+        for id in selection.iter() {
+            let backtrace = self.backtraces[ id.raw() as usize ] as usize;
+            if backtrace >= counts.len() {
+                counts.resize( backtrace + 1, 0 );
+            }
+
+            counts[ backtrace ] += 1;
+        }
+
+        let mut group_index_by_backtrace = vec![ std::usize::MAX; counts.len() ];
+        let mut groups = Vec::new();
+        for (backtrace, &count) in counts.iter().enumerate() {
+            if count != 0 {
+                group_index_by_backtrace[ backtrace ] = groups.len();
+                groups.push( (BacktraceId::new( backtrace as u32 ), Vec::with_capacity( count as usize )) );
+            }
+        }
+
+        for id in selection.iter() {
+            let backtrace = self.backtraces[ id.raw() as usize ] as usize;
+            groups[ group_index_by_backtrace[ backtrace ] ].1.push( id );
+        }
+
+        groups
+    }
+
+    #[cfg(test)]
+    fn from_rows( rows: &[(u64, u64, u64, Option< u64 >, u32)] ) -> Self {
+        AllocationColumns {
+            pointers: rows.iter().map( |row| row.0 ).collect::< Vec< _ > >().into(),
+            sizes: rows.iter().map( |row| row.1 ).collect::< Vec< _ > >().into(),
+            timestamps: rows.iter().map( |row| row.2 ).collect::< Vec< _ > >().into(),
+            deallocation_timestamps: rows.iter().map( |row| row.3.unwrap_or( u64::MAX ) ).collect::< Vec< _ > >().into(),
+            backtraces: rows.iter().map( |row| row.4 ).collect::< Vec< _ > >().into(),
+            markers: rows.iter().map( |_| 0 ).collect::< Vec< _ > >().into()
+        }
+    }
+}
+
+#[test]
+fn test_select() {
+    let mut rows = Vec::new();
+    for index in 0..150 {
+        // (pointer, size, timestamp, deallocation timestamp, backtrace)
+        rows.push( (0x1000 + index * 16, index, index * 10, if index % 2 == 0 { Some( index * 10 + 5 ) } else { None }, (index % 3) as u32) );
+    }
+
+    let columns = AllocationColumns::from_rows( &rows );
+    let ids = |selection: Selection| -> Vec< u64 > { selection.iter().map( |id| id.raw() ).collect() };
+
+    assert_eq!( columns.select( &ColumnFilter::default() ).count(), 150 );
+
+    let filter = ColumnFilter { size: (10, 13), ..ColumnFilter::default() };
+    assert_eq!( ids( columns.select( &filter ) ), vec![ 10, 11, 12, 13 ] );
+
+    let filter = ColumnFilter { size: (60, 80), deallocation_timestamp: (u64::MAX, u64::MAX), ..ColumnFilter::default() };
+    assert_eq!( ids( columns.select( &filter ) ), (61..80).step_by( 2 ).collect::< Vec< _ > >() );
+
+    let filter = ColumnFilter { lifetime: (0, 5), timestamp: (1000, 1300), ..ColumnFilter::default() };
+    assert_eq!( ids( columns.select( &filter ) ), (100..=130).step_by( 2 ).collect::< Vec< _ > >() );
+
+    let filter = ColumnFilter { size: (0, 9), backtraces: Some( vec![ false, true ] ), ..ColumnFilter::default() };
+    assert_eq!( ids( columns.select( &filter ) ), vec![ 1, 4, 7 ] );
+
+    let selection = columns.select( &ColumnFilter { size: (0, 6), ..ColumnFilter::default() } );
+    assert!( selection.contains( AllocationId::new( 6 ) ) );
+    assert!( !selection.contains( AllocationId::new( 7 ) ) );
+
+    let groups: Vec< (u32, Vec< u64 >) > = columns.group_by_backtrace( &selection ).into_iter()
+        .map( |(backtrace, ids)| (backtrace.raw(), ids.into_iter().map( |id| id.raw() ).collect()) )
+        .collect();
+    assert_eq!( groups, vec![ (0, vec![ 0, 3, 6 ]), (1, vec![ 1, 4 ]), (2, vec![ 2, 5 ]) ] );
+}
@@ -0,0 +1,590 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Component, Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+use flate2::read::GzDecoder;
+use nwind::BinaryData;
+use serde_json::Value;
+
+use crate::importer::invalid_data;
+use crate::image_registry::{self, Registry, verify_digest};
+
+/*
+    An image is a bunch of layers, each of which is a (usually gzipped) tarball of a part of the filesystem,
+    plus a manifest which lists them. We support:
+        - an OCI image layout (`index.json` + `blobs/<algorithm>/<hash>`), either as a directory
+          or as a tarball (e.g. from `skopeo copy ... oci-archive:image.tar` or `ctr image export`),
+        - a tarball from `docker save` (`manifest.json` + `<id>/layer.tar`),
+        - an image reference, in which case the image is exported from the local Docker daemon,
+          or pulled from its registry if there's no Docker daemon (or with a `registry://` prefix),
+        - a `containerd://` image reference, in which case the image is exported through `ctr`.
+
+    Every ELF file with a build ID is extracted from the layers into `<cache>/<image>/<build ID>/<filename>`,
+    so the whole directory can then be used as a symbol path. Every blob is checked against its digest
+    before anything is extracted from it, and the cache has to be private to the current user
+    since its contents are trusted once an image was completely extracted.
+*/
+
+const COMPLETE_MARKER: &str = ".complete";
+
+#[derive(PartialEq, Debug)]
+struct TarEntry {
+    path: String,
+    kind: u8,
+    size: u64
+}
+
+fn parse_octal( bytes: &[u8] ) -> io::Result< u64 > {
+    // Sizes which don't fit into the octal field are stored as a big endian number with the highest bit set.
+    if bytes.first().map( |&byte| byte & 0x80 != 0 ).unwrap_or( false ) {
+        return Ok( bytes[ 1.. ].iter().fold( 0, |value, &byte| (value << 8) | byte as u64 ) );
+    }
+
+    let string = String::from_utf8_lossy( bytes );
+    let string = string.trim_matches( |ch: char| ch == '\0' || ch == ' ' );
+    if string.is_empty() {
+        return Ok( 0 );
+    }
+
+    u64::from_str_radix( string, 8 ).map_err( |_| invalid_data( format!( "invalid number in a tar header: {:?}", string ) ) )
+}
+
+fn parse_string( bytes: &[u8] ) -> String {
+    let length = bytes.iter().position( |&byte| byte == 0 ).unwrap_or( bytes.len() );
+    String::from_utf8_lossy( &bytes[ ..length ] ).into_owned()
+}
+
+fn parse_pax_path( data: &[u8] ) -> Option< String > {
+    let data = String::from_utf8_lossy( data );
+    data.lines().filter_map( |line| {
+        let record = line.splitn( 2, ' ' ).nth( 1 )?;
+        if record.starts_with( "path=" ) {
+            Some( record[ "path=".len().. ].to_owned() )
+        } else {
+            None
+        }
+    }).last()
+}
+
+/// Calls the callback for every regular file in a tarball.
+fn for_each_tar_file< R, F >( mut fp: R, mut callback: F ) -> io::Result< () >
+    where R: Read,
+          F: FnMut( &TarEntry, &mut dyn Read ) -> io::Result< () >
+{
+    let mut long_path = None;
+    let mut header = [0; 512];
+    loop {
+        if let Err( error ) = fp.read_exact( &mut header ) {
+            if error.kind() == io::ErrorKind::UnexpectedEof {
+                break;
+            }
+
+            return Err( error );
+        }
+
+        if header.iter().all( |&byte| byte == 0 ) {
+            break;
+        }
+
+        let mut path = parse_string( &header[ 0..100 ] );
+        if &header[ 257..262 ] == b"ustar" {
+            let prefix = parse_string( &header[ 345..500 ] );
+            if !prefix.is_empty() {
+                path = format!( "{}/{}", prefix, path );
+            }
+        }
+
+        let kind = header[ 156 ];
+        let size = parse_octal( &header[ 124..136 ] )?;
+        let mut data = (&mut fp).take( size );
+        match kind {
+            b'L' => {
+                let mut buffer = Vec::new();
+                data.read_to_end( &mut buffer )?;
+                long_path = Some( parse_string( &buffer ) );
+            },
+            b'x' => {
+                let mut buffer = Vec::new();
+                data.read_to_end( &mut buffer )?;
+                long_path = parse_pax_path( &buffer );
+            },
+            b'0' | b'\0' | b'7' => {
+                let entry = TarEntry {
+                    path: long_path.take().unwrap_or( path ),
+                    kind,
+                    size
+                };
+
+                callback( &entry, &mut data )?;
+            },
+            _ => {
+                long_path = None;
+            }
+        }
+
+        io::copy( &mut data, &mut io::sink() )?;
+        let padding = (512 - size % 512) % 512;
+        io::copy( &mut (&mut fp).take( padding ), &mut io::sink() )?;
+    }
+
+    Ok(())
+}
+
+/// Returns the path relative to the root of the archive, or `None` if it'd escape it.
+fn sanitize_path( path: &str ) -> Option< PathBuf > {
+    let mut output = PathBuf::new();
+    for component in Path::new( path ).components() {
+        match component {
+            Component::Normal( component ) => output.push( component ),
+            Component::CurDir | Component::RootDir => {},
+            Component::ParentDir | Component::Prefix( _ ) => return None
+        }
+    }
+
+    if output.as_os_str().is_empty() {
+        None
+    } else {
+        Some( output )
+    }
+}
+
+fn unpack< R: Read >( fp: R, directory: &Path ) -> io::Result< () > {
+    for_each_tar_file( fp, |entry, data| {
+        let path = match sanitize_path( &entry.path ) {
+            Some( path ) => directory.join( path ),
+            None => return Ok(())
+        };
+
+        if let Some( parent ) = path.parent() {
+            fs::create_dir_all( parent )?;
+        }
+
+        io::copy( data, &mut File::create( path )? )?;
+        Ok(())
+    })
+}
+
+fn decompress< 'a, R: Read + 'a >( fp: R ) -> io::Result< Box< dyn Read + 'a > > {
+    let mut fp = BufReader::new( fp );
+    let magic = fp.fill_buf()?;
+    if magic.starts_with( &[0x1f, 0x8b] ) {
+        Ok( Box::new( GzDecoder::new( fp ) ) )
+    } else if magic.starts_with( &[0x28, 0xb5, 0x2f, 0xfd] ) {
+        Err( invalid_data( "zstd compressed layers are not supported" ) )
+    } else {
+        Ok( Box::new( fp ) )
+    }
+}
+
+/// Extracts every ELF file with a build ID from a single layer.
+fn extract_binaries< R: Read >( layer: R, output_directory: &Path ) -> io::Result< usize > {
+    let temporary_path = output_directory.join( format!( ".extracting-{}", process::id() ) );
+    let mut count = 0;
+    for_each_tar_file( decompress( layer )?, |entry, data| {
+        let mut magic = [0; 4];
+        if entry.size < magic.len() as u64 {
+            return Ok(());
+        }
+
+        data.read_exact( &mut magic )?;
+        if magic != *b"\x7fELF" {
+            return Ok(());
+        }
+
+        {
+            let mut fp = File::create( &temporary_path )?;
+            fp.write_all( &magic )?;
+            io::copy( data, &mut fp )?;
+        }
+
+        let build_id = match BinaryData::load_from_fs( &temporary_path ) {
+            Ok( binary ) => binary.build_id().map( |build_id| build_id.iter().map( |byte| format!( "{:02x}", byte ) ).collect::< String >() ),
+            Err( error ) => {
+                debug!( "Failed to load '{}' from the image: {}", entry.path, error );
+                None
+            }
+        };
+
+        let filename = Path::new( &entry.path ).file_name().map( |filename| filename.to_owned() );
+        match (build_id, filename) {
+            (Some( build_id ), Some( filename )) => {
+                let directory = output_directory.join( build_id );
+                fs::create_dir_all( &directory )?;
+                fs::rename( &temporary_path, directory.join( filename ) )?;
+                count += 1;
+            },
+            _ => fs::remove_file( &temporary_path )?
+        }
+
+        Ok(())
+    })?;
+
+    Ok( count )
+}
+
+fn read_json( path: &Path ) -> io::Result< Value > {
+    let fp = BufReader::new( File::open( path )? );
+    serde_json::from_reader( fp ).map_err( |error| invalid_data( format!( "failed to parse {:?}: {}", path, error ) ) )
+}
+
+fn read_blob_json( root: &Path, digest: &str ) -> io::Result< Value > {
+    let path = blob_path( root, digest )?;
+    verify_digest( File::open( &path )?, digest )?;
+    read_json( &path )
+}
+
+fn blob_path( root: &Path, digest: &str ) -> io::Result< PathBuf > {
+    let mut iter = digest.splitn( 2, ':' );
+    match (iter.next(), iter.next()) {
+        (Some( algorithm ), Some( hash )) if !hash.contains( '/' ) && !algorithm.contains( '/' ) => Ok( root.join( "blobs" ).join( algorithm ).join( hash ) ),
+        _ => Err( invalid_data( format!( "invalid digest: '{}'", digest ) ) )
+    }
+}
+
+fn host_architecture() -> &'static str {
+    match env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "arm" => "arm",
+        "mips64" => "mips64le",
+        architecture => architecture
+    }
+}
+
+/// Picks a manifest out of an index; if it's a multi-platform image then the one for the current architecture is preferred.
+fn select_manifest( index: &Value ) -> io::Result< &Value > {
+    let manifests = index[ "manifests" ].as_array().ok_or_else( || invalid_data( "the image index has no manifests" ) )?;
+    manifests.iter()
+        .find( |manifest| manifest[ "platform" ][ "architecture" ].as_str() == Some( host_architecture() ) )
+        .or_else( || manifests.first() )
+        .ok_or_else( || invalid_data( "the image index has no manifests" ) )
+}
+
+/// A manifest's layers, with the digests of their blobs.
+fn parse_layers( manifest: &Value ) -> io::Result< Vec< String > > {
+    let layers = manifest[ "layers" ].as_array().ok_or_else( || invalid_data( "the image manifest has no layers" ) )?;
+    layers.iter().map( |layer| {
+        layer[ "digest" ].as_str().map( |digest| digest.to_owned() ).ok_or_else( || invalid_data( "a layer without a digest" ) )
+    }).collect()
+}
+
+/// Returns a key which identifies the image, and the paths to its layers along with their digests.
+fn parse_oci_layout( root: &Path ) -> io::Result< (String, Vec< (PathBuf, String) >) > {
+    let mut index = read_json( &root.join( "index.json" ) )?;
+    loop {
+        let digest = select_manifest( &index )?[ "digest" ].as_str().ok_or_else( || invalid_data( "a manifest without a digest" ) )?.to_owned();
+        let manifest = read_blob_json( root, &digest )?;
+        if manifest.get( "manifests" ).is_some() {
+            // A nested index, e.g. for a multi-platform image.
+            index = manifest;
+            continue;
+        }
+
+        let key = manifest[ "config" ][ "digest" ].as_str().unwrap_or( &digest ).to_owned();
+        let layers = parse_layers( &manifest )?.into_iter().map( |digest| {
+            Ok( (blob_path( root, &digest )?, digest) )
+        }).collect::< io::Result< _ > >()?;
+
+        return Ok( (key, layers) );
+    }
+}
+
+fn parse_docker_archive( root: &Path ) -> io::Result< (String, Vec< (PathBuf, String) >) > {
+    let manifest = read_json( &root.join( "manifest.json" ) )?;
+    let manifest = manifest.get( 0 ).ok_or_else( || invalid_data( "the archive contains no images" ) )?;
+    let config_path = manifest[ "Config" ].as_str().and_then( sanitize_path ).ok_or_else( || invalid_data( "the image has no config" ) )?;
+
+    // The config is named after its digest, which is also what the Docker daemon uses as the image's ID.
+    let key = format!( "sha256:{}", config_path.file_stem().and_then( |stem| stem.to_str() ).unwrap_or( "" ) );
+    let config_path = root.join( config_path );
+    verify_digest( File::open( &config_path )?, &key )?;
+
+    // The config has the digests of the uncompressed layers, which is exactly how they're stored in the archive.
+    let config = read_json( &config_path )?;
+    let diff_ids = config[ "rootfs" ][ "diff_ids" ].as_array().ok_or_else( || invalid_data( "the image's config has no layer digests" ) )?;
+    let layers = manifest[ "Layers" ].as_array().ok_or_else( || invalid_data( "the image has no layers" ) )?;
+    if layers.len() != diff_ids.len() {
+        return Err( invalid_data( "the number of layers doesn't match the image's config" ) );
+    }
+
+    let layers = layers.iter().zip( diff_ids ).map( |(layer, digest)| {
+        let layer = layer.as_str().and_then( sanitize_path ).ok_or_else( || invalid_data( "invalid layer path" ) )?;
+        let digest = digest.as_str().ok_or_else( || invalid_data( "invalid layer digest" ) )?;
+        Ok( (root.join( layer ), digest.to_owned()) )
+    }).collect::< io::Result< _ > >()?;
+
+    Ok( (key, layers) )
+}
+
+fn sanitize_key( key: &str ) -> String {
+    key.chars().map( |ch| if ch.is_ascii_alphanumeric() || ch == '-' || ch == '.' { ch } else { '_' } ).collect()
+}
+
+/// Extracts the binaries out of an unpacked image, unless they were already extracted before.
+fn extract_image( root: &Path, cache: &Path ) -> io::Result< PathBuf > {
+    let (key, layers) = if root.join( "index.json" ).exists() {
+        parse_oci_layout( root )?
+    } else if root.join( "manifest.json" ).exists() {
+        parse_docker_archive( root )?
+    } else {
+        return Err( invalid_data( format!( "{:?} is neither an OCI image layout nor a 'docker save' archive", root ) ) );
+    };
+
+    let output_directory = cache.join( sanitize_key( &key ) );
+    if output_directory.join( COMPLETE_MARKER ).exists() {
+        return Ok( output_directory );
+    }
+
+    fs::create_dir_all( &output_directory )?;
+    let mut count = 0;
+    for (layer, digest) in layers {
+        if !layer.exists() {
+            // E.g. a duplicate layer in a `docker save` archive, which is stored as a symlink.
+            warn!( "Missing layer: {:?}", layer );
+            continue;
+        }
+
+        verify_digest( File::open( &layer )?, &digest ).map_err( |error| invalid_data( format!( "layer {:?}: {}", layer, error ) ) )?;
+
+        info!( "Extracting binaries from {:?}...", layer );
+        count += extract_binaries( File::open( &layer )?, &output_directory )?;
+    }
+
+    finish_extraction( &output_directory, count )
+}
+
+fn finish_extraction( output_directory: &Path, count: usize ) -> io::Result< PathBuf > {
+    info!( "Extracted {} binaries into {:?}", count, output_directory );
+    File::create( output_directory.join( COMPLETE_MARKER ) )?;
+    Ok( output_directory.to_owned() )
+}
+
+fn extract_from_archive< R: Read >( fp: R, cache: &Path ) -> io::Result< PathBuf > {
+    let temporary_directory = cache.join( format!( ".unpacked-{}", process::id() ) );
+    let result = unpack( fp, &temporary_directory ).and_then( |_| extract_image( &temporary_directory, cache ) );
+    let _ = fs::remove_dir_all( &temporary_directory );
+    result
+}
+
+fn docker_socket() -> PathBuf {
+    match env::var( "DOCKER_HOST" ) {
+        Ok( ref host ) if host.starts_with( "unix://" ) => PathBuf::from( &host[ "unix://".len().. ] ),
+        _ => PathBuf::from( "/var/run/docker.sock" )
+    }
+}
+
+/// Sends a request to the Docker daemon and returns a reader for the response's body.
+fn docker_request( path: &str ) -> io::Result< BufReader< UnixStream > > {
+    let socket = docker_socket();
+    let mut stream = UnixStream::connect( &socket ).map_err( |error| {
+        io::Error::new( error.kind(), format!( "failed to connect to the Docker daemon at {:?}: {}", socket, error ) )
+    })?;
+
+    // With HTTP/1.0 the daemon doesn't use a chunked encoding and simply closes the connection at the end.
+    write!( stream, "GET {} HTTP/1.0\r\nHost: docker\r\n\r\n", path )?;
+
+    let mut fp = BufReader::new( stream );
+    let mut status = String::new();
+    fp.read_line( &mut status )?;
+    loop {
+        let mut line = String::new();
+        if fp.read_line( &mut line )? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    if status.split_whitespace().nth( 1 ) != Some( "200" ) {
+        let mut body = String::new();
+        let _ = fp.read_to_string( &mut body );
+        return Err( io::Error::new( io::ErrorKind::Other, format!( "the Docker daemon returned '{}': {}", status.trim(), body.trim() ) ) );
+    }
+
+    Ok( fp )
+}
+
+fn extract_from_docker( reference: &str, cache: &Path ) -> io::Result< PathBuf > {
+    let image: Value = serde_json::from_reader( docker_request( &format!( "/images/{}/json", reference ) )? ).map_err( invalid_data )?;
+    if let Some( id ) = image[ "Id" ].as_str() {
+        let output_directory = cache.join( sanitize_key( id ) );
+        if output_directory.join( COMPLETE_MARKER ).exists() {
+            return Ok( output_directory );
+        }
+    }
+
+    info!( "Exporting '{}' from the Docker daemon...", reference );
+    extract_from_archive( docker_request( &format!( "/images/{}/get", reference ) )?, cache )
+}
+
+fn extract_from_registry( image: &str, cache: &Path ) -> io::Result< PathBuf > {
+    let reference = image_registry::parse_reference( image ).ok_or_else( || invalid_data( format!( "invalid image reference: '{}'", image ) ) )?;
+    let tag = reference.reference.clone();
+    let mut registry = Registry::new( reference );
+    let mut manifest = registry.fetch_manifest( &tag )?;
+    while manifest.get( "manifests" ).is_some() {
+        let digest = select_manifest( &manifest )?[ "digest" ].as_str().ok_or_else( || invalid_data( "a manifest without a digest" ) )?.to_owned();
+        manifest = registry.fetch_manifest( &digest )?;
+    }
+
+    let key = manifest[ "config" ][ "digest" ].as_str().ok_or_else( || invalid_data( "the image manifest has no config" ) )?.to_owned();
+    let output_directory = cache.join( sanitize_key( &key ) );
+    if output_directory.join( COMPLETE_MARKER ).exists() {
+        return Ok( output_directory );
+    }
+
+    fs::create_dir_all( &output_directory )?;
+    let temporary_path = cache.join( format!( ".layer-{}", process::id() ) );
+    let mut count = 0;
+    for digest in parse_layers( &manifest )? {
+        info!( "Downloading layer '{}' of '{}'...", digest, image );
+        let result = registry.download_blob( &digest, &temporary_path ).and_then( |_| {
+            extract_binaries( File::open( &temporary_path )?, &output_directory )
+        });
+
+        let _ = fs::remove_file( &temporary_path );
+        count += result?;
+    }
+
+    finish_extraction( &output_directory, count )
+}
+
+fn extract_from_containerd( reference: &str, cache: &Path ) -> io::Result< PathBuf > {
+    // This is the namespace which is used by Kubernetes.
+    let namespace = env::var( "CONTAINERD_NAMESPACE" ).unwrap_or_else( |_| "k8s.io".to_owned() );
+
+    info!( "Exporting '{}' from containerd...", reference );
+    let mut child = Command::new( "ctr" )
+        .args( &[ "--namespace", &namespace, "images", "export", "-", reference ] )
+        .stdout( Stdio::piped() )
+        .spawn()
+        .map_err( |error| io::Error::new( error.kind(), format!( "failed to run 'ctr': {}", error ) ) )?;
+
+    let result = extract_from_archive( child.stdout.take().unwrap(), cache );
+    let status = child.wait()?;
+    if !status.success() {
+        return Err( io::Error::new( io::ErrorKind::Other, format!( "'ctr images export' failed with {}", status ) ) );
+    }
+
+    result
+}
+
+/// The default cache for the extracted binaries; it's per-user, since everything in it is trusted.
+pub fn default_image_cache() -> PathBuf {
+    let cache = env::var_os( "XDG_CACHE_HOME" ).map( PathBuf::from ).or_else( || env::var_os( "HOME" ).map( |home| Path::new( &home ).join( ".cache" ) ) );
+    match cache {
+        Some( cache ) => cache.join( "memory-profiler" ).join( "images" ),
+        None => env::temp_dir().join( format!( "memory-profiler-images-{}", unsafe { libc::geteuid() } ) )
+    }
+}
+
+/// Creates the cache if it doesn't exist, and makes sure that nobody else could have put anything into it.
+fn prepare_cache( cache: &Path ) -> io::Result< () > {
+    if !cache.exists() {
+        fs::create_dir_all( cache )?;
+        fs::set_permissions( cache, fs::Permissions::from_mode( 0o700 ) )?;
+    }
+
+    let metadata = fs::metadata( cache )?;
+    if metadata.uid() != unsafe { libc::geteuid() } || metadata.mode() & 0o022 != 0 {
+        return Err( io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!( "the image cache {:?} has to be owned by the current user and not writable by anyone else", cache )
+        ));
+    }
+
+    Ok(())
+}
+
+/// Extracts the binaries out of a container image and returns a directory which can be used as a symbol path.
+///
+/// The `image` can either be a path to an OCI image layout, an OCI or `docker save` archive,
+/// a reference to an image known to the local Docker daemon or to containerd (with a `containerd://` prefix),
+/// or a reference to an image in a registry (with a `registry://` prefix, or if there's no local Docker daemon).
+pub fn extract_binaries_from_image( image: &str, cache: &Path ) -> io::Result< PathBuf > {
+    prepare_cache( cache )?;
+
+    let path = Path::new( image );
+    if path.is_dir() {
+        extract_image( path, cache )
+    } else if path.is_file() {
+        extract_from_archive( BufReader::new( File::open( path )? ), cache )
+    } else if let Some( reference ) = image.strip_prefix( "containerd://" ) {
+        extract_from_containerd( reference, cache )
+    } else if let Some( reference ) = image.strip_prefix( "registry://" ) {
+        extract_from_registry( reference, cache )
+    } else if docker_socket().exists() {
+        extract_from_docker( image, cache )
+    } else {
+        extract_from_registry( image, cache )
+    }
+}
+
+#[cfg(test)]
+fn tar_header( path: &str, kind: u8, size: usize ) -> Vec< u8 > {
+    let mut header = vec![ 0; 512 ];
+    header[ ..path.len() ].copy_from_slice( path.as_bytes() );
+    header[ 124..135 ].copy_from_slice( format!( "{:011o}", size ).as_bytes() );
+    header[ 156 ] = kind;
+    header[ 257..262 ].copy_from_slice( b"ustar" );
+    header
+}
+
+#[cfg(test)]
+fn tar_entry( output: &mut Vec< u8 >, path: &str, kind: u8, data: &[u8] ) {
+    output.extend( tar_header( path, kind, data.len() ) );
+    output.extend( data );
+    output.resize( (output.len() + 511) / 512 * 512, 0 );
+}
+
+#[test]
+fn test_for_each_tar_file() {
+    let long_path = format!( "usr/lib/{}/libfoo.so", "x".repeat( 120 ) );
+    let mut tarball = Vec::new();
+    tar_entry( &mut tarball, "etc/", b'5', b"" );
+    tar_entry( &mut tarball, "etc/hostname", b'0', b"localhost\n" );
+    tar_entry( &mut tarball, "././@LongLink", b'L', format!( "{}\0", long_path ).as_bytes() );
+    tar_entry( &mut tarball, "usr/lib/xxx", b'0', &[1; 600] );
+    tar_entry( &mut tarball, "PaxHeaders/libbar.so", b'x', b"30 path=usr/lib/libbar.so.1.2\n" );
+    tar_entry( &mut tarball, "libbar.so", b'0', b"bar" );
+    tarball.extend( vec![ 0; 1024 ] );
+
+    let mut files = Vec::new();
+    for_each_tar_file( &tarball[..], |entry, data| {
+        let mut buffer = Vec::new();
+        data.read_to_end( &mut buffer )?;
+        assert_eq!( buffer.len() as u64, entry.size );
+        files.push( (entry.path.clone(), buffer.len()) );
+        Ok(())
+    }).unwrap();
+
+    assert_eq!( files, vec![
+        ("etc/hostname".to_owned(), 10),
+        (long_path, 600),
+        ("usr/lib/libbar.so.1.2".to_owned(), 3)
+    ]);
+}
+
+#[test]
+fn test_sanitize_path() {
+    assert_eq!( sanitize_path( "./blobs/sha256/abc" ), Some( PathBuf::from( "blobs/sha256/abc" ) ) );
+    assert_eq!( sanitize_path( "/index.json" ), Some( PathBuf::from( "index.json" ) ) );
+    assert_eq!( sanitize_path( "../etc/passwd" ), None );
+    assert_eq!( sanitize_path( "./" ), None );
+}
+
+#[test]
+fn test_select_manifest() {
+    let index: Value = serde_json::from_str( &format!( r#"{{
+        "manifests": [
+            {{ "digest": "sha256:aaaa", "platform": {{ "architecture": "s390x", "os": "linux" }} }},
+            {{ "digest": "sha256:bbbb", "platform": {{ "architecture": "{}", "os": "linux" }} }}
+        ]
+    }}"#, host_architecture() ) ).unwrap();
+
+    assert_eq!( select_manifest( &index ).unwrap()[ "digest" ], "sha256:bbbb" );
+    assert_eq!( blob_path( Path::new( "/image" ), "sha256:bbbb" ).unwrap(), PathBuf::from( "/image/blobs/sha256/bbbb" ) );
+    assert!( blob_path( Path::new( "/image" ), "sha256:../../etc" ).is_err() );
+}
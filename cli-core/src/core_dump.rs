@@ -0,0 +1,257 @@
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian};
+use memmap::Mmap;
+
+use crate::importer::invalid_data;
+
+/*
+    A core file is an ELF file of type ET_CORE. The memory of the process is stored in its PT_LOAD segments
+    (`p_filesz` might be smaller than `p_memsz` if the kernel decided not to dump the whole mapping),
+    and the PT_NOTE segment has, among other things, an NT_PRPSINFO note with the PID of the process.
+*/
+
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRPSINFO: u32 = 3;
+
+#[derive(Clone, PartialEq, Debug)]
+struct Segment {
+    address: Range< u64 >,
+    offset: u64
+}
+
+pub struct CoreDump {
+    mmap: Mmap,
+    segments: Vec< Segment >,
+    pid: Option< u32 >
+}
+
+/// The raw values of a program header which we care about.
+struct ProgramHeader {
+    kind: u32,
+    offset: u64,
+    address: u64,
+    file_size: u64
+}
+
+fn parse_program_headers( bytes: &[u8] ) -> io::Result< (bool, Vec< ProgramHeader >) > {
+    if bytes.len() < 52 || &bytes[ ..4 ] != b"\x7fELF" {
+        return Err( invalid_data( "not an ELF file" ) );
+    }
+
+    let is_64bit = match bytes[ 4 ] {
+        1 => false,
+        2 => true,
+        _ => return Err( invalid_data( "invalid ELF class" ) )
+    };
+
+    if bytes[ 5 ] != 1 {
+        return Err( invalid_data( "only little endian core files are supported" ) );
+    }
+
+    if LittleEndian::read_u16( &bytes[ 16.. ] ) != ET_CORE {
+        return Err( invalid_data( "not a core file" ) );
+    }
+
+    let (phoff, phentsize, phnum) = if is_64bit {
+        if bytes.len() < 64 {
+            return Err( invalid_data( "truncated ELF header" ) );
+        }
+
+        (LittleEndian::read_u64( &bytes[ 32.. ] ), LittleEndian::read_u16( &bytes[ 54.. ] ), LittleEndian::read_u16( &bytes[ 56.. ] ))
+    } else {
+        (LittleEndian::read_u32( &bytes[ 28.. ] ) as u64, LittleEndian::read_u16( &bytes[ 42.. ] ), LittleEndian::read_u16( &bytes[ 44.. ] ))
+    };
+
+    // The fields we read have to fit into every header.
+    let minimum_phentsize = if is_64bit { 40 } else { 20 };
+    if phentsize < minimum_phentsize {
+        return Err( invalid_data( format!( "invalid program header size: {}", phentsize ) ) );
+    }
+
+    let mut headers = Vec::with_capacity( phnum as usize );
+    for index in 0..phnum as u64 {
+        // With `phnum` and `phentsize` being 16-bit this can only overflow because of the `phoff`.
+        let range = phoff.checked_add( index * phentsize as u64 ).and_then( |start| Some( start..start.checked_add( phentsize as u64 )? ) );
+        let header = range
+            .and_then( |range| bytes.get( range.start as usize..range.end as usize ) )
+            .ok_or_else( || invalid_data( "truncated program headers" ) )?;
+        let header = if is_64bit {
+            ProgramHeader {
+                kind: LittleEndian::read_u32( &header[ 0.. ] ),
+                offset: LittleEndian::read_u64( &header[ 8.. ] ),
+                address: LittleEndian::read_u64( &header[ 16.. ] ),
+                file_size: LittleEndian::read_u64( &header[ 32.. ] )
+            }
+        } else {
+            ProgramHeader {
+                kind: LittleEndian::read_u32( &header[ 0.. ] ),
+                offset: LittleEndian::read_u32( &header[ 4.. ] ) as u64,
+                address: LittleEndian::read_u32( &header[ 8.. ] ) as u64,
+                file_size: LittleEndian::read_u32( &header[ 16.. ] ) as u64
+            }
+        };
+
+        headers.push( header );
+    }
+
+    Ok( (is_64bit, headers) )
+}
+
+/// Finds the PID of the process in the notes.
+fn parse_pid( notes: &[u8], is_64bit: bool ) -> Option< u32 > {
+    let align = |value: usize| value.checked_add( 3 ).map( |value| value & !3 );
+    let mut offset = 0;
+    while offset + 12 <= notes.len() {
+        let name_size = LittleEndian::read_u32( &notes[ offset.. ] ) as usize;
+        let desc_size = LittleEndian::read_u32( &notes[ offset + 4.. ] ) as usize;
+        let kind = LittleEndian::read_u32( &notes[ offset + 8.. ] );
+        let desc_offset = (offset + 12).checked_add( align( name_size )? )?;
+        let desc = notes.get( desc_offset..desc_offset.checked_add( desc_size )? )?;
+        if kind == NT_PRPSINFO {
+            // struct elf_prpsinfo { char pr_state, pr_sname, pr_zomb, pr_nice; unsigned long pr_flag; uid_t pr_uid; gid_t pr_gid; pid_t pr_pid; ... }
+            // On 32-bit architectures the size of `uid_t` here varies, so we don't bother.
+            if !is_64bit {
+                return None;
+            }
+
+            return desc.get( 24..28 ).map( LittleEndian::read_u32 );
+        }
+
+        offset = desc_offset.checked_add( align( desc_size )? )?;
+    }
+
+    None
+}
+
+impl CoreDump {
+    pub fn load( path: &Path ) -> io::Result< Self > {
+        let fp = File::open( path )?;
+        let mmap = unsafe { Mmap::map( &fp )? };
+        let (is_64bit, headers) = parse_program_headers( &mmap )?;
+
+        let mut pid = None;
+        let mut segments = Vec::new();
+        for header in headers {
+            match header.kind {
+                PT_LOAD if header.file_size > 0 => {
+                    if header.offset.checked_add( header.file_size ).map( |end| end > mmap.len() as u64 ).unwrap_or( true ) {
+                        warn!( "Segment at 0x{:016X} is truncated in {:?}", header.address, path );
+                        continue;
+                    }
+
+                    let end = match header.address.checked_add( header.file_size ) {
+                        Some( end ) => end,
+                        None => {
+                            warn!( "Segment at 0x{:016X} wraps around the address space in {:?}", header.address, path );
+                            continue;
+                        }
+                    };
+
+                    segments.push( Segment {
+                        address: header.address..end,
+                        offset: header.offset
+                    });
+                },
+                PT_NOTE if pid.is_none() => {
+                    let notes = header.offset.checked_add( header.file_size ).and_then( |end| mmap.get( header.offset as usize..end as usize ) );
+                    if let Some( notes ) = notes {
+                        pid = parse_pid( notes, is_64bit );
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        segments.sort_by_key( |segment| segment.address.start );
+        Ok( CoreDump { mmap, segments, pid } )
+    }
+
+    /// The PID of the process which was dumped.
+    pub fn pid( &self ) -> Option< u32 > {
+        self.pid
+    }
+
+    /// Returns the bytes at the given address, or as many of them as were dumped
+    /// if the range crosses the end of a segment.
+    pub fn read( &self, address: u64, length: u64 ) -> Option< &[u8] > {
+        let index = match self.segments.binary_search_by_key( &address, |segment| segment.address.start ) {
+            Ok( index ) => index,
+            Err( 0 ) => return None,
+            Err( index ) => index - 1
+        };
+
+        let segment = &self.segments[ index ];
+        if address >= segment.address.end {
+            return None;
+        }
+
+        let start = segment.offset + (address - segment.address.start);
+        let end = segment.offset + std::cmp::min( address.saturating_add( length ), segment.address.end ) - segment.address.start;
+        Some( &self.mmap[ start as usize..end as usize ] )
+    }
+}
+
+#[test]
+fn test_parse_pid() {
+    let mut notes = Vec::new();
+    let mut push_note = |kind: u32, name: &[u8], desc: &[u8]| {
+        notes.extend( &(name.len() as u32).to_le_bytes() );
+        notes.extend( &(desc.len() as u32).to_le_bytes() );
+        notes.extend( &kind.to_le_bytes() );
+        notes.extend( name );
+        notes.resize( (notes.len() + 3) & !3, 0 );
+        notes.extend( desc );
+        notes.resize( (notes.len() + 3) & !3, 0 );
+    };
+
+    let mut prpsinfo = vec![ 0; 136 ];
+    prpsinfo[ 24..28 ].copy_from_slice( &1234_u32.to_le_bytes() );
+    push_note( 1, b"CORE\0", &[0; 336] );
+    push_note( NT_PRPSINFO, b"CORE\0", &prpsinfo );
+
+    assert_eq!( parse_pid( &notes, true ), Some( 1234 ) );
+    assert_eq!( parse_pid( &notes, false ), None );
+    assert_eq!( parse_pid( &notes[ ..20 ], true ), None );
+}
+
+#[cfg(test)]
+fn core_header( phoff: u64, phentsize: u16, phnum: u16 ) -> Vec< u8 > {
+    let mut bytes = vec![ 0; 64 ];
+    bytes[ ..4 ].copy_from_slice( b"\x7fELF" );
+    bytes[ 4 ] = 2;
+    bytes[ 5 ] = 1;
+    bytes[ 16..18 ].copy_from_slice( &ET_CORE.to_le_bytes() );
+    bytes[ 32..40 ].copy_from_slice( &phoff.to_le_bytes() );
+    bytes[ 54..56 ].copy_from_slice( &phentsize.to_le_bytes() );
+    bytes[ 56..58 ].copy_from_slice( &phnum.to_le_bytes() );
+    bytes
+}
+
+#[test]
+fn test_parse_program_headers_with_invalid_sizes() {
+    let mut bytes = core_header( 64, 56, 1 );
+    bytes.extend( vec![ 0; 56 ] );
+    bytes[ 64..68 ].copy_from_slice( &PT_LOAD.to_le_bytes() );
+    let (is_64bit, headers) = parse_program_headers( &bytes ).unwrap();
+    assert!( is_64bit );
+    assert_eq!( headers.len(), 1 );
+    assert_eq!( headers[ 0 ].kind, PT_LOAD );
+
+    assert!( parse_program_headers( &core_header( 64, 1, 1 ) ).is_err() );
+    assert!( parse_program_headers( &core_header( !0 - 10, 56, 2 ) ).is_err() );
+}
+
+#[test]
+fn test_parse_pid_with_huge_sizes() {
+    let mut notes = Vec::new();
+    notes.extend( &(!0_u32).to_le_bytes() );
+    notes.extend( &(!0_u32).to_le_bytes() );
+    notes.extend( &NT_PRPSINFO.to_le_bytes() );
+    assert_eq!( parse_pid( &notes, true ), None );
+}
@@ -8,15 +8,24 @@ use std::collections::BTreeMap;
 
 use ahash::AHashMap as HashMap;
 use string_interner;
+use speedy::{Readable, Writable, Context, Reader, Writer};
+
+use common::range_map::RangeMap;
 
 use crate::tree::Tree;
 use crate::tree_printer::dump_tree;
 use crate::frame::Frame;
+use crate::frame_rules::{FrameRules, FrameRuleResult};
+use crate::attribution::Attribution;
+use crate::recursion::collapse_recursion;
 use crate::vecvec::DenseVecVec;
+use crate::spill_vec::{Pod, SpillVec};
+use crate::backtrace_trie::{BacktraceTrie, FrameIds};
 use crate::util::{ReadableSize, table_to_string};
+use crate::site_id::SiteId;
 
 pub use common::{Timestamp};
-pub use common::event::DataId;
+pub use common::event::{DataId, AllocatorTunable};
 
 pub type StringInterner = string_interner::StringInterner< StringId >;
 
@@ -37,9 +46,26 @@ impl string_interner::Symbol for StringId {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+impl< 'a, C: Context > Readable< 'a, C > for StringId {
+    fn read_from< R: Reader< 'a, C > >( reader: &mut R ) -> Result< Self, C::Error > {
+        let value = reader.read_u32()?;
+        Ok( StringId( NonZeroU32::new( value ).unwrap_or( NonZeroU32::new( 1 ).unwrap() ) ) )
+    }
+}
+
+impl< C: Context > Writable< C > for StringId {
+    fn write_to< W: ?Sized + Writer< C > >( &self, writer: &mut W ) -> Result< (), C::Error > {
+        writer.write_u32( self.0.get() )
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Readable, Writable)]
+#[repr(transparent)]
 pub struct AllocationId( u64 );
 
+// Mapped straight from the index files.
+unsafe impl Pod for AllocationId {}
+
 impl AllocationId {
     pub(crate) fn new( raw: u64 ) -> Self {
         AllocationId( raw )
@@ -50,9 +76,12 @@ impl AllocationId {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Readable, Writable)]
+#[repr(transparent)]
 pub struct OperationId( u64 );
 
+unsafe impl Pod for OperationId {}
+
 impl OperationId {
     #[inline]
     pub(crate) fn new_allocation( id: AllocationId ) -> Self {
@@ -119,23 +148,40 @@ pub struct Data {
     pub(crate) initial_timestamp: Timestamp,
     pub(crate) last_timestamp: Timestamp,
     pub(crate) executable: String,
+    pub(crate) pid: u32,
     pub(crate) architecture: String,
     pub(crate) pointer_size: u64,
     pub(crate) interner: StringInterner,
-    pub(crate) operations: Vec< OperationId >,
-    pub(crate) allocations: Vec< Allocation >,
-    pub(crate) sorted_by_timestamp: Vec< AllocationId >,
-    pub(crate) sorted_by_address: Vec< AllocationId >,
-    pub(crate) sorted_by_size: Vec< AllocationId >,
+    pub(crate) operations: SpillVec< OperationId >,
+    pub(crate) allocations: SpillVec< Allocation >,
+    pub(crate) sorted_by_timestamp: SpillVec< AllocationId >,
+    pub(crate) sorted_by_address: SpillVec< AllocationId >,
+    pub(crate) sorted_by_size: SpillVec< AllocationId >,
     pub(crate) frames: Vec< Frame >,
     pub(crate) backtraces: Vec< BacktraceStorageRef >,
-    pub(crate) backtraces_storage: Vec< FrameId >,
+    pub(crate) backtrace_trie: BacktraceTrie,
     pub(crate) allocations_by_backtrace: DenseVecVec< AllocationId >,
     pub(crate) total_allocated: u64,
     pub(crate) total_allocated_count: u64,
     pub(crate) total_freed: u64,
     pub(crate) total_freed_count: u64,
+    pub(crate) peak_allocated: u64,
+    pub(crate) peak_allocated_timestamp: Timestamp,
+    pub(crate) unknown_deallocation_count: u64,
+    pub(crate) unknown_deallocation_range: Option< (Timestamp, Timestamp) >,
+    pub(crate) duplicate_allocation_count: u64,
+    pub(crate) lost_chunk_count: u64,
+    pub(crate) lost_event_count: u64,
+    pub(crate) wall_clock_start: Timestamp,
+    pub(crate) build_id: Option< String >,
+    pub(crate) library_build_ids: Vec< (String, Vec< u8 >) >,
     pub(crate) mallopts: Vec< Mallopt >,
+    pub(crate) allocator_stats: Vec< AllocatorStats >,
+    pub(crate) allocator_info: Option< AllocatorInfo >,
+    pub(crate) metadata: Vec< (String, String) >,
+    pub(crate) allocation_contents: Vec< AllocationContents >,
+    pub(crate) library_events: Vec< LibraryEvent >,
+    pub(crate) maps: RangeMap< MapRegion >,
     pub(crate) mmap_operations: Vec< MmapOperation >,
     pub(crate) maximum_backtrace_depth: u32,
     pub(crate) group_stats: Vec< GroupStatistics >
@@ -145,7 +191,7 @@ pub type DataPointer = u64;
 pub type FrameId = usize;
 pub type ThreadId = u32;
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Readable, Writable)]
 pub struct CodePointer( u64 );
 
 impl CodePointer {
@@ -178,7 +224,7 @@ impl From< u64 > for CodePointer {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Readable, Writable)]
 pub struct BacktraceId( u32 );
 
 impl BacktraceId {
@@ -193,7 +239,8 @@ impl BacktraceId {
     }
 }
 
-pub type BacktraceStorageRef = (u32, u32);
+/// The node of the backtrace trie which represents a given backtrace.
+pub type BacktraceStorageRef = u32;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum SourceKey {
@@ -212,7 +259,19 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+impl< 'a, C: Context > Readable< 'a, C > for AllocationFlags {
+    fn read_from< R: Reader< 'a, C > >( reader: &mut R ) -> Result< Self, C::Error > {
+        Ok( AllocationFlags::from_bits_truncate( reader.read_u8()? ) )
+    }
+}
+
+impl< C: Context > Writable< C > for AllocationFlags {
+    fn write_to< W: ?Sized + Writer< C > >( &self, writer: &mut W ) -> Result< (), C::Error > {
+        writer.write_u8( self.bits() )
+    }
+}
+
+#[derive(Copy, Clone, Debug, Readable, Writable)]
 pub struct Allocation {
     pub pointer: DataPointer,
     pub timestamp: Timestamp,
@@ -225,10 +284,12 @@ pub struct Allocation {
     pub flags: AllocationFlags,
     pub extra_usable_space: u32,
     pub marker: u32,
-    pub preceding_free_space: u32
+    pub preceding_free_space: u32,
+    /// The jemalloc arena which was explicitly requested through `MALLOCX_ARENA`, plus one; zero if none was.
+    pub jemalloc_arena: u16
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Readable, Writable)]
 pub struct GroupStatistics {
     pub first_allocation: Timestamp,
     pub last_allocation: Timestamp,
@@ -294,11 +355,24 @@ macro_rules! enum_primitive {
                 value.raw()
             }
         }
+
+        impl< 'a, C: Context > Readable< 'a, C > for $name {
+            fn read_from< R: Reader< 'a, C > >( reader: &mut R ) -> Result< Self, C::Error > {
+                let value: $primitive = Readable::read_from( reader )?;
+                Ok( value.into() )
+            }
+        }
+
+        impl< C: Context > Writable< C > for $name {
+            fn write_to< W: ?Sized + Writer< C > >( &self, writer: &mut W ) -> Result< (), C::Error > {
+                writer.write_value( &self.raw() )
+            }
+        }
     };
 }
 
 enum_primitive! {
-    #[derive(Debug)]
+    #[derive(Clone, Debug)]
     pub enum MalloptKind {
         Other( i32 ),
         TrimThreshold   = -1,
@@ -312,7 +386,7 @@ enum_primitive! {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Readable, Writable)]
 pub struct Mallopt {
     pub timestamp: Timestamp,
     pub backtrace: BacktraceId,
@@ -322,6 +396,65 @@ pub struct Mallopt {
     pub result: i32
 }
 
+/// A region from the last snapshot of `/proc/self/maps` seen in the data.
+#[derive(Clone, Debug, Readable, Writable)]
+pub struct MapRegion {
+    pub is_readable: bool,
+    pub is_writable: bool,
+    pub is_executable: bool,
+    pub is_shared: bool,
+    pub file_offset: u64,
+    pub name: String
+}
+
+/// A library being mapped into or unmapped from the address space,
+/// as seen through consecutive snapshots of `/proc/self/maps`.
+#[derive(Clone, Debug, Readable, Writable)]
+pub struct LibraryEvent {
+    pub timestamp: Timestamp,
+    pub library: String,
+    pub is_loaded: bool
+}
+
+/// The allocator which was used by the profiled process, along with how it was tuned.
+#[derive(Clone, Debug, Readable, Writable)]
+pub struct AllocatorInfo {
+    pub allocator: String,
+    pub allocator_version: String,
+    pub libc_version: String,
+    pub tunables: Vec< AllocatorTunable >
+}
+
+/// The first few bytes of an allocation, as sampled right before it was freed.
+#[derive(Clone, Debug, Readable, Writable)]
+pub struct AllocationContents {
+    pub allocation: AllocationId,
+    pub contents: Vec< u8 >
+}
+
+/// A periodic sample of the statistics reported by the allocator itself and by the OS.
+#[derive(Clone, Debug, Readable, Writable)]
+pub struct AllocatorStats {
+    pub timestamp: Timestamp,
+    pub heap_size: u64,
+    pub heap_used: u64,
+    pub heap_free: u64,
+    pub mmaped: u64,
+    pub resident: u64,
+    pub resident_breakdown: Option< ResidentMemory >,
+    /// The size and the number of the allocations tracked by the profiler which were alive when the sample was taken.
+    pub tracked_size: u64,
+    pub tracked_count: u64
+}
+
+/// How the resident memory is split between its kinds, as reported by the kernel.
+#[derive(Clone, Debug, Readable, Writable)]
+pub struct ResidentMemory {
+    pub anonymous: u64,
+    pub file: u64,
+    pub shared: u64
+}
+
 impl Allocation {
     #[inline]
     pub fn was_deallocated( &self ) -> bool {
@@ -353,6 +486,11 @@ impl Allocation {
         self.size + self.extra_usable_space as u64
     }
 
+    #[inline]
+    pub fn jemalloc_arena( &self ) -> Option< u32 > {
+        (self.jemalloc_arena as u32).checked_sub( 1 )
+    }
+
     #[inline]
     pub fn actual_range( &self, data: &Data ) -> Range< u64 > {
         let multiplier = if self.is_mmaped() { 2 } else { 1 };
@@ -360,7 +498,7 @@ impl Allocation {
     }
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, Readable, Writable)]
 pub struct Deallocation {
     pub timestamp: Timestamp,
     pub thread: ThreadId,
@@ -385,7 +523,7 @@ pub enum Operation< 'a > {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Readable, Writable)]
 pub struct MemoryMap {
     pub timestamp: Timestamp,
     pub pointer: DataPointer,
@@ -399,7 +537,7 @@ pub struct MemoryMap {
     pub offset: u64
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Readable, Writable)]
 pub struct MemoryUnmap {
     pub timestamp: Timestamp,
     pub pointer: DataPointer,
@@ -408,13 +546,45 @@ pub struct MemoryUnmap {
     pub thread: ThreadId
 }
 
-#[derive(Debug)]
+/// A mapping which was resized or moved by `mremap`.
+#[derive(Clone, Debug, Readable, Writable)]
+pub struct MemoryRemap {
+    pub timestamp: Timestamp,
+    pub old_pointer: DataPointer,
+    pub old_length: u64,
+    pub pointer: DataPointer,
+    pub length: u64,
+    pub flags: u32,
+    pub backtrace: BacktraceId,
+    pub thread: ThreadId
+}
+
+#[derive(Clone, Debug, Readable, Writable)]
 pub enum MmapOperation {
     Mmap( MemoryMap ),
-    Munmap( MemoryUnmap )
+    Munmap( MemoryUnmap ),
+    Mremap( MemoryRemap )
+}
+
+impl MmapOperation {
+    pub fn timestamp( &self ) -> Timestamp {
+        match *self {
+            MmapOperation::Mmap( ref op ) => op.timestamp,
+            MmapOperation::Munmap( ref op ) => op.timestamp,
+            MmapOperation::Mremap( ref op ) => op.timestamp
+        }
+    }
+
+    pub fn backtrace( &self ) -> BacktraceId {
+        match *self {
+            MmapOperation::Mmap( ref op ) => op.backtrace,
+            MmapOperation::Munmap( ref op ) => op.backtrace,
+            MmapOperation::Mremap( ref op ) => op.backtrace
+        }
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Readable, Writable)]
 pub struct ProtectionFlags( pub(crate) u32 );
 
 impl ProtectionFlags {
@@ -443,7 +613,7 @@ impl ProtectionFlags {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Readable, Writable)]
 pub struct MapFlags( pub(crate) u32 );
 
 impl MapFlags {
@@ -474,6 +644,19 @@ pub struct CountAndSize {
     pub size: u64
 }
 
+/// Collapses the recursion in backtraces only once per backtrace, instead of once per every allocation.
+pub struct BacktracesWithoutRecursion< 'a > {
+    data: &'a Data,
+    backtraces: HashMap< BacktraceId, Vec< (FrameId, &'a Frame) > >
+}
+
+impl< 'a > BacktracesWithoutRecursion< 'a > {
+    pub fn get( &mut self, id: BacktraceId ) -> &[(FrameId, &'a Frame)] {
+        let data = self.data;
+        self.backtraces.entry( id ).or_insert_with( || data.get_backtrace_without_recursion( id ) )
+    }
+}
+
 #[inline]
 fn binary_search_range< 'a, T, V, W, F >( array: &'a [T], min: Option< V >, max: Option< V >, callback: F ) -> Range< usize >
     where V: Ord + 'a,
@@ -614,6 +797,11 @@ impl Data {
         self.sorted_by( &self.sorted_by_address, min, max, |alloc| &alloc.pointer )
     }
 
+    #[inline]
+    pub fn allocations( &self ) -> &[Allocation] {
+        &self.allocations
+    }
+
     #[inline]
     pub fn allocations_with_id( &self ) -> impl Iterator< Item = (AllocationId, &Allocation) > {
         self.allocations.iter().enumerate().map( |(index, allocation)| (AllocationId::new( index as _ ), allocation) )
@@ -662,6 +850,49 @@ impl Data {
         self.total_freed_count
     }
 
+    pub fn peak_allocated( &self ) -> u64 {
+        self.peak_allocated
+    }
+
+    pub fn peak_allocated_timestamp( &self ) -> Timestamp {
+        self.peak_allocated_timestamp
+    }
+
+    /// The number of deallocations and reallocations of pointers which were never allocated.
+    pub fn unknown_deallocation_count( &self ) -> u64 {
+        self.unknown_deallocation_count
+    }
+
+    /// When the first and the last of the deallocations of pointers which were never allocated happened.
+    pub fn unknown_deallocation_range( &self ) -> Option< (Timestamp, Timestamp) > {
+        self.unknown_deallocation_range
+    }
+
+    /// The number of allocations of pointers which were already allocated.
+    pub fn duplicate_allocation_count( &self ) -> u64 {
+        self.duplicate_allocation_count
+    }
+
+    /// The number of damaged chunks of the data file which had to be skipped.
+    pub fn lost_chunk_count( &self ) -> u64 {
+        self.lost_chunk_count
+    }
+
+    /// The number of events which were dropped along with the damaged chunks.
+    pub fn lost_event_count( &self ) -> u64 {
+        self.lost_event_count
+    }
+
+    /// The wall clock time at which the profiling started, as reported by the profiled process.
+    pub fn wall_clock_start( &self ) -> Timestamp {
+        self.wall_clock_start
+    }
+
+    #[inline]
+    pub fn build_id( &self ) -> Option< &str > {
+        self.build_id.as_ref().map( |build_id| build_id.as_str() )
+    }
+
     #[inline]
     pub fn initial_timestamp( &self ) -> Timestamp {
         self.initial_timestamp
@@ -682,6 +913,11 @@ impl Data {
         &self.executable
     }
 
+    #[inline]
+    pub fn pid( &self ) -> u32 {
+        self.pid
+    }
+
     #[inline]
     pub fn architecture( &self ) -> &str {
         &self.architecture
@@ -702,16 +938,109 @@ impl Data {
         self.maximum_backtrace_depth
     }
 
+    /// Returns the frames of a given backtrace, starting from its innermost frame.
     #[inline]
-    pub fn get_frame_ids( &self, id: BacktraceId ) -> &[FrameId] {
-        let (offset, length) = self.backtraces[ id.0 as usize ];
-        &self.backtraces_storage[ (offset as usize)..(offset + length) as usize ]
+    pub fn get_frame_ids( &self, id: BacktraceId ) -> FrameIds {
+        self.backtrace_trie.get( self.backtraces[ id.0 as usize ] )
     }
 
     pub fn get_frame( &self, id: FrameId ) -> &Frame {
         &self.frames[ id ]
     }
 
+    /// Renames, collapses and drops frames from every backtrace according to the given rules.
+    pub fn apply_frame_rules( &mut self, rules: &FrameRules ) {
+        if rules.is_empty() {
+            return;
+        }
+
+        let mut is_dropped = vec![ false; self.frames.len() ];
+        let mut is_renamed = vec![ false; self.frames.len() ];
+        for (frame_id, frame) in self.frames.iter_mut().enumerate() {
+            let function = match frame.any_function() {
+                Some( function ) => function,
+                None => continue
+            };
+
+            match rules.apply( self.interner.resolve( function ).unwrap() ) {
+                FrameRuleResult::Unchanged => {},
+                FrameRuleResult::Dropped => is_dropped[ frame_id ] = true,
+                FrameRuleResult::Renamed( name ) => {
+                    frame.set_function( self.interner.get_or_intern( name ) );
+                    is_renamed[ frame_id ] = true;
+                }
+            }
+        }
+
+        let mut backtrace_trie = BacktraceTrie::new();
+        let mut maximum_backtrace_depth = 0;
+        for backtrace in self.backtraces.iter_mut() {
+            let mut frame_ids = FrameIds::new();
+            let mut last_renamed_function = None;
+            for frame_id in self.backtrace_trie.get( *backtrace ) {
+                if is_dropped[ frame_id ] {
+                    continue;
+                }
+
+                if is_renamed[ frame_id ] {
+                    let function = self.frames[ frame_id ].function();
+                    if last_renamed_function.is_some() && last_renamed_function == function {
+                        continue;
+                    }
+                    last_renamed_function = function;
+                } else {
+                    last_renamed_function = None;
+                }
+
+                frame_ids.push( frame_id );
+            }
+
+            maximum_backtrace_depth = std::cmp::max( maximum_backtrace_depth, frame_ids.len() as u32 );
+            *backtrace = backtrace_trie.insert( frame_ids );
+        }
+
+        backtrace_trie.shrink_to_fit();
+        self.backtrace_trie = backtrace_trie;
+        self.maximum_backtrace_depth = maximum_backtrace_depth;
+    }
+
+    /// Drops the innermost frames from every backtrace so that the allocations are attributed
+    /// to the frame chosen by the given attribution.
+    pub fn apply_attribution( &mut self, attribution: &Attribution ) {
+        if *attribution == Attribution::InnermostInline {
+            return;
+        }
+
+        let is_skipped: Vec< bool > = self.frames.iter().map( |frame| {
+            match *attribution {
+                Attribution::InnermostInline => false,
+                Attribution::OutermostNonInline => frame.is_inline(),
+                Attribution::OutsideLibraries( .. ) => {
+                    frame.library().map( |library| attribution.is_skipped_library( self.interner.resolve( library ).unwrap() ) ).unwrap_or( false )
+                }
+            }
+        }).collect();
+
+        let mut backtrace_trie = BacktraceTrie::new();
+        let mut maximum_backtrace_depth = 0;
+        for backtrace in self.backtraces.iter_mut() {
+            // The innermost frames come first.
+            let frame_ids = self.backtrace_trie.get( *backtrace );
+            let to_skip = frame_ids.iter().take_while( |&&frame_id| is_skipped[ frame_id ] ).count();
+
+            // If every frame would be skipped then there's nothing better to attribute the allocation to.
+            let to_skip = if to_skip == frame_ids.len() { 0 } else { to_skip };
+
+            let frame_ids = &frame_ids[ to_skip.. ];
+            maximum_backtrace_depth = std::cmp::max( maximum_backtrace_depth, frame_ids.len() as u32 );
+            *backtrace = backtrace_trie.insert( frame_ids.iter().copied() );
+        }
+
+        backtrace_trie.shrink_to_fit();
+        self.backtrace_trie = backtrace_trie;
+        self.maximum_backtrace_depth = maximum_backtrace_depth;
+    }
+
     pub fn get_allocation( &self, id: AllocationId ) -> &Allocation {
         &self.allocations[ id.raw() as usize ]
     }
@@ -725,13 +1054,40 @@ impl Data {
     }
 
     pub fn get_backtrace< 'a >( &'a self, id: BacktraceId ) -> impl SliceLikeIterator< Item = (FrameId, &'a Frame) > + Clone {
-        self.get_frame_ids( id ).iter().rev().map( move |&frame_id| (frame_id, &self.frames[ frame_id ]) )
+        let frame_ids = self.get_frame_ids( id );
+        (0..frame_ids.len()).rev().map( move |index| {
+            let frame_id = frame_ids[ index ];
+            (frame_id, &self.frames[ frame_id ])
+        })
+    }
+
+    /// Returns an ID of the allocation site with a given backtrace which can be used to match it across different captures.
+    pub fn get_site_id( &self, id: BacktraceId ) -> SiteId {
+        crate::site_id::site_id( self, id )
+    }
+
+    /// Returns the site ID only if it'll be the same on every run; see `site_id::stable_site_id`.
+    pub fn get_stable_site_id( &self, id: BacktraceId ) -> Option< SiteId > {
+        crate::site_id::stable_site_id( self, id )
     }
 
     pub fn get_group_statistics( &self, id: BacktraceId ) -> &GroupStatistics {
         &self.group_stats[ id.raw() as usize ]
     }
 
+    /// Returns the size of an allocation weighted by how long it lived, in byte-microseconds;
+    /// an allocation which was never deallocated is considered to live until the end.
+    pub fn byte_microseconds( &self, allocation: &Allocation ) -> u128 {
+        let end = allocation.deallocation.as_ref().map( |deallocation| deallocation.timestamp ).unwrap_or( self.last_timestamp );
+        allocation.size as u128 * (end - allocation.timestamp).as_usecs() as u128
+    }
+
+    /// Returns the sum of the given allocations' sizes weighted by how long each of them lived, in byte-seconds.
+    pub fn byte_seconds< 'a, I >( &self, allocations: I ) -> u64 where I: IntoIterator< Item = &'a Allocation > {
+        let byte_usecs: u128 = allocations.into_iter().map( |allocation| self.byte_microseconds( allocation ) ).sum();
+        (byte_usecs / 1_000_000) as u64
+    }
+
     pub fn all_backtraces< 'a >( &'a self ) ->
         impl SliceLikeIterator<
             Item = (
@@ -762,6 +1118,24 @@ impl Data {
         })
     }
 
+    /// Returns the backtrace starting from its outermost frame, with every recursive cycle collapsed into
+    /// the outermost call of the recursion; frames are considered the same if they're from the same function.
+    pub fn get_backtrace_without_recursion< 'a >( &'a self, id: BacktraceId ) -> Vec< (FrameId, &'a Frame) > {
+        collapse_recursion( self.get_backtrace( id ), |&(_, frame)| {
+            match frame.any_function() {
+                Some( function ) => (Some( function ), None),
+                None => (None, Some( frame.address() ))
+            }
+        })
+    }
+
+    pub fn backtraces_without_recursion( &self ) -> BacktracesWithoutRecursion< '_ > {
+        BacktracesWithoutRecursion {
+            data: self,
+            backtraces: HashMap::new()
+        }
+    }
+
     pub fn raw_tree( &self ) -> Tree< CodePointer, FrameId > {
         let mut tree = Tree::new();
         for (allocation_id, allocation) in self.allocations_with_id() {
@@ -777,22 +1151,30 @@ impl Data {
         tree
     }
 
-    pub fn tree_by_source< F >( &self, filter: F ) -> Tree< SourceKey, FrameId > where F: Fn( &Allocation ) -> bool {
+    pub fn tree_by_source< F >( &self, filter: F, collapse_recursion: bool ) -> Tree< SourceKey, FrameId > where F: Fn( &Allocation ) -> bool {
+        let to_node = |(frame_id, frame): (FrameId, &Frame)| {
+            let key = match (frame.source(), frame.line(), frame.function().or( frame.raw_function() )) {
+                (Some( source ), Some( line ), _) => SourceKey::Location( source, line ),
+                (_, _, Some( function )) => SourceKey::Function( function ),
+                _ => SourceKey::Address( frame.address() )
+            };
+
+            (key, frame_id)
+        };
+
         let mut tree = Tree::new();
+        let mut without_recursion = self.backtraces_without_recursion();
         for (allocation_id, allocation) in self.allocations_with_id() {
             if !filter( allocation ) {
                 continue;
             }
 
-            tree.add_allocation( &allocation, allocation_id, self.get_backtrace( allocation.backtrace ).map( |(frame_id, frame)| {
-                let key = match (frame.source(), frame.line(), frame.function().or( frame.raw_function() )) {
-                    (Some( source ), Some( line ), _) => SourceKey::Location( source, line ),
-                    (_, _, Some( function )) => SourceKey::Function( function ),
-                    _ => SourceKey::Address( frame.address() )
-                };
-
-                (key, frame_id)
-            }));
+            if collapse_recursion {
+                let backtrace = without_recursion.get( allocation.backtrace );
+                tree.add_allocation( &allocation, allocation_id, backtrace.iter().cloned().map( to_node ) );
+            } else {
+                tree.add_allocation( &allocation, allocation_id, self.get_backtrace( allocation.backtrace ).map( to_node ) );
+            }
         }
 
         tree
@@ -822,6 +1204,37 @@ impl Data {
         &self.mallopts
     }
 
+    pub fn allocator_stats( &self ) -> &[AllocatorStats] {
+        &self.allocator_stats
+    }
+
+    pub fn allocator_info( &self ) -> Option< &AllocatorInfo > {
+        self.allocator_info.as_ref()
+    }
+
+    /// The key-value pairs from the `MEMORY_PROFILER_METADATA_*` environment variables of the profiled process.
+    pub fn metadata( &self ) -> &[(String, String)] {
+        &self.metadata
+    }
+
+    pub fn get_allocation_contents( &self, id: AllocationId ) -> Option< &[u8] > {
+        let index = self.allocation_contents.binary_search_by_key( &id, |entry| entry.allocation ).ok()?;
+        Some( &self.allocation_contents[ index ].contents )
+    }
+
+    pub fn library_events( &self ) -> &[LibraryEvent] {
+        &self.library_events
+    }
+
+    pub fn get_library_build_id( &self, library: &str ) -> Option< &[u8] > {
+        let index = self.library_build_ids.binary_search_by( |(name, _)| name.as_str().cmp( library ) ).ok()?;
+        Some( &self.library_build_ids[ index ].1 )
+    }
+
+    pub fn get_map_region( &self, address: DataPointer ) -> Option< (Range< u64 >, &MapRegion) > {
+        self.maps.get( address )
+    }
+
     pub fn mmap_operations( &self ) -> &[MmapOperation] {
         &self.mmap_operations
     }
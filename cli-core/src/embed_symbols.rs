@@ -0,0 +1,138 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use ahash::AHashMap as HashMap;
+
+use common::event::{Event, SymbolTableEntry, SymbolTableFrame};
+use common::lz4_stream::Lz4Writer;
+use common::speedy::Writable;
+
+use memory_profiler_capture::raw::parse_events;
+
+use crate::data::{Data, FrameId, StringId};
+use crate::loader::Loader;
+use crate::symbol_sources::SymbolSources;
+
+#[derive(Default)]
+struct TableBuilder< 'a > {
+    strings: Vec< Cow< 'a, str > >,
+    string_to_index: HashMap< StringId, u32 >,
+    entries: BTreeMap< u64, Vec< SymbolTableFrame > >
+}
+
+impl< 'a > TableBuilder< 'a > {
+    fn intern( &mut self, data: &'a Data, id: Option< StringId > ) -> u32 {
+        let id = match id {
+            Some( id ) => id,
+            None => return 0xFFFFFFFF
+        };
+
+        let strings = &mut self.strings;
+        *self.string_to_index.entry( id ).or_insert_with( || {
+            strings.push( data.interner().resolve( id ).unwrap().into() );
+            strings.len() as u32 - 1
+        })
+    }
+}
+
+/// Groups the frames of every backtrace by their address, starting from the innermost one.
+fn frames_by_address( data: &Data ) -> HashMap< u64, Vec< FrameId > > {
+    let mut output: HashMap< u64, Vec< FrameId > > = HashMap::new();
+    for (backtrace_id, _) in data.all_backtraces() {
+        let frame_ids = data.get_frame_ids( backtrace_id );
+        let mut index = 0;
+        while index < frame_ids.len() {
+            let address = data.get_frame( frame_ids[ index ] ).address().raw();
+            let start = index;
+            while index < frame_ids.len() && data.get_frame( frame_ids[ index ] ).address().raw() == address {
+                index += 1;
+            }
+
+            output.entry( address ).or_insert_with( || frame_ids[ start..index ].to_vec() );
+        }
+    }
+
+    output
+}
+
+fn build_symbol_tables< 'a >( data: &'a Data, build_ids: &HashMap< String, Vec< u8 > > ) -> Vec< Event< 'a > > {
+    let mut builders: BTreeMap< &'a str, TableBuilder< 'a > > = BTreeMap::new();
+    for (address, frame_ids) in frames_by_address( data ) {
+        if frame_ids.iter().all( |&frame_id| data.get_frame( frame_id ).any_function().is_none() ) {
+            continue;
+        }
+
+        let (range, region) = match data.maps.get( address ) {
+            Some( region ) => region,
+            None => continue
+        };
+
+        if region.name.is_empty() || region.name.starts_with( "[" ) {
+            continue;
+        }
+
+        let builder = builders.entry( region.name.as_str() ).or_insert_with( TableBuilder::default );
+        let frames = frame_ids.iter().map( |&frame_id| {
+            let frame = data.get_frame( frame_id );
+            SymbolTableFrame {
+                raw_function: builder.intern( data, frame.raw_function() ),
+                function: builder.intern( data, frame.function() ),
+                source: builder.intern( data, frame.source() ),
+                line: frame.line().unwrap_or( 0xFFFFFFFF ),
+                column: frame.column().unwrap_or( 0xFFFFFFFF ),
+                is_inline: frame.is_inline()
+            }
+        }).collect();
+
+        let relative_address = address - range.start + region.file_offset;
+        builder.entries.insert( relative_address, frames );
+    }
+
+    builders.into_iter().map( |(library, builder)| {
+        let build_id = build_ids.get( library ).cloned().unwrap_or_default();
+        Event::SymbolTable {
+            build_id: build_id.into(),
+            library: library.into(),
+            strings: builder.strings,
+            entries: builder.entries.into_iter().map( |(address, frames)| SymbolTableEntry { address, frames } ).collect()
+        }
+    }).collect()
+}
+
+/// Rewrites a data file with the symbols of all of the addresses from its backtraces embedded into it,
+/// so that it can be analyzed without having access to the original binaries.
+pub fn embed_symbols< P: AsRef< Path >, G: Write >( input: P, output: G, symbol_sources: &SymbolSources ) -> Result< (), io::Error > {
+    let input = input.as_ref();
+    let (header, event_stream) = parse_events( File::open( input )? )?;
+    let mut loader = Loader::new( header, symbol_sources );
+    for event in event_stream {
+        loader.process( event? );
+    }
+
+    let build_ids = loader.library_build_ids();
+    let data = loader.finalize();
+    let tables = build_symbol_tables( &data, &build_ids );
+    info!( "Embedding symbol tables for {} libraries", tables.len() );
+
+    let (header, event_stream) = parse_events( File::open( input )? )?;
+    let mut ofp = Lz4Writer::new( output );
+    Event::Header( header ).write_to_stream( &mut ofp )?;
+    for table in tables {
+        table.write_to_stream( &mut ofp )?;
+    }
+
+    for event in event_stream {
+        let event = event?;
+        if let Event::SymbolTable { .. } = event {
+            continue;
+        }
+
+        event.write_to_stream( &mut ofp )?;
+    }
+
+    ofp.flush()?;
+    Ok(())
+}
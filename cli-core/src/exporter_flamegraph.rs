@@ -11,12 +11,12 @@ use super::{
 use crate::exporter_flamegraph_pl::dump_collation;
 use crate::io_adapter::IoAdapter;
 
-pub fn export_as_flamegraph< T, F >( data: &Data, output: T, filter: F )
+pub fn export_as_flamegraph< T, F >( data: &Data, output: T, filter: F, collapse_recursion: bool )
     where T: fmt::Write,
           F: Fn( &Allocation ) -> bool
 {
     let mut lines = Vec::new();
-    dump_collation( data, filter, |line| {
+    dump_collation( data, filter, collapse_recursion, |line| {
         lines.push( line.to_owned() );
         let result: Result< (), () > = Ok(());
         result
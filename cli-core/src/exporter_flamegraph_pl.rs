@@ -55,24 +55,29 @@ fn dump_collation_impl< O: FnMut( &str ) -> Result< (), E >, K: PartialEq + Clon
     Ok(())
 }
 
-pub fn dump_collation< F, O, E >( data: &Data, filter: F, mut output: O ) -> Result< (), E >
+pub fn dump_collation< F, O, E >( data: &Data, filter: F, collapse_recursion: bool, mut output: O ) -> Result< (), E >
     where F: Fn( &Allocation ) -> bool,
           O: FnMut( &str ) -> Result< (), E >
 {
     let mut tree: Tree< FrameId, &Frame > = Tree::new();
+    let mut without_recursion = data.backtraces_without_recursion();
     for (allocation_id, allocation) in data.allocations_with_id() {
         if !filter( allocation ) {
             continue;
         }
 
-        tree.add_allocation( allocation, allocation_id, data.get_backtrace( allocation.backtrace ) );
+        if collapse_recursion {
+            tree.add_allocation( allocation, allocation_id, without_recursion.get( allocation.backtrace ).iter().cloned() );
+        } else {
+            tree.add_allocation( allocation, allocation_id, data.get_backtrace( allocation.backtrace ) );
+        }
     }
 
     dump_collation_impl( data, &tree, 0, &mut Vec::new(), &mut Vec::new(), &mut output )
 }
 
-pub fn export_as_flamegraph_pl< T: fmt::Write, F: Fn( &Allocation ) -> bool >( data: &Data, mut output: T, filter: F ) -> fmt::Result {
-    dump_collation( data, filter, |line| {
+pub fn export_as_flamegraph_pl< T: fmt::Write, F: Fn( &Allocation ) -> bool >( data: &Data, mut output: T, filter: F, collapse_recursion: bool ) -> fmt::Result {
+    dump_collation( data, filter, collapse_recursion, |line| {
         writeln!( &mut output, "{}", line )
     })
 }
@@ -0,0 +1,295 @@
+use std::cmp::Reverse;
+use std::io::{self, Write};
+
+use ahash::AHashMap as HashMap;
+
+use super::{
+    Allocation,
+    BacktraceId,
+    Data,
+    FrameId,
+    Timestamp
+};
+
+/*
+    The output has the same format as massif's `massif.out.<pid>` (see `importer_massif.rs`),
+    so it can be viewed with `ms_print` or massif-visualizer.
+
+    Just as massif does it by default there are up to 100 snapshots, every 10th of them
+    is a detailed one, and in the detailed snapshots the nodes which hold less than 1%
+    of the heap are merged together. The snapshots are spread out evenly over the whole run,
+    with an extra one at the peak memory usage; the last snapshot is always detailed,
+    since that's where the leaks are. The times are in milliseconds since the start.
+*/
+
+const SNAPSHOT_COUNT: u64 = 100;
+const DETAILED_FREQUENCY: u64 = 10;
+const THRESHOLD_PERCENT: u64 = 1;
+const ROOT: &str = "(heap allocation functions) malloc/new/new[], --alloc-fns, etc.";
+
+#[derive(Copy, Clone, PartialEq)]
+enum SnapshotKind {
+    Empty,
+    Detailed,
+    Peak
+}
+
+#[derive(Default)]
+struct Node {
+    size: u64,
+    children: HashMap< FrameId, usize >
+}
+
+fn describe_frame( data: &Data, frame_id: FrameId ) -> String {
+    let frame = data.get_frame( frame_id );
+    let resolve = |id| data.interner().resolve( id ).unwrap();
+    let function = frame.any_function().map( resolve ).unwrap_or( "???" );
+    let mut output = format!( "0x{:X}: {}", frame.address().raw(), function );
+    if let (Some( source ), Some( line )) = (frame.source(), frame.line()) {
+        output.push_str( &format!( " ({}:{})", resolve( source ), line ) );
+    } else if let Some( library ) = frame.library() {
+        output.push_str( &format!( " (in {})", resolve( library ) ) );
+    }
+
+    output
+}
+
+struct Tree< 'a > {
+    data: &'a Data,
+    nodes: Vec< (Option< FrameId >, Node) >
+}
+
+impl< 'a > Tree< 'a > {
+    fn new( data: &'a Data, live: &HashMap< BacktraceId, u64 > ) -> Self {
+        let mut tree = Tree {
+            data,
+            nodes: vec![ (None, Node::default()) ]
+        };
+
+        for (&backtrace, &size) in live {
+            if size == 0 {
+                continue;
+            }
+
+            let mut frames: Vec< _ > = data.get_backtrace( backtrace ).map( |(frame_id, _)| frame_id ).collect();
+            frames.reverse();
+
+            let mut index = 0;
+            tree.nodes[ 0 ].1.size += size;
+            for frame_id in frames {
+                index = match tree.nodes[ index ].1.children.get( &frame_id ) {
+                    Some( &child ) => child,
+                    None => {
+                        let child = tree.nodes.len();
+                        tree.nodes[ index ].1.children.insert( frame_id, child );
+                        tree.nodes.push( (Some( frame_id ), Node::default()) );
+                        child
+                    }
+                };
+
+                tree.nodes[ index ].1.size += size;
+            }
+        }
+
+        tree
+    }
+
+    fn write( &self, output: &mut impl Write, index: usize, depth: usize, total: u64 ) -> io::Result< () > {
+        let (frame_id, node) = (self.nodes[ index ].0, &self.nodes[ index ].1);
+        let mut children: Vec< _ > = node.children.iter().map( |(&frame_id, &child)| (frame_id, child) ).collect();
+        children.sort_by_key( |&(frame_id, child)| (Reverse( self.nodes[ child ].1.size ), frame_id) );
+
+        let is_significant = |&(_, child): &(FrameId, usize)| self.nodes[ child ].1.size * 100 >= total * THRESHOLD_PERCENT;
+        let below_threshold: Vec< _ > = children.iter().cloned().filter( |child| !is_significant( child ) ).collect();
+        children.retain( is_significant );
+
+        let child_count = children.len() + if below_threshold.is_empty() { 0 } else { 1 };
+        let description = match frame_id {
+            Some( frame_id ) => describe_frame( self.data, frame_id ),
+            None => ROOT.to_owned()
+        };
+
+        writeln!( output, "{:indent$}n{}: {} {}", "", child_count, node.size, description, indent = depth )?;
+        for &(_, child) in &children {
+            self.write( output, child, depth + 1, total )?;
+        }
+
+        if !below_threshold.is_empty() {
+            let size: u64 = below_threshold.iter().map( |&(_, child)| self.nodes[ child ].1.size ).sum();
+            let places = if below_threshold.len() == 1 { "place" } else { "places" };
+            writeln!(
+                output,
+                "{:indent$}n0: {} in {} {}, below massif's threshold ({}.00%)",
+                "", size, below_threshold.len(), places, THRESHOLD_PERCENT, indent = depth + 1
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+struct Exporter< 'a, T: Write > {
+    data: &'a Data,
+    output: T,
+    snapshot_count: u64,
+    live: HashMap< BacktraceId, u64 >,
+    live_size: u64,
+    live_extra_size: u64
+}
+
+impl< 'a, T: Write > Exporter< 'a, T > {
+    fn write_snapshot( &mut self, timestamp: Timestamp, kind: SnapshotKind ) -> io::Result< () > {
+        writeln!( self.output, "#-----------" )?;
+        writeln!( self.output, "snapshot={}", self.snapshot_count )?;
+        writeln!( self.output, "#-----------" )?;
+        writeln!( self.output, "time={}", (timestamp - self.data.initial_timestamp()).as_msecs() )?;
+        writeln!( self.output, "mem_heap_B={}", self.live_size )?;
+        writeln!( self.output, "mem_heap_extra_B={}", self.live_extra_size )?;
+        writeln!( self.output, "mem_stacks_B=0" )?;
+        self.snapshot_count += 1;
+
+        match kind {
+            SnapshotKind::Empty => writeln!( self.output, "heap_tree=empty" ),
+            SnapshotKind::Detailed | SnapshotKind::Peak => {
+                writeln!( self.output, "heap_tree={}", if kind == SnapshotKind::Peak { "peak" } else { "detailed" } )?;
+                Tree::new( self.data, &self.live ).write( &mut self.output, 0, 0, self.live_size )
+            }
+        }
+    }
+}
+
+/// Writes a series of snapshots of the memory usage in the same format as Valgrind's massif uses.
+pub fn export_as_massif< T: Write, F: Fn( &Allocation ) -> bool >( data: &Data, output: T, filter: F ) -> io::Result< () > {
+    // Every event is a change in the memory usage of a backtrace. The sort is stable, so when
+    // a reallocation frees the old allocation and makes a new one at the same time the old one
+    // is freed first, and a peak isn't reported in the middle of it.
+    let mut events = Vec::new();
+    for allocation in data.allocations() {
+        if !filter( allocation ) {
+            continue;
+        }
+
+        let extra_size = allocation.extra_usable_space as u64;
+        events.push( (allocation.timestamp, true, allocation.backtrace, allocation.size, extra_size) );
+        if let Some( ref deallocation ) = allocation.deallocation {
+            events.push( (deallocation.timestamp, false, allocation.backtrace, allocation.size, extra_size) );
+        }
+    }
+
+    events.sort_by_key( |&(timestamp, _, _, _, _)| timestamp );
+
+    let mut peak = None;
+    let mut live_size: u64 = 0;
+    let mut peak_size = 0;
+    for (index, &(_, is_allocation, _, size, _)) in events.iter().enumerate() {
+        if is_allocation {
+            live_size += size;
+            if live_size > peak_size {
+                peak_size = live_size;
+                peak = Some( index );
+            }
+        } else {
+            live_size -= size;
+        }
+    }
+
+    let mut exporter = Exporter {
+        data,
+        output,
+        snapshot_count: 0,
+        live: HashMap::new(),
+        live_size: 0,
+        live_extra_size: 0
+    };
+
+    writeln!( exporter.output, "desc: (none)" )?;
+    writeln!( exporter.output, "cmd: {}", data.executable() )?;
+    writeln!( exporter.output, "time_unit: ms" )?;
+
+    let start = data.initial_timestamp().as_usecs();
+    let span = data.last_timestamp().as_usecs().saturating_sub( start );
+    let count = if span == 0 { 1 } else { SNAPSHOT_COUNT };
+    let mut next = 0;
+    for nth in 0..count {
+        let timestamp = if count == 1 { data.last_timestamp() } else { Timestamp::from_usecs( start + span * nth / (count - 1) ) };
+
+        let mut is_dirty = nth == 0;
+        while next < events.len() && events[ next ].0 <= timestamp {
+            let (event_timestamp, is_allocation, backtrace, size, extra_size) = events[ next ];
+            let live = exporter.live.entry( backtrace ).or_insert( 0 );
+            if is_allocation {
+                *live += size;
+                exporter.live_size += size;
+                exporter.live_extra_size += extra_size;
+            } else {
+                *live -= size;
+                exporter.live_size -= size;
+                exporter.live_extra_size -= extra_size;
+            }
+
+            is_dirty = true;
+            if Some( next ) == peak {
+                exporter.write_snapshot( event_timestamp, SnapshotKind::Peak )?;
+                is_dirty = false;
+            }
+
+            next += 1;
+        }
+
+        let is_last = nth + 1 == count;
+        let kind = if is_last || nth % DETAILED_FREQUENCY == DETAILED_FREQUENCY - 1 { SnapshotKind::Detailed } else { SnapshotKind::Empty };
+        if is_dirty || kind == SnapshotKind::Detailed {
+            exporter.write_snapshot( timestamp, kind )?;
+        }
+    }
+
+    exporter.output.flush()
+}
+
+#[test]
+fn test_export_as_massif() {
+    use crate::importer::{ImportWriter, ImportedFrame};
+
+    let frame = |address, function: &str| ImportedFrame {
+        address,
+        function: Some( function.to_owned() ),
+        source: Some( "main.c".to_owned() ),
+        line: Some( address as u32 ),
+        .. ImportedFrame::default()
+    };
+
+    let mut input = Vec::new();
+    let mut writer = ImportWriter::new( &mut input, &[ "./a.out" ], Timestamp::from_secs( 1 ) ).unwrap();
+    let foo = writer.backtrace( &[ frame( 0x10, "foo" ), frame( 0x20, "main" ) ] ).unwrap();
+    let bar = writer.backtrace( &[ frame( 0x30, "bar" ), frame( 0x20, "main" ) ] ).unwrap();
+    let tiny = writer.backtrace( &[ frame( 0x40, "tiny" ) ] ).unwrap();
+    writer.allocate( Timestamp::from_secs( 1 ), 0x1000, 600, foo ).unwrap();
+    writer.allocate( Timestamp::from_secs( 2 ), 0x2000, 400, bar ).unwrap();
+    writer.allocate( Timestamp::from_secs( 2 ), 0x3000, 1, tiny ).unwrap();
+    writer.deallocate( Timestamp::from_secs( 3 ), 0x2000 ).unwrap();
+    writer.allocate( Timestamp::from_secs( 4 ), 0x4000, 100, bar ).unwrap();
+    writer.finish().unwrap();
+
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( input ) ).unwrap();
+    let mut output = Vec::new();
+    export_as_massif( &data, &mut output, |_| true ).unwrap();
+    let output = String::from_utf8( output ).unwrap();
+
+    assert!( output.starts_with( "desc: (none)\ncmd: ./a.out\ntime_unit: ms\n" ) );
+    assert!( output.contains( "\
+heap_tree=peak
+n3: 1001 (heap allocation functions) malloc/new/new[], --alloc-fns, etc.
+ n1: 600 0x10: foo (main.c:16)
+  n0: 600 0x20: main (main.c:32)
+ n1: 400 0x30: bar (main.c:48)
+  n0: 400 0x20: main (main.c:32)
+ n0: 1 in 1 place, below massif's threshold (1.00%)
+" ) );
+
+    // Converting it back gives the same memory usage at the end.
+    let mut reimported = Vec::new();
+    crate::import_massif( output.as_bytes(), &mut reimported, Timestamp::from_secs( 1 ) ).unwrap();
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( reimported ) ).unwrap();
+    let leaked: u64 = data.allocations().iter().filter( |allocation| !allocation.was_deallocated() ).map( |allocation| allocation.size ).sum();
+    assert_eq!( leaked, 701 );
+}
@@ -0,0 +1,330 @@
+use std::io::{self, Write};
+
+use ahash::AHashMap as HashMap;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use super::{
+    Allocation,
+    BacktraceId,
+    Data,
+    FrameId,
+    StringId
+};
+
+/*
+    The output is a gzipped `profile.proto` message, which is what `go tool pprof` and Speedscope expect:
+
+        Profile { 1: sample_type[], 2: sample[], 3: mapping[], 4: location[], 5: function[], 6: string_table[],
+                  9: time_nanos, 10: duration_nanos, 14: default_sample_type }
+        ValueType { 1: type, 2: unit }
+        Sample { 1: location_id[] (packed), 2: value[] (packed) }
+        Mapping { 1: id, 5: filename, 7: has_functions, 8: has_filenames, 9: has_line_numbers, 10: has_inline_frames }
+        Location { 1: id, 2: mapping_id, 3: address, 4: line[] }
+        Line { 1: function_id, 2: line }
+        Function { 1: id, 2: name, 3: system_name, 4: filename }
+
+    There is one sample per backtrace, with the same four values as Go's heap profiles have:
+    the number and the size of all of the allocations, and of the ones which were never deallocated.
+
+    The frames are emitted innermost first; a location groups a frame with the frames which were inlined
+    into it (the inlined ones come first), and all of the IDs start from 1, since 0 means "none".
+*/
+
+const SAMPLE_TYPES: &[(&str, &str)] = &[
+    ("alloc_objects", "count"),
+    ("alloc_space", "bytes"),
+    ("inuse_objects", "count"),
+    ("inuse_space", "bytes")
+];
+
+fn write_varint( output: &mut Vec< u8 >, mut value: u64 ) {
+    while value >= 0x80 {
+        output.push( (value as u8) | 0x80 );
+        value >>= 7;
+    }
+
+    output.push( value as u8 );
+}
+
+fn write_integer( output: &mut Vec< u8 >, field: u64, value: u64 ) {
+    if value == 0 {
+        return;
+    }
+
+    write_varint( output, field << 3 );
+    write_varint( output, value );
+}
+
+fn write_bytes( output: &mut Vec< u8 >, field: u64, value: &[u8] ) {
+    write_varint( output, field << 3 | 2 );
+    write_varint( output, value.len() as u64 );
+    output.extend_from_slice( value );
+}
+
+fn write_packed( output: &mut Vec< u8 >, field: u64, values: &[u64] ) {
+    let mut buffer = Vec::new();
+    for &value in values {
+        write_varint( &mut buffer, value );
+    }
+
+    write_bytes( output, field, &buffer );
+}
+
+struct Exporter< 'a > {
+    data: &'a Data,
+    output: Vec< u8 >,
+    strings: HashMap< String, u64 >,
+    mappings: HashMap< StringId, u64 >,
+    locations: HashMap< Vec< FrameId >, u64 >,
+    functions: HashMap< (u64, u64, u64), u64 >
+}
+
+impl< 'a > Exporter< 'a > {
+    fn string( &mut self, string: &str ) -> u64 {
+        if let Some( &index ) = self.strings.get( string ) {
+            return index;
+        }
+
+        let index = self.strings.len() as u64;
+        self.strings.insert( string.to_owned(), index );
+        write_bytes( &mut self.output, 6, string.as_bytes() );
+        index
+    }
+
+    fn resolve( &mut self, id: Option< StringId > ) -> u64 {
+        match id {
+            Some( id ) => {
+                let data = self.data;
+                self.string( data.interner().resolve( id ).unwrap() )
+            },
+            None => 0
+        }
+    }
+
+    fn mapping( &mut self, library: StringId ) -> u64 {
+        if let Some( &id ) = self.mappings.get( &library ) {
+            return id;
+        }
+
+        let id = self.mappings.len() as u64 + 1;
+        let filename = self.resolve( Some( library ) );
+        let mut message = Vec::new();
+        write_integer( &mut message, 1, id );
+        write_integer( &mut message, 5, filename );
+
+        // The frames are already symbolized, so pprof shouldn't try to do it by itself.
+        for field in 7..=10 {
+            write_integer( &mut message, field, 1 );
+        }
+
+        write_bytes( &mut self.output, 3, &message );
+        self.mappings.insert( library, id );
+        id
+    }
+
+    fn function( &mut self, frame_id: FrameId ) -> Option< u64 > {
+        let data = self.data;
+        let frame = data.get_frame( frame_id );
+        let system_name = self.resolve( frame.raw_function() );
+        let name = match frame.function() {
+            Some( _ ) => self.resolve( frame.function() ),
+            None => system_name
+        };
+
+        if name == 0 {
+            return None;
+        }
+
+        let filename = self.resolve( frame.source() );
+        let key = (name, system_name, filename);
+        if let Some( &id ) = self.functions.get( &key ) {
+            return Some( id );
+        }
+
+        let id = self.functions.len() as u64 + 1;
+        let mut message = Vec::new();
+        write_integer( &mut message, 1, id );
+        write_integer( &mut message, 2, name );
+        write_integer( &mut message, 3, system_name );
+        write_integer( &mut message, 4, filename );
+        write_bytes( &mut self.output, 5, &message );
+
+        self.functions.insert( key, id );
+        Some( id )
+    }
+
+    /// Returns the ID of a location with the given frames, innermost first.
+    fn location( &mut self, frames: &[FrameId] ) -> u64 {
+        if let Some( &id ) = self.locations.get( frames ) {
+            return id;
+        }
+
+        let data = self.data;
+        let frame = data.get_frame( *frames.last().unwrap() );
+        let id = self.locations.len() as u64 + 1;
+        let mut message = Vec::new();
+        write_integer( &mut message, 1, id );
+        if let Some( library ) = frame.library() {
+            let mapping = self.mapping( library );
+            write_integer( &mut message, 2, mapping );
+        }
+        write_integer( &mut message, 3, frame.address().raw() );
+
+        for &frame_id in frames {
+            if let Some( function ) = self.function( frame_id ) {
+                let mut line = Vec::new();
+                write_integer( &mut line, 1, function );
+                write_integer( &mut line, 2, data.get_frame( frame_id ).line().unwrap_or( 0 ) as u64 );
+                write_bytes( &mut message, 4, &line );
+            }
+        }
+
+        write_bytes( &mut self.output, 4, &message );
+        self.locations.insert( frames.to_vec(), id );
+        id
+    }
+
+    fn sample_locations( &mut self, backtrace: BacktraceId ) -> Vec< u64 > {
+        let data = self.data;
+        let mut frames: Vec< _ > = data.get_backtrace( backtrace ).map( |(frame_id, frame)| (frame_id, frame.is_inline()) ).collect();
+        frames.reverse();
+
+        let mut locations = Vec::new();
+        let mut group = Vec::new();
+        for (frame_id, is_inline) in frames {
+            group.push( frame_id );
+            if !is_inline {
+                locations.push( self.location( &group ) );
+                group.clear();
+            }
+        }
+
+        if !group.is_empty() {
+            locations.push( self.location( &group ) );
+        }
+
+        locations
+    }
+}
+
+/// Writes the allocations, aggregated by their backtraces, as a gzipped pprof heap profile.
+pub fn export_as_pprof< T: io::Write, F: Fn( &Allocation ) -> bool >( data: &Data, output: T, filter: F ) -> io::Result< () > {
+    let mut samples: HashMap< BacktraceId, [u64; 4] > = HashMap::new();
+    for allocation in data.allocations() {
+        if !filter( allocation ) {
+            continue;
+        }
+
+        let values = samples.entry( allocation.backtrace ).or_default();
+        values[ 0 ] += 1;
+        values[ 1 ] += allocation.size;
+        if !allocation.was_deallocated() {
+            values[ 2 ] += 1;
+            values[ 3 ] += allocation.size;
+        }
+    }
+
+    let mut exporter = Exporter {
+        data,
+        output: Vec::new(),
+        strings: HashMap::new(),
+        mappings: HashMap::new(),
+        locations: HashMap::new(),
+        functions: HashMap::new()
+    };
+
+    // The first string in the string table must always be an empty one.
+    exporter.string( "" );
+
+    for &(kind, unit) in SAMPLE_TYPES {
+        let mut message = Vec::new();
+        write_integer( &mut message, 1, exporter.string( kind ) );
+        write_integer( &mut message, 2, exporter.string( unit ) );
+        write_bytes( &mut exporter.output, 1, &message );
+    }
+
+    let mut samples: Vec< _ > = samples.into_iter().collect();
+    samples.sort_by_key( |(backtrace, _)| backtrace.raw() );
+    for (backtrace, values) in samples {
+        let locations = exporter.sample_locations( backtrace );
+
+        let mut message = Vec::new();
+        write_packed( &mut message, 1, &locations );
+        write_packed( &mut message, 2, &values );
+        write_bytes( &mut exporter.output, 2, &message );
+    }
+
+    let default_sample_type = exporter.string( "inuse_space" );
+    let mut profile = exporter.output;
+    write_integer( &mut profile, 9, data.initial_timestamp().as_usecs() * 1000 );
+    write_integer( &mut profile, 10, (data.last_timestamp() - data.initial_timestamp()).as_usecs() * 1000 );
+    write_integer( &mut profile, 14, default_sample_type );
+
+    let mut output = GzEncoder::new( output, Compression::default() );
+    output.write_all( &profile )?;
+    output.finish()?;
+    Ok(())
+}
+
+#[test]
+fn test_write_varint() {
+    let mut output = Vec::new();
+    write_varint( &mut output, 1 );
+    write_varint( &mut output, 300 );
+    write_varint( &mut output, u64::MAX );
+    assert_eq!( output, vec![ 0x01, 0xAC, 0x02, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01 ] );
+
+    let mut output = Vec::new();
+    write_integer( &mut output, 1, 0 );
+    write_integer( &mut output, 2, 150 );
+    write_packed( &mut output, 4, &[ 3, 270 ] );
+    assert_eq!( output, vec![ 0x10, 0x96, 0x01, 0x22, 0x03, 0x03, 0x8E, 0x02 ] );
+}
+
+#[test]
+fn test_export_as_pprof() {
+    use common::Timestamp;
+    use crate::importer::{ImportWriter, ImportedFrame};
+
+    let frame = |address, function: &str, line, is_inline| ImportedFrame {
+        address,
+        library: Some( "/usr/bin/app".to_owned() ),
+        function: Some( function.to_owned() ),
+        source: Some( "main.c".to_owned() ),
+        line: Some( line ),
+        is_inline
+    };
+
+    let mut input = Vec::new();
+    let mut writer = ImportWriter::new( &mut input, &[], Timestamp::from_secs( 1 ) ).unwrap();
+    let backtrace = writer.backtrace( &[ frame( 0x10, "foo", 3, true ), frame( 0x10, "main", 10, false ), frame( 0x20, "main", 20, false ) ] ).unwrap();
+    writer.allocate( Timestamp::from_secs( 1 ), 0x1000, 16, backtrace ).unwrap();
+    writer.allocate( Timestamp::from_secs( 1 ), 0x2000, 32, backtrace ).unwrap();
+    writer.deallocate( Timestamp::from_secs( 1 ), 0x2000 ).unwrap();
+    writer.finish().unwrap();
+
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( input ) ).unwrap();
+    let mut profile = Vec::new();
+    export_as_pprof( &data, &mut profile, |_| true ).unwrap();
+
+    // Importing the profile back has to give the same allocations and backtraces.
+    let mut output = Vec::new();
+    crate::import_pprof( profile.as_slice(), &mut output ).unwrap();
+
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( output ) ).unwrap();
+    let allocations: Vec< _ > = data.allocations().iter().collect();
+    let sizes: Vec< _ > = allocations.iter().map( |allocation| (allocation.size, allocation.was_deallocated()) ).collect();
+    assert_eq!( sizes, vec![ (16, false), (32, true) ] );
+
+    let frames: Vec< _ > = data.get_backtrace( allocations[ 0 ].backtrace ).map( |(_, frame)| {
+        let function = frame.function().map( |id| data.interner().resolve( id ).unwrap().to_owned() );
+        (function, frame.line(), frame.is_inline())
+    }).collect();
+
+    assert_eq!( frames, vec![
+        (Some( "main".to_owned() ), Some( 20 ), false),
+        (Some( "main".to_owned() ), Some( 10 ), false),
+        (Some( "foo".to_owned() ), Some( 3 ), true)
+    ]);
+}
@@ -0,0 +1,191 @@
+use std::io;
+use std::path::Path;
+
+use rusqlite::{Connection, params};
+
+use crate::data::{Data, StringId};
+use crate::virtual_columns::{ColumnValue, VirtualColumns};
+
+const SCHEMA: &str = r#"
+CREATE TABLE info (
+    key TEXT PRIMARY KEY,
+    value TEXT
+);
+
+CREATE TABLE frames (
+    id INTEGER PRIMARY KEY,
+    address INTEGER NOT NULL,
+    is_inline INTEGER NOT NULL,
+    library TEXT,
+    function TEXT,
+    raw_function TEXT,
+    source TEXT,
+    source_line INTEGER,
+    source_column INTEGER
+);
+
+CREATE TABLE backtraces (
+    id INTEGER PRIMARY KEY,
+    alloc_count INTEGER NOT NULL,
+    alloc_size INTEGER NOT NULL,
+    free_count INTEGER NOT NULL,
+    free_size INTEGER NOT NULL,
+    min_size INTEGER NOT NULL,
+    max_size INTEGER NOT NULL,
+    first_allocation INTEGER NOT NULL,
+    last_allocation INTEGER NOT NULL,
+    byte_seconds INTEGER NOT NULL
+);
+
+CREATE TABLE backtrace_frames (
+    backtrace_id INTEGER NOT NULL REFERENCES backtraces ( id ),
+    depth INTEGER NOT NULL,
+    frame_id INTEGER NOT NULL REFERENCES frames ( id ),
+    PRIMARY KEY ( backtrace_id, depth )
+);
+
+CREATE TABLE allocations (
+    id INTEGER PRIMARY KEY,
+    pointer INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    thread INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    extra_usable_space INTEGER NOT NULL,
+    flags INTEGER NOT NULL,
+    marker INTEGER NOT NULL,
+    backtrace_id INTEGER NOT NULL REFERENCES backtraces ( id ),
+    deallocation_timestamp INTEGER,
+    deallocation_thread INTEGER,
+    deallocation_backtrace_id INTEGER REFERENCES backtraces ( id ),
+    reallocated_from INTEGER REFERENCES allocations ( id ),
+    reallocation INTEGER REFERENCES allocations ( id )
+);
+
+CREATE TABLE allocation_columns (
+    allocation_id INTEGER NOT NULL REFERENCES allocations ( id ),
+    name TEXT NOT NULL,
+    value,
+    PRIMARY KEY ( allocation_id, name )
+);
+
+CREATE INDEX allocations_by_timestamp ON allocations ( timestamp );
+CREATE INDEX allocations_by_backtrace ON allocations ( backtrace_id );
+"#;
+
+fn to_io_error( error: rusqlite::Error ) -> io::Error {
+    io::Error::new( io::ErrorKind::Other, error )
+}
+
+fn export( data: &Data, columns: &VirtualColumns, connection: &mut Connection ) -> Result< (), rusqlite::Error > {
+    connection.execute_batch( SCHEMA )?;
+
+    let transaction = connection.transaction()?;
+    {
+        let resolve = |id: Option< StringId >| id.map( |id| data.interner().resolve( id ).unwrap() );
+
+        let mut statement = transaction.prepare( "INSERT INTO info ( key, value ) VALUES ( ?, ? )" )?;
+        let info = [
+            ("id", data.id().to_string()),
+            ("executable", data.executable().to_owned()),
+            ("architecture", data.architecture().to_owned()),
+            ("pointer_size", data.pointer_size().to_string()),
+            ("initial_timestamp", data.initial_timestamp().as_usecs().to_string()),
+            ("last_timestamp", data.last_timestamp().as_usecs().to_string()),
+            ("build_id", data.build_id().unwrap_or( "" ).to_owned())
+        ];
+
+        for (key, value) in info.iter() {
+            statement.execute( params![ key, value ] )?;
+        }
+
+        let mut statement = transaction.prepare( "INSERT INTO frames VALUES ( ?, ?, ?, ?, ?, ?, ?, ?, ? )" )?;
+        for (frame_id, frame) in data.frames.iter().enumerate() {
+            statement.execute( params![
+                frame_id as i64,
+                frame.address().raw() as i64,
+                frame.is_inline(),
+                resolve( frame.library() ),
+                resolve( frame.function() ),
+                resolve( frame.raw_function() ),
+                resolve( frame.source() ),
+                frame.line(),
+                frame.column()
+            ])?;
+        }
+
+        let mut statement = transaction.prepare( "INSERT INTO backtraces VALUES ( ?, ?, ?, ?, ?, ?, ?, ?, ?, ? )" )?;
+        let mut frame_statement = transaction.prepare( "INSERT INTO backtrace_frames VALUES ( ?, ?, ? )" )?;
+        for (backtrace_id, _) in data.all_backtraces() {
+            let stats = data.get_group_statistics( backtrace_id );
+            statement.execute( params![
+                backtrace_id.raw(),
+                stats.alloc_count as i64,
+                stats.alloc_size as i64,
+                stats.free_count as i64,
+                stats.free_size as i64,
+                stats.min_size as i64,
+                stats.max_size as i64,
+                stats.first_allocation.as_usecs() as i64,
+                stats.last_allocation.as_usecs() as i64,
+                data.byte_seconds( data.get_allocations_by_backtrace( backtrace_id ).map( |(_, allocation)| allocation ) ) as i64
+            ])?;
+
+            // The frames are stored starting from the innermost one.
+            for (depth, &frame_id) in data.get_frame_ids( backtrace_id ).iter().enumerate() {
+                frame_statement.execute( params![ backtrace_id.raw(), depth as i64, frame_id as i64 ] )?;
+            }
+        }
+
+        let mut statement = transaction.prepare( "INSERT INTO allocations VALUES ( ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ? )" )?;
+        let mut column_statement = transaction.prepare( "INSERT INTO allocation_columns VALUES ( ?, ?, ? )" )?;
+        for (allocation_id, allocation) in data.allocations_with_id() {
+            let deallocation = allocation.deallocation.as_ref();
+            statement.execute( params![
+                allocation_id.raw() as i64,
+                allocation.pointer as i64,
+                allocation.timestamp.as_usecs() as i64,
+                allocation.thread,
+                allocation.size as i64,
+                allocation.extra_usable_space,
+                allocation.flags.bits(),
+                allocation.marker,
+                allocation.backtrace.raw(),
+                deallocation.map( |deallocation| deallocation.timestamp.as_usecs() as i64 ),
+                deallocation.map( |deallocation| deallocation.thread ),
+                deallocation.and_then( |deallocation| deallocation.backtrace ).map( |backtrace| backtrace.raw() ),
+                allocation.reallocated_from.map( |id| id.raw() as i64 ),
+                allocation.reallocation.map( |id| id.raw() as i64 )
+            ])?;
+
+            if columns.is_empty() {
+                continue;
+            }
+
+            for (name, value) in columns.names().zip( columns.evaluate( data, allocation ) ) {
+                let value = match value {
+                    ColumnValue::Null => rusqlite::types::Value::Null,
+                    ColumnValue::Bool( value ) => rusqlite::types::Value::Integer( value as i64 ),
+                    ColumnValue::Number( value ) if value.fract() == 0.0 && value.abs() < 9.0e15 => rusqlite::types::Value::Integer( value as i64 ),
+                    ColumnValue::Number( value ) => rusqlite::types::Value::Real( value ),
+                    ColumnValue::String( value ) => rusqlite::types::Value::Text( value )
+                };
+
+                column_statement.execute( params![ allocation_id.raw() as i64, name, value ] )?;
+            }
+        }
+    }
+
+    transaction.commit()
+}
+
+/// Writes the processed data into an SQLite database which can be queried directly,
+/// along with the values of the given virtual columns for every allocation.
+pub fn export_as_sqlite< P: AsRef< Path > >( data: &Data, columns: &VirtualColumns, path: P ) -> io::Result< () > {
+    let path = path.as_ref();
+    if path.exists() {
+        return Err( io::Error::new( io::ErrorKind::AlreadyExists, format!( "{:?} already exists", path ) ) );
+    }
+
+    let mut connection = Connection::open( path ).map_err( to_io_error )?;
+    export( data, columns, &mut connection ).map_err( to_io_error )
+}
@@ -0,0 +1,102 @@
+use std::io;
+use std::path::Path;
+
+use crate::attribution::Attribution;
+use crate::data::{Allocation, Data};
+use crate::frame_rules::FrameRules;
+use crate::loader::Loader;
+use crate::symbol_sources::SymbolSources;
+use crate::virtual_columns::{Expression, VirtualColumns};
+
+/// A data file loaded the same way by every subcommand which analyzes one: with the frame rules
+/// and the attribution applied, along with the virtual columns and the filter expression given on the command line.
+pub struct FilteredData {
+    pub data: Data,
+    columns: VirtualColumns,
+    filter: Option< Expression >
+}
+
+fn invalid_input< E: std::fmt::Display >( error: E ) -> io::Error {
+    io::Error::new( io::ErrorKind::InvalidInput, error.to_string() )
+}
+
+impl FilteredData {
+    /// The columns and the filter are parsed before the data is loaded, so that a typo doesn't have to wait for it.
+    pub fn load(
+        path: &Path,
+        symbol_sources: &SymbolSources,
+        frame_rules: Option< &Path >,
+        attribution: &Attribution,
+        columns: Option< &str >,
+        filter: Option< &str >
+    ) -> Result< Self, io::Error > {
+        let columns = VirtualColumns::parse( columns.unwrap_or( "" ), None ).map_err( invalid_input )?;
+        let filter = match filter {
+            Some( filter ) => Some( Expression::parse( filter, &columns, None ).map_err( invalid_input )? ),
+            None => None
+        };
+
+        let frame_rules = match frame_rules {
+            Some( path ) => Some( FrameRules::load( path )? ),
+            None => None
+        };
+
+        let mut data = Loader::load_from_file( path, symbol_sources )?;
+        if let Some( ref frame_rules ) = frame_rules {
+            data.apply_frame_rules( frame_rules );
+        }
+        data.apply_attribution( attribution );
+
+        Ok( FilteredData { data, columns, filter } )
+    }
+
+    pub fn columns( &self ) -> &VirtualColumns {
+        &self.columns
+    }
+
+    /// Whether the allocation passes the filter; everything does if there's none.
+    pub fn matches( &self, allocation: &Allocation ) -> bool {
+        match self.filter {
+            Some( ref filter ) => filter.evaluate( &self.data, allocation, &self.columns.evaluate( &self.data, allocation ) ).is_truthy(),
+            None => true
+        }
+    }
+}
+
+#[test]
+fn test_filtered_data() {
+    use crate::Timestamp;
+    use crate::importer::{ImportWriter, ImportedFrame};
+
+    let frame = ImportedFrame { address: 0x10, function: Some( "foo".to_owned() ), .. ImportedFrame::default() };
+    let mut input = Vec::new();
+    let mut writer = ImportWriter::new( &mut input, &[ "./a.out" ], Timestamp::from_secs( 1 ) ).unwrap();
+    let backtrace = writer.backtrace( &[ frame ] ).unwrap();
+    writer.allocate( Timestamp::from_secs( 1 ), 0x1000, 100, backtrace ).unwrap();
+    writer.allocate( Timestamp::from_secs( 2 ), 0x2000, 200, backtrace ).unwrap();
+    writer.finish().unwrap();
+
+    let directory = std::env::temp_dir().join( format!( "memory-profiler-test-filtered-{}", std::process::id() ) );
+    std::fs::create_dir_all( &directory ).unwrap();
+    let path = directory.join( "memory-profiling.dat" );
+    std::fs::write( &path, &input ).unwrap();
+
+    let symbol_sources = SymbolSources::default();
+    let attribution = Attribution::default();
+    let load = |columns, filter| FilteredData::load( &path, &symbol_sources, None, &attribution, columns, filter );
+
+    let filtered = load( Some( "big = size > 150" ), Some( "big" ) ).unwrap();
+    let matched: Vec< _ > = filtered.data.allocations().iter().filter( |allocation| filtered.matches( allocation ) ).map( |allocation| allocation.size ).collect();
+    assert_eq!( matched, vec![ 200 ] );
+    assert_eq!( filtered.columns().names().collect::< Vec< _ > >(), vec![ "big" ] );
+
+    let unfiltered = load( None, None ).unwrap();
+    assert!( unfiltered.data.allocations().iter().all( |allocation| unfiltered.matches( allocation ) ) );
+
+    // The filter is checked before anything is loaded.
+    let missing = directory.join( "missing.dat" );
+    let error = FilteredData::load( &missing, &symbol_sources, None, &attribution, None, Some( "size >" ) ).err().unwrap();
+    assert_eq!( error.kind(), io::ErrorKind::InvalidInput );
+
+    let _ = std::fs::remove_dir_all( &directory );
+}
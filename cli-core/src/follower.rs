@@ -0,0 +1,191 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ByteOrder};
+
+use common::chunked_stream::{CHUNK_HEADER_SIZE, ChunkContents, chunk_contents};
+use common::event::Event;
+use common::speedy::Readable;
+
+use memory_profiler_capture::is_encrypted;
+
+use crate::data::Data;
+use crate::loader::Loader;
+use crate::symbol_sources::SymbolSources;
+
+/// Incrementally loads a data stream which is delivered in pieces, e.g. straight from a profiled process.
+///
+/// An incomplete chunk or event at the end of what was pushed so far is kept until the rest of it arrives.
+pub struct IncrementalLoader {
+    symbol_sources: SymbolSources,
+    loader: Option< Loader >,
+    raw: Vec< u8 >,
+    decompressed: Vec< u8 >
+}
+
+impl IncrementalLoader {
+    pub fn new( symbol_sources: &SymbolSources ) -> Self {
+        IncrementalLoader {
+            symbol_sources: symbol_sources.clone(),
+            loader: None,
+            raw: Vec::new(),
+            decompressed: Vec::new()
+        }
+    }
+
+    /// Processes the next piece of the stream; returns the number of processed events.
+    pub fn push( &mut self, data: &[u8] ) -> Result< usize, io::Error > {
+        self.raw.extend_from_slice( data );
+        self.process_chunks()
+    }
+
+    fn process_chunks( &mut self ) -> Result< usize, io::Error > {
+        let mut position = 0;
+        while self.raw.len() - position >= CHUNK_HEADER_SIZE {
+            let kind = self.raw[ position ];
+            let length = LittleEndian::read_u32( &self.raw[ position + 1..position + CHUNK_HEADER_SIZE ] ) as usize;
+            let chunk_start = position + CHUNK_HEADER_SIZE;
+            if self.raw.len() - chunk_start < length {
+                break;
+            }
+
+            let chunk = &self.raw[ chunk_start..chunk_start + length ];
+            match chunk_contents( kind, chunk )? {
+                ChunkContents::Compressed( offset ) => {
+                    lz4_compress::decompress_into( &chunk[ offset.. ], &mut self.decompressed )
+                        .map_err( |_| io::Error::new( io::ErrorKind::InvalidData, "decompression error" ) )?;
+                },
+                ChunkContents::Uncompressed( offset ) => {
+                    self.decompressed.extend_from_slice( &chunk[ offset.. ] );
+                },
+                ChunkContents::Metadata => {},
+                ChunkContents::Damaged( event_count ) => {
+                    warn!( "Skipping a damaged chunk of {} bytes with {} events", CHUNK_HEADER_SIZE + length, event_count );
+                    if let Some( ref mut loader ) = self.loader {
+                        loader.record_data_loss( 1, event_count as u64 );
+                    }
+                }
+            }
+
+            position = chunk_start + length;
+        }
+
+        self.raw.drain( ..position );
+        self.process_events()
+    }
+
+    fn process_events( &mut self ) -> Result< usize, io::Error > {
+        let mut count = 0;
+        let mut position = 0;
+        loop {
+            let mut cursor = io::Cursor::new( &self.decompressed[ position.. ] );
+            let event: Event< 'static > = match Event::read_from_stream_unbuffered( &mut cursor ) {
+                Ok( event ) => event,
+                Err( error ) => {
+                    let error: io::Error = error.into();
+                    if error.kind() == io::ErrorKind::UnexpectedEof {
+                        break;
+                    }
+
+                    return Err( error );
+                }
+            };
+
+            position += cursor.position() as usize;
+            count += 1;
+
+            match self.loader {
+                Some( ref mut loader ) => loader.process( event ),
+                None => {
+                    let header = match event {
+                        Event::Header( header ) => header,
+                        _ => return Err( io::Error::new( io::ErrorKind::Other, "data file doesn't start with a proper header" ) )
+                    };
+
+                    self.loader = Some( Loader::new( header, &self.symbol_sources ) );
+                }
+            }
+        }
+
+        self.decompressed.drain( ..position );
+        Ok( count )
+    }
+
+    /// Returns the data loaded so far, or `None` if not even the header was received yet.
+    pub fn snapshot( &self ) -> Option< Data > {
+        self.loader.as_ref().map( |loader| loader.snapshot() )
+    }
+}
+
+/// Incrementally loads a data file which is still being written to.
+///
+/// Every call to `poll` picks up whatever was appended to the file since the last call.
+pub struct Follower {
+    fp: File,
+    loader: IncrementalLoader
+}
+
+impl Follower {
+    pub fn new< P: AsRef< Path > >( path: P, symbol_sources: &SymbolSources ) -> Result< Self, io::Error > {
+        let path = path.as_ref();
+        if is_encrypted( path )? {
+            return Err( io::Error::new( io::ErrorKind::Other, "following encrypted data files is not supported" ) );
+        }
+
+        let fp = File::open( path )?;
+        Ok( Follower {
+            fp,
+            loader: IncrementalLoader::new( symbol_sources )
+        })
+    }
+
+    /// Processes the data appended to the file since the last call; returns the number of processed events.
+    pub fn poll( &mut self ) -> Result< usize, io::Error > {
+        self.fp.read_to_end( &mut self.loader.raw )?;
+        self.loader.process_chunks()
+    }
+
+    /// Returns the data loaded so far, or `None` if not even the header was written yet.
+    pub fn snapshot( &self ) -> Option< Data > {
+        self.loader.snapshot()
+    }
+}
+
+#[test]
+fn test_incremental_loader_push() {
+    use crate::Timestamp;
+    use crate::importer::{ImportWriter, ImportedFrame};
+
+    let frame = ImportedFrame { address: 0x10, function: Some( "foo".to_owned() ), .. ImportedFrame::default() };
+    let mut input = Vec::new();
+    let mut writer = ImportWriter::new( &mut input, &[ "./a.out" ], Timestamp::from_secs( 1 ) ).unwrap();
+    let backtrace = writer.backtrace( &[ frame ] ).unwrap();
+    writer.allocate( Timestamp::from_secs( 1 ), 0x1000, 100, backtrace ).unwrap();
+    writer.allocate( Timestamp::from_secs( 2 ), 0x2000, 200, backtrace ).unwrap();
+    writer.deallocate( Timestamp::from_secs( 3 ), 0x1000 ).unwrap();
+    writer.finish().unwrap();
+
+    // Cut the stream at every possible point, including in the middle of the chunk headers.
+    let symbol_sources = SymbolSources::default();
+    let mut loader = IncrementalLoader::new( &symbol_sources );
+    assert_eq!( loader.push( &input[ ..3 ] ).unwrap(), 0 );
+    assert!( loader.snapshot().is_none() );
+
+    let mut count = 0;
+    for byte in &input[ 3.. ] {
+        count += loader.push( &[ *byte ] ).unwrap();
+    }
+
+    let data = loader.snapshot().unwrap();
+    assert!( count > 3 );
+    assert_eq!( data.allocations().len(), 2 );
+    assert_eq!( data.allocations().iter().filter( |allocation| allocation.was_deallocated() ).count(), 1 );
+
+    let mut loader = IncrementalLoader::new( &symbol_sources );
+    assert_eq!( loader.push( &input ).unwrap(), count );
+    assert_eq!( loader.snapshot().unwrap().allocations().len(), 2 );
+
+    let mut loader = IncrementalLoader::new( &symbol_sources );
+    assert!( loader.push( &[ 0xff, 1, 0, 0, 0, 0 ] ).is_err() );
+}
@@ -1,4 +1,5 @@
 use std::num::NonZeroU32;
+use speedy::{Readable, Writable, Context, Reader, Writer};
 use crate::data::{CodePointer, StringId};
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -76,6 +77,10 @@ impl Frame {
         self.column.map( |line| line.get() )
     }
 
+    pub fn set_address( &mut self, address: CodePointer ) {
+        self.address = address;
+    }
+
     pub fn set_is_inline( &mut self, value: bool ) {
         self.is_inline = value;
     }
@@ -109,7 +114,41 @@ impl Frame {
         self.count += value;
     }
 
+    pub(crate) fn reset_count( &mut self ) {
+        self.count = 0;
+    }
+
     pub fn any_function( &self ) -> Option< StringId > {
         self.function.or( self.raw_function )
     }
 }
+
+impl< 'a, C: Context > Readable< 'a, C > for Frame {
+    fn read_from< R: Reader< 'a, C > >( reader: &mut R ) -> Result< Self, C::Error > {
+        Ok( Frame {
+            address: CodePointer::new( reader.read_u64()? ),
+            count: reader.read_u64()?,
+            is_inline: reader.read_u8()? != 0,
+            library: Readable::read_from( reader )?,
+            function: Readable::read_from( reader )?,
+            raw_function: Readable::read_from( reader )?,
+            source: Readable::read_from( reader )?,
+            line: NonZeroU32::new( reader.read_u32()? ),
+            column: NonZeroU32::new( reader.read_u32()? )
+        })
+    }
+}
+
+impl< C: Context > Writable< C > for Frame {
+    fn write_to< W: ?Sized + Writer< C > >( &self, writer: &mut W ) -> Result< (), C::Error > {
+        writer.write_u64( self.address.raw() )?;
+        writer.write_u64( self.count )?;
+        writer.write_u8( self.is_inline as u8 )?;
+        writer.write_value( &self.library )?;
+        writer.write_value( &self.function )?;
+        writer.write_value( &self.raw_function )?;
+        writer.write_value( &self.source )?;
+        writer.write_u32( self.line().unwrap_or( 0 ) )?;
+        writer.write_u32( self.column().unwrap_or( 0 ) )
+    }
+}
@@ -0,0 +1,111 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use regex::Regex;
+
+#[derive(Clone, Debug)]
+enum FrameRuleAction {
+    Rename( String ),
+    Drop
+}
+
+#[derive(Clone, Debug)]
+struct FrameRule {
+    regex: Regex,
+    action: FrameRuleAction
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum FrameRuleResult {
+    Unchanged,
+    Renamed( String ),
+    Dropped
+}
+
+/// A list of rules used to rename, collapse and drop frames based on their function names.
+///
+/// Every non-empty line which doesn't start with `#` is a single rule of the form `<regex> => <replacement>`.
+/// The rules are applied in order, each one to the output of the previous one; the replacement can refer
+/// to the capture groups of its regex (e.g. `$1`), and an empty replacement drops the frame altogether.
+/// Consecutive frames which were renamed to the same name are collapsed into a single frame.
+#[derive(Clone, Debug, Default)]
+pub struct FrameRules {
+    rules: Vec< FrameRule >
+}
+
+impl FrameRules {
+    pub fn load( path: &Path ) -> Result< Self, io::Error > {
+        let input = fs::read_to_string( path )?;
+        Self::parse( &input ).map_err( |error| {
+            io::Error::new( io::ErrorKind::InvalidData, format!( "failed to parse {:?}: {}", path, error ) )
+        })
+    }
+
+    pub fn parse( input: &str ) -> Result< Self, String > {
+        let mut rules = Vec::new();
+        for (index, line) in input.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with( "#" ) {
+                continue;
+            }
+
+            let separator = line.rfind( "=>" ).ok_or_else( || format!( "line {}: missing '=>'", index + 1 ) )?;
+            let pattern = line[ ..separator ].trim();
+            let replacement = line[ separator + 2.. ].trim();
+            let regex = Regex::new( pattern ).map_err( |error| format!( "line {}: {}", index + 1, error ) )?;
+            let action = if replacement.is_empty() {
+                FrameRuleAction::Drop
+            } else {
+                FrameRuleAction::Rename( replacement.to_owned() )
+            };
+
+            rules.push( FrameRule { regex, action } );
+        }
+
+        Ok( FrameRules { rules } )
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn apply( &self, function: &str ) -> FrameRuleResult {
+        let mut output: Option< String > = None;
+        for rule in &self.rules {
+            let current = output.as_ref().map( |output| output.as_str() ).unwrap_or( function );
+            if !rule.regex.is_match( current ) {
+                continue;
+            }
+
+            match rule.action {
+                FrameRuleAction::Drop => return FrameRuleResult::Dropped,
+                FrameRuleAction::Rename( ref replacement ) => {
+                    output = Some( rule.regex.replace_all( current, replacement.as_str() ).into_owned() );
+                }
+            }
+        }
+
+        match output {
+            Some( output ) => FrameRuleResult::Renamed( output ),
+            None => FrameRuleResult::Unchanged
+        }
+    }
+}
+
+#[test]
+fn test_frame_rules() {
+    let rules = FrameRules::parse( r#"
+        # Comment
+        ^std::__1::.* => std::__1
+        <.+> => <...>
+        ^tcmalloc:: =>
+    "# ).unwrap();
+
+    assert_eq!( rules.apply( "main" ), FrameRuleResult::Unchanged );
+    assert_eq!( rules.apply( "std::__1::vector<int>::push_back" ), FrameRuleResult::Renamed( "std::__1".to_owned() ) );
+    assert_eq!( rules.apply( "foo<bar<int>>::baz" ), FrameRuleResult::Renamed( "foo<...>::baz".to_owned() ) );
+    assert_eq!( rules.apply( "tcmalloc::allocate" ), FrameRuleResult::Dropped );
+    assert!( FrameRules::parse( "foo" ).is_err() );
+    assert!( FrameRules::parse( "( => bar" ).is_err() );
+}
@@ -0,0 +1,275 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::importer::invalid_data;
+
+/*
+    A minimal client for the OCI distribution API (which is what both Docker Hub and every other registry implement):
+
+        GET /v2/<repository>/manifests/<tag or digest>
+        GET /v2/<repository>/blobs/<digest>
+
+    Anonymous access is tried first; if the registry wants a token then it's fetched from the realm it tells us about,
+    with the credentials from `MEMORY_PROFILER_REGISTRY_USERNAME` and `MEMORY_PROFILER_REGISTRY_PASSWORD` if they're set.
+*/
+
+const DOCKER_HUB: &str = "registry-1.docker.io";
+const MAX_MANIFEST_SIZE: u64 = 4 * 1024 * 1024;
+
+const MANIFEST_TYPES: &str = "\
+    application/vnd.oci.image.index.v1+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+
+#[derive(PartialEq, Debug)]
+pub struct Reference {
+    pub registry: String,
+    pub repository: String,
+    /// Either a tag or a digest.
+    pub reference: String
+}
+
+/// Parses an image reference the same way as Docker does, e.g. `ubuntu` is `registry-1.docker.io/library/ubuntu:latest`.
+pub fn parse_reference( image: &str ) -> Option< Reference > {
+    let (name, reference) = match image.find( '@' ) {
+        Some( index ) => (&image[ ..index ], image[ index + 1.. ].to_owned()),
+        None => {
+            let last_slash = image.rfind( '/' ).map( |index| index + 1 ).unwrap_or( 0 );
+            match image[ last_slash.. ].find( ':' ) {
+                Some( index ) => (&image[ ..last_slash + index ], image[ last_slash + index + 1.. ].to_owned()),
+                None => (image, "latest".to_owned())
+            }
+        }
+    };
+
+    if name.is_empty() || reference.is_empty() {
+        return None;
+    }
+
+    let mut iter = name.splitn( 2, '/' );
+    let first = iter.next()?;
+    let (registry, repository) = match iter.next() {
+        Some( rest ) if first.contains( '.' ) || first.contains( ':' ) || first == "localhost" => (first.to_owned(), rest.to_owned()),
+        Some( _ ) => (DOCKER_HUB.to_owned(), name.to_owned()),
+        None => (DOCKER_HUB.to_owned(), format!( "library/{}", name ))
+    };
+
+    let registry = if registry == "docker.io" { DOCKER_HUB.to_owned() } else { registry };
+    Some( Reference { registry, repository, reference } )
+}
+
+fn to_hex( bytes: &[u8] ) -> String {
+    bytes.iter().map( |byte| format!( "{:02x}", byte ) ).collect()
+}
+
+/// Hashes the data as it's being written, so that it can be checked against its digest afterwards.
+pub struct DigestWriter< W > {
+    inner: W,
+    hasher: Sha256
+}
+
+impl< W: Write > DigestWriter< W > {
+    pub fn new( inner: W ) -> Self {
+        DigestWriter { inner, hasher: Sha256::new() }
+    }
+
+    pub fn verify( self, digest: &str ) -> io::Result< W > {
+        let expected = digest.strip_prefix( "sha256:" ).ok_or_else( || invalid_data( format!( "unsupported digest: '{}'", digest ) ) )?;
+        let actual = to_hex( &self.hasher.finalize() );
+        if actual != expected {
+            return Err( invalid_data( format!( "digest mismatch: expected '{}', got 'sha256:{}'", digest, actual ) ) );
+        }
+
+        Ok( self.inner )
+    }
+}
+
+impl< W: Write > Write for DigestWriter< W > {
+    fn write( &mut self, buffer: &[u8] ) -> io::Result< usize > {
+        let count = self.inner.write( buffer )?;
+        self.hasher.update( &buffer[ ..count ] );
+        Ok( count )
+    }
+
+    fn flush( &mut self ) -> io::Result< () > {
+        self.inner.flush()
+    }
+}
+
+/// Checks that the data which is read from `fp` matches the `digest`.
+pub fn verify_digest< R: Read >( mut fp: R, digest: &str ) -> io::Result< () > {
+    let mut writer = DigestWriter::new( io::sink() );
+    io::copy( &mut fp, &mut writer )?;
+    writer.verify( digest ).map( |_| () )
+}
+
+fn parse_challenge( header: &str ) -> Option< (String, Vec< (String, String) >) > {
+    let mut parameters = header.strip_prefix( "Bearer " )?.trim();
+    let mut realm = None;
+    let mut query = Vec::new();
+    while !parameters.is_empty() {
+        let index = parameters.find( '=' )?;
+        let key = parameters[ ..index ].trim().to_owned();
+        let rest = &parameters[ index + 1.. ];
+
+        // The values are usually quoted, and can contain commas, e.g. `scope="repository:foo:pull,push"`.
+        let (value, rest) = if let Some( rest ) = rest.strip_prefix( '"' ) {
+            let end = rest.find( '"' )?;
+            (&rest[ ..end ], &rest[ end + 1.. ])
+        } else {
+            let end = rest.find( ',' ).unwrap_or( rest.len() );
+            (&rest[ ..end ], &rest[ end.. ])
+        };
+
+        if key == "realm" {
+            realm = Some( value.to_owned() );
+        } else {
+            query.push( (key, value.to_owned()) );
+        }
+
+        parameters = rest.trim_start_matches( |ch: char| ch == ',' || ch == ' ' );
+    }
+
+    Some( (realm?, query) )
+}
+
+fn to_io_error( error: ureq::Error ) -> io::Error {
+    io::Error::new( io::ErrorKind::Other, error.to_string() )
+}
+
+pub struct Registry {
+    agent: ureq::Agent,
+    reference: Reference,
+    token: Option< String >
+}
+
+impl Registry {
+    pub fn new( reference: Reference ) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect( Duration::from_secs( 30 ) )
+            .timeout_read( Duration::from_secs( 60 ) )
+            .build();
+
+        Registry { agent, reference, token: None }
+    }
+
+    fn authenticate( &mut self, challenge: &str ) -> io::Result< () > {
+        let (realm, query) = parse_challenge( challenge ).ok_or_else( || invalid_data( format!( "unsupported authentication challenge: '{}'", challenge ) ) )?;
+        let mut request = self.agent.get( &realm );
+        for (key, value) in &query {
+            request = request.query( key, value );
+        }
+
+        if let (Ok( username ), Ok( password )) = (env::var( "MEMORY_PROFILER_REGISTRY_USERNAME" ), env::var( "MEMORY_PROFILER_REGISTRY_PASSWORD" )) {
+            let credentials = base64_encode( format!( "{}:{}", username, password ).as_bytes() );
+            request = request.set( "Authorization", &format!( "Basic {}", credentials ) );
+        }
+
+        let response: Value = request.call().map_err( to_io_error )?.into_json()?;
+        let token = response[ "token" ].as_str().or_else( || response[ "access_token" ].as_str() ).ok_or_else( || invalid_data( "the registry didn't return a token" ) )?;
+        self.token = Some( token.to_owned() );
+        Ok(())
+    }
+
+    fn get( &mut self, path: &str, accept: &str ) -> io::Result< ureq::Response > {
+        let url = format!( "https://{}/v2/{}/{}", self.reference.registry, self.reference.repository, path );
+        for _ in 0..2 {
+            let mut request = self.agent.get( &url ).set( "Accept", accept );
+            if let Some( ref token ) = self.token {
+                request = request.set( "Authorization", &format!( "Bearer {}", token ) );
+            }
+
+            match request.call() {
+                Ok( response ) => return Ok( response ),
+                Err( ureq::Error::Status( 401, response ) ) if self.token.is_none() => {
+                    let challenge = response.header( "www-authenticate" ).unwrap_or( "" ).to_owned();
+                    self.authenticate( &challenge )?;
+                },
+                Err( error ) => return Err( to_io_error( error ) )
+            }
+        }
+
+        Err( io::Error::new( io::ErrorKind::PermissionDenied, format!( "access to '{}' was denied", url ) ) )
+    }
+
+    /// Fetches a manifest (or an index) by a tag or a digest; if it's fetched by a digest then the digest is verified.
+    pub fn fetch_manifest( &mut self, reference: &str ) -> io::Result< Value > {
+        let response = self.get( &format!( "manifests/{}", reference ), MANIFEST_TYPES )?;
+        let mut body = Vec::new();
+        response.into_reader().take( MAX_MANIFEST_SIZE ).read_to_end( &mut body )?;
+        if reference.contains( ':' ) {
+            verify_digest( &body[..], reference )?;
+        }
+
+        serde_json::from_slice( &body ).map_err( |error| invalid_data( format!( "failed to parse the manifest: {}", error ) ) )
+    }
+
+    /// Downloads a blob into a file, verifying its digest.
+    pub fn download_blob( &mut self, digest: &str, path: &Path ) -> io::Result< () > {
+        let response = self.get( &format!( "blobs/{}", digest ), "*/*" )?;
+        let mut writer = DigestWriter::new( File::create( path )? );
+        io::copy( &mut response.into_reader(), &mut writer )?;
+        writer.verify( digest )?;
+        Ok(())
+    }
+}
+
+fn base64_encode( data: &[u8] ) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::new();
+    for chunk in data.chunks( 3 ) {
+        let value = chunk.iter().enumerate().fold( 0_u32, |value, (index, &byte)| value | (byte as u32) << (16 - index * 8) );
+        for index in 0..4 {
+            if index <= chunk.len() {
+                output.push( ALPHABET[ (value >> (18 - index * 6)) as usize & 63 ] as char );
+            } else {
+                output.push( '=' );
+            }
+        }
+    }
+
+    output
+}
+
+#[test]
+fn test_parse_reference() {
+    let parse = |image| parse_reference( image ).map( |reference| (reference.registry, reference.repository, reference.reference) );
+    let owned = |a: &str, b: &str, c: &str| Some( (a.to_owned(), b.to_owned(), c.to_owned()) );
+    assert_eq!( parse( "ubuntu" ), owned( DOCKER_HUB, "library/ubuntu", "latest" ) );
+    assert_eq!( parse( "docker.io/grafana/grafana:10.0" ), owned( DOCKER_HUB, "grafana/grafana", "10.0" ) );
+    assert_eq!( parse( "registry.example.com/api-server:1.4.2" ), owned( "registry.example.com", "api-server", "1.4.2" ) );
+    assert_eq!( parse( "localhost:5000/team/api@sha256:abcd" ), owned( "localhost:5000", "team/api", "sha256:abcd" ) );
+    assert_eq!( parse( "ubuntu:" ), None );
+}
+
+#[test]
+fn test_verify_digest() {
+    let digest = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    assert!( verify_digest( &b"hello"[..], digest ).is_ok() );
+    assert!( verify_digest( &b"hello!"[..], digest ).is_err() );
+    assert!( verify_digest( &b"hello"[..], "md5:5d41402abc4b2a76b9719d911017c592" ).is_err() );
+}
+
+#[test]
+fn test_parse_challenge() {
+    let (realm, query) = parse_challenge( r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/ubuntu:pull,push""# ).unwrap();
+    assert_eq!( realm, "https://auth.docker.io/token" );
+    assert_eq!( query, vec![
+        ("service".to_owned(), "registry.docker.io".to_owned()),
+        ("scope".to_owned(), "repository:library/ubuntu:pull,push".to_owned())
+    ]);
+}
+
+#[test]
+fn test_base64_encode() {
+    assert_eq!( base64_encode( b"user:pass" ), "dXNlcjpwYXNz" );
+    assert_eq!( base64_encode( b"ab" ), "YWI=" );
+    assert_eq!( base64_encode( b"a" ), "YQ==" );
+}
@@ -0,0 +1,276 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+
+use ahash::AHashMap as HashMap;
+use flate2::read::MultiGzDecoder;
+
+use common::Timestamp;
+use common::speedy::Writable;
+use common::event::{
+    DataId,
+    Event,
+    AllocBody,
+    HeaderBody,
+    HEADER_FLAG_IS_LITTLE_ENDIAN
+};
+use common::lz4_stream::Lz4Writer;
+
+use crate::importer_heaptrack::is_heaptrack;
+use crate::importer_pprof::is_pprof;
+
+pub(crate) const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+
+/// The formats of the other memory profilers which are converted as they're loaded.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum ForeignFormat {
+    Pprof,
+    Heaptrack
+}
+
+/// Figures out whether a file was written by another memory profiler; `prefix` should be its first few kilobytes.
+///
+/// Any of the formats can be gzipped, so they're always told apart by their decompressed contents.
+pub(crate) fn detect_foreign_format( prefix: &[u8] ) -> Option< ForeignFormat > {
+    let mut decompressed = Vec::new();
+    let is_gzipped = prefix.starts_with( GZIP_MAGIC );
+    let contents = if is_gzipped {
+        // The prefix cuts the stream short, so an error is expected here; whatever was decompressed before it is enough.
+        let _ = MultiGzDecoder::new( prefix ).take( 256 ).read_to_end( &mut decompressed );
+        &decompressed[ .. ]
+    } else {
+        prefix
+    };
+
+    if is_heaptrack( contents ) {
+        Some( ForeignFormat::Heaptrack )
+    } else if is_gzipped && is_pprof( contents ) {
+        // The importer only supports gzipped profiles, which is how pprof always writes them.
+        Some( ForeignFormat::Pprof )
+    } else {
+        None
+    }
+}
+
+/// A single, already symbolicated frame of an imported backtrace.
+#[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub(crate) struct ImportedFrame {
+    pub address: u64,
+    pub library: Option< String >,
+    pub function: Option< String >,
+    pub source: Option< String >,
+    pub line: Option< u32 >,
+    pub is_inline: bool
+}
+
+/// Writes a native data file out of the data gathered by other memory profilers.
+///
+/// The backtraces are either written out already decoded (just as in a postprocessed data file),
+/// so the output can be loaded without having access to the original binaries, or as raw
+/// addresses which are symbolicated when the data is loaded; the two can't be mixed.
+pub(crate) struct ImportWriter< W: Write > {
+    ofp: Lz4Writer< W >,
+    strings: HashMap< String, u32 >,
+    frames: HashMap< ImportedFrame, u32 >,
+    backtraces: HashMap< Vec< u32 >, u64 >,
+    raw_backtrace_count: u64
+}
+
+pub(crate) fn invalid_data< T: Into< Box< dyn std::error::Error + Send + Sync > > >( error: T ) -> io::Error {
+    io::Error::new( io::ErrorKind::InvalidData, error )
+}
+
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+impl< W: Write > ImportWriter< W > {
+    /// The `start` is the wall clock time at which the profiling started; every timestamp is relative to it.
+    pub fn new( ofp: W, cmdline: &[&str], start: Timestamp ) -> io::Result< Self > {
+        let mut ofp = Lz4Writer::new( ofp );
+        let executable = cmdline.first().cloned().unwrap_or( "" );
+        let mut raw_cmdline = Vec::new();
+        for arg in cmdline {
+            raw_cmdline.extend_from_slice( arg.as_bytes() );
+            raw_cmdline.push( 0 );
+        }
+
+        let header = HeaderBody {
+            id: DataId::new( random_u64(), random_u64() ),
+            initial_timestamp: Timestamp::min(),
+            timestamp: Timestamp::min(),
+            wall_clock_secs: start.as_secs(),
+            wall_clock_nsecs: start.as_usecs() % 1_000_000 * 1000,
+            pid: 0,
+            cmdline: raw_cmdline,
+            executable: executable.as_bytes().to_owned(),
+            // None of the supported formats record this; it's only needed for unwinding, which is never done here.
+            arch: "x86_64".to_owned(),
+            flags: HEADER_FLAG_IS_LITTLE_ENDIAN,
+            pointer_size: 8
+        };
+
+        Event::Header( header ).write_to_stream( &mut ofp )?;
+        Ok( ImportWriter {
+            ofp,
+            strings: HashMap::new(),
+            frames: HashMap::new(),
+            backtraces: HashMap::new(),
+            raw_backtrace_count: 0
+        })
+    }
+
+    fn string( &mut self, string: Option< &str > ) -> io::Result< u32 > {
+        let string = match string {
+            Some( string ) => string,
+            None => return Ok( 0xFFFFFFFF )
+        };
+
+        if let Some( &id ) = self.strings.get( string ) {
+            return Ok( id );
+        }
+
+        let id = self.strings.len() as u32;
+        Event::String { id, string: string.into() }.write_to_stream( &mut self.ofp )?;
+        self.strings.insert( string.to_owned(), id );
+        Ok( id )
+    }
+
+    fn frame( &mut self, frame: &ImportedFrame ) -> io::Result< u32 > {
+        if let Some( &id ) = self.frames.get( frame ) {
+            return Ok( id );
+        }
+
+        let library = self.string( frame.library.as_deref() )?;
+        let function = self.string( frame.function.as_deref() )?;
+        let source = self.string( frame.source.as_deref() )?;
+        Event::DecodedFrame {
+            address: frame.address,
+            library,
+            raw_function: 0xFFFFFFFF,
+            function,
+            source,
+            line: frame.line.unwrap_or( 0xFFFFFFFF ),
+            column: 0xFFFFFFFF,
+            is_inline: frame.is_inline
+        }.write_to_stream( &mut self.ofp )?;
+
+        let id = self.frames.len() as u32;
+        self.frames.insert( frame.clone(), id );
+        Ok( id )
+    }
+
+    /// Returns the ID of a backtrace; the innermost frame has to be first.
+    pub fn backtrace( &mut self, frames: &[ImportedFrame] ) -> io::Result< u64 > {
+        let frame_ids = frames.iter().map( |frame| self.frame( frame ) ).collect::< io::Result< Vec< _ > > >()?;
+        if let Some( &id ) = self.backtraces.get( &frame_ids ) {
+            return Ok( id );
+        }
+
+        Event::DecodedBacktrace { frames: frame_ids.as_slice().into() }.write_to_stream( &mut self.ofp )?;
+        let id = self.backtraces.len() as u64;
+        self.backtraces.insert( frame_ids, id );
+        Ok( id )
+    }
+
+    /// Writes the memory maps of the profiled process, which are needed to symbolicate the raw backtraces.
+    pub fn maps( &mut self, contents: &str ) -> io::Result< () > {
+        Event::File {
+            timestamp: Timestamp::min(),
+            path: "/proc/self/maps".into(),
+            contents: contents.as_bytes().into()
+        }.write_to_stream( &mut self.ofp )
+    }
+
+    /// Returns the ID of a backtrace made out of return addresses; the innermost one has to be first.
+    pub fn raw_backtrace( &mut self, addresses: &[u64] ) -> io::Result< u64 > {
+        let id = self.raw_backtrace_count;
+        self.raw_backtrace_count += 1;
+        Event::Backtrace { id, addresses: addresses.into() }.write_to_stream( &mut self.ofp )?;
+        Ok( id )
+    }
+
+    pub fn allocate( &mut self, timestamp: Timestamp, pointer: u64, size: u64, backtrace: u64 ) -> io::Result< () > {
+        Event::Alloc {
+            timestamp,
+            allocation: AllocBody {
+                pointer,
+                size,
+                backtrace,
+                thread: 0,
+                flags: 0,
+                extra_usable_space: 0,
+                preceding_free_space: 0
+            }
+        }.write_to_stream( &mut self.ofp )
+    }
+
+    pub fn deallocate( &mut self, timestamp: Timestamp, pointer: u64 ) -> io::Result< () > {
+        Event::Free {
+            timestamp,
+            pointer,
+            backtrace: u64::MAX,
+            thread: 0
+        }.write_to_stream( &mut self.ofp )
+    }
+
+    pub fn finish( mut self ) -> io::Result< () > {
+        self.ofp.flush()
+    }
+}
+
+/// Hands out unique fake pointers for formats which don't record the real ones.
+#[derive(Default)]
+pub(crate) struct PointerAllocator {
+    next: u64
+}
+
+impl PointerAllocator {
+    pub fn allocate( &mut self, size: u64 ) -> u64 {
+        let pointer = 0x1000 + self.next;
+        self.next += (size.max( 1 ) + 15) & !15;
+        pointer
+    }
+}
+
+/// Writes `count` allocations of an equal share of `size` bytes each, which are either freed
+/// right away or left alive; this is how the formats which only have per-backtrace counters
+/// are turned into individual allocations.
+pub(crate) fn emit_allocations< W: Write >( writer: &mut ImportWriter< W >, pointers: &mut PointerAllocator, backtrace: u64, count: u64, size: u64, free: bool ) -> io::Result< () > {
+    for index in 0..count {
+        let size = size / count + if index < size % count { 1 } else { 0 };
+        let pointer = pointers.allocate( size );
+        writer.allocate( Timestamp::min(), pointer, size, backtrace )?;
+        if free {
+            writer.deallocate( Timestamp::min(), pointer )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_detect_foreign_format() {
+    let gzip = |data: &[u8]| {
+        let mut output = flate2::write::GzEncoder::new( Vec::new(), flate2::Compression::default() );
+        output.write_all( data ).unwrap();
+        output.finish().unwrap()
+    };
+
+    let heaptrack = b"v 10100 3\nX ./a.out\n";
+    let pprof = [0x0A, 0x04, 0x08, 0x01, 0x10, 0x02];
+    assert_eq!( detect_foreign_format( &gzip( &pprof ) ), Some( ForeignFormat::Pprof ) );
+    assert_eq!( detect_foreign_format( &gzip( heaptrack ) ), Some( ForeignFormat::Heaptrack ) );
+    assert_eq!( detect_foreign_format( heaptrack ), Some( ForeignFormat::Heaptrack ) );
+
+    // Only the start of a bigger file.
+    let compressed = gzip( &heaptrack.repeat( 1000 ) );
+    assert_eq!( detect_foreign_format( &compressed[ ..compressed.len() / 2 ] ), Some( ForeignFormat::Heaptrack ) );
+
+    let mut native = Vec::new();
+    ImportWriter::new( &mut native, &[ "./a.out" ], Timestamp::from_secs( 1 ) ).unwrap().finish().unwrap();
+    assert_eq!( detect_foreign_format( &native ), None );
+    assert_eq!( detect_foreign_format( &pprof ), None );
+    assert_eq!( detect_foreign_format( &gzip( b"" ) ), None );
+    assert_eq!( detect_foreign_format( b"" ), None );
+}
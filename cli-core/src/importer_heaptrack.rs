@@ -0,0 +1,334 @@
+use std::io::{self, BufRead, BufReader, Write};
+
+use ahash::AHashMap as HashMap;
+use flate2::bufread::MultiGzDecoder;
+
+use common::Timestamp;
+
+use crate::importer::{GZIP_MAGIC, ImportWriter, ImportedFrame, PointerAllocator, invalid_data};
+
+/*
+    The heaptrack data file is line based, with every number encoded in hex
+    and every index being 1-based (except for the alloc infos); see `exporter_heaptrack.rs`
+    for the details. The version 1 of the file format had the pointers in the `+` and `-` lines
+    instead of the alloc info indexes.
+*/
+
+struct Parser< 'a > {
+    line: &'a str,
+    iter: std::str::SplitWhitespace< 'a >
+}
+
+impl< 'a > Parser< 'a > {
+    fn new( line: &'a str ) -> Self {
+        // Skip the record type, which doesn't have to be a single byte in a malformed file.
+        let mut chars = line.chars();
+        chars.next();
+        Parser { line, iter: chars.as_str().split_whitespace() }
+    }
+
+    fn next_hex( &mut self ) -> io::Result< u64 > {
+        let line = self.line;
+        self.iter.next()
+            .and_then( |value| u64::from_str_radix( value, 16 ).ok() )
+            .ok_or_else( || invalid_data( format!( "malformed line: '{}'", line ) ) )
+    }
+
+    fn next_hex_opt( &mut self ) -> io::Result< Option< u64 > > {
+        match self.iter.clone().next() {
+            Some( _ ) => self.next_hex().map( Some ),
+            None => Ok( None )
+        }
+    }
+}
+
+struct Importer< W: Write > {
+    writer: ImportWriter< W >,
+    format_version: u64,
+    strings: Vec< String >,
+    ips: Vec< Vec< ImportedFrame > >,
+    traces: Vec< (usize, usize) >,
+    backtraces: HashMap< usize, u64 >,
+    alloc_infos: Vec< (u64, usize) >,
+    live_by_alloc_info: HashMap< usize, Vec< u64 > >,
+    pointers: PointerAllocator,
+    timestamp: Timestamp
+}
+
+fn get< T >( slice: &[T], index: u64, kind: &str ) -> io::Result< usize > {
+    let index = index as usize;
+    if index >= slice.len() {
+        return Err( invalid_data( format!( "invalid {} index: {}", kind, index ) ) );
+    }
+
+    Ok( index )
+}
+
+impl< W: Write > Importer< W > {
+    fn string( &self, index: u64 ) -> io::Result< Option< String > > {
+        if index == 0 {
+            return Ok( None );
+        }
+
+        let index = get( &self.strings, index, "string" )?;
+        Ok( Some( self.strings[ index ].clone() ) )
+    }
+
+    fn parse_ip( &self, mut parser: Parser ) -> io::Result< Vec< ImportedFrame > > {
+        let address = parser.next_hex()?;
+        let library = self.string( parser.next_hex()? )?;
+        let mut outer_frame = ImportedFrame {
+            address,
+            library: library.clone(),
+            .. ImportedFrame::default()
+        };
+
+        if let Some( function ) = parser.next_hex_opt()? {
+            outer_frame.function = self.string( function )?;
+            if let Some( source ) = parser.next_hex_opt()? {
+                outer_frame.source = self.string( source )?;
+                outer_frame.line = Some( parser.next_hex()? as u32 );
+            }
+        }
+
+        // The frames inlined into the function follow it, starting from the outermost one.
+        let mut frames = Vec::new();
+        while let Some( function ) = parser.next_hex_opt()? {
+            frames.push( ImportedFrame {
+                address,
+                library: library.clone(),
+                function: self.string( function )?,
+                source: self.string( parser.next_hex()? )?,
+                line: Some( parser.next_hex()? as u32 ),
+                is_inline: true
+            });
+        }
+
+        frames.reverse();
+        frames.push( outer_frame );
+        Ok( frames )
+    }
+
+    fn backtrace( &mut self, trace: usize ) -> io::Result< u64 > {
+        if let Some( &id ) = self.backtraces.get( &trace ) {
+            return Ok( id );
+        }
+
+        let mut frames = Vec::new();
+        let mut index = trace;
+        while index != 0 {
+            let (ip, parent) = self.traces[ index ];
+            frames.extend( self.ips[ ip ].iter().cloned() );
+            if parent >= index {
+                return Err( invalid_data( format!( "trace {} has an invalid parent: {}", index, parent ) ) );
+            }
+
+            index = parent;
+        }
+
+        let id = self.writer.backtrace( &frames )?;
+        self.backtraces.insert( trace, id );
+        Ok( id )
+    }
+
+    fn allocate( &mut self, alloc_info: usize, pointer: Option< u64 > ) -> io::Result< () > {
+        let (size, trace) = self.alloc_infos[ alloc_info ];
+        let backtrace = self.backtrace( trace )?;
+        let pointer = match pointer {
+            Some( pointer ) => pointer,
+            None => {
+                let pointer = self.pointers.allocate( size );
+                self.live_by_alloc_info.entry( alloc_info ).or_insert_with( Vec::new ).push( pointer );
+                pointer
+            }
+        };
+
+        self.writer.allocate( self.timestamp, pointer, size, backtrace )
+    }
+
+    fn process_line( &mut self, line: &str ) -> io::Result< () > {
+        let mut parser = Parser::new( line );
+        match line.as_bytes()[ 0 ] {
+            b'v' => {
+                parser.next_hex()?;
+                self.format_version = parser.next_hex_opt()?.unwrap_or( 1 );
+                if self.format_version > 3 {
+                    warn!( "Unknown heaptrack file format version: {}", self.format_version );
+                }
+            },
+            b's' => {
+                self.strings.push( line.get( 2.. ).unwrap_or( "" ).to_owned() );
+            },
+            b'i' => {
+                let frames = self.parse_ip( parser )?;
+                self.ips.push( frames );
+            },
+            b't' => {
+                let ip = get( &self.ips, parser.next_hex()?, "ip" )?;
+                let parent = parser.next_hex()? as usize;
+                self.traces.push( (ip, parent) );
+            },
+            b'a' => {
+                let size = parser.next_hex()?;
+                let trace = get( &self.traces, parser.next_hex()?, "trace" )?;
+                self.alloc_infos.push( (size, trace) );
+            },
+            b'c' => {
+                self.timestamp = Timestamp::from_usecs( parser.next_hex()? * 1000 );
+            },
+            b'+' if self.format_version < 2 => {
+                let size = parser.next_hex()?;
+                let trace = get( &self.traces, parser.next_hex()?, "trace" )?;
+                let pointer = parser.next_hex()?;
+                self.alloc_infos.push( (size, trace) );
+                self.allocate( self.alloc_infos.len() - 1, Some( pointer ) )?;
+            },
+            b'+' => {
+                let alloc_info = get( &self.alloc_infos, parser.next_hex()?, "alloc info" )?;
+                self.allocate( alloc_info, None )?;
+            },
+            b'-' if self.format_version < 2 => {
+                let pointer = parser.next_hex()?;
+                self.writer.deallocate( self.timestamp, pointer )?;
+            },
+            b'-' => {
+                let alloc_info = get( &self.alloc_infos, parser.next_hex()?, "alloc info" )?;
+                let pointer = self.live_by_alloc_info.get_mut( &alloc_info ).and_then( |pointers| pointers.pop() );
+                if let Some( pointer ) = pointer {
+                    self.writer.deallocate( self.timestamp, pointer )?;
+                }
+            },
+            // The command line, the system info, the RSS, the attach marker, the suppressions and the comments.
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks whether the decompressed data looks like a heaptrack capture (see `importer::detect_foreign_format`).
+///
+/// Every capture starts with heaptrack's version and the version of the format.
+pub(crate) fn is_heaptrack( decompressed: &[u8] ) -> bool {
+    decompressed.starts_with( b"v " )
+}
+
+/// Converts a file produced by heaptrack into a data file.
+///
+/// Heaptrack doesn't record when the profiling started, so the timestamps are relative to `start`.
+pub fn import_heaptrack< F: BufRead, G: Write >( mut ifp: F, ofp: G, start: Timestamp ) -> io::Result< () > {
+    let magic = ifp.fill_buf()?;
+    if magic.starts_with( GZIP_MAGIC ) {
+        // The older versions of heaptrack write gzipped files.
+        return import_uncompressed( BufReader::new( MultiGzDecoder::new( ifp ) ), ofp, start );
+    } else if magic.starts_with( &[0x28, 0xB5, 0x2F, 0xFD] ) {
+        return Err( invalid_data( "zstd compressed heaptrack files are not supported; decompress it first (with `zstd -d`)" ) );
+    }
+
+    import_uncompressed( ifp, ofp, start )
+}
+
+fn import_uncompressed< F: BufRead, G: Write >( ifp: F, ofp: G, start: Timestamp ) -> io::Result< () > {
+    // The header has to be written first, so the lines are buffered until the command line is found.
+    let mut lines = ifp.lines();
+    let mut buffered_lines = Vec::new();
+    let mut cmdline = String::new();
+    for line in &mut lines {
+        let line = line?;
+        let is_cmdline = line.starts_with( "X " );
+        let is_allocation = line.starts_with( '+' );
+        if is_cmdline {
+            cmdline = line[ 2.. ].to_owned();
+        }
+
+        buffered_lines.push( line );
+        if is_cmdline || is_allocation {
+            break;
+        }
+    }
+
+    let cmdline: Vec< &str > = cmdline.split_whitespace().collect();
+    let mut importer = Importer {
+        writer: ImportWriter::new( ofp, &cmdline, start )?,
+        format_version: 1,
+        strings: vec![ String::new() ],
+        ips: vec![ Vec::new() ],
+        traces: vec![ (0, 0) ],
+        backtraces: HashMap::new(),
+        alloc_infos: Vec::new(),
+        live_by_alloc_info: HashMap::new(),
+        pointers: PointerAllocator::default(),
+        timestamp: Timestamp::min()
+    };
+
+    for line in buffered_lines.into_iter().map( Ok ).chain( lines ) {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+
+        importer.process_line( &line )?;
+    }
+
+    importer.writer.finish()
+}
+
+#[test]
+fn test_import_heaptrack() {
+    let input = "\
+        v 10100 2\n\
+        X ./a.out --flag\n\
+        s libc.so.6\n\
+        s malloc\n\
+        s main\n\
+        s main.c\n\
+        i 1000 1 2\n\
+        i 2000 1 3 4 a\n\
+        t 2 0\n\
+        t 1 1\n\
+        a 10 2\n\
+        a 20 1\n\
+        + 0\n\
+        c 3e8\n\
+        + 1\n\
+        + 0\n\
+        - 0\n\
+    ";
+
+    let mut output = Vec::new();
+    import_heaptrack( input.as_bytes(), &mut output, Timestamp::from_secs( 100 ) ).unwrap();
+
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( output ) ).unwrap();
+    assert_eq!( data.executable(), "./a.out" );
+
+    let allocations: Vec< _ > = data.allocations().iter().collect();
+    assert_eq!( allocations.len(), 3 );
+    assert_eq!( allocations.iter().map( |allocation| allocation.size ).collect::< Vec< _ > >(), vec![ 16, 32, 16 ] );
+    assert_eq!( allocations.iter().filter( |allocation| allocation.was_deallocated() ).count(), 1 );
+    assert_eq!( allocations[ 1 ].timestamp, Timestamp::from_secs( 101 ) );
+
+    let frames: Vec< _ > = data.get_backtrace( allocations[ 0 ].backtrace ).map( |(_, frame)| {
+        let function = frame.function().map( |id| data.interner().resolve( id ).unwrap().to_owned() );
+        (function, frame.line())
+    }).collect();
+
+    assert_eq!( frames, vec![ (Some( "main".to_owned() ), Some( 10 )), (Some( "malloc".to_owned() ), None) ] );
+
+    let mut compressed = flate2::write::GzEncoder::new( Vec::new(), flate2::Compression::default() );
+    compressed.write_all( input.as_bytes() ).unwrap();
+    let compressed = compressed.finish().unwrap();
+
+    let mut output_from_compressed = Vec::new();
+    import_heaptrack( compressed.as_slice(), &mut output_from_compressed, Timestamp::from_secs( 100 ) ).unwrap();
+
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( output_from_compressed ) ).unwrap();
+    assert_eq!( data.allocations().len(), 3 );
+}
+
+#[test]
+fn test_parser_with_multibyte_record_type() {
+    let mut parser = Parser::new( "\u{e9} 1f" );
+    assert_eq!( parser.next_hex().unwrap(), 0x1f );
+    assert!( parser.next_hex_opt().unwrap().is_none() );
+    assert!( Parser::new( "" ).next_hex().is_err() );
+}
@@ -0,0 +1,172 @@
+use std::io::{self, BufRead, Write};
+
+use nwind::proc_maps::parse as parse_maps;
+
+use common::Timestamp;
+
+use crate::importer::{ImportWriter, PointerAllocator, emit_allocations, invalid_data};
+use crate::postprocessor::postprocess;
+use crate::symbol_sources::SymbolSources;
+
+/*
+    The heap profiles dumped by jemalloc look like this:
+
+        heap_v2/524288
+          t*: 28106: 56637512 [0: 0]
+          t1: 352: 16777344 [0: 0]
+        @ 0x7f2c5b0f5f3e 0x7f2c5b0d6b9e 0x55d1e6a2c1d5
+          t*: 13: 6688 [0: 0]
+          t1: 13: 6688 [0: 0]
+
+        MAPPED_LIBRARIES:
+        <the contents of /proc/self/maps>
+
+    The number after `heap_v2/` is the average number of bytes between the samples,
+    every `@` line is a backtrace made out of return addresses, and the counters are
+    `<live objects>: <live bytes> [<total objects>: <total bytes>]` (the totals are only
+    filled in with `prof_accum` enabled) with `t*` being the sum for all of the threads.
+*/
+
+struct Stack {
+    addresses: Vec< u64 >,
+    live_count: u64,
+    live_size: u64,
+    total_count: u64,
+    total_size: u64
+}
+
+fn parse_counters( line: &str ) -> Option< (u64, u64, u64, u64) > {
+    let numbers: Vec< u64 > = line
+        .split( |character: char| character == ':' || character == '[' || character == ']' || character.is_whitespace() )
+        .filter( |chunk| !chunk.is_empty() )
+        .map( |chunk| chunk.parse().ok() )
+        .collect::< Option< _ > >()?;
+
+    if numbers.len() != 4 {
+        return None;
+    }
+
+    Some( (numbers[ 0 ], numbers[ 1 ], numbers[ 2 ], numbers[ 3 ]) )
+}
+
+/// Undoes the sampling the same way as `jeprof` does.
+fn unsample( count: u64, size: u64, sample_period: u64 ) -> (u64, u64) {
+    if count == 0 || sample_period == 0 {
+        return (count, size);
+    }
+
+    let ratio = (size as f64 / count as f64) / sample_period as f64;
+    let scale = 1.0 / (1.0 - (-ratio).exp());
+    ((count as f64 * scale).round() as u64, (size as f64 * scale).round() as u64)
+}
+
+/// Converts a heap profile dumped by jemalloc (e.g. with `prof:true,prof_final:true` in `MALLOC_CONF`) into a data file.
+///
+/// The profile only has the sampled counters of every backtrace, so they're unsampled and turned
+/// into allocations of equal sizes, all of which happen at `start`; the live objects are never freed,
+/// and if the profile was gathered with `prof_accum` the rest of the objects are freed right away.
+/// The backtraces are symbolicated using the given `symbol_sources`.
+pub fn import_jemalloc< F: BufRead, G: Write >( ifp: F, ofp: G, start: Timestamp, symbol_sources: &SymbolSources ) -> io::Result< () > {
+    let mut lines = ifp.lines();
+    let sample_period = match lines.next() {
+        Some( line ) => {
+            let line = line?;
+            let period = line.trim().strip_prefix( "heap_v2/" ).and_then( |period| period.parse().ok() );
+            period.ok_or_else( || invalid_data( format!( "unsupported heap profile format: '{}'", line ) ) )?
+        },
+        None => return Err( invalid_data( "the heap profile is empty" ) )
+    };
+
+    let mut stacks: Vec< Stack > = Vec::new();
+    let mut maps = String::new();
+    let mut in_maps = false;
+    for line in lines {
+        let line = line?;
+        if in_maps {
+            maps.push_str( &line );
+            maps.push( '\n' );
+        } else if line.starts_with( "MAPPED_LIBRARIES:" ) {
+            in_maps = true;
+        } else if let Some( addresses ) = line.strip_prefix( "@" ) {
+            let addresses = addresses.split_whitespace().map( |address| {
+                u64::from_str_radix( address.trim_start_matches( "0x" ), 16 ).ok()
+            }).collect::< Option< Vec< _ > > >();
+
+            let addresses = addresses.ok_or_else( || invalid_data( format!( "malformed backtrace: '{}'", line ) ) )?;
+            stacks.push( Stack { addresses, live_count: 0, live_size: 0, total_count: 0, total_size: 0 } );
+        } else if let Some( counters ) = line.trim_start().strip_prefix( "t*:" ) {
+            // The counters before the first backtrace are the totals for the whole process.
+            if let Some( stack ) = stacks.last_mut() {
+                let (live_count, live_size, total_count, total_size) = parse_counters( counters )
+                    .ok_or_else( || invalid_data( format!( "malformed counters: '{}'", line ) ) )?;
+
+                stack.live_count = live_count;
+                stack.live_size = live_size;
+                stack.total_count = total_count;
+                stack.total_size = total_size;
+            }
+        }
+    }
+
+    let executable = parse_maps( &maps ).into_iter()
+        .map( |region| region.name )
+        .find( |name| !name.is_empty() && !name.starts_with( "[" ) )
+        .unwrap_or_default();
+
+    let mut raw = Vec::new();
+    let mut writer = ImportWriter::new( &mut raw, &[executable.as_str()], start )?;
+    writer.maps( &maps )?;
+
+    let mut pointers = PointerAllocator::default();
+    for stack in stacks {
+        let backtrace = writer.raw_backtrace( &stack.addresses )?;
+        let (live_count, live_size) = unsample( stack.live_count, stack.live_size, sample_period );
+        let (total_count, total_size) = unsample( stack.total_count, stack.total_size, sample_period );
+        emit_allocations( &mut writer, &mut pointers, backtrace, live_count, live_size, false )?;
+        emit_allocations( &mut writer, &mut pointers, backtrace, total_count.saturating_sub( live_count ), total_size.saturating_sub( live_size ), true )?;
+    }
+
+    writer.finish()?;
+    postprocess( io::Cursor::new( raw ), ofp, symbol_sources )
+}
+
+#[test]
+fn test_parse_counters() {
+    assert_eq!( parse_counters( " 13: 6688 [0: 0]" ), Some( (13, 6688, 0, 0) ) );
+    assert_eq!( parse_counters( " 1: 2 [3: 4]" ), Some( (1, 2, 3, 4) ) );
+    assert_eq!( parse_counters( " 1: 2" ), None );
+}
+
+#[test]
+fn test_unsample() {
+    assert_eq!( unsample( 0, 0, 524288 ), (0, 0) );
+    // Huge allocations are always sampled.
+    assert_eq!( unsample( 1, 100 * 1024 * 1024, 524288 ), (1, 100 * 1024 * 1024) );
+    // Smaller ones are scaled up.
+    assert_eq!( unsample( 1, 524288, 524288 ), (2, 829411) );
+}
+
+#[test]
+fn test_import_jemalloc() {
+    let input = "\
+heap_v2/524288
+  t*: 2: 134217728 [0: 0]
+@ 0x401000 0x402000
+  t*: 2: 134217728 [3: 201326592]
+  t0: 2: 134217728 [3: 201326592]
+
+MAPPED_LIBRARIES:
+00400000-00452000 r-xp 00000000 08:02 173521      /usr/bin/foo
+";
+
+    let mut output = Vec::new();
+    import_jemalloc( input.as_bytes(), &mut output, Timestamp::from_secs( 100 ), &SymbolSources::default() ).unwrap();
+
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( output ) ).unwrap();
+    assert_eq!( data.executable(), "/usr/bin/foo" );
+
+    let allocations: Vec< _ > = data.allocations().iter().collect();
+    assert_eq!( allocations.len(), 3 );
+    assert_eq!( allocations.iter().filter( |allocation| !allocation.was_deallocated() ).map( |allocation| allocation.size ).sum::< u64 >(), 134217728 );
+    assert_eq!( allocations.iter().filter( |allocation| allocation.was_deallocated() ).count(), 1 );
+}
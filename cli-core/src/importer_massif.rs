@@ -0,0 +1,297 @@
+use std::io::{self, BufRead, Write};
+
+use ahash::AHashMap as HashMap;
+
+use common::Timestamp;
+
+use crate::importer::{ImportWriter, ImportedFrame, PointerAllocator, invalid_data};
+
+/*
+    Massif's output consists of a few header lines (`desc:`, `cmd:` and `time_unit:`)
+    followed by a list of snapshots:
+
+        snapshot=1
+        time=1234
+        mem_heap_B=1000
+        mem_heap_extra_B=8
+        mem_stacks_B=0
+        heap_tree=detailed
+        n2: 1000 (heap allocation functions) malloc/new/new[], --alloc-fns, etc.
+         n0: 600 0x4005BB: main (main.c:10)
+         n0: 400 in 3 places, below massif's threshold (1.00%)
+
+    Only the `detailed` and `peak` snapshots have a tree; the nesting is given by the indentation,
+    the children of the root are the innermost frames and every node's size includes its children.
+*/
+
+const BELOW_THRESHOLD: &str = "[below massif's threshold]";
+const UNATTRIBUTED: &str = "[not in a detailed snapshot]";
+
+struct Snapshot {
+    time: u64,
+    heap_size: u64,
+    tree: Option< Vec< (Vec< ImportedFrame >, u64) > >
+}
+
+fn parse_frame( description: &str ) -> ImportedFrame {
+    if description.starts_with( "in " ) && description.contains( "below massif's threshold" ) {
+        return ImportedFrame {
+            function: Some( BELOW_THRESHOLD.to_owned() ),
+            .. ImportedFrame::default()
+        };
+    }
+
+    let mut frame = ImportedFrame::default();
+    let mut rest = description;
+    if let Some( index ) = rest.find( ": " ) {
+        if let Some( address ) = rest[ ..index ].strip_prefix( "0x" ) {
+            if let Ok( address ) = u64::from_str_radix( address, 16 ) {
+                frame.address = address;
+                rest = &rest[ index + 2.. ];
+            }
+        }
+    }
+
+    if rest.ends_with( ')' ) {
+        if let Some( index ) = rest.rfind( " (" ) {
+            let location = &rest[ index + 2..rest.len() - 1 ];
+            if let Some( library ) = location.strip_prefix( "in " ) {
+                frame.library = Some( library.rsplit( '/' ).next().unwrap().to_owned() );
+            } else if let Some( index ) = location.rfind( ':' ) {
+                frame.source = Some( location[ ..index ].to_owned() );
+                frame.line = location[ index + 1.. ].parse().ok();
+            }
+
+            rest = &rest[ ..index ];
+        }
+    }
+
+    if !rest.is_empty() && rest != "???" {
+        frame.function = Some( rest.to_owned() );
+    }
+
+    frame
+}
+
+/// Returns the backtraces from the tree along with how many bytes were allocated by each of them.
+fn parse_tree( lines: &[String] ) -> io::Result< Vec< (Vec< ImportedFrame >, u64) > > {
+    let mut output = Vec::new();
+    let mut ancestors: Vec< (Option< ImportedFrame >, usize) > = Vec::new();
+    for line in lines {
+        let depth = line.len() - line.trim_start().len();
+        let malformed = || invalid_data( format!( "malformed heap tree line: '{}'", line ) );
+        let line = line.trim_start();
+        let rest = &line[ line.find( ": " ).ok_or_else( malformed )? + 2.. ];
+        let (size, description) = match rest.find( ' ' ) {
+            Some( index ) => (&rest[ ..index ], &rest[ index + 1.. ]),
+            None => (rest, "")
+        };
+
+        let size: u64 = size.parse().map_err( |_| malformed() )?;
+        if depth > ancestors.len() {
+            return Err( malformed() );
+        }
+
+        ancestors.truncate( depth );
+        if let Some( &(_, parent) ) = ancestors.last() {
+            let parent_size: &mut u64 = &mut output[ parent ].1;
+            *parent_size = parent_size.saturating_sub( size );
+        }
+
+        // The root is just a placeholder for the allocation functions.
+        let frame = if depth == 0 { None } else { Some( parse_frame( description ) ) };
+        ancestors.push( (frame, output.len()) );
+        let frames = ancestors.iter().filter_map( |(frame, _)| frame.clone() ).collect();
+        output.push( (frames, size) );
+    }
+
+    output.retain( |(frames, size): &(Vec< ImportedFrame >, u64)| *size > 0 && !frames.is_empty() );
+    Ok( output )
+}
+
+#[derive(Default)]
+struct Backtrace {
+    live: Vec< (u64, u64) >,
+    live_size: u64
+}
+
+/// Converts the output of Valgrind's massif into a data file.
+///
+/// Massif only records how much memory was allocated from every backtrace at the time
+/// of each snapshot, so the data file will contain a single allocation for every increase
+/// in memory usage between the snapshots, which is then freed when the usage goes down.
+/// The times are relative to `start`; if massif was run with a `--time-unit` other than `ms`
+/// every unit is treated as a microsecond.
+pub fn import_massif< F: BufRead, G: Write >( ifp: F, ofp: G, start: Timestamp ) -> io::Result< () > {
+    let mut cmdline = String::new();
+    let mut time_unit = String::new();
+    let mut snapshots: Vec< Snapshot > = Vec::new();
+    let mut tree_lines = Vec::new();
+    let mut lines = ifp.lines().peekable();
+    while let Some( line ) = lines.next() {
+        let line = line?;
+        if let Some( value ) = line.strip_prefix( "cmd: " ) {
+            cmdline = value.to_owned();
+        } else if let Some( value ) = line.strip_prefix( "time_unit: " ) {
+            time_unit = value.trim().to_owned();
+        } else if line.starts_with( "snapshot=" ) {
+            snapshots.push( Snapshot { time: 0, heap_size: 0, tree: None } );
+        } else if let Some( snapshot ) = snapshots.last_mut() {
+            if let Some( value ) = line.strip_prefix( "time=" ) {
+                snapshot.time = value.parse().map_err( |_| invalid_data( format!( "malformed line: '{}'", line ) ) )?;
+            } else if let Some( value ) = line.strip_prefix( "mem_heap_B=" ) {
+                snapshot.heap_size = value.parse().map_err( |_| invalid_data( format!( "malformed line: '{}'", line ) ) )?;
+            } else if line == "heap_tree=detailed" || line == "heap_tree=peak" {
+                tree_lines.clear();
+                while let Some( Ok( line ) ) = lines.peek() {
+                    if !line.trim_start().starts_with( 'n' ) {
+                        break;
+                    }
+
+                    tree_lines.push( lines.next().unwrap()? );
+                }
+
+                snapshot.tree = Some( parse_tree( &tree_lines )? );
+            }
+        }
+    }
+
+    if snapshots.is_empty() {
+        return Err( invalid_data( "no snapshots found; is this really a massif output file?" ) );
+    }
+
+    let cmdline: Vec< &str > = cmdline.split_whitespace().collect();
+    let mut writer = ImportWriter::new( ofp, &cmdline, start )?;
+    let unattributed = writer.backtrace( &[ImportedFrame {
+        function: Some( UNATTRIBUTED.to_owned() ),
+        .. ImportedFrame::default()
+    }])?;
+
+    let mut pointers = PointerAllocator::default();
+    let mut backtraces: HashMap< u64, Backtrace > = HashMap::new();
+    let mut targets: HashMap< u64, u64 > = HashMap::new();
+    for snapshot in snapshots {
+        let timestamp = if time_unit == "ms" { Timestamp::from_usecs( snapshot.time * 1000 ) } else { Timestamp::from_usecs( snapshot.time ) };
+        if let Some( tree ) = snapshot.tree {
+            targets.clear();
+            for (frames, size) in tree {
+                let backtrace = writer.backtrace( &frames )?;
+                *targets.entry( backtrace ).or_insert( 0 ) += size;
+            }
+        } else {
+            // Only the total is known here, so whatever changed since the last detailed snapshot is left unattributed.
+            targets.remove( &unattributed );
+            let attributed: u64 = targets.values().sum();
+            targets.insert( unattributed, snapshot.heap_size.saturating_sub( attributed ) );
+        }
+
+        for backtrace in backtraces.keys().cloned().collect::< Vec< _ > >() {
+            targets.entry( backtrace ).or_insert( 0 );
+        }
+
+        let mut targets: Vec< _ > = targets.iter().map( |(&backtrace, &size)| (backtrace, size) ).collect();
+        targets.sort();
+        for (backtrace_id, target) in targets {
+            let backtrace = backtraces.entry( backtrace_id ).or_insert_with( Backtrace::default );
+            while backtrace.live_size > target {
+                let (pointer, size) = backtrace.live.pop().unwrap();
+                backtrace.live_size -= size;
+                writer.deallocate( timestamp, pointer )?;
+            }
+
+            if backtrace.live_size < target {
+                let size = target - backtrace.live_size;
+                let pointer = pointers.allocate( size );
+                backtrace.live.push( (pointer, size) );
+                backtrace.live_size += size;
+                writer.allocate( timestamp, pointer, size, backtrace_id )?;
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+#[test]
+fn test_parse_frame() {
+    let frame = parse_frame( "0x4008F2: std::vector<int>::push_back(int const&) (stl_vector.h:1192)" );
+    assert_eq!( frame.address, 0x4008F2 );
+    assert_eq!( frame.function.as_deref(), Some( "std::vector<int>::push_back(int const&)" ) );
+    assert_eq!( frame.source.as_deref(), Some( "stl_vector.h" ) );
+    assert_eq!( frame.line, Some( 1192 ) );
+
+    let frame = parse_frame( "0x400550: foo (in /usr/lib/libfoo.so)" );
+    assert_eq!( frame.function.as_deref(), Some( "foo" ) );
+    assert_eq!( frame.library.as_deref(), Some( "libfoo.so" ) );
+
+    let frame = parse_frame( "0x400550: ???" );
+    assert_eq!( frame.function, None );
+
+    let frame = parse_frame( "in 3 places, below massif's threshold (1.00%)" );
+    assert_eq!( frame.function.as_deref(), Some( BELOW_THRESHOLD ) );
+}
+
+#[test]
+fn test_import_massif() {
+    let input = "\
+desc: --time-unit=ms
+cmd: ./a.out --flag
+time_unit: ms
+#-----------
+snapshot=0
+#-----------
+time=0
+mem_heap_B=0
+mem_heap_extra_B=0
+mem_stacks_B=0
+heap_tree=empty
+#-----------
+snapshot=1
+#-----------
+time=1000
+mem_heap_B=1000
+mem_heap_extra_B=0
+mem_stacks_B=0
+heap_tree=detailed
+n2: 1000 (heap allocation functions) malloc/new/new[], --alloc-fns, etc.
+ n1: 600 0x400600: foo (a.c:5)
+  n0: 600 0x4005BB: main (a.c:10)
+ n0: 400 0x4005CC: main (a.c:11)
+#-----------
+snapshot=2
+#-----------
+time=2000
+mem_heap_B=1500
+mem_heap_extra_B=0
+mem_stacks_B=0
+heap_tree=empty
+#-----------
+snapshot=3
+#-----------
+time=3000
+mem_heap_B=300
+mem_heap_extra_B=0
+mem_stacks_B=0
+heap_tree=peak
+n1: 300 (heap allocation functions) malloc/new/new[], --alloc-fns, etc.
+ n1: 300 0x400600: foo (a.c:5)
+  n0: 300 0x4005BB: main (a.c:10)
+";
+
+    let mut output = Vec::new();
+    import_massif( input.as_bytes(), &mut output, Timestamp::from_secs( 100 ) ).unwrap();
+
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( output ) ).unwrap();
+    assert_eq!( data.executable(), "./a.out" );
+
+    let allocations: Vec< _ > = data.allocations().iter().collect();
+    let sizes: Vec< _ > = allocations.iter().map( |allocation| (allocation.size, allocation.was_deallocated()) ).collect();
+    assert_eq!( sizes, vec![ (600, true), (400, true), (500, true), (300, false) ] );
+    assert_eq!( allocations[ 2 ].timestamp, Timestamp::from_secs( 102 ) );
+
+    let frames: Vec< _ > = data.get_backtrace( allocations[ 3 ].backtrace ).map( |(_, frame)| {
+        frame.function().map( |id| data.interner().resolve( id ).unwrap().to_owned() )
+    }).collect();
+
+    assert_eq!( frames, vec![ Some( "main".to_owned() ), Some( "foo".to_owned() ) ] );
+}
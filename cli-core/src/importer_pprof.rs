@@ -0,0 +1,463 @@
+use std::io::{self, Read, Write};
+
+use ahash::AHashMap as HashMap;
+use flate2::read::GzDecoder;
+
+use common::Timestamp;
+
+use crate::importer::{ImportWriter, ImportedFrame, PointerAllocator, emit_allocations, invalid_data};
+
+/*
+    A pprof profile is a gzipped protobuf message (see `profile.proto` in the pprof repository);
+    only the fields which are needed here are decoded:
+
+        Profile { 1: sample_type[], 2: sample[], 3: mapping[], 4: location[], 5: function[], 6: string_table[], 9: time_nanos }
+        ValueType { 1: type, 2: unit }
+        Sample { 1: location_id[], 2: value[] }
+        Mapping { 1: id, 5: filename }
+        Location { 1: id, 2: mapping_id, 3: address, 4: line[] }
+        Line { 1: function_id, 2: line }
+        Function { 1: id, 2: name, 4: filename }
+
+    Every string is an index into the string table, and the first location of a sample is the innermost one.
+*/
+
+/// The largest decompressed profile which will be imported.
+const MAX_PROFILE_SIZE: u64 = 512 * 1024 * 1024;
+
+/// The largest number of allocations a profile can be turned into.
+const MAX_ALLOCATION_COUNT: u64 = 100_000_000;
+
+/// Checks whether the decompressed data looks like a pprof profile (see `importer::detect_foreign_format`).
+///
+/// The first byte has to be the key of one of the repeated, length delimited fields with which a profile starts.
+pub(crate) fn is_pprof( decompressed: &[u8] ) -> bool {
+    match decompressed.first() {
+        Some( &key ) => key & 7 == 2 && (1..=6).contains( &(key >> 3) ),
+        None => false
+    }
+}
+
+enum Value< 'a > {
+    Varint( u64 ),
+    Bytes( &'a [u8] )
+}
+
+struct Message< 'a > {
+    data: &'a [u8]
+}
+
+fn truncated() -> io::Error {
+    invalid_data( "truncated protobuf message" )
+}
+
+impl< 'a > Message< 'a > {
+    fn varint( &mut self ) -> io::Result< u64 > {
+        let mut value = 0;
+        for shift in (0..64).step_by( 7 ) {
+            let (&byte, rest) = self.data.split_first().ok_or_else( truncated )?;
+            self.data = rest;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok( value );
+            }
+        }
+
+        Err( invalid_data( "malformed varint" ) )
+    }
+
+    fn skip( &mut self, length: usize ) -> io::Result< &'a [u8] > {
+        if self.data.len() < length {
+            return Err( truncated() );
+        }
+
+        let (bytes, rest) = self.data.split_at( length );
+        self.data = rest;
+        Ok( bytes )
+    }
+
+    fn next_field( &mut self ) -> io::Result< Option< (u64, Value< 'a >) > > {
+        if self.data.is_empty() {
+            return Ok( None );
+        }
+
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Value::Varint( self.varint()? ),
+            1 => Value::Bytes( self.skip( 8 )? ),
+            2 => {
+                let length = self.varint()? as usize;
+                Value::Bytes( self.skip( length )? )
+            },
+            5 => Value::Bytes( self.skip( 4 )? ),
+            kind => return Err( invalid_data( format!( "unsupported protobuf wire type: {}", kind ) ) )
+        };
+
+        Ok( Some( (key >> 3, value) ) )
+    }
+
+    fn fields( data: &'a [u8], mut callback: impl FnMut( u64, Value< 'a > ) -> io::Result< () > ) -> io::Result< () > {
+        let mut message = Message { data };
+        while let Some( (field, value) ) = message.next_field()? {
+            callback( field, value )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Handles both the packed and the unpacked encoding of repeated integers.
+fn push_integers( output: &mut Vec< u64 >, value: Value ) -> io::Result< () > {
+    match value {
+        Value::Varint( value ) => output.push( value ),
+        Value::Bytes( data ) => {
+            let mut message = Message { data };
+            while !message.data.is_empty() {
+                output.push( message.varint()? );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn integer( value: Value ) -> u64 {
+    match value {
+        Value::Varint( value ) => value,
+        Value::Bytes( _ ) => 0
+    }
+}
+
+#[derive(Default)]
+struct Location {
+    mapping: u64,
+    address: u64,
+    lines: Vec< (u64, u64) >
+}
+
+#[derive(Default)]
+struct Profile {
+    sample_types: Vec< u64 >,
+    samples: Vec< (Vec< u64 >, Vec< u64 >) >,
+    mappings: HashMap< u64, u64 >,
+    locations: HashMap< u64, Location >,
+    functions: HashMap< u64, (u64, u64) >,
+    strings: Vec< String >,
+    time_nanos: u64
+}
+
+fn parse_profile( data: &[u8] ) -> io::Result< Profile > {
+    let mut profile = Profile::default();
+    Message::fields( data, |field, value| {
+        match (field, value) {
+            (1, Value::Bytes( data )) => {
+                let mut kind = 0;
+                Message::fields( data, |field, value| {
+                    if field == 1 {
+                        kind = integer( value );
+                    }
+                    Ok(())
+                })?;
+                profile.sample_types.push( kind );
+            },
+            (2, Value::Bytes( data )) => {
+                let mut locations = Vec::new();
+                let mut values = Vec::new();
+                Message::fields( data, |field, value| {
+                    match field {
+                        1 => push_integers( &mut locations, value ),
+                        2 => push_integers( &mut values, value ),
+                        _ => Ok(())
+                    }
+                })?;
+                profile.samples.push( (locations, values) );
+            },
+            (3, Value::Bytes( data )) => {
+                let mut id = 0;
+                let mut filename = 0;
+                Message::fields( data, |field, value| {
+                    match field {
+                        1 => id = integer( value ),
+                        5 => filename = integer( value ),
+                        _ => {}
+                    }
+                    Ok(())
+                })?;
+                profile.mappings.insert( id, filename );
+            },
+            (4, Value::Bytes( data )) => {
+                let mut id = 0;
+                let mut location = Location::default();
+                Message::fields( data, |field, value| {
+                    match (field, value) {
+                        (1, value) => id = integer( value ),
+                        (2, value) => location.mapping = integer( value ),
+                        (3, value) => location.address = integer( value ),
+                        (4, Value::Bytes( data )) => {
+                            let mut line = (0, 0);
+                            Message::fields( data, |field, value| {
+                                match field {
+                                    1 => line.0 = integer( value ),
+                                    2 => line.1 = integer( value ),
+                                    _ => {}
+                                }
+                                Ok(())
+                            })?;
+                            location.lines.push( line );
+                        },
+                        _ => {}
+                    }
+                    Ok(())
+                })?;
+                profile.locations.insert( id, location );
+            },
+            (5, Value::Bytes( data )) => {
+                let mut id = 0;
+                let mut function = (0, 0);
+                Message::fields( data, |field, value| {
+                    match field {
+                        1 => id = integer( value ),
+                        2 => function.0 = integer( value ),
+                        4 => function.1 = integer( value ),
+                        _ => {}
+                    }
+                    Ok(())
+                })?;
+                profile.functions.insert( id, function );
+            },
+            (6, Value::Bytes( data )) => {
+                profile.strings.push( String::from_utf8_lossy( data ).into_owned() );
+            },
+            (9, value) => {
+                profile.time_nanos = integer( value );
+            },
+            _ => {}
+        }
+
+        Ok(())
+    })?;
+
+    Ok( profile )
+}
+
+impl Profile {
+    fn string( &self, index: u64 ) -> Option< String > {
+        match self.strings.get( index as usize ) {
+            Some( string ) if !string.is_empty() => Some( string.clone() ),
+            _ => None
+        }
+    }
+
+    fn sample_type( &self, name: &str ) -> Option< usize > {
+        self.sample_types.iter().position( |&kind| self.strings.get( kind as usize ).map( |kind| kind.as_str() ) == Some( name ) )
+    }
+
+    /// Returns the frames of a location; the inlined functions come first.
+    fn frames( &self, id: u64 ) -> io::Result< Vec< ImportedFrame > > {
+        let location = self.locations.get( &id ).ok_or_else( || invalid_data( format!( "invalid location ID: {}", id ) ) )?;
+        let library = self.mappings.get( &location.mapping ).and_then( |&filename| self.string( filename ) );
+        if location.lines.is_empty() {
+            return Ok( vec![ ImportedFrame { address: location.address, library, .. ImportedFrame::default() } ] );
+        }
+
+        let count = location.lines.len();
+        location.lines.iter().enumerate().map( |(index, &(function, line))| {
+            let &(name, filename) = self.functions.get( &function ).ok_or_else( || invalid_data( format!( "invalid function ID: {}", function ) ) )?;
+            Ok( ImportedFrame {
+                address: location.address,
+                library: library.clone(),
+                function: self.string( name ),
+                source: self.string( filename ),
+                line: if line == 0 { None } else { Some( line as u32 ) },
+                is_inline: index + 1 != count
+            })
+        }).collect()
+    }
+}
+
+/// Converts a gzipped pprof heap profile (e.g. from Go's `/debug/pprof/heap` or gperftools) into a data file.
+///
+/// Just as with the jemalloc profiles only the (already unsampled) counters of every backtrace
+/// are known, so they're turned into allocations of equal sizes, all of which happen at the time
+/// the profile was taken; the objects which aren't in use anymore are freed right away.
+pub fn import_pprof< F: Read, G: Write >( ifp: F, ofp: G ) -> io::Result< () > {
+    let mut data = Vec::new();
+    GzDecoder::new( ifp ).take( MAX_PROFILE_SIZE + 1 ).read_to_end( &mut data )?;
+    if data.len() as u64 > MAX_PROFILE_SIZE {
+        return Err( invalid_data( format!( "the profile is bigger than {} bytes when decompressed", MAX_PROFILE_SIZE ) ) );
+    }
+
+    let profile = parse_profile( &data )?;
+    let inuse_count = profile.sample_type( "inuse_objects" );
+    let inuse_size = profile.sample_type( "inuse_space" );
+    let alloc_count = profile.sample_type( "alloc_objects" );
+    let alloc_size = profile.sample_type( "alloc_space" );
+    if inuse_size.is_none() && alloc_size.is_none() {
+        return Err( invalid_data( "not a heap profile; it has neither the 'inuse_space' nor the 'alloc_space' sample types" ) );
+    }
+
+    let mut writer = ImportWriter::new( ofp, &[], Timestamp::from_usecs( profile.time_nanos / 1000 ) )?;
+    let mut pointers = PointerAllocator::default();
+    let mut backtraces = HashMap::new();
+    let mut total_count: u64 = 0;
+    for (locations, values) in &profile.samples {
+        // The values are int64s, so a negative one would turn into an absurdly big count.
+        let value = |index: Option< usize >| -> io::Result< u64 > {
+            let value = index.and_then( |index| values.get( index ).cloned() ).unwrap_or( 0 );
+            if value as i64 >= 0 {
+                Ok( value )
+            } else {
+                Err( invalid_data( format!( "negative sample value: {}", value as i64 ) ) )
+            }
+        };
+
+        let (inuse_count, inuse_size) = (value( inuse_count )?, value( inuse_size )?);
+        let (alloc_count, alloc_size) = (value( alloc_count )?, value( alloc_size )?);
+        if inuse_size == 0 && alloc_size == 0 {
+            continue;
+        }
+
+        let backtrace = match backtraces.get( locations ) {
+            Some( &backtrace ) => backtrace,
+            None => {
+                let mut frames = Vec::new();
+                for &location in locations {
+                    frames.extend( profile.frames( location )? );
+                }
+
+                let backtrace = writer.backtrace( &frames )?;
+                backtraces.insert( locations.clone(), backtrace );
+                backtrace
+            }
+        };
+
+        // Some profilers don't record the object counts, in which case every sample is a single allocation;
+        // there also can't be more objects than bytes.
+        let freed_size = alloc_size.saturating_sub( inuse_size );
+        let inuse_count = if inuse_size > 0 { inuse_count.max( 1 ).min( inuse_size ) } else { 0 };
+        let freed_count = if freed_size > 0 { alloc_count.saturating_sub( inuse_count ).max( 1 ).min( freed_size ) } else { 0 };
+
+        total_count = total_count.saturating_add( inuse_count ).saturating_add( freed_count );
+        if total_count > MAX_ALLOCATION_COUNT {
+            return Err( invalid_data( format!( "the profile has more than {} allocations", MAX_ALLOCATION_COUNT ) ) );
+        }
+
+        emit_allocations( &mut writer, &mut pointers, backtrace, inuse_count, inuse_size, false )?;
+        emit_allocations( &mut writer, &mut pointers, backtrace, freed_count, freed_size, true )?;
+    }
+
+    writer.finish()
+}
+
+#[cfg(test)]
+fn encode_field( output: &mut Vec< u8 >, field: u64, value: &[u8] ) {
+    output.push( (field << 3 | 2) as u8 );
+    output.push( value.len() as u8 );
+    output.extend_from_slice( value );
+}
+
+#[test]
+fn test_import_pprof() {
+    let mut profile = Vec::new();
+    for string in &[ "", "alloc_objects", "alloc_space", "inuse_objects", "inuse_space", "main", "foo", "main.go", "count", "bytes" ] {
+        encode_field( &mut profile, 6, string.as_bytes() );
+    }
+
+    encode_field( &mut profile, 1, &[0x08, 1, 0x10, 8] );
+    encode_field( &mut profile, 1, &[0x08, 2, 0x10, 9] );
+    encode_field( &mut profile, 1, &[0x08, 3, 0x10, 8] );
+    encode_field( &mut profile, 1, &[0x08, 4, 0x10, 9] );
+
+    encode_field( &mut profile, 5, &[0x08, 1, 0x10, 5, 0x20, 7] );
+    encode_field( &mut profile, 5, &[0x08, 2, 0x10, 6, 0x20, 7] );
+
+    // The location 1 has `foo` inlined into `main`.
+    let mut location = vec![ 0x08, 1, 0x18, 0x10 ];
+    encode_field( &mut location, 4, &[0x08, 2, 0x10, 3] );
+    encode_field( &mut location, 4, &[0x08, 1, 0x10, 10] );
+    encode_field( &mut profile, 4, &location );
+
+    let mut location = vec![ 0x08, 2, 0x18, 0x20 ];
+    encode_field( &mut location, 4, &[0x08, 1, 0x10, 20] );
+    encode_field( &mut profile, 4, &location );
+
+    // 3 allocations of 48 bytes in total, 2 of which are still in use.
+    let mut sample = Vec::new();
+    encode_field( &mut sample, 1, &[1, 2] );
+    encode_field( &mut sample, 2, &[3, 48, 2, 32] );
+    encode_field( &mut profile, 2, &sample );
+
+    let mut input = flate2::write::GzEncoder::new( Vec::new(), flate2::Compression::default() );
+    input.write_all( &profile ).unwrap();
+    let input = input.finish().unwrap();
+    assert!( is_pprof( &profile ) );
+
+    let mut output = Vec::new();
+    import_pprof( input.as_slice(), &mut output ).unwrap();
+
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( output ) ).unwrap();
+    let allocations: Vec< _ > = data.allocations().iter().collect();
+    let sizes: Vec< _ > = allocations.iter().map( |allocation| (allocation.size, allocation.was_deallocated()) ).collect();
+    assert_eq!( sizes, vec![ (16, false), (16, false), (16, true) ] );
+
+    let frames: Vec< _ > = data.get_backtrace( allocations[ 0 ].backtrace ).map( |(_, frame)| {
+        let function = frame.function().map( |id| data.interner().resolve( id ).unwrap().to_owned() );
+        (function, frame.line(), frame.is_inline())
+    }).collect();
+
+    assert_eq!( frames, vec![
+        (Some( "main".to_owned() ), Some( 20 ), false),
+        (Some( "main".to_owned() ), Some( 10 ), false),
+        (Some( "foo".to_owned() ), Some( 3 ), true)
+    ]);
+}
+
+#[test]
+fn test_is_pprof() {
+    assert!( is_pprof( &[0x0A, 0x04, 0x08, 0x01, 0x10, 0x02] ) );
+    assert!( !is_pprof( b"v 10100 3\nX ./a.out\n" ) );
+    assert!( !is_pprof( b"" ) );
+}
+
+#[test]
+fn test_import_pprof_with_absurd_counts() {
+    let mut profile = Vec::new();
+    for string in &[ "", "inuse_objects", "inuse_space", "count", "bytes" ] {
+        encode_field( &mut profile, 6, string.as_bytes() );
+    }
+
+    encode_field( &mut profile, 1, &[0x08, 1, 0x10, 3] );
+    encode_field( &mut profile, 1, &[0x08, 2, 0x10, 4] );
+    encode_field( &mut profile, 5, &[0x08, 1] );
+    encode_field( &mut profile, 4, &[0x08, 1, 0x18, 0x10] );
+
+    // 2^62 objects in 4 bytes.
+    let mut sample = Vec::new();
+    encode_field( &mut sample, 1, &[1] );
+    encode_field( &mut sample, 2, &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x40, 4] );
+    encode_field( &mut profile, 2, &sample );
+
+    let mut input = flate2::write::GzEncoder::new( Vec::new(), flate2::Compression::default() );
+    input.write_all( &profile ).unwrap();
+    let input = input.finish().unwrap();
+
+    let mut output = Vec::new();
+    import_pprof( input.as_slice(), &mut output ).unwrap();
+
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( output ) ).unwrap();
+    assert_eq!( data.allocations().iter().map( |allocation| allocation.size ).collect::< Vec< _ > >(), vec![ 1, 1, 1, 1 ] );
+
+    // A negative count.
+    let mut profile_with_negative_count = profile.clone();
+    let length = profile_with_negative_count.len();
+    profile_with_negative_count.truncate( length - sample.len() - 2 );
+    let mut sample = Vec::new();
+    encode_field( &mut sample, 1, &[1] );
+    encode_field( &mut sample, 2, &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, 4] );
+    encode_field( &mut profile_with_negative_count, 2, &sample );
+
+    let mut input = flate2::write::GzEncoder::new( Vec::new(), flate2::Compression::default() );
+    input.write_all( &profile_with_negative_count ).unwrap();
+    let input = input.finish().unwrap();
+    assert!( import_pprof( input.as_slice(), &mut Vec::new() ).is_err() );
+}
@@ -0,0 +1,540 @@
+use std::any::TypeId;
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufWriter, Read, Write};
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use memmap::Mmap;
+use speedy::{Readable, Writable, Context, Reader, Writer};
+
+use crate::data::{Allocation, AllocationId, Data, OperationId, StringInterner};
+use crate::spill_vec::{Pod, SpillVec};
+use crate::symbol_sources::SymbolSources;
+
+const INDEX_MAGIC: u32 = 0x5844_4950;
+const INDEX_VERSION: u32 = 18;
+
+/// Every table starts at a page boundary.
+const TABLE_ALIGNMENT: usize = 4096;
+
+/*
+    An index file consists of:
+
+        IndexHeader
+        Contents
+        the data without the ID tables (`Contents::metadata_length` bytes)
+        the raw ID tables, each aligned to a page boundary
+
+    The ID tables (the operations and the allocations sorted in various ways) are written out exactly
+    as they're laid out in memory, so that they can be mapped straight from the index file instead of
+    being read into memory; the kernel can always drop their pages and read them back from the index
+    when they're needed again. Only the types for which every bit pattern is valid (see `spill_vec::Pod`)
+    can be mapped like this, so the allocations themselves are serialized along with the rest of the data,
+    and are spilled to disk as usual when they don't fit into the memory budget.
+
+    The metadata and the tables are covered by `Contents::checksum`, which is verified before any
+    of the tables are mapped, so that a damaged index is caught early. The checksum isn't meant
+    to stop a malicious index, so on top of that every ID in the tables and in the allocations is
+    checked to be in range. An index which fails any of these checks is ignored, so it's regenerated
+    from the data file.
+*/
+
+/// Identifies the data file (and the symbols) an index was generated from.
+#[derive(PartialEq, Debug, Readable, Writable)]
+struct IndexHeader {
+    magic: u32,
+    version: u32,
+    data_size: u64,
+    data_modified_secs: u64,
+    data_modified_nsecs: u32,
+    debug_symbols: Vec< String >,
+    sysroots: Vec< String >,
+    symbol_paths: Vec< String >,
+    symbols_fingerprint: u64,
+    layout: u64
+}
+
+/// Where the parts of the data are in the index file.
+#[derive(Readable, Writable)]
+struct Contents {
+    metadata_length: u64,
+    table_lengths: Vec< u64 >,
+    checksum: u64
+}
+
+/// The size of a single element of each of the tables, in the order in which they're written.
+fn element_sizes() -> [usize; 4] {
+    [
+        mem::size_of::< OperationId >(),
+        mem::size_of::< AllocationId >(),
+        mem::size_of::< AllocationId >(),
+        mem::size_of::< AllocationId >()
+    ]
+}
+
+/// A fast checksum which is good enough to catch a damaged index; it's not meant to stop a malicious one.
+fn checksum( parts: &[&[u8]] ) -> u64 {
+    const MULTIPLIER: u64 = 0x517c_c1b7_2722_0a95;
+    let mix = |hash: u64, value: u64| (hash.rotate_left( 5 ) ^ value).wrapping_mul( MULTIPLIER );
+
+    let mut hash = 0;
+    for part in parts {
+        hash = mix( hash, part.len() as u64 );
+        let mut chunks = part.chunks_exact( 8 );
+        for chunk in &mut chunks {
+            hash = mix( hash, u64::from_le_bytes( chunk.try_into().unwrap() ) );
+        }
+
+        for &byte in chunks.remainder() {
+            hash = mix( hash, byte as u64 );
+        }
+    }
+
+    hash
+}
+
+/// Identifies the memory layout of the tables; the type IDs are different for every compiler and every set of compilation options.
+fn table_layout() -> u64 {
+    let mut hasher = DefaultHasher::new();
+    TypeId::of::< OperationId >().hash( &mut hasher );
+    TypeId::of::< AllocationId >().hash( &mut hasher );
+    cfg!( target_endian = "little" ).hash( &mut hasher );
+    hasher.finish()
+}
+
+fn align( offset: usize ) -> usize {
+    (offset + TABLE_ALIGNMENT - 1) / TABLE_ALIGNMENT * TABLE_ALIGNMENT
+}
+
+fn map_table< T: Pod >( mmap: &Arc< Mmap >, range: &Range< usize > ) -> io::Result< SpillVec< T > > {
+    SpillVec::from_mapping( mmap.clone(), range.start, (range.end - range.start) / mem::size_of::< T >() )
+}
+
+/// Whether every ID in the tables and in the allocations refers to an existing allocation or backtrace.
+fn are_tables_consistent( data: &Data ) -> bool {
+    let count = data.allocations.len() as u64;
+    let backtrace_count = data.backtraces.len() as u64;
+    let is_operation_valid = |operation: &OperationId| {
+        (operation.is_allocation() || operation.is_deallocation() || operation.is_reallocation()) && operation.id().raw() < count
+    };
+
+    let is_id_valid = |id: Option< AllocationId >| id.map( |id| id.raw() < count ).unwrap_or( true );
+    let is_allocation_valid = |allocation: &Allocation| {
+        (allocation.backtrace.raw() as u64) < backtrace_count &&
+        allocation.deallocation.and_then( |deallocation| deallocation.backtrace ).map( |backtrace| (backtrace.raw() as u64) < backtrace_count ).unwrap_or( true ) &&
+        is_id_valid( allocation.reallocation ) &&
+        is_id_valid( allocation.reallocated_from )
+    };
+
+    data.operations.iter().all( is_operation_valid ) &&
+    data.allocations.iter().all( is_allocation_valid ) &&
+    data.sorted_by_timestamp.iter().chain( data.sorted_by_address.iter() ).chain( data.sorted_by_size.iter() ).all( |id| id.raw() < count )
+}
+
+fn hash_modification_times( path: &Path, hasher: &mut DefaultHasher, is_top_level: bool ) {
+    path.hash( hasher );
+    let metadata = match fs::metadata( path ) {
+        Ok( metadata ) => metadata,
+        Err( _ ) => return
+    };
+
+    // The symlinks to directories are only followed at the top level, so that they can't form a loop.
+    let is_symlink = fs::symlink_metadata( path ).map( |metadata| metadata.file_type().is_symlink() ).unwrap_or( false );
+    if metadata.is_dir() && (is_top_level || !is_symlink) {
+        let mut entries: Vec< _ > = match fs::read_dir( path ) {
+            Ok( entries ) => entries.filter_map( |entry| entry.ok() ).map( |entry| entry.path() ).collect(),
+            Err( _ ) => return
+        };
+
+        entries.sort();
+        for entry in entries {
+            hash_modification_times( &entry, hasher, false );
+        }
+    } else {
+        metadata.len().hash( hasher );
+        metadata.modified().ok().and_then( |modified| modified.duration_since( UNIX_EPOCH ).ok() ).hash( hasher );
+    }
+}
+
+/// Identifies the current contents of the symbol sources, so that the index is regenerated
+/// when any of the files in them change, and not only when they're given through different paths.
+fn symbols_fingerprint( symbol_sources: &SymbolSources ) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for path in symbol_sources.debug_symbols.iter().chain( &symbol_sources.sysroots ).chain( &symbol_sources.symbol_paths ) {
+        hash_modification_times( path, &mut hasher, true );
+    }
+
+    hasher.finish()
+}
+
+fn paths_to_strings( paths: &[PathBuf] ) -> Vec< String > {
+    paths.iter().map( |path| path.to_string_lossy().into_owned() ).collect()
+}
+
+fn strings_to_paths( strings: &[String] ) -> Vec< PathBuf > {
+    strings.iter().map( PathBuf::from ).collect()
+}
+
+impl IndexHeader {
+    fn new( path: &Path, symbol_sources: &SymbolSources ) -> io::Result< Self > {
+        let metadata = fs::metadata( path )?;
+        let modified = metadata.modified()?.duration_since( UNIX_EPOCH ).unwrap_or_default();
+        Ok( IndexHeader {
+            magic: INDEX_MAGIC,
+            version: INDEX_VERSION,
+            data_size: metadata.len(),
+            data_modified_secs: modified.as_secs(),
+            data_modified_nsecs: modified.subsec_nanos(),
+            debug_symbols: paths_to_strings( &symbol_sources.debug_symbols ),
+            sysroots: paths_to_strings( &symbol_sources.sysroots ),
+            symbol_paths: paths_to_strings( &symbol_sources.symbol_paths ),
+            symbols_fingerprint: symbols_fingerprint( symbol_sources ),
+            layout: table_layout()
+        })
+    }
+
+    fn symbol_sources( &self ) -> SymbolSources {
+        SymbolSources {
+            debug_symbols: strings_to_paths( &self.debug_symbols ),
+            sysroots: strings_to_paths( &self.sysroots ),
+            symbol_paths: strings_to_paths( &self.symbol_paths )
+        }
+    }
+}
+
+fn index_path( path: &Path ) -> PathBuf {
+    let mut index_path = path.as_os_str().to_owned();
+    index_path.push( ".idx" );
+    index_path.into()
+}
+
+/// Loads the data from the index file of a given data file, if it exists and is up-to-date.
+///
+/// Returns the symbols which were used to generate the index along with the data.
+pub(crate) fn load_index( path: &Path ) -> io::Result< Option< (SymbolSources, Data) > > {
+    let index_path = index_path( path );
+    let fp = match File::open( &index_path ) {
+        Ok( fp ) => fp,
+        Err( ref error ) if error.kind() == io::ErrorKind::NotFound => return Ok( None ),
+        Err( error ) => return Err( error )
+    };
+
+    let mmap = unsafe { Mmap::map( &fp )? };
+    let mut cursor = io::Cursor::new( &mmap[..] );
+    let header = match IndexHeader::read_from_stream_unbuffered( &mut cursor ) {
+        Ok( header ) => header,
+        Err( _ ) => {
+            info!( "Ignoring unreadable index file {:?}", index_path );
+            return Ok( None );
+        }
+    };
+
+    let symbol_sources = header.symbol_sources();
+    let expected_header = IndexHeader::new( path, &symbol_sources )?;
+    if header != expected_header {
+        info!( "Ignoring outdated index file {:?}", index_path );
+        return Ok( None );
+    }
+
+    let contents = match Contents::read_from_stream_unbuffered( &mut cursor ) {
+        Ok( contents ) if contents.table_lengths.len() == element_sizes().len() => contents,
+        _ => {
+            info!( "Ignoring unreadable index file {:?}", index_path );
+            return Ok( None );
+        }
+    };
+
+    let start = cursor.position() as usize;
+    let end = start.saturating_add( contents.metadata_length as usize );
+    if end > mmap.len() {
+        info!( "Ignoring truncated index file {:?}", index_path );
+        return Ok( None );
+    }
+
+    let mut ranges = Vec::new();
+    let mut offset = align( end );
+    for (&length, &element_size) in contents.table_lengths.iter().zip( element_sizes().iter() ) {
+        let table_end = (length as usize).checked_mul( element_size ).and_then( |size| size.checked_add( offset ) );
+        match table_end {
+            Some( table_end ) if table_end <= mmap.len() => {
+                ranges.push( offset..table_end );
+                offset = align( table_end );
+            },
+            _ => {
+                info!( "Ignoring truncated index file {:?}", index_path );
+                return Ok( None );
+            }
+        }
+    }
+
+    let mut parts = vec![ &mmap[ start..end ] ];
+    parts.extend( ranges.iter().map( |range| &mmap[ range.clone() ] ) );
+    if checksum( &parts ) != contents.checksum {
+        info!( "Ignoring corrupted index file {:?}", index_path );
+        return Ok( None );
+    }
+
+    let mut data = Data::read_from_buffer( &mmap[ start..end ] )?;
+    let mmap = Arc::new( mmap );
+    data.operations = map_table( &mmap, &ranges[ 0 ] )?;
+    data.sorted_by_timestamp = map_table( &mmap, &ranges[ 1 ] )?;
+    data.sorted_by_address = map_table( &mmap, &ranges[ 2 ] )?;
+    data.sorted_by_size = map_table( &mmap, &ranges[ 3 ] )?;
+
+    if !are_tables_consistent( &data ) {
+        info!( "Ignoring inconsistent index file {:?}", index_path );
+        return Ok( None );
+    }
+
+    Ok( Some( (symbol_sources, data) ) )
+}
+
+/// Writes an index file next to a given data file which can be used to load it without reprocessing.
+pub(crate) fn write_index( path: &Path, symbol_sources: &SymbolSources, data: &Data ) -> io::Result< () > {
+    let index_path = index_path( path );
+    let mut tmp_path = index_path.clone().into_os_string();
+    tmp_path.push( ".tmp" );
+
+    let header = IndexHeader::new( path, symbol_sources )?;
+    let mut metadata = Vec::new();
+    data.write_to_stream( &mut metadata )?;
+
+    let tables = [
+        data.operations.as_bytes(),
+        data.sorted_by_timestamp.as_bytes(),
+        data.sorted_by_address.as_bytes(),
+        data.sorted_by_size.as_bytes()
+    ];
+
+    let mut parts = vec![ &metadata[..] ];
+    parts.extend( tables.iter().cloned() );
+
+    let contents = Contents {
+        checksum: checksum( &parts ),
+        metadata_length: metadata.len() as u64,
+        table_lengths: vec![
+            data.operations.len() as u64,
+            data.sorted_by_timestamp.len() as u64,
+            data.sorted_by_address.len() as u64,
+            data.sorted_by_size.len() as u64
+        ]
+    };
+
+    {
+        let mut prefix = Vec::new();
+        header.write_to_stream( &mut prefix )?;
+        contents.write_to_stream( &mut prefix )?;
+
+        let mut fp = BufWriter::new( File::create( &tmp_path )? );
+        fp.write_all( &prefix )?;
+        fp.write_all( &metadata )?;
+
+        let mut offset = prefix.len() + metadata.len();
+        for table in &tables {
+            let padding = align( offset ) - offset;
+            io::copy( &mut io::repeat( 0 ).take( padding as u64 ), &mut fp )?;
+            fp.write_all( table )?;
+            offset += padding + table.len();
+        }
+
+        fp.flush()?;
+    }
+
+    fs::rename( &tmp_path, &index_path )
+}
+
+fn read_interner< 'a, C: Context, R: Reader< 'a, C > >( reader: &mut R ) -> Result< StringInterner, C::Error > {
+    let length = reader.read_u64()? as usize;
+    let mut interner = StringInterner::with_capacity( length );
+    for _ in 0..length {
+        let length = reader.read_u32()? as usize;
+        let bytes = reader.read_cow( length )?;
+        interner.get_or_intern( String::from_utf8_lossy( &bytes ) );
+    }
+
+    Ok( interner )
+}
+
+fn write_interner< C: Context, W: ?Sized + Writer< C > >( interner: &StringInterner, writer: &mut W ) -> Result< (), C::Error > {
+    writer.write_u64( interner.len() as u64 )?;
+    for (_, string) in interner.iter() {
+        writer.write_u32( string.len() as u32 )?;
+        writer.write_bytes( string.as_bytes() )?;
+    }
+
+    Ok(())
+}
+
+impl< 'a, C: Context > Readable< 'a, C > for Data {
+    fn read_from< R: Reader< 'a, C > >( reader: &mut R ) -> Result< Self, C::Error > {
+        Ok( Data {
+            id: Readable::read_from( reader )?,
+            initial_timestamp: Readable::read_from( reader )?,
+            last_timestamp: Readable::read_from( reader )?,
+            executable: Readable::read_from( reader )?,
+            pid: Readable::read_from( reader )?,
+            architecture: Readable::read_from( reader )?,
+            pointer_size: Readable::read_from( reader )?,
+            interner: read_interner( reader )?,
+            // The ID tables are mapped separately.
+            operations: SpillVec::new(),
+            allocations: Readable::read_from( reader )?,
+            sorted_by_timestamp: SpillVec::new(),
+            sorted_by_address: SpillVec::new(),
+            sorted_by_size: SpillVec::new(),
+            frames: Readable::read_from( reader )?,
+            backtraces: Readable::read_from( reader )?,
+            backtrace_trie: Readable::read_from( reader )?,
+            allocations_by_backtrace: Readable::read_from( reader )?,
+            total_allocated: Readable::read_from( reader )?,
+            total_allocated_count: Readable::read_from( reader )?,
+            total_freed: Readable::read_from( reader )?,
+            total_freed_count: Readable::read_from( reader )?,
+            peak_allocated: Readable::read_from( reader )?,
+            peak_allocated_timestamp: Readable::read_from( reader )?,
+            unknown_deallocation_count: Readable::read_from( reader )?,
+            unknown_deallocation_range: Readable::read_from( reader )?,
+            duplicate_allocation_count: Readable::read_from( reader )?,
+            lost_chunk_count: Readable::read_from( reader )?,
+            lost_event_count: Readable::read_from( reader )?,
+            wall_clock_start: Readable::read_from( reader )?,
+            build_id: Readable::read_from( reader )?,
+            library_build_ids: Readable::read_from( reader )?,
+            mallopts: Readable::read_from( reader )?,
+            allocator_stats: Readable::read_from( reader )?,
+            allocator_info: Readable::read_from( reader )?,
+            metadata: Readable::read_from( reader )?,
+            allocation_contents: Readable::read_from( reader )?,
+            library_events: Readable::read_from( reader )?,
+            maps: Readable::read_from( reader )?,
+            mmap_operations: Readable::read_from( reader )?,
+            maximum_backtrace_depth: Readable::read_from( reader )?,
+            group_stats: Readable::read_from( reader )?
+        })
+    }
+}
+
+impl< C: Context > Writable< C > for Data {
+    fn write_to< W: ?Sized + Writer< C > >( &self, writer: &mut W ) -> Result< (), C::Error > {
+        writer.write_value( &self.id )?;
+        writer.write_value( &self.initial_timestamp )?;
+        writer.write_value( &self.last_timestamp )?;
+        writer.write_value( &self.executable )?;
+        writer.write_value( &self.pid )?;
+        writer.write_value( &self.architecture )?;
+        writer.write_value( &self.pointer_size )?;
+        write_interner( &self.interner, writer )?;
+        writer.write_value( &self.allocations )?;
+        writer.write_value( &self.frames )?;
+        writer.write_value( &self.backtraces )?;
+        writer.write_value( &self.backtrace_trie )?;
+        writer.write_value( &self.allocations_by_backtrace )?;
+        writer.write_value( &self.total_allocated )?;
+        writer.write_value( &self.total_allocated_count )?;
+        writer.write_value( &self.total_freed )?;
+        writer.write_value( &self.total_freed_count )?;
+        writer.write_value( &self.peak_allocated )?;
+        writer.write_value( &self.peak_allocated_timestamp )?;
+        writer.write_value( &self.unknown_deallocation_count )?;
+        writer.write_value( &self.unknown_deallocation_range )?;
+        writer.write_value( &self.duplicate_allocation_count )?;
+        writer.write_value( &self.lost_chunk_count )?;
+        writer.write_value( &self.lost_event_count )?;
+        writer.write_value( &self.wall_clock_start )?;
+        writer.write_value( &self.build_id )?;
+        writer.write_value( &self.library_build_ids )?;
+        writer.write_value( &self.mallopts )?;
+        writer.write_value( &self.allocator_stats )?;
+        writer.write_value( &self.allocator_info )?;
+        writer.write_value( &self.metadata )?;
+        writer.write_value( &self.allocation_contents )?;
+        writer.write_value( &self.library_events )?;
+        writer.write_value( &self.maps )?;
+        writer.write_value( &self.mmap_operations )?;
+        writer.write_value( &self.maximum_backtrace_depth )?;
+        writer.write_value( &self.group_stats )
+    }
+}
+
+#[test]
+fn test_damaged_index_is_ignored() {
+    use std::fs::OpenOptions;
+    use crate::Timestamp;
+    use crate::importer::{ImportWriter, ImportedFrame};
+
+    let frame = ImportedFrame { address: 0x10, function: Some( "foo".to_owned() ), .. ImportedFrame::default() };
+    let mut input = Vec::new();
+    let mut writer = ImportWriter::new( &mut input, &[ "./a.out" ], Timestamp::from_secs( 1 ) ).unwrap();
+    let backtrace = writer.backtrace( &[ frame ] ).unwrap();
+    writer.allocate( Timestamp::from_secs( 1 ), 0x1000, 100, backtrace ).unwrap();
+    writer.allocate( Timestamp::from_secs( 2 ), 0x2000, 200, backtrace ).unwrap();
+    writer.deallocate( Timestamp::from_secs( 3 ), 0x1000 ).unwrap();
+    writer.finish().unwrap();
+
+    let path = std::env::temp_dir().join( format!( "memory-profiler-test-index-{}.dat", std::process::id() ) );
+    fs::write( &path, &input ).unwrap();
+
+    let symbol_sources = SymbolSources::default();
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( input ) ).unwrap();
+    write_index( &path, &symbol_sources, &data ).unwrap();
+
+    let (_, indexed) = load_index( &path ).unwrap().unwrap();
+    assert_eq!( indexed.allocations().len(), 2 );
+    assert_eq!( indexed.operations.len(), data.operations.len() );
+
+    // Flip a byte of the last table.
+    let index_path = index_path( &path );
+    let mut index = fs::read( &index_path ).unwrap();
+    let last = index.len() - 1;
+    index[ last ] ^= 0xff;
+    fs::write( &index_path, &index ).unwrap();
+    assert!( load_index( &path ).unwrap().is_none() );
+
+    index[ last ] ^= 0xff;
+    fs::write( &index_path, &index ).unwrap();
+    assert!( load_index( &path ).unwrap().is_some() );
+
+    OpenOptions::new().write( true ).open( &index_path ).unwrap().set_len( index.len() as u64 - 1 ).unwrap();
+    assert!( load_index( &path ).unwrap().is_none() );
+
+    let _ = fs::remove_file( &index_path );
+    let _ = fs::remove_file( &path );
+}
+
+#[test]
+fn test_ids_in_allocations_are_checked() {
+    use crate::Timestamp;
+    use crate::data::BacktraceId;
+    use crate::importer::{ImportWriter, ImportedFrame};
+
+    let frame = ImportedFrame { address: 0x10, function: Some( "foo".to_owned() ), .. ImportedFrame::default() };
+    let mut input = Vec::new();
+    let mut writer = ImportWriter::new( &mut input, &[ "./a.out" ], Timestamp::from_secs( 1 ) ).unwrap();
+    let backtrace = writer.backtrace( &[ frame ] ).unwrap();
+    writer.allocate( Timestamp::from_secs( 1 ), 0x1000, 100, backtrace ).unwrap();
+    writer.finish().unwrap();
+
+    let mut data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( input ) ).unwrap();
+    assert!( are_tables_consistent( &data ) );
+
+    let original = data.allocations[ 0 ];
+    data.allocations[ 0 ].backtrace = BacktraceId::new( 1000 );
+    assert!( !are_tables_consistent( &data ) );
+
+    data.allocations[ 0 ] = original;
+    data.allocations[ 0 ].reallocation = Some( AllocationId::new( 1 ) );
+    assert!( !are_tables_consistent( &data ) );
+
+    data.allocations[ 0 ] = original;
+    data.allocations[ 0 ].reallocated_from = Some( AllocationId::new( 1000 ) );
+    assert!( !are_tables_consistent( &data ) );
+
+    data.allocations[ 0 ] = original;
+    assert!( are_tables_consistent( &data ) );
+}
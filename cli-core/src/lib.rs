@@ -13,34 +13,91 @@ pub mod cmd_analyze_size;
 mod util;
 mod tree;
 mod tree_printer;
-mod reader;
 mod loader;
 mod postprocessor;
 mod squeeze;
+mod shard_split;
 mod frame;
+mod frame_rules;
+mod attribution;
+mod recursion;
 mod data;
 mod io_adapter;
 mod exporter_replay;
 mod exporter_heaptrack;
+mod importer;
+mod importer_heaptrack;
+mod importer_massif;
+mod importer_jemalloc;
+mod importer_pprof;
 mod exporter_flamegraph;
 mod exporter_flamegraph_pl;
+mod exporter_pprof;
+mod exporter_massif;
+#[cfg(feature = "sqlite")]
+mod exporter_sqlite;
 mod vecvec;
 mod threaded_lz4_stream;
 mod repack;
+mod index;
+mod follower;
+mod spill_vec;
+mod backtrace_trie;
+mod resymbolicate;
+mod symbol_sources;
+mod embed_symbols;
+mod site_id;
+mod symbol_cache;
+mod virtual_columns;
+mod columns;
+mod filtered_data;
+mod container_image;
+mod image_registry;
+mod core_dump;
+mod live_allocations;
+mod mapped_regions;
+pub mod cmd_inspect;
+pub mod cmd_editor_server;
+pub mod cmd_report;
 
-pub use crate::data::{Data, DataId, CodePointer, DataPointer, BacktraceId, Timestamp, Operation, StringId, Allocation, AllocationId, FrameId, Mallopt, MalloptKind, MmapOperation, MemoryMap, MemoryUnmap, CountAndSize};
-pub use crate::loader::Loader;
+pub use crate::data::{Data, DataId, CodePointer, DataPointer, BacktraceId, Timestamp, Operation, StringId, Allocation, AllocationId, FrameId, Mallopt, MalloptKind, AllocatorStats, ResidentMemory, AllocatorInfo, AllocatorTunable, AllocationContents, LibraryEvent, MapRegion, MmapOperation, MemoryMap, MemoryUnmap, MemoryRemap, CountAndSize, BacktracesWithoutRecursion};
+pub use crate::loader::{Loader, Shard};
+pub use crate::site_id::SiteId;
+pub use crate::symbol_sources::SymbolSources;
+pub use crate::container_image::{default_image_cache, extract_binaries_from_image};
+pub use crate::core_dump::CoreDump;
+pub use crate::live_allocations::LiveAllocations;
+pub use crate::mapped_regions::{MappedRegion, MappedRegions};
+pub use crate::follower::{Follower, IncrementalLoader};
+pub use crate::spill_vec::set_memory_budget;
+pub use crate::symbol_cache::set_symbol_cache_directory;
 pub use crate::tree::{Tree, Node, NodeId};
 pub use crate::frame::Frame;
+pub use crate::frame_rules::FrameRules;
+pub use crate::attribution::Attribution;
 pub use crate::exporter_replay::export_as_replay;
 pub use crate::exporter_heaptrack::export_as_heaptrack;
+pub use crate::exporter_massif::export_as_massif;
+pub use crate::importer_heaptrack::import_heaptrack;
+pub use crate::importer_massif::import_massif;
+pub use crate::importer_jemalloc::import_jemalloc;
+pub use crate::importer_pprof::import_pprof;
 pub use crate::exporter_flamegraph_pl::export_as_flamegraph_pl;
 pub use crate::exporter_flamegraph::export_as_flamegraph;
+pub use crate::exporter_pprof::export_as_pprof;
+#[cfg(feature = "sqlite")]
+pub use crate::exporter_sqlite::export_as_sqlite;
 pub use crate::vecvec::VecVec;
-pub use crate::util::table_to_string;
+pub use crate::columns::{AllocationColumns, ColumnFilter, Selection};
+pub use crate::util::{format_size, table_to_string};
+pub use crate::io_adapter::IoAdapter;
 pub use crate::postprocessor::postprocess;
+pub use crate::embed_symbols::embed_symbols;
 pub use crate::squeeze::squeeze_data;
-pub use crate::reader::parse_events;
-pub use crate::repack::repack;
+pub use crate::shard_split::split_into_shards;
+pub use memory_profiler_capture::raw::parse_events;
+pub use crate::repack::{repack, repack_v2};
+pub use crate::virtual_columns::{ColumnValue, Expression, ExpressionError, VirtualColumns};
+pub use crate::filtered_data::FilteredData;
 
 pub use common::event;
@@ -0,0 +1,70 @@
+use std::cmp::max;
+
+use crate::data::{AllocationId, Data};
+
+/// The allocations which were still alive at the end of the data, sorted by their address.
+pub struct LiveAllocations {
+    /// The allocations as `(address, size, id)`.
+    entries: Vec< (u64, u64, AllocationId) >
+}
+
+impl LiveAllocations {
+    pub fn new( data: &Data ) -> Self {
+        let entries = data.allocations_with_id()
+            .filter( |(_, allocation)| !allocation.was_deallocated() )
+            .map( |(id, allocation)| (allocation.pointer, allocation.size, id) )
+            .collect();
+
+        Self::from_entries( entries )
+    }
+
+    fn from_entries( mut entries: Vec< (u64, u64, AllocationId) > ) -> Self {
+        entries.sort_by_key( |&(address, _, _)| address );
+        LiveAllocations { entries }
+    }
+
+    pub fn len( &self ) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter( &self ) -> impl Iterator< Item = (u64, u64, AllocationId) > + '_ {
+        self.entries.iter().cloned()
+    }
+
+    /// Finds the live allocation which contains the given address, as `(address, size, id)`.
+    pub fn find( &self, address: u64 ) -> Option< (u64, u64, AllocationId) > {
+        let index = match self.entries.binary_search_by_key( &address, |&(address, _, _)| address ) {
+            Ok( index ) => index,
+            Err( 0 ) => return None,
+            Err( index ) => index - 1
+        };
+
+        let entry = self.entries[ index ];
+        if address < entry.0 + max( entry.1, 1 ) {
+            Some( entry )
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn test_find() {
+    let live = LiveAllocations::from_entries( vec![
+        (0x2000, 16, AllocationId::new( 1 )),
+        (0x1000, 32, AllocationId::new( 0 )),
+        (0x3000, 0, AllocationId::new( 2 ))
+    ]);
+
+    assert_eq!( live.find( 0xfff ), None );
+    assert_eq!( live.find( 0x1000 ).map( |entry| entry.2 ), Some( AllocationId::new( 0 ) ) );
+    assert_eq!( live.find( 0x101f ).map( |entry| entry.2 ), Some( AllocationId::new( 0 ) ) );
+    assert_eq!( live.find( 0x1020 ), None );
+    assert_eq!( live.find( 0x2008 ).map( |entry| entry.2 ), Some( AllocationId::new( 1 ) ) );
+    assert_eq!( live.find( 0x3000 ).map( |entry| entry.2 ), Some( AllocationId::new( 2 ) ) );
+    assert_eq!( live.find( 0x3001 ), None );
+}
@@ -5,13 +5,17 @@ use std::io::{self, Read};
 use std::borrow::Cow;
 use std::sync::Arc;
 use std::time::Instant;
-use std::ffi::OsStr;
+use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::cmp;
+use std::fmt;
+use std::str::FromStr;
 
 use std::collections::hash_map;
 use ahash::AHashMap as HashMap;
 use ahash::AHashSet as HashSet;
 use byteorder::{BigEndian, LittleEndian, ByteOrder};
+use memmap::Mmap;
 use nwind::{arch, BinaryData, AddressSpace, IAddressSpace, DebugInfoIndex};
 use nwind::proc_maps::Region;
 use nwind::proc_maps::parse as parse_maps;
@@ -22,15 +26,23 @@ use common::event::{
     HeaderBody,
     AllocBody,
     FramesInvalidated,
+    BUILD_IDS_PATH,
     HEADER_FLAG_IS_LITTLE_ENDIAN
 };
+use common::parse_metadata;
 use common::range_map::RangeMap;
 
+use memory_profiler_capture::raw::parse_events_with_data_loss;
+use memory_profiler_capture::is_encrypted;
+
 use crate::frame::Frame;
 use crate::data::{
     Allocation,
     AllocationFlags,
     AllocationId,
+    AllocatorInfo,
+    AllocationContents,
+    AllocatorStats,
     BacktraceId,
     BacktraceStorageRef,
     CodePointer,
@@ -40,20 +52,31 @@ use crate::data::{
     Deallocation,
     FrameId,
     GroupStatistics,
+    LibraryEvent,
+    MapRegion,
     Mallopt,
     MemoryMap,
     MemoryUnmap,
+    MemoryRemap,
     MmapOperation,
     OperationId,
     ProtectionFlags,
     MapFlags,
+    ResidentMemory,
     ThreadId,
     Timestamp,
     StringInterner,
     StringId
 };
 use crate::vecvec::DenseVecVec;
-use crate::reader::parse_events;
+use crate::spill_vec::SpillVec;
+use crate::backtrace_trie::BacktraceTrie;
+use crate::symbol_sources::SymbolSources;
+use crate::index::{load_index, write_index};
+use crate::symbol_cache::{self, CachedFrame};
+use crate::importer::{ForeignFormat, detect_foreign_format};
+use crate::importer_heaptrack::import_heaptrack;
+use crate::importer_pprof::import_pprof;
 
 #[derive(Clone, PartialEq, Eq, Default, Debug, Hash)]
 pub struct AddressMapping {
@@ -80,6 +103,99 @@ fn test_clean_symbol() {
     );
 }
 
+/// The parts of the loader's state which are moved into the final data.
+struct DataParts {
+    interner: StringInterner,
+    operations: SpillVec< (Timestamp, OperationId) >,
+    allocations: SpillVec< Allocation >,
+    allocations_by_backtrace: HashMap< BacktraceId, Vec< AllocationId > >,
+    frames: Vec< Frame >,
+    backtraces: Vec< BacktraceStorageRef >,
+    backtrace_trie: BacktraceTrie,
+    group_stats: Vec< GroupStatistics >,
+    mallopts: Vec< Mallopt >,
+    allocator_stats: Vec< AllocatorStats >,
+    allocation_contents: Vec< AllocationContents >,
+    library_events: Vec< LibraryEvent >,
+    mmap_operations: Vec< MmapOperation >
+}
+
+/// The symbol tables which were embedded in the data file.
+#[derive(Default)]
+struct EmbeddedSymbols {
+    tables: Vec< HashMap< u64, Vec< Frame > > >,
+    by_library: HashMap< String, usize >,
+    by_build_id: HashMap< Vec< u8 >, usize >
+}
+
+impl EmbeddedSymbols {
+    fn add( &mut self, library: String, build_id: Vec< u8 >, table: HashMap< u64, Vec< Frame > > ) {
+        let index = self.tables.len();
+        self.tables.push( table );
+        self.by_library.insert( library, index );
+        if !build_id.is_empty() {
+            self.by_build_id.insert( build_id, index );
+        }
+    }
+
+    fn lookup( &self, maps: &RangeMap< Region >, binaries: &HashMap< String, Arc< BinaryData > >, address: u64 ) -> Option< &[Frame] > {
+        if self.tables.is_empty() {
+            return None;
+        }
+
+        let (range, region) = maps.get( address )?;
+        let index = binaries.get( &region.name )
+            .and_then( |binary_data| binary_data.build_id() )
+            .and_then( |build_id| self.by_build_id.get( build_id ) )
+            .or_else( || self.by_library.get( &region.name ) )?;
+
+        let relative_address = address - range.start + region.file_offset;
+        self.tables[ *index ].get( &relative_address ).map( |frames| frames.as_slice() )
+    }
+}
+
+/// A part of a capture which is loaded by a single worker when the analysis is split across
+/// multiple processes; every shard contains the allocations from a disjoint set of backtraces,
+/// as identified by the capture itself, so the split doesn't depend on how they're deduplicated.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32
+}
+
+impl Shard {
+    pub(crate) fn contains( &self, backtrace: u64 ) -> bool {
+        backtrace % self.count as u64 == self.index as u64
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        write!( fmt, "{}/{}", self.index, self.count )
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+    fn from_str( value: &str ) -> Result< Self, Self::Err > {
+        let mut parts = value.splitn( 2, '/' );
+        let index = parts.next().and_then( |index| index.trim().parse::< u32 >().ok() );
+        let count = parts.next().and_then( |count| count.trim().parse::< u32 >().ok() );
+        match (index, count) {
+            (Some( index ), Some( count )) if index < count => Ok( Shard { index, count } ),
+            _ => Err( format!( "invalid shard '{}'; expected '<index>/<count>' with the index starting from zero", value ) )
+        }
+    }
+}
+
+#[test]
+fn test_parse_shard() {
+    assert_eq!( "1/4".parse::< Shard >(), Ok( Shard { index: 1, count: 4 } ) );
+    assert!( "4/4".parse::< Shard >().is_err() );
+    assert!( "1".parse::< Shard >().is_err() );
+    assert!( "a/b".parse::< Shard >().is_err() );
+}
+
 pub struct Loader {
     id: DataId,
     header: HeaderBody,
@@ -87,15 +203,22 @@ pub struct Loader {
     address_space: Box< dyn IAddressSpace >,
     address_space_needs_reloading: bool,
     debug_info_index: DebugInfoIndex,
+    binary_index: DebugInfoIndex,
+    symbol_sources: SymbolSources,
     binaries: HashMap< String, Arc< BinaryData > >,
+    missing_binaries: HashSet< String >,
+    binaries_with_debug_binary: HashSet< String >,
+    /// The build IDs of the libraries as they were recorded by the profiler.
+    expected_build_ids: HashMap< String, Vec< u8 > >,
+    embedded_symbols: EmbeddedSymbols,
     maps: RangeMap< Region >,
     backtraces: Vec< BacktraceStorageRef >,
-    backtraces_storage: Vec< FrameId >,
+    backtrace_trie: BacktraceTrie,
     backtrace_to_id: HashMap< Vec< u64 >, BacktraceId >,
     backtrace_remappings: HashMap< u64, BacktraceId >,
     group_stats: Vec< GroupStatistics >,
-    operations: Vec< (Timestamp, OperationId) >,
-    allocations: Vec< Allocation >,
+    operations: SpillVec< (Timestamp, OperationId) >,
+    allocations: SpillVec< Allocation >,
     allocation_map: HashMap< (u64, u64), AllocationId >,
     allocation_range_map: RangeMap< AllocationId >,
     allocation_range_map_dirty: bool,
@@ -103,25 +226,159 @@ pub struct Loader {
     frames: Vec< Frame >,
     frame_to_id: HashMap< Frame, FrameId >,
     frames_by_address: HashMap< u64, Range< usize > >,
+    frames_by_address_storage: Vec< FrameId >,
     shared_ptr_backtraces: HashSet< BacktraceId >,
     shared_ptr_allocations: HashMap< DataPointer, AllocationId >,
     total_allocated: u64,
     total_allocated_count: u64,
     total_freed: u64,
     total_freed_count: u64,
+    unknown_deallocation_count: u64,
+    unknown_deallocation_range: Option< (Timestamp, Timestamp) >,
+    duplicate_allocation_count: u64,
+    lost_chunk_count: u64,
+    lost_event_count: u64,
+    wall_clock_start: Timestamp,
     frame_skip_ranges: Vec< Range< u64 > >,
     symbol_new_range: Range< u64 >,
     marker: u32,
     mallopts: Vec< Mallopt >,
+    allocator_stats: Vec< AllocatorStats >,
+    allocator_info: Option< AllocatorInfo >,
+    metadata: Vec< (String, String) >,
+    allocation_contents: Vec< AllocationContents >,
+    loaded_libraries: HashSet< String >,
+    library_events: Vec< LibraryEvent >,
     timestamp_to_wall_clock: u64,
     is_little_endian: bool,
     mmap_operations: Vec< MmapOperation >,
     maximum_backtrace_depth: u32,
     previous_backtrace_on_thread: HashMap< u32, Vec< u64 > >,
-    string_id_map: HashMap< u32, StringId >
+    string_id_map: HashMap< u32, StringId >,
+    shard: Option< Shard >,
+    foreign_allocations: HashSet< (u64, u64) >,
+    snapshot_indices: RefCell< SortedIndices >
+}
+
+/// The sorted tables built by the last snapshot; the allocations and the operations
+/// which were added since then only need to be sorted on their own and merged into them.
+#[derive(Default)]
+struct SortedIndices {
+    operation_count: usize,
+    operations: SpillVec< (Timestamp, OperationId) >,
+    sorted_by_timestamp: SpillVec< AllocationId >,
+    sorted_by_address: SpillVec< AllocationId >,
+    sorted_by_size: SpillVec< AllocationId >
+}
+
+/// Merges the `new` elements into the already sorted `old` ones; on ties the old ones go first.
+fn merge_sorted< T: Copy, K: Ord, F: Fn( &T ) -> K >( old: &[T], new: SpillVec< T >, key: F ) -> SpillVec< T > {
+    if old.is_empty() {
+        return new;
+    }
+
+    let mut output = SpillVec::with_capacity( old.len() + new.len() );
+    let mut old = old.iter().peekable();
+    let mut new = new.iter().peekable();
+    loop {
+        let is_old_first = match (old.peek(), new.peek()) {
+            (Some( a ), Some( b )) => key( a ) <= key( b ),
+            (Some( _ ), None) => true,
+            (None, Some( _ )) => false,
+            (None, None) => break
+        };
+
+        if is_old_first {
+            output.push( *old.next().unwrap() );
+        } else {
+            output.push( *new.next().unwrap() );
+        }
+    }
+
+    output
+}
+
+/// Sorts the allocations which aren't in the `previous` index yet and merges them into it.
+fn extend_index< K: Ord, F: Fn( AllocationId ) -> K >( previous: &[AllocationId], allocation_count: usize, key: F ) -> SpillVec< AllocationId > {
+    let mut index = SpillVec::with_capacity( allocation_count - previous.len() );
+    for id in previous.len()..allocation_count {
+        index.push( AllocationId::new( id as _ ) );
+    }
+
+    // The unstable sort doesn't need any extra memory, and every key includes the allocation's ID anyway.
+    index.sort_unstable_by_key( |&id| key( id ) );
+    merge_sorted( previous, index, |&id| key( id ) )
+}
+
+fn parse_hex( string: &str ) -> Option< Vec< u8 > > {
+    if string.len() % 2 != 0 || !string.is_ascii() {
+        return None;
+    }
+
+    (0..string.len()).step_by( 2 ).map( |index| u8::from_str_radix( &string[ index..index + 2 ], 16 ).ok() ).collect()
+}
+
+#[test]
+fn test_parse_hex() {
+    assert_eq!( parse_hex( "00ff1a" ), Some( vec![ 0x00, 0xff, 0x1a ] ) );
+    assert_eq!( parse_hex( "0ff" ), None );
+    assert_eq!( parse_hex( "zz" ), None );
+}
+
+/// Checks whether a binary has the build ID which the profiler saw; the binaries without one can't be checked, so they're accepted.
+pub(crate) fn is_build_id_matching( binary_data: &BinaryData, expected_build_id: Option< &[u8] > ) -> bool {
+    match (binary_data.build_id(), expected_build_id) {
+        (Some( build_id ), Some( expected_build_id )) => build_id == expected_build_id,
+        _ => true
+    }
+}
+
+/// Checks whether an index generated with a given set of symbols can be used when loading the data with another set.
+fn is_index_usable( indexed_sources: &SymbolSources, symbol_sources: &SymbolSources ) -> bool {
+    indexed_sources.sysroots == symbol_sources.sysroots &&
+    indexed_sources.symbol_paths == symbol_sources.symbol_paths &&
+    indexed_sources.debug_symbols.iter().all( |path| symbol_sources.debug_symbols.contains( path ) )
+}
+
+/// Converts the file into a data file if it was written by another memory profiler, so that it can be loaded like any other.
+fn import_if_foreign( path: &Path ) -> io::Result< Option< Vec< u8 > > > {
+    let mut prefix = Vec::new();
+    File::open( path )?.take( 4096 ).read_to_end( &mut prefix )?;
+    let format = match detect_foreign_format( &prefix ) {
+        Some( format ) => format,
+        None => return Ok( None )
+    };
+
+    let mut output = Vec::new();
+    let ifp = io::BufReader::new( File::open( path )? );
+    match format {
+        ForeignFormat::Pprof => {
+            info!( "Importing a pprof profile..." );
+            import_pprof( ifp, &mut output )?;
+        },
+        ForeignFormat::Heaptrack => {
+            info!( "Importing a heaptrack capture..." );
+
+            // Heaptrack doesn't record when it started, so this is the best guess there is.
+            let modified = std::fs::metadata( path )?.modified()?.duration_since( std::time::UNIX_EPOCH ).unwrap_or_default();
+            import_heaptrack( ifp, &mut output, Timestamp::from_usecs( modified.as_micros() as u64 ) )?;
+        }
+    }
+
+    Ok( Some( output ) )
 }
 
-fn address_to_frame< F: FnMut( Frame ) >( address_space: &dyn IAddressSpace, interner: &mut StringInterner, address: u64, mut callback: F ) {
+pub(crate) fn new_address_space( arch: &str ) -> Box< dyn IAddressSpace > {
+    match arch {
+        "arm" => Box::new( AddressSpace::< arch::arm::Arch >::new() ),
+        "x86_64" => Box::new( AddressSpace::< arch::amd64::Arch >::new() ),
+        "mips64" => Box::new( AddressSpace::< arch::mips64::Arch >::new() ),
+        "aarch64" => Box::new( AddressSpace::< arch::aarch64::Arch >::new() ),
+        _ => panic!( "Unknown architecture: {}", arch )
+    }
+}
+
+pub(crate) fn address_to_frame< F: FnMut( Frame ) >( address_space: &dyn IAddressSpace, interner: &mut StringInterner, address: u64, mut callback: F ) {
     address_space.decode_symbol_while( address, &mut |frame| {
         let mut output = Frame::new_unknown( CodePointer::new( address ) );
         if let Some( str ) = frame.library.take() {
@@ -169,7 +426,7 @@ impl PointerSize for u64 {
     }
 }
 
-fn get_basename( path: &str ) -> &str {
+pub(crate) fn get_basename( path: &str ) -> &str {
     if path.is_empty() {
         return path;
     }
@@ -183,7 +440,16 @@ fn get_basename( path: &str ) -> &str {
     &path[ path.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ]
 }
 
-fn into_key( id: event::AllocationId, pointer: DataPointer ) -> (u64, u64) {
+/// Returns the key under which the symbols for a given address are cached and the address relative to its binary,
+/// or `None` if the binary doesn't have a build ID and it's impossible to tell whether it's the same binary as before.
+fn symbol_cache_key( maps: &RangeMap< Region >, binaries: &HashMap< String, Arc< BinaryData > >, binaries_with_debug_binary: &HashSet< String >, address: u64 ) -> Option< (Vec< u8 >, u64) > {
+    let (range, region) = maps.get( address )?;
+    let build_id = binaries.get( &region.name )?.build_id()?;
+    let key = symbol_cache::cache_key( build_id, binaries_with_debug_binary.contains( &region.name ) );
+    Some( (key, address - range.start + region.file_offset) )
+}
+
+pub(crate) fn into_key( id: event::AllocationId, pointer: DataPointer ) -> (u64, u64) {
     if !id.is_invalid() && !id.is_untracked() {
         (id.thread, id.allocation)
     } else {
@@ -192,19 +458,14 @@ fn into_key( id: event::AllocationId, pointer: DataPointer ) -> (u64, u64) {
 }
 
 impl Loader {
-    pub fn new( header: HeaderBody, debug_info_index: DebugInfoIndex ) -> Self {
-        let address_space: Box< dyn IAddressSpace > = match &*header.arch {
-            "arm" => Box::new( AddressSpace::< arch::arm::Arch >::new() ),
-            "x86_64" => Box::new( AddressSpace::< arch::amd64::Arch >::new() ),
-            "mips64" => Box::new( AddressSpace::< arch::mips64::Arch >::new() ),
-            "aarch64" => Box::new( AddressSpace::< arch::aarch64::Arch >::new() ),
-            _ => panic!( "Unknown architecture: {}", header.arch )
-        };
+    pub fn new( header: HeaderBody, symbol_sources: &SymbolSources ) -> Self {
+        let address_space = new_address_space( &header.arch );
 
         let flags = header.flags;
         let timestamp = header.timestamp;
         let wall_clock_secs = header.wall_clock_secs;
         let wall_clock_nsecs = header.wall_clock_nsecs;
+        let wall_clock_start = Timestamp::from_timespec( wall_clock_secs, wall_clock_nsecs ) - (header.timestamp - header.initial_timestamp);
 
         let mut loader = Loader {
             id: header.id,
@@ -212,16 +473,22 @@ impl Loader {
             interner: RefCell::new( StringInterner::new() ),
             address_space,
             address_space_needs_reloading: true,
-            debug_info_index,
+            debug_info_index: symbol_sources.debug_info_index(),
+            binary_index: symbol_sources.binary_index(),
+            symbol_sources: symbol_sources.clone(),
             binaries: Default::default(),
+            missing_binaries: Default::default(),
+            binaries_with_debug_binary: Default::default(),
+            expected_build_ids: Default::default(),
+            embedded_symbols: Default::default(),
             maps: RangeMap::new(),
             backtraces: Default::default(),
-            backtraces_storage: Default::default(),
+            backtrace_trie: BacktraceTrie::new(),
             backtrace_to_id: Default::default(),
             backtrace_remappings: Default::default(),
             group_stats: Default::default(),
-            operations: Vec::with_capacity( 100000 ),
-            allocations: Vec::with_capacity( 100000 ),
+            operations: SpillVec::with_capacity( 100000 ),
+            allocations: SpillVec::with_capacity( 100000 ),
             allocation_map: Default::default(),
             allocation_range_map: RangeMap::new(),
             allocation_range_map_dirty: true,
@@ -229,58 +496,173 @@ impl Loader {
             frames: Default::default(),
             frame_to_id: Default::default(),
             frames_by_address: Default::default(),
+            frames_by_address_storage: Default::default(),
             shared_ptr_backtraces: Default::default(),
             shared_ptr_allocations: Default::default(),
             total_allocated: 0,
             total_allocated_count: 0,
             total_freed: 0,
             total_freed_count: 0,
+            unknown_deallocation_count: 0,
+            unknown_deallocation_range: None,
+            duplicate_allocation_count: 0,
+            lost_chunk_count: 0,
+            lost_event_count: 0,
+            wall_clock_start,
             frame_skip_ranges: Vec::with_capacity( 4 ),
             symbol_new_range: -1_i64 as u64..0,
             marker: 0,
             mallopts: Default::default(),
+            allocator_stats: Default::default(),
+            allocator_info: None,
+            metadata: Default::default(),
+            allocation_contents: Default::default(),
+            loaded_libraries: Default::default(),
+            library_events: Default::default(),
             timestamp_to_wall_clock: 0,
             is_little_endian: (flags & HEADER_FLAG_IS_LITTLE_ENDIAN) != 0,
             mmap_operations: Default::default(),
             maximum_backtrace_depth: 0,
             previous_backtrace_on_thread: Default::default(),
-            string_id_map: Default::default()
+            string_id_map: Default::default(),
+            shard: None,
+            foreign_allocations: Default::default(),
+            snapshot_indices: Default::default()
         };
 
         loader.update_timestamp_to_wall_clock( timestamp, wall_clock_secs, wall_clock_nsecs );
         loader
     }
 
+    /// Records that a given number of chunks with a given number of events were damaged and had to be skipped.
+    pub(crate) fn record_data_loss( &mut self, chunks: u64, events: u64 ) {
+        self.lost_chunk_count += chunks;
+        self.lost_event_count += events;
+    }
+
     fn update_timestamp_to_wall_clock( &mut self, timestamp: Timestamp, wall_clock_secs: u64, wall_clock_nsecs: u64 ) {
         self.timestamp_to_wall_clock = Timestamp::from_timespec( wall_clock_secs, wall_clock_nsecs ).as_usecs().wrapping_sub( timestamp.as_usecs() );
     }
 
     pub fn load_from_stream_without_debug_info< F: Read + Send + 'static >( fp: F ) -> Result< Data, io::Error > {
-        use std::iter;
+        Loader::load_from_stream( fp, &SymbolSources::default() )
+    }
+
+    /// Loads the data from a file, memory-mapping it if possible.
+    ///
+    /// The data is decompressed on the fly, so mapping the file avoids an extra copy
+    /// through the read buffers and lets the kernel drop the already processed pages.
+    ///
+    /// The processed data is saved into a `.idx` file next to the original one
+    /// (unless the original is encrypted, as the index itself isn't),
+    /// which is then used instead of the original file on subsequent loads.
+    /// If only new debug symbols were added since then the index is resymbolicated
+    /// with them instead of reprocessing the whole file.
+    ///
+    /// The per-allocation tables are always served from the index once it's written,
+    /// even on the first load, so they're only paged in when a query actually needs them.
+    pub fn load_from_file< P: AsRef< Path > >( path: P, symbol_sources: &SymbolSources ) -> Result< Data, io::Error > {
+        let path = path.as_ref();
+        match load_index( path ) {
+            Ok( Some( (ref indexed_sources, _) ) ) if !is_index_usable( indexed_sources, symbol_sources ) => {
+                info!( "Ignoring the index file since it was generated with different symbols" );
+            },
+            Ok( Some( (indexed_sources, mut data) ) ) => {
+                info!( "Loaded data from the index file" );
+                let extra_debug_symbols: Vec< &PathBuf > = symbol_sources.debug_symbols.iter().filter( |path| !indexed_sources.debug_symbols.contains( path ) ).collect();
+                if !extra_debug_symbols.is_empty() {
+                    data.resymbolicate( extra_debug_symbols );
+                    if let Err( error ) = write_index( path, symbol_sources, &data ) {
+                        warn!( "Failed to write the index file: {}", error );
+                    }
+                }
+
+                return Ok( data );
+            },
+            Ok( None ) => {},
+            Err( error ) => {
+                warn!( "Failed to load the index file: {}", error );
+            }
+        }
+
+        let data = if let Some( imported ) = import_if_foreign( path )? {
+            Loader::load_from_stream( io::Cursor::new( imported ), symbol_sources )?
+        } else {
+            let fp = File::open( path )?;
+            match unsafe { Mmap::map( &fp ) } {
+                Ok( mmap ) => Loader::load_from_stream( io::Cursor::new( mmap ), symbol_sources )?,
+                Err( error ) => {
+                    debug!( "Failed to mmap the data file: {}", error );
+                    Loader::load_from_stream( fp, symbol_sources )?
+                }
+            }
+        };
+
+        if is_encrypted( path )? {
+            info!( "Not writing an index file since the data file is encrypted" );
+            return Ok( data );
+        }
 
-        let empty: iter::Empty< &OsStr > = iter::empty();
-        Loader::load_from_stream( fp, empty )
+        if let Err( error ) = write_index( path, symbol_sources, &data ) {
+            warn!( "Failed to write the index file: {}", error );
+            return Ok( data );
+        }
+
+        // Switch over to the tables mapped from the index so that they don't have to stay in memory.
+        match load_index( path ) {
+            Ok( Some( (_, indexed) ) ) => Ok( indexed ),
+            Ok( None ) => Ok( data ),
+            Err( error ) => {
+                warn!( "Failed to load the index file which was just written: {}", error );
+                Ok( data )
+            }
+        }
     }
 
-    pub fn load_from_stream< F: Read + Send + 'static, D: AsRef< OsStr >, I: IntoIterator< Item = D > >( fp: F, debug_symbols: I ) -> Result< Data, io::Error > {
+    /// Loads only a single shard of the data from a file.
+    ///
+    /// The index file isn't used here since it always contains the whole data.
+    pub fn load_shard_from_file< P: AsRef< Path > >( path: P, symbol_sources: &SymbolSources, shard: Shard ) -> Result< Data, io::Error > {
+        if let Some( imported ) = import_if_foreign( path.as_ref() )? {
+            return Loader::load_from_stream_impl( io::Cursor::new( imported ), symbol_sources, Some( shard ) );
+        }
+
+        let fp = File::open( path.as_ref() )?;
+        match unsafe { Mmap::map( &fp ) } {
+            Ok( mmap ) => Loader::load_from_stream_impl( io::Cursor::new( mmap ), symbol_sources, Some( shard ) ),
+            Err( error ) => {
+                debug!( "Failed to mmap the data file: {}", error );
+                Loader::load_from_stream_impl( fp, symbol_sources, Some( shard ) )
+            }
+        }
+    }
+
+    pub fn load_from_stream< F: Read + Send + 'static >( fp: F, symbol_sources: &SymbolSources ) -> Result< Data, io::Error > {
+        Loader::load_from_stream_impl( fp, symbol_sources, None )
+    }
+
+    fn load_from_stream_impl< F: Read + Send + 'static >( fp: F, symbol_sources: &SymbolSources, shard: Option< Shard > ) -> Result< Data, io::Error > {
         debug!( "Starting to load data..." );
 
         let start_timestamp = Instant::now();
-        let (header, event_stream) = parse_events( fp )?;
-
-        let mut debug_info_index = DebugInfoIndex::new();
-        for path in debug_symbols {
-            debug_info_index.add( path.as_ref() );
+        let (header, event_stream, data_loss) = parse_events_with_data_loss( fp )?;
+        let mut loader = Loader::new( header, symbol_sources );
+        loader.shard = shard;
+        if let Some( shard ) = shard {
+            info!( "Loading only the shard {} of the data", shard );
         }
 
-        let mut loader = Loader::new( header, debug_info_index );
-
         for event in event_stream {
             let event = event?;
             loader.process( event );
         }
 
+        loader.record_data_loss( data_loss.chunks(), data_loss.events() );
         let output = loader.finalize();
+        if let Err( error ) = symbol_cache::flush() {
+            warn!( "Failed to write the symbol cache: {}", error );
+        }
+
         let elapsed = start_timestamp.elapsed();
         info!( "Loaded data in {}s {:03}", elapsed.as_secs(), elapsed.subsec_millis() );
         Ok( output )
@@ -290,6 +672,10 @@ impl Loader {
         Timestamp::from_usecs( timestamp.as_usecs().wrapping_add( self.timestamp_to_wall_clock ) )
     }
 
+    fn parse_jemalloc_arena( flags: u32 ) -> u16 {
+        ((flags & event::ALLOC_FLAG_JEMALLOC_ARENA_MASK) >> event::ALLOC_FLAG_JEMALLOC_ARENA_SHIFT) as u16
+    }
+
     fn parse_flags( &self, backtrace: BacktraceId, flags: u32 ) -> AllocationFlags {
         let mut allocation_flags = AllocationFlags::empty();
         if self.shared_ptr_backtraces.contains( &backtrace ) {
@@ -329,8 +715,15 @@ impl Loader {
         thread: ThreadId,
         flags: u32,
         extra_usable_space: u32,
-        preceding_free_space: u64
+        preceding_free_space: u64,
+        in_shard: bool
     ) {
+        if !in_shard {
+            self.foreign_allocations.insert( into_key( id, pointer ) );
+            return;
+        }
+
+        let jemalloc_arena = Self::parse_jemalloc_arena( flags );
         let flags = self.parse_flags( backtrace, flags );
         let allocation_id = AllocationId::new( self.allocations.len() as _ );
         let allocation = Allocation {
@@ -345,13 +738,15 @@ impl Loader {
             flags,
             extra_usable_space,
             preceding_free_space: preceding_free_space as u32,
-            marker: self.marker
+            marker: self.marker,
+            jemalloc_arena
         };
 
         let key = into_key( id, pointer );
         let entry = self.allocation_map.entry( key );
         if let hash_map::Entry::Occupied( entry ) = entry {
             warn!( "Duplicate allocation of 0x{:016X}; old backtrace = {:?}, new backtrace = {:?}", pointer, self.allocations[ entry.get().raw() as usize ].backtrace, backtrace );
+            self.duplicate_allocation_count += 1;
             return;
         }
 
@@ -379,6 +774,23 @@ impl Loader {
         self.allocations_by_backtrace.get_mut( &backtrace ).unwrap().push( allocation_id );
     }
 
+    fn is_in_shard( &self, backtrace: u64 ) -> bool {
+        self.shard.map( |shard| shard.contains( backtrace ) ).unwrap_or( true )
+    }
+
+    fn add_unknown_deallocation( &mut self, timestamp: Timestamp ) {
+        // Every worker sees every deallocation, so only one of them should count those.
+        if self.shard.map( |shard| shard.index != 0 ).unwrap_or( false ) {
+            return;
+        }
+
+        self.unknown_deallocation_count += 1;
+        self.unknown_deallocation_range = match self.unknown_deallocation_range {
+            Some( (first, last) ) => Some( (cmp::min( first, timestamp ), cmp::max( last, timestamp )) ),
+            None => Some( (timestamp, timestamp) )
+        };
+    }
+
     fn handle_free(
         &mut self,
         id: event::AllocationId,
@@ -388,10 +800,15 @@ impl Loader {
         thread: ThreadId
     ) {
         let key = into_key( id, pointer );
+        if self.foreign_allocations.remove( &key ) {
+            return;
+        }
+
         let allocation_id = match self.allocation_map.remove( &key ) {
             Some( id ) => id,
             None => {
                 debug!( "Unknown deallocation of 0x{:016X} at backtrace = {:?}", pointer, backtrace );
+                self.add_unknown_deallocation( timestamp );
                 return;
             }
         };
@@ -425,14 +842,33 @@ impl Loader {
         thread: ThreadId,
         flags: u32,
         extra_usable_space: u32,
-        preceding_free_space: u64
+        preceding_free_space: u64,
+        in_shard: bool
     ) {
+        if !in_shard {
+            // As far as this shard is concerned the old allocation was simply freed.
+            self.handle_free( id, timestamp, old_pointer, Some( backtrace ), thread );
+            self.foreign_allocations.insert( into_key( id, new_pointer ) );
+            return;
+        }
+
         let old_key = into_key( id, old_pointer );
+        if self.foreign_allocations.remove( &old_key ) {
+            // The old allocation belongs to another shard, so here the new one is a fresh allocation.
+            self.handle_alloc( id, timestamp, new_pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space, true );
+            return;
+        }
+
         let allocation_id = match self.allocation_map.remove( &old_key ) {
             Some( id ) => id,
-            None => return
+            None => {
+                debug!( "Unknown reallocation of 0x{:016X} at backtrace = {:?}", old_pointer, backtrace );
+                self.add_unknown_deallocation( timestamp );
+                return;
+            }
         };
 
+        let jemalloc_arena = Self::parse_jemalloc_arena( flags );
         let flags = self.parse_flags( backtrace, flags );
         let reallocation_id = AllocationId::new( self.allocations.len() as _ );
         {
@@ -459,13 +895,15 @@ impl Loader {
             flags,
             extra_usable_space,
             preceding_free_space: preceding_free_space as u32,
-            marker: self.marker
+            marker: self.marker,
+            jemalloc_arena
         };
 
         let new_key = into_key( id, new_pointer );
         let entry = self.allocation_map.entry( new_key );
         if let hash_map::Entry::Occupied( entry ) = entry {
             warn!( "Duplicate allocation (during realloc) of 0x{:016X}; old backtrace = {:?}, new backtrace = {:?}", new_pointer, self.allocations[ entry.get().raw() as usize ].backtrace, backtrace );
+            self.duplicate_allocation_count += 1;
             return;
         }
 
@@ -510,7 +948,6 @@ impl Loader {
 
             let container_address = base_address + (mem::size_of::< P >() * index) as u64;
 
-
             if let Some( &container_allocation_id ) = self.allocation_range_map.get_value( container_address ) {
                 let allocation: &Allocation = &self.allocations[ allocation_id.raw() as usize ];
                 let container_allocation = &self.allocations[ container_allocation_id.raw() as usize ];
@@ -538,8 +975,42 @@ impl Loader {
         }
 
         self.address_space_needs_reloading = false;
+        let symbol_sources = &self.symbol_sources;
+        let binary_index = &mut self.binary_index;
+        for region in self.maps.values() {
+            if !region.is_executable || region.name.is_empty() || region.name.starts_with( "[" ) {
+                continue;
+            }
+
+            if self.binaries.contains_key( &region.name ) || self.missing_binaries.contains( &region.name ) {
+                continue;
+            }
+
+            let expected_build_id = self.expected_build_ids.get( &region.name ).map( |build_id| build_id.as_slice() );
+            let binary_data = symbol_sources.load_from_sysroot( &region.name, expected_build_id )
+                .or_else( || {
+                    let binary_data = binary_index.get( get_basename( &region.name ), None, expected_build_id )?;
+                    if !is_build_id_matching( binary_data, expected_build_id ) {
+                        warn!( "Ignoring the binary for {:?} from the symbol paths since its build ID doesn't match", region.name );
+                        return None;
+                    }
+
+                    Some( binary_data.clone() )
+                });
+
+            match binary_data {
+                Some( binary_data ) => {
+                    self.binaries.insert( region.name.clone(), binary_data );
+                },
+                None => {
+                    self.missing_binaries.insert( region.name.clone() );
+                }
+            }
+        }
+
         let binaries = &self.binaries;
         let debug_info_index = &mut self.debug_info_index;
+        let binaries_with_debug_binary = &mut self.binaries_with_debug_binary;
         let regions: Vec< Region > = self.maps.values().cloned().collect();
         self.address_space.reload( regions, &mut |region, handle| {
             handle.should_load_frame_descriptions( false );
@@ -554,6 +1025,7 @@ impl Loader {
             };
 
             if let Some( debug_binary_data ) = debug_binary_data {
+                binaries_with_debug_binary.insert( region.name.clone() );
                 handle.set_debug_binary( debug_binary_data.clone() );
             }
         });
@@ -578,13 +1050,13 @@ impl Loader {
     }
 
     fn handle_backtrace( &mut self, id: BacktraceId, potentially_call_to_new: bool ) {
-        let (offset, length) = self.backtraces[ id.raw() as usize ];
-        self.maximum_backtrace_depth = cmp::max( self.maximum_backtrace_depth, length as _ );
+        let frame_ids = self.backtrace_trie.get( self.backtraces[ id.raw() as usize ] );
+        self.maximum_backtrace_depth = cmp::max( self.maximum_backtrace_depth, frame_ids.len() as _ );
 
         if potentially_call_to_new {
             let interner = self.interner.get_mut();
             let frames = &self.frames;
-            let mut iter = self.backtraces_storage[ offset as usize.. ].iter().rev().flat_map( |&id| frames[ id ].raw_function().and_then( |id| interner.resolve( id ) ) );
+            let mut iter = frame_ids.iter().rev().flat_map( |&id| frames[ id ].raw_function().and_then( |id| interner.resolve( id ) ) );
             if let Some( name ) = iter.next() {
                 if name == "_ZNSt16_Sp_counted_baseILN9__gnu_cxx12_Lock_policyE2EEC4Ev" {
                     self.shared_ptr_backtraces.insert( id );
@@ -626,8 +1098,8 @@ impl Loader {
         let mut addresses = addresses.into_owned();
         addresses.drain( 0..to_skip );
 
-        let backtrace_storage_offset = self.backtraces_storage.len();
-        let backtrace_storage = &mut self.backtraces_storage;
+        let mut frame_ids: Vec< FrameId > = Vec::with_capacity( addresses.len() );
+        let frames_by_address_storage = &mut self.frames_by_address_storage;
         let frames = &mut self.frames;
         let frame_to_id = &mut self.frame_to_id;
         let mut interner = self.interner.get_mut();
@@ -636,13 +1108,13 @@ impl Loader {
             let address = if address > 0 { address - 1 } else { 0 };
             if let Some( range ) = self.frames_by_address.get( &address ).cloned() {
                 for index in range {
-                    let frame_id = backtrace_storage[ index ];
-                    backtrace_storage.push( frame_id );
+                    let frame_id = frames_by_address_storage[ index ];
+                    frame_ids.push( frame_id );
                     callback( frame_id, false );
                 }
             } else {
-                let offset = backtrace_storage.len();
-                address_to_frame( &*self.address_space, &mut interner, address, |frame| {
+                let offset = frames_by_address_storage.len();
+                let mut add_frame = |frame: Frame| {
                     let (frame_id, is_new) = if let Some( &frame_id ) = frame_to_id.get( &frame ) {
                         (frame_id, false)
                     } else {
@@ -653,19 +1125,45 @@ impl Loader {
                     };
 
                     callback( frame_id, is_new );
-                    backtrace_storage.push( frame_id );
-                });
+                    frame_ids.push( frame_id );
+                    frames_by_address_storage.push( frame_id );
+                };
+
+                if let Some( embedded_frames ) = self.embedded_symbols.lookup( &self.maps, &self.binaries, address ) {
+                    for embedded_frame in embedded_frames {
+                        let mut frame = embedded_frame.clone();
+                        frame.set_address( CodePointer::new( address ) );
+                        add_frame( frame );
+                    }
+                } else {
+                    let cache_key = symbol_cache_key( &self.maps, &self.binaries, &self.binaries_with_debug_binary, address );
+                    let cached_frames = cache_key.as_ref().and_then( |&(ref key, relative_address)| symbol_cache::lookup( key, relative_address ) );
+                    if let Some( cached_frames ) = cached_frames {
+                        for cached_frame in &cached_frames {
+                            add_frame( cached_frame.to_frame( address, &mut interner ) );
+                        }
+                    } else {
+                        let mut decoded_frames = Vec::new();
+                        address_to_frame( &*self.address_space, &mut interner, address, |frame| decoded_frames.push( frame ) );
+                        if let Some( (key, relative_address) ) = cache_key {
+                            let cached_frames = decoded_frames.iter().map( |frame| CachedFrame::from_frame( frame, &interner ) ).collect();
+                            symbol_cache::insert( &key, relative_address, cached_frames );
+                        }
 
-                self.frames_by_address.insert( address, offset..backtrace_storage.len() );
+                        for frame in decoded_frames {
+                            add_frame( frame );
+                        }
+                    }
+                }
+
+                self.frames_by_address.insert( address, offset..frames_by_address_storage.len() );
             }
         }
 
         let id = BacktraceId::new( self.backtraces.len() as _ );
         self.backtrace_remappings.insert( raw_id, id );
 
-        let backtrace_length = backtrace_storage.len() - backtrace_storage_offset;
-        let backtrace_storage_ref = (backtrace_storage_offset as _, backtrace_length as _);
-
+        let backtrace_storage_ref = self.backtrace_trie.insert( frame_ids );
         self.backtrace_to_id.insert( addresses, id );
         self.backtraces.push( backtrace_storage_ref );
 
@@ -738,21 +1236,47 @@ impl Loader {
         }
     }
 
+    /// Returns the build IDs of all of the libraries for which we have a binary.
+    pub(crate) fn library_build_ids( &self ) -> HashMap< String, Vec< u8 > > {
+        self.binaries.iter().filter_map( |(path, binary_data)| {
+            binary_data.build_id().map( |build_id| (path.clone(), build_id.to_owned()) )
+        }).collect()
+    }
+
     pub(crate) fn get_frame( &self, id: FrameId ) -> &Frame {
         &self.frames[ id ]
     }
 
+    fn update_loaded_libraries( &mut self, timestamp: Timestamp, libraries: HashSet< String > ) {
+        let timestamp = self.shift_timestamp( timestamp );
+        let mut unloaded: Vec< _ > = self.loaded_libraries.difference( &libraries ).cloned().collect();
+        let mut loaded: Vec< _ > = libraries.difference( &self.loaded_libraries ).cloned().collect();
+        unloaded.sort();
+        loaded.sort();
+
+        for library in unloaded {
+            self.library_events.push( LibraryEvent { timestamp, library, is_loaded: false } );
+        }
+
+        for library in loaded {
+            self.library_events.push( LibraryEvent { timestamp, library, is_loaded: true } );
+        }
+
+        self.loaded_libraries = libraries;
+    }
+
     pub fn process( &mut self, event: Event ) {
         match event {
             Event::Header( header ) => {
                 assert_eq!( header.id, self.header.id );
                 assert_eq!( header.initial_timestamp, self.header.initial_timestamp );
             },
-            Event::File { ref path, ref contents, .. } if path == "/proc/self/maps" => {
+            Event::File { timestamp, ref path, ref contents } if path == "/proc/self/maps" => {
                 let contents = String::from_utf8_lossy( &contents );
                 trace!( "/proc/self/maps:\n{}", contents );
 
                 let mut maps = Vec::new();
+                let mut libraries = HashSet::new();
 
                 self.frame_skip_ranges.clear();
                 for region in parse_maps( &contents ) {
@@ -765,9 +1289,15 @@ impl Loader {
                         }
                     }
 
+                    if region.is_executable && !region.name.is_empty() && !region.name.starts_with( "[" ) {
+                        libraries.insert( get_basename( &region.name ).to_owned() );
+                    }
+
                     maps.push( (region.start..region.end, region) );
                 }
 
+                self.update_loaded_libraries( timestamp, libraries );
+
                 for range in &self.frame_skip_ranges {
                     debug!( "Skip range: 0x{:016X}-0x{:016X}", range.start, range.end );
                 }
@@ -780,6 +1310,17 @@ impl Loader {
 
                 self.address_space_needs_reloading = true;
             },
+            Event::File { ref path, ref contents, .. } if path == BUILD_IDS_PATH => {
+                for line in String::from_utf8_lossy( &contents ).lines() {
+                    let mut parts = line.splitn( 2, ' ' );
+                    let (build_id, library) = match (parts.next().and_then( parse_hex ), parts.next()) {
+                        (Some( build_id ), Some( library )) => (build_id, library),
+                        _ => continue
+                    };
+
+                    self.expected_build_ids.insert( library.to_owned(), build_id );
+                }
+            },
             Event::File { ref path, ref contents, .. } => {
                 if !contents.starts_with( b"\x7FELF" ) {
                     return;
@@ -788,6 +1329,7 @@ impl Loader {
                 trace!( "File: {}", path );
                 if let Ok( binary_data ) = BinaryData::load_from_owned_bytes( &path, contents.clone().into_owned() ) {
                     self.scan_for_symbols( &binary_data );
+                    self.missing_binaries.remove( path.deref() );
                     self.binaries.insert( path.deref().to_owned(), Arc::new( binary_data ) );
                 }
             },
@@ -825,36 +1367,75 @@ impl Loader {
                 frame.set_is_inline( is_inline );
                 self.frames.push( frame );
             },
+            Event::SymbolTable { build_id, library, strings, entries } => {
+                let interner = self.interner.get_mut();
+                let strings: Vec< StringId > = strings.iter().map( |string| interner.get_or_intern( &**string ) ).collect();
+                let library_id = interner.get_or_intern( get_basename( &library ) );
+                let string = |index: u32| strings.get( index as usize ).cloned();
+
+                let mut table = HashMap::with_capacity( entries.len() );
+                for entry in entries {
+                    let frames = entry.frames.iter().map( |embedded_frame| {
+                        let mut frame = Frame::new_unknown( CodePointer::new( 0 ) );
+                        frame.set_library( library_id );
+                        if let Some( id ) = string( embedded_frame.raw_function ) {
+                            frame.set_raw_function( id );
+                        }
+                        if let Some( id ) = string( embedded_frame.function ) {
+                            frame.set_function( id );
+                        }
+                        if let Some( id ) = string( embedded_frame.source ) {
+                            frame.set_source( id );
+                        }
+                        if embedded_frame.line != 0xFFFFFFFF {
+                            frame.set_line( embedded_frame.line );
+                        }
+                        if embedded_frame.column != 0xFFFFFFFF {
+                            frame.set_column( embedded_frame.column );
+                        }
+
+                        frame.set_is_inline( embedded_frame.is_inline );
+                        frame
+                    }).collect();
+
+                    table.insert( entry.address, frames );
+                }
+
+                debug!( "Loaded an embedded symbol table for {} with {} entries", library, table.len() );
+                self.embedded_symbols.add( library.into_owned(), build_id.into_owned(), table );
+            },
             Event::DecodedBacktrace { frames } => {
                 let id = BacktraceId::new( self.backtraces.len() as _ );
                 self.backtrace_remappings.insert( id.raw() as _, id );
 
-                let backtrace_storage_offset = self.backtraces_storage.len();
-                self.backtraces_storage.extend( frames.iter().cloned().map( |id| id as usize ) );
-                let backtrace_length = self.backtraces_storage.len() - backtrace_storage_offset;
-                let backtrace_storage_ref = (backtrace_storage_offset as _, backtrace_length as _);
+                let frame_ids: Vec< FrameId > = frames.iter().map( |&id| id as usize ).collect();
+                let backtrace_storage_ref = self.backtrace_trie.insert( frame_ids );
                 self.backtraces.push( backtrace_storage_ref );
                 self.handle_backtrace( id, true );
             },
             Event::Alloc { timestamp, allocation: AllocBody { pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space } } => {
                 let timestamp = self.shift_timestamp( timestamp );
+                let in_shard = self.is_in_shard( backtrace );
                 let backtrace = self.lookup_backtrace( backtrace ).unwrap();
-                self.handle_alloc( event::AllocationId::UNTRACKED, timestamp, pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space );
+                self.handle_alloc( event::AllocationId::UNTRACKED, timestamp, pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space, in_shard );
             },
             Event::AllocEx { id, timestamp, allocation: AllocBody { pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space } } => {
                 let timestamp = self.shift_timestamp( timestamp );
+                let in_shard = self.is_in_shard( backtrace );
                 let backtrace = self.lookup_backtrace( backtrace ).unwrap();
-                self.handle_alloc( id, timestamp, pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space );
+                self.handle_alloc( id, timestamp, pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space, in_shard );
             },
             Event::Realloc { timestamp, old_pointer, allocation: AllocBody { pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space } } => {
                 let timestamp = self.shift_timestamp( timestamp );
+                let in_shard = self.is_in_shard( backtrace );
                 let backtrace = self.lookup_backtrace( backtrace ).unwrap();
-                self.handle_realloc( event::AllocationId::UNTRACKED, timestamp, old_pointer, pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space );
+                self.handle_realloc( event::AllocationId::UNTRACKED, timestamp, old_pointer, pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space, in_shard );
             },
             Event::ReallocEx { id, timestamp, old_pointer, allocation: AllocBody { pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space } } => {
                 let timestamp = self.shift_timestamp( timestamp );
+                let in_shard = self.is_in_shard( backtrace );
                 let backtrace = self.lookup_backtrace( backtrace ).unwrap();
-                self.handle_realloc( id, timestamp, old_pointer, pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space );
+                self.handle_realloc( id, timestamp, old_pointer, pointer, size, backtrace, thread, flags, extra_usable_space, preceding_free_space, in_shard );
             },
             Event::Free { timestamp, pointer, backtrace, thread } => {
                 let timestamp = self.shift_timestamp( timestamp );
@@ -897,6 +1478,22 @@ impl Loader {
 
                 self.mmap_operations.push( MmapOperation::Munmap( munmap ) );
             },
+            Event::MemoryRemap { timestamp, old_pointer, old_length, pointer, length, flags, backtrace, thread } => {
+                let timestamp = self.shift_timestamp( timestamp );
+                let backtrace = self.lookup_backtrace( backtrace ).unwrap();
+                let mremap = MemoryRemap {
+                    timestamp,
+                    old_pointer,
+                    old_length,
+                    pointer,
+                    length,
+                    flags,
+                    backtrace,
+                    thread
+                };
+
+                self.mmap_operations.push( MmapOperation::Mremap( mremap ) );
+            },
             Event::Mallopt { timestamp, backtrace, thread, param, value, result } => {
                 let timestamp = self.shift_timestamp( timestamp );
                 let backtrace = self.lookup_backtrace( backtrace ).unwrap();
@@ -910,8 +1507,49 @@ impl Loader {
                 };
                 self.mallopts.push( mallopt );
             },
-            Event::Environ { .. } => {
-                // TODO
+            Event::AllocatorStats { timestamp, heap_size, heap_used, heap_free, mmaped, resident } => {
+                let timestamp = self.shift_timestamp( timestamp );
+                self.allocator_stats.push( AllocatorStats {
+                    timestamp,
+                    heap_size,
+                    heap_used,
+                    heap_free,
+                    mmaped,
+                    resident,
+                    resident_breakdown: None,
+                    tracked_size: self.total_allocated - self.total_freed,
+                    tracked_count: self.total_allocated_count - self.total_freed_count
+                });
+            },
+            Event::ResidentMemory { timestamp, anonymous, file, shared } => {
+                // It's always written right after the allocator statistics which were sampled at the same time.
+                let timestamp = self.shift_timestamp( timestamp );
+                if let Some( stats ) = self.allocator_stats.last_mut() {
+                    if stats.timestamp == timestamp {
+                        stats.resident_breakdown = Some( ResidentMemory { anonymous, file, shared } );
+                    }
+                }
+            },
+            Event::AllocatorInfo { allocator, allocator_version, libc_version, tunables } => {
+                self.allocator_info = Some( AllocatorInfo {
+                    allocator: allocator.into_owned(),
+                    allocator_version: allocator_version.into_owned(),
+                    libc_version: libc_version.into_owned(),
+                    tunables
+                });
+            },
+            Event::AllocationContents { id, pointer, contents } => {
+                if let Some( &allocation ) = self.allocation_map.get( &into_key( id, pointer ) ) {
+                    self.allocation_contents.push( AllocationContents {
+                        allocation,
+                        contents: contents.into_owned()
+                    });
+                }
+            },
+            Event::Environ { entry } => {
+                if let Some( entry ) = parse_metadata( &entry ) {
+                    self.metadata.push( entry );
+                }
             },
             Event::WallClock { timestamp, sec, nsec } => {
                 self.update_timestamp_to_wall_clock( timestamp, sec, nsec );
@@ -953,6 +1591,10 @@ impl Loader {
                 }
             },
             Event::GroupStatistics { backtrace, first_allocation, last_allocation, free_count, free_size, min_size, max_size } => {
+                if !self.is_in_shard( backtrace ) {
+                    return;
+                }
+
                 let first_allocation = self.shift_timestamp( first_allocation );
                 let last_allocation = self.shift_timestamp( last_allocation );
                 let backtrace = self.lookup_backtrace( backtrace ).unwrap();
@@ -969,14 +1611,66 @@ impl Loader {
         }
     }
 
+    /// Builds the data out of everything which was loaded so far without consuming the loader.
+    ///
+    /// Only what was loaded since the last snapshot has to be sorted, so taking snapshots
+    /// periodically doesn't get slower and slower as the data grows.
+    pub fn snapshot( &self ) -> Data {
+        let mut indices = self.snapshot_indices.borrow_mut();
+        let mut operations = SpillVec::with_capacity( self.operations.len() - indices.operation_count );
+        for &operation in &self.operations[ indices.operation_count.. ] {
+            operations.push( operation );
+        }
+
+        let parts = DataParts {
+            interner: self.interner.borrow().clone(),
+            operations,
+            allocations: self.allocations.clone(),
+            allocations_by_backtrace: self.allocations_by_backtrace.clone(),
+            frames: self.frames.clone(),
+            backtraces: self.backtraces.clone(),
+            backtrace_trie: self.backtrace_trie.clone(),
+            group_stats: self.group_stats.clone(),
+            mallopts: self.mallopts.clone(),
+            allocator_stats: self.allocator_stats.clone(),
+            allocation_contents: self.allocation_contents.clone(),
+            library_events: self.library_events.clone(),
+            mmap_operations: self.mmap_operations.clone()
+        };
+
+        let data = self.build_data( parts, Some( &mut *indices ) );
+        indices.operation_count = self.operations.len();
+        data
+    }
+
     pub fn finalize( mut self ) -> Data {
+        let parts = DataParts {
+            interner: mem::take( self.interner.get_mut() ),
+            operations: mem::take( &mut self.operations ),
+            allocations: mem::take( &mut self.allocations ),
+            allocations_by_backtrace: mem::take( &mut self.allocations_by_backtrace ),
+            frames: mem::take( &mut self.frames ),
+            backtraces: mem::take( &mut self.backtraces ),
+            backtrace_trie: mem::take( &mut self.backtrace_trie ),
+            group_stats: mem::take( &mut self.group_stats ),
+            mallopts: mem::take( &mut self.mallopts ),
+            allocator_stats: mem::take( &mut self.allocator_stats ),
+            allocation_contents: mem::take( &mut self.allocation_contents ),
+            library_events: mem::take( &mut self.library_events ),
+            mmap_operations: mem::take( &mut self.mmap_operations )
+        };
+
+        self.build_data( parts, None )
+    }
+
+    /// Builds the data; if the `indices` from the previous snapshot are given then the `parts` are expected
+    /// to contain only the operations since then, and the `indices` are updated for the next snapshot.
+    fn build_data( &self, mut parts: DataParts, mut indices: Option< &mut SortedIndices > ) -> Data {
         let initial_timestamp = self.shift_timestamp( self.header.initial_timestamp );
-        let indices: Vec< AllocationId > = (0..self.allocations.len()).into_iter().map( |id| AllocationId::new( id as _ ) ).collect();
 
-        for (raw_backtrace_id, stats) in self.group_stats.iter().enumerate() {
-            let (backtrace_offset, backtrace_len) = self.backtraces[ raw_backtrace_id ];
-            for &frame_id in &self.backtraces_storage[ backtrace_offset as usize..(backtrace_offset + backtrace_len) as usize ] {
-                self.frames[ frame_id ].increment_count( stats.alloc_count );
+        for (raw_backtrace_id, stats) in parts.group_stats.iter().enumerate() {
+            for frame_id in parts.backtrace_trie.get( parts.backtraces[ raw_backtrace_id ] ) {
+                parts.frames[ frame_id ].increment_count( stats.alloc_count );
             }
         }
 
@@ -988,65 +1682,235 @@ impl Loader {
             )
         }
 
-        let mut sorted_by_timestamp = indices.clone();
-        sorted_by_timestamp.sort_by( |&a_id, &b_id| cmp_by_time( &self.allocations, a_id, b_id ) );
+        let maps = RangeMap::from_vec( self.maps.values().map( |region| {
+            let map_region = MapRegion {
+                is_readable: region.is_read,
+                is_writable: region.is_write,
+                is_executable: region.is_executable,
+                is_shared: region.is_shared,
+                file_offset: region.file_offset,
+                name: region.name.clone()
+            };
 
-        let mut sorted_by_address = indices.clone();
-        sorted_by_address.sort_by_key( |index| self.allocations[ index.raw() as usize ].pointer );
+            (region.start..region.end, map_region)
+        }).collect() );
+
+        parts.allocations.shrink_to_fit();
+        parts.frames.shrink_to_fit();
+        parts.backtraces.shrink_to_fit();
+        parts.backtrace_trie.shrink_to_fit();
+        parts.mallopts.shrink_to_fit();
+        parts.allocator_stats.shrink_to_fit();
+        parts.allocation_contents.sort_by_key( |entry| entry.allocation );
+        parts.allocation_contents.shrink_to_fit();
+        parts.library_events.shrink_to_fit();
+        parts.mmap_operations.shrink_to_fit();
+        parts.group_stats.shrink_to_fit();
+
+        let last_timestamp = parts.group_stats.iter().map( |stats| stats.last_allocation ).max().unwrap_or( initial_timestamp );
+
+        // Every index only needs read access to the allocations, so they're all built at the same time.
+        let empty = SortedIndices::default();
+        let previous = indices.as_ref().map( |indices| &**indices ).unwrap_or( &empty );
+        let mut raw_operations = mem::take( &mut parts.operations );
+        let raw_allocations_by_backtrace = mem::take( &mut parts.allocations_by_backtrace );
+        let allocations = &parts.allocations;
+        let allocation_count = allocations.len();
+        let (sorted_by_timestamp, sorted_by_address, sorted_by_size, sorted_operations, operations, peak_allocated, peak_allocated_timestamp, allocations_by_backtrace) = crossbeam_utils::thread::scope( |scope| {
+            let sorted_by_timestamp = scope.spawn( |_| {
+                extend_index( &previous.sorted_by_timestamp, allocation_count, |id| (allocations[ id.raw() as usize ].timestamp, id) )
+            });
+
+            let sorted_by_address = scope.spawn( |_| {
+                extend_index( &previous.sorted_by_address, allocation_count, |id| (allocations[ id.raw() as usize ].pointer, id) )
+            });
+
+            let sorted_by_size = scope.spawn( |_| {
+                extend_index( &previous.sorted_by_size, allocation_count, |id| (allocations[ id.raw() as usize ].size, id) )
+            });
+
+            let allocations_by_backtrace = scope.spawn( move |_| {
+                let mut allocations_by_backtrace = DenseVecVec::new();
+                let mut index: Vec< _ > = raw_allocations_by_backtrace.into_iter().collect();
+                index.sort_by_key( |&(k, _)| k );
+
+                for (backtrace_id, mut allocation_ids) in index {
+                    allocation_ids.sort_by( |&a_id, &b_id| cmp_by_time( allocations, a_id, b_id ) );
+                    let index = allocations_by_backtrace.push( allocation_ids );
+                    assert_eq!( index, backtrace_id.raw() as _ );
+                }
 
-        let mut sorted_by_size = indices;
-        sorted_by_size.sort_by_key( |index| self.allocations[ index.raw() as usize ].size );
+                allocations_by_backtrace.shrink_to_fit();
+                allocations_by_backtrace
+            });
 
-        self.operations.sort_by_key( |(timestamp, _)| *timestamp );
-        let operations: Vec< _ > = self.operations.into_iter().map( |(_, op)| op ).collect();
+            raw_operations.sort_by_key( |(timestamp, _)| *timestamp );
+            let sorted_operations = merge_sorted( &previous.operations, raw_operations, |&(timestamp, _)| timestamp );
+            let mut operations = SpillVec::with_capacity( sorted_operations.len() );
+            for &(_, op) in sorted_operations.iter() {
+                operations.push( op );
+            }
 
-        self.allocations.shrink_to_fit();
-        self.frames.shrink_to_fit();
-        self.backtraces.shrink_to_fit();
-        self.backtraces_storage.shrink_to_fit();
-        self.mallopts.shrink_to_fit();
-        self.mmap_operations.shrink_to_fit();
-        self.group_stats.shrink_to_fit();
+            let mut current_allocated = 0;
+            let mut peak_allocated = 0;
+            let mut peak_allocated_timestamp = initial_timestamp;
+            for op in operations.iter() {
+                let allocation = &allocations[ op.id().raw() as usize ];
+                if op.is_allocation() {
+                    current_allocated += allocation.size;
+                } else if op.is_reallocation() {
+                    let old_allocation = &allocations[ allocation.reallocated_from.unwrap().raw() as usize ];
+                    current_allocated -= old_allocation.size;
+                    current_allocated += allocation.size;
+                } else {
+                    current_allocated -= allocation.size;
+                }
+
+                if current_allocated > peak_allocated {
+                    peak_allocated = current_allocated;
+                    peak_allocated_timestamp = if op.is_deallocation() {
+                        allocation.deallocation.as_ref().unwrap().timestamp
+                    } else {
+                        allocation.timestamp
+                    };
+                }
+            }
 
-        let mut allocations_by_backtrace = DenseVecVec::new();
-        let mut index: Vec< _ > = self.allocations_by_backtrace.into_iter().collect();
-        index.sort_by_key( |&(k, _)| k );
+            (
+                sorted_by_timestamp.join().unwrap(),
+                sorted_by_address.join().unwrap(),
+                sorted_by_size.join().unwrap(),
+                sorted_operations,
+                operations,
+                peak_allocated,
+                peak_allocated_timestamp,
+                allocations_by_backtrace.join().unwrap()
+            )
+        }).unwrap();
 
-        let allocations = &self.allocations;
-        for (backtrace_id, mut allocation_ids) in index {
-            allocation_ids.sort_by( |&a_id, &b_id| cmp_by_time( allocations, a_id, b_id ) );
-            let index = allocations_by_backtrace.push( allocation_ids );
-            assert_eq!( index, backtrace_id.raw() as _ );
+        if let Some( ref mut indices ) = indices {
+            indices.operations = sorted_operations;
+            indices.sorted_by_timestamp = sorted_by_timestamp.clone();
+            indices.sorted_by_address = sorted_by_address.clone();
+            indices.sorted_by_size = sorted_by_size.clone();
         }
 
-        allocations_by_backtrace.shrink_to_fit();
+        let build_id = self.binaries.get( &*String::from_utf8_lossy( &self.header.executable ) )
+            .and_then( |binary_data| binary_data.build_id() )
+            .map( |build_id| build_id.iter().map( |byte| format!( "{:02x}", byte ) ).collect() );
+
+        let mut library_build_ids: Vec< _ > = self.library_build_ids().into_iter().collect();
+        library_build_ids.sort();
 
-        let last_timestamp = self.group_stats.iter().map( |stats| stats.last_allocation ).max().unwrap_or( initial_timestamp );
         Data {
             id: self.id,
             initial_timestamp,
             last_timestamp,
             executable: String::from_utf8_lossy( &self.header.executable ).into_owned(),
-            architecture: self.header.arch,
+            pid: self.header.pid,
+            architecture: self.header.arch.clone(),
             pointer_size: self.header.pointer_size as _,
-            interner: self.interner.into_inner(),
-            allocations: self.allocations,
+            interner: parts.interner,
+            allocations: parts.allocations,
             sorted_by_timestamp,
             sorted_by_address,
             sorted_by_size,
             operations,
-            frames: self.frames,
-            backtraces: self.backtraces,
-            backtraces_storage: self.backtraces_storage,
+            frames: parts.frames,
+            backtraces: parts.backtraces,
+            backtrace_trie: parts.backtrace_trie,
             allocations_by_backtrace,
             total_allocated: self.total_allocated,
             total_allocated_count: self.total_allocated_count,
             total_freed: self.total_freed,
             total_freed_count: self.total_freed_count,
-            mallopts: self.mallopts,
-            mmap_operations: self.mmap_operations,
+            peak_allocated,
+            peak_allocated_timestamp,
+            unknown_deallocation_count: self.unknown_deallocation_count,
+            unknown_deallocation_range: self.unknown_deallocation_range,
+            duplicate_allocation_count: self.duplicate_allocation_count,
+            lost_chunk_count: self.lost_chunk_count,
+            lost_event_count: self.lost_event_count,
+            wall_clock_start: self.wall_clock_start,
+            build_id,
+            library_build_ids,
+            mallopts: parts.mallopts,
+            allocator_stats: parts.allocator_stats,
+            allocator_info: self.allocator_info.clone(),
+            metadata: self.metadata.clone(),
+            allocation_contents: parts.allocation_contents,
+            library_events: parts.library_events,
+            maps,
+            mmap_operations: parts.mmap_operations,
             maximum_backtrace_depth: self.maximum_backtrace_depth,
-            group_stats: self.group_stats
+            group_stats: parts.group_stats
         }
     }
 }
+
+#[test]
+fn test_incremental_snapshot() {
+    use crate::importer::{ImportWriter, ImportedFrame};
+    use memory_profiler_capture::raw::parse_events;
+
+    let mut input = Vec::new();
+    let mut writer = ImportWriter::new( &mut input, &[ "./a.out" ], Timestamp::from_secs( 1 ) ).unwrap();
+    let backtrace = writer.backtrace( &[ ImportedFrame { address: 0x10, .. ImportedFrame::default() } ] ).unwrap();
+    for nth in 0..20 {
+        writer.allocate( Timestamp::from_secs( 1 + nth ), 0x1000 * (20 - nth), 10 + (nth * 7) % 13, backtrace ).unwrap();
+        if nth % 3 == 2 {
+            writer.deallocate( Timestamp::from_secs( 1 + nth ), 0x1000 * (22 - nth) ).unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    let (header, events) = parse_events( io::Cursor::new( input ) ).unwrap();
+    let mut loader = Loader::new( header, &SymbolSources::default() );
+    for (nth, event) in events.enumerate() {
+        loader.process( event.unwrap() );
+        if nth % 5 == 0 {
+            loader.snapshot();
+        }
+    }
+
+    let snapshot = loader.snapshot();
+    let data = loader.finalize();
+    assert_eq!( data.allocations.len(), 20 );
+    assert_eq!( &snapshot.operations[..], &data.operations[..] );
+    assert_eq!( &snapshot.sorted_by_timestamp[..], &data.sorted_by_timestamp[..] );
+    assert_eq!( &snapshot.sorted_by_address[..], &data.sorted_by_address[..] );
+    assert_eq!( &snapshot.sorted_by_size[..], &data.sorted_by_size[..] );
+    assert_eq!( snapshot.peak_allocated, data.peak_allocated );
+}
+
+#[test]
+fn test_load_gzipped_heaptrack() {
+    use std::io::Write;
+
+    let input = "\
+        v 10100 2\n\
+        X ./a.out\n\
+        s main\n\
+        i 1000 0 1\n\
+        t 1 0\n\
+        a 10 1\n\
+        + 0\n\
+        + 0\n\
+        - 0\n\
+    ";
+
+    let mut compressed = flate2::write::GzEncoder::new( Vec::new(), flate2::Compression::default() );
+    compressed.write_all( input.as_bytes() ).unwrap();
+
+    let directory = std::env::temp_dir().join( format!( "memory-profiler-test-heaptrack-{}", std::process::id() ) );
+    std::fs::create_dir_all( &directory ).unwrap();
+    let path = directory.join( "heaptrack.a.out.1234.gz" );
+    std::fs::write( &path, compressed.finish().unwrap() ).unwrap();
+
+    let data = Loader::load_from_file( &path, &SymbolSources::default() ).unwrap();
+    let _ = std::fs::remove_dir_all( &directory );
+
+    assert_eq!( data.executable(), "./a.out" );
+    assert_eq!( data.allocations().len(), 2 );
+    assert_eq!( data.allocations().iter().filter( |allocation| allocation.was_deallocated() ).count(), 1 );
+}
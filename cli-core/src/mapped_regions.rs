@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+
+use crate::data::{BacktraceId, Data, MapFlags, MmapOperation, ProtectionFlags, ThreadId, Timestamp};
+
+/// The kernel always maps whole pages, so the lengths are rounded up to this.
+const PAGE_SIZE: u64 = 4096;
+
+fn page_align( length: u64 ) -> u64 {
+    length.saturating_add( PAGE_SIZE - 1 ) & !(PAGE_SIZE - 1)
+}
+
+/// A region of memory which was mapped and wasn't unmapped yet.
+#[derive(Clone, Debug)]
+pub struct MappedRegion {
+    pub address: u64,
+    pub length: u64,
+    /// When the region was originally mapped.
+    pub timestamp: Timestamp,
+    /// The backtrace of the `mmap` which created the region.
+    pub backtrace: BacktraceId,
+    pub thread: ThreadId,
+    /// `None` when the region was mapped before the profiling started and only
+    /// became known through a `mremap`; the same goes for the `flags` and the `file_descriptor`.
+    pub protection: Option< ProtectionFlags >,
+    pub flags: Option< MapFlags >,
+    pub file_descriptor: Option< u32 >,
+    pub offset: u64,
+    /// Whether the region was moved or resized with `mremap` since it was mapped.
+    pub was_remapped: bool
+}
+
+impl MappedRegion {
+    pub fn end( &self ) -> u64 {
+        self.address + self.length
+    }
+}
+
+/// The regions which were mapped at a given point in time, sorted by their address.
+///
+/// Only the operations done after the profiling was started are known, so anything
+/// which was mapped before that (e.g. the executable itself) won't be here.
+#[derive(Default)]
+pub struct MappedRegions {
+    regions: BTreeMap< u64, MappedRegion >,
+    total_length: u64
+}
+
+impl MappedRegions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replays every mmap operation up to (and including) the given timestamp.
+    pub fn at( data: &Data, timestamp: Timestamp ) -> Self {
+        let mut regions = Self::new();
+        for op in data.mmap_operations() {
+            if op.timestamp() <= timestamp {
+                regions.apply( op );
+            }
+        }
+
+        regions
+    }
+
+    pub fn len( &self ) -> usize {
+        self.regions.len()
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// The total size of all of the regions, in bytes.
+    pub fn total_length( &self ) -> u64 {
+        self.total_length
+    }
+
+    pub fn iter( &self ) -> impl Iterator< Item = &MappedRegion > {
+        self.regions.values()
+    }
+
+    /// Finds the region which contains the given address.
+    pub fn find( &self, address: u64 ) -> Option< &MappedRegion > {
+        self.regions.range( ..=address ).next_back().map( |(_, region)| region ).filter( |region| address < region.end() )
+    }
+
+    pub fn apply( &mut self, op: &MmapOperation ) {
+        match *op {
+            MmapOperation::Mmap( ref op ) => {
+                let region = MappedRegion {
+                    address: op.pointer,
+                    length: page_align( op.length ),
+                    timestamp: op.timestamp,
+                    backtrace: op.backtrace,
+                    thread: op.thread,
+                    protection: Some( op.mmap_protection ),
+                    flags: Some( op.mmap_flags ),
+                    file_descriptor: Some( op.file_descriptor ),
+                    offset: op.offset,
+                    was_remapped: false
+                };
+
+                // With `MAP_FIXED` the new mapping silently replaces whatever was there.
+                self.remove_range( region.address, region.length );
+                self.insert( region );
+            },
+            MmapOperation::Munmap( ref op ) => {
+                self.remove_range( op.pointer, page_align( op.length ) );
+            },
+            MmapOperation::Mremap( ref op ) => {
+                // An `old_length` of zero creates a second mapping of the same pages without touching the old one.
+                let old_region = if op.old_length == 0 {
+                    self.find( op.old_pointer ).cloned()
+                } else {
+                    self.remove_range( op.old_pointer, page_align( op.old_length ) )
+                };
+
+                let length = page_align( op.length );
+                self.remove_range( op.pointer, length );
+
+                let region = match old_region {
+                    Some( old_region ) => MappedRegion {
+                        address: op.pointer,
+                        length,
+                        offset: old_region.offset + op.old_pointer.saturating_sub( old_region.address ),
+                        was_remapped: true,
+                        .. old_region
+                    },
+                    None => MappedRegion {
+                        address: op.pointer,
+                        length,
+                        timestamp: op.timestamp,
+                        backtrace: op.backtrace,
+                        thread: op.thread,
+                        protection: None,
+                        flags: None,
+                        file_descriptor: None,
+                        offset: 0,
+                        was_remapped: true
+                    }
+                };
+
+                self.insert( region );
+            }
+        }
+    }
+
+    fn insert( &mut self, region: MappedRegion ) {
+        if region.length == 0 {
+            return;
+        }
+
+        self.total_length += region.length;
+        self.regions.insert( region.address, region );
+    }
+
+    /// Unmaps the given range, splitting any region which is only partially covered by it,
+    /// and returns the lowest of the regions which were affected (as it was before the split).
+    fn remove_range( &mut self, address: u64, length: u64 ) -> Option< MappedRegion > {
+        let end = address.saturating_add( length );
+        let overlapping: Vec< u64 > = self.regions.range( ..end ).rev()
+            .take_while( |(_, region)| region.end() > address )
+            .map( |(&start, _)| start )
+            .collect();
+
+        let mut lowest = None;
+        for start in overlapping {
+            let region = self.regions.remove( &start ).unwrap();
+            self.total_length -= region.length;
+
+            if region.address < address {
+                self.insert( MappedRegion {
+                    length: address - region.address,
+                    .. region.clone()
+                });
+            }
+
+            if region.end() > end {
+                self.insert( MappedRegion {
+                    address: end,
+                    length: region.end() - end,
+                    offset: region.offset + (end - region.address),
+                    .. region.clone()
+                });
+            }
+
+            lowest = Some( region );
+        }
+
+        lowest
+    }
+}
+
+#[test]
+fn test_mapped_regions() {
+    use crate::data::{MemoryMap, MemoryRemap, MemoryUnmap};
+
+    let mmap = |pointer, length| MmapOperation::Mmap( MemoryMap {
+        timestamp: Timestamp::from_secs( 1 ),
+        pointer,
+        length,
+        backtrace: BacktraceId::new( 1 ),
+        requested_address: 0,
+        mmap_protection: ProtectionFlags( 0x3 ),
+        mmap_flags: MapFlags( 0x22 ),
+        file_descriptor: !0,
+        thread: 1,
+        offset: 0
+    });
+
+    let munmap = |pointer, length| MmapOperation::Munmap( MemoryUnmap {
+        timestamp: Timestamp::from_secs( 2 ),
+        pointer,
+        length,
+        backtrace: BacktraceId::new( 2 ),
+        thread: 1
+    });
+
+    let mremap = |old_pointer, old_length, pointer, length| MmapOperation::Mremap( MemoryRemap {
+        timestamp: Timestamp::from_secs( 3 ),
+        old_pointer,
+        old_length,
+        pointer,
+        length,
+        flags: 1,
+        backtrace: BacktraceId::new( 3 ),
+        thread: 1
+    });
+
+    let ranges = |regions: &MappedRegions| -> Vec< (u64, u64, u32) > {
+        regions.iter().map( |region| (region.address, region.length, region.backtrace.raw()) ).collect()
+    };
+
+    let mut regions = MappedRegions::new();
+    regions.apply( &mmap( 0x10000, 0x4000 ) );
+    regions.apply( &mmap( 0x20000, 100 ) );
+    assert_eq!( ranges( &regions ), vec![ (0x10000, 0x4000, 1), (0x20000, 0x1000, 1) ] );
+    assert_eq!( regions.total_length(), 0x5000 );
+
+    // Punching a hole in the middle splits the region in two.
+    regions.apply( &munmap( 0x11000, 0x1000 ) );
+    assert_eq!( ranges( &regions ), vec![ (0x10000, 0x1000, 1), (0x12000, 0x2000, 1), (0x20000, 0x1000, 1) ] );
+    assert_eq!( regions.find( 0x12000 ).unwrap().offset, 0x2000 );
+    assert!( regions.find( 0x11000 ).is_none() );
+
+    // Moving a region keeps where it was originally mapped from.
+    regions.apply( &mremap( 0x20000, 0x1000, 0x30000, 0x3000 ) );
+    assert_eq!( ranges( &regions ), vec![ (0x10000, 0x1000, 1), (0x12000, 0x2000, 1), (0x30000, 0x3000, 1) ] );
+    assert!( regions.find( 0x30000 ).unwrap().was_remapped );
+
+    // A region which we've never seen being mapped gets attributed to the `mremap`.
+    regions.apply( &mremap( 0x40000, 0x1000, 0x40000, 0x2000 ) );
+    assert_eq!( regions.find( 0x41000 ).unwrap().backtrace.raw(), 3 );
+    assert!( regions.find( 0x41000 ).unwrap().protection.is_none() );
+
+    regions.apply( &munmap( 0x0, 0x100000 ) );
+    assert!( regions.is_empty() );
+    assert_eq!( regions.total_length(), 0 );
+}
@@ -1,13 +1,9 @@
 use std::io::{self, Read, Write};
-use std::ffi::OsStr;
 use std::u64;
 
 use ahash::AHashSet as HashSet;
 use string_interner::Symbol;
 
-use nwind::{
-    DebugInfoIndex
-};
 use common::speedy::{
     Writable,
 };
@@ -21,24 +17,19 @@ use common::lz4_stream::{
     Lz4Writer
 };
 
+use memory_profiler_capture::raw::parse_events;
+
 use crate::loader::Loader;
-use crate::reader::parse_events;
+use crate::symbol_sources::SymbolSources;
 
-pub fn postprocess< F, G, D, I  >( ifp: F, ofp: G, debug_symbols: I ) -> Result< (), io::Error >
+pub fn postprocess< F, G >( ifp: F, ofp: G, symbol_sources: &SymbolSources ) -> Result< (), io::Error >
     where F: Read + Send + 'static,
-          G: Write,
-          D: AsRef< OsStr >,
-          I: IntoIterator< Item = D >
+          G: Write
 {
     let mut ofp = Lz4Writer::new( ofp );
     let (header, event_stream) = parse_events( ifp )?;
 
-    let mut debug_info_index = DebugInfoIndex::new();
-    for path in debug_symbols {
-        debug_info_index.add( path.as_ref() );
-    }
-
-    let mut loader = Loader::new( header.clone(), debug_info_index );
+    let mut loader = Loader::new( header.clone(), symbol_sources );
     Event::Header( header ).write_to_stream( &mut ofp )?;
 
     let mut frames = Vec::new();
@@ -70,6 +61,7 @@ pub fn postprocess< F, G, D, I  >( ifp: F, ofp: G, debug_symbols: I ) -> Result<
             Event::FreeEx { ref mut backtrace, .. } |
             Event::MemoryMap { ref mut backtrace, .. } |
             Event::MemoryUnmap { ref mut backtrace, .. } |
+            Event::MemoryRemap { ref mut backtrace, .. } |
             Event::Mallopt { ref mut backtrace, .. } |
             Event::GroupStatistics { ref mut backtrace, .. } => {
                 if let Some( target_backtrace ) = loader.lookup_backtrace( *backtrace ) {
@@ -90,11 +82,19 @@ pub fn postprocess< F, G, D, I  >( ifp: F, ofp: G, debug_symbols: I ) -> Result<
             Event::Header { .. } => {},
             Event::MemoryDump { .. } => {},
             Event::Marker { .. } => {},
+            Event::AllocatorStats { .. } => {},
+            Event::ResidentMemory { .. } => {},
             Event::Environ { .. } => {},
             Event::WallClock { .. } => {},
             Event::String { .. } => {},
             Event::DecodedFrame { .. } => {},
-            Event::DecodedBacktrace { .. } => {}
+            Event::DecodedBacktrace { .. } => {},
+            Event::SymbolTable { .. } => {
+                process = true;
+                write = false;
+            },
+            Event::AllocatorInfo { .. } => {},
+            Event::AllocationContents { .. } => {}
         }
 
         if write {
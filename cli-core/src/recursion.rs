@@ -0,0 +1,39 @@
+use std::hash::Hash;
+
+use ahash::AHashMap as HashMap;
+
+/// Collapses every recursive cycle of a backtrace given from its outermost frame, so that
+/// whenever a frame with the same key as one of its callers appears again everything since
+/// that caller is dropped and the caller stands in for the whole cycle.
+pub fn collapse_recursion< T, K, I, F >( frames: I, key: F ) -> Vec< T >
+    where I: IntoIterator< Item = T >,
+          K: Eq + Hash,
+          F: Fn( &T ) -> K
+{
+    let mut output: Vec< T > = Vec::new();
+    let mut index_by_key: HashMap< K, usize > = HashMap::new();
+    for frame in frames {
+        let frame_key = key( &frame );
+        if let Some( &index ) = index_by_key.get( &frame_key ) {
+            for dropped in output.drain( index + 1.. ) {
+                index_by_key.remove( &key( &dropped ) );
+            }
+            continue;
+        }
+
+        index_by_key.insert( frame_key, output.len() );
+        output.push( frame );
+    }
+
+    output
+}
+
+#[test]
+fn test_collapse_recursion() {
+    let collapse = |frames: &[u32]| collapse_recursion( frames.iter().copied(), |&frame| frame );
+    assert_eq!( collapse( &[1, 2, 3] ), vec![ 1, 2, 3 ] );
+    assert_eq!( collapse( &[1, 2, 2, 2, 3] ), vec![ 1, 2, 3 ] );
+    assert_eq!( collapse( &[1, 2, 3, 2, 3, 2, 3, 4] ), vec![ 1, 2, 3, 4 ] );
+    assert_eq!( collapse( &[1, 2, 3, 4, 2, 5] ), vec![ 1, 2, 5 ] );
+    assert_eq!( collapse( &[] ), Vec::< u32 >::new() );
+}
@@ -6,8 +6,9 @@ use common::speedy::{
 
 use common::event::Event;
 use common::lz4_stream::Lz4Writer;
+use common::chunked_stream::ChunkedWriter;
 
-use crate::reader::parse_events;
+use memory_profiler_capture::raw::parse_events;
 
 pub fn repack< F, G >( disable_compression: bool, input_fp: F, output_fp: G ) -> Result< (), io::Error >
     where F: Read + Send + 'static,
@@ -28,4 +29,23 @@ pub fn repack< F, G >( disable_compression: bool, input_fp: F, output_fp: G ) ->
     output_fp.flush()?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Rewrites a data file of any version into the second, seekable version of the format.
+pub fn repack_v2< F, G >( input_fp: F, output_fp: G ) -> Result< (), io::Error >
+    where F: Read + Send + 'static,
+          G: Write
+{
+    let (header, event_stream) = parse_events( input_fp )?;
+    let mut output_fp = ChunkedWriter::new( output_fp )?;
+
+    output_fp.write_event( &Event::Header( header ) )?;
+    for event in event_stream {
+        let event = event?;
+        output_fp.write_event( &event )?;
+    }
+
+    output_fp.finish()?;
+
+    Ok(())
+}
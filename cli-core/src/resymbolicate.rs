@@ -0,0 +1,119 @@
+use std::ffi::OsStr;
+
+use ahash::AHashMap as HashMap;
+use nwind::DebugInfoIndex;
+use nwind::proc_maps::Region;
+
+use crate::backtrace_trie::{BacktraceTrie, FrameIds};
+use crate::data::{Data, FrameId};
+use crate::frame::Frame;
+use crate::loader::{address_to_frame, get_basename, new_address_space};
+
+impl Data {
+    /// Tries to resolve the frames which are missing symbols using extra debug symbols,
+    /// without reloading the whole data file; returns how many frames were resolved.
+    ///
+    /// The frames are looked up in the last memory map snapshot seen in the data,
+    /// so only the libraries which were still loaded at the end can be resolved.
+    pub fn resymbolicate< D: AsRef< OsStr >, I: IntoIterator< Item = D > >( &mut self, debug_symbols: I ) -> usize {
+        let mut debug_info_index = DebugInfoIndex::new();
+        for path in debug_symbols {
+            debug_info_index.add( path.as_ref() );
+        }
+
+        let mut regions = Vec::with_capacity( self.maps.len() );
+        for index in 0..self.maps.len() {
+            let (range, region) = self.maps.get_by_index( index ).unwrap();
+            regions.push( Region {
+                start: range.start,
+                end: range.end,
+                is_read: region.is_readable,
+                is_write: region.is_writable,
+                is_executable: region.is_executable,
+                is_shared: region.is_shared,
+                file_offset: region.file_offset,
+                major: 0,
+                minor: 0,
+                inode: 0,
+                name: region.name.clone()
+            });
+        }
+
+        let mut address_space = new_address_space( &self.architecture );
+        address_space.reload( regions, &mut |region, handle| {
+            handle.should_load_frame_descriptions( false );
+            if let Some( debug_binary_data ) = debug_info_index.get( get_basename( &region.name ), None, None ) {
+                handle.set_debug_binary( debug_binary_data.clone() );
+            }
+        });
+
+        let mut frame_to_id: HashMap< Frame, FrameId > = HashMap::new();
+        for (frame_id, frame) in self.frames.iter().enumerate() {
+            let mut frame = frame.clone();
+            frame.reset_count();
+            frame_to_id.insert( frame, frame_id );
+        }
+
+        let mut replacements: HashMap< FrameId, FrameIds > = HashMap::new();
+        for frame_id in 0..self.frames.len() {
+            if self.frames[ frame_id ].any_function().is_some() {
+                continue;
+            }
+
+            let mut decoded = Vec::new();
+            address_to_frame( &*address_space, &mut self.interner, self.frames[ frame_id ].address().raw(), |frame| {
+                decoded.push( frame );
+            });
+
+            if !decoded.iter().any( |frame| frame.any_function().is_some() ) {
+                continue;
+            }
+
+            let frames = &mut self.frames;
+            let frame_ids = decoded.into_iter().map( |frame| {
+                *frame_to_id.entry( frame.clone() ).or_insert_with( || {
+                    frames.push( frame );
+                    frames.len() - 1
+                })
+            }).collect();
+
+            replacements.insert( frame_id, frame_ids );
+        }
+
+        if replacements.is_empty() {
+            return 0;
+        }
+
+        let mut backtrace_trie = BacktraceTrie::new();
+        let mut maximum_backtrace_depth = 0;
+        for backtrace in self.backtraces.iter_mut() {
+            let mut frame_ids = FrameIds::new();
+            for frame_id in self.backtrace_trie.get( *backtrace ) {
+                match replacements.get( &frame_id ) {
+                    Some( replacement ) => frame_ids.extend( replacement.iter().cloned() ),
+                    None => frame_ids.push( frame_id )
+                }
+            }
+
+            maximum_backtrace_depth = std::cmp::max( maximum_backtrace_depth, frame_ids.len() as u32 );
+            *backtrace = backtrace_trie.insert( frame_ids );
+        }
+
+        backtrace_trie.shrink_to_fit();
+        self.backtrace_trie = backtrace_trie;
+        self.maximum_backtrace_depth = maximum_backtrace_depth;
+
+        for frame in self.frames.iter_mut() {
+            frame.reset_count();
+        }
+
+        for (raw_backtrace_id, stats) in self.group_stats.iter().enumerate() {
+            for frame_id in self.backtrace_trie.get( self.backtraces[ raw_backtrace_id ] ) {
+                self.frames[ frame_id ].increment_count( stats.alloc_count );
+            }
+        }
+
+        info!( "Resolved {} previously unresolved frames", replacements.len() );
+        replacements.len()
+    }
+}
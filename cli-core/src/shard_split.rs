@@ -0,0 +1,161 @@
+use std::io::{self, Read, Write};
+
+use ahash::AHashMap as HashMap;
+
+use common::Timestamp;
+use common::speedy::Writable;
+use common::event::{
+    Event,
+    AllocBody,
+    AllocationId
+};
+
+use crate::loader::{Shard, into_key};
+use crate::threaded_lz4_stream::Lz4Writer;
+
+use memory_profiler_capture::raw::parse_events;
+
+/*
+    Every output gets all of the events which aren't about a particular allocation (the backtraces,
+    the maps, the symbols, etc.), while the allocations are only written to the output of their shard,
+    so that a worker only has to go through its own share of them.
+
+    The deallocations don't say which backtrace they belong to, so the shard of every live allocation
+    is kept in memory. A reallocation which moves an allocation to another shard is split into
+    a deallocation in the old shard and an allocation in the new one. Whatever the loader couldn't
+    match anyway goes to the first shard, which is the only one that counts the unknown deallocations.
+*/
+
+fn shard_of( backtrace: u64, count: usize ) -> usize {
+    (0..count).find( |&index| Shard { index: index as u32, count: count as u32 }.contains( backtrace ) ).unwrap()
+}
+
+/// Splits a capture into one capture per shard; loading the `n`th of them with `--shard <n>/<count>`
+/// gives the same data as loading that shard from the original capture.
+pub fn split_into_shards< F, G >( input_fp: F, outputs: Vec< G > ) -> Result< (), io::Error >
+    where F: Read + Send + 'static,
+          G: Write + Send + 'static
+{
+    assert!( !outputs.is_empty() );
+
+    let (header, event_stream) = parse_events( input_fp )?;
+    let mut outputs: Vec< _ > = outputs.into_iter().map( Lz4Writer::new ).collect();
+    for output in &mut outputs {
+        Event::Header( header.clone() ).write_to_stream( output )?;
+    }
+
+    let count = outputs.len();
+    let mut shard_by_allocation: HashMap< (u64, u64), usize > = HashMap::new();
+    for event in event_stream {
+        let event = event?;
+        match event {
+            Event::Alloc { ref allocation, .. } => {
+                let shard = shard_of( allocation.backtrace, count );
+                shard_by_allocation.insert( into_key( AllocationId::UNTRACKED, allocation.pointer ), shard );
+                event.write_to_stream( &mut outputs[ shard ] )?;
+            },
+            Event::AllocEx { id, ref allocation, .. } => {
+                let shard = shard_of( allocation.backtrace, count );
+                shard_by_allocation.insert( into_key( id, allocation.pointer ), shard );
+                event.write_to_stream( &mut outputs[ shard ] )?;
+            },
+            Event::Realloc { timestamp, old_pointer, allocation } => {
+                split_realloc( &mut outputs, &mut shard_by_allocation, AllocationId::UNTRACKED, timestamp, old_pointer, allocation )?;
+            },
+            Event::ReallocEx { id, timestamp, old_pointer, allocation } => {
+                split_realloc( &mut outputs, &mut shard_by_allocation, id, timestamp, old_pointer, allocation )?;
+            },
+            Event::Free { pointer, .. } => {
+                let shard = shard_by_allocation.remove( &into_key( AllocationId::UNTRACKED, pointer ) ).unwrap_or( 0 );
+                event.write_to_stream( &mut outputs[ shard ] )?;
+            },
+            Event::FreeEx { id, pointer, .. } => {
+                let shard = shard_by_allocation.remove( &into_key( id, pointer ) ).unwrap_or( 0 );
+                event.write_to_stream( &mut outputs[ shard ] )?;
+            },
+            Event::GroupStatistics { backtrace, .. } => {
+                event.write_to_stream( &mut outputs[ shard_of( backtrace, count ) ] )?;
+            },
+            event => {
+                for output in &mut outputs {
+                    event.write_to_stream( output )?;
+                }
+            }
+        }
+    }
+
+    for output in &mut outputs {
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+fn split_realloc< G: Write + Send + 'static >(
+    outputs: &mut [Lz4Writer< G >],
+    shard_by_allocation: &mut HashMap< (u64, u64), usize >,
+    id: AllocationId,
+    timestamp: Timestamp,
+    old_pointer: u64,
+    allocation: AllocBody
+) -> Result< (), io::Error > {
+    let new_shard = shard_of( allocation.backtrace, outputs.len() );
+    let old_shard = match shard_by_allocation.remove( &into_key( id, old_pointer ) ) {
+        Some( old_shard ) => old_shard,
+        None => {
+            // The loader drops the new allocation too in this case.
+            return Event::ReallocEx { id, timestamp, old_pointer, allocation }.write_to_stream( &mut outputs[ 0 ] );
+        }
+    };
+
+    shard_by_allocation.insert( into_key( id, allocation.pointer ), new_shard );
+    if old_shard == new_shard {
+        return Event::ReallocEx { id, timestamp, old_pointer, allocation }.write_to_stream( &mut outputs[ new_shard ] );
+    }
+
+    Event::FreeEx {
+        id,
+        timestamp,
+        pointer: old_pointer,
+        backtrace: allocation.backtrace,
+        thread: allocation.thread
+    }.write_to_stream( &mut outputs[ old_shard ] )?;
+
+    Event::AllocEx { id, timestamp, allocation }.write_to_stream( &mut outputs[ new_shard ] )
+}
+
+#[test]
+fn test_split_into_shards() {
+    use std::fs::{self, File};
+    use crate::importer::{ImportWriter, ImportedFrame};
+
+    let frame = |address| ImportedFrame { address, .. ImportedFrame::default() };
+    let mut input = Vec::new();
+    let mut writer = ImportWriter::new( &mut input, &[ "./a.out" ], Timestamp::from_secs( 1 ) ).unwrap();
+    let foo = writer.backtrace( &[ frame( 0x10 ) ] ).unwrap();
+    let bar = writer.backtrace( &[ frame( 0x20 ) ] ).unwrap();
+    writer.allocate( Timestamp::from_secs( 1 ), 0x1000, 10, foo ).unwrap();
+    writer.allocate( Timestamp::from_secs( 2 ), 0x2000, 20, bar ).unwrap();
+    writer.allocate( Timestamp::from_secs( 3 ), 0x3000, 30, bar ).unwrap();
+    writer.deallocate( Timestamp::from_secs( 4 ), 0x2000 ).unwrap();
+    writer.deallocate( Timestamp::from_secs( 4 ), 0x4000 ).unwrap();
+    writer.finish().unwrap();
+
+    let paths: Vec< _ > = (0..2).map( |index| std::env::temp_dir().join( format!( "memory-profiler-test-shard-{}-{}", std::process::id(), index ) ) ).collect();
+    let outputs = paths.iter().map( |path| File::create( path ) ).collect::< Result< Vec< _ >, _ > >().unwrap();
+    split_into_shards( io::Cursor::new( input ), outputs ).unwrap();
+
+    let shards: Vec< _ > = paths.iter().map( |path| crate::Loader::load_from_stream_without_debug_info( File::open( path ).unwrap() ).unwrap() ).collect();
+    for path in &paths {
+        let _ = fs::remove_file( path );
+    }
+
+    let sizes = |data: &crate::Data| -> Vec< u64 > { data.allocations().iter().map( |allocation| allocation.size ).collect() };
+    let (foo_shard, bar_shard) = (shard_of( foo, 2 ), shard_of( bar, 2 ));
+    assert_ne!( foo_shard, bar_shard );
+    assert_eq!( sizes( &shards[ foo_shard ] ), vec![ 10 ] );
+    assert_eq!( sizes( &shards[ bar_shard ] ), vec![ 20, 30 ] );
+    assert_eq!( shards[ bar_shard ].total_freed_count(), 1 );
+    assert_eq!( shards[ 0 ].unknown_deallocation_count(), 1 );
+    assert_eq!( shards[ 1 ].unknown_deallocation_count(), 0 );
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+
+use crate::data::{BacktraceId, Data, StringId};
+use crate::frame::Frame;
+
+/// Identifies an allocation site in a way which is stable across runs and builds.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct SiteId( u64 );
+
+impl SiteId {
+    pub fn raw( &self ) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for SiteId {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        write!( fmt, "{:016x}", self.0 )
+    }
+}
+
+/// A 64-bit FNV-1a hasher; unlike the default hashers its output doesn't change between processes.
+struct StableHasher( u64 );
+
+impl StableHasher {
+    fn new() -> Self {
+        StableHasher( 0xcbf2_9ce4_8422_2325 )
+    }
+
+    fn write( &mut self, bytes: &[u8] ) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul( 0x0000_0100_0000_01b3 );
+        }
+    }
+
+    fn write_u64( &mut self, value: u64 ) {
+        self.write( &value.to_le_bytes() );
+    }
+
+    fn write_str( &mut self, value: &str ) {
+        self.write_u64( value.len() as u64 );
+        self.write( value.as_bytes() );
+    }
+}
+
+fn file_name( path: &str ) -> &str {
+    path.rsplit( '/' ).next().unwrap_or( path )
+}
+
+fn resolve( data: &Data, id: Option< StringId > ) -> Option< &str > {
+    id.map( |id| data.interner().resolve( id ).unwrap() )
+}
+
+/// Hashes a frame; returns `false` if there was nothing stable in it to hash.
+fn hash_frame( data: &Data, frame: &Frame, hasher: &mut StableHasher ) -> bool {
+    // The directories in the source paths depend on where the program was built, so only the file names are used.
+    if let Some( function ) = resolve( data, frame.any_function() ) {
+        hasher.write( b"S" );
+        hasher.write_str( function );
+        hasher.write_str( resolve( data, frame.source() ).map( file_name ).unwrap_or( "" ) );
+        hasher.write_u64( frame.line().unwrap_or( 0 ) as u64 );
+        return true;
+    }
+
+    let address = frame.address().raw();
+    if let Some( (range, region) ) = data.get_map_region( address ) {
+        let relative_address = address - range.start + region.file_offset;
+        hasher.write( b"R" );
+        match data.get_library_build_id( &region.name ) {
+            Some( build_id ) => hasher.write( build_id ),
+            None => hasher.write_str( file_name( &region.name ) )
+        }
+        hasher.write_u64( relative_address );
+        return true;
+    }
+
+    // There's nothing stable that we could use.
+    hasher.write( b"A" );
+    hasher.write_u64( address );
+    false
+}
+
+fn hash_backtrace( data: &Data, backtrace_id: BacktraceId ) -> (SiteId, bool) {
+    let mut hasher = StableHasher::new();
+    let mut is_stable = true;
+    for (_, frame) in data.get_backtrace( backtrace_id ) {
+        is_stable &= hash_frame( data, frame, &mut hasher );
+    }
+
+    (SiteId( hasher.0 ), is_stable)
+}
+
+pub(crate) fn site_id( data: &Data, backtrace_id: BacktraceId ) -> SiteId {
+    hash_backtrace( data, backtrace_id ).0
+}
+
+/// Same as `site_id`, except it returns `None` if any of the frames had neither symbols
+/// nor a mapped library, in which case the ID would be different on every run.
+pub(crate) fn stable_site_id( data: &Data, backtrace_id: BacktraceId ) -> Option< SiteId > {
+    match hash_backtrace( data, backtrace_id ) {
+        (site_id, true) => Some( site_id ),
+        (_, false) => None
+    }
+}
+
+#[test]
+fn test_stable_hasher() {
+    let mut hasher = StableHasher::new();
+    hasher.write( b"a" );
+    assert_eq!( hasher.0, 0xaf63_dc4c_8601_ec8c );
+
+    assert_eq!( file_name( "/build/src/main.rs" ), "main.rs" );
+    assert_eq!( file_name( "main.rs" ), "main.rs" );
+}
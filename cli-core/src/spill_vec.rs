@@ -0,0 +1,369 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::process;
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use memmap::{Mmap, MmapMut};
+use speedy::{Readable, Writable, Context, Reader, Writer};
+
+static MEMORY_BUDGET: AtomicUsize = AtomicUsize::new( usize::MAX );
+static MEMORY_USED: AtomicUsize = AtomicUsize::new( 0 );
+static SPILL_FILE_COUNTER: AtomicUsize = AtomicUsize::new( 0 );
+
+/// A type whose values can be written out and mapped back as raw bytes.
+///
+/// # Safety
+///
+/// The type must have no padding and every bit pattern must be a valid value of it,
+/// e.g. a `#[repr(transparent)]` wrapper around an integer.
+pub unsafe trait Pod: Copy {}
+
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+
+/// Sets how many bytes the big per-allocation tables can take in memory before they're spilled to disk.
+pub fn set_memory_budget( bytes: u64 ) {
+    MEMORY_BUDGET.store( bytes as usize, Ordering::SeqCst );
+}
+
+struct Spilled {
+    fp: File,
+    mmap: MmapMut,
+    length: usize,
+    capacity: usize
+}
+
+/// A read-only view into a file which is shared with other vectors, e.g. a table of an index file.
+struct Mapped {
+    mmap: Arc< Mmap >,
+    offset: usize,
+    length: usize
+}
+
+enum Storage< T > {
+    Memory( Vec< T > ),
+    Spilled( Spilled ),
+    Mapped( Mapped )
+}
+
+/// A vector which moves its contents into a memory-mapped temporary file once
+/// the memory budget is exhausted, so that the kernel can page it out when needed.
+///
+/// It can also be mapped straight from a file, in which case its contents are copied
+/// only once it's modified.
+pub struct SpillVec< T: Copy > {
+    storage: Storage< T >
+}
+
+fn create_spill_file( size: usize ) -> io::Result< File > {
+    let counter = SPILL_FILE_COUNTER.fetch_add( 1, Ordering::SeqCst );
+    let path = env::temp_dir().join( format!( "memory-profiler-spill-{}-{}", process::id(), counter ) );
+    let fp = OpenOptions::new().read( true ).write( true ).create_new( true ).open( &path )?;
+
+    // The file will be deleted as soon as it's closed.
+    fs::remove_file( &path )?;
+    fp.set_len( size as u64 )?;
+    Ok( fp )
+}
+
+impl< T: Copy > SpillVec< T > {
+    pub fn new() -> Self {
+        SpillVec {
+            storage: Storage::Memory( Vec::new() )
+        }
+    }
+
+    pub fn with_capacity( capacity: usize ) -> Self {
+        let mut vec = Self::new();
+        vec.reserve( capacity );
+        vec
+    }
+
+    pub fn is_spilled( &self ) -> bool {
+        match self.storage {
+            Storage::Memory( _ ) => false,
+            Storage::Spilled( _ ) | Storage::Mapped( _ ) => true
+        }
+    }
+
+    /// Copies the contents of a mapped vector so that they can be modified.
+    fn unmap( &mut self ) {
+        if let Storage::Mapped( _ ) = self.storage {
+            let mut vec = Self::with_capacity( self.len() );
+            for &value in self.iter() {
+                vec.push( value );
+            }
+
+            *self = vec;
+        }
+    }
+
+    fn spill( &mut self, capacity: usize ) {
+        let fp = match create_spill_file( capacity * mem::size_of::< T >() ) {
+            Ok( fp ) => fp,
+            Err( error ) => {
+                warn!( "Failed to create a spill file: {}", error );
+                return;
+            }
+        };
+
+        let mut mmap = match unsafe { MmapMut::map_mut( &fp ) } {
+            Ok( mmap ) => mmap,
+            Err( error ) => {
+                warn!( "Failed to map a spill file: {}", error );
+                return;
+            }
+        };
+
+        let vec = match self.storage {
+            Storage::Memory( ref mut vec ) => mem::replace( vec, Vec::new() ),
+            Storage::Spilled( _ ) | Storage::Mapped( _ ) => unreachable!()
+        };
+
+        unsafe {
+            ptr::copy_nonoverlapping( vec.as_ptr(), mmap.as_mut_ptr() as *mut T, vec.len() );
+        }
+
+        debug!( "Spilled {} elements into a temporary file", vec.len() );
+        MEMORY_USED.fetch_sub( vec.capacity() * mem::size_of::< T >(), Ordering::SeqCst );
+        self.storage = Storage::Spilled( Spilled {
+            fp,
+            mmap,
+            length: vec.len(),
+            capacity
+        });
+    }
+
+    pub fn reserve( &mut self, additional: usize ) {
+        self.unmap();
+
+        let required = self.len() + additional;
+        match self.storage {
+            Storage::Memory( ref mut vec ) => {
+                if required <= vec.capacity() {
+                    return;
+                }
+
+                let new_capacity = std::cmp::max( required, vec.capacity() * 2 );
+                let extra = (new_capacity - vec.capacity()) * mem::size_of::< T >();
+                if MEMORY_USED.load( Ordering::SeqCst ).saturating_add( extra ) <= MEMORY_BUDGET.load( Ordering::SeqCst ) {
+                    let old_capacity = vec.capacity();
+                    vec.reserve_exact( new_capacity - vec.len() );
+                    MEMORY_USED.fetch_add( (vec.capacity() - old_capacity) * mem::size_of::< T >(), Ordering::SeqCst );
+                    return;
+                }
+            },
+            Storage::Spilled( ref mut spilled ) => {
+                if required <= spilled.capacity {
+                    return;
+                }
+
+                let new_capacity = std::cmp::max( required, spilled.capacity * 2 );
+                let result = spilled.fp.set_len( (new_capacity * mem::size_of::< T >()) as u64 ).and_then( |_| unsafe { MmapMut::map_mut( &spilled.fp ) } );
+                match result {
+                    Ok( mmap ) => {
+                        spilled.mmap = mmap;
+                        spilled.capacity = new_capacity;
+                    },
+                    Err( error ) => panic!( "failed to grow a spill file: {}", error )
+                }
+
+                return;
+            },
+            Storage::Mapped( _ ) => unreachable!()
+        }
+
+        let new_capacity = std::cmp::max( required, self.len() * 2 );
+        self.spill( new_capacity );
+
+        if let Storage::Memory( ref mut vec ) = self.storage {
+            // We couldn't spill, so we have no choice but to go over the budget.
+            let old_capacity = vec.capacity();
+            vec.reserve( additional );
+            MEMORY_USED.fetch_add( (vec.capacity() - old_capacity) * mem::size_of::< T >(), Ordering::SeqCst );
+        }
+    }
+
+    #[inline]
+    pub fn push( &mut self, value: T ) {
+        self.reserve( 1 );
+        match self.storage {
+            Storage::Memory( ref mut vec ) => vec.push( value ),
+            Storage::Spilled( ref mut spilled ) => {
+                unsafe {
+                    ptr::write( (spilled.mmap.as_mut_ptr() as *mut T).add( spilled.length ), value );
+                }
+                spilled.length += 1;
+            },
+            Storage::Mapped( _ ) => unreachable!()
+        }
+    }
+
+    pub fn shrink_to_fit( &mut self ) {
+        if let Storage::Memory( ref mut vec ) = self.storage {
+            let old_capacity = vec.capacity();
+            vec.shrink_to_fit();
+            MEMORY_USED.fetch_sub( (old_capacity - vec.capacity()) * mem::size_of::< T >(), Ordering::SeqCst );
+        }
+    }
+}
+
+impl< T: Pod > SpillVec< T > {
+    /// Creates a vector out of `length` elements which were written with `as_bytes` at `offset` in the given file.
+    pub fn from_mapping( mmap: Arc< Mmap >, offset: usize, length: usize ) -> io::Result< Self > {
+        let end = length.checked_mul( mem::size_of::< T >() ).and_then( |size| size.checked_add( offset ) );
+        if end.map( |end| end > mmap.len() ).unwrap_or( true ) {
+            return Err( io::Error::new( io::ErrorKind::InvalidData, "a mapped table is out of bounds" ) );
+        }
+
+        if (mmap.as_ptr() as usize + offset) % mem::align_of::< T >() != 0 {
+            return Err( io::Error::new( io::ErrorKind::InvalidData, "a mapped table is misaligned" ) );
+        }
+
+        Ok( SpillVec {
+            storage: Storage::Mapped( Mapped { mmap, offset, length } )
+        })
+    }
+
+    /// The raw contents of the vector.
+    pub fn as_bytes( &self ) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts( self.as_ptr() as *const u8, self.len() * mem::size_of::< T >() )
+        }
+    }
+}
+
+impl< T: Copy > Drop for SpillVec< T > {
+    fn drop( &mut self ) {
+        if let Storage::Memory( ref vec ) = self.storage {
+            MEMORY_USED.fetch_sub( vec.capacity() * mem::size_of::< T >(), Ordering::SeqCst );
+        }
+    }
+}
+
+impl< T: Copy > Default for SpillVec< T > {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl< T: Copy > Clone for SpillVec< T > {
+    fn clone( &self ) -> Self {
+        let mut vec = Self::with_capacity( self.len() );
+        for &value in self.iter() {
+            vec.push( value );
+        }
+
+        vec
+    }
+}
+
+impl< T: Copy > Deref for SpillVec< T > {
+    type Target = [T];
+
+    #[inline]
+    fn deref( &self ) -> &Self::Target {
+        match self.storage {
+            Storage::Memory( ref vec ) => vec,
+            Storage::Spilled( ref spilled ) => unsafe {
+                slice::from_raw_parts( spilled.mmap.as_ptr() as *const T, spilled.length )
+            },
+            Storage::Mapped( ref mapped ) => unsafe {
+                slice::from_raw_parts( mapped.mmap.as_ptr().add( mapped.offset ) as *const T, mapped.length )
+            }
+        }
+    }
+}
+
+impl< T: Copy > DerefMut for SpillVec< T > {
+    #[inline]
+    fn deref_mut( &mut self ) -> &mut Self::Target {
+        self.unmap();
+        match self.storage {
+            Storage::Memory( ref mut vec ) => vec,
+            Storage::Spilled( ref mut spilled ) => unsafe {
+                slice::from_raw_parts_mut( spilled.mmap.as_mut_ptr() as *mut T, spilled.length )
+            },
+            Storage::Mapped( _ ) => unreachable!()
+        }
+    }
+}
+
+impl< T: Copy > From< Vec< T > > for SpillVec< T > {
+    fn from( vec: Vec< T > ) -> Self {
+        let mut output = Self::with_capacity( vec.len() );
+        for value in vec {
+            output.push( value );
+        }
+
+        output
+    }
+}
+
+impl< 'a, C: Context, T: Copy + Readable< 'a, C > > Readable< 'a, C > for SpillVec< T > {
+    fn read_from< R: Reader< 'a, C > >( reader: &mut R ) -> Result< Self, C::Error > {
+        let length = reader.read_u64()? as usize;
+        let mut vec = Self::with_capacity( length );
+        for _ in 0..length {
+            vec.push( T::read_from( reader )? );
+        }
+
+        Ok( vec )
+    }
+}
+
+impl< C: Context, T: Copy + Writable< C > > Writable< C > for SpillVec< T > {
+    fn write_to< W: ?Sized + Writer< C > >( &self, writer: &mut W ) -> Result< (), C::Error > {
+        writer.write_u64( self.len() as u64 )?;
+        for value in self.iter() {
+            writer.write_value( value )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_spill_vec() {
+    let mut vec: SpillVec< u64 > = SpillVec::new();
+    vec.spill( 4 );
+    assert!( vec.is_spilled() );
+
+    for value in 0..1000 {
+        vec.push( value );
+    }
+
+    assert_eq!( vec.len(), 1000 );
+    assert_eq!( vec[ 999 ], 999 );
+    vec.sort_unstable_by_key( |&value| std::cmp::Reverse( value ) );
+    assert_eq!( vec[ 0 ], 999 );
+    assert_eq!( vec.clone().iter().sum::< u64 >(), 999 * 1000 / 2 );
+}
+
+#[test]
+fn test_mapped_spill_vec() {
+    use std::io::Write;
+
+    let values: SpillVec< u64 > = (0..100).collect::< Vec< _ > >().into();
+    let mut fp = create_spill_file( 0 ).unwrap();
+    fp.write_all( &[0; 8] ).unwrap();
+    fp.write_all( values.as_bytes() ).unwrap();
+
+    let mmap = Arc::new( unsafe { Mmap::map( &fp ).unwrap() } );
+    assert!( SpillVec::< u64 >::from_mapping( mmap.clone(), 8, 101 ).is_err() );
+
+    let mut vec: SpillVec< u64 > = SpillVec::from_mapping( mmap, 8, 100 ).unwrap();
+    assert!( vec.is_spilled() );
+    assert_eq!( &vec[..], &values[..] );
+
+    vec.push( 100 );
+    vec[ 0 ] = 1000;
+    assert_eq!( vec.len(), 101 );
+    assert_eq!( vec.iter().sum::< u64 >(), 1000 + 100 * 101 / 2 );
+}
@@ -18,7 +18,7 @@ use common::event::{
 use crate::loader::Loader;
 use crate::threaded_lz4_stream::Lz4Writer;
 
-use crate::reader::parse_events;
+use memory_profiler_capture::raw::parse_events;
 
 struct BufferedAllocation {
     timestamp: Timestamp,
@@ -80,6 +80,7 @@ pub fn squeeze_data< F, G >( input_fp: F, output_fp: G, threshold: Option< u64 >
         let mut stats_by_backtrace: HashMap< u64, GroupStatistics > = Default::default();
         let mut allocations_by_id: HashMap< AllocationId, smallvec::SmallVec< [BufferedAllocation; 1] > > = Default::default();
         let mut allocations_by_pointer: HashMap< u64, smallvec::SmallVec< [BufferedAllocation; 1] > > = Default::default();
+        let mut contents_by_pointer: HashMap< u64, Vec< u8 > > = Default::default();
 
         for event in event_stream {
             let event = event?;
@@ -257,6 +258,7 @@ pub fn squeeze_data< F, G >( input_fp: F, output_fp: G, threshold: Option< u64 >
                     continue;
                 },
                 Event::FreeEx { id, timestamp, pointer, backtrace, thread } => {
+                    let contents = contents_by_pointer.remove( &pointer );
                     let entry;
                     if !id.is_invalid() && !id.is_untracked() {
                         entry = allocations_by_id.remove( &id );
@@ -275,6 +277,11 @@ pub fn squeeze_data< F, G >( input_fp: F, output_fp: G, threshold: Option< u64 >
                                 let lifetime = timestamp - entry[0].timestamp;
                                 if lifetime > threshold {
                                     emit( id, entry, &mut ofp )?;
+                                    if let Some( contents ) = contents {
+                                        let event = Event::AllocationContents { id, pointer, contents: contents.into() };
+                                        event.write_to_stream( &mut ofp )?;
+                                    }
+
                                     let event = Event::FreeEx { id, timestamp, pointer, backtrace, thread };
                                     event.write_to_stream( &mut ofp )?;
                                     continue;
@@ -292,8 +299,14 @@ pub fn squeeze_data< F, G >( input_fp: F, output_fp: G, threshold: Option< u64 >
 
                     continue;
                 },
+                Event::AllocationContents { pointer, ref contents, .. } => {
+                    // The allocation itself is only emitted right before it's freed.
+                    contents_by_pointer.insert( pointer, contents.to_vec() );
+                    continue;
+                },
                 Event::MemoryMap { ref mut backtrace, .. } |
                 Event::MemoryUnmap { ref mut backtrace, .. } |
+                Event::MemoryRemap { ref mut backtrace, .. } |
                 Event::Mallopt { ref mut backtrace, .. } => {
                     *backtrace = backtrace_map.get( backtrace ).copied().unwrap();
                 },
@@ -327,11 +340,15 @@ pub fn squeeze_data< F, G >( input_fp: F, output_fp: G, threshold: Option< u64 >
                 Event::Header { .. } => {},
                 Event::MemoryDump { .. } => {},
                 Event::Marker { .. } => {},
+                Event::AllocatorStats { .. } => {},
+                Event::ResidentMemory { .. } => {},
                 Event::Environ { .. } => {},
                 Event::WallClock { .. } => {},
                 Event::String { .. } => {},
                 Event::DecodedFrame { .. } => {},
-                Event::DecodedBacktrace { .. } => {}
+                Event::DecodedBacktrace { .. } => {},
+                Event::SymbolTable { .. } => {},
+                Event::AllocatorInfo { .. } => {}
             }
 
             event.write_to_stream( &mut ofp )?;
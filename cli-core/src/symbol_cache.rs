@@ -0,0 +1,304 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use ahash::AHashMap as HashMap;
+use parking_lot::Mutex;
+use speedy::{Readable, Writable};
+
+use crate::data::{CodePointer, StringInterner};
+use crate::frame::Frame;
+
+const CACHE_VERSION: u32 = 1;
+
+/// How many addresses can be cached in memory at most; once there are more
+/// the binaries which weren't used for the longest time are evicted.
+const MAX_CACHED_ADDRESSES: usize = 1_000_000;
+
+/// A decoded frame which doesn't depend on any particular data's string interner.
+#[derive(Clone, PartialEq, Debug, Readable, Writable)]
+pub(crate) struct CachedFrame {
+    library: Option< String >,
+    function: Option< String >,
+    raw_function: Option< String >,
+    source: Option< String >,
+    line: Option< u32 >,
+    column: Option< u32 >,
+    is_inline: bool
+}
+
+impl CachedFrame {
+    pub(crate) fn from_frame( frame: &Frame, interner: &StringInterner ) -> Self {
+        let resolve = |id| interner.resolve( id ).unwrap().to_owned();
+        CachedFrame {
+            library: frame.library().map( resolve ),
+            function: frame.function().map( resolve ),
+            raw_function: frame.raw_function().map( resolve ),
+            source: frame.source().map( resolve ),
+            line: frame.line(),
+            column: frame.column(),
+            is_inline: frame.is_inline()
+        }
+    }
+
+    pub(crate) fn to_frame( &self, address: u64, interner: &mut StringInterner ) -> Frame {
+        let mut frame = Frame::new_unknown( CodePointer::new( address ) );
+        if let Some( ref library ) = self.library {
+            frame.set_library( interner.get_or_intern( library ) );
+        }
+        if let Some( ref function ) = self.function {
+            frame.set_function( interner.get_or_intern( function ) );
+        }
+        if let Some( ref raw_function ) = self.raw_function {
+            frame.set_raw_function( interner.get_or_intern( raw_function ) );
+        }
+        if let Some( ref source ) = self.source {
+            frame.set_source( interner.get_or_intern( source ) );
+        }
+        if let Some( line ) = self.line {
+            frame.set_line( line );
+        }
+        if let Some( column ) = self.column {
+            frame.set_column( column );
+        }
+
+        frame.set_is_inline( self.is_inline );
+        frame
+    }
+}
+
+#[derive(Readable, Writable)]
+struct CacheFile {
+    version: u32,
+    frames: Vec< (u64, Vec< CachedFrame >) >
+}
+
+#[derive(Default)]
+struct Table {
+    frames: HashMap< u64, Vec< CachedFrame > >,
+    is_dirty: bool,
+    last_used: u64
+}
+
+struct SymbolCache {
+    directory: Option< PathBuf >,
+    tables: HashMap< Vec< u8 >, Table >,
+    address_count: usize,
+    max_address_count: usize,
+    use_counter: u64
+}
+
+impl SymbolCache {
+    fn new( max_address_count: usize ) -> Self {
+        SymbolCache {
+            directory: None,
+            tables: HashMap::new(),
+            address_count: 0,
+            max_address_count,
+            use_counter: 0
+        }
+    }
+
+    fn table( &mut self, key: &[u8] ) -> &mut Table {
+        if !self.tables.contains_key( key ) {
+            let table = load_table( &self.directory, key );
+            self.address_count += table.frames.len();
+            self.tables.insert( key.to_owned(), table );
+        }
+
+        self.use_counter += 1;
+        let table = self.tables.get_mut( key ).unwrap();
+        table.last_used = self.use_counter;
+        table
+    }
+
+    fn lookup( &mut self, key: &[u8], relative_address: u64 ) -> Option< Vec< CachedFrame > > {
+        let frames = self.table( key ).frames.get( &relative_address ).cloned();
+        self.evict( key );
+        frames
+    }
+
+    fn insert( &mut self, key: &[u8], relative_address: u64, frames: Vec< CachedFrame > ) {
+        let table = self.table( key );
+        let is_new = table.frames.insert( relative_address, frames ).is_none();
+        table.is_dirty = true;
+        if is_new {
+            self.address_count += 1;
+        }
+
+        self.evict( key );
+    }
+
+    /// Evicts the least recently used tables, except the one for `current_key`, until the cache is within its limit.
+    /// The evicted tables are written into the cache directory first, so nothing which was decoded is lost.
+    fn evict( &mut self, current_key: &[u8] ) {
+        while self.address_count > self.max_address_count {
+            let key = match self.tables.iter().filter( |(key, _)| key.as_slice() != current_key ).min_by_key( |(_, table)| table.last_used ) {
+                Some( (key, _) ) => key.clone(),
+                None => break
+            };
+
+            let table = self.tables.remove( &key ).unwrap();
+            self.address_count -= table.frames.len();
+            if table.is_dirty {
+                if let Some( ref directory ) = self.directory {
+                    if let Err( error ) = write_table( directory, &key, &table ) {
+                        warn!( "Failed to write the symbol cache into {:?}: {}", directory, error );
+                    }
+                }
+            }
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref SYMBOL_CACHE: Mutex< SymbolCache > = Mutex::new( SymbolCache::new( MAX_CACHED_ADDRESSES ) );
+}
+
+/// Sets a directory where the symbolication results are persisted, so that they can be reused across restarts.
+pub fn set_symbol_cache_directory( path: PathBuf ) {
+    SYMBOL_CACHE.lock().directory = Some( path );
+}
+
+fn hex( bytes: &[u8] ) -> String {
+    bytes.iter().map( |byte| format!( "{:02x}", byte ) ).collect()
+}
+
+fn load_table( directory: &Option< PathBuf >, key: &[u8] ) -> Table {
+    let path = match *directory {
+        Some( ref directory ) => directory.join( format!( "{}.symbols", hex( key ) ) ),
+        None => return Table::default()
+    };
+
+    let bytes = match fs::read( &path ) {
+        Ok( bytes ) => bytes,
+        Err( ref error ) if error.kind() == io::ErrorKind::NotFound => return Table::default(),
+        Err( error ) => {
+            warn!( "Failed to read the symbol cache from {:?}: {}", path, error );
+            return Table::default();
+        }
+    };
+
+    match CacheFile::read_from_buffer( &bytes ) {
+        Ok( file ) if file.version == CACHE_VERSION => {
+            debug!( "Loaded {} cached symbols from {:?}", file.frames.len(), path );
+            Table {
+                frames: file.frames.into_iter().collect(),
+                is_dirty: false,
+                last_used: 0
+            }
+        },
+        Ok( _ ) => {
+            info!( "Ignoring {:?} since it was written by an incompatible version", path );
+            Table::default()
+        },
+        Err( error ) => {
+            warn!( "Failed to load the symbol cache from {:?}: {}", path, error );
+            Table::default()
+        }
+    }
+}
+
+/// The key under which the symbols of a given binary are cached. Whether a separate debug binary was found
+/// is a part of the key, since the same binary will be decoded differently with and without it.
+pub(crate) fn cache_key( build_id: &[u8], has_debug_binary: bool ) -> Vec< u8 > {
+    let mut key = build_id.to_owned();
+    key.push( has_debug_binary as u8 );
+    key
+}
+
+/// Returns the frames cached for a given address relative to the binary identified by `key`.
+pub(crate) fn lookup( key: &[u8], relative_address: u64 ) -> Option< Vec< CachedFrame > > {
+    SYMBOL_CACHE.lock().lookup( key, relative_address )
+}
+
+pub(crate) fn insert( key: &[u8], relative_address: u64, frames: Vec< CachedFrame > ) {
+    SYMBOL_CACHE.lock().insert( key, relative_address, frames );
+}
+
+fn write_table( directory: &Path, key: &[u8], table: &Table ) -> io::Result< () > {
+    fs::create_dir_all( directory )?;
+
+    let path = directory.join( format!( "{}.symbols", hex( key ) ) );
+    let tmp_path = directory.join( format!( "{}.symbols.tmp", hex( key ) ) );
+    let file = CacheFile {
+        version: CACHE_VERSION,
+        frames: table.frames.iter().map( |(&address, frames)| (address, frames.clone()) ).collect()
+    };
+
+    {
+        let mut fp = BufWriter::new( File::create( &tmp_path )? );
+        file.write_to_stream( &mut fp )?;
+        fp.flush()?;
+    }
+
+    fs::rename( &tmp_path, &path )
+}
+
+/// Writes the cached symbols which were added since the last flush into the cache directory, if one was set.
+pub(crate) fn flush() -> io::Result< () > {
+    let mut cache = SYMBOL_CACHE.lock();
+    let directory = match cache.directory {
+        Some( ref directory ) => directory.clone(),
+        None => return Ok(())
+    };
+
+    let mut flushed_count = 0;
+    for (key, table) in cache.tables.iter_mut() {
+        if !table.is_dirty {
+            continue;
+        }
+
+        write_table( &directory, key, table )?;
+        table.is_dirty = false;
+        flushed_count += 1;
+    }
+
+    if flushed_count != 0 {
+        debug!( "Flushed {} symbol cache files", flushed_count );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_cached_frame_roundtrip() {
+    let mut interner = StringInterner::new();
+    let mut frame = Frame::new_unknown( CodePointer::new( 0x1000 ) );
+    frame.set_library( interner.get_or_intern( "libfoo.so" ) );
+    frame.set_function( interner.get_or_intern( "foo" ) );
+    frame.set_line( 10 );
+    frame.set_is_inline( true );
+
+    let cached = CachedFrame::from_frame( &frame, &interner );
+    let mut other_interner = StringInterner::new();
+    other_interner.get_or_intern( "something else" );
+
+    let decoded = cached.to_frame( 0x2000, &mut other_interner );
+    assert_eq!( CachedFrame::from_frame( &decoded, &other_interner ), cached );
+    assert_eq!( decoded.address(), CodePointer::new( 0x2000 ) );
+    assert_eq!( hex( &cache_key( &[0xab, 0x01], true ) ), "ab0101" );
+}
+
+#[test]
+fn test_symbol_cache_eviction() {
+    let frames = || vec![ CachedFrame::from_frame( &Frame::new_unknown( CodePointer::new( 0 ) ), &StringInterner::new() ) ];
+    let mut cache = SymbolCache::new( 3 );
+    cache.insert( b"a", 1, frames() );
+    cache.insert( b"a", 2, frames() );
+    cache.insert( b"b", 1, frames() );
+    assert!( cache.lookup( b"a", 1 ).is_some() );
+
+    // The least recently used binary is evicted first.
+    cache.insert( b"c", 1, frames() );
+    assert_eq!( cache.address_count, 3 );
+    assert!( !cache.tables.contains_key( b"b".as_ref() ) );
+    assert!( cache.lookup( b"a", 2 ).is_some() );
+
+    // The binary which is being used right now is kept even if it alone is over the limit.
+    cache.insert( b"c", 2, frames() );
+    cache.insert( b"c", 3, frames() );
+    cache.insert( b"c", 4, frames() );
+    assert_eq!( cache.tables.len(), 1 );
+    assert_eq!( cache.address_count, 4 );
+}
@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use nwind::{BinaryData, DebugInfoIndex};
+
+use crate::loader::is_build_id_matching;
+
+/// Where to look for the binaries and the debug symbols of the libraries recorded in the data.
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct SymbolSources {
+    /// Files or directories with extra debug symbols.
+    pub debug_symbols: Vec< PathBuf >,
+    /// Directories containing a copy of the profiled machine's filesystem.
+    pub sysroots: Vec< PathBuf >,
+    /// Files or directories with binaries which are matched to the libraries by their build ID or name.
+    pub symbol_paths: Vec< PathBuf >
+}
+
+impl SymbolSources {
+    pub(crate) fn debug_info_index( &self ) -> DebugInfoIndex {
+        let mut debug_info_index = DebugInfoIndex::new();
+        for path in self.debug_symbols.iter().chain( self.symbol_paths.iter() ) {
+            debug_info_index.add( path.as_os_str() );
+        }
+
+        debug_info_index
+    }
+
+    pub(crate) fn binary_index( &self ) -> DebugInfoIndex {
+        let mut binary_index = DebugInfoIndex::new();
+        for path in &self.symbol_paths {
+            binary_index.add( path.as_os_str() );
+        }
+
+        binary_index
+    }
+
+    /// Looks for a library, as it was named on the profiled machine, in the sysroots;
+    /// the binaries with a different build ID than the one the profiler saw are skipped.
+    pub(crate) fn load_from_sysroot( &self, path: &str, expected_build_id: Option< &[u8] > ) -> Option< Arc< BinaryData > > {
+        let relative_path = path.trim_start_matches( '/' );
+        for sysroot in &self.sysroots {
+            let candidate = sysroot.join( relative_path );
+            if !candidate.is_file() {
+                continue;
+            }
+
+            match BinaryData::load_from_fs( &candidate ) {
+                Ok( ref binary_data ) if !is_build_id_matching( binary_data, expected_build_id ) => {
+                    warn!( "Ignoring {:?} from the sysroot since its build ID doesn't match", candidate );
+                },
+                Ok( binary_data ) => {
+                    debug!( "Found {:?} in the sysroot at {:?}", path, candidate );
+                    return Some( Arc::new( binary_data ) );
+                },
+                Err( error ) => {
+                    warn!( "Failed to load {:?}: {}", candidate, error );
+                }
+            }
+        }
+
+        None
+    }
+}
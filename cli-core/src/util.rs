@@ -99,6 +99,23 @@ impl fmt::Display for ReadableSize {
     }
 }
 
+/// Formats a size in bytes with binary units, e.g. `1.5 KiB`.
+pub fn format_size( size: u64 ) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!( "{} B", size )
+    } else {
+        format!( "{:.1} {}", value, UNITS[ unit ] )
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub struct ReadableAddress( pub u64 );
 
@@ -145,3 +162,10 @@ pub fn table_to_string( table: &[ Vec< String > ] ) -> String {
 
     output
 }
+
+#[test]
+fn test_format_size() {
+    assert_eq!( format_size( 100 ), "100 B" );
+    assert_eq!( format_size( 1536 ), "1.5 KiB" );
+    assert_eq!( format_size( 3 * 1024 * 1024 ), "3.0 MiB" );
+}
@@ -2,6 +2,8 @@ use std::iter::FusedIterator;
 use std::cmp::Ordering;
 use std::u32;
 
+use speedy::{Readable, Writable};
+
 #[derive(Default)]
 pub struct VecVec< K, T > {
     index: Vec< (K, u32, u32) >,
@@ -69,12 +71,22 @@ impl< K, T > VecVec< K, T > {
         })
     }
 
+    /// Like `sort_by_key`, but calls the callback only once for every element.
+    pub fn sort_by_cached_key< U, F >( &mut self, mut callback: F )
+        where F: FnMut( (&K, &[T]) ) -> U, U: Ord
+    {
+        let storage = &self.storage;
+        self.index.sort_by_cached_key( |&(ref key, offset, length)| {
+            callback( (key, &storage[ (offset as usize)..(offset + length) as usize ]) )
+        })
+    }
+
     pub fn reverse( &mut self ) {
         self.index.reverse();
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Readable, Writable)]
 pub struct DenseVecVec< T > {
     index: Vec< (u32, u32) >,
     storage: Vec< T >
@@ -0,0 +1,907 @@
+//! Derived per-allocation fields defined through simple expressions, e.g.:
+//!
+//! ```text
+//! lifetime_ms = lifetime * 1000; is_huge = size > 1M; subsystem = regex_extract(top_frame, "^(\w+)::")
+//! ```
+//!
+//! An expression can use the built-in fields of an allocation, the columns defined before it,
+//! numbers (optionally with a `K`/`KiB`, `M`/`MiB` or `G`/`GiB` binary suffix, or a `us`, `ms`, `s`, `min` or `h` duration suffix),
+//! strings, booleans and `null`, the `||`, `&&`, `!`, `==`, `!=`, `<`, `<=`, `>`, `>=`, `+`, `-`, `*`, `/` and `%` operators,
+//! the `~` and `!~` operators, which check whether the string on the left matches the regex on the right,
+//! and the following functions:
+//!
+//!   * `regex_extract(string, "pattern")` - the first capture group of the match, or the whole match if there is none
+//!   * `matches(string, "pattern")` - whether the pattern matches
+//!   * `if(condition, value, otherwise)`
+//!
+//! The `function`, `source` and `library` fields stand for every frame of the backtrace, so they can only
+//! be used with `~` and `!~`, e.g. `function ~ "parse_"` matches if any of the functions matches the regex.
+//!
+//! Whenever an operand is `null` (e.g. the lifetime of a leaked allocation) the result is `null` too,
+//! which is treated as `false` when the expression is used as a filter.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use regex::{Regex, RegexBuilder};
+
+use crate::data::{Allocation, BacktraceId, Data, DataId};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ColumnValue {
+    Null,
+    Bool( bool ),
+    Number( f64 ),
+    String( String )
+}
+
+impl ColumnValue {
+    pub fn is_truthy( &self ) -> bool {
+        match *self {
+            ColumnValue::Null => false,
+            ColumnValue::Bool( value ) => value,
+            ColumnValue::Number( value ) => value != 0.0,
+            ColumnValue::String( ref value ) => !value.is_empty()
+        }
+    }
+}
+
+impl fmt::Display for ColumnValue {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            ColumnValue::Null => write!( fmt, "null" ),
+            ColumnValue::Bool( value ) => write!( fmt, "{}", value ),
+            ColumnValue::Number( value ) => write!( fmt, "{}", value ),
+            ColumnValue::String( ref value ) => write!( fmt, "{}", value )
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExpressionError( String );
+
+impl fmt::Display for ExpressionError {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        fmt.write_str( &self.0 )
+    }
+}
+
+impl Error for ExpressionError {}
+
+fn error< T >( message: String ) -> Result< T, ExpressionError > {
+    Err( ExpressionError( message ) )
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Field {
+    Size,
+    Address,
+    Timestamp,
+    DeallocationTimestamp,
+    Lifetime,
+    Thread,
+    BacktraceId,
+    BacktraceDepth,
+    Marker,
+    IsLeaked,
+    IsMmaped,
+    InMainArena,
+    JemallocArena,
+    TopFrame,
+    TopSource
+}
+
+const FIELDS: &[(&str, Field)] = &[
+    ("size", Field::Size),
+    ("address", Field::Address),
+    ("timestamp", Field::Timestamp),
+    ("deallocation_timestamp", Field::DeallocationTimestamp),
+    ("lifetime", Field::Lifetime),
+    ("thread", Field::Thread),
+    ("backtrace_id", Field::BacktraceId),
+    ("backtrace_depth", Field::BacktraceDepth),
+    ("marker", Field::Marker),
+    ("is_leaked", Field::IsLeaked),
+    ("is_mmaped", Field::IsMmaped),
+    ("in_main_arena", Field::InMainArena),
+    ("jemalloc_arena", Field::JemallocArena),
+    ("top_frame", Field::TopFrame),
+    ("top_source", Field::TopSource)
+];
+
+/// A field which has a value for every frame of the backtrace.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum FrameField {
+    Function,
+    Source,
+    Library
+}
+
+const FRAME_FIELDS: &[(&str, FrameField)] = &[
+    ("function", FrameField::Function),
+    ("source", FrameField::Source),
+    ("library", FrameField::Library)
+];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Operator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Literal( ColumnValue ),
+    Field( Field ),
+    Column( usize ),
+    Not( Box< Node > ),
+    Negate( Box< Node > ),
+    Binary( Operator, Box< Node >, Box< Node > ),
+    RegexExtract( Box< Node >, Regex ),
+    Matches( Box< Node >, Regex ),
+    FrameField( FrameField ),
+    MatchesAnyFrame( FrameField, Regex, Arc< MatchedBacktraces > ),
+    If( Box< Node >, Box< Node >, Box< Node > )
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number( f64 ),
+    String( String ),
+    Identifier( String ),
+    Symbol( &'static str )
+}
+
+const SYMBOLS: &[&str] = &[
+    "||", "&&", "==", "!=", "!~", "<=", ">=", "<", ">", "!", "~", "=", "+", "-", "*", "/", "%", "(", ")", ",", ";"
+];
+
+fn tokenize( input: &str ) -> Result< Vec< Token >, ExpressionError > {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some( &(offset, ch) ) = chars.peek() {
+        if ch.is_whitespace() {
+            chars.next();
+        } else if ch.is_ascii_digit() || ch == '.' {
+            let mut end = offset;
+            while let Some( &(index, ch) ) = chars.peek() {
+                if !ch.is_ascii_digit() && ch != '.' {
+                    break;
+                }
+                end = index + ch.len_utf8();
+                chars.next();
+            }
+
+            let mut value: f64 = match input[ offset..end ].parse() {
+                Ok( value ) => value,
+                Err( _ ) => return error( format!( "invalid number: '{}'", &input[ offset..end ] ) )
+            };
+
+            let mut suffix_end = end;
+            while let Some( &(index, ch) ) = chars.peek() {
+                if !ch.is_alphabetic() {
+                    break;
+                }
+                suffix_end = index + ch.len_utf8();
+                chars.next();
+            }
+
+            // The durations are in seconds, since that's what the `lifetime` and the timestamps are in.
+            // The sizes are only in uppercase, so that e.g. `m` can't be mistaken for minutes.
+            let multiplier = match &input[ end..suffix_end ] {
+                "" | "s" => 1.0,
+                "K" | "KiB" => 1024.0,
+                "M" | "MiB" => 1024.0 * 1024.0,
+                "G" | "GiB" => 1024.0 * 1024.0 * 1024.0,
+                "us" => 0.000_001,
+                "ms" => 0.001,
+                "min" => 60.0,
+                "h" => 3600.0,
+                suffix => return error( format!( "invalid suffix of a number: '{}'", suffix ) )
+            };
+
+            value *= multiplier;
+            tokens.push( Token::Number( value ) );
+        } else if ch.is_alphabetic() || ch == '_' {
+            let mut end = offset;
+            while let Some( &(index, ch) ) = chars.peek() {
+                if !ch.is_alphanumeric() && ch != '_' {
+                    break;
+                }
+                end = index + ch.len_utf8();
+                chars.next();
+            }
+
+            tokens.push( Token::Identifier( input[ offset..end ].to_owned() ) );
+        } else if ch == '"' {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some( (_, '"') ) => break,
+                    Some( (_, '\\') ) => match chars.next() {
+                        Some( (_, ch @ '"') ) | Some( (_, ch @ '\\') ) => value.push( ch ),
+                        // Keep the other escapes intact since they're most likely a part of a regex.
+                        Some( (_, ch) ) => {
+                            value.push( '\\' );
+                            value.push( ch );
+                        },
+                        None => return error( "unterminated string".to_owned() )
+                    },
+                    Some( (_, ch) ) => value.push( ch ),
+                    None => return error( "unterminated string".to_owned() )
+                }
+            }
+
+            tokens.push( Token::String( value ) );
+        } else {
+            let symbol = SYMBOLS.iter().find( |symbol| input[ offset.. ].starts_with( *symbol ) );
+            match symbol {
+                Some( &symbol ) => {
+                    for _ in 0..symbol.len() {
+                        chars.next();
+                    }
+                    tokens.push( Token::Symbol( symbol ) );
+                },
+                None => return error( format!( "unexpected character: '{}'", ch ) )
+            }
+        }
+    }
+
+    Ok( tokens )
+}
+
+/// How deeply an expression can be nested. The expressions come straight from the URL
+/// and both the parser and the evaluator are recursive, so this keeps them from overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec< Token >,
+    position: usize,
+    columns: Vec< String >,
+    regex_size_limit: Option< usize >,
+    /// How many times the parser has recursed into a nested expression.
+    nesting: usize,
+    /// The depth of the node which was parsed last.
+    depth: usize
+}
+
+impl Parser {
+    fn new( tokens: Vec< Token >, columns: Vec< String >, regex_size_limit: Option< usize > ) -> Self {
+        Parser {
+            tokens,
+            position: 0,
+            columns,
+            regex_size_limit,
+            nesting: 0,
+            depth: 0
+        }
+    }
+
+    fn set_depth( &mut self, depth: usize ) -> Result< (), ExpressionError > {
+        if depth > MAX_DEPTH {
+            return error( format!( "the expression is nested more than {} levels deep", MAX_DEPTH ) );
+        }
+
+        self.depth = depth;
+        Ok(())
+    }
+
+    fn nested< T, F: FnOnce( &mut Self ) -> Result< T, ExpressionError > >( &mut self, callback: F ) -> Result< T, ExpressionError > {
+        if self.nesting >= MAX_DEPTH {
+            return error( format!( "the expression is nested more than {} levels deep", MAX_DEPTH ) );
+        }
+
+        self.nesting += 1;
+        let result = callback( self );
+        self.nesting -= 1;
+        result
+    }
+
+    fn peek( &self ) -> Option< &Token > {
+        self.tokens.get( self.position )
+    }
+
+    fn next( &mut self ) -> Option< Token > {
+        let token = self.tokens.get( self.position ).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat( &mut self, symbol: &str ) -> bool {
+        match self.peek() {
+            Some( &Token::Symbol( next ) ) if next == symbol => {
+                self.position += 1;
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn expect( &mut self, symbol: &str ) -> Result< (), ExpressionError > {
+        if self.eat( symbol ) {
+            Ok(())
+        } else {
+            match self.peek() {
+                Some( token ) => error( format!( "expected '{}', found {:?}", symbol, token ) ),
+                None => error( format!( "expected '{}', found the end of the expression", symbol ) )
+            }
+        }
+    }
+
+    fn binary( &mut self, operators: &[(&str, Operator)], next: fn( &mut Self ) -> Result< Node, ExpressionError > ) -> Result< Node, ExpressionError > {
+        let mut lhs = next( self )?;
+        'outer: loop {
+            for &(symbol, operator) in operators {
+                if self.eat( symbol ) {
+                    let lhs_depth = self.depth;
+                    let rhs = next( self )?;
+                    self.set_depth( std::cmp::max( lhs_depth, self.depth ) + 1 )?;
+                    lhs = Node::Binary( operator, Box::new( lhs ), Box::new( rhs ) );
+                    continue 'outer;
+                }
+            }
+
+            return Ok( lhs );
+        }
+    }
+
+    fn parse_or( &mut self ) -> Result< Node, ExpressionError > {
+        self.binary( &[("||", Operator::Or)], Self::parse_and )
+    }
+
+    fn parse_and( &mut self ) -> Result< Node, ExpressionError > {
+        self.binary( &[("&&", Operator::And)], Self::parse_match )
+    }
+
+    fn parse_match( &mut self ) -> Result< Node, ExpressionError > {
+        let lhs = self.parse_comparison()?;
+        let is_negated = if self.eat( "~" ) {
+            false
+        } else if self.eat( "!~" ) {
+            true
+        } else {
+            return Ok( lhs );
+        };
+
+        let regex = self.parse_regex()?;
+        let node = match lhs {
+            Node::FrameField( field ) => Node::MatchesAnyFrame( field, regex, Default::default() ),
+            lhs => Node::Matches( Box::new( lhs ), regex )
+        };
+
+        self.set_depth( self.depth + 1 )?;
+        if is_negated {
+            self.set_depth( self.depth + 1 )?;
+            Ok( Node::Not( Box::new( node ) ) )
+        } else {
+            Ok( node )
+        }
+    }
+
+    fn parse_comparison( &mut self ) -> Result< Node, ExpressionError > {
+        self.binary( &[
+            ("==", Operator::Equal),
+            ("!=", Operator::NotEqual),
+            ("<=", Operator::LessOrEqual),
+            (">=", Operator::GreaterOrEqual),
+            ("<", Operator::Less),
+            (">", Operator::Greater)
+        ], Self::parse_sum )
+    }
+
+    fn parse_sum( &mut self ) -> Result< Node, ExpressionError > {
+        self.binary( &[("+", Operator::Add), ("-", Operator::Subtract)], Self::parse_product )
+    }
+
+    fn parse_product( &mut self ) -> Result< Node, ExpressionError > {
+        self.binary( &[("*", Operator::Multiply), ("/", Operator::Divide), ("%", Operator::Remainder)], Self::parse_unary )
+    }
+
+    fn parse_unary( &mut self ) -> Result< Node, ExpressionError > {
+        if self.eat( "!" ) {
+            let node = self.nested( Self::parse_unary )?;
+            self.set_depth( self.depth + 1 )?;
+            Ok( Node::Not( Box::new( node ) ) )
+        } else if self.eat( "-" ) {
+            let node = self.nested( Self::parse_unary )?;
+            self.set_depth( self.depth + 1 )?;
+            Ok( Node::Negate( Box::new( node ) ) )
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_regex( &mut self ) -> Result< Regex, ExpressionError > {
+        let pattern = match self.next() {
+            Some( Token::String( pattern ) ) => pattern,
+            _ => return error( "expected a regex pattern as a string".to_owned() )
+        };
+
+        let mut builder = RegexBuilder::new( &pattern );
+        if let Some( size_limit ) = self.regex_size_limit {
+            builder.size_limit( size_limit );
+            builder.dfa_size_limit( size_limit );
+        }
+
+        builder.build().map_err( |err| ExpressionError( format!( "invalid regex '{}': {}", pattern, err ) ) )
+    }
+
+    fn parse_call( &mut self, name: &str ) -> Result< Node, ExpressionError > {
+        let node = match name {
+            "regex_extract" | "matches" => {
+                let string = self.parse_or()?;
+                self.set_depth( self.depth + 1 )?;
+                self.expect( "," )?;
+                let regex = self.parse_regex()?;
+                if name == "matches" {
+                    Node::Matches( Box::new( string ), regex )
+                } else {
+                    Node::RegexExtract( Box::new( string ), regex )
+                }
+            },
+            "if" => {
+                let condition = self.parse_or()?;
+                let mut depth = self.depth;
+                self.expect( "," )?;
+                let value = self.parse_or()?;
+                depth = std::cmp::max( depth, self.depth );
+                self.expect( "," )?;
+                let otherwise = self.parse_or()?;
+                depth = std::cmp::max( depth, self.depth );
+                self.set_depth( depth + 1 )?;
+                Node::If( Box::new( condition ), Box::new( value ), Box::new( otherwise ) )
+            },
+            _ => return error( format!( "unknown function: '{}'", name ) )
+        };
+
+        self.expect( ")" )?;
+        Ok( node )
+    }
+
+    fn parse_primary( &mut self ) -> Result< Node, ExpressionError > {
+        // Every leaf has a depth of one; the rest set their own depth.
+        self.depth = 1;
+        match self.next() {
+            Some( Token::Number( value ) ) => Ok( Node::Literal( ColumnValue::Number( value ) ) ),
+            Some( Token::String( value ) ) => Ok( Node::Literal( ColumnValue::String( value ) ) ),
+            Some( Token::Symbol( "(" ) ) => {
+                let node = self.nested( Self::parse_or )?;
+                self.expect( ")" )?;
+                Ok( node )
+            },
+            Some( Token::Identifier( name ) ) => {
+                if self.eat( "(" ) {
+                    return self.nested( |parser| parser.parse_call( &name ) );
+                }
+
+                match name.as_str() {
+                    "true" => return Ok( Node::Literal( ColumnValue::Bool( true ) ) ),
+                    "false" => return Ok( Node::Literal( ColumnValue::Bool( false ) ) ),
+                    "null" => return Ok( Node::Literal( ColumnValue::Null ) ),
+                    _ => {}
+                }
+
+                if let Some( &(_, field) ) = FIELDS.iter().find( |&&(field_name, _)| field_name == name ) {
+                    return Ok( Node::Field( field ) );
+                }
+
+                if let Some( &(_, field) ) = FRAME_FIELDS.iter().find( |&&(field_name, _)| field_name == name ) {
+                    return match self.peek() {
+                        Some( &Token::Symbol( "~" ) ) | Some( &Token::Symbol( "!~" ) ) => Ok( Node::FrameField( field ) ),
+                        _ => error( format!( "'{}' can only be used with '~' or '!~'", name ) )
+                    };
+                }
+
+                if let Some( index ) = self.columns.iter().position( |column_name| *column_name == name ) {
+                    return Ok( Node::Column( index ) );
+                }
+
+                error( format!( "unknown field or column: '{}'", name ) )
+            },
+            Some( token ) => error( format!( "unexpected {:?}", token ) ),
+            None => error( "unexpected end of the expression".to_owned() )
+        }
+    }
+}
+
+fn top_frame( data: &Data, allocation: &Allocation, source: bool ) -> ColumnValue {
+    let backtrace: Vec< _ > = data.get_backtrace( allocation.backtrace ).collect();
+    for &(_, frame) in backtrace.iter().rev() {
+        let id = if source { frame.source() } else { frame.any_function() };
+        if let Some( id ) = id {
+            return ColumnValue::String( data.interner().resolve( id ).unwrap().to_owned() );
+        }
+    }
+
+    ColumnValue::Null
+}
+
+/// Returns a bitset of the backtraces which have at least one frame matching the regex.
+fn match_backtraces( data: &Data, field: FrameField, regex: &Regex ) -> Vec< u64 > {
+    let mut cache = HashMap::new();
+    let mut matched = Vec::new();
+    for (backtrace_id, mut backtrace) in data.all_backtraces() {
+        let is_match = backtrace.any( |(_, frame)| {
+            let id = match field {
+                FrameField::Function => frame.any_function(),
+                FrameField::Source => frame.source(),
+                FrameField::Library => frame.library()
+            };
+
+            id.map( |id| *cache.entry( id ).or_insert_with( || regex.is_match( data.interner().resolve( id ).unwrap() ) ) ).unwrap_or( false )
+        });
+
+        if is_match {
+            let index = backtrace_id.raw() as usize;
+            if matched.len() <= index / 64 {
+                matched.resize( index / 64 + 1, 0 );
+            }
+
+            matched[ index / 64 ] |= 1_u64 << (index % 64);
+        }
+    }
+
+    matched
+}
+
+fn is_matched( matched: &[u64], backtrace: BacktraceId ) -> bool {
+    let index = backtrace.raw() as usize;
+    matched.get( index / 64 ).map( |word| word & (1_u64 << (index % 64)) != 0 ).unwrap_or( false )
+}
+
+/// Which backtraces of a given data set match a `~` on one of the frame fields.
+///
+/// The regex is only matched once per backtrace when the expression is first evaluated
+/// instead of on every frame of every allocation, just as `prepare_filter` does for the filters.
+#[derive(Default)]
+struct MatchedBacktraces {
+    matched: RwLock< Option< (DataId, Vec< u64 >) > >
+}
+
+impl fmt::Debug for MatchedBacktraces {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
+        fmt.write_str( "MatchedBacktraces" )
+    }
+}
+
+impl MatchedBacktraces {
+    fn contains( &self, data: &Data, backtrace: BacktraceId, field: FrameField, regex: &Regex ) -> bool {
+        if let Some( (id, ref matched) ) = *self.matched.read() {
+            if id == data.id() {
+                return is_matched( matched, backtrace );
+            }
+        }
+
+        let matched = match_backtraces( data, field, regex );
+        let result = is_matched( &matched, backtrace );
+        *self.matched.write() = Some( (data.id(), matched) );
+        result
+    }
+}
+
+fn evaluate_field( data: &Data, allocation: &Allocation, field: Field ) -> ColumnValue {
+    let seconds = |timestamp: crate::Timestamp| ColumnValue::Number( (timestamp - data.initial_timestamp()).as_usecs() as f64 / 1_000_000.0 );
+    match field {
+        Field::Size => ColumnValue::Number( allocation.size as f64 ),
+        Field::Address => ColumnValue::Number( allocation.pointer as f64 ),
+        Field::Timestamp => seconds( allocation.timestamp ),
+        Field::DeallocationTimestamp => allocation.deallocation.as_ref().map( |deallocation| seconds( deallocation.timestamp ) ).unwrap_or( ColumnValue::Null ),
+        Field::Lifetime => allocation.deallocation.as_ref().map( |deallocation| {
+            ColumnValue::Number( (deallocation.timestamp - allocation.timestamp).as_usecs() as f64 / 1_000_000.0 )
+        }).unwrap_or( ColumnValue::Null ),
+        Field::Thread => ColumnValue::Number( allocation.thread as f64 ),
+        Field::BacktraceId => ColumnValue::Number( allocation.backtrace.raw() as f64 ),
+        Field::BacktraceDepth => ColumnValue::Number( data.get_backtrace( allocation.backtrace ).len() as f64 ),
+        Field::Marker => ColumnValue::Number( allocation.marker as f64 ),
+        Field::IsLeaked => ColumnValue::Bool( allocation.deallocation.is_none() ),
+        Field::IsMmaped => ColumnValue::Bool( allocation.is_mmaped() ),
+        Field::InMainArena => ColumnValue::Bool( !allocation.in_non_main_arena() ),
+        Field::JemallocArena => allocation.jemalloc_arena().map( |arena| ColumnValue::Number( arena as f64 ) ).unwrap_or( ColumnValue::Null ),
+        Field::TopFrame => top_frame( data, allocation, false ),
+        Field::TopSource => top_frame( data, allocation, true )
+    }
+}
+
+#[test]
+fn test_tokenize() {
+    assert_eq!(
+        tokenize( r#"is_huge = size >= 1.5M; x="a\"b\d""# ).unwrap(),
+        vec![
+            Token::Identifier( "is_huge".to_owned() ),
+            Token::Symbol( "=" ),
+            Token::Identifier( "size".to_owned() ),
+            Token::Symbol( ">=" ),
+            Token::Number( 1.5 * 1024.0 * 1024.0 ),
+            Token::Symbol( ";" ),
+            Token::Identifier( "x".to_owned() ),
+            Token::Symbol( "=" ),
+            Token::String( r#"a"b\d"#.to_owned() )
+        ]
+    );
+
+    assert!( tokenize( r#""abc"# ).is_err() );
+
+    assert_eq!(
+        tokenize( r#"lifetime<1.5s && lifetime >= 250ms && function!~"x""# ).unwrap(),
+        vec![
+            Token::Identifier( "lifetime".to_owned() ),
+            Token::Symbol( "<" ),
+            Token::Number( 1.5 ),
+            Token::Symbol( "&&" ),
+            Token::Identifier( "lifetime".to_owned() ),
+            Token::Symbol( ">=" ),
+            Token::Number( 0.25 ),
+            Token::Symbol( "&&" ),
+            Token::Identifier( "function".to_owned() ),
+            Token::Symbol( "!~" ),
+            Token::String( "x".to_owned() )
+        ]
+    );
+
+    assert!( tokenize( "1KB" ).is_err() );
+    assert_eq!( tokenize( "2MiB" ).unwrap(), vec![ Token::Number( 2.0 * 1024.0 * 1024.0 ) ] );
+    assert_eq!( tokenize( "2min" ).unwrap(), vec![ Token::Number( 120.0 ) ] );
+    assert!( tokenize( "2m" ).is_err() );
+    assert!( tokenize( "2k" ).is_err() );
+}
+
+#[test]
+fn test_matches_any_frame() {
+    use std::io;
+    use crate::Timestamp;
+    use crate::importer::{ImportWriter, ImportedFrame};
+
+    let frame = |address, function: &str| ImportedFrame {
+        address,
+        function: Some( function.to_owned() ),
+        .. ImportedFrame::default()
+    };
+
+    let mut input = Vec::new();
+    let mut writer = ImportWriter::new( &mut input, &[ "./a.out" ], Timestamp::from_secs( 1 ) ).unwrap();
+    let foo = writer.backtrace( &[ frame( 0x10, "parse_foo" ), frame( 0x20, "main" ) ] ).unwrap();
+    let bar = writer.backtrace( &[ frame( 0x30, "bar" ), frame( 0x20, "main" ) ] ).unwrap();
+    writer.allocate( Timestamp::from_secs( 1 ), 0x1000, 100, foo ).unwrap();
+    writer.allocate( Timestamp::from_secs( 1 ), 0x2000, 100, bar ).unwrap();
+    writer.allocate( Timestamp::from_secs( 1 ), 0x3000, 100, foo ).unwrap();
+    writer.finish().unwrap();
+
+    let data = crate::Loader::load_from_stream_without_debug_info( io::Cursor::new( input ) ).unwrap();
+    let columns = VirtualColumns::default();
+    let evaluate = |expression: &Expression| -> Vec< bool > {
+        data.allocations().iter().map( |allocation| expression.evaluate( &data, allocation, &[] ).is_truthy() ).collect()
+    };
+
+    let expression = Expression::parse( r#"function ~ "^parse_""#, &columns, None ).unwrap();
+    assert_eq!( evaluate( &expression ), vec![ true, false, true ] );
+    assert_eq!( evaluate( &expression.clone() ), vec![ true, false, true ] );
+
+    let expression = Expression::parse( r#"function !~ "^ma""#, &columns, None ).unwrap();
+    assert_eq!( evaluate( &expression ), vec![ false, false, false ] );
+}
+
+#[test]
+fn test_parse_columns() {
+    let columns = VirtualColumns::parse( "lifetime_ms = lifetime * 1000; is_short = lifetime_ms < 10;", None ).unwrap();
+    assert_eq!( columns.names().collect::< Vec< _ > >(), vec![ "lifetime_ms", "is_short" ] );
+    assert!( Expression::parse( "is_short && !is_leaked", &columns, None ).is_ok() );
+
+    assert!( VirtualColumns::parse( "a = b; b = 1", None ).is_err() );
+    assert!( VirtualColumns::parse( "size = 1", None ).is_err() );
+    assert!( VirtualColumns::parse( "a = regex_extract(top_frame, \"(\")", None ).is_err() );
+    assert!( Expression::parse( "size > 1 1", &columns, None ).is_err() );
+
+    let columns = VirtualColumns::default();
+    assert!( Expression::parse( r#"size > 4096 && lifetime < 1s && function ~ "parse_""#, &columns, None ).is_ok() );
+    assert!( Expression::parse( r#"top_frame !~ "^std::" || source ~ "\.c$""#, &columns, None ).is_ok() );
+    assert!( Expression::parse( r#"function == "main""#, &columns, None ).is_err() );
+    assert!( Expression::parse( r#"size ~ 1"#, &columns, None ).is_err() );
+}
+
+#[test]
+fn test_parse_too_deeply_nested() {
+    let columns = VirtualColumns::default();
+    let parse = |input: String| Expression::parse( &input, &columns, None );
+    assert!( parse( format!( "{}1{}", "(".repeat( 32 ), ")".repeat( 32 ) ) ).is_ok() );
+    assert!( parse( format!( "{}1{}", "(".repeat( 100000 ), ")".repeat( 100000 ) ) ).is_err() );
+    assert!( parse( format!( "{}1", "-".repeat( 100000 ) ) ).is_err() );
+    assert!( parse( format!( "{}1", "!".repeat( 100000 ) ) ).is_err() );
+    assert!( parse( format!( "1{}", " + 1".repeat( 100000 ) ) ).is_err() );
+    assert!( parse( format!( "{}1{}", "if(true, ".repeat( 100000 ), ", 1)".repeat( 100000 ) ) ).is_err() );
+}
+
+#[test]
+fn test_apply() {
+    assert_eq!( apply( Operator::Greater, ColumnValue::Number( 2.0 ), ColumnValue::Number( 1.0 ) ), ColumnValue::Bool( true ) );
+    assert_eq!( apply( Operator::Less, ColumnValue::Null, ColumnValue::Number( 1.0 ) ), ColumnValue::Null );
+    assert_eq!( apply( Operator::Add, ColumnValue::String( "a".to_owned() ), ColumnValue::String( "b".to_owned() ) ), ColumnValue::String( "ab".to_owned() ) );
+    assert_eq!( apply( Operator::Divide, ColumnValue::Number( 1.0 ), ColumnValue::Number( 0.0 ) ), ColumnValue::Null );
+}
+
+fn apply( operator: Operator, lhs: ColumnValue, rhs: ColumnValue ) -> ColumnValue {
+    use std::cmp::Ordering;
+
+    let ordering = match (&lhs, &rhs) {
+        (&ColumnValue::Number( lhs ), &ColumnValue::Number( rhs )) => lhs.partial_cmp( &rhs ),
+        (&ColumnValue::String( ref lhs ), &ColumnValue::String( ref rhs )) => Some( lhs.cmp( rhs ) ),
+        (&ColumnValue::Bool( lhs ), &ColumnValue::Bool( rhs )) => Some( lhs.cmp( &rhs ) ),
+        _ => None
+    };
+
+    let compare = |check: fn( Ordering ) -> bool| ordering.map( |ordering| ColumnValue::Bool( check( ordering ) ) ).unwrap_or( ColumnValue::Null );
+    let arithmetic = |callback: fn( f64, f64 ) -> f64| match (&lhs, &rhs) {
+        (&ColumnValue::Number( lhs ), &ColumnValue::Number( rhs )) => ColumnValue::Number( callback( lhs, rhs ) ),
+        _ => ColumnValue::Null
+    };
+
+    match operator {
+        Operator::Or | Operator::And => unreachable!(),
+        Operator::Equal => compare( |ordering| ordering == Ordering::Equal ),
+        Operator::NotEqual => compare( |ordering| ordering != Ordering::Equal ),
+        Operator::Less => compare( |ordering| ordering == Ordering::Less ),
+        Operator::LessOrEqual => compare( |ordering| ordering != Ordering::Greater ),
+        Operator::Greater => compare( |ordering| ordering == Ordering::Greater ),
+        Operator::GreaterOrEqual => compare( |ordering| ordering != Ordering::Less ),
+        Operator::Add => match (&lhs, &rhs) {
+            (&ColumnValue::String( ref lhs ), &ColumnValue::String( ref rhs )) => ColumnValue::String( format!( "{}{}", lhs, rhs ) ),
+            _ => arithmetic( |lhs, rhs| lhs + rhs )
+        },
+        Operator::Subtract => arithmetic( |lhs, rhs| lhs - rhs ),
+        Operator::Multiply => arithmetic( |lhs, rhs| lhs * rhs ),
+        Operator::Divide => if rhs == ColumnValue::Number( 0.0 ) { ColumnValue::Null } else { arithmetic( |lhs, rhs| lhs / rhs ) },
+        Operator::Remainder => if rhs == ColumnValue::Number( 0.0 ) { ColumnValue::Null } else { arithmetic( |lhs, rhs| lhs % rhs ) }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Expression {
+    root: Node
+}
+
+impl Expression {
+    fn parse_tokens( parser: &mut Parser ) -> Result< Self, ExpressionError > {
+        let root = parser.parse_or()?;
+        Ok( Expression { root } )
+    }
+
+    /// Parses an expression which can refer to the given virtual columns.
+    pub fn parse( input: &str, columns: &VirtualColumns, regex_size_limit: Option< usize > ) -> Result< Self, ExpressionError > {
+        let mut parser = Parser::new( tokenize( input )?, columns.names().map( |name| name.to_owned() ).collect(), regex_size_limit );
+
+        let expression = Self::parse_tokens( &mut parser )?;
+        if let Some( token ) = parser.peek() {
+            return error( format!( "unexpected {:?}", token ) );
+        }
+
+        Ok( expression )
+    }
+
+    /// Evaluates the expression for a given allocation; `columns` are the values of the virtual columns it can refer to.
+    pub fn evaluate( &self, data: &Data, allocation: &Allocation, columns: &[ColumnValue] ) -> ColumnValue {
+        self.evaluate_node( &self.root, data, allocation, columns )
+    }
+
+    fn evaluate_node( &self, node: &Node, data: &Data, allocation: &Allocation, columns: &[ColumnValue] ) -> ColumnValue {
+        let evaluate = |node: &Node| self.evaluate_node( node, data, allocation, columns );
+        match *node {
+            Node::Literal( ref value ) => value.clone(),
+            Node::Field( field ) => evaluate_field( data, allocation, field ),
+            Node::Column( index ) => columns[ index ].clone(),
+            Node::Not( ref node ) => match evaluate( node ) {
+                ColumnValue::Null => ColumnValue::Null,
+                value => ColumnValue::Bool( !value.is_truthy() )
+            },
+            Node::Negate( ref node ) => match evaluate( node ) {
+                ColumnValue::Number( value ) => ColumnValue::Number( -value ),
+                _ => ColumnValue::Null
+            },
+            Node::Binary( Operator::Or, ref lhs, ref rhs ) => {
+                ColumnValue::Bool( evaluate( lhs ).is_truthy() || evaluate( rhs ).is_truthy() )
+            },
+            Node::Binary( Operator::And, ref lhs, ref rhs ) => {
+                ColumnValue::Bool( evaluate( lhs ).is_truthy() && evaluate( rhs ).is_truthy() )
+            },
+            Node::Binary( operator, ref lhs, ref rhs ) => apply( operator, evaluate( lhs ), evaluate( rhs ) ),
+            Node::RegexExtract( ref node, ref regex ) => match evaluate( node ) {
+                ColumnValue::String( string ) => regex.captures( &string )
+                    .and_then( |captures| captures.get( 1 ).or_else( || captures.get( 0 ) ) )
+                    .map( |capture| ColumnValue::String( capture.as_str().to_owned() ) )
+                    .unwrap_or( ColumnValue::Null ),
+                _ => ColumnValue::Null
+            },
+            Node::Matches( ref node, ref regex ) => match evaluate( node ) {
+                ColumnValue::String( string ) => ColumnValue::Bool( regex.is_match( &string ) ),
+                _ => ColumnValue::Null
+            },
+            // This can only end up here through something like `-function ~ "..."`.
+            Node::FrameField( _ ) => ColumnValue::Null,
+            Node::MatchesAnyFrame( field, ref regex, ref matched ) => ColumnValue::Bool( matched.contains( data, allocation.backtrace, field, regex ) ),
+            Node::If( ref condition, ref value, ref otherwise ) => {
+                if evaluate( condition ).is_truthy() {
+                    evaluate( value )
+                } else {
+                    evaluate( otherwise )
+                }
+            }
+        }
+    }
+}
+
+/// A list of `name = expression` definitions separated by semicolons, where every
+/// expression can also refer to the columns which were defined before it.
+#[derive(Clone, Default, Debug)]
+pub struct VirtualColumns {
+    columns: Vec< (String, Expression) >
+}
+
+impl VirtualColumns {
+    pub fn parse( input: &str, regex_size_limit: Option< usize > ) -> Result< Self, ExpressionError > {
+        let mut parser = Parser::new( tokenize( input )?, Vec::new(), regex_size_limit );
+
+        let mut columns: Vec< (String, Expression) > = Vec::new();
+        while parser.peek().is_some() {
+            if parser.eat( ";" ) {
+                continue;
+            }
+
+            let name = match parser.next() {
+                Some( Token::Identifier( name ) ) => name,
+                Some( token ) => return error( format!( "expected a column name, found {:?}", token ) ),
+                None => unreachable!()
+            };
+
+            if FIELDS.iter().any( |&(field_name, _)| field_name == name ) || parser.columns.contains( &name ) {
+                return error( format!( "duplicate column: '{}'", name ) );
+            }
+
+            parser.expect( "=" )?;
+            let expression = Expression::parse_tokens( &mut parser )?;
+            parser.columns.push( name.clone() );
+            columns.push( (name, expression) );
+
+            if parser.peek().is_some() {
+                parser.expect( ";" )?;
+            }
+        }
+
+        Ok( VirtualColumns { columns } )
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.columns.is_empty()
+    }
+
+    pub fn names( &self ) -> impl ExactSizeIterator< Item = &str > {
+        self.columns.iter().map( |(name, _)| name.as_str() )
+    }
+
+    pub fn index_of( &self, name: &str ) -> Option< usize > {
+        self.columns.iter().position( |(column_name, _)| column_name == name )
+    }
+
+    /// Evaluates every column for a given allocation.
+    pub fn evaluate( &self, data: &Data, allocation: &Allocation ) -> Vec< ColumnValue > {
+        let mut values = Vec::with_capacity( self.columns.len() );
+        for (_, expression) in &self.columns {
+            let value = expression.evaluate( data, allocation, &values );
+            values.push( value );
+        }
+
+        values
+    }
+}
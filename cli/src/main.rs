@@ -3,20 +3,112 @@ extern crate log;
 
 use std::process;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::io;
 use std::fs::File;
 use std::error::Error;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 use structopt::StructOpt;
 
 use cli_core::{
+    Allocation,
+    Attribution,
+    Data,
+    FilteredData,
+    Follower,
     Loader,
+    SymbolSources,
+    Timestamp,
+    IoAdapter,
     export_as_replay,
     export_as_heaptrack,
+    export_as_pprof,
+    export_as_massif,
+    export_as_flamegraph,
+    export_as_flamegraph_pl,
+    import_heaptrack,
+    import_massif,
+    import_jemalloc,
     postprocess
 };
 
+use cli_core::cmd_report::ReportFormat;
+
+#[derive(StructOpt, Debug)]
+struct SymbolOpts {
+    /// A file or directory with extra debugging symbols; can be specified multiple times
+    #[structopt(short = "d", long = "debug-symbols", parse(from_os_str))]
+    debug_symbols: Vec< PathBuf >,
+    /// A directory with a copy of the profiled machine's filesystem; can be specified multiple times
+    #[structopt(long = "sysroot", parse(from_os_str))]
+    sysroot: Vec< PathBuf >,
+    /// A file or directory with binaries matched to the profiled libraries by build ID or name; can be specified multiple times
+    #[structopt(long = "symbol-path", parse(from_os_str))]
+    symbol_path: Vec< PathBuf >,
+    /// A container image (an OCI image layout, an OCI or `docker save` archive, a reference to an image known to the local
+    /// Docker daemon, `containerd://<reference>` or `registry://<reference>`) from which the binaries are extracted; can be specified multiple times
+    #[structopt(long = "image")]
+    image: Vec< String >,
+    /// A directory where the binaries extracted from the container images are kept
+    #[structopt(long = "image-cache", parse(from_os_str))]
+    image_cache: Option< PathBuf >
+}
+
+impl SymbolOpts {
+    fn into_symbol_sources( self ) -> io::Result< SymbolSources > {
+        let mut symbol_paths = self.symbol_path;
+        let image_cache = self.image_cache.unwrap_or_else( cli_core::default_image_cache );
+        for image in &self.image {
+            symbol_paths.push( cli_core::extract_binaries_from_image( image, &image_cache )? );
+        }
+
+        Ok( SymbolSources {
+            debug_symbols: self.debug_symbols,
+            sysroots: self.sysroot,
+            symbol_paths
+        })
+    }
+}
+
+/// Which of the allocations are included in a flamegraph.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum FlamegraphKind {
+    All,
+    Leaked,
+    Temporary,
+    Peak
+}
+
+impl FromStr for FlamegraphKind {
+    type Err = String;
+    fn from_str( value: &str ) -> Result< Self, Self::Err > {
+        match value {
+            "all" => Ok( FlamegraphKind::All ),
+            "leaked" => Ok( FlamegraphKind::Leaked ),
+            "temporary" => Ok( FlamegraphKind::Temporary ),
+            "peak" => Ok( FlamegraphKind::Peak ),
+            _ => Err( format!( "invalid kind '{}'; expected 'all', 'leaked', 'temporary' or 'peak'", value ) )
+        }
+    }
+}
+
+impl FlamegraphKind {
+    fn matches( self, data: &Data, allocation: &Allocation ) -> bool {
+        match self {
+            FlamegraphKind::All => true,
+            FlamegraphKind::Leaked => !allocation.was_deallocated(),
+            FlamegraphKind::Temporary => allocation.was_deallocated(),
+            FlamegraphKind::Peak => {
+                let peak = data.peak_allocated_timestamp();
+                allocation.timestamp <= peak && allocation.deallocation.as_ref().map( |deallocation| deallocation.timestamp > peak ).unwrap_or( true )
+            }
+        }
+    }
+}
+
 #[derive(StructOpt, Debug)]
 enum Opt {
     /// Generates a raw data file which can be used to replay all of the allocations
@@ -27,44 +119,369 @@ enum Opt {
         #[structopt(parse(from_os_str))]
         input: PathBuf
     },
+    /// Converts a file produced by heaptrack into a data file
+    #[structopt(name = "import-heaptrack")]
+    ImportHeaptrack {
+        /// The file to which the converted data will be written
+        #[structopt(long, short = "o", parse(from_os_str))]
+        output: PathBuf,
+
+        /// A heaptrack data file; either uncompressed or gzipped
+        #[structopt(parse(from_os_str), required = false)]
+        input: PathBuf
+    },
+    /// Converts the output of Valgrind's massif into a data file
+    #[structopt(name = "import-massif")]
+    ImportMassif {
+        /// The file to which the converted data will be written
+        #[structopt(long, short = "o", parse(from_os_str))]
+        output: PathBuf,
+
+        /// A `massif.out.<pid>` file
+        #[structopt(parse(from_os_str), required = false)]
+        input: PathBuf
+    },
+    /// Converts a heap profile dumped by jemalloc into a data file
+    #[structopt(name = "import-jemalloc")]
+    ImportJemalloc {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+
+        /// The file to which the converted data will be written
+        #[structopt(long, short = "o", parse(from_os_str))]
+        output: PathBuf,
+
+        /// A `.heap` file
+        #[structopt(parse(from_os_str), required = false)]
+        input: PathBuf
+    },
     /// Generates a raw data file which can be loaded into heaptrack GUI
     #[structopt(name = "export-heaptrack")]
     ExportHeaptrack {
-        /// A file or directory with extra debugging symbols; can be specified multiple times
-        #[structopt(short = "d", long = "debug-symbols", parse(from_os_str))]
-        debug_symbols: Vec< PathBuf >,
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// A file with rules used to rename, collapse or drop frames
+        #[structopt(long = "frame-rules", parse(from_os_str))]
+        frame_rules: Option< PathBuf >,
+        /// To which frame the allocations are attributed: `innermost-inline`, `outermost-non-inline` or `outside:<library>,...`
+        #[structopt(long = "attribute-to", default_value = "innermost-inline")]
+        attribute_to: Attribution,
+        /// Virtual columns of the form `<name> = <expression>; ...` which can be used in the `--filter`
+        #[structopt(long = "columns")]
+        columns: Option< String >,
+        /// An expression which every exported allocation has to match, e.g. `size > 1M && !is_leaked`
+        #[structopt(long = "filter")]
+        filter: Option< String >,
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: PathBuf,
+        #[structopt(parse(from_os_str))]
+        input: PathBuf
+    },
+    /// Generates a gzipped pprof profile which can be viewed with `go tool pprof` or Speedscope
+    #[structopt(name = "export-pprof")]
+    ExportPprof {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// A file with rules used to rename, collapse or drop frames
+        #[structopt(long = "frame-rules", parse(from_os_str))]
+        frame_rules: Option< PathBuf >,
+        /// To which frame the allocations are attributed: `innermost-inline`, `outermost-non-inline` or `outside:<library>,...`
+        #[structopt(long = "attribute-to", default_value = "innermost-inline")]
+        attribute_to: Attribution,
+        /// Virtual columns of the form `<name> = <expression>; ...` which can be used in the `--filter`
+        #[structopt(long = "columns")]
+        columns: Option< String >,
+        /// An expression which every exported allocation has to match, e.g. `size > 1M && !is_leaked`
+        #[structopt(long = "filter")]
+        filter: Option< String >,
         #[structopt(short = "o", long = "output", parse(from_os_str))]
         output: PathBuf,
         #[structopt(parse(from_os_str))]
         input: PathBuf
     },
+    /// Generates a series of snapshots in massif's format which can be viewed with `ms_print` or massif-visualizer
+    #[structopt(name = "export-massif")]
+    ExportMassif {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// A file with rules used to rename, collapse or drop frames
+        #[structopt(long = "frame-rules", parse(from_os_str))]
+        frame_rules: Option< PathBuf >,
+        /// To which frame the allocations are attributed: `innermost-inline`, `outermost-non-inline` or `outside:<library>,...`
+        #[structopt(long = "attribute-to", default_value = "innermost-inline")]
+        attribute_to: Attribution,
+        /// Virtual columns of the form `<name> = <expression>; ...` which can be used in the `--filter`
+        #[structopt(long = "columns")]
+        columns: Option< String >,
+        /// An expression which every exported allocation has to match, e.g. `size > 1M && !is_leaked`
+        #[structopt(long = "filter")]
+        filter: Option< String >,
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: PathBuf,
+        #[structopt(parse(from_os_str))]
+        input: PathBuf
+    },
+    /// Generates an SVG flamegraph, or a file with collapsed stacks for `flamegraph.pl`
+    #[structopt(name = "flamegraph")]
+    Flamegraph {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// A file with rules used to rename, collapse or drop frames
+        #[structopt(long = "frame-rules", parse(from_os_str))]
+        frame_rules: Option< PathBuf >,
+        /// To which frame the allocations are attributed: `innermost-inline`, `outermost-non-inline` or `outside:<library>,...`
+        #[structopt(long = "attribute-to", default_value = "innermost-inline")]
+        attribute_to: Attribution,
+        /// Which allocations to include: `all`, `leaked`, `temporary` (the ones which were deallocated)
+        /// or `peak` (the ones which were alive when the memory usage was at its peak)
+        #[structopt(long = "kind", default_value = "all")]
+        kind: FlamegraphKind,
+        /// Collapses every recursive cycle into the outermost call of the recursion
+        #[structopt(long = "collapse-recursion")]
+        collapse_recursion: bool,
+        /// Writes the collapsed stacks instead of an SVG
+        #[structopt(long = "collapsed")]
+        collapsed: bool,
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: PathBuf,
+        #[structopt(parse(from_os_str))]
+        input: PathBuf
+    },
+    /// Generates an SQLite database with all of the processed data
+    #[cfg(feature = "sqlite")]
+    #[structopt(name = "export-sqlite")]
+    ExportSqlite {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// A file with rules used to rename, collapse or drop frames
+        #[structopt(long = "frame-rules", parse(from_os_str))]
+        frame_rules: Option< PathBuf >,
+        /// To which frame the allocations are attributed: `innermost-inline`, `outermost-non-inline` or `outside:<library>,...`
+        #[structopt(long = "attribute-to", default_value = "innermost-inline")]
+        attribute_to: Attribution,
+        /// Virtual columns of the form `<name> = <expression>; ...` to export for every allocation
+        #[structopt(long = "columns")]
+        columns: Option< String >,
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: PathBuf,
+        #[structopt(parse(from_os_str))]
+        input: PathBuf
+    },
+    /// Periodically prints a summary of a data file which is still being written to
+    #[structopt(name = "follow")]
+    Follow {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// How often, in seconds, to check for new data
+        #[structopt(long = "interval", default_value = "5")]
+        interval: u64,
+        #[structopt(parse(from_os_str))]
+        input: PathBuf
+    },
+    /// Answers queries about the live allocations of a data file which is still being written to, one per line of the standard input;
+    /// used by the debugger integration
+    #[structopt(name = "inspect")]
+    Inspect {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        #[structopt(parse(from_os_str))]
+        input: PathBuf
+    },
+    /// Serves the statistics of the allocations made by every line of the source code through JSON-RPC on the standard input and output;
+    /// meant to be launched by an editor extension
+    #[structopt(name = "editor-server")]
+    EditorServer {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// A file with rules used to rename, collapse or drop frames
+        #[structopt(long = "frame-rules", parse(from_os_str))]
+        frame_rules: Option< PathBuf >,
+        /// To which frame the allocations are attributed: `innermost-inline`, `outermost-non-inline` or `outside:<library>,...`
+        #[structopt(long = "attribute-to", default_value = "innermost-inline")]
+        attribute_to: Attribution,
+        #[structopt(parse(from_os_str))]
+        input: PathBuf
+    },
+    /// Generates a self-contained HTML or markdown report of the backtraces which leaked the most memory
+    #[structopt(name = "report")]
+    Report {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// A file with rules used to rename, collapse or drop frames
+        #[structopt(long = "frame-rules", parse(from_os_str))]
+        frame_rules: Option< PathBuf >,
+        /// To which frame the allocations are attributed: `innermost-inline`, `outermost-non-inline` or `outside:<library>,...`
+        #[structopt(long = "attribute-to", default_value = "innermost-inline")]
+        attribute_to: Attribution,
+        /// The format of the report: `html` or `markdown`
+        #[structopt(long = "format", default_value = "html")]
+        format: ReportFormat,
+        /// How many of the backtraces which leaked the most are included
+        #[structopt(long = "count", default_value = "20")]
+        count: usize,
+        /// The file to which the report will be written; it's printed out if not specified
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option< PathBuf >,
+        #[structopt(parse(from_os_str))]
+        input: PathBuf
+    },
     /// Gathers memory tracking data from a given machine
     #[structopt(name = "gather")]
     Gather {
         target: Option< String >
     },
+    /// Prints a JSON summary of the live allocations of a process with the embedded server enabled
+    #[structopt(name = "live-summary")]
+    LiveSummary {
+        /// The address of the embedded server, e.g. `localhost:8100`
+        target: String
+    },
     /// Launches a server with all of the data exposed through a REST API
     #[cfg(feature = "subcommand-server")]
     #[structopt(name = "server")]
     Server {
-        /// A file or directory with extra debugging symbols; can be specified multiple times
-        #[structopt(short = "d", long = "debug-symbols", parse(from_os_str))]
-        debug_symbols: Vec< PathBuf >,
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// A file with rules used to rename, collapse or drop frames
+        #[structopt(long = "frame-rules", parse(from_os_str))]
+        frame_rules: Option< PathBuf >,
+        /// To which frame the allocations are attributed: `innermost-inline` (the default), `outermost-non-inline` or `outside:<library>,...`
+        #[structopt(long = "attribute-to")]
+        attribute_to: Option< Attribution >,
+        /// The maximum number of queries which can run at the same time
+        #[structopt(long = "max-concurrent-queries")]
+        max_concurrent_queries: Option< usize >,
+        /// The number of seconds after which a query is aborted
+        #[structopt(long = "query-timeout")]
+        query_timeout: Option< u64 >,
+        /// The maximum number of bytes the regexes and the intermediate results of a single query can use
+        #[structopt(long = "query-memory-budget")]
+        query_memory_budget: Option< usize >,
+        /// The maximum number of megabytes the loaded allocations can take in memory before they're spilled to disk
+        #[structopt(long = "memory-budget")]
+        memory_budget: Option< u64 >,
+        /// A directory where the decoded symbols are cached across restarts, keyed by the build IDs of the binaries
+        #[structopt(long = "symbol-cache", parse(from_os_str))]
+        symbol_cache: Option< PathBuf >,
+        /// A shared library with extra analyses to load into the server; can be specified multiple times
+        #[structopt(long = "plugin", parse(from_os_str))]
+        plugin: Vec< PathBuf >,
+        /// A core dump of a profiled process, which makes the contents of its live allocations available; can be specified multiple times
+        #[structopt(long = "core", parse(from_os_str))]
+        core: Vec< PathBuf >,
+        /// A file where the loaded data files, the symbol options, the sessions and the annotations are saved,
+        /// and from which they're restored when the server is started again
+        #[structopt(long = "workspace", parse(from_os_str))]
+        workspace: Option< PathBuf >,
+        /// Loads only the given shard of the allocations, e.g. `0/4`; used by the workers of a sharded analysis
+        #[structopt(long = "shard")]
+        shard: Option< cli_core::Shard >,
+        /// Splits the analysis across the given number of local worker processes
+        #[structopt(long = "shards")]
+        shards: Option< u32 >,
+        /// The address of a worker which was started with `--shard`; can be specified multiple times
+        #[structopt(long = "worker")]
+        worker: Vec< String >,
         /// The network interface on which to start the HTTP server
         #[structopt(short = "i", long = "interface", default_value = "127.0.0.1")]
         interface: String,
         /// The port on which to start the HTTP server
         #[structopt(short = "p", long = "port", default_value = "8080")]
         port: u16,
+        /// The address on which to accept the data streamed by processes started with `MEMORY_PROFILER_STREAM_TO`, e.g. `0.0.0.0:8200`
+        #[structopt(long = "listen")]
+        listen: Option< String >,
+        /// A file with the token which the streaming processes have to send through `MEMORY_PROFILER_STREAM_TOKEN`;
+        /// required unless `--listen` is on a loopback address
+        #[structopt(long = "listen-token-file", parse(from_os_str))]
+        listen_token_file: Option< PathBuf >,
         #[structopt(parse(from_os_str), required = false)]
         input: Vec< PathBuf >
     },
+    /// Checks the memory usage of a data file against a baseline of per-site budgets; meant to be used in CI
+    #[cfg(feature = "subcommand-server")]
+    #[structopt(name = "check")]
+    Check {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// A file with rules used to rename, collapse or drop frames
+        #[structopt(long = "frame-rules", parse(from_os_str))]
+        frame_rules: Option< PathBuf >,
+        /// The baseline file with the per-site budgets
+        #[structopt(long = "baseline", parse(from_os_str))]
+        baseline: PathBuf,
+        /// Writes a new baseline based on the data file instead of checking it
+        #[structopt(long = "update-baseline")]
+        update_baseline: bool,
+        /// By how many percent the usage can grow before going over the budgets of a newly written baseline
+        #[structopt(long = "tolerance", default_value = "10")]
+        tolerance: f64,
+        /// How many of the biggest sites get their own budgets in a newly written baseline
+        #[structopt(long = "baseline-sites", default_value = "50")]
+        baseline_sites: usize,
+        /// Writes the results as JSON into the given file
+        #[structopt(long = "json-output", parse(from_os_str))]
+        json_output: Option< PathBuf >,
+        /// Writes the markdown summary into the given file instead of printing it out
+        #[structopt(long = "markdown-output", parse(from_os_str))]
+        markdown_output: Option< PathBuf >,
+        /// The URL of a server with the same data file loaded; the summary will link to the backtraces on it
+        #[structopt(long = "server-url")]
+        server_url: Option< String >,
+        #[structopt(parse(from_os_str))]
+        input: PathBuf
+    },
+    /// Pushes the metrics and the profile of a data file to an OpenTelemetry collector over OTLP/HTTP
+    #[cfg(feature = "subcommand-server")]
+    #[structopt(name = "push-otlp")]
+    PushOtlp {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// A file with rules used to rename, collapse or drop frames
+        #[structopt(long = "frame-rules", parse(from_os_str))]
+        frame_rules: Option< PathBuf >,
+        /// The base URL of the collector's OTLP/HTTP receiver, e.g. `http://localhost:4318`
+        #[structopt(long = "collector")]
+        collector: String,
+        #[structopt(parse(from_os_str))]
+        input: PathBuf
+    },
+    /// Compares two data files and shows how the allocations of every site have changed
+    #[cfg(feature = "subcommand-server")]
+    #[structopt(name = "diff")]
+    Diff {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// A file with rules used to rename, collapse or drop frames
+        #[structopt(long = "frame-rules", parse(from_os_str))]
+        frame_rules: Option< PathBuf >,
+        /// To which frame the allocations are attributed: `innermost-inline`, `outermost-non-inline` or `outside:<library>,...`
+        #[structopt(long = "attribute-to", default_value = "innermost-inline")]
+        attribute_to: Attribution,
+        /// How many of the sites which have changed the most are shown
+        #[structopt(long = "count", default_value = "20")]
+        count: usize,
+        /// By which delta the sites are sorted: `leaked_size`, `leaked_count`, `allocated_size`, `allocated_count`, `freed_size` or `freed_count`
+        #[structopt(long = "sort-by", default_value = "leaked_size")]
+        sort_by: server_core::DiffSortBy,
+        /// Also shows the sites which haven't changed at all
+        #[structopt(long = "include-unchanged")]
+        include_unchanged: bool,
+        /// Writes the whole comparison as JSON into the given file
+        #[structopt(long = "json-output", parse(from_os_str))]
+        json_output: Option< PathBuf >,
+        /// The data file to compare against, e.g. from before the change
+        #[structopt(parse(from_os_str))]
+        base: PathBuf,
+        /// The data file which is compared, e.g. from after the change
+        #[structopt(parse(from_os_str))]
+        target: PathBuf
+    },
     /// Generates a new data file with all of the stack traces decoded and deduplicated
     #[structopt(name = "postprocess")]
     Postprocess {
-        /// A file or directory with extra debugging symbols; can be specified multiple times
-        #[structopt(short = "d", long = "debug-symbols", parse(from_os_str))]
-        debug_symbols: Vec< PathBuf >,
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
 
         /// The file to which the postprocessed data will be written
         #[structopt(long, short = "o", parse(from_os_str))]
@@ -73,6 +490,19 @@ enum Opt {
         #[structopt(parse(from_os_str), required = false)]
         input: PathBuf
     },
+    /// Generates a new data file with the symbols of all of the stack traces embedded in it
+    #[structopt(name = "embed-symbols")]
+    EmbedSymbols {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+
+        /// The file to which the data with embedded symbols will be written
+        #[structopt(long, short = "o", parse(from_os_str))]
+        output: PathBuf,
+
+        #[structopt(parse(from_os_str), required = false)]
+        input: PathBuf
+    },
     /// Generates a new data file with temporary allocations stripped away
     #[structopt(name = "squeeze")]
     Squeeze {
@@ -87,11 +517,26 @@ enum Opt {
         #[structopt(parse(from_os_str), required = false)]
         input: PathBuf
     },
+    /// Writes the index files of the given data files ahead of time, so that any subcommand can load them without reprocessing
+    #[structopt(name = "index")]
+    Index {
+        #[structopt(flatten)]
+        symbols: SymbolOpts,
+        /// The maximum number of megabytes the loaded allocations can take in memory before they're spilled to disk
+        #[structopt(long = "memory-budget")]
+        memory_budget: Option< u64 >,
+        #[structopt(parse(from_os_str), required = true)]
+        input: Vec< PathBuf >
+    },
     #[structopt(name = "repack", raw(setting = "structopt::clap::AppSettings::Hidden"))]
     Repack {
         #[structopt(long)]
         disable_compression: bool,
 
+        /// Writes the data in the seekable format (version 2)
+        #[structopt(long)]
+        format_v2: bool,
+
         #[structopt(long, short = "o", parse(from_os_str))]
         output: PathBuf,
 
@@ -104,6 +549,96 @@ enum Opt {
     }
 }
 
+/// Starts the local workers of a sharded analysis with the same arguments as this process
+/// and returns their addresses; they listen on the ports right after the coordinator's.
+///
+/// The inputs are split into the `split_directory` first, so that every worker only has
+/// to load its own part of them.
+#[cfg(feature = "subcommand-server")]
+fn spawn_workers( count: u32, port: u16, inputs: &[PathBuf], split_directory: &Path ) -> Result< Vec< String >, Box< dyn Error > > {
+    if count == 0 {
+        return Err( "the number of shards must be greater than zero".into() );
+    }
+
+    std::fs::create_dir_all( split_directory )?;
+    let mut inputs_by_shard = vec![ Vec::new(); count as usize ];
+    for (nth, input) in inputs.iter().enumerate() {
+        info!( "Splitting {:?} into {} shards...", input, count );
+        let paths: Vec< _ > = (0..count).map( |index| split_directory.join( format!( "{}-{}.dat", nth, index ) ) ).collect();
+        let outputs = paths.iter().map( File::create ).collect::< Result< Vec< _ >, _ > >()?;
+        cli_core::split_into_shards( File::open( input )?, outputs )
+            .map_err( |error| format!( "failed to split {:?}: {}; only the native captures can be split into shards", input, error ) )?;
+
+        for (index, path) in paths.into_iter().enumerate() {
+            inputs_by_shard[ index ].push( path );
+        }
+    }
+
+    let mut args = Vec::new();
+    let mut skip_value = false;
+    for arg in env::args_os().skip( 1 ) {
+        if skip_value {
+            skip_value = false;
+            continue;
+        }
+
+        let text = arg.to_string_lossy();
+        if text == "--shards" || text == "--port" || text == "-p" || text == "--interface" || text == "-i" {
+            skip_value = true;
+            continue;
+        }
+
+        if text.starts_with( "--shards=" ) || text.starts_with( "--port=" ) || text.starts_with( "--interface=" ) {
+            continue;
+        }
+
+        // Every worker gets its own part of the inputs instead.
+        if inputs.iter().any( |input| input.as_os_str() == arg ) {
+            continue;
+        }
+
+        args.push( arg );
+    }
+
+    let mut children = Vec::new();
+    let mut addresses = Vec::new();
+    for index in 0..count {
+        let worker_port = port as u32 + 1 + index;
+        if worker_port > std::u16::MAX as u32 {
+            return Err( "not enough free ports after the one specified with `--port`".into() );
+        }
+
+        info!( "Starting worker {}/{} on port {}...", index, count, worker_port );
+        let child = process::Command::new( env::current_exe()? )
+            .args( &args )
+            .arg( "--shard" ).arg( format!( "{}/{}", index, count ) )
+            .arg( "--interface" ).arg( "127.0.0.1" )
+            .arg( "--port" ).arg( worker_port.to_string() )
+            .args( &inputs_by_shard[ index as usize ] )
+            .spawn()?;
+
+        children.push( child );
+        addresses.push( format!( "127.0.0.1:{}", worker_port ) );
+    }
+
+    // The coordinator is useless without all of its workers.
+    for mut child in children {
+        thread::spawn( move || {
+            let status = child.wait();
+            error!( "Worker process {} has exited: {:?}", child.id(), status );
+            process::exit( 1 );
+        });
+    }
+
+    Ok( addresses )
+}
+
+/// Used as the start of the profiling for the imported files which don't record the wall clock time.
+fn modification_time( path: &Path ) -> io::Result< Timestamp > {
+    let elapsed = std::fs::metadata( path )?.modified()?.duration_since( SystemTime::UNIX_EPOCH ).unwrap_or_default();
+    Ok( Timestamp::from_usecs( elapsed.as_micros() as u64 ) )
+}
+
 fn run( opt: Opt ) -> Result< (), Box< dyn Error > > {
     match opt {
         Opt::ExportReplay { output, input } => {
@@ -114,35 +649,220 @@ fn run( opt: Opt ) -> Result< (), Box< dyn Error > > {
 
             export_as_replay( &data, data_out, |_| true )?;
         },
-        Opt::ExportHeaptrack { debug_symbols, output, input } => {
-            let fp = File::open( input )?;
-            let data = Loader::load_from_stream( fp, debug_symbols )?;
-            let data_out = File::create( output )?;
-            let data_out = io::BufWriter::new( data_out );
+        Opt::ImportHeaptrack { output, input } => {
+            let ifp = io::BufReader::new( File::open( &input )? );
+            let ofp = File::create( output )?;
+            import_heaptrack( ifp, ofp, modification_time( &input )? )?;
+        },
+        Opt::ImportMassif { output, input } => {
+            let ifp = io::BufReader::new( File::open( &input )? );
+            let ofp = File::create( output )?;
+            import_massif( ifp, ofp, modification_time( &input )? )?;
+        },
+        Opt::ImportJemalloc { symbols, output, input } => {
+            let ifp = io::BufReader::new( File::open( &input )? );
+            let ofp = File::create( output )?;
+            import_jemalloc( ifp, ofp, modification_time( &input )?, &symbols.into_symbol_sources()? )?;
+        },
+        Opt::ExportHeaptrack { symbols, frame_rules, attribute_to, columns, filter, output, input } => {
+            let filtered = FilteredData::load( &input, &symbols.into_symbol_sources()?, frame_rules.as_deref(), &attribute_to, columns.as_deref(), filter.as_deref() )?;
+            let data_out = io::BufWriter::new( File::create( output )? );
+            export_as_heaptrack( &filtered.data, data_out, |allocation| filtered.matches( allocation ) )?;
+        },
+        Opt::ExportPprof { symbols, frame_rules, attribute_to, columns, filter, output, input } => {
+            let filtered = FilteredData::load( &input, &symbols.into_symbol_sources()?, frame_rules.as_deref(), &attribute_to, columns.as_deref(), filter.as_deref() )?;
+            let data_out = io::BufWriter::new( File::create( output )? );
+            export_as_pprof( &filtered.data, data_out, |allocation| filtered.matches( allocation ) )?;
+        },
+        Opt::ExportMassif { symbols, frame_rules, attribute_to, columns, filter, output, input } => {
+            let filtered = FilteredData::load( &input, &symbols.into_symbol_sources()?, frame_rules.as_deref(), &attribute_to, columns.as_deref(), filter.as_deref() )?;
+            let data_out = io::BufWriter::new( File::create( output )? );
+            export_as_massif( &filtered.data, data_out, |allocation| filtered.matches( allocation ) )?;
+        },
+        Opt::Flamegraph { symbols, frame_rules, attribute_to, kind, collapse_recursion, collapsed, output, input } => {
+            let data = FilteredData::load( &input, &symbols.into_symbol_sources()?, frame_rules.as_deref(), &attribute_to, None, None )?.data;
+            let mut data_out = io::BufWriter::new( File::create( output )? );
 
-            export_as_heaptrack( &data, data_out, |_| true )?;
+            let filter = |allocation: &Allocation| kind.matches( &data, allocation );
+            if collapsed {
+                export_as_flamegraph_pl( &data, IoAdapter::new( &mut data_out ), filter, collapse_recursion )
+                    .map_err( |_| io::Error::new( io::ErrorKind::Other, "failed to write the collapsed stacks" ) )?;
+            } else {
+                export_as_flamegraph( &data, IoAdapter::new( &mut data_out ), filter, collapse_recursion );
+            }
+
+            io::Write::flush( &mut data_out )?;
+        },
+        #[cfg(feature = "sqlite")]
+        Opt::ExportSqlite { symbols, frame_rules, attribute_to, columns, output, input } => {
+            let filtered = FilteredData::load( &input, &symbols.into_symbol_sources()?, frame_rules.as_deref(), &attribute_to, columns.as_deref(), None )?;
+            cli_core::export_as_sqlite( &filtered.data, filtered.columns(), output )?;
+        },
+        Opt::Follow { symbols, interval, input } => {
+            let mut follower = Follower::new( input, &symbols.into_symbol_sources()? )?;
+            loop {
+                if follower.poll()? != 0 {
+                    if let Some( data ) = follower.snapshot() {
+                        let live: Vec< _ > = data.allocations().iter().filter( |allocation| !allocation.was_deallocated() ).collect();
+                        info!(
+                            "Elapsed: {}s, allocated: {} ({} bytes), freed: {} ({} bytes), live: {} ({} bytes), peak: {} bytes",
+                            (data.last_timestamp() - data.initial_timestamp()).as_secs(),
+                            data.total_allocated_count(),
+                            data.total_allocated(),
+                            data.total_freed_count(),
+                            data.total_freed(),
+                            live.len(),
+                            live.iter().map( |allocation| allocation.size ).sum::< u64 >(),
+                            data.peak_allocated()
+                        );
+                    }
+                }
+
+                thread::sleep( Duration::from_secs( interval ) );
+            }
+        },
+        Opt::Inspect { symbols, input } => {
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+            cli_core::cmd_inspect::inspect( input, &symbols.into_symbol_sources()?, stdin.lock(), stdout.lock() )?;
+        },
+        Opt::EditorServer { symbols, frame_rules, attribute_to, input } => {
+            let data = FilteredData::load( &input, &symbols.into_symbol_sources()?, frame_rules.as_deref(), &attribute_to, None, None )?.data;
+
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+            cli_core::cmd_editor_server::editor_server( &data, stdin.lock(), stdout.lock() )?;
+        },
+        Opt::Report { symbols, frame_rules, attribute_to, format, count, output, input } => {
+            let data = FilteredData::load( &input, &symbols.into_symbol_sources()?, frame_rules.as_deref(), &attribute_to, None, None )?.data;
+
+            match output {
+                Some( output ) => {
+                    let mut data_out = io::BufWriter::new( File::create( output )? );
+                    cli_core::cmd_report::report( &data, format, count, &mut data_out )?;
+                    io::Write::flush( &mut data_out )?;
+                },
+                None => {
+                    let stdout = io::stdout();
+                    cli_core::cmd_report::report( &data, format, count, stdout.lock() )?;
+                }
+            }
         },
         Opt::Gather { target } => {
             cli_core::cmd_gather::main( target.as_ref().map( |target| target.as_str() ) )?;
         },
+        Opt::LiveSummary { target } => {
+            let summary = cli_core::cmd_gather::live_summary( target.as_str() )?;
+            println!( "{}", summary );
+        },
         #[cfg(feature = "subcommand-server")]
-        Opt::Server { debug_symbols, input, interface, port } => {
-            server_core::main( input, debug_symbols, false, &interface, port )?;
+        Opt::Server { symbols, frame_rules, attribute_to, max_concurrent_queries, query_timeout, query_memory_budget, memory_budget, symbol_cache, plugin, core, workspace, shard, shards, mut worker, input, interface, port, listen, listen_token_file } => {
+            if workspace.is_some() && (shard.is_some() || shards.is_some() || !worker.is_empty()) {
+                return Err( "workspaces can't be used with a sharded analysis".into() );
+            }
+
+            if listen.is_some() && (shard.is_some() || shards.is_some() || !worker.is_empty()) {
+                return Err( "live streams can't be used with a sharded analysis".into() );
+            }
+
+            // This extracts the images before any workers are spawned, so that they don't all do it at the same time.
+            let symbol_sources = symbols.into_symbol_sources()?;
+            let split_directory = env::temp_dir().join( format!( "memory-profiler-shards-{}", process::id() ) );
+            if let Some( shards ) = shards {
+                worker.extend( spawn_workers( shards, port, &input, &split_directory )? );
+            }
+
+            if !worker.is_empty() {
+                let result = server_core::coordinator_main( worker, &interface, port );
+                let _ = std::fs::remove_dir_all( &split_directory );
+                result?;
+                return Ok(());
+            }
+
+            if let Some( memory_budget ) = memory_budget {
+                cli_core::set_memory_budget( memory_budget * 1024 * 1024 );
+            }
+
+            if let Some( symbol_cache ) = symbol_cache {
+                cli_core::set_symbol_cache_directory( symbol_cache );
+            }
+
+            let limits = server_core::QueryLimits {
+                max_concurrent_queries,
+                timeout: query_timeout.map( std::time::Duration::from_secs ),
+                memory_budget: query_memory_budget
+            };
+
+            server_core::main( input, symbol_sources, frame_rules, attribute_to, plugin, core, workspace, limits, false, shard, &interface, port, listen, listen_token_file )?;
+        },
+        #[cfg(feature = "subcommand-server")]
+        Opt::Check { symbols, frame_rules, baseline, update_baseline, tolerance, baseline_sites, json_output, markdown_output, server_url, input } => {
+            let options = server_core::CheckOptions {
+                baseline,
+                update_baseline,
+                tolerance,
+                baseline_sites,
+                json_output,
+                markdown_output,
+                server_url
+            };
+
+            if !server_core::check_main( input, symbols.into_symbol_sources()?, frame_rules, options )? {
+                process::exit( 1 );
+            }
         },
-        Opt::Postprocess { debug_symbols, output, input } => {
+        #[cfg(feature = "subcommand-server")]
+        Opt::PushOtlp { symbols, frame_rules, collector, input } => {
+            server_core::push_otlp_main( input, symbols.into_symbol_sources()?, frame_rules, &collector )?;
+        },
+        #[cfg(feature = "subcommand-server")]
+        Opt::Diff { symbols, frame_rules, attribute_to, count, sort_by, include_unchanged, json_output, base, target } => {
+            let options = server_core::DiffOptions {
+                count,
+                sort_by,
+                include_unchanged,
+                json_output
+            };
+
+            server_core::diff_main( base, target, symbols.into_symbol_sources()?, frame_rules, attribute_to, options )?;
+        },
+        Opt::Postprocess { symbols, output, input } => {
             let ifp = File::open( input )?;
             let ofp = File::create( output )?;
-            postprocess( ifp, ofp, debug_symbols )?;
+            postprocess( ifp, ofp, &symbols.into_symbol_sources()? )?;
+        },
+        Opt::EmbedSymbols { symbols, output, input } => {
+            let ofp = File::create( output )?;
+            cli_core::embed_symbols( input, ofp, &symbols.into_symbol_sources()? )?;
         },
         Opt::Squeeze { output, input, threshold } => {
             let ifp = File::open( &input )?;
             let ofp = File::create( output )?;
             cli_core::squeeze_data( ifp, ofp, threshold )?;
         },
-        Opt::Repack { disable_compression, input, output } => {
+        Opt::Index { symbols, memory_budget, input } => {
+            if let Some( memory_budget ) = memory_budget {
+                cli_core::set_memory_budget( memory_budget * 1024 * 1024 );
+            }
+
+            let symbol_sources = symbols.into_symbol_sources()?;
+            for path in input {
+                info!( "Indexing {:?}...", path );
+                Loader::load_from_file( &path, &symbol_sources )?;
+            }
+        },
+        Opt::Repack { disable_compression, format_v2, input, output } => {
             let ifp = File::open( &input )?;
             let ofp = File::create( output )?;
-            cli_core::repack( disable_compression, ifp, ofp )?;
+            if format_v2 {
+                if disable_compression {
+                    return Err( "the seekable format is always compressed".into() );
+                }
+
+                cli_core::repack_v2( ifp, io::BufWriter::new( ofp ) )?;
+            } else {
+                cli_core::repack( disable_compression, ifp, ofp )?;
+            }
         },
         Opt::AnalyzeSize { input } => {
             let ifp = File::open( &input )?;
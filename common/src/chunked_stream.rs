@@ -0,0 +1,528 @@
+//! The second version of the on-disk format.
+//!
+//! Like the original format the file is a sequence of `[kind: u8][length: u32][payload]` chunks,
+//! but every data chunk is compressed independently, contains only whole events and starts
+//! with a short summary of what's inside it along with a checksum of the whole chunk, so a damaged
+//! chunk can be skipped without affecting the rest of the file. The file ends with a seek index of all of the data
+//! chunks followed by a fixed size footer pointing to it, so any part of the file can be read
+//! without scanning it from the start. If the file is truncated (and the index is missing)
+//! the index can be rebuilt by walking over the chunk headers. The profiler itself never writes
+//! the index, since the process can die at any point; `repack` can be used to add it.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian, ByteOrder};
+use crc32fast::Hasher;
+use lz4_compress;
+use speedy::Writable;
+
+use crate::event::Event;
+use crate::timestamp::Timestamp;
+
+pub const CHUNK_KIND_LZ4: u8 = 1;
+pub const CHUNK_KIND_RAW: u8 = 2;
+pub const CHUNK_KIND_V2_HEADER: u8 = 3;
+pub const CHUNK_KIND_V2_DATA: u8 = 4;
+pub const CHUNK_KIND_V2_INDEX: u8 = 5;
+pub const CHUNK_KIND_V2_FOOTER: u8 = 6;
+
+pub const CHUNK_HEADER_SIZE: usize = 5;
+
+const V2_MAGIC: &[u8; 4] = b"MPv2";
+const V2_VERSION: u32 = 3;
+const V2_HEADER_PAYLOAD_SIZE: usize = 8;
+const DATA_CHUNK_SUMMARY_SIZE: usize = 28;
+const DATA_CHUNK_CHECKSUM_OFFSET: usize = 24;
+const INDEX_ENTRY_SIZE: usize = 32;
+const FOOTER_SIZE: usize = CHUNK_HEADER_SIZE + 8;
+const CHUNK_SIZE: usize = 512 * 1024;
+
+/// Describes what a given chunk contains.
+pub enum ChunkContents {
+    /// LZ4 compressed data starting at a given offset of the payload.
+    Compressed( usize ),
+    /// Uncompressed data starting at a given offset of the payload.
+    Uncompressed( usize ),
+    /// Metadata which doesn't contain any events.
+    Metadata,
+    /// A data chunk which failed its checksum; according to its (possibly also damaged)
+    /// summary it contained a given number of events.
+    Damaged( u32 )
+}
+
+/// Calculates the checksum of a data chunk's payload, skipping the checksum itself.
+fn data_chunk_checksum( payload: &[u8] ) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update( &payload[ ..DATA_CHUNK_CHECKSUM_OFFSET ] );
+    hasher.update( &payload[ DATA_CHUNK_CHECKSUM_OFFSET + 4.. ] );
+    hasher.finalize()
+}
+
+fn is_data_chunk_intact( payload: &[u8] ) -> bool {
+    LittleEndian::read_u32( &payload[ DATA_CHUNK_CHECKSUM_OFFSET.. ] ) == data_chunk_checksum( payload )
+}
+
+/// Figures out what a chunk of either version of the format contains.
+pub fn chunk_contents( kind: u8, payload: &[u8] ) -> io::Result< ChunkContents > {
+    match kind {
+        CHUNK_KIND_LZ4 => Ok( ChunkContents::Compressed( 0 ) ),
+        CHUNK_KIND_RAW => Ok( ChunkContents::Uncompressed( 0 ) ),
+        CHUNK_KIND_V2_HEADER => {
+            if payload.len() != V2_HEADER_PAYLOAD_SIZE || &payload[ ..4 ] != V2_MAGIC {
+                return Err( io::Error::new( io::ErrorKind::InvalidData, "malformed format header" ) );
+            }
+
+            let version = LittleEndian::read_u32( &payload[ 4.. ] );
+            if version != V2_VERSION {
+                return Err( io::Error::new( io::ErrorKind::InvalidData, format!( "unsupported format version: {}", version ) ) );
+            }
+
+            Ok( ChunkContents::Metadata )
+        },
+        CHUNK_KIND_V2_DATA => {
+            if payload.len() < DATA_CHUNK_SUMMARY_SIZE {
+                return Err( io::Error::new( io::ErrorKind::InvalidData, "malformed data chunk" ) );
+            }
+
+            if !is_data_chunk_intact( payload ) {
+                return Ok( ChunkContents::Damaged( LittleEndian::read_u32( &payload[ 4..8 ] ) ) );
+            }
+
+            Ok( ChunkContents::Compressed( DATA_CHUNK_SUMMARY_SIZE ) )
+        },
+        CHUNK_KIND_V2_INDEX | CHUNK_KIND_V2_FOOTER => Ok( ChunkContents::Metadata ),
+        _ => Err( io::Error::new( io::ErrorKind::InvalidData, format!( "unexpected chunk kind: {}", kind ) ) )
+    }
+}
+
+/// An entry of the seek index; describes a single data chunk.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChunkIndexEntry {
+    /// The offset of the chunk's header in the file.
+    pub offset: u64,
+    /// The length of the chunk's payload.
+    pub length: u32,
+    pub event_count: u32,
+    pub first_timestamp: Timestamp,
+    pub last_timestamp: Timestamp
+}
+
+impl ChunkIndexEntry {
+    fn read_summary( offset: u64, length: u32, summary: &[u8] ) -> Self {
+        ChunkIndexEntry {
+            offset,
+            length,
+            event_count: LittleEndian::read_u32( &summary[ 4..8 ] ),
+            first_timestamp: Timestamp::from_usecs( LittleEndian::read_u64( &summary[ 8..16 ] ) ),
+            last_timestamp: Timestamp::from_usecs( LittleEndian::read_u64( &summary[ 16..24 ] ) )
+        }
+    }
+}
+
+/// The seek index of a file in the second version of the format.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChunkIndex {
+    pub entries: Vec< ChunkIndexEntry >,
+    /// Whether the index had to be rebuilt because the file wasn't properly finished.
+    pub was_recovered: bool
+}
+
+impl ChunkIndex {
+    /// Loads the index from the end of the file, or rebuilds it if it's missing.
+    pub fn load< F: Read + Seek >( fp: &mut F ) -> io::Result< Self > {
+        fp.seek( SeekFrom::Start( 0 ) )?;
+        let mut header = [0; CHUNK_HEADER_SIZE + V2_HEADER_PAYLOAD_SIZE];
+        fp.read_exact( &mut header )?;
+        if header[ 0 ] != CHUNK_KIND_V2_HEADER {
+            return Err( io::Error::new( io::ErrorKind::InvalidData, "not a seekable data file" ) );
+        }
+        chunk_contents( header[ 0 ], &header[ CHUNK_HEADER_SIZE.. ] )?;
+
+        match Self::load_from_footer( fp ) {
+            Ok( Some( entries ) ) => return Ok( ChunkIndex { entries, was_recovered: false } ),
+            Ok( None ) => {},
+            Err( ref error ) if error.kind() == io::ErrorKind::UnexpectedEof || error.kind() == io::ErrorKind::InvalidData => {},
+            Err( error ) => return Err( error )
+        }
+
+        let entries = Self::rebuild( fp, header.len() as u64 )?;
+        Ok( ChunkIndex { entries, was_recovered: true } )
+    }
+
+    fn load_from_footer< F: Read + Seek >( fp: &mut F ) -> io::Result< Option< Vec< ChunkIndexEntry > > > {
+        let file_length = fp.seek( SeekFrom::End( 0 ) )?;
+        if file_length < FOOTER_SIZE as u64 {
+            return Ok( None );
+        }
+
+        fp.seek( SeekFrom::Start( file_length - FOOTER_SIZE as u64 ) )?;
+        let kind = fp.read_u8()?;
+        let length = fp.read_u32::< LittleEndian >()?;
+        if kind != CHUNK_KIND_V2_FOOTER || length != 8 {
+            return Ok( None );
+        }
+
+        let index_offset = fp.read_u64::< LittleEndian >()?;
+        fp.seek( SeekFrom::Start( index_offset ) )?;
+        let kind = fp.read_u8()?;
+        let length = fp.read_u32::< LittleEndian >()? as usize;
+        if kind != CHUNK_KIND_V2_INDEX || length < 4 {
+            return Ok( None );
+        }
+
+        let count = fp.read_u32::< LittleEndian >()? as usize;
+        if length != 4 + count * INDEX_ENTRY_SIZE {
+            return Ok( None );
+        }
+
+        let mut entries = Vec::with_capacity( count );
+        for _ in 0..count {
+            entries.push( ChunkIndexEntry {
+                offset: fp.read_u64::< LittleEndian >()?,
+                length: fp.read_u32::< LittleEndian >()?,
+                event_count: fp.read_u32::< LittleEndian >()?,
+                first_timestamp: Timestamp::from_usecs( fp.read_u64::< LittleEndian >()? ),
+                last_timestamp: Timestamp::from_usecs( fp.read_u64::< LittleEndian >()? )
+            });
+        }
+
+        Ok( Some( entries ) )
+    }
+
+    fn rebuild< F: Read + Seek >( fp: &mut F, mut offset: u64 ) -> io::Result< Vec< ChunkIndexEntry > > {
+        let file_length = fp.seek( SeekFrom::End( 0 ) )?;
+        fp.seek( SeekFrom::Start( offset ) )?;
+
+        let mut entries = Vec::new();
+        let mut summary = [0; DATA_CHUNK_SUMMARY_SIZE];
+        while offset + CHUNK_HEADER_SIZE as u64 <= file_length {
+            let kind = fp.read_u8()?;
+            let length = fp.read_u32::< LittleEndian >()?;
+            let next_offset = offset + CHUNK_HEADER_SIZE as u64 + length as u64;
+            if next_offset > file_length {
+                break;
+            }
+
+            match kind {
+                CHUNK_KIND_V2_DATA if length as usize >= DATA_CHUNK_SUMMARY_SIZE => {
+                    fp.read_exact( &mut summary )?;
+                    entries.push( ChunkIndexEntry::read_summary( offset, length, &summary ) );
+                },
+                // A stream which was forwarded from the profiler can have more than one header.
+                CHUNK_KIND_V2_HEADER | CHUNK_KIND_V2_INDEX | CHUNK_KIND_V2_FOOTER => {},
+                _ => break
+            }
+
+            fp.seek( SeekFrom::Start( next_offset ) )?;
+            offset = next_offset;
+        }
+
+        Ok( entries )
+    }
+
+    /// Returns the data chunks which might contain events from a given time range.
+    pub fn chunks_in_range( &self, range: Range< Timestamp > ) -> impl Iterator< Item = &ChunkIndexEntry > {
+        self.entries.iter().filter( move |entry| entry.first_timestamp < range.end && entry.last_timestamp >= range.start )
+    }
+}
+
+/// Reads and decompresses the events contained in a given data chunk.
+///
+/// Returns an `InvalidData` error if the chunk is damaged.
+pub fn read_chunk< F: Read + Seek >( fp: &mut F, entry: &ChunkIndexEntry ) -> io::Result< Vec< u8 > > {
+    fp.seek( SeekFrom::Start( entry.offset + CHUNK_HEADER_SIZE as u64 ) )?;
+    let mut payload = vec![ 0; entry.length as usize ];
+    fp.read_exact( &mut payload )?;
+    if payload.len() < DATA_CHUNK_SUMMARY_SIZE || !is_data_chunk_intact( &payload ) {
+        return Err( io::Error::new( io::ErrorKind::InvalidData, format!( "data chunk at offset {} is damaged", entry.offset ) ) );
+    }
+
+    let uncompressed_length = LittleEndian::read_u32( &payload[ ..4 ] ) as usize;
+    let mut output = Vec::with_capacity( uncompressed_length );
+    lz4_compress::decompress_into( &payload[ DATA_CHUNK_SUMMARY_SIZE.. ], &mut output )
+        .map_err( |_| io::Error::new( io::ErrorKind::InvalidData, "decompression error" ) )?;
+
+    Ok( output )
+}
+
+/// Writes events in the second version of the format.
+pub struct ChunkedWriter< F: Write > {
+    fp: Option< F >,
+    offset: u64,
+    buffer: Vec< u8 >,
+    compression_buffer: Vec< u8 >,
+    event_count: u32,
+    first_timestamp: Option< Timestamp >,
+    last_timestamp: Timestamp,
+    index: Option< Vec< ChunkIndexEntry > >
+}
+
+fn write_header< F: Write >( fp: &mut F ) -> io::Result< () > {
+    fp.write_u8( CHUNK_KIND_V2_HEADER )?;
+    fp.write_u32::< LittleEndian >( V2_HEADER_PAYLOAD_SIZE as u32 )?;
+    fp.write_all( V2_MAGIC )?;
+    fp.write_u32::< LittleEndian >( V2_VERSION )
+}
+
+impl< F: Write > ChunkedWriter< F > {
+    fn with_index( fp: F, offset: u64, index: Option< Vec< ChunkIndexEntry > > ) -> Self {
+        ChunkedWriter {
+            fp: Some( fp ),
+            offset,
+            buffer: Vec::new(),
+            compression_buffer: Vec::new(),
+            event_count: 0,
+            first_timestamp: None,
+            last_timestamp: Timestamp::min(),
+            index
+        }
+    }
+
+    pub fn new( mut fp: F ) -> io::Result< Self > {
+        write_header( &mut fp )?;
+        Ok( Self::with_index( fp, (CHUNK_HEADER_SIZE + V2_HEADER_PAYLOAD_SIZE) as u64, Some( Vec::new() ) ) )
+    }
+
+    /// Creates a writer for a stream which can be cut off at any point, like the one written
+    /// by the profiler itself; it never writes the seek index, which has to be rebuilt when the stream is loaded.
+    pub fn new_stream( mut fp: F ) -> io::Result< Self > {
+        write_header( &mut fp )?;
+        Ok( Self::with_index( fp, 0, None ) )
+    }
+
+    /// Like `new_stream`, but for continuing a stream which was already started.
+    pub fn continue_stream( fp: F ) -> Self {
+        Self::with_index( fp, 0, None )
+    }
+
+    pub fn write_event( &mut self, event: &Event ) -> io::Result< () > {
+        event.write_to_stream( &mut self.buffer )?;
+        self.event_count += 1;
+        if let Some( timestamp ) = event.timestamp() {
+            if self.first_timestamp.is_none() {
+                self.first_timestamp = Some( timestamp );
+            }
+            self.last_timestamp = timestamp;
+        }
+
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush_chunk()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush_chunk( &mut self ) -> io::Result< () > {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let first_timestamp = self.first_timestamp.take().unwrap_or( self.last_timestamp );
+        self.compression_buffer.clear();
+        self.compression_buffer.resize( DATA_CHUNK_SUMMARY_SIZE, 0 );
+        LittleEndian::write_u32( &mut self.compression_buffer[ 0..4 ], self.buffer.len() as u32 );
+        LittleEndian::write_u32( &mut self.compression_buffer[ 4..8 ], self.event_count );
+        LittleEndian::write_u64( &mut self.compression_buffer[ 8..16 ], first_timestamp.as_usecs() );
+        LittleEndian::write_u64( &mut self.compression_buffer[ 16..24 ], self.last_timestamp.as_usecs() );
+        lz4_compress::compress_into( &self.buffer, &mut self.compression_buffer );
+        let checksum = data_chunk_checksum( &self.compression_buffer );
+        LittleEndian::write_u32( &mut self.compression_buffer[ DATA_CHUNK_CHECKSUM_OFFSET.. ], checksum );
+
+        let fp = self.fp.as_mut().unwrap();
+        fp.write_u8( CHUNK_KIND_V2_DATA )?;
+        fp.write_u32::< LittleEndian >( self.compression_buffer.len() as u32 )?;
+        fp.write_all( &self.compression_buffer )?;
+
+        if let Some( ref mut index ) = self.index {
+            index.push( ChunkIndexEntry::read_summary( self.offset, self.compression_buffer.len() as u32, &self.compression_buffer ) );
+        }
+
+        self.offset += (CHUNK_HEADER_SIZE + self.compression_buffer.len()) as u64;
+        self.buffer.clear();
+        self.event_count = 0;
+
+        Ok(())
+    }
+
+    fn write_index( &mut self ) -> io::Result< () > {
+        let index = match self.index {
+            Some( ref index ) => index,
+            None => return self.fp.as_mut().unwrap().flush()
+        };
+
+        let index_offset = self.offset;
+        let fp = self.fp.as_mut().unwrap();
+        fp.write_u8( CHUNK_KIND_V2_INDEX )?;
+        fp.write_u32::< LittleEndian >( (4 + index.len() * INDEX_ENTRY_SIZE) as u32 )?;
+        fp.write_u32::< LittleEndian >( index.len() as u32 )?;
+        for entry in index {
+            fp.write_u64::< LittleEndian >( entry.offset )?;
+            fp.write_u32::< LittleEndian >( entry.length )?;
+            fp.write_u32::< LittleEndian >( entry.event_count )?;
+            fp.write_u64::< LittleEndian >( entry.first_timestamp.as_usecs() )?;
+            fp.write_u64::< LittleEndian >( entry.last_timestamp.as_usecs() )?;
+        }
+
+        fp.write_u8( CHUNK_KIND_V2_FOOTER )?;
+        fp.write_u32::< LittleEndian >( 8 )?;
+        fp.write_u64::< LittleEndian >( index_offset )?;
+        fp.flush()
+    }
+
+    /// Writes out the buffered events as a chunk, even if it's not full yet.
+    pub fn flush( &mut self ) -> io::Result< () > {
+        self.flush_chunk()?;
+        self.fp.as_mut().unwrap().flush()
+    }
+
+    pub fn inner( &self ) -> &F {
+        self.fp.as_ref().unwrap()
+    }
+
+    pub fn inner_mut_without_flush( &mut self ) -> &mut F {
+        self.fp.as_mut().unwrap()
+    }
+
+    pub fn inner_mut( &mut self ) -> io::Result< &mut F > {
+        self.flush()?;
+        Ok( self.fp.as_mut().unwrap() )
+    }
+
+    /// Flushes the buffered events and continues the stream with another output.
+    pub fn replace_inner( &mut self, fp: F ) -> io::Result< () > {
+        self.flush()?;
+        self.fp = Some( fp );
+        Ok(())
+    }
+
+    /// Flushes the buffered events and returns the output without writing the seek index.
+    pub fn into_inner( mut self ) -> io::Result< F > {
+        self.flush()?;
+        Ok( self.fp.take().unwrap() )
+    }
+
+    /// Writes out the remaining events along with the seek index.
+    pub fn finish( mut self ) -> io::Result< F > {
+        self.flush_chunk()?;
+        self.write_index()?;
+        Ok( self.fp.take().unwrap() )
+    }
+}
+
+impl< F: Write > Drop for ChunkedWriter< F > {
+    fn drop( &mut self ) {
+        if self.fp.is_some() {
+            let _ = self.flush_chunk().and_then( |_| self.write_index() );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use speedy::Readable;
+    use super::*;
+
+    fn marker_events( count: u32 ) -> Vec< Event< 'static > > {
+        (0..count).map( |index| {
+            if index % 2 == 0 {
+                Event::Marker { value: index }
+            } else {
+                Event::WallClock { timestamp: Timestamp::from_secs( index as u64 ), sec: 0, nsec: 0 }
+            }
+        }).collect()
+    }
+
+    fn write_events( events: &[Event] ) -> Vec< u8 > {
+        let mut writer = ChunkedWriter::new( Vec::new() ).unwrap();
+        for event in events {
+            writer.write_event( event ).unwrap();
+        }
+
+        writer.finish().unwrap()
+    }
+
+    fn read_events( data: &[u8], index: &ChunkIndex ) -> Vec< Event< 'static > > {
+        let mut fp = Cursor::new( data );
+        let mut events = Vec::new();
+        for entry in &index.entries {
+            let chunk = read_chunk( &mut fp, entry ).unwrap();
+            let mut chunk = Cursor::new( chunk );
+            for _ in 0..entry.event_count {
+                events.push( Event::read_from_stream_unbuffered( &mut chunk ).unwrap() );
+            }
+        }
+
+        events
+    }
+
+    #[test]
+    fn test_chunked_stream_roundtrip() {
+        let events = marker_events( 200000 );
+        let data = write_events( &events );
+
+        let index = ChunkIndex::load( &mut Cursor::new( &data ) ).unwrap();
+        assert!( !index.was_recovered );
+        assert!( index.entries.len() > 1 );
+        assert_eq!( read_events( &data, &index ), events );
+
+        let range = Timestamp::from_secs( 1000 )..Timestamp::from_secs( 1001 );
+        assert_eq!( index.chunks_in_range( range ).count(), 1 );
+    }
+
+    #[test]
+    fn test_chunked_stream_recovery() {
+        let events = marker_events( 200000 );
+        let data = write_events( &events );
+        let full_index = ChunkIndex::load( &mut Cursor::new( &data ) ).unwrap();
+
+        let truncated = &data[ ..full_index.entries[ 1 ].offset as usize + 10 ];
+        let index = ChunkIndex::load( &mut Cursor::new( truncated ) ).unwrap();
+        assert!( index.was_recovered );
+        assert_eq!( index.entries, &full_index.entries[ ..1 ] );
+    }
+
+    #[test]
+    fn test_chunked_stream_damaged_chunk() {
+        let events = marker_events( 200000 );
+        let mut data = write_events( &events );
+        let index = ChunkIndex::load( &mut Cursor::new( &data ) ).unwrap();
+
+        let entry = &index.entries[ 1 ];
+        let position = entry.offset as usize + CHUNK_HEADER_SIZE + DATA_CHUNK_SUMMARY_SIZE + 100;
+        data[ position ] ^= 0xFF;
+
+        let payload = &data[ entry.offset as usize + CHUNK_HEADER_SIZE..entry.offset as usize + CHUNK_HEADER_SIZE + entry.length as usize ];
+        match chunk_contents( CHUNK_KIND_V2_DATA, payload ).unwrap() {
+            ChunkContents::Damaged( event_count ) => assert_eq!( event_count, entry.event_count ),
+            _ => panic!( "the damaged chunk wasn't detected" )
+        }
+
+        let mut fp = Cursor::new( &data );
+        assert_eq!( read_chunk( &mut fp, entry ).unwrap_err().kind(), io::ErrorKind::InvalidData );
+        assert!( read_chunk( &mut fp, &index.entries[ 0 ] ).is_ok() );
+        assert!( read_chunk( &mut fp, &index.entries[ 2 ] ).is_ok() );
+    }
+
+    #[test]
+    fn test_chunked_stream_without_index() {
+        let events = marker_events( 200000 );
+        let (head, tail) = events.split_at( 1000 );
+
+        let mut writer = ChunkedWriter::new_stream( Vec::new() ).unwrap();
+        for event in head {
+            writer.write_event( event ).unwrap();
+        }
+
+        let mut writer = ChunkedWriter::continue_stream( writer.into_inner().unwrap() );
+        for event in tail {
+            writer.write_event( event ).unwrap();
+        }
+
+        let data = writer.finish().unwrap();
+        let index = ChunkIndex::load( &mut Cursor::new( &data ) ).unwrap();
+        assert!( index.was_recovered );
+        assert_eq!( read_events( &data, &index ), events );
+    }
+}
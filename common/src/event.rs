@@ -8,6 +8,10 @@ use crate::timestamp::Timestamp;
 
 pub const HEADER_FLAG_IS_LITTLE_ENDIAN: u64 = 1;
 
+/// The path of the `Event::File` which contains the build IDs of the loaded libraries,
+/// one `<hex build ID> <path>` per line.
+pub const BUILD_IDS_PATH: &str = "[build-ids]";
+
 #[derive(Clone, PartialEq, Debug, Readable, Writable)]
 pub struct HeaderBody {
     pub id: DataId,
@@ -65,6 +69,11 @@ fn test_data_id_string_conversions() {
 pub const ALLOC_FLAG_JEMALLOC: u32 = 1 << 30;
 pub const ALLOC_FLAG_CALLOC: u32 = 1 << 31;
 
+/// The jemalloc arena which was explicitly requested through `MALLOCX_ARENA`, plus one;
+/// zero if jemalloc was left to pick the arena by itself.
+pub const ALLOC_FLAG_JEMALLOC_ARENA_SHIFT: u32 = 16;
+pub const ALLOC_FLAG_JEMALLOC_ARENA_MASK: u32 = 0xfff << ALLOC_FLAG_JEMALLOC_ARENA_SHIFT;
+
 // These are the same as glibc's allocator flags.
 pub const ALLOC_FLAG_PREV_IN_USE: u32 = 1;
 pub const ALLOC_FLAG_MMAPED: u32 = 2;
@@ -112,6 +121,33 @@ pub struct AllocBody {
     pub preceding_free_space: u64
 }
 
+/// A single frame of an embedded symbol table entry; the string fields are indexes
+/// into the table's strings, with `0xFFFFFFFF` meaning that the field is missing.
+#[derive(Clone, PartialEq, Debug, Readable, Writable)]
+pub struct SymbolTableFrame {
+    pub raw_function: u32,
+    pub function: u32,
+    pub source: u32,
+    pub line: u32,
+    pub column: u32,
+    pub is_inline: bool
+}
+
+/// The frames an address resolves to, starting from the innermost one.
+#[derive(Clone, PartialEq, Debug, Readable, Writable)]
+pub struct SymbolTableEntry {
+    /// The address relative to the library, as if it was mapped at its file offsets.
+    pub address: u64,
+    pub frames: Vec< SymbolTableFrame >
+}
+
+/// An environment variable through which the allocator was tuned.
+#[derive(Clone, PartialEq, Debug, Readable, Writable)]
+pub struct AllocatorTunable {
+    pub name: String,
+    pub value: String
+}
+
 #[derive(Clone, PartialEq, Debug, Readable, Writable)]
 pub enum Event< 'a > {
     Header( HeaderBody ),
@@ -242,6 +278,84 @@ pub enum Event< 'a > {
         backtrace: u64,
         thread: u32
     },
+    AllocatorStats {
+        timestamp: Timestamp,
+        heap_size: u64,
+        heap_used: u64,
+        heap_free: u64,
+        mmaped: u64,
+        resident: u64
+    },
+    SymbolTable {
+        build_id: Cow< 'a, [u8] >,
+        library: Cow< 'a, str >,
+        strings: Vec< Cow< 'a, str > >,
+        entries: Vec< SymbolTableEntry >
+    },
+    AllocatorInfo {
+        allocator: Cow< 'a, str >,
+        allocator_version: Cow< 'a, str >,
+        libc_version: Cow< 'a, str >,
+        tunables: Vec< AllocatorTunable >
+    },
+    AllocationContents {
+        id: AllocationId,
+        pointer: u64,
+        contents: Cow< 'a, [u8] >
+    },
+    ResidentMemory {
+        timestamp: Timestamp,
+        anonymous: u64,
+        file: u64,
+        shared: u64
+    },
+    MemoryRemap {
+        timestamp: Timestamp,
+        old_pointer: u64,
+        old_length: u64,
+        pointer: u64,
+        length: u64,
+        flags: u32,
+        backtrace: u64,
+        thread: u32
+    },
+}
+
+impl< 'a > Event< 'a > {
+    /// Returns the timestamp of the event, if it has one.
+    pub fn timestamp( &self ) -> Option< Timestamp > {
+        match *self {
+            Event::Header( ref header ) => Some( header.timestamp ),
+            Event::Alloc { timestamp, .. } |
+            Event::Realloc { timestamp, .. } |
+            Event::Free { timestamp, .. } |
+            Event::File { timestamp, .. } |
+            Event::MemoryMap { timestamp, .. } |
+            Event::MemoryUnmap { timestamp, .. } |
+            Event::MemoryRemap { timestamp, .. } |
+            Event::Mallopt { timestamp, .. } |
+            Event::WallClock { timestamp, .. } |
+            Event::AllocEx { timestamp, .. } |
+            Event::ReallocEx { timestamp, .. } |
+            Event::FreeEx { timestamp, .. } |
+            Event::AllocatorStats { timestamp, .. } |
+            Event::ResidentMemory { timestamp, .. } => Some( timestamp ),
+            Event::Backtrace { .. } |
+            Event::MemoryDump { .. } |
+            Event::Marker { .. } |
+            Event::Environ { .. } |
+            Event::PartialBacktrace { .. } |
+            Event::String { .. } |
+            Event::DecodedFrame { .. } |
+            Event::DecodedBacktrace { .. } |
+            Event::GroupStatistics { .. } |
+            Event::PartialBacktrace32 { .. } |
+            Event::Backtrace32 { .. } |
+            Event::SymbolTable { .. } |
+            Event::AllocatorInfo { .. } |
+            Event::AllocationContents { .. } => None
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
@@ -1,12 +1,16 @@
 pub extern crate speedy;
 
 mod os_util;
+mod metadata;
 mod timestamp;
 
 pub mod event;
 pub mod lz4_stream;
+pub mod chunked_stream;
 pub mod request;
 pub mod range_map;
+pub mod timeline;
 
 pub use crate::os_util::get_local_ips;
+pub use crate::metadata::{METADATA_PREFIX, parse_metadata};
 pub use crate::timestamp::Timestamp;
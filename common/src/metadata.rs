@@ -0,0 +1,27 @@
+/// The prefix of the environment variables whose values are stored as the capture's metadata.
+pub const METADATA_PREFIX: &str = "MEMORY_PROFILER_METADATA_";
+
+/// Picks out the `MEMORY_PROFILER_METADATA_<KEY>=<value>` entries from the profiled process' environment;
+/// the rest of the environment isn't kept since it might contain secrets.
+pub fn parse_metadata( entry: &[u8] ) -> Option< (String, String) > {
+    let entry = String::from_utf8_lossy( entry );
+    if !entry.starts_with( METADATA_PREFIX ) {
+        return None;
+    }
+
+    let index = entry.find( '=' )?;
+    let key = &entry[ METADATA_PREFIX.len()..index ];
+    if key.is_empty() {
+        return None;
+    }
+
+    Some( (key.to_lowercase(), entry[ index + 1.. ].to_owned()) )
+}
+
+#[test]
+fn test_parse_metadata() {
+    assert_eq!( parse_metadata( b"MEMORY_PROFILER_METADATA_POD_NAME=api-7d9f" ), Some( ("pod_name".to_owned(), "api-7d9f".to_owned()) ) );
+    assert_eq!( parse_metadata( b"MEMORY_PROFILER_METADATA_LABELS=a=b" ), Some( ("labels".to_owned(), "a=b".to_owned()) ) );
+    assert_eq!( parse_metadata( b"MEMORY_PROFILER_METADATA_=x" ), None );
+    assert_eq!( parse_metadata( b"PATH=/usr/bin" ), None );
+}
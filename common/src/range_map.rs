@@ -3,6 +3,8 @@ use std::cmp::{Ordering, min, max};
 use std::iter;
 use std::slice;
 
+use speedy::{Readable, Writable, Context, Reader, Writer};
+
 trait RangeExt< T: PartialOrd > {
     fn includes( &self, point: T ) -> bool;
     fn is_outside_of( &self, range: &Range< T > ) -> bool;
@@ -186,6 +188,34 @@ impl< T > IntoIterator for RangeMap< T > {
     }
 }
 
+impl< 'a, C: Context, T: Readable< 'a, C > > Readable< 'a, C > for RangeMap< T > {
+    fn read_from< R: Reader< 'a, C > >( reader: &mut R ) -> Result< Self, C::Error > {
+        let length = reader.read_u64()? as usize;
+        let mut values = Vec::with_capacity( length );
+        for _ in 0..length {
+            let start = reader.read_u64()?;
+            let end = reader.read_u64()?;
+            let value = T::read_from( reader )?;
+            values.push( (start..end, value) );
+        }
+
+        Ok( RangeMap { values } )
+    }
+}
+
+impl< C: Context, T: Writable< C > > Writable< C > for RangeMap< T > {
+    fn write_to< W: ?Sized + Writer< C > >( &self, writer: &mut W ) -> Result< (), C::Error > {
+        writer.write_u64( self.values.len() as u64 )?;
+        for &(ref range, ref value) in &self.values {
+            writer.write_u64( range.start )?;
+            writer.write_u64( range.end )?;
+            writer.write_value( value )?;
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::RangeMap;
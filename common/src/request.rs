@@ -1,15 +1,66 @@
 use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
 use speedy::{Readable, Writable};
 use crate::timestamp::Timestamp;
 use crate::event::DataId;
 
+/*
+    After accepting a connection the server sends `Response::Start`; everything else is driven by the client.
+
+    The client then sends `Request::Hello` with its protocol version and capabilities, and the server responds with
+    a `Response::Hello` with its own. Both sides then stick to the lower of the two versions and only use
+    the capabilities which both of them have. A server which predates the handshake doesn't understand
+    `Request::Hello` and just drops the connection, in which case the client reconnects and assumes
+    `LEGACY_CAPABILITIES`.
+
+    New variants of `Request` and `Response` can only ever be appended, and a new request must only be sent
+    (and a new response is only ever sent in reply to a new request) when the peer has the matching capability.
+*/
+
+/// The version which is sent in the `BroadcastHeader`; older clients refuse to talk to servers with
+/// a higher version, so it's frozen, and the actual version is negotiated with `Request::Hello`.
 pub const PROTOCOL_VERSION: u32 = 2;
 
+/// The version which is negotiated with `Request::Hello`.
+pub const HANDSHAKE_PROTOCOL_VERSION: u32 = 3;
+
+pub const CAPABILITY_STREAMING: u64 = 1 << 0;
+pub const CAPABILITY_MEMORY_DUMP: u64 = 1 << 1;
+pub const CAPABILITY_SUMMARY: u64 = 1 << 2;
+
+/// The capabilities of a server which doesn't support the handshake.
+pub const LEGACY_CAPABILITIES: u64 = CAPABILITY_STREAMING | CAPABILITY_MEMORY_DUMP;
+
+#[derive(Clone, PartialEq, Debug, Readable, Writable)]
+pub struct Handshake {
+    pub protocol_version: u32,
+    pub capabilities: u64
+}
+
+impl Handshake {
+    /// What both of the sides can use.
+    pub fn negotiate( &self, peer: &Handshake ) -> Handshake {
+        Handshake {
+            protocol_version: self.protocol_version.min( peer.protocol_version ),
+            capabilities: self.capabilities & peer.capabilities
+        }
+    }
+
+    pub fn has( &self, capability: u64 ) -> bool {
+        self.capabilities & capability != 0
+    }
+}
+
 #[derive(PartialEq, Debug, Readable, Writable)]
 pub enum Request {
     StartStreaming,
     TriggerMemoryDump,
-    Ping
+    Ping,
+    GetSummary,
+    Hello( Handshake )
 }
 
 #[derive(PartialEq, Debug, Readable, Writable)]
@@ -18,7 +69,11 @@ pub enum Response< 'a > {
     Data( Cow< 'a, [u8] > ),
     FinishedInitialStreaming,
     Pong,
-    Finished
+    Finished,
+    Summary( Cow< 'a, str > ),
+    Hello( Handshake ),
+    /// Sent before `Start` when streaming into a server which requires a token.
+    Authenticate( Cow< 'a, str > )
 }
 
 #[derive(PartialEq, Debug, Readable, Writable)]
@@ -35,3 +90,61 @@ pub struct BroadcastHeader {
     pub listener_port: u16,
     pub protocol_version: u32
 }
+
+/// Reads the token which has to be presented by whoever connects to a listener on the given `address`,
+/// e.g. with `Response::Authenticate`; `option` is the command line option through which the `path` is given.
+///
+/// Without a token only a loopback address can be listened on, so that nobody from outside can connect.
+pub fn read_token( address: &str, path: Option< &Path >, option: &str ) -> io::Result< Option< String > > {
+    if let Some( path ) = path {
+        let token = fs::read_to_string( path )?.trim().to_owned();
+        if token.is_empty() {
+            return Err( io::Error::new( io::ErrorKind::InvalidData, format!( "the token in {:?} is empty", path ) ) );
+        }
+
+        return Ok( Some( token ) );
+    }
+
+    let is_loopback = address.parse::< SocketAddr >().map( |address| address.ip().is_loopback() ).unwrap_or( false );
+    if !is_loopback {
+        return Err( io::Error::new( io::ErrorKind::InvalidInput, format!( "listening on '{}' requires a '{}'", address, option ) ) );
+    }
+
+    Ok( None )
+}
+
+/// Compares the tokens in constant time, so that the expected one can't be guessed byte by byte.
+pub fn is_token_valid( given: &str, token: &str ) -> bool {
+    let (given, token) = (given.as_bytes(), token.as_bytes());
+    given.len() == token.len() && given.iter().zip( token ).fold( 0, |acc, (a, b)| acc | (a ^ b) ) == 0
+}
+
+#[test]
+fn test_read_token() {
+    let path = std::env::temp_dir().join( format!( "memory-profiler-test-token-{}", std::process::id() ) );
+    fs::write( &path, "secret\n" ).unwrap();
+    assert_eq!( read_token( "0.0.0.0:8200", Some( &path ), "--token-file" ).unwrap(), Some( "secret".to_owned() ) );
+    assert_eq!( read_token( "127.0.0.1:8200", None, "--token-file" ).unwrap(), None );
+    assert!( read_token( "0.0.0.0:8200", None, "--token-file" ).unwrap_err().to_string().contains( "--token-file" ) );
+
+    fs::write( &path, "\n" ).unwrap();
+    assert!( read_token( "0.0.0.0:8200", Some( &path ), "--token-file" ).is_err() );
+    let _ = fs::remove_file( &path );
+
+    assert!( is_token_valid( "secret", "secret" ) );
+    assert!( !is_token_valid( "secreT", "secret" ) );
+    assert!( !is_token_valid( "secret2", "secret" ) );
+}
+
+#[test]
+fn test_negotiate() {
+    let client = Handshake { protocol_version: 3, capabilities: CAPABILITY_STREAMING | CAPABILITY_SUMMARY };
+    let server = Handshake { protocol_version: 4, capabilities: CAPABILITY_STREAMING | CAPABILITY_MEMORY_DUMP | (1 << 10) };
+    let negotiated = client.negotiate( &server );
+
+    assert_eq!( negotiated, server.negotiate( &client ) );
+    assert_eq!( negotiated.protocol_version, 3 );
+    assert!( negotiated.has( CAPABILITY_STREAMING ) );
+    assert!( !negotiated.has( CAPABILITY_SUMMARY ) );
+    assert!( !negotiated.has( CAPABILITY_MEMORY_DUMP ) );
+}
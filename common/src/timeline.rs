@@ -0,0 +1,128 @@
+/// A single operation, as far as the timeline is concerned.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TimelineOperation {
+    /// `is_leaked` is set if the allocation was never deallocated.
+    Allocation { size: u64, is_leaked: bool },
+    Deallocation { size: u64 },
+    Reallocation { old_size: u64, new_size: u64, is_leaked: bool }
+}
+
+/// The per-second memory usage shown by the web UI; both the server and the WebAssembly build
+/// answer `/timeline` with it.
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Timeline {
+    pub xs: Vec< u64 >,
+    pub size_delta: Vec< i64 >,
+    pub count_delta: Vec< i64 >,
+    pub allocated_size: Vec< u64 >,
+    pub allocated_count: Vec< u64 >,
+    pub leaked_size: Vec< u64 >,
+    pub leaked_count: Vec< u64 >,
+    pub allocations: Vec< u32 >,
+    pub deallocations: Vec< u32 >
+}
+
+impl Timeline {
+    pub fn with_capacity( capacity: usize ) -> Self {
+        Timeline {
+            xs: Vec::with_capacity( capacity ),
+            size_delta: Vec::with_capacity( capacity ),
+            count_delta: Vec::with_capacity( capacity ),
+            allocated_size: Vec::with_capacity( capacity ),
+            allocated_count: Vec::with_capacity( capacity ),
+            leaked_size: Vec::with_capacity( capacity ),
+            leaked_count: Vec::with_capacity( capacity ),
+            allocations: Vec::with_capacity( capacity ),
+            deallocations: Vec::with_capacity( capacity )
+        }
+    }
+
+    fn push_empty( &mut self, x: u64 ) {
+        let last = |values: &[u64]| values.last().cloned().unwrap_or( 0 );
+        let allocated_size = last( &self.allocated_size );
+        let allocated_count = last( &self.allocated_count );
+        let leaked_size = last( &self.leaked_size );
+        let leaked_count = last( &self.leaked_count );
+
+        self.xs.push( x );
+        self.size_delta.push( 0 );
+        self.count_delta.push( 0 );
+        self.allocated_size.push( allocated_size );
+        self.allocated_count.push( allocated_count );
+        self.leaked_size.push( leaked_size );
+        self.leaked_count.push( leaked_count );
+        self.allocations.push( 0 );
+        self.deallocations.push( 0 );
+    }
+
+    /// Adds an operation which happened during the `secs`th second; the operations have to be added in order.
+    pub fn add( &mut self, secs: u64, operation: TimelineOperation ) {
+        match self.xs.last().cloned() {
+            Some( last_secs ) if last_secs == secs => {},
+            Some( last_secs ) => {
+                assert!( secs > last_secs );
+
+                // The gaps are filled in so that the graph stays flat instead of being interpolated.
+                if last_secs + 1 < secs {
+                    self.push_empty( last_secs + 1 );
+                }
+
+                if last_secs + 2 < secs {
+                    self.push_empty( secs - 1 );
+                }
+
+                self.push_empty( secs );
+            },
+            None => self.push_empty( secs )
+        }
+
+        let (size_delta, count_delta, leaked_size) = match operation {
+            TimelineOperation::Allocation { size, is_leaked } => {
+                *self.allocations.last_mut().unwrap() += 1;
+                (size as i64, 1, if is_leaked { Some( size ) } else { None })
+            },
+            TimelineOperation::Deallocation { size } => {
+                *self.deallocations.last_mut().unwrap() += 1;
+                (-(size as i64), -1, None)
+            },
+            TimelineOperation::Reallocation { old_size, new_size, is_leaked } => {
+                *self.allocations.last_mut().unwrap() += 1;
+                *self.deallocations.last_mut().unwrap() += 1;
+                (new_size as i64 - old_size as i64, 0, if is_leaked { Some( new_size ) } else { None })
+            }
+        };
+
+        if let Some( size ) = leaked_size {
+            *self.leaked_size.last_mut().unwrap() += size;
+            *self.leaked_count.last_mut().unwrap() += 1;
+        }
+
+        let allocated_size = self.allocated_size.last_mut().unwrap();
+        *allocated_size = (*allocated_size as i64 + size_delta) as _;
+        let allocated_count = self.allocated_count.last_mut().unwrap();
+        *allocated_count = (*allocated_count as i64 + count_delta) as _;
+        *self.size_delta.last_mut().unwrap() += size_delta;
+        *self.count_delta.last_mut().unwrap() += count_delta;
+    }
+}
+
+#[test]
+fn test_timeline() {
+    let mut timeline = Timeline::default();
+    timeline.add( 10, TimelineOperation::Allocation { size: 10, is_leaked: false } );
+    timeline.add( 10, TimelineOperation::Allocation { size: 20, is_leaked: false } );
+    timeline.add( 11, TimelineOperation::Deallocation { size: 10 } );
+    timeline.add( 12, TimelineOperation::Reallocation { old_size: 20, new_size: 5, is_leaked: true } );
+    timeline.add( 20, TimelineOperation::Allocation { size: 1, is_leaked: true } );
+
+    assert_eq!( timeline.xs, vec![ 10, 11, 12, 13, 19, 20 ] );
+    assert_eq!( timeline.allocated_size, vec![ 30, 20, 5, 5, 5, 6 ] );
+    assert_eq!( timeline.allocated_count, vec![ 2, 1, 1, 1, 1, 2 ] );
+    assert_eq!( timeline.size_delta, vec![ 30, -10, -15, 0, 0, 1 ] );
+    assert_eq!( timeline.count_delta, vec![ 2, -1, 0, 0, 0, 1 ] );
+    assert_eq!( timeline.leaked_size, vec![ 0, 0, 5, 5, 5, 6 ] );
+    assert_eq!( timeline.leaked_count, vec![ 0, 0, 1, 1, 1, 2 ] );
+    assert_eq!( timeline.allocations, vec![ 2, 0, 1, 0, 0, 1 ] );
+    assert_eq!( timeline.deallocations, vec![ 0, 1, 1, 0, 0, 0 ] );
+}
@@ -86,6 +86,11 @@ impl Timestamp {
         self.0
     }
 
+    #[inline]
+    pub fn as_secs_f64( &self ) -> f64 {
+        self.0 as f64 / 1_000_000.0
+    }
+
     #[inline]
     pub fn fract_nsecs( &self ) -> u64 {
         (self.as_usecs() - self.as_secs() * 1_000_000) * 1000
@@ -99,6 +104,7 @@ fn test_timestamp() {
     assert_eq!( ts.as_msecs(), 333987 );
     assert_eq!( ts.as_usecs(), 333987654 );
     assert_eq!( ts.fract_nsecs(), 987_654_000 );
+    assert_eq!( ts.as_secs_f64(), 333.987654 );
 
     assert_eq!(
         ts - Timestamp::from_secs( 133 ),
@@ -0,0 +1,295 @@
+# A gdb plugin which shows what the memory profiler knows about the live allocations of the debugged process.
+#
+# Load it with `source gdb/memory_profiler.py` (e.g. from your `~/.gdbinit`); it runs
+# `memory-profiler-cli inspect` in the background on the data file which the process is writing.
+#
+# Commands:
+#   memory-profiler allocation <expression>   the live allocation which contains the given address
+#   memory-profiler backtrace [<expression>]  the backtrace of the allocation which contains the given address,
+#                                             or the address last examined with `x` if none was given
+#   memory-profiler summary                   the number and the total size of the live allocations
+#   memory-profiler file <path>               use the given data file instead of looking for it automatically
+#
+# Settings:
+#   set memory-profiler-cli <path>            the `memory-profiler-cli` binary to use
+
+import json
+import os
+import re
+import subprocess
+
+import gdb
+
+
+class State(object):
+    def __init__(self):
+        self.cli = "memory-profiler-cli"
+        self.path = None
+        self.process = None
+        self.process_path = None
+
+    def stop(self):
+        if self.process is not None:
+            self.process.stdin.close()
+            self.process.wait()
+            self.process = None
+            self.process_path = None
+
+
+state = State()
+
+
+# The default of `MEMORY_PROFILER_OUTPUT`.
+DEFAULT_OUTPUT = "memory-profiling_%e_%t_%p.dat"
+
+
+def output_pattern(pid):
+    try:
+        with open("/proc/{}/environ".format(pid), "rb") as fp:
+            environ = fp.read().split(b"\0")
+    except (IOError, OSError):
+        return DEFAULT_OUTPUT
+
+    prefix = b"MEMORY_PROFILER_OUTPUT="
+    for entry in environ:
+        if entry.startswith(prefix):
+            return entry[len(prefix):].decode("utf-8", "replace")
+
+    return DEFAULT_OUTPUT
+
+
+def output_regex(pattern, pid, executable):
+    """Turns the profiler's output pattern into a regex which matches the paths it can generate for a given process."""
+    output = ""
+    placeholders = {
+        "%": re.escape("%"),
+        "p": str(pid),
+        "e": re.escape(executable),
+        "t": r"\d+",
+        "n": r"\d*"
+    }
+
+    characters = iter(pattern)
+    for ch in characters:
+        if ch == "%":
+            output += placeholders.get(next(characters, ""), "")
+        else:
+            output += re.escape(ch)
+
+    # A relative path is relative to the working directory the process had when it opened the file.
+    if not pattern.startswith("/"):
+        output = "(?:.*/)?" + output
+
+    return re.compile(output + "$")
+
+
+def find_data_file(pid):
+    """Looks for the data file among the files the process has open, matching them against its `MEMORY_PROFILER_OUTPUT`."""
+    fd_directory = "/proc/{}/fd".format(pid)
+    try:
+        fds = os.listdir(fd_directory)
+        executable = os.path.basename(os.readlink("/proc/{}/exe".format(pid)))
+    except OSError:
+        return None
+
+    regex = output_regex(output_pattern(pid), pid, executable)
+    for fd in fds:
+        try:
+            path = os.readlink(os.path.join(fd_directory, fd))
+        except OSError:
+            continue
+
+        if regex.match(path) and os.path.isfile(path):
+            return path
+
+    return None
+
+
+def data_file():
+    if state.path is not None:
+        return state.path
+
+    inferior = gdb.selected_inferior()
+    if inferior is None or inferior.pid == 0:
+        raise gdb.GdbError("the program is not being run")
+
+    path = find_data_file(inferior.pid)
+    if path is None:
+        raise gdb.GdbError(
+            "process {} isn't writing any data file; is it running under the memory profiler? "
+            "(use `memory-profiler file <path>` to point to its data file manually)".format(inferior.pid)
+        )
+
+    return path
+
+
+def query(command):
+    path = data_file()
+    if state.process is not None and (state.process_path != path or state.process.poll() is not None):
+        state.stop()
+
+    if state.process is None:
+        try:
+            state.process = subprocess.Popen(
+                [state.cli, "inspect", path],
+                stdin=subprocess.PIPE,
+                stdout=subprocess.PIPE,
+                universal_newlines=True,
+                env=dict(os.environ, RUST_LOG="error")
+            )
+        except OSError as error:
+            raise gdb.GdbError("failed to launch '{}': {}".format(state.cli, error))
+
+        state.process_path = path
+
+    state.process.stdin.write(command + "\n")
+    state.process.stdin.flush()
+    line = state.process.stdout.readline()
+    if not line:
+        state.stop()
+        raise gdb.GdbError("'{} inspect' has exited unexpectedly".format(state.cli))
+
+    response = json.loads(line)
+    if "error" in response:
+        raise gdb.GdbError(response["error"])
+
+    return response
+
+
+def evaluate_address(expression):
+    value = gdb.parse_and_eval(expression)
+    if value.type.strip_typedefs().code == gdb.TYPE_CODE_ARRAY:
+        value = value.address
+
+    return int(value.cast(gdb.lookup_type("unsigned long")))
+
+
+def format_frame(index, frame):
+    function = frame["function"] or "??"
+    location = ""
+    if frame["source"] is not None:
+        location = " at {}:{}".format(frame["source"], frame["line"] or 0)
+    elif frame["library"] is not None:
+        location = " from {}".format(frame["library"])
+
+    return "#{:<3} 0x{:016x} in {}{}".format(index, frame["address"], function, location)
+
+
+def lookup_allocation(address):
+    allocation = query("allocation 0x{:x}".format(address))["allocation"]
+    if allocation is None:
+        raise gdb.GdbError(
+            "0x{:x} is not within any live allocation known to the profiler "
+            "(the most recent allocations might not be in the data file yet)".format(address)
+        )
+
+    return allocation
+
+
+def print_allocation(address, allocation):
+    print("0x{:x} is {} bytes into a live allocation of {} bytes at 0x{:x}".format(address, allocation["offset"], allocation["size"], allocation["address"]))
+    print("Allocated by thread {} at {:.3f}s since the start of the profiling".format(allocation["thread"], allocation["timestamp"]))
+
+
+class MemoryProfilerPrefix(gdb.Command):
+    """Shows what the memory profiler knows about the live allocations of the debugged process."""
+
+    def __init__(self):
+        super(MemoryProfilerPrefix, self).__init__("memory-profiler", gdb.COMMAND_DATA, gdb.COMPLETE_NONE, True)
+
+
+class MemoryProfilerAllocation(gdb.Command):
+    """Shows the live allocation which contains the given address.
+Usage: memory-profiler allocation <expression>"""
+
+    def __init__(self):
+        super(MemoryProfilerAllocation, self).__init__("memory-profiler allocation", gdb.COMMAND_DATA, gdb.COMPLETE_EXPRESSION)
+
+    def invoke(self, argument, from_tty):
+        if not argument:
+            raise gdb.GdbError("missing address")
+
+        address = evaluate_address(argument)
+        print_allocation(address, lookup_allocation(address))
+
+
+class MemoryProfilerBacktrace(gdb.Command):
+    """Shows the backtrace of the live allocation which contains the given address.
+Usage: memory-profiler backtrace [<expression>]
+Without an expression the address last examined with `x` is used."""
+
+    def __init__(self):
+        super(MemoryProfilerBacktrace, self).__init__("memory-profiler backtrace", gdb.COMMAND_DATA, gdb.COMPLETE_EXPRESSION)
+
+    def invoke(self, argument, from_tty):
+        if argument:
+            address = evaluate_address(argument)
+        else:
+            last = gdb.convenience_variable("_")
+            if last is None:
+                raise gdb.GdbError("missing address, and nothing was examined with `x` yet")
+
+            address = int(last.cast(gdb.lookup_type("unsigned long")))
+
+        allocation = lookup_allocation(address)
+        print_allocation(address, allocation)
+        for index, frame in enumerate(allocation["backtrace"]):
+            print(format_frame(index, frame))
+
+
+class MemoryProfilerSummary(gdb.Command):
+    """Shows the number and the total size of the live allocations.
+Usage: memory-profiler summary"""
+
+    def __init__(self):
+        super(MemoryProfilerSummary, self).__init__("memory-profiler summary", gdb.COMMAND_DATA, gdb.COMPLETE_NONE)
+
+    def invoke(self, argument, from_tty):
+        summary = query("summary")
+        print("Data file: {}".format(state.process_path))
+        print("Live allocations: {} ({} bytes) after {:.1f}s of profiling".format(summary["live_count"], summary["live_size"], summary["elapsed"]))
+
+
+class MemoryProfilerFile(gdb.Command):
+    """Uses the given data file instead of looking for the one the debugged process is writing.
+Usage: memory-profiler file [<path>]
+Without a path the data file is looked for automatically again."""
+
+    def __init__(self):
+        super(MemoryProfilerFile, self).__init__("memory-profiler file", gdb.COMMAND_FILES, gdb.COMPLETE_FILENAME)
+
+    def invoke(self, argument, from_tty):
+        state.stop()
+        state.path = os.path.abspath(os.path.expanduser(argument)) if argument else None
+
+
+class MemoryProfilerCli(gdb.Parameter):
+    """The `memory-profiler-cli` binary which is used to query the data file."""
+
+    set_doc = "Set the path to `memory-profiler-cli`."
+    show_doc = "Show the path to `memory-profiler-cli`."
+
+    def __init__(self):
+        super(MemoryProfilerCli, self).__init__("memory-profiler-cli", gdb.COMMAND_FILES, gdb.PARAM_FILENAME)
+        self.value = state.cli
+
+    def get_set_string(self):
+        state.stop()
+        state.cli = self.value
+        return ""
+
+    def get_show_string(self, value):
+        return value
+
+
+def on_exited(event):
+    state.stop()
+
+
+MemoryProfilerPrefix()
+MemoryProfilerAllocation()
+MemoryProfilerBacktrace()
+MemoryProfilerSummary()
+MemoryProfilerFile()
+MemoryProfilerCli()
+gdb.events.exited.connect(on_exited)
@@ -0,0 +1,211 @@
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_derive::Deserialize;
+
+use crate::process::{assert_file_exists, run_in_the_background};
+
+/*
+    The types below mirror the JSON returned by the server's REST API; only the fields
+    which are useful in tests are deserialized.
+*/
+
+/// An entry of the server's `/list`.
+#[derive(Deserialize, Debug)]
+pub struct ResponseMetadata {
+    pub id: String,
+    pub executable: String,
+    pub architecture: String
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Debug, Hash)]
+#[serde(transparent)]
+pub struct Secs( pub u64 );
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Debug, Hash)]
+#[serde(transparent)]
+pub struct FractNanos( pub u32 );
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Debug)]
+pub struct Timeval {
+    pub secs: Secs,
+    pub fract_nsecs: FractNanos
+}
+
+#[derive(PartialEq, Deserialize, Debug)]
+pub struct Deallocation {
+    pub timestamp: Timeval,
+    pub thread: u32
+}
+
+#[derive(PartialEq, Deserialize, Debug)]
+pub struct Frame {
+    pub address: u64,
+    pub address_s: String,
+    pub count: u64,
+    pub library: Option< String >,
+    pub function: Option< String >,
+    pub raw_function: Option< String >,
+    pub source: Option< String >,
+    pub line: Option< u32 >,
+    pub column: Option< u32 >,
+    pub is_inline: bool
+}
+
+#[derive(PartialEq, Deserialize, Debug)]
+pub struct Allocation {
+    pub address: u64,
+    pub address_s: String,
+    pub timestamp: Timeval,
+    pub timestamp_relative: Timeval,
+    pub timestamp_relative_p: f32,
+    pub thread: u32,
+    pub size: u64,
+    pub backtrace_id: u32,
+    pub deallocation: Option< Deallocation >,
+    pub backtrace: Vec< Frame >,
+    pub is_mmaped: bool,
+    pub in_main_arena: bool,
+    pub extra_space: u32
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResponseAllocations {
+    pub allocations: Vec< Allocation >,
+    pub total_count: u64
+}
+
+#[derive(PartialEq, Deserialize, Debug)]
+pub struct AllocationGroupData {
+    pub size: u64,
+    pub min_size: u64,
+    pub max_size: u64,
+    pub min_timestamp: Timeval,
+    pub min_timestamp_relative: Timeval,
+    pub min_timestamp_relative_p: f32,
+    pub max_timestamp: Timeval,
+    pub max_timestamp_relative: Timeval,
+    pub max_timestamp_relative_p: f32,
+    pub interval: Timeval,
+    pub leaked_count: u64,
+    pub allocated_count: u64
+}
+
+#[derive(PartialEq, Deserialize, Debug)]
+pub struct AllocationGroup {
+    pub all: AllocationGroupData,
+    pub only_matched: AllocationGroupData,
+    pub backtrace_id: u32,
+    pub backtrace: Vec< Frame >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResponseAllocationGroups {
+    pub allocations: Vec< AllocationGroup >,
+    pub total_count: u64
+}
+
+/// Everything the server knows about the allocations from a single data file.
+pub struct Analysis {
+    pub response: ResponseAllocations,
+    pub groups: ResponseAllocationGroups
+}
+
+/// Whether any of the frames of the allocation's backtrace is from a source file whose path ends with `expected`.
+pub fn is_from_source( alloc: &Allocation, expected: &str ) -> bool {
+    alloc.backtrace.iter().any( |frame| {
+        frame.source.as_ref().map( |source| {
+            source.ends_with( expected )
+        }).unwrap_or( false )
+    })
+}
+
+/// Whether any of the frames of the allocation's backtrace is from the function with the given (mangled) symbol.
+pub fn is_from_function( alloc: &Allocation, expected: &str ) -> bool {
+    alloc.backtrace.iter().any( |frame| {
+        frame.raw_function.as_ref().map( |symbol| {
+            symbol == expected
+        }).unwrap_or( false )
+    })
+}
+
+/// Whether any of the frames of the allocation's backtrace is from a function whose (mangled) symbol contains `expected`.
+pub fn is_from_function_fuzzy( alloc: &Allocation, expected: &str ) -> bool {
+    alloc.backtrace.iter().any( |frame| {
+        frame.raw_function.as_ref().map( |symbol| {
+            symbol.contains( expected )
+        }).unwrap_or( false )
+    })
+}
+
+impl Analysis {
+    /// The allocations made from the given source file, in the order in which they were made.
+    pub fn allocations_from_source< 'a >( &'a self, source: &'a str ) -> impl Iterator< Item = &Allocation > + 'a {
+        self.response.allocations.iter().filter( move |alloc| is_from_source( alloc, source ) )
+    }
+}
+
+/// Asserts that the allocation's backtrace, starting from its outermost frame, starts with the given (mangled) symbols.
+pub fn assert_allocation_backtrace( alloc: &Allocation, expected: &[&str] ) {
+    let mut actual: Vec< _ > = alloc.backtrace.iter().map( |frame| frame.raw_function.clone().unwrap_or( String::new() ) ).collect();
+    actual.reverse();
+
+    let matches = actual.len() >= expected.len() && actual.iter().zip( expected.iter() ).all( |(lhs, rhs)| lhs == rhs );
+    if matches {
+        return;
+    }
+
+    panic!( "Unexpected backtrace!\n\nActual:\n{}\n\nExpected to start with:\n{}\n", actual.join( "\n" ), expected.join( "\n" ) );
+}
+
+/// Loads the given data file into `memory-profiler-cli server` and fetches all of its allocations.
+///
+/// The `name` is the file name of the executable which was profiled.
+pub fn analyze( cli: impl AsRef< Path >, cwd: impl AsRef< Path >, name: &str, path: impl AsRef< Path > ) -> Analysis {
+    let cwd = cwd.as_ref();
+    let path = path.as_ref();
+    assert_file_exists( path );
+
+    static PORT: AtomicUsize = AtomicUsize::new( 8080 );
+    let port = PORT.fetch_add( 1, Ordering::SeqCst );
+
+    let _child = run_in_the_background(
+        cwd,
+        cli.as_ref(),
+        &[OsString::from( "server" ), path.as_os_str().to_owned(), OsString::from( "--port" ), OsString::from( format!( "{}", port ) )],
+        &[("RUST_LOG", "server_core=debug,cli_core=debug,actix_net=info")]
+    );
+
+    let start = Instant::now();
+    let mut found = false;
+    while start.elapsed() < Duration::from_secs( 10 ) {
+        thread::sleep( Duration::from_millis( 100 ) );
+        if let Some( response ) = attohttpc::get( &format!( "http://localhost:{}/list", port ) ).send().ok() {
+            assert_eq!( response.status(), attohttpc::StatusCode::OK );
+            assert_eq!( *response.headers().get( attohttpc::header::CONTENT_TYPE ).unwrap(), "application/json" );
+            let list: Vec< ResponseMetadata > = serde_json::from_str( &response.text().unwrap() ).unwrap();
+            if !list.is_empty() {
+                assert_eq!( list[ 0 ].executable.split( "/" ).last().unwrap(), name );
+                found = true;
+                break;
+            }
+        }
+    }
+
+    assert!( found );
+
+    let response = attohttpc::get( &format!( "http://localhost:{}/data/last/allocations", port ) ).send().unwrap();
+    assert_eq!( response.status(), attohttpc::StatusCode::OK );
+    assert_eq!( *response.headers().get( attohttpc::header::CONTENT_TYPE ).unwrap(), "application/json" );
+    let response: ResponseAllocations = serde_json::from_str( &response.text().unwrap() ).unwrap();
+
+    let groups = attohttpc::get( &format!( "http://localhost:{}/data/last/allocation_groups", port ) ).send().unwrap();
+    assert_eq!( groups.status(), attohttpc::StatusCode::OK );
+    assert_eq!( *groups.headers().get( attohttpc::header::CONTENT_TYPE ).unwrap(), "application/json" );
+    let groups: ResponseAllocationGroups = serde_json::from_str( &groups.text().unwrap() ).unwrap();
+
+    Analysis { response, groups }
+}
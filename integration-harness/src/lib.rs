@@ -0,0 +1,30 @@
+//! The machinery behind the profiler's own integration tests, for writing allocation regression tests
+//! against your own binaries: it runs a program with the profiler preloaded, loads the resulting data
+//! file into `memory-profiler-cli server` and fetches the allocations through its REST API.
+//!
+//! ```rust,ignore
+//! use memory_profiler_harness::*;
+//!
+//! #[test]
+//! fn startup_does_not_leak() {
+//!     let profiler = std::path::Path::new( "/opt/memory-profiler/target" );
+//!     let cwd = std::env::temp_dir();
+//!
+//!     run_on_target( &cwd, "/usr/bin/my-server", &["--exit-after-startup"], &profiled_env( find_preload( profiler ), "startup.dat" ) )
+//!         .assert_success();
+//!
+//!     let analysis = analyze( find_cli( profiler ), &cwd, "my-server", cwd.join( "startup.dat" ) );
+//!     assert!( analysis.allocations_from_source( "startup.rs" ).all( |allocation| allocation.deallocation.is_some() ) );
+//! }
+//! ```
+//!
+//! The tests can be run on another architecture by setting `MEMORY_PROFILER_TEST_TARGET` to its target triple
+//! and `MEMORY_PROFILER_TEST_RUNNER` to a program which runs the binaries there (e.g. through an emulator).
+
+mod analysis;
+mod process;
+mod target;
+
+pub use crate::analysis::*;
+pub use crate::process::*;
+pub use crate::target::*;
@@ -0,0 +1,138 @@
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+use crate::process::{ChildHandle, CommandResult, run, run_in_the_background};
+
+/// The target triple of the machine on which the tests run, from `MEMORY_PROFILER_TEST_TARGET`;
+/// unset when they run on the host.
+pub fn target() -> Option< String > {
+    std::env::var( "MEMORY_PROFILER_TEST_TARGET" ).ok()
+}
+
+/// Finds `libmemory_profiler.so` under the given Cargo target directory, or at `MEMORY_PROFILER_TEST_PRELOAD_PATH`
+/// (relative to the target directory) if it's set.
+pub fn find_preload( build_root: &Path ) -> PathBuf {
+    let path = if let Ok( path ) = std::env::var( "MEMORY_PROFILER_TEST_PRELOAD_PATH" ) {
+        build_root.join( path ).join( "libmemory_profiler.so" )
+    } else {
+        let target = match target() {
+            Some( target ) => target,
+            None => "x86_64-unknown-linux-gnu".to_owned()
+        };
+
+        let mut potential_paths = vec![
+            build_root.join( &target ).join( "debug" ).join( "libmemory_profiler.so" ),
+            build_root.join( &target ).join( "release" ).join( "libmemory_profiler.so" )
+        ];
+
+        if target == env!( "TARGET" ) {
+            potential_paths.push( build_root.join( "debug" ).join( "libmemory_profiler.so" ) );
+            potential_paths.push( build_root.join( "release" ).join( "libmemory_profiler.so" ) );
+        }
+
+        potential_paths.retain( |path| path.exists() );
+        if potential_paths.is_empty() {
+            panic!( "No libmemory_profiler.so found!" );
+        }
+
+        if potential_paths.len() > 1 {
+            panic!( "Multiple libmemory_profiler.so found; specify the one which you want to use for tests with MEMORY_PROFILER_TEST_PRELOAD_PATH!" );
+        }
+
+        potential_paths.pop().unwrap()
+    };
+
+    assert!( path.exists(), "{:?} doesn't exist", path );
+    path
+}
+
+/// Finds `memory-profiler-cli` under the given Cargo target directory, or at `MEMORY_PROFILER_TEST_CLI_PATH`
+/// (relative to the target directory) if it's set.
+pub fn find_cli( build_root: &Path ) -> PathBuf {
+    if let Ok( path ) = std::env::var( "MEMORY_PROFILER_TEST_CLI_PATH" ) {
+        build_root.join( path ).join( "memory-profiler-cli" )
+    } else {
+        build_root.join( "x86_64-unknown-linux-gnu" ).join( "release" ).join( "memory-profiler-cli" )
+    }
+}
+
+/// The environment variables needed to run a program under the profiler.
+pub fn profiled_env( preload: impl AsRef< Path >, output: impl AsRef< OsStr > ) -> Vec< (OsString, OsString) > {
+    vec![
+        ("LD_PRELOAD".into(), preload.as_ref().as_os_str().to_owned()),
+        ("MEMORY_PROFILER_LOG".into(), "debug".into()),
+        ("MEMORY_PROFILER_OUTPUT".into(), output.as_ref().to_owned())
+    ]
+}
+
+/// The prefix of the cross compilation toolchain for the `target()`.
+pub fn target_toolchain_prefix() -> &'static str {
+    let target = match target() {
+        Some( target ) => target,
+        None => return "".into()
+    };
+
+    match target.as_str() {
+        "aarch64-unknown-linux-gnu" => "aarch64-linux-gnu-",
+        "armv7-unknown-linux-gnueabihf" => "arm-linux-gnueabihf-",
+        "mips64-unknown-linux-gnuabi64" => "mips64-linux-gnuabi64-",
+        "x86_64-unknown-linux-gnu" => "x86_64-linux-gnu-",
+        target => panic!( "Unknown target: '{}'", target )
+    }
+}
+
+pub fn compiler_cc() -> String {
+    format!( "{}gcc", target_toolchain_prefix() )
+}
+
+pub fn compiler_cxx() -> String {
+    format!( "{}g++", target_toolchain_prefix() )
+}
+
+fn map_to_target(
+    executable: impl AsRef< OsStr >,
+    args: &[impl AsRef< OsStr >],
+    envs: &[(impl AsRef< OsStr >, impl AsRef< OsStr >)]
+) -> (OsString, Vec< OsString >, Vec< (OsString, OsString) >) {
+    let mut executable = executable.as_ref().to_owned();
+    let mut args: Vec< OsString > =
+        args.iter().map( |arg| arg.as_ref().to_owned() ).collect();
+    let mut envs: Vec< (OsString, OsString) > =
+        envs.iter().map( |(key, value)| (key.as_ref().to_owned(), value.as_ref().to_owned()) ).collect();
+
+    if let Some( runner ) = std::env::var_os( "MEMORY_PROFILER_TEST_RUNNER" ) {
+        args = std::iter::once( executable ).chain( args.into_iter() ).collect();
+        executable = runner;
+        if let Some( index ) = envs.iter().position( |&(ref key, _)| key == "LD_PRELOAD" ) {
+            let (_, value) = envs.remove( index );
+            envs.push( ("TARGET_LD_PRELOAD".into(), value) );
+        }
+    }
+
+    (executable, args, envs)
+}
+
+/// Runs the given command, through the `MEMORY_PROFILER_TEST_RUNNER` if one is set (e.g. an emulator
+/// for another architecture, which gets the `LD_PRELOAD` as `TARGET_LD_PRELOAD`), and waits for it to exit.
+pub fn run_on_target< C, E, S, P, Q >( cwd: C, executable: E, args: &[S], envs: &[(P, Q)] ) -> CommandResult
+    where C: AsRef< Path >,
+          E: AsRef< OsStr >,
+          S: AsRef< OsStr >,
+          P: AsRef< OsStr >,
+          Q: AsRef< OsStr >
+{
+    let (executable, args, envs) = map_to_target( executable, args, envs );
+    run( cwd, executable, &args, &envs )
+}
+
+/// Same as `run_on_target`, except it doesn't wait for the command to exit.
+pub fn run_in_the_background_on_target< C, E, S, P, Q >( cwd: C, executable: E, args: &[S], envs: &[(P, Q)] ) -> ChildHandle
+    where C: AsRef< Path >,
+          E: AsRef< OsStr >,
+          S: AsRef< OsStr >,
+          P: AsRef< OsStr >,
+          Q: AsRef< OsStr >
+{
+    let (executable, args, envs) = map_to_target( executable, args, envs );
+    run_in_the_background( cwd, executable, &args, &envs )
+}
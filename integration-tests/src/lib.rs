@@ -1,5 +1,2 @@
-#[cfg(test)]
-mod utils;
-
 #[cfg(test)]
 mod tests;
@@ -1,7 +1,5 @@
 use {
-    serde_derive::{
-        Deserialize
-    },
+    memory_profiler_harness::*,
     std::{
         ffi::{
             OsString,
@@ -22,9 +20,6 @@ use {
             Duration,
             Instant
         }
-    },
-    crate::{
-        utils::*
     }
 };
 
@@ -32,10 +27,6 @@ fn repository_root() -> PathBuf {
     Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( ".." ).canonicalize().unwrap()
 }
 
-fn target() -> Option< String > {
-    std::env::var( "MEMORY_PROFILER_TEST_TARGET" ).ok()
-}
-
 fn build_root() -> PathBuf {
     if let Some( path ) = std::env::var_os( "CARGO_TARGET_DIR" ) {
         let path: PathBuf = path.into();
@@ -50,210 +41,11 @@ fn build_root() -> PathBuf {
 }
 
 fn preload_path() -> PathBuf {
-    let path = if let Ok( path ) = std::env::var( "MEMORY_PROFILER_TEST_PRELOAD_PATH" ) {
-        build_root().join( path ).join( "libmemory_profiler.so" )
-    } else {
-        let target = match target() {
-            Some( target ) => target,
-            None => "x86_64-unknown-linux-gnu".to_owned()
-        };
-
-        let mut potential_paths = vec![
-            build_root().join( &target ).join( "debug" ).join( "libmemory_profiler.so" ),
-            build_root().join( &target ).join( "release" ).join( "libmemory_profiler.so" )
-        ];
-
-        if target == env!( "TARGET" ) {
-            potential_paths.push( build_root().join( "debug" ).join( "libmemory_profiler.so" ) );
-            potential_paths.push( build_root().join( "release" ).join( "libmemory_profiler.so" ) );
-        }
-
-        potential_paths.retain( |path| path.exists() );
-        if potential_paths.is_empty() {
-            panic!( "No libmemory_profiler.so found!" );
-        }
-
-        if potential_paths.len() > 1 {
-            panic!( "Multiple libmemory_profiler.so found; specify the one which you want to use for tests with MEMORY_PROFILER_TEST_PRELOAD_PATH!" );
-        }
-
-        potential_paths.pop().unwrap()
-    };
-
-    assert!( path.exists(), "{:?} doesn't exist", path );
-    path
+    find_preload( &build_root() )
 }
 
 fn cli_path() -> PathBuf {
-    if let Ok( path ) = std::env::var( "MEMORY_PROFILER_TEST_CLI_PATH" ) {
-        build_root().join( path ).join( "memory-profiler-cli" )
-    } else {
-        build_root().join( "x86_64-unknown-linux-gnu" ).join( "release" ).join( "memory-profiler-cli" )
-    }
-}
-
-fn target_toolchain_prefix() -> &'static str {
-    let target = match target() {
-        Some( target ) => target,
-        None => return "".into()
-    };
-
-    match target.as_str() {
-        "aarch64-unknown-linux-gnu" => "aarch64-linux-gnu-",
-        "armv7-unknown-linux-gnueabihf" => "arm-linux-gnueabihf-",
-        "mips64-unknown-linux-gnuabi64" => "mips64-linux-gnuabi64-",
-        "x86_64-unknown-linux-gnu" => "x86_64-linux-gnu-",
-        target => panic!( "Unknown target: '{}'", target )
-    }
-}
-
-fn compiler_cc() -> String {
-    format!( "{}gcc", target_toolchain_prefix() )
-}
-
-fn compiler_cxx() -> String {
-    format!( "{}g++", target_toolchain_prefix() )
-}
-
-#[derive(Deserialize)]
-struct ResponseMetadata {
-    pub id: String,
-    pub executable: String,
-    pub architecture: String
-}
-
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Debug, Hash)]
-#[serde(transparent)]
-pub struct Secs( u64 );
-
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Debug, Hash)]
-#[serde(transparent)]
-pub struct FractNanos( u32 );
-
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Debug)]
-pub struct Timeval {
-    pub secs: Secs,
-    pub fract_nsecs: FractNanos
-}
-
-#[derive(PartialEq, Deserialize, Debug)]
-pub struct Deallocation {
-    pub timestamp: Timeval,
-    pub thread: u32
-}
-
-#[derive(PartialEq, Deserialize, Debug)]
-pub struct Frame {
-    pub address: u64,
-    pub address_s: String,
-    pub count: u64,
-    pub library: Option< String >,
-    pub function: Option< String >,
-    pub raw_function: Option< String >,
-    pub source: Option< String >,
-    pub line: Option< u32 >,
-    pub column: Option< u32 >,
-    pub is_inline: bool
-}
-
-#[derive(PartialEq, Deserialize, Debug)]
-pub struct Allocation {
-    pub address: u64,
-    pub address_s: String,
-    pub timestamp: Timeval,
-    pub timestamp_relative: Timeval,
-    pub timestamp_relative_p: f32,
-    pub thread: u32,
-    pub size: u64,
-    pub backtrace_id: u32,
-    pub deallocation: Option< Deallocation >,
-    pub backtrace: Vec< Frame >,
-    pub is_mmaped: bool,
-    pub in_main_arena: bool,
-    pub extra_space: u32
-}
-
-#[derive(Deserialize, Debug)]
-struct ResponseAllocations {
-    pub allocations: Vec< Allocation >,
-    pub total_count: u64
-}
-
-#[derive(PartialEq, Deserialize, Debug)]
-pub struct AllocationGroupData {
-    pub size: u64,
-    pub min_size: u64,
-    pub max_size: u64,
-    pub min_timestamp: Timeval,
-    pub min_timestamp_relative: Timeval,
-    pub min_timestamp_relative_p: f32,
-    pub max_timestamp: Timeval,
-    pub max_timestamp_relative: Timeval,
-    pub max_timestamp_relative_p: f32,
-    pub interval: Timeval,
-    pub leaked_count: u64,
-    pub allocated_count: u64
-}
-
-#[derive(PartialEq, Deserialize, Debug)]
-pub struct AllocationGroup {
-    pub all: AllocationGroupData,
-    pub only_matched: AllocationGroupData,
-    pub backtrace_id: u32,
-    pub backtrace: Vec< Frame >
-}
-
-#[derive(Deserialize, Debug)]
-pub struct ResponseAllocationGroups {
-    pub allocations: Vec< AllocationGroup >,
-    pub total_count: u64
-}
-
-struct Analysis {
-    response: ResponseAllocations,
-    groups: ResponseAllocationGroups
-}
-
-fn is_from_source( alloc: &Allocation, expected: &str ) -> bool {
-    alloc.backtrace.iter().any( |frame| {
-        frame.source.as_ref().map( |source| {
-            source.ends_with( expected )
-        }).unwrap_or( false )
-    })
-}
-
-fn is_from_function( alloc: &Allocation, expected: &str ) -> bool {
-    alloc.backtrace.iter().any( |frame| {
-        frame.raw_function.as_ref().map( |symbol| {
-            symbol == expected
-        }).unwrap_or( false )
-    })
-}
-
-fn is_from_function_fuzzy( alloc: &Allocation, expected: &str ) -> bool {
-    alloc.backtrace.iter().any( |frame| {
-        frame.raw_function.as_ref().map( |symbol| {
-            symbol.contains( expected )
-        }).unwrap_or( false )
-    })
-}
-
-impl Analysis {
-    fn allocations_from_source< 'a >( &'a self, source: &'a str ) -> impl Iterator< Item = &Allocation > + 'a {
-        self.response.allocations.iter().filter( move |alloc| is_from_source( alloc, source ) )
-    }
-}
-
-fn assert_allocation_backtrace( alloc: &Allocation, expected: &[&str] ) {
-    let mut actual: Vec< _ > = alloc.backtrace.iter().map( |frame| frame.raw_function.clone().unwrap_or( String::new() ) ).collect();
-    actual.reverse();
-
-    let matches = actual.len() >= expected.len() && actual.iter().zip( expected.iter() ).all( |(lhs, rhs)| lhs == rhs );
-    if matches {
-        return;
-    }
-
-    panic!( "Unexpected backtrace!\n\nActual:\n{}\n\nExpected to start with:\n{}\n", actual.join( "\n" ), expected.join( "\n" ) );
+    find_cli( &build_root() )
 }
 
 fn workdir() -> PathBuf {
@@ -263,50 +55,7 @@ fn workdir() -> PathBuf {
 }
 
 fn analyze( name: &str, path: impl AsRef< Path > ) -> Analysis {
-    let cwd = workdir();
-
-    let path = path.as_ref();
-    assert_file_exists( path );
-
-    static PORT: AtomicUsize = AtomicUsize::new( 8080 );
-    let port = PORT.fetch_add( 1, Ordering::SeqCst );
-
-    let _child = run_in_the_background(
-        &cwd,
-        cli_path(),
-        &[OsString::from( "server" ), path.as_os_str().to_owned(), OsString::from( "--port" ), OsString::from( format!( "{}", port ) )],
-        &[("RUST_LOG", "server_core=debug,cli_core=debug,actix_net=info")]
-    );
-
-    let start = Instant::now();
-    let mut found = false;
-    while start.elapsed() < Duration::from_secs( 10 ) {
-        thread::sleep( Duration::from_millis( 100 ) );
-        if let Some( response ) = attohttpc::get( &format!( "http://localhost:{}/list", port ) ).send().ok() {
-            assert_eq!( response.status(), attohttpc::StatusCode::OK );
-            assert_eq!( *response.headers().get( attohttpc::header::CONTENT_TYPE ).unwrap(), "application/json" );
-            let list: Vec< ResponseMetadata > = serde_json::from_str( &response.text().unwrap() ).unwrap();
-            if !list.is_empty() {
-                assert_eq!( list[ 0 ].executable.split( "/" ).last().unwrap(), name );
-                found = true;
-                break;
-            }
-        }
-    }
-
-    assert!( found );
-
-    let response = attohttpc::get( &format!( "http://localhost:{}/data/last/allocations", port ) ).send().unwrap();
-    assert_eq!( response.status(), attohttpc::StatusCode::OK );
-    assert_eq!( *response.headers().get( attohttpc::header::CONTENT_TYPE ).unwrap(), "application/json" );
-    let response: ResponseAllocations = serde_json::from_str( &response.text().unwrap() ).unwrap();
-
-    let groups = attohttpc::get( &format!( "http://localhost:{}/data/last/allocation_groups", port ) ).send().unwrap();
-    assert_eq!( groups.status(), attohttpc::StatusCode::OK );
-    assert_eq!( *groups.headers().get( attohttpc::header::CONTENT_TYPE ).unwrap(), "application/json" );
-    let groups: ResponseAllocationGroups = serde_json::from_str( &groups.text().unwrap() ).unwrap();
-
-    Analysis { response, groups }
+    memory_profiler_harness::analyze( cli_path(), workdir(), name, path )
 }
 
 fn get_basename( path: &str ) -> &str {
@@ -394,51 +143,6 @@ fn compile( source: &str ) {
     compile_with_flags( source, &[] );
 }
 
-fn map_to_target(
-    executable: impl AsRef< OsStr >,
-    args: &[impl AsRef< OsStr >],
-    envs: &[(impl AsRef< OsStr >, impl AsRef< OsStr >)]
-) -> (OsString, Vec< OsString >, Vec< (OsString, OsString) >) {
-    let mut executable = executable.as_ref().to_owned();
-    let mut args: Vec< OsString > =
-        args.iter().map( |arg| arg.as_ref().to_owned() ).collect();
-    let mut envs: Vec< (OsString, OsString) > =
-        envs.iter().map( |(key, value)| (key.as_ref().to_owned(), value.as_ref().to_owned()) ).collect();
-
-    if let Some( runner ) = std::env::var_os( "MEMORY_PROFILER_TEST_RUNNER" ) {
-        args = std::iter::once( executable ).chain( args.into_iter() ).collect();
-        executable = runner;
-        if let Some( index ) = envs.iter().position( |&(ref key, _)| key == "LD_PRELOAD" ) {
-            let (_, value) = envs.remove( index );
-            envs.push( ("TARGET_LD_PRELOAD".into(), value) );
-        }
-    }
-
-    (executable, args, envs)
-}
-
-pub fn run_on_target< C, E, S, P, Q >( cwd: C, executable: E, args: &[S], envs: &[(P, Q)] ) -> CommandResult
-    where C: AsRef< Path >,
-          E: AsRef< OsStr >,
-          S: AsRef< OsStr >,
-          P: AsRef< OsStr >,
-          Q: AsRef< OsStr >
-{
-    let (executable, args, envs) = map_to_target( executable, args, envs );
-    run( cwd, executable, &args, &envs )
-}
-
-pub fn run_in_the_background_on_target< C, E, S, P, Q >( cwd: C, executable: E, args: &[S], envs: &[(P, Q)] ) -> ChildHandle
-    where C: AsRef< Path >,
-          E: AsRef< OsStr >,
-          S: AsRef< OsStr >,
-          P: AsRef< OsStr >,
-          Q: AsRef< OsStr >
-{
-    let (executable, args, envs) = map_to_target( executable, args, envs );
-    run_in_the_background( cwd, executable, &args, &envs )
-}
-
 #[test]
 fn test_basic() {
     let cwd = workdir();
@@ -1119,3 +823,48 @@ fn test_cull() {
 
     assert_eq!( iter.next(), None );
 }
+
+#[cfg(feature = "test-python")]
+#[test]
+fn test_python_bindings() {
+    let cwd = workdir();
+
+    compile( "basic.c" );
+
+    run_on_target(
+        &cwd,
+        "./basic",
+        EMPTY_ARGS,
+        &[
+            ("LD_PRELOAD", preload_path().into_os_string()),
+            ("MEMORY_PROFILER_LOG", "debug".into()),
+            ("MEMORY_PROFILER_OUTPUT", "memory-profiling-python.dat".into())
+        ]
+    ).assert_success();
+
+    let python_root = repository_root().join( "python" );
+    let target_dir = build_root().join( "python" );
+    run(
+        &python_root,
+        "cargo",
+        &[ "build" ],
+        &[
+            ("CARGO_TARGET_DIR", target_dir.clone())
+        ]
+    ).assert_success();
+
+    // Python only imports extension modules which are named after them.
+    let module_dir = target_dir.join( "module" );
+    std::fs::create_dir_all( &module_dir ).unwrap();
+    std::fs::copy( target_dir.join( "debug" ).join( "libmemory_profiler.so" ), module_dir.join( "memory_profiler.so" ) ).unwrap();
+
+    run(
+        &python_root,
+        "python3",
+        &[ "-m", "unittest", "discover", "-v", "-s", "tests" ],
+        &[
+            ("PYTHONPATH", module_dir.into_os_string()),
+            ("MEMORY_PROFILER_TEST_CAPTURE", cwd.join( "memory-profiling-python.dat" ).into_os_string())
+        ]
+    ).assert_success();
+}
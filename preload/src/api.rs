@@ -1,6 +1,7 @@
 use std::mem;
 use std::ptr;
 use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use libc::{
     c_void,
@@ -13,7 +14,7 @@ use common::event;
 use common::Timestamp;
 
 use crate::InternalEvent;
-use crate::event::{InternalAllocationId, send_event, send_event_throttled};
+use crate::event::{ContentsSample, InternalAllocationId, send_event, send_event_throttled};
 use crate::global::{StrongThreadHandle, on_exit};
 use crate::opt;
 use crate::syscall;
@@ -167,14 +168,79 @@ unsafe fn tracking_pointer( pointer: *mut c_void, usable_size: usize ) -> *mut I
     (pointer as *mut u8).add( tracking_offset ) as *mut InternalAllocationId
 }
 
+/// Copies the first few bytes of an allocation which is about to be freed, if enabled.
+unsafe fn sample_contents( pointer: *mut c_void, usable_size: usize ) -> Option< ContentsSample > {
+    let length = ContentsSample::length_for( usable_size )?;
+    Some( ContentsSample::copy_from( pointer as *const u8, length ) )
+}
+
 enum AllocationKind {
     Malloc,
     Calloc,
     Aligned( size_t )
 }
 
+// These are the same as jemalloc's `MALLOCX_*` flags.
+const MALLOCX_LG_ALIGN_MASK: c_int = 0x3f;
+const MALLOCX_ZERO: c_int = 0x40;
+const MALLOCX_ARENA_SHIFT: u32 = 20;
+
+fn mallocx_alignment( flags: c_int ) -> Option< size_t > {
+    match flags & MALLOCX_LG_ALIGN_MASK {
+        0 => None,
+        lg_alignment => Some( 1 << lg_alignment )
+    }
+}
+
+/// Converts the arena picked through `MALLOCX_ARENA` into our own allocation flags.
+fn jemalloc_arena_flags( flags: c_int ) -> u32 {
+    let arena = (flags as u32 >> MALLOCX_ARENA_SHIFT) << event::ALLOC_FLAG_JEMALLOC_ARENA_SHIFT;
+    arena & event::ALLOC_FLAG_JEMALLOC_ARENA_MASK
+}
+
+/// Strips the flags which refer to the application's arenas and thread caches.
+///
+/// The application's jemalloc is replaced with our own, so those don't exist there.
+fn jemalloc_real_flags( flags: c_int ) -> c_int {
+    flags & (MALLOCX_LG_ALIGN_MASK | MALLOCX_ZERO)
+}
+
+#[test]
+fn test_mallocx_flags() {
+    // MALLOCX_LG_ALIGN( 6 ) | MALLOCX_ZERO | MALLOCX_TCACHE_NONE | MALLOCX_ARENA( 3 )
+    let flags = 6 | 0x40 | (1 << 8) | (4 << 20);
+    assert_eq!( mallocx_alignment( flags ), Some( 64 ) );
+    assert_eq!( mallocx_alignment( 0 ), None );
+    assert_eq!( jemalloc_arena_flags( flags ) >> event::ALLOC_FLAG_JEMALLOC_ARENA_SHIFT, 4 );
+    assert_eq!( jemalloc_arena_flags( 0 ), 0 );
+    assert_eq!( jemalloc_real_flags( flags ), 6 | 0x40 );
+}
+
+#[test]
+fn test_nallocx_without_jemalloc() {
+    unsafe {
+        assert_eq!( nallocx( 100, 0 ), 100 );
+        assert_eq!( nallocx( 100, 6 ), 128 );
+        assert_eq!( nallocx( usize::MAX, 6 ), 0 );
+    }
+}
+
+#[test]
+fn test_free_memory_allocated_before_hooking() {
+    unsafe {
+        // Zeroed, so that it can't accidentally end with a valid ID.
+        let pointer = calloc_real( 1, 100 );
+        let metadata = get_allocation_metadata( pointer );
+        assert!( !std::ptr::read_unaligned( tracking_pointer( pointer, metadata.usable_size ) ).is_valid() );
+        free( pointer );
+
+        let pointer = calloc_real( 1, 100 );
+        dallocx( pointer, 0 );
+    }
+}
+
 #[inline(always)]
-unsafe fn allocate( requested_size: usize, kind: AllocationKind ) -> *mut c_void {
+unsafe fn allocate( requested_size: usize, kind: AllocationKind, extra_flags: u32 ) -> *mut c_void {
     let effective_size = match requested_size.checked_add( mem::size_of::< InternalAllocationId >() ) {
         Some( size ) => size,
         None => return ptr::null_mut()
@@ -224,6 +290,7 @@ unsafe fn allocate( requested_size: usize, kind: AllocationKind ) -> *mut c_void
     if matches!( kind, AllocationKind::Calloc ) {
         metadata.flags |= event::ALLOC_FLAG_CALLOC;
     }
+    metadata.flags |= extra_flags;
 
     send_event_throttled( move || {
         InternalEvent::Alloc {
@@ -244,7 +311,7 @@ unsafe fn allocate( requested_size: usize, kind: AllocationKind ) -> *mut c_void
 
 #[cfg_attr(not(test), no_mangle)]
 pub unsafe extern "C" fn malloc( size: size_t ) -> *mut c_void {
-    allocate( size, AllocationKind::Malloc )
+    allocate( size, AllocationKind::Malloc, 0 )
 }
 
 #[cfg_attr(not(test), no_mangle)]
@@ -254,11 +321,11 @@ pub unsafe extern "C" fn calloc( count: size_t, element_size: size_t ) -> *mut c
         Some( size ) => size
     };
 
-    allocate( size, AllocationKind::Calloc )
+    allocate( size, AllocationKind::Calloc, 0 )
 }
 
 #[inline(always)]
-unsafe fn realloc_impl( old_pointer: *mut c_void, requested_size: size_t ) -> *mut c_void {
+unsafe fn realloc_impl( old_pointer: *mut c_void, requested_size: size_t, extra_flags: u32 ) -> *mut c_void {
     let old_address = match NonZeroUsize::new( old_pointer as usize ) {
         Some( old_address ) => old_address,
         None => return malloc( requested_size )
@@ -277,7 +344,16 @@ unsafe fn realloc_impl( old_pointer: *mut c_void, requested_size: size_t ) -> *m
     let old_metadata = get_allocation_metadata( old_pointer );
     let old_tracking_pointer = tracking_pointer( old_pointer, old_metadata.usable_size );
     let id = std::ptr::read_unaligned( old_tracking_pointer );
-    debug_assert!( id.is_valid() );
+    if !id.is_valid() {
+        // Allocated before we were attached, so it doesn't have any space for the ID; move it into a fresh allocation.
+        let new_pointer = allocate( requested_size, AllocationKind::Malloc, extra_flags );
+        if !new_pointer.is_null() {
+            ptr::copy_nonoverlapping( old_pointer as *const u8, new_pointer as *mut u8, std::cmp::min( old_metadata.usable_size, requested_size ) );
+            free_real( old_pointer );
+        }
+
+        return new_pointer;
+    }
 
     let mut thread = StrongThreadHandle::acquire();
     let new_pointer = realloc_real( old_pointer, effective_size );
@@ -316,7 +392,7 @@ unsafe fn realloc_impl( old_pointer: *mut c_void, requested_size: size_t ) -> *m
                 new_size: requested_size as usize,
                 new_usable_size: new_metadata.usable_size,
                 new_preceding_free_space: new_metadata.preceding_free_space,
-                new_flags: new_metadata.flags,
+                new_flags: new_metadata.flags | extra_flags,
                 backtrace,
                 timestamp,
                 thread: thread.decay()
@@ -329,6 +405,7 @@ unsafe fn realloc_impl( old_pointer: *mut c_void, requested_size: size_t ) -> *m
             InternalEvent::Free {
                 id,
                 address: old_address,
+                contents: None,
                 backtrace,
                 timestamp,
                 thread: thread.decay()
@@ -341,7 +418,7 @@ unsafe fn realloc_impl( old_pointer: *mut c_void, requested_size: size_t ) -> *m
 
 #[cfg_attr(not(test), no_mangle)]
 pub unsafe extern "C" fn realloc( old_ptr: *mut c_void, size: size_t ) -> *mut c_void {
-    realloc_impl( old_ptr, size )
+    realloc_impl( old_ptr, size, 0 )
 }
 
 #[cfg_attr(not(test), no_mangle)]
@@ -354,7 +431,7 @@ pub unsafe extern "C" fn reallocarray( old_ptr: &mut c_void, count: size_t, elem
         Some( size ) => size as size_t
     };
 
-    realloc_impl( old_ptr, size )
+    realloc_impl( old_ptr, size, 0 )
 }
 
 #[cfg_attr(not(test), no_mangle)]
@@ -367,15 +444,20 @@ pub unsafe extern "C" fn free( pointer: *mut c_void ) {
     let metadata = get_allocation_metadata( pointer );
     let tracking_pointer = tracking_pointer( pointer, metadata.usable_size );
     let id = std::ptr::read_unaligned( tracking_pointer );
-    debug_assert!( id.is_valid() );
+    if !id.is_valid() {
+        // Allocated before we were attached (see `memory_profiler_attach`).
+        free_real( pointer );
+        return;
+    }
 
     let mut thread = StrongThreadHandle::acquire();
-    free_real( pointer );
-
     if id.is_untracked() && !crate::global::is_actively_running() {
         thread = None;
     }
 
+    let contents = if thread.is_some() { sample_contents( pointer, metadata.usable_size ) } else { None };
+    free_real( pointer );
+
     let mut thread = if let Some( thread ) = thread { thread } else { return };
     let mut backtrace = Backtrace::new();
     if opt::get().grab_backtraces_on_free {
@@ -386,6 +468,7 @@ pub unsafe extern "C" fn free( pointer: *mut c_void ) {
         InternalEvent::Free {
             id,
             address,
+            contents,
             backtrace,
             timestamp: get_timestamp_if_enabled(),
             thread: thread.decay()
@@ -406,7 +489,7 @@ pub unsafe extern "C" fn _rjem_mallocx( requested_size: size_t, flags: c_int ) -
     };
 
     let mut thread = StrongThreadHandle::acquire();
-    let pointer = jem_mallocx_real( effective_size, flags );
+    let pointer = jem_mallocx_real( effective_size, jemalloc_real_flags( flags ) );
 
     if !crate::global::is_actively_running() {
         thread = None;
@@ -440,7 +523,7 @@ pub unsafe extern "C" fn _rjem_mallocx( requested_size: size_t, flags: c_int ) -
             size: requested_size as usize,
             usable_size,
             preceding_free_space: 0,
-            flags: event::ALLOC_FLAG_JEMALLOC,
+            flags: event::ALLOC_FLAG_JEMALLOC | jemalloc_arena_flags( flags ),
             backtrace,
             timestamp: get_timestamp_if_enabled(),
             thread: thread.decay()
@@ -526,12 +609,13 @@ pub unsafe extern "C" fn _rjem_sdallocx( pointer: *mut c_void, requested_size: s
     debug_assert!( id.is_valid() );
 
     let mut thread = StrongThreadHandle::acquire();
-    jem_sdallocx_real( pointer, effective_size, flags );
-
     if id.is_untracked() && !crate::global::is_actively_running() {
         thread = None;
     }
 
+    let contents = if thread.is_some() { sample_contents( pointer, usable_size ) } else { None };
+    jem_sdallocx_real( pointer, effective_size, jemalloc_real_flags( flags ) );
+
     let mut thread = if let Some( thread ) = thread { thread } else { return };
     let mut backtrace = Backtrace::new();
     if opt::get().grab_backtraces_on_free {
@@ -542,6 +626,7 @@ pub unsafe extern "C" fn _rjem_sdallocx( pointer: *mut c_void, requested_size: s
         InternalEvent::Free {
             id,
             address,
+            contents,
             backtrace,
             timestamp: get_timestamp_if_enabled(),
             thread: thread.decay()
@@ -572,7 +657,7 @@ pub unsafe extern "C" fn _rjem_rallocx( old_pointer: *mut c_void, requested_size
     debug_assert!( id.is_valid() );
 
     let mut thread = StrongThreadHandle::acquire();
-    let new_pointer = jem_rallocx_real( old_pointer, effective_size, flags );
+    let new_pointer = jem_rallocx_real( old_pointer, effective_size, jemalloc_real_flags( flags ) );
     if id.is_untracked() && !crate::global::is_actively_running() {
         thread = None;
     }
@@ -610,7 +695,7 @@ pub unsafe extern "C" fn _rjem_rallocx( old_pointer: *mut c_void, requested_size
                 new_size: requested_size as usize,
                 new_usable_size,
                 new_preceding_free_space: 0,
-                new_flags: event::ALLOC_FLAG_JEMALLOC,
+                new_flags: event::ALLOC_FLAG_JEMALLOC | jemalloc_arena_flags( flags ),
                 backtrace,
                 timestamp,
                 thread: thread.decay()
@@ -623,6 +708,7 @@ pub unsafe extern "C" fn _rjem_rallocx( old_pointer: *mut c_void, requested_size
             InternalEvent::Free {
                 id,
                 address: old_address,
+                contents: None,
                 backtrace,
                 timestamp,
                 thread: thread.decay()
@@ -651,7 +737,7 @@ pub unsafe extern "C" fn _rjem_xallocx( pointer: *mut c_void, requested_size: si
     debug_assert!( id.is_valid() );
 
     let mut thread = StrongThreadHandle::acquire();
-    let new_effective_size = jem_xallocx_real( pointer, effective_size, extra, flags );
+    let new_effective_size = jem_xallocx_real( pointer, effective_size, extra, jemalloc_real_flags( flags ) );
     let new_requested_size = new_effective_size.checked_sub( mem::size_of::< InternalAllocationId >() ).expect( "_rjem_xallocx: underflow" );
     if id.is_untracked() && !crate::global::is_actively_running() {
         thread = None;
@@ -686,7 +772,7 @@ pub unsafe extern "C" fn _rjem_xallocx( pointer: *mut c_void, requested_size: si
             new_size: new_requested_size as usize,
             new_usable_size,
             new_preceding_free_space: 0,
-            new_flags: event::ALLOC_FLAG_JEMALLOC,
+            new_flags: event::ALLOC_FLAG_JEMALLOC | jemalloc_arena_flags( flags ),
             backtrace,
             timestamp,
             thread: thread.decay()
@@ -703,7 +789,7 @@ pub unsafe extern "C" fn _rjem_nallocx( requested_size: size_t, flags: c_int ) -
         None => return 0
     };
 
-    jem_nallocx_real( effective_size, flags ).checked_sub( mem::size_of::< InternalAllocationId >() ).expect( "_rjem_nallocx: underflow" )
+    jem_nallocx_real( effective_size, jemalloc_real_flags( flags ) ).checked_sub( mem::size_of::< InternalAllocationId >() ).expect( "_rjem_nallocx: underflow" )
 }
 
 #[cfg_attr(not(test), no_mangle)]
@@ -723,28 +809,47 @@ pub unsafe extern "C" fn _rjem_mallctl( name: *const libc::c_char, _oldp: *mut c
 }
 
 #[cfg_attr(not(test), no_mangle)]
-pub unsafe extern "C" fn _rjem_posix_memalign( _pointer: *mut *mut c_void, _alignment: size_t, _size: size_t ) -> c_int {
-    todo!( "_rjem_posix_memalign" );
+pub unsafe extern "C" fn _rjem_posix_memalign( pointer: *mut *mut c_void, alignment: size_t, requested_size: size_t ) -> c_int {
+    let ptr_size = mem::size_of::< *const c_void >();
+    if alignment % ptr_size != 0 || !(alignment / ptr_size).is_power_of_two() || alignment == 0 {
+        return libc::EINVAL;
+    }
+
+    *pointer = _rjem_mallocx( requested_size, alignment.trailing_zeros() as c_int );
+    if (*pointer).is_null() {
+        libc::ENOMEM
+    } else {
+        0
+    }
 }
 
 #[cfg_attr(not(test), no_mangle)]
-pub unsafe extern "C" fn _rjem_aligned_alloc( _alignment: size_t, _size: size_t ) -> *mut c_void {
-    todo!( "_rjem_aligned_alloc" );
+pub unsafe extern "C" fn _rjem_aligned_alloc( alignment: size_t, requested_size: size_t ) -> *mut c_void {
+    if !alignment.is_power_of_two() {
+        *libc::__errno_location() = libc::EINVAL;
+        return ptr::null_mut();
+    }
+
+    _rjem_mallocx( requested_size, alignment.trailing_zeros() as c_int )
 }
 
 #[cfg_attr(not(test), no_mangle)]
-pub unsafe extern "C" fn _rjem_free( _pointer: *mut c_void ) {
-    todo!( "_rjem_free" );
+pub unsafe extern "C" fn _rjem_free( pointer: *mut c_void ) {
+    _rjem_dallocx( pointer, 0 )
 }
 
 #[cfg_attr(not(test), no_mangle)]
-pub unsafe extern "C" fn _rjem_sallocx( _pointer: *const c_void, _flags: c_int ) -> size_t {
-    todo!( "_rjem_dallocx" );
+pub unsafe extern "C" fn _rjem_sallocx( pointer: *const c_void, _flags: c_int ) -> size_t {
+    _rjem_malloc_usable_size( pointer )
 }
 
 #[cfg_attr(not(test), no_mangle)]
-pub unsafe extern "C" fn _rjem_dallocx( _pointer: *mut c_void, _flags: c_int ) {
-    todo!( "_rjem_dallocx" );
+pub unsafe extern "C" fn _rjem_dallocx( pointer: *mut c_void, flags: c_int ) {
+    if pointer.is_null() {
+        return;
+    }
+
+    _rjem_sdallocx( pointer, _rjem_malloc_usable_size( pointer ), flags )
 }
 
 #[cfg_attr(not(test), no_mangle)]
@@ -773,6 +878,84 @@ pub unsafe extern "C" fn _rjem_malloc_stats_print(
     jem_malloc_stats_print_real( write_cb, cbopaque, opts )
 }
 
+/*
+    These are for applications which are dynamically linked to `libjemalloc.so`.
+
+    We override `malloc` and `free`, so whatever jemalloc's own non-standard entry points
+    return has to come from the same allocator, otherwise it'd end up being freed by the wrong one.
+    They aren't exported; the calls to them are redirected here at startup (see `attach::redirect_jemalloc`).
+*/
+
+static REAL_NALLOCX: AtomicUsize = AtomicUsize::new( 0 );
+
+pub fn set_real_nallocx( address: usize ) {
+    REAL_NALLOCX.store( address, Ordering::Relaxed );
+}
+
+pub unsafe extern "C" fn mallocx( requested_size: size_t, flags: c_int ) -> *mut c_void {
+    let arena_flags = jemalloc_arena_flags( flags );
+    let alignment = mallocx_alignment( flags );
+    let pointer = match alignment {
+        Some( alignment ) => allocate( requested_size, AllocationKind::Aligned( alignment ), arena_flags ),
+        None if flags & MALLOCX_ZERO != 0 => allocate( requested_size, AllocationKind::Calloc, arena_flags ),
+        None => allocate( requested_size, AllocationKind::Malloc, arena_flags )
+    };
+
+    if alignment.is_some() && flags & MALLOCX_ZERO != 0 && !pointer.is_null() {
+        ptr::write_bytes( pointer as *mut u8, 0, requested_size );
+    }
+
+    pointer
+}
+
+pub unsafe extern "C" fn rallocx( old_pointer: *mut c_void, requested_size: size_t, flags: c_int ) -> *mut c_void {
+    if mallocx_alignment( flags ).is_none() && flags & MALLOCX_ZERO == 0 {
+        return realloc_impl( old_pointer, requested_size, jemalloc_arena_flags( flags ) );
+    }
+
+    // A plain `realloc` can neither align nor zero the memory, so this becomes a new allocation.
+    let new_pointer = mallocx( requested_size, flags );
+    if !new_pointer.is_null() {
+        let old_size = sallocx( old_pointer, 0 );
+        ptr::copy_nonoverlapping( old_pointer as *const u8, new_pointer as *mut u8, std::cmp::min( old_size, requested_size ) );
+        free( old_pointer );
+    }
+
+    new_pointer
+}
+
+pub unsafe extern "C" fn xallocx( pointer: *mut c_void, _requested_size: size_t, _extra: size_t, flags: c_int ) -> size_t {
+    // Resizing in place is never possible, which the caller has to be prepared for anyway.
+    sallocx( pointer, flags )
+}
+
+pub unsafe extern "C" fn sallocx( pointer: *const c_void, _flags: c_int ) -> size_t {
+    let metadata = get_allocation_metadata( pointer as *mut c_void );
+    metadata.usable_size - mem::size_of::< InternalAllocationId >()
+}
+
+pub unsafe extern "C" fn nallocx( requested_size: size_t, flags: c_int ) -> size_t {
+    let address = REAL_NALLOCX.load( Ordering::Relaxed );
+    if address != 0 {
+        let real: unsafe extern "C" fn( size_t, c_int ) -> size_t = mem::transmute( address );
+        return real( requested_size, flags );
+    }
+
+    let alignment = mallocx_alignment( flags ).unwrap_or( 1 );
+    match requested_size.checked_add( alignment - 1 ) {
+        Some( size ) => size & !(alignment - 1),
+        None => 0
+    }
+}
+
+pub unsafe extern "C" fn dallocx( pointer: *mut c_void, _flags: c_int ) {
+    free( pointer )
+}
+
+pub unsafe extern "C" fn sdallocx( pointer: *mut c_void, _requested_size: size_t, _flags: c_int ) {
+    free( pointer )
+}
+
 #[cfg_attr(not(test), no_mangle)]
 pub unsafe extern "C" fn posix_memalign( memptr: *mut *mut c_void, alignment: size_t, requested_size: size_t ) -> c_int {
     if memptr.is_null() {
@@ -784,7 +967,7 @@ pub unsafe extern "C" fn posix_memalign( memptr: *mut *mut c_void, alignment: si
         return libc::EINVAL;
     }
 
-    let pointer = allocate( requested_size, AllocationKind::Aligned( alignment ) );
+    let pointer = allocate( requested_size, AllocationKind::Aligned( alignment ), 0 );
     *memptr = pointer;
 
     if pointer.is_null() {
@@ -832,6 +1015,51 @@ pub unsafe extern "C" fn mmap( addr: *mut c_void, length: size_t, prot: c_int, f
     ptr
 }
 
+/// On 64-bit targets `off_t` is already 64-bit wide, so this is the same as `mmap`.
+#[cfg(target_pointer_width = "64")]
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn mmap64( addr: *mut c_void, length: size_t, prot: c_int, flags: c_int, fildes: c_int, off: off_t ) -> *mut c_void {
+    mmap( addr, length, prot, flags, fildes, off )
+}
+
+/// The `new_address` is a variadic argument, but since it's only read when `MREMAP_FIXED`
+/// is set it's fine to take it as a normal one.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn mremap( old_address: *mut c_void, old_size: size_t, new_size: size_t, flags: c_int, new_address: *mut c_void ) -> *mut c_void {
+    let mut thread = StrongThreadHandle::acquire();
+    if !opt::get().gather_mmap_calls {
+        thread = None;
+    }
+
+    let mut thread = if let Some( thread ) = thread {
+        thread
+    } else {
+        return syscall::mremap( old_address, old_size, new_size, flags, new_address );
+    };
+
+    let mut backtrace = Backtrace::new();
+    unwind::grab( &mut thread, &mut backtrace );
+
+    let _lock = crate::global::MMAP_LOCK.lock();
+    let ptr = syscall::mremap( old_address, old_size, new_size, flags, new_address );
+    if ptr == libc::MAP_FAILED {
+        return ptr;
+    }
+
+    send_event_throttled( || InternalEvent::Mremap {
+        old_pointer: old_address as usize,
+        old_length: old_size as usize,
+        pointer: ptr as usize,
+        length: new_size as usize,
+        flags: flags as u32,
+        backtrace,
+        timestamp: get_timestamp_if_enabled(),
+        thread: thread.decay()
+    });
+
+    ptr
+}
+
 #[cfg_attr(not(test), no_mangle)]
 pub unsafe extern "C" fn munmap( ptr: *mut c_void, length: size_t ) -> c_int {
     let mut thread = StrongThreadHandle::acquire();
@@ -969,6 +1197,65 @@ pub unsafe extern "C" fn memory_profiler_sync() {
     debug!( "Sync finished" );
 }
 
+/// Starts counting the allocations made by every thread of the process; returns 0 on success,
+/// or -1 if the profiling isn't running.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn memory_profiler_start_measurement() -> c_int {
+    if !crate::global::is_actively_running() {
+        return -1;
+    }
+
+    let thread = StrongThreadHandle::acquire();
+    *crate::measurement::RESULT.lock() = None;
+    send_event( InternalEvent::StartMeasurement );
+    mem::drop( thread );
+    0
+}
+
+/// Stops the measurement started with `memory_profiler_start_measurement` and writes its results
+/// into `output`; returns 0 on success, or -1 if the profiler didn't respond in time.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn memory_profiler_stop_measurement( output: *mut crate::measurement::Measurement ) -> c_int {
+    let thread = StrongThreadHandle::acquire();
+    send_event( InternalEvent::StopMeasurement );
+    mem::drop( thread );
+
+    // The processing thread picks up the events at least every 250ms.
+    for _ in 0..10000 {
+        if let Some( result ) = crate::measurement::RESULT.lock().take() {
+            if !output.is_null() {
+                *output = result;
+            }
+
+            return 0;
+        }
+
+        std::thread::sleep( std::time::Duration::from_millis( 1 ) );
+    }
+
+    -1
+}
+
+/// Hooks the allocation functions of a process into which the profiler was `dlopen`ed instead of being preloaded;
+/// returns the number of hooked call sites, or -1 if attaching isn't supported on this architecture.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn memory_profiler_attach() -> c_int {
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    {
+        let count = crate::attach::attach();
+        info!( "Attached to a running process; hooked {} call sites", count );
+
+        // Runs the startup initialization, if nothing was allocated yet since we were hooked.
+        mem::drop( StrongThreadHandle::acquire() );
+        count as c_int
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        -1
+    }
+}
+
 #[cfg_attr(not(test), no_mangle)]
 pub unsafe extern "C" fn __register_frame( fde: *const u8 ) {
     debug!( "Registering new frame: 0x{:016X}", fde as usize );
@@ -0,0 +1,268 @@
+use std::ffi::CStr;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use libc::c_int;
+
+/*
+    When the profiler is `dlopen`ed into an already running process (see `memory-profiler-agent sidecar --inject`)
+    nobody calls our `malloc` & co. since every library was already bound to the ones from libc. So we go through
+    the relocations of every loaded object and point their GOT entries for the functions we hook to ourselves,
+    which is exactly what the dynamic linker would have done if we were preloaded.
+
+    The memory which was allocated before we were attached doesn't have a valid `InternalAllocationId` at its end,
+    so the hooks pass it straight to libc (see `free` and `realloc_impl`).
+
+    Only the libraries which are loaded at the time of the attach are patched.
+
+    The GOT pages have to be made writable to be patched. The ones within an object's `PT_GNU_RELRO` segment
+    were made read-only by the dynamic linker, so they're made read-only again once the object is patched;
+    the rest (e.g. `.got.plt` when the binding is lazy) are still written to by the dynamic linker, so they're left as they are.
+
+    The same is done for jemalloc's non-standard entry points (`mallocx` & co.) when the program is linked
+    to a `libjemalloc.so`, since the memory they return would otherwise end up in our `free`. We don't export
+    those symbols ourselves, because plenty of libraries check whether a weak `mallocx` is defined to find out
+    whether they're running on top of jemalloc. This is only safe if nothing was allocated through them yet,
+    so it's done at startup when we're preloaded, and never when we're attached.
+*/
+
+#[cfg(target_arch = "x86_64")]
+const R_GLOB_DAT: u32 = 6;
+#[cfg(target_arch = "x86_64")]
+const R_JUMP_SLOT: u32 = 7;
+
+#[cfg(target_arch = "aarch64")]
+const R_GLOB_DAT: u32 = 1025;
+#[cfg(target_arch = "aarch64")]
+const R_JUMP_SLOT: u32 = 1026;
+
+const DT_NULL: i64 = 0;
+const DT_PLTRELSZ: i64 = 2;
+const DT_STRTAB: i64 = 5;
+const DT_SYMTAB: i64 = 6;
+const DT_RELA: i64 = 7;
+const DT_RELASZ: i64 = 8;
+const DT_JMPREL: i64 = 23;
+
+#[repr(C)]
+struct Dyn {
+    tag: i64,
+    value: u64
+}
+
+#[repr(C)]
+struct Rela {
+    offset: u64,
+    info: u64,
+    addend: i64
+}
+
+#[repr(C)]
+struct Sym {
+    name: u32,
+    info: u8,
+    other: u8,
+    shndx: u16,
+    value: u64,
+    size: u64
+}
+
+static ATTACHED: AtomicBool = AtomicBool::new( false );
+
+fn hooks() -> [(&'static [u8], usize); 18] {
+    use crate::api::*;
+    [
+        (b"malloc", malloc as usize),
+        (b"calloc", calloc as usize),
+        (b"realloc", realloc as usize),
+        (b"reallocarray", reallocarray as usize),
+        (b"free", free as usize),
+        (b"posix_memalign", posix_memalign as usize),
+        (b"memalign", memalign as usize),
+        (b"aligned_alloc", aligned_alloc as usize),
+        (b"valloc", valloc as usize),
+        (b"pvalloc", pvalloc as usize),
+        (b"mallopt", mallopt as usize),
+        (b"mmap", mmap as usize),
+        (b"mmap64", mmap64 as usize),
+        (b"mremap", mremap as usize),
+        (b"munmap", munmap as usize),
+        (b"fork", fork as usize),
+        (b"_exit", _exit as usize),
+        (b"_Exit", _Exit as usize)
+    ]
+}
+
+fn jemalloc_hooks() -> [(&'static [u8], usize); 7] {
+    use crate::api::*;
+    [
+        (b"mallocx", mallocx as usize),
+        (b"rallocx", rallocx as usize),
+        (b"xallocx", xallocx as usize),
+        (b"sallocx", sallocx as usize),
+        (b"nallocx", nallocx as usize),
+        (b"dallocx", dallocx as usize),
+        (b"sdallocx", sdallocx as usize)
+    ]
+}
+
+struct State< 'a > {
+    own_address: usize,
+    hooks: &'a [(&'static [u8], usize)],
+    patched: usize,
+    /// The pages of the object's RELRO segment, as they were protected by the dynamic linker.
+    relro: Option< (usize, usize) >,
+    /// The pages of the RELRO segment which were made writable to patch them.
+    relro_pages: Vec< usize >
+}
+
+unsafe fn patch( state: &mut State, base: usize, relocations: usize, size: usize, symtab: usize, strtab: usize ) {
+    let count = size / mem::size_of::< Rela >();
+    for index in 0..count {
+        let relocation = &*(relocations as *const Rela).add( index );
+        let kind = (relocation.info & 0xffffffff) as u32;
+        if kind != R_JUMP_SLOT && kind != R_GLOB_DAT {
+            continue;
+        }
+
+        let symbol = &*(symtab as *const Sym).add( (relocation.info >> 32) as usize );
+        let name = CStr::from_ptr( (strtab + symbol.name as usize) as *const libc::c_char ).to_bytes();
+        let address = match state.hooks.iter().find( |(hook, _)| *hook == name ) {
+            Some( &(_, address) ) => address,
+            None => continue
+        };
+
+        let slot = (base + relocation.offset as usize) as *mut usize;
+
+        // The GOT is usually read-only after the relocation (RELRO).
+        let page = (slot as usize) & !(crate::PAGE_SIZE - 1);
+        if libc::mprotect( page as *mut libc::c_void, crate::PAGE_SIZE, libc::PROT_READ | libc::PROT_WRITE ) < 0 {
+            warn!( "Failed to make the GOT entry for '{}' writable: {}", String::from_utf8_lossy( name ), std::io::Error::last_os_error() );
+            continue;
+        }
+
+        std::ptr::write_volatile( slot, address );
+        state.patched += 1;
+
+        if let Some( (start, end) ) = state.relro {
+            if page >= start && page < end && !state.relro_pages.contains( &page ) {
+                state.relro_pages.push( page );
+            }
+        }
+    }
+}
+
+unsafe extern fn callback( info: *mut libc::dl_phdr_info, _: libc::size_t, data: *mut libc::c_void ) -> c_int {
+    let state = &mut *(data as *mut State);
+    let info = &*info;
+    let base = info.dlpi_addr as usize;
+
+    let mut dynamic = None;
+    let mut relro = None;
+    for index in 0..info.dlpi_phnum as usize {
+        let header = &*info.dlpi_phdr.add( index );
+        if header.p_type == libc::PT_LOAD && header.p_flags & libc::PF_X != 0 {
+            let start = base + header.p_vaddr as usize;
+            if (start..start + header.p_memsz as usize).contains( &state.own_address ) {
+                return 0;
+            }
+        } else if header.p_type == libc::PT_DYNAMIC {
+            dynamic = Some( (base + header.p_vaddr as usize) as *const Dyn );
+        } else if header.p_type == libc::PT_GNU_RELRO {
+            // Same as the dynamic linker, which only protects the pages which are fully covered by the segment.
+            let start = base + header.p_vaddr as usize;
+            let end = start + header.p_memsz as usize;
+            relro = Some( (start & !(crate::PAGE_SIZE - 1), end & !(crate::PAGE_SIZE - 1)) );
+        }
+    }
+
+    let dynamic = match dynamic {
+        Some( dynamic ) => dynamic,
+        None => return 0
+    };
+
+    // The dynamic linker relocates most of these in-place, but not for every object (e.g. not for the vDSO).
+    let adjust = |value: u64| if (value as usize) < base { base + value as usize } else { value as usize };
+
+    let (mut symtab, mut strtab) = (0, 0);
+    let (mut rela, mut rela_size) = (0, 0);
+    let (mut jmprel, mut jmprel_size) = (0, 0);
+    let mut entry = dynamic;
+    while (*entry).tag != DT_NULL {
+        let value = (*entry).value;
+        match (*entry).tag {
+            DT_SYMTAB => symtab = adjust( value ),
+            DT_STRTAB => strtab = adjust( value ),
+            DT_RELA => rela = adjust( value ),
+            DT_RELASZ => rela_size = value as usize,
+            DT_JMPREL => jmprel = adjust( value ),
+            DT_PLTRELSZ => jmprel_size = value as usize,
+            _ => {}
+        }
+
+        entry = entry.add( 1 );
+    }
+
+    if symtab == 0 || strtab == 0 {
+        return 0;
+    }
+
+    state.relro = relro;
+    state.relro_pages.clear();
+
+    if rela != 0 {
+        patch( state, base, rela, rela_size, symtab, strtab );
+    }
+
+    if jmprel != 0 {
+        patch( state, base, jmprel, jmprel_size, symtab, strtab );
+    }
+
+    for &page in &state.relro_pages {
+        if libc::mprotect( page as *mut libc::c_void, crate::PAGE_SIZE, libc::PROT_READ ) < 0 {
+            warn!( "Failed to make the GOT page at 0x{:016X} read-only again: {}", page, std::io::Error::last_os_error() );
+        }
+    }
+
+    0
+}
+
+fn patch_loaded_objects( hooks: &[(&'static [u8], usize)] ) -> usize {
+    let mut state = State {
+        own_address: patch_loaded_objects as usize,
+        hooks,
+        patched: 0,
+        relro: None,
+        relro_pages: Vec::new()
+    };
+
+    unsafe {
+        libc::dl_iterate_phdr( Some( callback ), &mut state as *mut _ as *mut libc::c_void );
+    }
+
+    state.patched
+}
+
+/// Hooks the allocation functions of every library which is currently loaded; returns how many GOT entries were patched.
+pub fn attach() -> usize {
+    ATTACHED.store( true, Ordering::SeqCst );
+    patch_loaded_objects( &hooks() )
+}
+
+/// Whether we were `dlopen`ed into a running process instead of being preloaded.
+pub fn is_attached() -> bool {
+    ATTACHED.load( Ordering::SeqCst )
+}
+
+/// Redirects the calls to a dynamically linked jemalloc's `mallocx` & co. to our own; does nothing if there's no jemalloc.
+pub fn redirect_jemalloc() {
+    let nallocx = unsafe { libc::dlsym( libc::RTLD_DEFAULT, b"nallocx\0".as_ptr() as *const libc::c_char ) };
+    let mallocx = unsafe { libc::dlsym( libc::RTLD_DEFAULT, b"mallocx\0".as_ptr() as *const libc::c_char ) };
+    if mallocx.is_null() {
+        return;
+    }
+
+    crate::api::set_real_nallocx( nallocx as usize );
+    let count = patch_loaded_objects( &jemalloc_hooks() );
+    info!( "Found a dynamically linked jemalloc; hooked {} of its call sites", count );
+}
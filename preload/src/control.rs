@@ -0,0 +1,90 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::opt;
+use crate::utils::generate_filename;
+
+/*
+    The control socket accepts one command per line and answers every one of them with a single line:
+
+        status -> "enabled" or "disabled"
+        start  -> "enabled"
+        stop   -> "disabled"
+
+    Unlike the signals this tells the caller what the state actually is, and it can't kill a process
+    which doesn't have the profiler loaded, since then there's simply no socket to connect to.
+*/
+
+fn state() -> &'static str {
+    if crate::global::is_actively_running() {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+fn handle( stream: UnixStream ) -> io::Result< () > {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new( stream ).lines() {
+        let line = line?;
+        match line.trim() {
+            "status" => {},
+            "start" => {
+                crate::global::enable();
+            },
+            "stop" => {
+                crate::global::disable();
+            },
+            command => {
+                writeln!( writer, "error: unknown command '{}'", command )?;
+                continue;
+            }
+        }
+
+        writeln!( writer, "{}", state() )?;
+    }
+
+    Ok(())
+}
+
+fn run( listener: UnixListener ) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok( stream ) => {
+                if let Err( error ) = handle( stream ) {
+                    warn!( "Failed to handle a control connection: {}", error );
+                }
+            },
+            Err( error ) => warn!( "Failed to accept a control connection: {}", error )
+        }
+    }
+}
+
+/// Starts listening on `MEMORY_PROFILER_CONTROL_SOCKET`, if it's set.
+pub fn initialize() {
+    let path = match opt::get().control_socket {
+        Some( ref pattern ) => generate_filename( pattern, None ),
+        None => return
+    };
+
+    // A socket left over by a process with the same PID.
+    let _ = fs::remove_file( &path );
+    let listener = match UnixListener::bind( &path ) {
+        Ok( listener ) => listener,
+        Err( error ) => {
+            error!( "Couldn't bind the control socket at '{}': {}", path, error );
+            return;
+        }
+    };
+
+    if let Err( error ) = fs::set_permissions( &path, fs::Permissions::from_mode( 0o600 ) ) {
+        warn!( "Couldn't restrict the permissions of '{}': {}", path, error );
+    }
+
+    info!( "Listening for commands on '{}'", path );
+    if let Err( error ) = crate::global::spawn_internal_thread( "mem-prof-ctrl", move || run( listener ) ) {
+        error!( "Couldn't start the control thread: {}", error );
+    }
+}
@@ -0,0 +1,42 @@
+use std::fs::File;
+use std::io::{self, Write};
+
+/// An output file which is transparently encrypted with `age` for a given recipient.
+pub struct EncryptedFile {
+    writer: Option< age::stream::StreamWriter< File > >
+}
+
+impl EncryptedFile {
+    pub fn new( fp: File, recipient: &str ) -> io::Result< Self > {
+        let recipient: age::x25519::Recipient = recipient.parse().map_err( |error: &str| {
+            io::Error::new( io::ErrorKind::InvalidInput, format!( "invalid encryption recipient: {}", error ) )
+        })?;
+
+        let encryptor = age::Encryptor::with_recipients( vec![ Box::new( recipient ) ] );
+        let writer = encryptor.wrap_output( fp )?;
+        Ok( EncryptedFile {
+            writer: Some( writer )
+        })
+    }
+}
+
+impl Write for EncryptedFile {
+    fn write( &mut self, data: &[u8] ) -> io::Result< usize > {
+        self.writer.as_mut().unwrap().write( data )
+    }
+
+    fn flush( &mut self ) -> io::Result< () > {
+        self.writer.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for EncryptedFile {
+    fn drop( &mut self ) {
+        // The last chunk is only written out when the stream is finished.
+        if let Some( writer ) = self.writer.take() {
+            if let Err( error ) = writer.finish().and_then( |mut fp| fp.flush() ) {
+                warn!( "Failed to finish writing the encrypted output: {}", error );
+            }
+        }
+    }
+}
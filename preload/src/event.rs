@@ -67,6 +67,61 @@ impl From< InternalAllocationId > for common::event::AllocationId {
     }
 }
 
+/// The maximum number of bytes of an allocation which can be sampled.
+pub const MAX_SAMPLED_CONTENTS: usize = 64;
+
+/// The first few bytes of an allocation; they're stored inline, so sampling them doesn't allocate.
+pub struct ContentsSample {
+    length: usize,
+    bytes: [u8; MAX_SAMPLED_CONTENTS]
+}
+
+impl ContentsSample {
+    /// Returns how many bytes should be sampled from an allocation of a given usable size
+    /// (which includes the space for its ID), if any.
+    pub fn length_for( usable_size: usize ) -> Option< usize > {
+        let usable_size = usable_size.saturating_sub( std::mem::size_of::< InternalAllocationId >() );
+        let opts = crate::opt::get();
+        if opts.sample_contents == 0 || usable_size < opts.sample_contents_min_size || usable_size > opts.sample_contents_max_size {
+            return None;
+        }
+
+        Some( std::cmp::min( usable_size, opts.sample_contents ) )
+    }
+
+    /// Copies the first `length` bytes starting at `pointer`.
+    pub unsafe fn copy_from( pointer: *const u8, length: usize ) -> Self {
+        let mut sample = ContentsSample {
+            length: std::cmp::min( length, MAX_SAMPLED_CONTENTS ),
+            bytes: [0; MAX_SAMPLED_CONTENTS]
+        };
+
+        std::ptr::copy_nonoverlapping( pointer, sample.bytes.as_mut_ptr(), sample.length );
+        sample
+    }
+
+    /// Copies the first `length` bytes starting at `address` without crashing if the memory's not there anymore.
+    pub fn read_from( address: usize, length: usize ) -> Option< Self > {
+        let mut sample = ContentsSample {
+            length: std::cmp::min( length, MAX_SAMPLED_CONTENTS ),
+            bytes: [0; MAX_SAMPLED_CONTENTS]
+        };
+
+        let local = libc::iovec { iov_base: sample.bytes.as_mut_ptr() as *mut libc::c_void, iov_len: sample.length };
+        let remote = libc::iovec { iov_base: address as *mut libc::c_void, iov_len: sample.length };
+        let count = unsafe { libc::process_vm_readv( libc::getpid(), &local, 1, &remote, 1, 0 ) };
+        if count != sample.length as isize {
+            return None;
+        }
+
+        Some( sample )
+    }
+
+    pub fn as_slice( &self ) -> &[u8] {
+        &self.bytes[ ..self.length ]
+    }
+}
+
 pub(crate) enum InternalEvent {
     Alloc {
         id: InternalAllocationId,
@@ -94,6 +149,7 @@ pub(crate) enum InternalEvent {
     Free {
         id: InternalAllocationId,
         address: NonZeroUsize,
+        contents: Option< ContentsSample >,
         backtrace: Backtrace,
         timestamp: Timestamp,
         thread: WeakThreadHandle
@@ -103,6 +159,8 @@ pub(crate) enum InternalEvent {
     SetMarker {
         value: u32
     },
+    StartMeasurement,
+    StopMeasurement,
     Mmap {
         pointer: usize,
         requested_address: usize,
@@ -122,6 +180,16 @@ pub(crate) enum InternalEvent {
         timestamp: Timestamp,
         thread: WeakThreadHandle
     },
+    Mremap {
+        old_pointer: usize,
+        old_length: usize,
+        pointer: usize,
+        length: usize,
+        flags: u32,
+        backtrace: Backtrace,
+        timestamp: Timestamp,
+        thread: WeakThreadHandle
+    },
     Mallopt {
         param: i32,
         value: i32,
@@ -225,14 +225,28 @@ fn spawn_processing_thread() {
     *thread_handle = Some( new_handle );
 }
 
+/// Spawns a helper thread of the processing thread; its own allocations aren't tracked.
+pub(crate) fn spawn_internal_thread< F >( name: &str, callback: F ) -> std::io::Result< thread::JoinHandle< () > > where F: FnOnce() + Send + 'static {
+    thread::Builder::new().name( name.into() ).spawn( move || {
+        TLS.with( |tls| {
+            unsafe {
+                *tls.is_internal.get() = true;
+            }
+            tls.set_enabled( false );
+        });
+
+        callback();
+    })
+}
+
 #[cfg(target_arch = "x86_64")]
-fn find_internal_syms< const N: usize >( names: &[&str; N] ) -> [usize; N] {
-    let mut addresses = [0; N];
+fn find_internal_syms( names: &[String] ) -> Vec< usize > {
+    let mut addresses = vec![ 0; names.len() ];
 
     unsafe {
         use goblin::elf64::header::Header;
         use goblin::elf64::section_header::SectionHeader;
-        use goblin::elf::section_header::SHT_SYMTAB;
+        use goblin::elf::section_header::{SHN_UNDEF, SHT_SYMTAB};
         use goblin::elf::sym::sym64::Sym;
 
         let mut path = libc::getauxval( libc::AT_EXECFN ) as *const libc::c_char;
@@ -282,6 +296,11 @@ fn find_internal_syms< const N: usize >( names: &[&str; N] ) -> [usize; N] {
             );
 
             for sym in syms {
+                // These are the ones which the executable imports from elsewhere.
+                if sym.st_shndx as u32 == SHN_UNDEF {
+                    continue;
+                }
+
                 let bytes = &strtab_bytes[ sym.st_name as usize.. ];
                 let name = &bytes[ ..bytes.iter().position( |&byte| byte == 0 ).unwrap_or( bytes.len() ) ];
                 for (target_name, output_address) in names.iter().zip( addresses.iter_mut() ) {
@@ -306,29 +325,41 @@ fn find_internal_syms< const N: usize >( names: &[&str; N] ) -> [usize; N] {
     addresses
 }
 
+/// The prefixes with which jemalloc can be built; the Rust crates use the first one.
+///
+/// An unprefixed jemalloc is the executable's `malloc` itself, which has already handed out memory
+/// by the time we get here; freeing that through our wrappers would be undefined behavior.
 #[cfg(target_arch = "x86_64")]
-fn hook_jemalloc() {
-    let names = [
-        "_rjem_malloc",
-        "_rjem_mallocx",
-        "_rjem_calloc",
-        "_rjem_sdallocx",
-        "_rjem_realloc",
-        "_rjem_rallocx",
-        "_rjem_nallocx",
-        "_rjem_xallocx",
-        "_rjem_malloc_usable_size",
-        "_rjem_mallctl",
-        "_rjem_posix_memalign",
-        "_rjem_aligned_alloc",
-        "_rjem_free",
-        "_rjem_sallocx",
-        "_rjem_dallocx",
-        "_rjem_mallctlnametomib",
-        "_rjem_mallctlbymib",
-        "_rjem_malloc_stats_print",
-    ];
+const JEMALLOC_PREFIXES: [&str; 2] = [ "_rjem_", "je_" ];
 
+#[cfg(target_arch = "x86_64")]
+const JEMALLOC_FUNCTIONS: [&str; 18] = [
+    "malloc",
+    "mallocx",
+    "calloc",
+    "sdallocx",
+    "realloc",
+    "rallocx",
+    "nallocx",
+    "xallocx",
+    "malloc_usable_size",
+    "mallctl",
+    "posix_memalign",
+    "aligned_alloc",
+    "free",
+    "sallocx",
+    "dallocx",
+    "mallctlnametomib",
+    "mallctlbymib",
+    "malloc_stats_print",
+];
+
+/// Redirects a jemalloc which was statically linked into the executable to our own wrappers.
+///
+/// A jemalloc which lives in its own shared library is taken care of by `attach::redirect_jemalloc`,
+/// but here the calls never go through the dynamic linker.
+#[cfg(target_arch = "x86_64")]
+fn hook_jemalloc() {
     let replacements = [
         crate::api::_rjem_malloc as usize,
         crate::api::_rjem_mallocx as usize,
@@ -350,16 +381,35 @@ fn hook_jemalloc() {
         crate::api::_rjem_malloc_stats_print as usize,
     ];
 
-    let addresses = find_internal_syms( &names );
-    if addresses.iter().all( |&address| address == 0 ) {
+    let all_names: Vec< String > = JEMALLOC_PREFIXES.iter()
+        .flat_map( |prefix| JEMALLOC_FUNCTIONS.iter().map( move |name| format!( "{}{}", prefix, name ) ) )
+        .collect();
+
+    let all_addresses = find_internal_syms( &all_names );
+    let mallocx_index = JEMALLOC_FUNCTIONS.iter().position( |&name| name == "mallocx" ).unwrap();
+    let mut found = false;
+    for ((prefix, names), addresses) in JEMALLOC_PREFIXES.iter().zip( all_names.chunks( JEMALLOC_FUNCTIONS.len() ) ).zip( all_addresses.chunks( JEMALLOC_FUNCTIONS.len() ) ) {
+        // Plenty of things can define a `malloc`, but only jemalloc has a `mallocx`.
+        if addresses[ mallocx_index ] == 0 {
+            continue;
+        }
+
+        info!( "Found jemalloc with the \"{}\" prefix in the executable", prefix );
+        found = true;
+        patch_jemalloc( names, &replacements, addresses );
+    }
+
+    if !found {
         info!( "Couldn't find jemalloc in the executable's address space" );
-        return;
     }
+}
 
+#[cfg(target_arch = "x86_64")]
+fn patch_jemalloc( names: &[String], replacements: &[usize], addresses: &[usize] ) {
     assert_eq!( names.len(), replacements.len() );
     assert_eq!( names.len(), addresses.len() );
 
-    for ((name, replacement), address) in names.iter().zip( replacements ).zip( addresses ) {
+    for ((name, &replacement), &address) in names.iter().zip( replacements ).zip( addresses ) {
         if address == 0 {
             info!( "Symbol not found: \"{}\"", name );
             continue;
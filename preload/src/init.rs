@@ -88,11 +88,20 @@ pub fn startup() {
     }
 
     initialize_atexit_hook();
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    {
+        if !crate::attach::is_attached() {
+            crate::attach::redirect_jemalloc();
+        }
+    }
+
     if !opt::get().disabled_by_default {
         crate::global::toggle();
     }
 
     initialize_signal_handlers();
+    crate::control::initialize();
 
     env::remove_var( "LD_PRELOAD" );
     info!( "Startup initialization finished" );
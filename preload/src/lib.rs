@@ -35,6 +35,14 @@ mod init;
 mod processing_thread;
 mod global;
 mod ordered_map;
+mod metrics_push;
+mod live_tracker;
+mod measurement;
+mod control;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod attach;
+#[cfg(feature = "encryption")]
+mod encryption;
 
 use crate::event::InternalEvent;
 use crate::utils::read_file;
@@ -76,6 +84,7 @@ pub use crate::api::{
     free,
     posix_memalign,
     mmap,
+    mremap,
     munmap,
     mallopt,
     memalign,
@@ -87,5 +96,11 @@ pub use crate::api::{
     memory_profiler_override_next_timestamp,
     memory_profiler_start,
     memory_profiler_stop,
-    memory_profiler_sync
+    memory_profiler_sync,
+    memory_profiler_start_measurement,
+    memory_profiler_stop_measurement,
+    memory_profiler_attach
 };
+
+#[cfg(target_pointer_width = "64")]
+pub use crate::api::mmap64;
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt::Write;
+use std::mem;
+
+use crate::timestamp::Timestamp;
+
+/// How often, in seconds, the window over which the allocation rate is calculated moves forward.
+const RATE_WINDOW: u64 = 10;
+
+/// How many of the innermost frames of a site are included in the summary.
+const MAX_SUMMARY_FRAMES: usize = 32;
+
+/*
+    The backtrace IDs are only meaningful together with the `Backtrace` events of the data file,
+    which a client that only asks for the summary never sees, and the same backtrace can get
+    a new ID once it falls out of the backtrace cache. So the sites are keyed by their frames instead,
+    and they're reported with the frames resolved to `library!symbol+0xoffset` (or `library+0xoffset`
+    if there's no symbol) and with an ID which is a hash of the library relative addresses,
+    and so is the same for every run of the same binaries.
+*/
+
+// These are taken from FNV.
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+fn hash_frames( frames: &[usize] ) -> u64 {
+    frames.iter().fold( FNV_OFFSET, |hash, &frame| (hash ^ frame as u64).wrapping_mul( FNV_PRIME ) )
+}
+
+struct ResolvedFrame {
+    library: Option< String >,
+    symbol: Option< (String, usize) >,
+    /// The offset from the start of the library, or the absolute address if it's not known.
+    offset: usize
+}
+
+fn resolve_frame( address: usize ) -> ResolvedFrame {
+    let mut info: libc::Dl_info = unsafe { mem::zeroed() };
+    if unsafe { libc::dladdr( address as *const libc::c_void, &mut info ) } == 0 || info.dli_fname.is_null() {
+        return ResolvedFrame { library: None, symbol: None, offset: address };
+    }
+
+    let path = unsafe { CStr::from_ptr( info.dli_fname ) }.to_string_lossy();
+    let library = path.rsplit( '/' ).next().unwrap_or( "" ).to_owned();
+    let symbol = if info.dli_sname.is_null() {
+        None
+    } else {
+        let name = unsafe { CStr::from_ptr( info.dli_sname ) }.to_string_lossy().into_owned();
+        Some( (name, address.wrapping_sub( info.dli_saddr as usize )) )
+    };
+
+    ResolvedFrame { library: Some( library ), symbol, offset: address.wrapping_sub( info.dli_fbase as usize ) }
+}
+
+impl ResolvedFrame {
+    fn describe( &self ) -> String {
+        match (&self.library, &self.symbol) {
+            (Some( library ), Some( (symbol, offset) )) => format!( "{}!{}+0x{:x}", library, symbol, offset ),
+            (Some( library ), None) => format!( "{}+0x{:x}", library, self.offset ),
+            (None, _) => format!( "0x{:x}", self.offset )
+        }
+    }
+}
+
+fn push_json_string( output: &mut String, value: &str ) {
+    output.push( '"' );
+    for ch in value.chars() {
+        match ch {
+            '"' => output.push_str( "\\\"" ),
+            '\\' => output.push_str( "\\\\" ),
+            ch if (ch as u32) < 0x20 => { let _ = write!( output, "\\u{:04x}", ch as u32 ); },
+            ch => output.push( ch )
+        }
+    }
+    output.push( '"' );
+}
+
+/// One of the sites which hold the most live memory.
+#[derive(PartialEq, Debug)]
+pub struct TopSite {
+    /// A hash of the library relative addresses of the frames, which is stable across runs.
+    pub id: u64,
+    /// The innermost frames of the site, resolved to the libraries and the symbols they're from.
+    pub frames: Vec< String >,
+    pub live_count: u64,
+    pub live_size: u64
+}
+
+struct Site {
+    frames: Vec< usize >,
+    live_count: u64,
+    live_size: u64
+}
+
+/// Keeps track of the live allocations, so that a summary of them is always at hand.
+pub struct LiveTracker {
+    started: Timestamp,
+    allocations: HashMap< u64, (u64, u64) >,
+    /// The live allocations of every site, keyed by the hash of its frames.
+    sites: HashMap< u64, Site >,
+    live_size: u64,
+    allocated_count: u64,
+    allocated_size: u64,
+    /// The time and the totals at the start of the previous and of the current window.
+    rate_marks: [(Timestamp, u64, u64); 2]
+}
+
+impl LiveTracker {
+    pub fn new( now: Timestamp ) -> Self {
+        LiveTracker {
+            started: now,
+            allocations: HashMap::new(),
+            sites: HashMap::new(),
+            live_size: 0,
+            allocated_count: 0,
+            allocated_size: 0,
+            rate_marks: [(now, 0, 0); 2]
+        }
+    }
+
+    pub fn on_allocation( &mut self, pointer: u64, size: u64, frames: &[usize] ) {
+        let key = hash_frames( frames );
+        if let Some( (old_size, old_key) ) = self.allocations.insert( pointer, (size, key) ) {
+            self.remove( old_size, old_key );
+        }
+
+        self.allocated_count += 1;
+        self.allocated_size += size;
+        self.live_size += size;
+
+        let site = self.sites.entry( key ).or_insert_with( || Site { frames: frames.to_vec(), live_count: 0, live_size: 0 } );
+        site.live_count += 1;
+        site.live_size += size;
+    }
+
+    pub fn on_deallocation( &mut self, pointer: u64 ) {
+        if let Some( (size, key) ) = self.allocations.remove( &pointer ) {
+            self.remove( size, key );
+        }
+    }
+
+    fn remove( &mut self, size: u64, key: u64 ) {
+        self.live_size -= size;
+        if let Some( site ) = self.sites.get_mut( &key ) {
+            site.live_count -= 1;
+            site.live_size -= size;
+            if site.live_count == 0 {
+                self.sites.remove( &key );
+            }
+        }
+    }
+
+    pub fn live_size( &self ) -> u64 {
+        self.live_size
+    }
+
+    pub fn live_count( &self ) -> u64 {
+        self.allocations.len() as u64
+    }
+
+    /// The number of allocations made since the start.
+    pub fn allocated_count( &self ) -> u64 {
+        self.allocated_count
+    }
+
+    /// The number of bytes allocated since the start.
+    pub fn allocated_size( &self ) -> u64 {
+        self.allocated_size
+    }
+
+    /// The sites which hold the most memory.
+    pub fn top_sites( &self, count: usize ) -> Vec< TopSite > {
+        let mut sites: Vec< _ > = self.sites.iter().collect();
+        sites.sort_by( |a, b| b.1.live_size.cmp( &a.1.live_size ).then_with( || a.0.cmp( b.0 ) ) );
+        sites.truncate( count );
+
+        // Only the few sites which are reported are resolved, since `dladdr` isn't exactly cheap.
+        sites.into_iter().map( |(_, site)| {
+            let frames: Vec< _ > = site.frames.iter().map( |&address| resolve_frame( address ) ).collect();
+            let id = frames.iter().fold( FNV_OFFSET, |hash, frame| {
+                let hash = frame.library.as_ref().map( |library| library.bytes().fold( hash, |hash, byte| (hash ^ byte as u64).wrapping_mul( FNV_PRIME ) ) ).unwrap_or( hash );
+                (hash ^ frame.offset as u64).wrapping_mul( FNV_PRIME )
+            });
+
+            TopSite {
+                id,
+                frames: frames.iter().take( MAX_SUMMARY_FRAMES ).map( |frame| frame.describe() ).collect(),
+                live_count: site.live_count,
+                live_size: site.live_size
+            }
+        }).collect()
+    }
+
+    pub fn update_rate( &mut self, now: Timestamp ) {
+        if (now - self.rate_marks[ 1 ].0).as_secs() >= RATE_WINDOW {
+            self.rate_marks[ 0 ] = self.rate_marks[ 1 ];
+            self.rate_marks[ 1 ] = (now, self.allocated_count, self.allocated_size);
+        }
+    }
+
+    /// The number of allocations and of bytes allocated per second, over the last 10 to 20 seconds.
+    fn rate( &self, now: Timestamp ) -> (u64, u64) {
+        let (start, count, size) = self.rate_marks[ 0 ];
+        let elapsed = (now - start).as_msecs().max( 1 );
+        ((self.allocated_count - count) * 1000 / elapsed, (self.allocated_size - size) * 1000 / elapsed)
+    }
+
+    pub fn summary_json( &self, now: Timestamp, initial_timestamp: Timestamp ) -> String {
+        let (allocations_per_second, allocated_bytes_per_second) = self.rate( now );
+        let mut output = String::new();
+        let _ = write!(
+            output,
+            "{{\"elapsed\":{},\"tracked_for\":{},\"live_bytes\":{},\"live_allocations\":{},\"allocated_bytes\":{},\"allocations\":{},\"allocations_per_second\":{},\"allocated_bytes_per_second\":{},\"top_sites\":[",
+            (now - initial_timestamp).as_secs(),
+            (now - self.started).as_secs(),
+            self.live_size,
+            self.live_count(),
+            self.allocated_size,
+            self.allocated_count,
+            allocations_per_second,
+            allocated_bytes_per_second
+        );
+
+        for (index, site) in self.top_sites( 20 ).into_iter().enumerate() {
+            if index != 0 {
+                output.push( ',' );
+            }
+
+            // The ID is a string since it doesn't fit into a double.
+            let _ = write!( output, "{{\"site\":\"{:016x}\",\"frames\":[", site.id );
+            for (index, frame) in site.frames.iter().enumerate() {
+                if index != 0 {
+                    output.push( ',' );
+                }
+
+                push_json_string( &mut output, frame );
+            }
+
+            let _ = write!( output, "],\"live_allocations\":{},\"live_bytes\":{}}}", site.live_count, site.live_size );
+        }
+
+        output.push_str( "]}" );
+        output
+    }
+}
+
+#[test]
+fn test_live_tracker() {
+    let mut tracker = LiveTracker::new( Timestamp::from_secs( 0 ) );
+    tracker.on_allocation( 0x1000, 100, &[ 1 ] );
+    tracker.on_allocation( 0x2000, 50, &[ 2, 20 ] );
+    tracker.on_allocation( 0x3000, 60, &[ 2, 20 ] );
+    tracker.on_allocation( 0x4000, 10, &[ 3 ] );
+    tracker.on_deallocation( 0x1000 );
+    tracker.on_deallocation( 0x5000 );
+
+    assert_eq!( tracker.live_size(), 120 );
+    assert_eq!( tracker.live_count(), 3 );
+
+    let top_sites = tracker.top_sites( 1 );
+    assert_eq!( top_sites.len(), 1 );
+    assert_eq!( top_sites[ 0 ].frames, vec![ "0x2".to_owned(), "0x20".to_owned() ] );
+    assert_eq!( (top_sites[ 0 ].live_count, top_sites[ 0 ].live_size), (2, 110) );
+
+    tracker.update_rate( Timestamp::from_secs( 10 ) );
+    tracker.on_allocation( 0x6000, 30, &[ 3 ] );
+    assert_eq!( tracker.rate( Timestamp::from_secs( 10 ) ), (0, 25) );
+
+    tracker.update_rate( Timestamp::from_secs( 20 ) );
+    assert_eq!( tracker.rate( Timestamp::from_secs( 20 ) ), (0, 3) );
+
+    let top_sites = tracker.top_sites( 2 );
+    assert_eq!(
+        tracker.summary_json( Timestamp::from_secs( 20 ), Timestamp::from_secs( 0 ) ),
+        format!(
+            "{{\"elapsed\":20,\"tracked_for\":20,\"live_bytes\":150,\"live_allocations\":4,\"allocated_bytes\":250,\"allocations\":5,\
+             \"allocations_per_second\":0,\"allocated_bytes_per_second\":3,\"top_sites\":[\
+             {{\"site\":\"{:016x}\",\"frames\":[\"0x2\",\"0x20\"],\"live_allocations\":2,\"live_bytes\":110}},\
+             {{\"site\":\"{:016x}\",\"frames\":[\"0x3\"],\"live_allocations\":2,\"live_bytes\":40}}]}}",
+            top_sites[ 0 ].id,
+            top_sites[ 1 ].id
+        )
+    );
+}
+
+#[test]
+fn test_site_ids_are_library_relative() {
+    fn site() {}
+
+    let frame = resolve_frame( site as usize );
+    assert!( frame.library.is_some() );
+    assert_ne!( frame.offset, site as usize );
+
+    let mut json = String::new();
+    push_json_string( &mut json, "a\"b\\c\n" );
+    assert_eq!( json, "\"a\\\"b\\\\c\\u000a\"" );
+}
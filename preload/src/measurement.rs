@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use crate::spin_lock::SpinLock;
+
+/// What was allocated between `memory_profiler_start_measurement` and `memory_profiler_stop_measurement`.
+///
+/// This is a part of the C API, so its layout can't change.
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Measurement {
+    pub allocations: u64,
+    pub reallocations: u64,
+    pub deallocations: u64,
+    pub allocated_bytes: u64,
+    /// Only the memory which was allocated during the measurement is counted here.
+    pub deallocated_bytes: u64,
+    pub peak_bytes: u64
+}
+
+/// Accumulates a `Measurement` on the processing thread, for every thread of the process.
+#[derive(Default)]
+pub struct MeasurementTracker {
+    measurement: Measurement,
+    /// The allocations made during the measurement which are still alive, and their sizes.
+    live: HashMap< u64, u64 >,
+    current_bytes: u64
+}
+
+impl MeasurementTracker {
+    fn allocate( &mut self, pointer: u64, size: u64 ) {
+        self.live.insert( pointer, size );
+        self.current_bytes += size;
+        self.measurement.peak_bytes = self.measurement.peak_bytes.max( self.current_bytes );
+    }
+
+    fn free( &mut self, pointer: u64 ) -> Option< u64 > {
+        let size = self.live.remove( &pointer )?;
+        self.current_bytes -= size;
+        Some( size )
+    }
+
+    pub fn on_allocation( &mut self, pointer: u64, size: u64 ) {
+        self.measurement.allocations += 1;
+        self.measurement.allocated_bytes += size;
+        self.allocate( pointer, size );
+    }
+
+    pub fn on_reallocation( &mut self, old_pointer: u64, pointer: u64, size: u64 ) {
+        self.measurement.reallocations += 1;
+        match self.free( old_pointer ) {
+            Some( old_size ) if old_size > size => self.measurement.deallocated_bytes += old_size - size,
+            Some( old_size ) => self.measurement.allocated_bytes += size - old_size,
+            // We don't know how big it was, so the whole new size counts.
+            None => self.measurement.allocated_bytes += size
+        }
+
+        self.allocate( pointer, size );
+    }
+
+    pub fn on_deallocation( &mut self, pointer: u64 ) {
+        self.measurement.deallocations += 1;
+        if let Some( size ) = self.free( pointer ) {
+            self.measurement.deallocated_bytes += size;
+        }
+    }
+
+    pub fn finish( self ) -> Measurement {
+        self.measurement
+    }
+}
+
+/// The result of the last measurement, which the processing thread hands over to `memory_profiler_stop_measurement`.
+pub static RESULT: SpinLock< Option< Measurement > > = SpinLock::new( None );
+
+#[test]
+fn test_measurement_tracker() {
+    let mut tracker = MeasurementTracker::default();
+    tracker.on_allocation( 0x1000, 100 );
+    tracker.on_allocation( 0x2000, 1000 );
+    tracker.on_reallocation( 0x1000, 0x3000, 300 );
+    tracker.on_deallocation( 0x2000 );
+    tracker.on_deallocation( 0x9000 );
+    tracker.on_reallocation( 0x3000, 0x3000, 50 );
+
+    let measurement = tracker.finish();
+    assert_eq!( measurement.allocations, 2 );
+    assert_eq!( measurement.reallocations, 2 );
+    assert_eq!( measurement.deallocations, 2 );
+    assert_eq!( measurement.allocated_bytes, 1300 );
+    assert_eq!( measurement.deallocated_bytes, 1250 );
+    assert_eq!( measurement.peak_bytes, 1300 );
+}
@@ -0,0 +1,269 @@
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::PID;
+use crate::live_tracker::LiveTracker;
+use crate::timestamp::Timestamp;
+
+const TIMEOUT: Duration = Duration::from_secs( 1 );
+
+enum Target {
+    Statsd( SocketAddr ),
+    Pushgateway { address: SocketAddr, host: String, path: String }
+}
+
+fn resolve( host: &str ) -> Result< SocketAddr, String > {
+    host.to_socket_addrs()
+        .map_err( |error| format!( "failed to resolve '{}': {}", host, error ) )?
+        .next()
+        .ok_or_else( || format!( "failed to resolve '{}'", host ) )
+}
+
+fn parse_target( target: &str ) -> Result< Target, String > {
+    if let Some( host ) = target.strip_prefix( "statsd://" ) {
+        return Ok( Target::Statsd( resolve( host )? ) );
+    }
+
+    if let Some( rest ) = target.strip_prefix( "http://" ) {
+        let (host, path) = match rest.find( '/' ) {
+            Some( index ) if index + 1 < rest.len() => (&rest[ ..index ], rest[ index.. ].to_owned()),
+            Some( index ) => (&rest[ ..index ], format!( "/metrics/job/memory-profiler/instance/{}", *PID )),
+            None => (rest, format!( "/metrics/job/memory-profiler/instance/{}", *PID ))
+        };
+
+        return Ok( Target::Pushgateway { address: resolve( host )?, host: host.to_owned(), path } );
+    }
+
+    Err( format!( "unsupported target '{}'; expected either 'statsd://<host>:<port>' or 'http://<host>:<port>[/<path>]'", target ) )
+}
+
+#[derive(PartialEq, Debug)]
+struct Summary {
+    live_size: u64,
+    live_count: u64,
+    allocations_per_second: u64,
+    allocated_bytes_per_second: u64,
+    top_site: Option< (u64, u64) >
+}
+
+fn format_statsd( summary: &Summary ) -> String {
+    let mut output = String::new();
+    let _ = writeln!( output, "memory_profiler.live_bytes:{}|g", summary.live_size );
+    let _ = writeln!( output, "memory_profiler.live_allocations:{}|g", summary.live_count );
+    let _ = writeln!( output, "memory_profiler.allocations_per_second:{}|g", summary.allocations_per_second );
+    let _ = writeln!( output, "memory_profiler.allocated_bytes_per_second:{}|g", summary.allocated_bytes_per_second );
+    // A gauge can't hold the site's ID, since it doesn't fit into a double.
+    if let Some( (_, size) ) = summary.top_site {
+        let _ = writeln!( output, "memory_profiler.top_site.live_bytes:{}|g", size );
+    }
+
+    output
+}
+
+fn format_prometheus( summary: &Summary ) -> String {
+    let mut output = String::new();
+    let mut gauge = |name: &str, help: &str, labels: &str, value: u64| {
+        let _ = writeln!( output, "# HELP {} {}", name, help );
+        let _ = writeln!( output, "# TYPE {} gauge", name );
+        let _ = writeln!( output, "{}{} {}", name, labels, value );
+    };
+
+    gauge( "memory_profiler_live_bytes", "The total size of the live allocations.", "", summary.live_size );
+    gauge( "memory_profiler_live_allocations", "The number of live allocations.", "", summary.live_count );
+    gauge( "memory_profiler_allocations_per_second", "The number of allocations made per second since the last push.", "", summary.allocations_per_second );
+    gauge( "memory_profiler_allocated_bytes_per_second", "The number of bytes allocated per second since the last push.", "", summary.allocated_bytes_per_second );
+    if let Some( (site, size) ) = summary.top_site {
+        let labels = format!( "{{site=\"{:016x}\"}}", site );
+        gauge( "memory_profiler_top_site_live_bytes", "The total size of the live allocations from the site which holds the most memory.", &labels, size );
+    }
+
+    output
+}
+
+fn push( target: &Target, summary: &Summary ) -> io::Result< () > {
+    match *target {
+        Target::Statsd( address ) => {
+            let socket = UdpSocket::bind( if address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" } )?;
+            socket.send_to( format_statsd( summary ).as_bytes(), address )?;
+        },
+        Target::Pushgateway { address, ref host, ref path } => {
+            let body = format_prometheus( summary );
+            let mut stream = TcpStream::connect_timeout( &address, TIMEOUT )?;
+            stream.set_read_timeout( Some( TIMEOUT ) )?;
+            stream.set_write_timeout( Some( TIMEOUT ) )?;
+            write!(
+                stream,
+                "PUT {} HTTP/1.0\r\nHost: {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                path,
+                host,
+                body.len(),
+                body
+            )?;
+
+            let mut response = [0; 16];
+            let length = stream.read( &mut response )?;
+            let response = String::from_utf8_lossy( &response[ ..length ] );
+            if !response.starts_with( "HTTP/1.1 2" ) && !response.starts_with( "HTTP/1.0 2" ) {
+                return Err( io::Error::new( io::ErrorKind::Other, format!( "unexpected response: {:?}", response ) ) );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn push_thread_main( target: String, summaries: mpsc::Receiver< Summary > ) {
+    let target = match parse_target( &target ) {
+        Ok( target ) => target,
+        Err( error ) => {
+            warn!( "Metrics won't be pushed: {}", error );
+            return;
+        }
+    };
+
+    for summary in summaries {
+        if let Err( error ) = push( &target, &summary ) {
+            warn!( "Failed to push the metrics: {}", error );
+        }
+    }
+}
+
+/// Periodically pushes a few summary gauges of the live allocations
+/// to a statsd server or a Prometheus pushgateway.
+///
+/// The summaries are computed on the processing thread from its `LiveTracker`, but they're sent
+/// from a separate thread, so that a slow or an unreachable target doesn't hold up the processing.
+pub struct MetricsPusher {
+    interval: Timestamp,
+    last_push: Timestamp,
+    /// The number of allocations and of bytes allocated since the start at the time of the last push.
+    last_allocated_count: u64,
+    last_allocated_size: u64,
+    sender: Option< SyncSender< Summary > >,
+    thread: Option< JoinHandle< () > >
+}
+
+impl MetricsPusher {
+    pub fn new( target: &str, interval: u64, now: Timestamp ) -> Option< Self > {
+        // Only a single summary can be queued; if the target can't keep up the newer ones are dropped.
+        let (sender, receiver) = mpsc::sync_channel( 1 );
+        let target = target.to_owned();
+        let thread = match crate::global::spawn_internal_thread( "mem-prof-metrics", move || push_thread_main( target, receiver ) ) {
+            Ok( thread ) => thread,
+            Err( error ) => {
+                warn!( "Metrics won't be pushed: failed to start a thread: {}", error );
+                return None;
+            }
+        };
+
+        Some( MetricsPusher {
+            interval: Timestamp::from_secs( interval.max( 1 ) ),
+            last_push: now,
+            last_allocated_count: 0,
+            last_allocated_size: 0,
+            sender: Some( sender ),
+            thread: Some( thread )
+        })
+    }
+
+    fn summary( &self, tracker: &LiveTracker, elapsed: Timestamp ) -> Summary {
+        let elapsed = elapsed.as_msecs().max( 1 );
+        Summary {
+            live_size: tracker.live_size(),
+            live_count: tracker.live_count(),
+            allocations_per_second: (tracker.allocated_count() - self.last_allocated_count) * 1000 / elapsed,
+            allocated_bytes_per_second: (tracker.allocated_size() - self.last_allocated_size) * 1000 / elapsed,
+            top_site: tracker.top_sites( 1 ).into_iter().map( |site| (site.id, site.live_size) ).next()
+        }
+    }
+
+    fn next_summary( &mut self, tracker: &LiveTracker, now: Timestamp ) -> Summary {
+        let summary = self.summary( tracker, now - self.last_push );
+        self.last_push = now;
+        self.last_allocated_count = tracker.allocated_count();
+        self.last_allocated_size = tracker.allocated_size();
+        summary
+    }
+
+    pub fn push_if_necessary( &mut self, tracker: &LiveTracker, now: Timestamp ) {
+        if now - self.last_push < self.interval {
+            return;
+        }
+
+        let summary = self.next_summary( tracker, now );
+        if let Some( ref sender ) = self.sender {
+            match sender.try_send( summary ) {
+                Ok(()) | Err( TrySendError::Disconnected( _ ) ) => {},
+                Err( TrySendError::Full( _ ) ) => debug!( "The previous metrics are still being pushed; skipping this push" )
+            }
+        }
+    }
+
+    /// Pushes the final summary and waits until everything was sent.
+    pub fn finish( mut self, tracker: &LiveTracker, now: Timestamp ) {
+        let summary = self.next_summary( tracker, now );
+        if let Some( sender ) = self.sender.take() {
+            let _ = sender.send( summary );
+        }
+
+        if let Some( thread ) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[test]
+fn test_formatting() {
+    let summary = Summary {
+        live_size: 1024,
+        live_count: 3,
+        allocations_per_second: 10,
+        allocated_bytes_per_second: 2048,
+        top_site: Some( (0x1234, 1000) )
+    };
+
+    assert_eq!(
+        format_statsd( &summary ),
+        "memory_profiler.live_bytes:1024|g\n\
+         memory_profiler.live_allocations:3|g\n\
+         memory_profiler.allocations_per_second:10|g\n\
+         memory_profiler.allocated_bytes_per_second:2048|g\n\
+         memory_profiler.top_site.live_bytes:1000|g\n"
+    );
+
+    let prometheus = format_prometheus( &summary );
+    assert!( prometheus.contains( "\nmemory_profiler_live_bytes 1024\n" ) );
+    assert!( prometheus.contains( "\nmemory_profiler_top_site_live_bytes{site=\"0000000000001234\"} 1000\n" ) );
+}
+
+#[test]
+fn test_summary() {
+    let pusher = MetricsPusher {
+        interval: Timestamp::from_secs( 1 ),
+        last_push: Timestamp::from_secs( 0 ),
+        last_allocated_count: 0,
+        last_allocated_size: 0,
+        sender: None,
+        thread: None
+    };
+
+    let mut tracker = LiveTracker::new( Timestamp::from_secs( 0 ) );
+    tracker.on_allocation( 0x1000, 100, &[ 1 ] );
+    tracker.on_allocation( 0x2000, 50, &[ 2 ] );
+    tracker.on_allocation( 0x3000, 60, &[ 2 ] );
+    tracker.on_deallocation( 0x1000 );
+    tracker.on_deallocation( 0x4000 );
+
+    let site = tracker.top_sites( 1 )[ 0 ].id;
+    assert_eq!( pusher.summary( &tracker, Timestamp::from_secs( 2 ) ), Summary {
+        live_size: 110,
+        live_count: 2,
+        allocations_per_second: 1,
+        allocated_bytes_per_second: 105,
+        top_site: Some( (site, 110) )
+    });
+}
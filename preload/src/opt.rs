@@ -2,6 +2,8 @@ use std::borrow::Cow;
 use std::env;
 use std::ffi::OsStr;
 
+use crate::event::MAX_SAMPLED_CONTENTS;
+
 pub struct Opts {
     is_initialized: bool,
 
@@ -13,6 +15,7 @@ pub struct Opts {
     pub enable_shadow_stack: bool,
     pub grab_backtraces_on_free: bool,
     pub include_file: Option< String >,
+    pub encryption_recipient: Option< String >,
     pub output_path_pattern: Cow< 'static, str >,
     pub precise_timestamps: bool,
     pub register_sigusr1: bool,
@@ -24,7 +27,17 @@ pub struct Opts {
     pub backtrace_cache_size: usize,
     pub cull_temporary_allocations: bool,
     pub temporary_allocation_lifetime_threshold: u64,
-    pub temporary_allocation_pending_threshold: usize
+    pub temporary_allocation_pending_threshold: usize,
+    pub allocator_stats_interval: u64,
+    pub sample_contents: usize,
+    pub sample_contents_min_size: usize,
+    pub sample_contents_max_size: usize,
+    pub metrics_push_target: Option< String >,
+    pub metrics_push_interval: u64,
+    pub stream_to: Option< String >,
+    pub stream_token: Option< String >,
+    pub control_socket: Option< String >,
+    pub skip_backtrace_for: Vec< String >
 }
 
 static mut OPTS: Opts = Opts {
@@ -38,6 +51,7 @@ static mut OPTS: Opts = Opts {
     enable_shadow_stack: true,
     grab_backtraces_on_free: false,
     include_file: None,
+    encryption_recipient: None,
     output_path_pattern: Cow::Borrowed( "memory-profiling_%e_%t_%p.dat" ),
     precise_timestamps: false,
     register_sigusr1: true,
@@ -50,6 +64,16 @@ static mut OPTS: Opts = Opts {
     cull_temporary_allocations: false,
     temporary_allocation_lifetime_threshold: 10000,
     temporary_allocation_pending_threshold: 320 * 1024,
+    allocator_stats_interval: 0,
+    sample_contents: 0,
+    sample_contents_min_size: 0,
+    sample_contents_max_size: usize::MAX,
+    metrics_push_target: None,
+    metrics_push_interval: 10,
+    stream_to: None,
+    stream_token: None,
+    control_socket: None,
+    skip_backtrace_for: Vec::new()
 };
 
 trait ParseVar: Sized {
@@ -92,6 +116,13 @@ impl ParseVar for String {
     }
 }
 
+impl ParseVar for Vec< String > {
+    fn parse_var( value: &OsStr ) -> Option< Self > {
+        let value = value.to_str()?;
+        Some( value.split( ',' ).map( |item| item.trim() ).filter( |item| !item.is_empty() ).map( |item| item.into() ).collect() )
+    }
+}
+
 impl< 'a > ParseVar for Cow< 'a, str > {
     fn parse_var( value: &OsStr ) -> Option< Self > {
         let value = String::parse_var( value )?;
@@ -135,6 +166,7 @@ pub unsafe fn initialize() {
         "MEMORY_PROFILER_ENABLE_SERVER"             => &mut opts.enable_server,
         "MEMORY_PROFILER_GRAB_BACKTRACES_ON_FREE"   => &mut opts.grab_backtraces_on_free,
         "MEMORY_PROFILER_INCLUDE_FILE"              => &mut opts.include_file,
+        "MEMORY_PROFILER_ENCRYPTION_RECIPIENT"      => &mut opts.encryption_recipient,
         "MEMORY_PROFILER_OUTPUT"                    => &mut opts.output_path_pattern,
         "MEMORY_PROFILER_PRECISE_TIMESTAMPS"        => &mut opts.precise_timestamps,
         "MEMORY_PROFILER_REGISTER_SIGUSR1"          => &mut opts.register_sigusr1,
@@ -150,7 +182,26 @@ pub unsafe fn initialize() {
         "MEMORY_PROFILER_TEMPORARY_ALLOCATION_LIFETIME_THRESHOLD"
             => &mut opts.temporary_allocation_lifetime_threshold,
         "MEMORY_PROFILER_TEMPORARY_ALLOCATION_PENDING_THRESHOLD"
-            => &mut opts.temporary_allocation_pending_threshold
+            => &mut opts.temporary_allocation_pending_threshold,
+        "MEMORY_PROFILER_ALLOCATOR_STATS_INTERVAL"
+            => &mut opts.allocator_stats_interval,
+        "MEMORY_PROFILER_SAMPLE_CONTENTS"           => &mut opts.sample_contents,
+        "MEMORY_PROFILER_SAMPLE_CONTENTS_MIN_SIZE"  => &mut opts.sample_contents_min_size,
+        "MEMORY_PROFILER_SAMPLE_CONTENTS_MAX_SIZE"  => &mut opts.sample_contents_max_size,
+        "MEMORY_PROFILER_METRICS_PUSH_TARGET"       => &mut opts.metrics_push_target,
+        "MEMORY_PROFILER_METRICS_PUSH_INTERVAL"     => &mut opts.metrics_push_interval,
+        "MEMORY_PROFILER_STREAM_TO"                 => &mut opts.stream_to,
+        "MEMORY_PROFILER_CONTROL_SOCKET"            => &mut opts.control_socket,
+        "MEMORY_PROFILER_SKIP_BACKTRACE_FOR"        => &mut opts.skip_backtrace_for
+    }
+
+    // This one is a secret, so it's not logged.
+    opts.stream_token = env::var( "MEMORY_PROFILER_STREAM_TOKEN" ).ok();
+    info!( "    {:40} = {}", "MEMORY_PROFILER_STREAM_TOKEN", if opts.stream_token.is_some() { "<set>" } else { "<unset>" } );
+
+    if opts.sample_contents > MAX_SAMPLED_CONTENTS {
+        warn!( "At most {} bytes of every allocation can be sampled", MAX_SAMPLED_CONTENTS );
+        opts.sample_contents = MAX_SAMPLED_CONTENTS;
     }
 
     opts.is_initialized = true;
@@ -2,7 +2,7 @@ use std::hash::Hash;
 use std::mem;
 use std::fs::{self, File, remove_file};
 use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
-use std::net::{TcpListener, TcpStream, UdpSocket, IpAddr, SocketAddr};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs, UdpSocket, IpAddr, SocketAddr};
 use std::time::Duration;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
@@ -20,19 +20,24 @@ use std::collections::HashMap;
 
 use common::speedy::{Writable, Readable};
 
+use common::chunked_stream::ChunkedWriter;
 use common::event::{DataId, Event, AllocBody};
-use common::lz4_stream::Lz4Writer;
 use common::request::{
     PROTOCOL_VERSION,
+    HANDSHAKE_PROTOCOL_VERSION,
+    CAPABILITY_STREAMING,
+    CAPABILITY_MEMORY_DUMP,
+    CAPABILITY_SUMMARY,
     Request,
     Response,
-    BroadcastHeader
+    BroadcastHeader,
+    Handshake
 };
 use common::get_local_ips;
 
 use crate::{CMDLINE, EXECUTABLE, PID};
 use crate::arch;
-use crate::event::{InternalEvent, send_event, timed_recv_all_events};
+use crate::event::{ContentsSample, InternalAllocationId, InternalEvent, send_event, timed_recv_all_events};
 use crate::global::AllocationLock;
 use crate::opt;
 use crate::timestamp::{Timestamp, get_timestamp, get_wall_clock};
@@ -44,6 +49,14 @@ use crate::utils::{
 use crate::writer_memory;
 use crate::writers;
 use crate::ordered_map::OrderedMap;
+use crate::metrics_push::MetricsPusher;
+use crate::live_tracker::LiveTracker;
+use crate::measurement::{self, MeasurementTracker};
+#[cfg(feature = "encryption")]
+use crate::encryption::EncryptedFile;
+
+/// For how long, in seconds, the live allocations are still tracked after the last summary was asked for.
+const SUMMARY_IDLE_TIMEOUT: u64 = 300;
 
 fn get_hash< T: Hash >( value: T ) -> u64 {
     use std::collections::hash_map::DefaultHasher;
@@ -73,8 +86,32 @@ fn generate_data_id() -> DataId {
     DataId::new( a, b )
 }
 
+enum OutputFile {
+    Plain( File ),
+    #[cfg(feature = "encryption")]
+    Encrypted( EncryptedFile )
+}
+
+impl io::Write for OutputFile {
+    fn write( &mut self, data: &[u8] ) -> io::Result< usize > {
+        match *self {
+            OutputFile::Plain( ref mut fp ) => fp.write( data ),
+            #[cfg(feature = "encryption")]
+            OutputFile::Encrypted( ref mut fp ) => fp.write( data )
+        }
+    }
+
+    fn flush( &mut self ) -> io::Result< () > {
+        match *self {
+            OutputFile::Plain( ref mut fp ) => fp.flush(),
+            #[cfg(feature = "encryption")]
+            OutputFile::Encrypted( ref mut fp ) => fp.flush()
+        }
+    }
+}
+
 struct Output {
-    file: Option< (PathBuf, File) >,
+    file: Option< (PathBuf, OutputFile) >,
     clients: Vec< Client >
 }
 
@@ -86,7 +123,7 @@ impl Output {
         }
     }
 
-    fn set_file( &mut self, fp: File, path: PathBuf ) {
+    fn set_file( &mut self, fp: OutputFile, path: PathBuf ) {
         self.file = Some( (path, fp) );
     }
 
@@ -95,7 +132,15 @@ impl Output {
     }
 }
 
-fn poll_clients( id: DataId, initial_timestamp: Timestamp, poll_fds: &mut Vec< libc::pollfd >, output: &mut Lz4Writer< Output > ) {
+fn poll_clients(
+    id: DataId,
+    initial_timestamp: Timestamp,
+    now: Timestamp,
+    live_tracker: &mut Option< LiveTracker >,
+    last_summary_request: &mut Timestamp,
+    poll_fds: &mut Vec< libc::pollfd >,
+    output: &mut ChunkedWriter< Output >
+) {
     poll_fds.clear();
 
     for client in output.inner().clients.iter() {
@@ -162,6 +207,32 @@ fn poll_clients( id: DataId, initial_timestamp: Timestamp, poll_fds: &mut Vec< l
                     info!( "Failed to respond to a client ping: {}", error );
                     client.running = false;
                 }
+            },
+            Request::GetSummary => {
+                trace!( "Received a GetSummary request" );
+                *last_summary_request = now;
+                if live_tracker.is_none() {
+                    info!( "Starting to track the live allocations for the summaries..." );
+                }
+
+                let summary = live_tracker.get_or_insert_with( || LiveTracker::new( now ) ).summary_json( now, initial_timestamp );
+
+                if let Err( error ) = Response::Summary( summary.into() ).write_to_stream( &mut client.stream ) {
+                    info!( "Failed to send the summary to a client: {}", error );
+                    client.running = false;
+                }
+            },
+            Request::Hello( handshake ) => {
+                debug!( "Received a handshake from a client with protocol version {} and capabilities 0x{:x}", handshake.protocol_version, handshake.capabilities );
+                let response = Response::Hello( Handshake {
+                    protocol_version: HANDSHAKE_PROTOCOL_VERSION,
+                    capabilities: CAPABILITY_STREAMING | CAPABILITY_MEMORY_DUMP | CAPABILITY_SUMMARY
+                });
+
+                if let Err( error ) = response.write_to_stream( &mut client.stream ) {
+                    info!( "Failed to respond to a client handshake: {}", error );
+                    client.running = false;
+                }
             }
         }
     }
@@ -239,7 +310,7 @@ impl Client {
     fn stream_initial_data( &mut self, id: DataId, initial_timestamp: Timestamp, path: &Path, file: &mut File ) -> io::Result< () > {
         if !opt::get().write_binaries_to_output {
             info!( "Streaming the binaries which were suppressed in the original output file..." );
-            let mut serializer = Lz4Writer::new( &mut *self );
+            let mut serializer = ChunkedWriter::new_stream( &mut *self )?;
             writers::write_header( id, initial_timestamp, &mut serializer )?;
             writers::write_binaries( &mut serializer )?;
             serializer.flush()?;
@@ -259,25 +330,38 @@ impl Client {
         Ok(())
     }
 
-    fn start_streaming( &mut self, id: DataId, initial_timestamp: Timestamp, output: &mut Option< (PathBuf, File) > ) -> io::Result< () > {
+    fn start_streaming( &mut self, id: DataId, initial_timestamp: Timestamp, output: &mut Option< (PathBuf, OutputFile) > ) -> io::Result< () > {
         // First client which connects to us gets streamed all of the data
         // which we've gathered up until this point.
 
-        if let Some( (path, mut fp) ) = output.take() {
-            match self.stream_initial_data( id, initial_timestamp, &path, &mut fp ) {
-                Ok(()) => return Ok(()),
-                Err( error ) => {
-                    fp.seek( SeekFrom::End( 0 ) )?;
-                    *output = Some( (path, fp) );
-                    return Err( error );
+        match output.take() {
+            Some( (path, OutputFile::Plain( mut fp )) ) => {
+                match self.stream_initial_data( id, initial_timestamp, &path, &mut fp ) {
+                    Ok(()) => return Ok(()),
+                    Err( error ) => {
+                        fp.seek( SeekFrom::End( 0 ) )?;
+                        *output = Some( (path, OutputFile::Plain( fp )) );
+                        return Err( error );
+                    }
                 }
-            }
+            },
+            #[cfg(feature = "encryption")]
+            Some( file ) => {
+                // The encrypted output is never streamed in plain text.
+                *output = Some( file );
+            },
+            None => {}
         }
 
+        self.stream_fresh_initial_data( id, initial_timestamp )
+    }
+
+    fn stream_fresh_initial_data( &mut self, id: DataId, initial_timestamp: Timestamp ) -> io::Result< () > {
         {
-            let mut serializer = Lz4Writer::new( &mut *self );
+            let mut serializer = ChunkedWriter::new_stream( &mut *self )?;
             writers::write_header( id, initial_timestamp, &mut serializer )?;
             writers::write_maps( &mut serializer )?;
+            writers::write_build_ids( &mut serializer )?;
             writers::write_binaries( &mut serializer )?;
             serializer.flush()?;
         }
@@ -295,6 +379,42 @@ impl Drop for Client {
     }
 }
 
+/// Connects to a `memory-profiler-cli server --listen` and starts streaming everything to it.
+///
+/// Unlike with the clients which connect to us this doesn't take over the output file,
+/// since we connect before any events are written and don't have to catch up.
+fn connect_to_stream_target( target: &str, id: DataId, initial_timestamp: Timestamp, listener_port: u16 ) -> Option< Client > {
+    let address = match target.to_socket_addrs().ok().and_then( |mut addresses| addresses.next() ) {
+        Some( address ) => address,
+        None => {
+            error!( "Failed to resolve '{}'", target );
+            return None;
+        }
+    };
+
+    let result = TcpStream::connect_timeout( &address, Duration::from_secs( 5 ) ).and_then( |mut stream| {
+        if let Some( ref token ) = opt::get().stream_token {
+            Response::Authenticate( token.as_str().into() ).write_to_stream( &mut stream )?;
+        }
+
+        let mut client = Client::new( id, initial_timestamp, listener_port, stream )?;
+        client.stream_fresh_initial_data( id, initial_timestamp )?;
+        client.streaming = true;
+        Ok( client )
+    });
+
+    match result {
+        Ok( client ) => {
+            info!( "Streaming to {}", target );
+            Some( client )
+        },
+        Err( error ) => {
+            error!( "Failed to start streaming to {}: {}", target, error );
+            None
+        }
+    }
+}
+
 fn broadcast_header( id: DataId, initial_timestamp: Timestamp, listener_port: u16 ) -> BroadcastHeader {
     let (timestamp, wall_clock_secs, wall_clock_nsecs) = get_wall_clock();
 
@@ -368,7 +488,24 @@ fn send_broadcast( id: DataId, initial_timestamp: Timestamp, listener_port: u16
     output
 }
 
-fn initialize_output_file() -> Option< (File, PathBuf) > {
+#[cfg(feature = "encryption")]
+fn encrypt_output( fp: File, recipient: &str ) -> Option< OutputFile > {
+    match EncryptedFile::new( fp, recipient ) {
+        Ok( fp ) => Some( OutputFile::Encrypted( fp ) ),
+        Err( error ) => {
+            error!( "Couldn't initialize the encryption of the output: {}", error );
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+fn encrypt_output( _: File, _: &str ) -> Option< OutputFile > {
+    error!( "The output was supposed to be encrypted, but the profiler was compiled without the `encryption` feature" );
+    None
+}
+
+fn initialize_output_file() -> Option< (OutputFile, PathBuf) > {
     static COUNTER: AtomicUsize = AtomicUsize::new( 0 );
 
     let output_path = generate_filename( &opt::get().output_path_pattern, Some( &COUNTER ) );
@@ -410,6 +547,14 @@ fn initialize_output_file() -> Option< (File, PathBuf) > {
         }
     }
 
+    let fp = match opt::get().encryption_recipient {
+        Some( ref recipient ) => {
+            info!( "File '{}' will be encrypted", output_path );
+            encrypt_output( fp, recipient )?
+        },
+        None => OutputFile::Plain( fp )
+    };
+
     Some( (fp, output_path.into()) )
 }
 
@@ -473,9 +618,10 @@ impl BacktraceCache {
         }
     }
 
-    pub fn resolve( &mut self, tid: u32, backtrace: crate::unwind::Backtrace ) -> (u64, Option< &[usize] >) {
+    /// Returns the ID of the backtrace, whether it wasn't seen before, and its frames.
+    pub fn resolve( &mut self, tid: u32, backtrace: crate::unwind::Backtrace ) -> (u64, bool, &[usize]) {
         if backtrace.is_empty() {
-            return (0, None);
+            return (0, false, &[]);
         }
 
         let thread_state = self.thread_state.entry( tid ).or_insert_with( BacktraceCacheThreadState::default );
@@ -488,6 +634,16 @@ impl BacktraceCache {
 
         let mut key: usize = 0;
         match backtrace.stale_count {
+            // A truncated backtrace says nothing about the rest of the thread's stack,
+            // so it can't be what the next partial backtrace is based on.
+            None if backtrace.is_truncated => {
+                self.buffer.clear();
+                for &frame in &backtrace.frames {
+                    key = key.wrapping_mul( PRIME );
+                    key ^= frame;
+                    self.buffer.push( frame );
+                }
+            },
             None => {
                 thread_state.current_backtrace.clear();
                 thread_state.current_backtrace.reserve( backtrace.frames.len() );
@@ -517,6 +673,12 @@ impl BacktraceCache {
             }
         }
 
+        let current_backtrace = if backtrace.is_truncated {
+            &self.buffer
+        } else {
+            &thread_state.current_backtrace
+        };
+
         let id = match self.cache.get_mut( &key ) {
             None => {
                 if cfg!( debug_assertions ) {
@@ -529,14 +691,14 @@ impl BacktraceCache {
                 self.next_id += 1;
                 self.cache.put( key, BacktraceCacheEntry {
                     id,
-                    backtrace: thread_state.current_backtrace.clone()
+                    backtrace: current_backtrace.clone()
                 });
 
                 id
             },
             Some( entry ) => {
-                if entry.backtrace == thread_state.current_backtrace {
-                    return (entry.id, None);
+                if entry.backtrace == *current_backtrace {
+                    return (entry.id, false, current_backtrace);
                 } else {
                     if cfg!( debug_assertions ) {
                         info!( "Backtrace cache conflict detected!" );
@@ -547,14 +709,14 @@ impl BacktraceCache {
 
                     entry.id = id;
                     entry.backtrace.clear();
-                    entry.backtrace.extend_from_slice( &thread_state.current_backtrace );
+                    entry.backtrace.extend_from_slice( current_backtrace );
 
                     id
                 }
             }
         };
 
-        (id, Some( &thread_state.current_backtrace ))
+        (id, true, current_backtrace)
     }
 }
 
@@ -582,7 +744,7 @@ impl AllocationBucket {
         now.as_usecs() >= self.events[0].timestamp.as_usecs() + opt::get().temporary_allocation_lifetime_threshold * 1000
     }
 
-    fn emit( &mut self, fp: &mut impl Write ) -> Result< (), std::io::Error > {
+    fn emit( &mut self, fp: &mut ChunkedWriter< impl Write > ) -> Result< (), std::io::Error > {
         if self.events.len() == 0 {
             return Ok(());
         }
@@ -591,20 +753,20 @@ impl AllocationBucket {
 
         let BufferedAllocation { timestamp, allocation } = iter.next().unwrap();
         let mut old_pointer = allocation.pointer;
-        Event::AllocEx {
+        fp.write_event( &Event::AllocEx {
             id: self.id,
             timestamp,
             allocation
-        }.write_to_stream( &mut *fp )?;
+        })?;
 
         while let Some( BufferedAllocation { timestamp, allocation } ) = iter.next() {
             let new_pointer = allocation.pointer;
-            Event::ReallocEx {
+            fp.write_event( &Event::ReallocEx {
                 id: self.id,
                 timestamp,
                 old_pointer,
                 allocation
-            }.write_to_stream( &mut *fp )?;
+            })?;
             old_pointer = new_pointer;
         }
 
@@ -619,13 +781,15 @@ pub(crate) fn thread_main() {
     let initial_timestamp = get_timestamp();
     info!( "Data ID: {}", uuid );
 
-    let mut output_writer = Lz4Writer::new( Output::new() );
+    let mut output_writer = ChunkedWriter::continue_stream( Output::new() );
     if let Some( (fp, path) ) = initialize_output_file() {
-        let mut fp = Lz4Writer::new( fp );
-        match writers::write_initial_data( uuid, initial_timestamp, &mut fp ) {
-            Ok(()) => {
-                let fp = fp.into_inner().unwrap();
+        let result = ChunkedWriter::new_stream( fp ).and_then( |mut fp| {
+            writers::write_initial_data( uuid, initial_timestamp, &mut fp )?;
+            fp.into_inner()
+        });
 
+        match result {
+            Ok( fp ) => {
                 let mut output = Output::new();
                 output.set_file( fp, path );
                 output_writer.replace_inner( output ).unwrap();
@@ -639,12 +803,25 @@ pub(crate) fn thread_main() {
     let mut listener = None;
 
     if opt::get().enable_server {
-        if let Some( listener_instance ) = create_listener() {
+        if opt::get().encryption_recipient.is_some() {
+            warn!( "The embedded server is disabled since the output is encrypted" );
+        } else if let Some( listener_instance ) = create_listener() {
             let listener_port = listener_instance.local_addr().expect( "couldn't grab the local address of the listener" ).port();
             listener = Some( (listener_instance, listener_port) );
         }
     }
 
+    if let Some( ref target ) = opt::get().stream_to {
+        if opt::get().encryption_recipient.is_some() {
+            warn!( "Streaming to {} is disabled since the output is encrypted", target );
+        } else {
+            let listener_port = listener.as_ref().map( |&(_, listener_port)| listener_port ).unwrap_or( 0 );
+            if let Some( client ) = connect_to_stream_target( target, uuid, initial_timestamp, listener_port ) {
+                output_writer.inner_mut_without_flush().clients.push( client );
+            }
+        }
+    }
+
     let mut events = Vec::new();
     let mut last_flush_timestamp = get_timestamp();
     let mut coarse_timestamp = get_timestamp();
@@ -661,6 +838,25 @@ pub(crate) fn thread_main() {
     let mut stats_by_backtrace: HashMap< u64, GroupStatistics > = HashMap::new();
     let mut stats_by_backtrace_updated = false;
     let mut last_stats_by_backtrace_flush = get_timestamp();
+    let mut last_allocator_stats = get_timestamp();
+    let mut metrics_pusher = opt::get().metrics_push_target.as_ref().and_then( |target| {
+        MetricsPusher::new( target, opt::get().metrics_push_interval, coarse_timestamp )
+    });
+
+    // The contents of the allocations are sampled when they're freed, so the ones which are never freed
+    // are kept track of here and sampled at the end.
+    let mut unfreed_allocations: HashMap< usize, (InternalAllocationId, usize) > = HashMap::new();
+    // Unless the metrics are pushed the live allocations are only tracked once somebody asks for a summary,
+    // and only for as long as there's a client connected or the summaries keep being asked for.
+    let mut live_tracker = if metrics_pusher.is_some() {
+        Some( LiveTracker::new( coarse_timestamp ) )
+    } else {
+        None
+    };
+    let mut last_summary_request = coarse_timestamp;
+
+    let mut measurement_tracker: Option< MeasurementTracker > = None;
+
     loop {
         timed_recv_all_events( &mut events, Duration::from_millis( 250 ) );
 
@@ -691,7 +887,16 @@ pub(crate) fn thread_main() {
                     Err( _ ) => {}
                 }
 
-                poll_clients( uuid, initial_timestamp, &mut poll_fds, &mut output_writer );
+                poll_clients( uuid, initial_timestamp, coarse_timestamp, &mut live_tracker, &mut last_summary_request, &mut poll_fds, &mut output_writer );
+
+                let is_idle =
+                    output_writer.inner().clients.is_empty() &&
+                    (coarse_timestamp - last_summary_request).as_secs() >= SUMMARY_IDLE_TIMEOUT;
+
+                if metrics_pusher.is_none() && live_tracker.is_some() && is_idle {
+                    info!( "Nobody asked for a summary in a while; no longer tracking the live allocations" );
+                    live_tracker = None;
+                }
             }
         }
 
@@ -733,12 +938,31 @@ pub(crate) fn thread_main() {
                     min_size: stats.min_size,
                     max_size: stats.max_size
                 };
-                let _ = event.write_to_stream( &mut output_writer );
+                let _ = output_writer.write_event( &event );
             }
 
             last_stats_by_backtrace_flush = coarse_timestamp;
         }
 
+        let allocator_stats_interval = opt::get().allocator_stats_interval;
+        if running && allocator_stats_interval != 0 && (coarse_timestamp - last_allocator_stats).as_secs() >= allocator_stats_interval {
+            last_allocator_stats = coarse_timestamp;
+            if !output_writer.inner().is_none() {
+                if let Err( error ) = writers::write_allocator_stats( &mut output_writer ) {
+                    warn!( "Failed to write allocator statistics: {}", error );
+                }
+            }
+        }
+
+        if let Some( ref mut live_tracker ) = live_tracker {
+            live_tracker.update_rate( coarse_timestamp );
+            if running {
+                if let Some( ref mut metrics_pusher ) = metrics_pusher {
+                    metrics_pusher.push_if_necessary( live_tracker, coarse_timestamp );
+                }
+            }
+        }
+
         if events.is_empty() && !running {
             break;
         }
@@ -768,6 +992,10 @@ pub(crate) fn thread_main() {
                 } => {
                     debug_assert!( id.is_valid() );
 
+                    if let Some( ref mut measurement_tracker ) = measurement_tracker {
+                        measurement_tracker.on_allocation( address.get() as u64, size as u64 );
+                    }
+
                     if skip {
                         continue;
                     }
@@ -780,7 +1008,7 @@ pub(crate) fn thread_main() {
                     let tid = thread.tid();
                     mem::drop( thread );
 
-                    if let Ok( backtrace ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
+                    if let Ok( (backtrace, frames) ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
                         let allocation = AllocBody {
                             pointer: address.get() as u64,
                             size: size as u64,
@@ -791,6 +1019,14 @@ pub(crate) fn thread_main() {
                             preceding_free_space: preceding_free_space as u64
                         };
 
+                        if let Some( ref mut live_tracker ) = live_tracker {
+                            live_tracker.on_allocation( allocation.pointer, allocation.size, frames );
+                        }
+
+                        if let Some( length ) = ContentsSample::length_for( usable_size ) {
+                            unfreed_allocations.insert( address.get(), (id, length) );
+                        }
+
                         if running && opt::get().cull_temporary_allocations && !id.is_untracked() {
                             let mut bucket = AllocationBucket {
                                 id: id.into(),
@@ -802,11 +1038,11 @@ pub(crate) fn thread_main() {
                                 error!( "Duplicate allocation 0x{:08X} with ID {}; this should never happen", address.get(), id );
                             }
                         } else {
-                            let _ = Event::AllocEx {
+                            let _ = serializer.write_event( &Event::AllocEx {
                                 id: id.into(),
                                 timestamp,
                                 allocation
-                            }.write_to_stream( &mut *serializer );
+                            });
                         }
                     }
                 },
@@ -828,6 +1064,10 @@ pub(crate) fn thread_main() {
                         error!( "Allocation 0x{:08X} with invalid ID {} was reallocated; this should never happen; you probably have an out-of-bounds write somewhere", old_address.get(), id );
                     }
 
+                    if let Some( ref mut measurement_tracker ) = measurement_tracker {
+                        measurement_tracker.on_reallocation( old_address.get() as u64, new_address.get() as u64, new_size as u64 );
+                    }
+
                     if skip {
                         continue;
                     }
@@ -840,7 +1080,7 @@ pub(crate) fn thread_main() {
                     let tid = thread.tid();
                     mem::drop( thread );
 
-                    if let Ok( backtrace ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
+                    if let Ok( (backtrace, frames) ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
                         let allocation = AllocBody {
                             pointer: new_address.get() as u64,
                             size: new_size as u64,
@@ -851,6 +1091,16 @@ pub(crate) fn thread_main() {
                             preceding_free_space: new_preceding_free_space as u64
                         };
 
+                        if let Some( ref mut live_tracker ) = live_tracker {
+                            live_tracker.on_deallocation( old_address.get() as u64 );
+                            live_tracker.on_allocation( allocation.pointer, allocation.size, frames );
+                        }
+
+                        unfreed_allocations.remove( &old_address.get() );
+                        if let Some( length ) = ContentsSample::length_for( new_usable_size ) {
+                            unfreed_allocations.insert( new_address.get(), (id, length) );
+                        }
+
                         let mut allocation = Some( allocation );
                         if running && opt::get().cull_temporary_allocations && !id.is_untracked() && id.is_valid() {
                             if let Some( bucket ) = allocations.get_mut( &(id.thread, id.allocation) ) {
@@ -885,13 +1135,14 @@ pub(crate) fn thread_main() {
                                 old_pointer: old_address.get() as u64,
                                 allocation
                             };
-                            let _ = event.write_to_stream( &mut *serializer );
+                            let _ = serializer.write_event( &event );
                         }
                     }
                 },
                 InternalEvent::Free {
                     id,
                     address,
+                    contents,
                     backtrace,
                     mut timestamp,
                     thread
@@ -902,6 +1153,10 @@ pub(crate) fn thread_main() {
                         error!( "Allocation 0x{:08X} with invalid ID {} was freed; this should never happen; you probably have an out-of-bounds write somewhere", address.get(), id );
                     }
 
+                    if let Some( ref mut measurement_tracker ) = measurement_tracker {
+                        measurement_tracker.on_deallocation( address.get() as u64 );
+                    }
+
                     if skip {
                         continue;
                     }
@@ -914,7 +1169,13 @@ pub(crate) fn thread_main() {
                     let tid = thread.tid();
                     mem::drop( thread );
 
-                    if let Ok( backtrace ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
+                    if let Some( ref mut live_tracker ) = live_tracker {
+                        live_tracker.on_deallocation( address.get() as u64 );
+                    }
+
+                    unfreed_allocations.remove( &address.get() );
+
+                    if let Ok( (backtrace, _) ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
                         let mut should_write = true;
                         if running && opt::get().cull_temporary_allocations && !id.is_untracked() && id.is_valid() {
                             if let Some( mut bucket ) = allocations.remove( &(id.thread, id.allocation) ) {
@@ -954,13 +1215,21 @@ pub(crate) fn thread_main() {
                         }
 
                         if should_write {
-                            let _ = Event::FreeEx {
+                            if let Some( contents ) = contents {
+                                let _ = serializer.write_event( &Event::AllocationContents {
+                                    id: id.into(),
+                                    pointer: address.get() as u64,
+                                    contents: contents.as_slice().into()
+                                });
+                            }
+
+                            let _ = serializer.write_event( &Event::FreeEx {
                                 id: id.into(),
                                 timestamp,
                                 pointer: address.get() as u64,
                                 backtrace,
                                 thread: tid
-                            }.write_to_stream( &mut *serializer );
+                            });
                         }
                     }
                 },
@@ -977,7 +1246,7 @@ pub(crate) fn thread_main() {
                     let tid = thread.tid();
                     mem::drop( thread );
 
-                    if let Ok( backtrace ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
+                    if let Ok( (backtrace, _) ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
                         let event = Event::MemoryMap {
                             timestamp,
                             pointer: pointer as u64,
@@ -991,7 +1260,7 @@ pub(crate) fn thread_main() {
                             offset
                         };
 
-                        let _ = event.write_to_stream( &mut *serializer );
+                        let _ = serializer.write_event( &event );
                     }
                 },
                 InternalEvent::Munmap { ptr, len, backtrace, mut timestamp, thread } => {
@@ -1007,9 +1276,37 @@ pub(crate) fn thread_main() {
                     let tid = thread.tid();
                     mem::drop( thread );
 
-                    if let Ok( backtrace ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
+                    if let Ok( (backtrace, _) ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
                         let event = Event::MemoryUnmap { timestamp, pointer: ptr as u64, length: len as u64, backtrace, thread: tid };
-                        let _ = event.write_to_stream( &mut *serializer );
+                        let _ = serializer.write_event( &event );
+                    }
+                },
+                InternalEvent::Mremap { old_pointer, old_length, pointer, length, flags, backtrace, mut timestamp, thread } => {
+                    if skip {
+                        continue;
+                    }
+
+                    if timestamp == Timestamp::min() {
+                        timestamp = coarse_timestamp;
+                    }
+
+                    let timestamp = timestamp_override.take().unwrap_or( timestamp );
+                    let tid = thread.tid();
+                    mem::drop( thread );
+
+                    if let Ok( (backtrace, _) ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
+                        let event = Event::MemoryRemap {
+                            timestamp,
+                            old_pointer: old_pointer as u64,
+                            old_length: old_length as u64,
+                            pointer: pointer as u64,
+                            length: length as u64,
+                            flags,
+                            backtrace,
+                            thread: tid
+                        };
+
+                        let _ = serializer.write_event( &event );
                     }
                 },
                 InternalEvent::Mallopt { param, value, result, mut timestamp, backtrace, thread } => {
@@ -1025,9 +1322,9 @@ pub(crate) fn thread_main() {
                     let tid = thread.tid();
                     mem::drop( thread );
 
-                    if let Ok( backtrace ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
+                    if let Ok( (backtrace, _) ) = writers::write_backtrace( &mut *serializer, tid, backtrace, &mut backtrace_cache ) {
                         let event = Event::Mallopt { timestamp, param, value, result, backtrace, thread: tid };
-                        let _ = event.write_to_stream( &mut *serializer );
+                        let _ = serializer.write_event( &event );
                     }
                 },
                 InternalEvent::Exit => {
@@ -1052,7 +1349,14 @@ pub(crate) fn thread_main() {
                     }
 
                     let event = Event::Marker { value };
-                    let _ = event.write_to_stream( &mut *serializer );
+                    let _ = serializer.write_event( &event );
+                },
+                InternalEvent::StartMeasurement => {
+                    measurement_tracker = Some( MeasurementTracker::default() );
+                },
+                InternalEvent::StopMeasurement => {
+                    let result = measurement_tracker.take().unwrap_or_default().finish();
+                    *measurement::RESULT.lock() = Some( result );
                 },
                 InternalEvent::OverrideNextTimestamp { timestamp } => {
                     timestamp_override = Some( timestamp );
@@ -1068,8 +1372,10 @@ pub(crate) fn thread_main() {
                                 contents: binary.as_bytes().into()
                             };
 
-                            let _ = event.write_to_stream( &mut *serializer );
+                            let _ = serializer.write_event( &event );
                         }
+                    } else {
+                        let _ = writers::write_build_ids( &mut *serializer );
                     }
 
                     debug!( "Writing new maps..." );
@@ -1079,7 +1385,7 @@ pub(crate) fn thread_main() {
                         contents: maps.as_bytes().into()
                     };
 
-                    let _ = event.write_to_stream( &mut *serializer );
+                    let _ = serializer.write_event( &event );
                 }
             }
         }
@@ -1090,6 +1396,20 @@ pub(crate) fn thread_main() {
         }
     }
 
+    if let (Some( metrics_pusher ), Some( live_tracker )) = (metrics_pusher.take(), live_tracker.as_ref()) {
+        metrics_pusher.finish( live_tracker, get_timestamp() );
+    }
+
+    for (address, (id, length)) in unfreed_allocations.drain() {
+        if let Some( contents ) = ContentsSample::read_from( address, length ) {
+            let _ = output_writer.write_event( &Event::AllocationContents {
+                id: id.into(),
+                pointer: address as u64,
+                contents: contents.as_slice().into()
+            });
+        }
+    }
+
     let _ = output_writer.flush();
     for client in &mut output_writer.inner_mut_without_flush().clients {
         let _ = Response::Finished.write_to_stream( &mut client.stream );
@@ -16,6 +16,7 @@ macro_rules! syscall {
     (@to_libc MMAP) => { libc::SYS_mmap };
     (@to_libc MMAP2) => { libc::SYS_mmap2 };
     (@to_libc MUNMAP) => { libc::SYS_munmap };
+    (@to_libc MREMAP) => { libc::SYS_mremap };
 
     ($num:ident) => {
         libc::syscall( syscall!( @to_libc $num ) )
@@ -105,3 +106,7 @@ pub unsafe fn mmap( addr: *mut libc::c_void, length: libc::size_t, prot: libc::c
 pub unsafe fn munmap( addr: *mut libc::c_void, length: libc::size_t ) -> libc::c_int {
     syscall!( MUNMAP, addr, length ) as libc::c_int
 }
+
+pub unsafe fn mremap( old_address: *mut libc::c_void, old_size: libc::size_t, new_size: libc::size_t, flags: libc::c_int, new_address: *mut libc::c_void ) -> *mut libc::c_void {
+    syscall!( MREMAP, old_address, old_size, new_size, flags, new_address ) as *mut libc::c_void
+}
@@ -1,4 +1,6 @@
+use std::ffi::CStr;
 use std::mem::{self, transmute};
+use std::ops::Range;
 use std::sync::{Arc, Weak};
 use libc::{self, c_void, c_int, uintptr_t};
 use perf_event_open::{Perf, EventSource, Event};
@@ -92,6 +94,9 @@ impl Drop for Cache {
 pub struct Backtrace {
     pub frames: Vec< usize >,
     pub stale_count: Option< u32 >,
+    /// Set when the unwinding was stopped at the first frame outside of the profiler
+    /// since it was in one of the libraries from `MEMORY_PROFILER_SKIP_BACKTRACE_FOR`.
+    pub is_truncated: bool,
     cache: Weak< Cache >
 }
 
@@ -111,6 +116,7 @@ impl Backtrace {
         Backtrace {
             frames: Vec::new(),
             stale_count: None,
+            is_truncated: false,
             cache: Weak::new()
         }
     }
@@ -201,6 +207,71 @@ lazy_static! {
     };
 }
 
+/// The address ranges of the code of the profiler itself and of the libraries
+/// listed in `MEMORY_PROFILER_SKIP_BACKTRACE_FOR`.
+#[derive(Default)]
+struct CodeRanges {
+    profiler: Vec< Range< usize > >,
+    skipped: Vec< Range< usize > >
+}
+
+lazy_static! {
+    static ref CODE_RANGES: RwLock< CodeRanges > = RwLock::new( find_code_ranges() );
+}
+
+/// Whenever the file name of a library matches a name from the list, ignoring the version suffix
+/// (so that e.g. `libssl.so` matches `/usr/lib/libssl.so.3`).
+fn matches_library( path: &[u8], name: &str ) -> bool {
+    let basename = &path[ path.iter().rposition( |&byte| byte == b'/' ).map( |index| index + 1 ).unwrap_or( 0 ).. ];
+    let name = name.as_bytes();
+    basename.starts_with( name ) && (basename.len() == name.len() || basename[ name.len() ] == b'.')
+}
+
+fn find_code_ranges() -> CodeRanges {
+    struct State {
+        own_address: usize,
+        ranges: CodeRanges
+    }
+
+    unsafe extern fn callback( info: *mut libc::dl_phdr_info, _: libc::size_t, data: *mut libc::c_void ) -> libc::c_int {
+        let state = &mut *(data as *mut State);
+        let info = &*info;
+
+        let mut ranges = Vec::new();
+        for index in 0..info.dlpi_phnum as usize {
+            let header = &*info.dlpi_phdr.add( index );
+            if header.p_type == libc::PT_LOAD && header.p_flags & libc::PF_X != 0 {
+                let start = info.dlpi_addr as usize + header.p_vaddr as usize;
+                ranges.push( start..start + header.p_memsz as usize );
+            }
+        }
+
+        if ranges.iter().any( |range| range.contains( &state.own_address ) ) {
+            state.ranges.profiler.extend( ranges );
+        } else if !info.dlpi_name.is_null() {
+            let path = CStr::from_ptr( info.dlpi_name ).to_bytes();
+            if opt::get().skip_backtrace_for.iter().any( |name| matches_library( path, name ) ) {
+                state.ranges.skipped.extend( ranges );
+            }
+        }
+
+        0
+    }
+
+    let mut state = State {
+        own_address: find_code_ranges as usize,
+        ranges: CodeRanges::default()
+    };
+
+    if !opt::get().skip_backtrace_for.is_empty() {
+        unsafe {
+            libc::dl_iterate_phdr( Some( callback ), &mut state as *mut _ as *mut libc::c_void );
+        }
+    }
+
+    state.ranges
+}
+
 pub unsafe fn register_frame_by_pointer( fde: *const u8 ) {
     AS.write().register_fde_from_pointer( fde )
 }
@@ -248,6 +319,10 @@ fn reload() -> parking_lot::RwLockReadGuard< 'static, LocalAddressSpace > {
     let mut address_space = AS.write();
     info!( "Reloading address space" );
     let update = address_space.reload().unwrap();
+    if !opt::get().skip_backtrace_for.is_empty() {
+        *CODE_RANGES.write() = find_code_ranges();
+    }
+
     crate::event::send_event( crate::event::InternalEvent::AddressSpaceUpdated {
         maps: update.maps,
         new_binaries: update.new_binaries
@@ -319,6 +394,35 @@ fn on_broken_unwinding( last_backtrace_depth: usize, stale_frame_count: usize )
     unsafe { libc::abort(); }
 }
 
+/// Unwinds up to the first frame outside of the profiler, and if it's in one of the libraries
+/// whose allocations should have no backtraces then stops there.
+fn grab_truncated( address_space: &LocalAddressSpace, unwind_ctx: &mut LocalUnwindContext, out: &mut Backtrace ) -> bool {
+    let ranges = CODE_RANGES.read();
+    if ranges.skipped.is_empty() {
+        return false;
+    }
+
+    let mut is_truncated = false;
+    address_space.unwind( unwind_ctx, |address| {
+        out.frames.push( address );
+        if ranges.profiler.iter().any( |range| range.contains( &address ) ) {
+            return UnwindControl::Continue;
+        }
+
+        is_truncated = ranges.skipped.iter().any( |range| range.contains( &address ) );
+        UnwindControl::Stop
+    });
+
+    if is_truncated {
+        out.stale_count = None;
+        out.is_truncated = true;
+    } else {
+        out.frames.clear();
+    }
+
+    is_truncated
+}
+
 #[inline(never)]
 pub fn grab( tls: &mut StrongThreadHandle, out: &mut Backtrace ) {
     out.reserve_from_cache( tls.unwind_cache() );
@@ -343,6 +447,11 @@ pub fn grab( tls: &mut StrongThreadHandle, out: &mut Backtrace ) {
         }
     };
 
+    // The rest of the stack is left alone, so the shadow stack (if any) is unaffected.
+    if !opt::get().skip_backtrace_for.is_empty() && grab_truncated( &address_space, unwind_ctx, out ) {
+        return;
+    }
+
     let debug_crosscheck_unwind_results = opt::crosscheck_unwind_results_with_libunwind() && !address_space.is_shadow_stack_enabled();
     if debug_crosscheck_unwind_results || !opt::emit_partial_backtraces() {
         address_space.unwind( unwind_ctx, |address| {
@@ -409,3 +518,45 @@ pub fn grab( tls: &mut StrongThreadHandle, out: &mut Backtrace ) {
         }
     }
 }
+
+#[test]
+fn test_matches_library() {
+    assert!( matches_library( b"/usr/lib/libssl.so.3", "libssl.so" ) );
+    assert!( matches_library( b"/usr/lib/libssl.so", "libssl.so" ) );
+    assert!( matches_library( b"libfoo.so", "libfoo.so" ) );
+    assert!( !matches_library( b"/usr/lib/libssl.so.3", "libss" ) );
+    assert!( !matches_library( b"/usr/lib/libssl2.so", "libssl" ) );
+    assert!( !matches_library( b"/opt/libssl.so/libcrypto.so", "libssl.so" ) );
+}
+
+#[test]
+fn test_truncated_backtraces_in_the_cache() {
+    use crate::processing_thread::BacktraceCache;
+
+    let backtrace = |frames: &[usize], stale_count: Option< u32 >, is_truncated: bool| {
+        let mut backtrace = Backtrace::new();
+        backtrace.frames.extend_from_slice( frames );
+        backtrace.stale_count = stale_count;
+        backtrace.is_truncated = is_truncated;
+        backtrace
+    };
+
+    let mut cache = BacktraceCache::new( 16 );
+    let (full_id, is_new, frames) = cache.resolve( 1, backtrace( &[ 1, 2, 3 ], None, false ) );
+    assert!( is_new );
+    assert_eq!( frames, &[ 1, 2, 3 ] );
+
+    // What `grab_truncated` returns: the profiler's frames and the first frame from the skipped library.
+    let (truncated_id, is_new, frames) = cache.resolve( 1, backtrace( &[ 10, 11 ], None, true ) );
+    assert!( is_new );
+    assert_ne!( truncated_id, full_id );
+    assert_eq!( frames, &[ 10, 11 ] );
+
+    assert_eq!( cache.resolve( 2, backtrace( &[ 10, 11 ], None, true ) ), (truncated_id, false, &[ 10, 11 ][..]) );
+
+    // The truncated backtrace isn't what the next partial backtrace of the thread is based on.
+    let (partial_id, is_new, frames) = cache.resolve( 1, backtrace( &[ 4 ], Some( 1 ), false ) );
+    assert!( is_new );
+    assert_ne!( partial_id, truncated_id );
+    assert_eq!( frames, &[ 4, 2, 3 ] );
+}
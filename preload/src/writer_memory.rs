@@ -5,8 +5,8 @@ use std::ptr;
 
 use nwind::proc_maps::parse as parse_maps;
 
+use common::chunked_stream::ChunkedWriter;
 use common::event::Event;
-use common::speedy::Writable;
 
 use crate::PAGE_SIZE;
 use crate::syscall;
@@ -24,7 +24,7 @@ fn is_accessible< U: Read + Seek >( mut fp: U, address: u64 ) -> bool {
     }
 }
 
-fn memory_dump_body< U: Write >( mut serializer: &mut U ) -> io::Result< () > {
+fn memory_dump_body< U: Write >( serializer: &mut ChunkedWriter< U > ) -> io::Result< () > {
     let mut buffer = Vec::new();
     buffer.resize( 1024 * 128, 0 );
     let mut buffer = buffer.into_boxed_slice();
@@ -72,11 +72,11 @@ fn memory_dump_body< U: Write >( mut serializer: &mut U ) -> io::Result< () > {
             fp.seek( SeekFrom::Start( address ) )?;
             fp.read_exact( &mut buffer[ 0..chunk_size as usize ] )?;
             let data = &buffer[ 0..chunk_size as usize ];
-            Event::MemoryDump {
+            serializer.write_event( &Event::MemoryDump {
                 address,
                 length: chunk_size as u64,
                 data: data.into()
-            }.write_to_stream( &mut serializer )?;
+            })?;
 
             end -= chunk_size;
         }
@@ -115,7 +115,7 @@ fn memory_dump_body< U: Write >( mut serializer: &mut U ) -> io::Result< () > {
     Ok(())
 }
 
-pub fn write_memory_dump< U: Write >( serializer: &mut U ) -> io::Result< () > {
+pub fn write_memory_dump< U: Write >( serializer: &mut ChunkedWriter< U > ) -> io::Result< () > {
     info!( "Writing a memory dump..." );
     serializer.flush()?;
 
@@ -1,16 +1,18 @@
 use std::collections::HashSet;
+use std::env;
 use std::ffi::CStr;
 use std::fs::{self, File};
 use std::io::{self, Write};
 use std::mem;
 use std::ops::Deref;
 use std::path::Path;
+use std::slice;
 
 use nwind::proc_maps::Region;
 use nwind::proc_maps::parse as parse_maps;
 
-use common::event::{DataId, Event, HeaderBody, HEADER_FLAG_IS_LITTLE_ENDIAN};
-use common::speedy::Writable;
+use common::chunked_stream::ChunkedWriter;
+use common::event::{AllocatorTunable, DataId, Event, HeaderBody, BUILD_IDS_PATH, HEADER_FLAG_IS_LITTLE_ENDIAN};
 use common::Timestamp;
 
 use crate::{CMDLINE, EXECUTABLE, PID};
@@ -35,12 +37,12 @@ fn mmap_file< P: AsRef< Path >, R, F: FnOnce( &[u8] ) -> R >( path: P, callback:
     Ok( callback( slice ) )
 }
 
-fn write_file< U: Write >( mut serializer: &mut U, path: &str, bytes: &[u8] ) -> io::Result< () > {
-    Event::File {
+fn write_file< U: Write >( serializer: &mut ChunkedWriter< U >, path: &str, bytes: &[u8] ) -> io::Result< () > {
+    serializer.write_event( &Event::File {
         timestamp: get_timestamp(),
         path: path.into(),
         contents: bytes.into()
-    }.write_to_stream( &mut serializer )?;
+    })?;
 
     Ok(())
 }
@@ -68,12 +70,126 @@ fn new_header_body( id: DataId, initial_timestamp: Timestamp ) -> io::Result< He
     })
 }
 
-pub fn write_header< U: Write >( id: DataId, initial_timestamp: Timestamp, serializer: &mut U ) -> io::Result< () > {
-    Event::Header( new_header_body( id, initial_timestamp )? ).write_to_stream( serializer )?;
+/// The environment variables through which the supported allocators can be tuned.
+const ALLOCATOR_TUNABLES: &[&str] = &[
+    "GLIBC_TUNABLES",
+    "MALLOC_ARENA_MAX",
+    "MALLOC_ARENA_TEST",
+    "MALLOC_MMAP_THRESHOLD_",
+    "MALLOC_MMAP_MAX_",
+    "MALLOC_TRIM_THRESHOLD_",
+    "MALLOC_TOP_PAD_",
+    "MALLOC_PERTURB_",
+    "MALLOC_CONF",
+    "TCMALLOC_RELEASE_RATE",
+    "TCMALLOC_MAX_TOTAL_THREAD_CACHE_BYTES"
+];
+
+unsafe fn find_symbol( name: &[u8] ) -> *mut libc::c_void {
+    libc::dlsym( libc::RTLD_DEFAULT, name.as_ptr() as *const libc::c_char )
+}
+
+unsafe fn string_from_ptr( pointer: *const libc::c_char ) -> String {
+    if pointer.is_null() {
+        return String::new();
+    }
+
+    CStr::from_ptr( pointer ).to_string_lossy().into_owned()
+}
+
+type MallctlFn = unsafe extern "C" fn( *const libc::c_char, *mut libc::c_void, *mut libc::size_t, *mut libc::c_void, libc::size_t ) -> libc::c_int;
+
+unsafe fn get_jemalloc_version( mallctl: MallctlFn ) -> String {
+    let mut version: *const libc::c_char = std::ptr::null();
+    let mut length = mem::size_of_val( &version );
+    let result = mallctl(
+        b"version\0".as_ptr() as *const libc::c_char,
+        &mut version as *mut _ as *mut libc::c_void,
+        &mut length,
+        std::ptr::null_mut(),
+        0
+    );
+
+    if result != 0 {
+        return String::new();
+    }
+
+    string_from_ptr( version )
+}
+
+fn get_libc_version() -> String {
+    unsafe {
+        let gnu_get_libc_version = find_symbol( b"gnu_get_libc_version\0" );
+        if gnu_get_libc_version.is_null() {
+            return String::new();
+        }
+
+        let gnu_get_libc_version: unsafe extern "C" fn() -> *const libc::c_char = mem::transmute( gnu_get_libc_version );
+        string_from_ptr( gnu_get_libc_version() )
+    }
+}
+
+#[cfg(feature = "jemalloc")]
+fn get_allocator( _: &str ) -> (&'static str, String) {
+    extern "C" {
+        #[link_name = "_rjem_mp_mallctl"]
+        fn jem_mallctl_real( name: *const libc::c_char, oldp: *mut libc::c_void, oldlenp: *mut libc::size_t, newp: *mut libc::c_void, newlen: libc::size_t ) -> libc::c_int;
+    }
+
+    ("jemalloc", unsafe { get_jemalloc_version( jem_mallctl_real ) })
+}
+
+#[cfg(not(feature = "jemalloc"))]
+fn get_allocator( libc_version: &str ) -> (&'static str, String) {
+    unsafe {
+        let tc_version = find_symbol( b"tc_version\0" );
+        if !tc_version.is_null() {
+            let tc_version: unsafe extern "C" fn( *mut libc::c_int, *mut libc::c_int, *mut *const libc::c_char ) -> *const libc::c_char = mem::transmute( tc_version );
+            let version = string_from_ptr( tc_version( std::ptr::null_mut(), std::ptr::null_mut(), std::ptr::null_mut() ) );
+            return ("tcmalloc", version);
+        }
+
+        let mallctl = find_symbol( b"mallctl\0" );
+        if !mallctl.is_null() {
+            let mallctl: MallctlFn = mem::transmute( mallctl );
+            return ("jemalloc", get_jemalloc_version( mallctl ));
+        }
+    }
+
+    if !libc_version.is_empty() {
+        ("glibc", libc_version.to_owned())
+    } else {
+        ("unknown", String::new())
+    }
+}
+
+fn write_allocator_info< U: Write >( serializer: &mut ChunkedWriter< U > ) -> io::Result< () > {
+    let libc_version = get_libc_version();
+    let (allocator, allocator_version) = get_allocator( &libc_version );
+    let tunables = ALLOCATOR_TUNABLES.iter().filter_map( |&name| {
+        env::var_os( name ).map( |value| AllocatorTunable {
+            name: name.to_owned(),
+            value: value.to_string_lossy().into_owned()
+        })
+    }).collect();
+
+    serializer.write_event( &Event::AllocatorInfo {
+        allocator: allocator.into(),
+        allocator_version: allocator_version.into(),
+        libc_version: libc_version.into(),
+        tunables
+    })?;
+
     Ok(())
 }
 
-pub fn write_binaries< U: Write >( mut serializer: &mut U ) -> io::Result< () > {
+pub fn write_header< U: Write >( id: DataId, initial_timestamp: Timestamp, serializer: &mut ChunkedWriter< U > ) -> io::Result< () > {
+    serializer.write_event( &Event::Header( new_header_body( id, initial_timestamp )? ) )?;
+    write_allocator_info( serializer )?;
+    Ok(())
+}
+
+pub fn write_binaries< U: Write >( serializer: &mut ChunkedWriter< U > ) -> io::Result< () > {
     let regions = read_maps()?;
     let mut files = HashSet::new();
     for region in regions {
@@ -95,7 +211,7 @@ pub fn write_binaries< U: Write >( mut serializer: &mut U ) -> io::Result< () >
     serializer.flush()?;
     for filename in files {
         debug!( "Writing '{}'...", filename );
-        match mmap_file( &filename, |bytes| write_file( &mut serializer, &filename, bytes ) ) {
+        match mmap_file( &filename, |bytes| write_file( &mut *serializer, &filename, bytes ) ) {
             Ok( result ) => {
                 result?
             },
@@ -108,24 +224,167 @@ pub fn write_binaries< U: Write >( mut serializer: &mut U ) -> io::Result< () >
     Ok(())
 }
 
-pub fn write_maps< U: Write >( serializer: &mut U ) -> io::Result< Vec< u8 > > {
+/// Finds the contents of the `NT_GNU_BUILD_ID` note in a `PT_NOTE` segment.
+fn find_build_id( mut notes: &[u8] ) -> Option< &[u8] > {
+    const NT_GNU_BUILD_ID: u32 = 3;
+    let read_u32 = |bytes: &[u8]| u32::from_ne_bytes( [bytes[ 0 ], bytes[ 1 ], bytes[ 2 ], bytes[ 3 ]] );
+    let align = |value: usize| (value + 3) & !3;
+
+    while notes.len() >= 12 {
+        let name_size = read_u32( &notes[ 0..4 ] ) as usize;
+        let desc_size = read_u32( &notes[ 4..8 ] ) as usize;
+        let kind = read_u32( &notes[ 8..12 ] );
+        let desc_start = 12 + align( name_size );
+        if desc_start.checked_add( desc_size ).map( |desc_end| desc_end > notes.len() ).unwrap_or( true ) {
+            return None;
+        }
+
+        if kind == NT_GNU_BUILD_ID && &notes[ 12..12 + name_size ] == b"GNU\0" {
+            return Some( &notes[ desc_start..desc_start + desc_size ] );
+        }
+
+        notes = &notes[ (desc_start + align( desc_size )).min( notes.len() ).. ];
+    }
+
+    None
+}
+
+/// Returns the paths and the build IDs of every loaded object which has one.
+fn read_build_ids() -> Vec< (String, Vec< u8 >) > {
+    unsafe extern "C" fn callback( info: *mut libc::dl_phdr_info, _size: libc::size_t, data: *mut libc::c_void ) -> libc::c_int {
+        let output = &mut *(data as *mut Vec< (String, Vec< u8 >) >);
+        let info = &*info;
+        let path = if info.dlpi_name.is_null() || *info.dlpi_name == 0 {
+            String::from_utf8_lossy( &EXECUTABLE ).into_owned()
+        } else {
+            CStr::from_ptr( info.dlpi_name ).to_string_lossy().into_owned()
+        };
+
+        // E.g. the vDSO, which isn't a file.
+        if !path.starts_with( "/" ) {
+            return 0;
+        }
+
+        let headers = slice::from_raw_parts( info.dlpi_phdr, info.dlpi_phnum as usize );
+        for header in headers {
+            if header.p_type != libc::PT_NOTE {
+                continue;
+            }
+
+            let notes = slice::from_raw_parts( (info.dlpi_addr as usize + header.p_vaddr as usize) as *const u8, header.p_memsz as usize );
+            if let Some( build_id ) = find_build_id( notes ) {
+                output.push( (path, build_id.to_owned()) );
+                break;
+            }
+        }
+
+        0
+    }
+
+    let mut output = Vec::new();
+    unsafe {
+        libc::dl_iterate_phdr( Some( callback ), &mut output as *mut _ as *mut libc::c_void );
+    }
+
+    output
+}
+
+/// Writes the build IDs of the loaded libraries, so that the binaries which are picked up
+/// on the machine where the data is analyzed can be checked to be the same ones.
+pub fn write_build_ids< U: Write >( serializer: &mut ChunkedWriter< U > ) -> io::Result< () > {
+    let mut contents = String::new();
+    for (path, build_id) in read_build_ids() {
+        for byte in build_id {
+            contents.push_str( &format!( "{:02x}", byte ) );
+        }
+
+        contents.push( ' ' );
+        contents.push_str( &path );
+        contents.push( '\n' );
+    }
+
+    write_file( serializer, BUILD_IDS_PATH, contents.as_bytes() )
+}
+
+pub fn write_maps< U: Write >( serializer: &mut ChunkedWriter< U > ) -> io::Result< Vec< u8 > > {
     let maps = read_file( "/proc/self/maps" )?;
-    Event::File { timestamp: get_timestamp(), path: "/proc/self/maps".into(), contents: maps.clone().into() }.write_to_stream( serializer )?;
+    serializer.write_event( &Event::File { timestamp: get_timestamp(), path: "/proc/self/maps".into(), contents: maps.clone().into() } )?;
     Ok( maps )
 }
 
-fn write_wallclock< U: Write >( serializer: &mut U ) -> io::Result< () > {
+fn write_wallclock< U: Write >( serializer: &mut ChunkedWriter< U > ) -> io::Result< () > {
     let (timestamp, sec, nsec) = get_wall_clock();
-    Event::WallClock { timestamp, sec, nsec }.write_to_stream( serializer )?;
+    serializer.write_event( &Event::WallClock { timestamp, sec, nsec } )?;
     Ok(())
 }
 
-fn write_uptime< U: Write >( serializer: &mut U ) -> io::Result< () > {
+fn get_resident_memory() -> io::Result< u64 > {
+    let statm = read_file( "/proc/self/statm" )?;
+    let statm = String::from_utf8_lossy( &statm );
+    statm.split_whitespace().nth( 1 )
+        .and_then( |pages| pages.parse::< u64 >().ok() )
+        .map( |pages| pages * crate::PAGE_SIZE as u64 )
+        .ok_or_else( || io::Error::new( io::ErrorKind::InvalidData, "malformed /proc/self/statm" ) )
+}
+
+#[cfg(not(feature = "jemalloc"))]
+fn get_heap_stats() -> (u64, u64, u64, u64) {
+    // The fields are C `int`s, so they wrap around once they go over 2GB.
+    let info = unsafe { libc::mallinfo() };
+    (info.arena as u32 as u64, info.uordblks as u32 as u64, info.fordblks as u32 as u64, info.hblkhd as u32 as u64)
+}
+
+#[cfg(feature = "jemalloc")]
+fn get_heap_stats() -> (u64, u64, u64, u64) {
+    (0, 0, 0, 0)
+}
+
+/// Returns the anonymous, file-backed and shared resident memory, if the kernel reports them.
+fn get_resident_memory_breakdown() -> Option< (u64, u64, u64) > {
+    // This is only extra detail, so if it can't be read the total resident memory is still recorded.
+    let status = read_file( "/proc/self/status" ).ok()?;
+    let status = String::from_utf8_lossy( &status );
+    let mut anonymous = None;
+    let mut file = None;
+    let mut shared = None;
+    for line in status.lines() {
+        let mut iter = line.split_whitespace();
+        let field = match iter.next() {
+            Some( "RssAnon:" ) => &mut anonymous,
+            Some( "RssFile:" ) => &mut file,
+            Some( "RssShmem:" ) => &mut shared,
+            _ => continue
+        };
+
+        *field = iter.next().and_then( |kilobytes| kilobytes.parse::< u64 >().ok() ).map( |kilobytes| kilobytes * 1024 );
+    }
+
+    match (anonymous, file, shared) {
+        (Some( anonymous ), Some( file ), Some( shared )) => Some( (anonymous, file, shared) ),
+        _ => None
+    }
+}
+
+pub fn write_allocator_stats< U: Write >( serializer: &mut ChunkedWriter< U > ) -> io::Result< () > {
+    let (heap_size, heap_used, heap_free, mmaped) = get_heap_stats();
+    let resident = get_resident_memory()?;
+    let timestamp = get_timestamp();
+    serializer.write_event( &Event::AllocatorStats { timestamp, heap_size, heap_used, heap_free, mmaped, resident } )?;
+
+    // Older kernels don't break down the resident memory, in which case only the total is available.
+    if let Some( (anonymous, file, shared) ) = get_resident_memory_breakdown() {
+        serializer.write_event( &Event::ResidentMemory { timestamp, anonymous, file, shared } )?;
+    }
+
+    Ok(())
+}
+
+fn write_uptime< U: Write >( serializer: &mut ChunkedWriter< U > ) -> io::Result< () > {
     let uptime = fs::read( "/proc/uptime" )?;
     write_file( serializer, "/proc/uptime", &uptime )
 }
 
-fn write_environ< U: Write >( mut serializer: U ) -> io::Result< () > {
+fn write_environ< U: Write >( serializer: &mut ChunkedWriter< U > ) -> io::Result< () > {
     extern "C" {
         static environ: *const *const libc::c_char;
     }
@@ -134,9 +393,9 @@ fn write_environ< U: Write >( mut serializer: U ) -> io::Result< () > {
         let mut ptr = environ;
         while !(*ptr).is_null() {
             let string = CStr::from_ptr( *ptr );
-            Event::Environ {
+            serializer.write_event( &Event::Environ {
                 entry: string.to_bytes().into()
-            }.write_to_stream( &mut serializer )?;
+            })?;
 
             ptr = ptr.offset( 1 );
         }
@@ -145,33 +404,33 @@ fn write_environ< U: Write >( mut serializer: U ) -> io::Result< () > {
     Ok(())
 }
 
-pub fn write_backtrace< U: Write >( serializer: &mut U, thread: u32, backtrace: Backtrace, cache: &mut BacktraceCache ) -> io::Result< u64 > {
-    let (id, backtrace) = cache.resolve( thread, backtrace );
-    let backtrace = match backtrace {
-        Some( backtrace ) => backtrace,
-        None => return Ok( id )
-    };
+/// Writes the backtrace out unless it was already written; returns its ID and its frames.
+pub fn write_backtrace< 'a, U: Write >( serializer: &mut ChunkedWriter< U >, thread: u32, backtrace: Backtrace, cache: &'a mut BacktraceCache ) -> io::Result< (u64, &'a [usize]) > {
+    let (id, is_new, backtrace) = cache.resolve( thread, backtrace );
+    if !is_new {
+        return Ok( (id, backtrace) );
+    }
 
     if mem::size_of::< usize >() == mem::size_of::< u32 >() {
         let frames: &[u32] = unsafe { std::slice::from_raw_parts( backtrace.as_ptr() as *const u32, backtrace.len() ) };
-        Event::Backtrace32 {
+        serializer.write_event( &Event::Backtrace32 {
             id,
             addresses: frames.into()
-        }.write_to_stream( serializer )?;
+        })?;
     } else if mem::size_of::< usize >() == mem::size_of::< u64 >() {
         let frames: &[u64] = unsafe { std::slice::from_raw_parts( backtrace.as_ptr() as *const u64, backtrace.len() ) };
-        Event::Backtrace {
+        serializer.write_event( &Event::Backtrace {
             id,
             addresses: frames.into()
-        }.write_to_stream( serializer )?;
+        })?;
     } else {
         unreachable!();
     }
 
-    Ok( id )
+    Ok( (id, backtrace) )
 }
 
-fn write_included_files< U: Write >( serializer: &mut U ) -> io::Result< () > {
+fn write_included_files< U: Write >( serializer: &mut ChunkedWriter< U > ) -> io::Result< () > {
     let pattern = match opt::get().include_file {
         Some( ref pattern ) => pattern,
         None => return Ok(())
@@ -214,30 +473,51 @@ fn write_included_files< U: Write >( serializer: &mut U ) -> io::Result< () > {
     Ok(())
 }
 
-pub fn write_initial_data< T >( id: DataId, initial_timestamp: Timestamp, mut fp: T ) -> Result< (), io::Error > where T: Write {
+pub fn write_initial_data< T >( id: DataId, initial_timestamp: Timestamp, fp: &mut ChunkedWriter< T > ) -> Result< (), io::Error > where T: Write {
     info!( "Writing initial header..." );
     write_header( id, initial_timestamp, &mut fp )?;
 
     info!( "Writing wall clock..." );
-    write_wallclock( &mut fp )?;
+    write_wallclock( fp )?;
 
     info!( "Writing uptime..." );
-    write_uptime( &mut fp )?;
-    write_included_files( &mut fp )?;
+    write_uptime( fp )?;
+    write_included_files( fp )?;
 
     info!( "Writing environ..." );
-    write_environ( &mut fp )?;
+    write_environ( fp )?;
 
     info!( "Writing maps..." );
-    write_maps( &mut fp )?;
+    write_maps( fp )?;
+    write_build_ids( fp )?;
     fp.flush()?;
 
     if opt::get().write_binaries_to_output {
         info!( "Writing binaries..." );
-        write_binaries( &mut fp )?;
+        write_binaries( fp )?;
     }
 
     info!( "Flushing..." );
     fp.flush()?;
     Ok(())
 }
+
+#[test]
+fn test_find_build_id() {
+    let mut notes = Vec::new();
+    let mut note = |name: &[u8], kind: u32, desc: &[u8]| {
+        notes.extend_from_slice( &(name.len() as u32).to_ne_bytes() );
+        notes.extend_from_slice( &(desc.len() as u32).to_ne_bytes() );
+        notes.extend_from_slice( &kind.to_ne_bytes() );
+        notes.extend_from_slice( name );
+        notes.resize( (notes.len() + 3) & !3, 0 );
+        notes.extend_from_slice( desc );
+        notes.resize( (notes.len() + 3) & !3, 0 );
+    };
+
+    note( b"GNU\0", 1, &[ 0, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0 ] );
+    note( b"GNU\0", 3, &[ 0xde, 0xad, 0xbe, 0xef, 0x01 ] );
+    assert_eq!( find_build_id( &notes ), Some( &[ 0xde, 0xad, 0xbe, 0xef, 0x01 ][..] ) );
+    assert_eq!( find_build_id( &notes[ ..notes.len() - 4 ] ), None );
+    assert_eq!( find_build_id( &[] ), None );
+}
@@ -0,0 +1,274 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use numpy::IntoPyArray;
+use pyo3::exceptions::{PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3::PyIterProtocol;
+
+use cli_core::{
+    BacktraceId,
+    Data,
+    Loader,
+    Operation,
+    StringId,
+    SymbolSources
+};
+
+/// A single allocation, along with its deallocation if it was freed.
+#[pyclass(module = "memory_profiler")]
+#[derive(Clone)]
+struct Allocation {
+    #[pyo3(get)]
+    id: u64,
+    #[pyo3(get)]
+    pointer: u64,
+    #[pyo3(get)]
+    size: u64,
+    #[pyo3(get)]
+    timestamp: u64,
+    #[pyo3(get)]
+    thread: u32,
+    #[pyo3(get)]
+    backtrace: u32,
+    #[pyo3(get)]
+    deallocation_timestamp: Option< u64 >,
+    #[pyo3(get)]
+    deallocation_thread: Option< u32 >
+}
+
+/// A single frame of a backtrace; everything except the address is only known if the binaries had symbols.
+#[pyclass(module = "memory_profiler")]
+#[derive(Clone)]
+struct Frame {
+    #[pyo3(get)]
+    address: u64,
+    #[pyo3(get)]
+    library: Option< String >,
+    #[pyo3(get)]
+    function: Option< String >,
+    #[pyo3(get)]
+    raw_function: Option< String >,
+    #[pyo3(get)]
+    source: Option< String >,
+    #[pyo3(get)]
+    line: Option< u32 >,
+    #[pyo3(get)]
+    column: Option< u32 >,
+    #[pyo3(get)]
+    is_inline: bool
+}
+
+/// A fully loaded data file; all of the timestamps are in microseconds.
+#[pyclass(module = "memory_profiler")]
+struct Capture {
+    data: Arc< Data >
+}
+
+#[pyclass(module = "memory_profiler")]
+struct Allocations {
+    data: Arc< Data >,
+    index: usize
+}
+
+fn resolve( data: &Data, id: Option< StringId > ) -> Option< String > {
+    id.map( |id| data.interner().resolve( id ).unwrap().to_owned() )
+}
+
+fn get_allocation( data: &Data, index: usize ) -> Allocation {
+    let allocation = &data.allocations()[ index ];
+    Allocation {
+        id: index as u64,
+        pointer: allocation.pointer,
+        size: allocation.size,
+        timestamp: allocation.timestamp.as_usecs(),
+        thread: allocation.thread,
+        backtrace: allocation.backtrace.raw(),
+        deallocation_timestamp: allocation.deallocation.as_ref().map( |deallocation| deallocation.timestamp.as_usecs() ),
+        deallocation_thread: allocation.deallocation.as_ref().map( |deallocation| deallocation.thread )
+    }
+}
+
+/// Samples the memory usage at the end of every `interval`, starting from `start`.
+fn build_timeline( start: u64, interval: u64, changes: impl Iterator< Item = (u64, i64, i64) > ) -> (Vec< u64 >, Vec< u64 >, Vec< u64 >) {
+    let mut timestamps = Vec::new();
+    let mut sizes = Vec::new();
+    let mut counts = Vec::new();
+    let mut size: i64 = 0;
+    let mut count: i64 = 0;
+    let mut push = |timestamps: &mut Vec< u64 >, size: i64, count: i64| {
+        timestamps.push( start + (timestamps.len() as u64 + 1) * interval );
+        sizes.push( size.max( 0 ) as u64 );
+        counts.push( count.max( 0 ) as u64 );
+    };
+
+    for (timestamp, size_delta, count_delta) in changes {
+        let bucket = timestamp.saturating_sub( start ) / interval;
+        while (timestamps.len() as u64) < bucket {
+            push( &mut timestamps, size, count );
+        }
+
+        size += size_delta;
+        count += count_delta;
+    }
+
+    push( &mut timestamps, size, count );
+    (timestamps, sizes, counts)
+}
+
+#[pymethods]
+impl Capture {
+    #[getter]
+    fn id( &self ) -> String {
+        self.data.id().to_string()
+    }
+
+    #[getter]
+    fn executable( &self ) -> &str {
+        self.data.executable()
+    }
+
+    #[getter]
+    fn architecture( &self ) -> &str {
+        self.data.architecture()
+    }
+
+    #[getter]
+    fn initial_timestamp( &self ) -> u64 {
+        self.data.initial_timestamp().as_usecs()
+    }
+
+    #[getter]
+    fn last_timestamp( &self ) -> u64 {
+        self.data.last_timestamp().as_usecs()
+    }
+
+    /// Returns an iterator over every allocation, ordered by when they were made.
+    fn allocations( &self ) -> Allocations {
+        Allocations {
+            data: self.data.clone(),
+            index: 0
+        }
+    }
+
+    /// Returns all of the allocations as a dict of NumPy arrays, one per field, which can be
+    /// passed as-is to `pandas.DataFrame`; `deallocation_timestamp` is -1 for leaked allocations.
+    fn allocation_arrays( &self, py: Python ) -> PyResult< PyObject > {
+        let allocations = self.data.allocations();
+        let mut pointers = Vec::with_capacity( allocations.len() );
+        let mut sizes = Vec::with_capacity( allocations.len() );
+        let mut timestamps = Vec::with_capacity( allocations.len() );
+        let mut threads = Vec::with_capacity( allocations.len() );
+        let mut backtraces = Vec::with_capacity( allocations.len() );
+        let mut deallocation_timestamps = Vec::with_capacity( allocations.len() );
+        for allocation in allocations {
+            pointers.push( allocation.pointer );
+            sizes.push( allocation.size );
+            timestamps.push( allocation.timestamp.as_usecs() );
+            threads.push( allocation.thread );
+            backtraces.push( allocation.backtrace.raw() );
+            deallocation_timestamps.push( allocation.deallocation.as_ref().map( |deallocation| deallocation.timestamp.as_usecs() as i64 ).unwrap_or( -1 ) );
+        }
+
+        let dict = PyDict::new( py );
+        dict.set_item( "pointer", pointers.into_pyarray( py ) )?;
+        dict.set_item( "size", sizes.into_pyarray( py ) )?;
+        dict.set_item( "timestamp", timestamps.into_pyarray( py ) )?;
+        dict.set_item( "thread", threads.into_pyarray( py ) )?;
+        dict.set_item( "backtrace", backtraces.into_pyarray( py ) )?;
+        dict.set_item( "deallocation_timestamp", deallocation_timestamps.into_pyarray( py ) )?;
+        Ok( dict.to_object( py ) )
+    }
+
+    /// Returns the frames of a backtrace, as referred to by the `backtrace` of the allocations.
+    fn backtrace( &self, id: u32 ) -> PyResult< Vec< Frame > > {
+        if id as usize >= self.data.unique_backtrace_count() {
+            return Err( PyIndexError::new_err( format!( "no such backtrace: {}", id ) ) );
+        }
+
+        let data = &self.data;
+        let frames = data.get_backtrace( BacktraceId::new( id ) ).map( |(_, frame)| Frame {
+            address: frame.address().raw(),
+            library: resolve( data, frame.library() ),
+            function: resolve( data, frame.function() ),
+            raw_function: resolve( data, frame.raw_function() ),
+            source: resolve( data, frame.source() ),
+            line: frame.line(),
+            column: frame.column(),
+            is_inline: frame.is_inline()
+        }).collect();
+
+        Ok( frames )
+    }
+
+    /// Returns how much memory was allocated at the end of every `interval` seconds
+    /// as a dict of NumPy arrays: `timestamp`, `allocated_size` and `allocated_count`.
+    #[args(interval = "1.0")]
+    fn timeline( &self, py: Python, interval: f64 ) -> PyResult< PyObject > {
+        let interval = (interval * 1_000_000.0) as u64;
+        if interval == 0 {
+            return Err( PyValueError::new_err( "the interval must be at least one microsecond" ) );
+        }
+
+        let data = &self.data;
+        let (timestamps, sizes, counts) = py.allow_threads( || {
+            let changes = data.operations().map( |operation| match operation {
+                Operation::Allocation { allocation, .. } => (allocation.timestamp.as_usecs(), allocation.size as i64, 1),
+                Operation::Deallocation { allocation, deallocation, .. } => (deallocation.timestamp.as_usecs(), -(allocation.size as i64), -1),
+                Operation::Reallocation { new_allocation, old_allocation, .. } => (new_allocation.timestamp.as_usecs(), new_allocation.size as i64 - old_allocation.size as i64, 0)
+            });
+
+            build_timeline( data.initial_timestamp().as_usecs(), interval, changes )
+        });
+
+        let dict = PyDict::new( py );
+        dict.set_item( "timestamp", timestamps.into_pyarray( py ) )?;
+        dict.set_item( "allocated_size", sizes.into_pyarray( py ) )?;
+        dict.set_item( "allocated_count", counts.into_pyarray( py ) )?;
+        Ok( dict.to_object( py ) )
+    }
+}
+
+#[pyproto]
+impl PyIterProtocol for Allocations {
+    fn __iter__( slf: PyRef< Self > ) -> PyRef< Self > {
+        slf
+    }
+
+    fn __next__( mut slf: PyRefMut< Self > ) -> Option< Allocation > {
+        if slf.index >= slf.data.allocations().len() {
+            return None;
+        }
+
+        let allocation = get_allocation( &slf.data, slf.index );
+        slf.index += 1;
+        Some( allocation )
+    }
+}
+
+/// Loads a data file, looking for the symbols in the same places as the CLI's
+/// `--debug-symbols`, `--sysroot` and `--symbol-path` do.
+#[pyfunction(debug_symbols = "None", sysroots = "None", symbol_paths = "None")]
+fn load( py: Python, path: String, debug_symbols: Option< Vec< String > >, sysroots: Option< Vec< String > >, symbol_paths: Option< Vec< String > > ) -> PyResult< Capture > {
+    let to_paths = |paths: Option< Vec< String > >| paths.unwrap_or_default().into_iter().map( PathBuf::from ).collect();
+    let symbol_sources = SymbolSources {
+        debug_symbols: to_paths( debug_symbols ),
+        sysroots: to_paths( sysroots ),
+        symbol_paths: to_paths( symbol_paths )
+    };
+
+    let data = py.allow_threads( || Loader::load_from_file( path, &symbol_sources ) )?;
+    Ok( Capture {
+        data: Arc::new( data )
+    })
+}
+
+#[pymodule]
+fn memory_profiler( _py: Python, module: &PyModule ) -> PyResult< () > {
+    module.add_class::< Capture >()?;
+    module.add_class::< Allocation >()?;
+    module.add_class::< Frame >()?;
+    module.add_function( wrap_pyfunction!( load, module )? )?;
+    Ok(())
+}
@@ -0,0 +1,87 @@
+"""
+Tests of the Python bindings against a capture of `integration-tests/test-programs/basic.c`;
+these are run by the `test_python_bindings` integration test, which builds the module and sets
+`MEMORY_PROFILER_TEST_CAPTURE` to the path of the capture.
+"""
+
+import os
+import unittest
+
+import memory_profiler
+
+
+class TestCapture( unittest.TestCase ):
+    @classmethod
+    def setUpClass( cls ):
+        cls.capture = memory_profiler.load( os.environ["MEMORY_PROFILER_TEST_CAPTURE"] )
+
+    def from_basic( self ):
+        allocations = []
+        for allocation in self.capture.allocations():
+            frames = self.capture.backtrace( allocation.backtrace )
+            if any( frame.source is not None and frame.source.endswith( "basic.c" ) for frame in frames ):
+                allocations.append( allocation )
+
+        return allocations
+
+    def test_metadata( self ):
+        self.assertEqual( len( self.capture.id ), 32 )
+        self.assertTrue( self.capture.executable.endswith( "basic" ) )
+        self.assertLessEqual( self.capture.initial_timestamp, self.capture.last_timestamp )
+
+    def test_allocations( self ):
+        allocations = self.from_basic()
+        self.assertEqual( [allocation.size for allocation in allocations], [10, 100, 1000, 10000, 100000, 1000000] )
+
+        leaked = [allocation.deallocation_timestamp is None for allocation in allocations]
+        self.assertEqual( leaked, [True, False, False, True, True, True] )
+
+        self.assertEqual( allocations[1].deallocation_thread, allocations[1].thread )
+        self.assertEqual( allocations[5].pointer % 65536, 0 )
+
+        ids = [allocation.id for allocation in self.capture.allocations()]
+        self.assertEqual( ids, list( range( len( ids ) ) ) )
+
+    def test_backtrace( self ):
+        allocation = self.from_basic()[0]
+        functions = [frame.function for frame in self.capture.backtrace( allocation.backtrace )]
+        self.assertIn( "foobar", functions )
+        self.assertIn( "main", functions )
+
+        with self.assertRaises( IndexError ):
+            self.capture.backtrace( 2 ** 32 - 1 )
+
+    def test_allocation_arrays( self ):
+        arrays = self.capture.allocation_arrays()
+        allocations = list( self.capture.allocations() )
+        self.assertEqual( set( arrays.keys() ), {"pointer", "size", "timestamp", "thread", "backtrace", "deallocation_timestamp"} )
+        for key in arrays:
+            self.assertEqual( len( arrays[key] ), len( allocations ) )
+
+        self.assertEqual( list( arrays["size"] ), [allocation.size for allocation in allocations] )
+        self.assertEqual(
+            list( arrays["deallocation_timestamp"] ),
+            [-1 if allocation.deallocation_timestamp is None else allocation.deallocation_timestamp for allocation in allocations]
+        )
+
+    def test_timeline( self ):
+        timeline = self.capture.timeline( interval = 0.001 )
+        timestamps = list( timeline["timestamp"] )
+        self.assertEqual( timestamps, sorted( timestamps ) )
+        self.assertEqual( len( timeline["allocated_size"] ), len( timestamps ) )
+
+        # At the end only the leaked allocations are left.
+        leaked = [allocation for allocation in self.capture.allocations() if allocation.deallocation_timestamp is None]
+        self.assertEqual( timeline["allocated_size"][-1], sum( allocation.size for allocation in leaked ) )
+        self.assertEqual( timeline["allocated_count"][-1], len( leaked ) )
+
+        with self.assertRaises( ValueError ):
+            self.capture.timeline( interval = 0.0 )
+
+    def test_load_missing_file( self ):
+        with self.assertRaises( OSError ):
+            memory_profiler.load( "/nonexistent/memory-profiling.dat" )
+
+
+if __name__ == "__main__":
+    unittest.main()
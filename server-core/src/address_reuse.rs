@@ -0,0 +1,275 @@
+use std::cmp::{max, min, Reverse};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use ahash::AHashMap as HashMap;
+
+use cli_core::{
+    Allocation,
+    BacktraceId,
+    Data,
+    Operation
+};
+
+use crate::fragmentation::{RegionKey, non_main_arena_heap_size, region_key};
+use crate::protocol;
+use crate::get_frame;
+
+/// The freed address ranges of a single region which weren't reused yet.
+#[derive(Default)]
+struct Holes {
+    by_address: BTreeMap< u64, u64 >,
+    by_size: BTreeMap< u64, u64 >,
+    total: u64
+}
+
+impl Holes {
+    fn add_hole( &mut self, start: u64, end: u64 ) {
+        self.by_address.insert( start, end );
+        *self.by_size.entry( end - start ).or_insert( 0 ) += 1;
+        self.total += end - start;
+    }
+
+    fn remove_hole( &mut self, start: u64 ) -> u64 {
+        let end = self.by_address.remove( &start ).unwrap();
+        let count = self.by_size.get_mut( &(end - start) ).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.by_size.remove( &(end - start) );
+        }
+
+        self.total -= end - start;
+        end
+    }
+
+    /// Marks a range as free, coalescing it with the adjacent free ranges like the allocator would.
+    fn free( &mut self, range: &Range< u64 > ) {
+        self.take( range );
+
+        let mut start = range.start;
+        let mut end = range.end;
+        let previous = self.by_address.range( ..start ).next_back().map( |(&hole_start, &hole_end)| (hole_start, hole_end) );
+        if let Some( (hole_start, hole_end) ) = previous {
+            if hole_end == start {
+                self.remove_hole( hole_start );
+                start = hole_start;
+            }
+        }
+
+        if self.by_address.contains_key( &end ) {
+            end = self.remove_hole( end );
+        }
+
+        self.add_hole( start, end );
+    }
+
+    /// Marks a range as used and returns how many of its bytes were previously freed.
+    fn take( &mut self, range: &Range< u64 > ) -> u64 {
+        let overlapping: Vec< (u64, u64) > = self.by_address.range( ..range.end ).rev()
+            .take_while( |&(_, &hole_end)| hole_end > range.start )
+            .map( |(&hole_start, &hole_end)| (hole_start, hole_end) )
+            .collect();
+
+        let mut reused = 0;
+        for (hole_start, hole_end) in overlapping {
+            self.remove_hole( hole_start );
+            reused += min( hole_end, range.end ) - max( hole_start, range.start );
+            if hole_start < range.start {
+                self.add_hole( hole_start, range.start );
+            }
+            if hole_end > range.end {
+                self.add_hole( range.end, hole_end );
+            }
+        }
+
+        reused
+    }
+
+    fn largest( &self ) -> u64 {
+        self.by_size.keys().next_back().cloned().unwrap_or( 0 )
+    }
+}
+
+#[test]
+fn test_holes() {
+    let mut holes = Holes::default();
+    holes.free( &(0..10) );
+    holes.free( &(20..30) );
+    holes.free( &(10..20) );
+    assert_eq!( holes.by_address.len(), 1 );
+    assert_eq!( holes.total, 30 );
+
+    assert_eq!( holes.take( &(5..8) ), 3 );
+    assert_eq!( holes.total, 27 );
+    assert_eq!( holes.largest(), 22 );
+
+    assert_eq!( holes.take( &(25..40) ), 5 );
+    assert_eq!( holes.take( &(40..50) ), 0 );
+    assert_eq!( holes.by_address.iter().map( |(&start, &end)| (start, end) ).collect::< Vec< _ > >(), vec![ (0, 5), (8, 25) ] );
+}
+
+#[derive(Default, Copy, Clone)]
+struct Sample {
+    allocated_size: u64,
+    reused_size: u64,
+    hole_size: u64,
+    used: u64
+}
+
+#[derive(Default)]
+struct SiteReuse {
+    allocated_count: u64,
+    allocated_size: u64,
+    reused_size: u64,
+    unfit_count: u64,
+    unfit_size: u64
+}
+
+struct State< 'a > {
+    data: &'a Data,
+    heap_size: u64,
+    holes: HashMap< RegionKey, Holes >,
+    sites: HashMap< BacktraceId, SiteReuse >,
+    current: Sample
+}
+
+impl< 'a > State< 'a > {
+    fn free( &mut self, allocation: &Allocation ) {
+        let range = allocation.actual_range( self.data );
+        let holes = self.holes.entry( region_key( allocation, &range, self.heap_size ) ).or_insert_with( Holes::default );
+
+        self.current.hole_size -= holes.total;
+        holes.free( &range );
+        self.current.hole_size += holes.total;
+        self.current.used -= range.end - range.start;
+    }
+
+    fn allocate( &mut self, allocation: &Allocation ) {
+        let range = allocation.actual_range( self.data );
+        let size = range.end - range.start;
+        let holes = self.holes.entry( region_key( allocation, &range, self.heap_size ) ).or_insert_with( Holes::default );
+
+        // There was enough free space, but it was too fragmented for this allocation to fit in.
+        let is_unfit = holes.total >= size && holes.largest() < size;
+
+        self.current.hole_size -= holes.total;
+        let reused = holes.take( &range );
+        self.current.hole_size += holes.total;
+        self.current.used += size;
+        self.current.allocated_size += size;
+        self.current.reused_size += reused;
+
+        let site = self.sites.entry( allocation.backtrace ).or_insert_with( SiteReuse::default );
+        site.allocated_count += 1;
+        site.allocated_size += size;
+        site.reused_size += reused;
+        if reused == 0 && is_unfit {
+            site.unfit_count += 1;
+            site.unfit_size += size;
+        }
+    }
+}
+
+/// Replays the allocations to see how much of the freed space gets reused by the subsequent
+/// allocations, and which sites get fresh memory only because the free space is too fragmented.
+pub fn get_address_reuse< 'a >(
+    data: &'a Data,
+    backtrace_format: &protocol::BacktraceFormat,
+    params: &protocol::RequestAddressReuse
+) -> protocol::ResponseAddressReuse< 'a > {
+    let remaining = params.count.unwrap_or( 10 ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+
+    let mut state = State {
+        data,
+        heap_size: non_main_arena_heap_size( data ),
+        holes: HashMap::new(),
+        sites: HashMap::new(),
+        current: Sample::default()
+    };
+
+    let mut xs = Vec::new();
+    let mut samples: Vec< Sample > = Vec::new();
+    let mut x = None;
+    for op in data.operations() {
+        let timestamp = match op {
+            Operation::Allocation { allocation, .. } => allocation.timestamp,
+            Operation::Deallocation { deallocation, .. } => deallocation.timestamp,
+            Operation::Reallocation { new_allocation, .. } => new_allocation.timestamp
+        }.as_secs();
+
+        if x != Some( timestamp ) {
+            if let Some( x ) = x {
+                xs.push( x );
+                samples.push( state.current );
+                state.current.allocated_size = 0;
+                state.current.reused_size = 0;
+
+                // The seconds without any operations hold whatever was there before them.
+                if x + 1 != timestamp {
+                    xs.push( x + 1 );
+                    samples.push( state.current );
+                }
+                if x + 2 < timestamp {
+                    xs.push( timestamp - 1 );
+                    samples.push( state.current );
+                }
+            }
+
+            x = Some( timestamp );
+        }
+
+        match op {
+            Operation::Allocation { allocation, .. } => {
+                if !allocation.is_mmaped() {
+                    state.allocate( allocation );
+                }
+            },
+            Operation::Deallocation { allocation, .. } => {
+                if !allocation.is_mmaped() {
+                    state.free( allocation );
+                }
+            },
+            Operation::Reallocation { new_allocation, old_allocation, .. } => {
+                if !old_allocation.is_mmaped() {
+                    state.free( old_allocation );
+                }
+                if !new_allocation.is_mmaped() {
+                    state.allocate( new_allocation );
+                }
+            }
+        }
+    }
+
+    if let Some( x ) = x {
+        xs.push( x );
+        samples.push( state.current );
+    }
+
+    let mut sites: Vec< _ > = state.sites.into_iter().collect();
+    sites.sort_by_key( |(backtrace_id, site)| (Reverse( site.unfit_size ), Reverse( site.allocated_size - site.reused_size ), *backtrace_id) );
+
+    let total_count = sites.len() as u64;
+    let sites = sites.into_iter().skip( skip ).take( remaining ).map( |(backtrace_id, site)| {
+        protocol::AddressReuseSite {
+            backtrace_id: backtrace_id.raw(),
+            site_id: data.get_site_id( backtrace_id ).to_string(),
+            backtrace: data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, backtrace_format, frame ) ).collect(),
+            allocated_count: site.allocated_count,
+            allocated_size: site.allocated_size,
+            reused_size: site.reused_size,
+            unfit_count: site.unfit_count,
+            unfit_size: site.unfit_size
+        }
+    }).collect();
+
+    protocol::ResponseAddressReuse {
+        xs,
+        allocated_size: samples.iter().map( |sample| sample.allocated_size ).collect(),
+        reused_size: samples.iter().map( |sample| sample.reused_size ).collect(),
+        hole_size: samples.iter().map( |sample| sample.hole_size ).collect(),
+        used: samples.iter().map( |sample| sample.used ).collect(),
+        sites,
+        total_count
+    }
+}
@@ -0,0 +1,101 @@
+use std::cmp::min;
+
+use cli_core::{
+    Data,
+    Operation
+};
+
+use crate::protocol;
+
+/// Returns the amount of memory which was tracked by the profiler at the moment of each allocator statistics sample.
+fn get_tracked_size_at_samples( data: &Data ) -> Vec< u64 > {
+    let samples = data.allocator_stats();
+    let mut tracked_size = Vec::with_capacity( samples.len() );
+    let mut operations = data.operations().peekable();
+    let mut current_size: i64 = 0;
+
+    for sample in samples {
+        while let Some( op ) = operations.peek() {
+            let (timestamp, delta) = match op {
+                Operation::Allocation { allocation, .. } => (allocation.timestamp, allocation.size as i64),
+                Operation::Deallocation { allocation, deallocation, .. } => (deallocation.timestamp, -(allocation.size as i64)),
+                Operation::Reallocation { new_allocation, old_allocation, .. } => {
+                    (new_allocation.timestamp, new_allocation.size as i64 - old_allocation.size as i64)
+                }
+            };
+
+            if timestamp > sample.timestamp {
+                break;
+            }
+
+            current_size += delta;
+            operations.next();
+        }
+
+        tracked_size.push( current_size as u64 );
+    }
+
+    tracked_size
+}
+
+/// Returns the periodically sampled allocator and OS statistics alongside
+/// the amount of memory which was tracked by the profiler at the same moment.
+pub fn get_allocator_stats_timeline( data: &Data ) -> protocol::ResponseAllocatorStatsTimeline {
+    let samples = data.allocator_stats();
+    protocol::ResponseAllocatorStatsTimeline {
+        xs: samples.iter().map( |sample| sample.timestamp.as_secs() ).collect(),
+        tracked_size: get_tracked_size_at_samples( data ),
+        heap_size: samples.iter().map( |sample| sample.heap_size ).collect(),
+        heap_used: samples.iter().map( |sample| sample.heap_used ).collect(),
+        heap_free: samples.iter().map( |sample| sample.heap_free ).collect(),
+        mmaped: samples.iter().map( |sample| sample.mmaped ).collect(),
+        resident: samples.iter().map( |sample| sample.resident ).collect()
+    }
+}
+
+/// Splits the resident memory into the tracked heap, the memory which the allocator holds on top of it,
+/// the file-backed memory and whatever is left; the file-backed memory is only known if the kernel reported it.
+fn break_down_resident_memory( resident: u64, file: Option< u64 >, tracked: u64, allocator_size: u64 ) -> (u64, u64, Option< u64 >, u64) {
+    let file = file.map( |file| min( file, resident ) );
+    let anonymous = resident - file.unwrap_or( 0 );
+    let tracked = min( tracked, anonymous );
+    let allocator_overhead = min( allocator_size.saturating_sub( tracked ), anonymous - tracked );
+    let unknown = anonymous - tracked - allocator_overhead;
+    (tracked, allocator_overhead, file, unknown)
+}
+
+#[test]
+fn test_break_down_resident_memory() {
+    assert_eq!( break_down_resident_memory( 1000, Some( 200 ), 500, 600 ), (500, 100, Some( 200 ), 200) );
+    assert_eq!( break_down_resident_memory( 1000, None, 500, 600 ), (500, 100, None, 400) );
+    assert_eq!( break_down_resident_memory( 1000, Some( 200 ), 900, 1000 ), (800, 0, Some( 200 ), 0) );
+    assert_eq!( break_down_resident_memory( 1000, Some( 2000 ), 0, 0 ), (0, 0, Some( 1000 ), 0) );
+}
+
+/// Returns a timeline of the resident memory broken down by what it's used for.
+pub fn get_resident_memory_timeline( data: &Data ) -> protocol::ResponseResidentMemoryTimeline {
+    let samples = data.allocator_stats();
+    let mut response = protocol::ResponseResidentMemoryTimeline {
+        xs: Vec::with_capacity( samples.len() ),
+        resident: Vec::with_capacity( samples.len() ),
+        tracked_heap: Vec::with_capacity( samples.len() ),
+        allocator_overhead: Vec::with_capacity( samples.len() ),
+        mapped_files: Vec::with_capacity( samples.len() ),
+        unknown: Vec::with_capacity( samples.len() )
+    };
+
+    for (sample, tracked_size) in samples.iter().zip( get_tracked_size_at_samples( data ) ) {
+        let file = sample.resident_breakdown.as_ref().map( |breakdown| breakdown.file + breakdown.shared );
+        let (tracked_heap, allocator_overhead, mapped_files, unknown) =
+            break_down_resident_memory( sample.resident, file, tracked_size, sample.heap_size + sample.mmaped );
+
+        response.xs.push( sample.timestamp.as_secs() );
+        response.resident.push( sample.resident );
+        response.tracked_heap.push( tracked_heap );
+        response.allocator_overhead.push( allocator_overhead );
+        response.mapped_files.push( mapped_files );
+        response.unknown.push( unknown );
+    }
+
+    response
+}
@@ -0,0 +1,163 @@
+use std::cmp::{max, min, Reverse};
+use std::ops::Range;
+
+use ahash::AHashMap as HashMap;
+
+use cli_core::{
+    Allocation,
+    Data,
+    MalloptKind,
+    Timestamp,
+    format_size
+};
+
+use crate::changes::site_name;
+use crate::fragmentation::{RegionKey, non_main_arena_heap_size, region_key};
+use crate::protocol;
+use crate::get_frame;
+
+/// The default `M_TRIM_THRESHOLD` of glibc.
+const DEFAULT_TRIM_THRESHOLD: u64 = 128 * 1024;
+
+struct Region< 'a > {
+    lowest: u64,
+    peak_top: u64,
+    used: u64,
+    live: Vec< (Range< u64 >, &'a Allocation) >
+}
+
+/// Returns the trim threshold which was in effect at a given time.
+fn get_trim_threshold( data: &Data, timestamp: Timestamp ) -> u64 {
+    data.mallopts().iter()
+        .filter( |mallopt| mallopt.timestamp <= timestamp && mallopt.result == 1 )
+        .filter( |mallopt| match mallopt.kind {
+            MalloptKind::TrimThreshold => true,
+            _ => false
+        })
+        .last()
+        .map( |mallopt| mallopt.value as u32 as u64 )
+        .unwrap_or( DEFAULT_TRIM_THRESHOLD )
+}
+
+/// Returns how much memory could be trimmed from the top of a heap if the topmost live
+/// allocations were freed, for each of those allocations starting from the topmost one.
+fn releasable_sizes( live: &[Range< u64 >], lowest: u64 ) -> Vec< u64 > {
+    let top = match live.last() {
+        Some( range ) => range.end,
+        None => return Vec::new()
+    };
+
+    (0..live.len()).rev().map( |index| {
+        let next_top = if index == 0 { lowest } else { live[ index - 1 ].end };
+        top - next_top
+    }).collect()
+}
+
+#[test]
+fn test_releasable_sizes() {
+    assert_eq!( releasable_sizes( &[0..10, 50..60, 60..100], 0 ), vec![ 40, 90, 100 ] );
+    assert_eq!( releasable_sizes( &[], 0 ), Vec::< u64 >::new() );
+}
+
+/// Explains which allocations keep the free memory inside of the allocator's heaps from being returned
+/// to the OS; glibc can only give the memory back by trimming the top of a heap, so a single long-lived
+/// allocation at the top pins all of the free memory below it.
+pub fn get_arena_trim< 'a >(
+    data: &'a Data,
+    backtrace_format: &protocol::BacktraceFormat,
+    params: &protocol::RequestArenaTrim
+) -> protocol::ResponseArenaTrim< 'a > {
+    let pinning_count = params.pinning_count.unwrap_or( 5 ) as usize;
+    let timestamp = params.at.map( |at| at.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) ).unwrap_or( data.last_timestamp() );
+    let trim_threshold = get_trim_threshold( data, timestamp );
+    let heap_size = non_main_arena_heap_size( data );
+
+    let mut regions: HashMap< RegionKey, Region > = HashMap::new();
+    for (_, allocation) in data.alloc_sorted_by_timestamp( None, Some( timestamp ) ) {
+        if allocation.is_mmaped() {
+            continue;
+        }
+
+        let range = allocation.actual_range( data );
+        let region = regions.entry( region_key( allocation, &range, heap_size ) ).or_insert_with( || Region {
+            lowest: range.start,
+            peak_top: range.end,
+            used: 0,
+            live: Vec::new()
+        });
+
+        region.lowest = min( region.lowest, range.start );
+        region.peak_top = max( region.peak_top, range.end );
+
+        let is_live = allocation.deallocation.as_ref().map( |deallocation| deallocation.timestamp > timestamp ).unwrap_or( true );
+        if is_live {
+            region.used += range.end - range.start;
+            region.live.push( (range, allocation) );
+        }
+    }
+
+    let mut output = Vec::new();
+    for (key, mut region) in regions {
+        region.live.sort_by_key( |&(ref range, _)| range.start );
+        let top = region.live.last().map( |(range, _)| range.end ).unwrap_or( region.lowest );
+        let pinned_size = (top - region.lowest).saturating_sub( region.used );
+        if pinned_size == 0 {
+            continue;
+        }
+
+        let ranges: Vec< _ > = region.live.iter().map( |(range, _)| range.clone() ).collect();
+        let releasable = releasable_sizes( &ranges, region.lowest );
+        let top_releasable_size = releasable[ 0 ];
+        let pinning: Vec< _ > = region.live.iter().rev().zip( releasable ).take( pinning_count ).map( |(&(ref range, allocation), releasable_size)| {
+            protocol::PinningAllocation {
+                address: range.start,
+                size: allocation.size,
+                timestamp: allocation.timestamp.into(),
+                timestamp_relative: (allocation.timestamp - data.initial_timestamp()).into(),
+                backtrace_id: allocation.backtrace.raw(),
+                site_id: data.get_site_id( allocation.backtrace ).to_string(),
+                backtrace: data.get_backtrace( allocation.backtrace ).map( |(_, frame)| get_frame( data, backtrace_format, frame ) ).collect(),
+                releasable_size
+            }
+        }).collect();
+
+        let (kind, arena_name) = match key {
+            RegionKey::MainArena => (protocol::ArenaKind::Main, "the main arena".to_owned()),
+            RegionKey::NonMainArenaHeap( base ) => (protocol::ArenaKind::NonMain, format!( "the non-main arena heap at 0x{:X}", base ))
+        };
+
+        let &(_, top_allocation) = region.live.last().unwrap();
+        let mut explanation = format!(
+            "{} of free memory in {} can't be returned to the OS because the top of the heap is pinned by a {} allocation from {} made {}s into the run which is still alive; freeing it would let {} be trimmed",
+            format_size( pinned_size ),
+            arena_name,
+            format_size( top_allocation.size ),
+            site_name( data, top_allocation.backtrace ),
+            (top_allocation.timestamp - data.initial_timestamp()).as_secs(),
+            format_size( top_releasable_size )
+        );
+
+        if top_releasable_size < trim_threshold {
+            explanation.push_str( &format!( ", although that's still below the trim threshold of {}", format_size( trim_threshold ) ) );
+        }
+
+        output.push( protocol::PinnedRegion {
+            kind,
+            start: region.lowest,
+            top,
+            peak_top: region.peak_top,
+            used: region.used,
+            pinned_size,
+            explanation,
+            pinning
+        });
+    }
+
+    output.sort_by_key( |region| (Reverse( region.pinned_size ), region.start) );
+    protocol::ResponseArenaTrim {
+        timestamp: timestamp.into(),
+        trim_threshold,
+        total_pinned_size: output.iter().map( |region| region.pinned_size ).sum(),
+        regions: output
+    }
+}
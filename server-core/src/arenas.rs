@@ -0,0 +1,135 @@
+use ahash::AHashMap as HashMap;
+
+use cli_core::{
+    Allocation,
+    Data,
+    Operation
+};
+
+use crate::protocol;
+use crate::fragmentation::non_main_arena_heap_size;
+
+#[derive(Default, Copy, Clone)]
+struct Sample {
+    main_arena_size: u64,
+    main_arena_count: u64,
+    non_main_arena_size: u64,
+    non_main_arena_count: u64,
+    non_main_arena_heap_count: u64,
+    mmaped_size: u64,
+    mmaped_count: u64
+}
+
+struct State< 'a > {
+    data: &'a Data,
+    heap_size: u64,
+    allocations_per_heap: HashMap< u64, u64 >,
+    current: Sample
+}
+
+impl< 'a > State< 'a > {
+    fn add( &mut self, allocation: &Allocation ) {
+        if allocation.is_mmaped() {
+            self.current.mmaped_size += allocation.size;
+            self.current.mmaped_count += 1;
+        } else if allocation.in_main_arena() {
+            self.current.main_arena_size += allocation.size;
+            self.current.main_arena_count += 1;
+        } else {
+            self.current.non_main_arena_size += allocation.size;
+            self.current.non_main_arena_count += 1;
+
+            let heap = allocation.actual_range( self.data ).start & !(self.heap_size - 1);
+            let count = self.allocations_per_heap.entry( heap ).or_insert( 0 );
+            if *count == 0 {
+                self.current.non_main_arena_heap_count += 1;
+            }
+            *count += 1;
+        }
+    }
+
+    fn remove( &mut self, allocation: &Allocation ) {
+        if allocation.is_mmaped() {
+            self.current.mmaped_size -= allocation.size;
+            self.current.mmaped_count -= 1;
+        } else if allocation.in_main_arena() {
+            self.current.main_arena_size -= allocation.size;
+            self.current.main_arena_count -= 1;
+        } else {
+            self.current.non_main_arena_size -= allocation.size;
+            self.current.non_main_arena_count -= 1;
+
+            let heap = allocation.actual_range( self.data ).start & !(self.heap_size - 1);
+            let count = self.allocations_per_heap.get_mut( &heap ).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.allocations_per_heap.remove( &heap );
+                self.current.non_main_arena_heap_count -= 1;
+            }
+        }
+    }
+}
+
+pub fn get_arena_timeline( data: &Data ) -> protocol::ResponseArenaTimeline {
+    let maximum_len = (data.last_timestamp().as_secs() - data.initial_timestamp().as_secs()) as usize;
+    let mut xs = Vec::with_capacity( maximum_len );
+    let mut samples: Vec< Sample > = Vec::with_capacity( maximum_len );
+    let mut x = (-1_i32) as u64;
+
+    let mut state = State {
+        data,
+        heap_size: non_main_arena_heap_size( data ),
+        allocations_per_heap: HashMap::new(),
+        current: Sample::default()
+    };
+
+    for op in data.operations() {
+        let timestamp = match op {
+            Operation::Allocation { allocation, .. } => {
+                state.add( allocation );
+                allocation.timestamp
+            },
+            Operation::Deallocation { allocation, deallocation, .. } => {
+                state.remove( allocation );
+                deallocation.timestamp
+            },
+            Operation::Reallocation { new_allocation, old_allocation, .. } => {
+                state.remove( old_allocation );
+                state.add( new_allocation );
+                new_allocation.timestamp
+            }
+        };
+
+        let timestamp = timestamp.as_secs();
+        if timestamp != x {
+            if x != (-1_i32 as u64) && x + 1 != timestamp {
+                let last_sample = samples.last().cloned().unwrap();
+
+                xs.push( x + 1 );
+                samples.push( last_sample );
+
+                if x + 2 != timestamp {
+                    xs.push( timestamp - 1 );
+                    samples.push( last_sample );
+                }
+            }
+
+            x = timestamp;
+            xs.push( x );
+            samples.push( Sample::default() );
+        }
+
+        *samples.last_mut().unwrap() = state.current;
+    }
+
+    protocol::ResponseArenaTimeline {
+        xs,
+        main_arena_size: samples.iter().map( |sample| sample.main_arena_size ).collect(),
+        main_arena_count: samples.iter().map( |sample| sample.main_arena_count ).collect(),
+        non_main_arena_size: samples.iter().map( |sample| sample.non_main_arena_size ).collect(),
+        non_main_arena_count: samples.iter().map( |sample| sample.non_main_arena_count ).collect(),
+        non_main_arena_heap_count: samples.iter().map( |sample| sample.non_main_arena_heap_count ).collect(),
+        mmaped_size: samples.iter().map( |sample| sample.mmaped_size ).collect(),
+        mmaped_count: samples.iter().map( |sample| sample.mmaped_count ).collect()
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use ahash::AHashMap as HashMap;
+use serde::Serialize;
+
+use cli_core::{
+    AllocationColumns,
+    BacktraceId,
+    Data
+};
+
+use crate::protocol;
+use crate::filter::{AllocationMatcher, Filter};
+use crate::streaming_serializer::{StreamingSerializer, Rows};
+use crate::{get_frame, strip_template};
+
+/// The comparisons get quadratically more expensive with the distance, so it's capped.
+const MAX_DISTANCE: u32 = 8;
+
+#[derive(PartialEq, Eq, Hash)]
+enum FrameKey {
+    Function( String ),
+    Address( u64 )
+}
+
+#[derive(Default)]
+struct ClusterEntry {
+    backtrace_ids: Vec< BacktraceId >,
+    count: u64,
+    size: u64,
+    leaked_count: u64,
+    leaked_size: u64
+}
+
+/// Calculates the edit distance between two sequences, or returns `None` if it's greater than `max_distance`.
+fn edit_distance_within( lhs: &[u32], rhs: &[u32], max_distance: usize ) -> Option< usize > {
+    if (lhs.len() as isize - rhs.len() as isize).abs() as usize > max_distance {
+        return None;
+    }
+
+    let mut previous: Vec< usize > = (0..=rhs.len()).collect();
+    let mut current = vec![ 0; rhs.len() + 1 ];
+    for (i, &lhs_item) in lhs.iter().enumerate() {
+        current[ 0 ] = i + 1;
+        let mut row_min = current[ 0 ];
+        for (j, &rhs_item) in rhs.iter().enumerate() {
+            let substitution = previous[ j ] + (lhs_item != rhs_item) as usize;
+            current[ j + 1 ] = std::cmp::min( substitution, std::cmp::min( previous[ j + 1 ], current[ j ] ) + 1 );
+            row_min = std::cmp::min( row_min, current[ j + 1 ] );
+        }
+
+        if row_min > max_distance {
+            return None;
+        }
+
+        std::mem::swap( &mut previous, &mut current );
+    }
+
+    let distance = previous[ rhs.len() ];
+    if distance > max_distance {
+        None
+    } else {
+        Some( distance )
+    }
+}
+
+#[test]
+fn test_edit_distance_within() {
+    assert_eq!( edit_distance_within( &[1, 2, 3], &[1, 2, 3], 0 ), Some( 0 ) );
+    assert_eq!( edit_distance_within( &[1, 2, 3], &[1, 4, 3], 1 ), Some( 1 ) );
+    assert_eq!( edit_distance_within( &[1, 2, 3], &[1, 2, 5, 3], 1 ), Some( 1 ) );
+    assert_eq!( edit_distance_within( &[1, 2, 3], &[4, 5, 3], 1 ), None );
+    assert_eq!( edit_distance_within( &[1, 2, 3], &[1, 2, 3, 4, 5], 1 ), None );
+    assert_eq!( edit_distance_within( &[], &[1], 1 ), Some( 1 ) );
+}
+
+/// Converts the backtrace into a sequence of frame keys; the template arguments are stripped
+/// so that frames which only differ in their instantiations are considered identical.
+fn frame_keys( data: &Data, backtrace_id: BacktraceId, interned: &mut HashMap< FrameKey, u32 > ) -> Vec< u32 > {
+    data.get_backtrace( backtrace_id ).map( |(_, frame)| {
+        let key = match frame.any_function() {
+            Some( id ) => FrameKey::Function( strip_template( data.interner().resolve( id ).unwrap() ) ),
+            None => FrameKey::Address( frame.address().raw() )
+        };
+
+        let next_index = interned.len() as u32;
+        *interned.entry( key ).or_insert( next_index )
+    }).collect()
+}
+
+/// Groups the backtraces of the matched allocations into clusters whose backtraces differ by at most
+/// `max_distance` frames from the cluster's representative, which is the backtrace with the most allocated bytes.
+pub fn get_backtrace_clusters< 'a >(
+    data: &'a Data,
+    columns: Option< &AllocationColumns >,
+    backtrace_format: protocol::BacktraceFormat,
+    params: protocol::RequestBacktraceClusters,
+    filter: Filter
+) -> protocol::ResponseBacktraceClusters< impl Serialize + Rows + 'a > {
+    let remaining = params.count.unwrap_or( -1_i32 as _ ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+    let max_distance = std::cmp::min( params.max_distance.unwrap_or( 2 ), MAX_DISTANCE ) as usize;
+    let sort_by = params.sort_by.unwrap_or( protocol::BacktraceClustersSortBy::Size );
+    let order = params.order.unwrap_or( protocol::Order::Dsc );
+
+    let mut entry_by_backtrace: HashMap< BacktraceId, ClusterEntry > = HashMap::new();
+    let matcher = AllocationMatcher::new( data, columns, &filter );
+    let iter = data.alloc_sorted_by_timestamp( filter.timestamp_start_opt(), filter.timestamp_end_opt() );
+    for (id, allocation) in iter {
+        if !matcher.matches( id, allocation ) {
+            continue;
+        }
+
+        let entry = entry_by_backtrace.entry( allocation.backtrace ).or_insert_with( ClusterEntry::default );
+        entry.count += 1;
+        entry.size += allocation.size;
+        if allocation.deallocation.is_none() {
+            entry.leaked_count += 1;
+            entry.leaked_size += allocation.size;
+        }
+    }
+
+    let mut backtraces: Vec< _ > = entry_by_backtrace.into_iter().collect();
+    backtraces.sort_by_key( |(backtrace_id, entry)| (std::cmp::Reverse( entry.size ), *backtrace_id) );
+
+    let mut interned = HashMap::new();
+    let mut clusters: Vec< (BacktraceId, Vec< u32 >, ClusterEntry) > = Vec::new();
+    let mut cluster_by_keys: HashMap< Vec< u32 >, usize > = HashMap::new();
+    let mut clusters_by_length: BTreeMap< usize, Vec< usize > > = BTreeMap::new();
+    for (backtrace_id, entry) in backtraces {
+        crate::query_limits::check_deadline();
+        let keys = frame_keys( data, backtrace_id, &mut interned );
+        let mut cluster_index = cluster_by_keys.get( &keys ).copied();
+        if cluster_index.is_none() {
+            let range = keys.len().saturating_sub( max_distance )..=keys.len() + max_distance;
+            cluster_index = clusters_by_length.range( range ).flat_map( |(_, indexes)| indexes.iter().copied() )
+                .filter_map( |index| edit_distance_within( &keys, &clusters[ index ].1, max_distance ).map( |distance| (distance, index) ) )
+                .min()
+                .map( |(_, index)| index );
+        }
+
+        let cluster_index = match cluster_index {
+            Some( index ) => index,
+            None => {
+                let index = clusters.len();
+                clusters_by_length.entry( keys.len() ).or_insert_with( Vec::new ).push( index );
+                clusters.push( (backtrace_id, keys.clone(), ClusterEntry::default()) );
+                index
+            }
+        };
+
+        cluster_by_keys.entry( keys ).or_insert( cluster_index );
+
+        let cluster = &mut clusters[ cluster_index ].2;
+        cluster.backtrace_ids.push( backtrace_id );
+        cluster.count += entry.count;
+        cluster.size += entry.size;
+        cluster.leaked_count += entry.leaked_count;
+        cluster.leaked_size += entry.leaked_size;
+    }
+
+    let mut entries: Vec< _ > = clusters.into_iter().map( |(backtrace_id, _, entry)| (backtrace_id, entry) ).collect();
+    entries.sort_by( |(lhs_key, lhs), (rhs_key, rhs)| {
+        let ordering = match sort_by {
+            protocol::BacktraceClustersSortBy::Count => lhs.count.cmp( &rhs.count ),
+            protocol::BacktraceClustersSortBy::Size => lhs.size.cmp( &rhs.size ),
+            protocol::BacktraceClustersSortBy::LeakedSize => lhs.leaked_size.cmp( &rhs.leaked_size ),
+            protocol::BacktraceClustersSortBy::BacktraceCount => lhs.backtrace_ids.len().cmp( &rhs.backtrace_ids.len() )
+        };
+
+        ordering.then_with( || lhs_key.cmp( rhs_key ) )
+    });
+
+    if order == protocol::Order::Dsc {
+        entries.reverse();
+    }
+
+    let total_count = entries.len() as u64;
+    let entries = Arc::new( entries );
+    let clusters = move || {
+        let backtrace_format = backtrace_format.clone();
+        let entries = entries.clone();
+        (0..entries.len())
+            .skip( skip )
+            .take( remaining )
+            .map( move |index| {
+                let (backtrace_id, ref entry) = entries[ index ];
+                let backtrace = data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, &backtrace_format, frame ) ).collect();
+                protocol::BacktraceCluster {
+                    backtrace_id: backtrace_id.raw(),
+                    backtrace,
+                    backtrace_ids: entry.backtrace_ids.iter().map( |backtrace_id| backtrace_id.raw() ).collect(),
+                    count: entry.count,
+                    size: entry.size,
+                    leaked_count: entry.leaked_count,
+                    leaked_size: entry.leaked_size
+                }
+            })
+    };
+
+    protocol::ResponseBacktraceClusters {
+        clusters: StreamingSerializer::new( clusters ),
+        total_count
+    }
+}
@@ -1,27 +1,82 @@
 use std::fmt;
 use std::mem;
 use std::io;
+use std::thread;
+use std::time::Instant;
 
 use bytes::Bytes;
 use crate::streaming_channel::{self, streaming_channel};
 
+struct Recording {
+    output: Vec< u8 >,
+    limit: usize,
+    callback: Box< dyn FnOnce( Vec< u8 > ) + Send >
+}
+
 pub struct ByteSender {
     buffer: Vec< u8 >,
-    tx: streaming_channel::Sender< Bytes >
+    tx: streaming_channel::Sender< Bytes >,
+    recording: Option< Recording >,
+    deadline: Option< Instant >,
+    timed_out: bool
 }
 
 pub fn byte_channel() -> (ByteSender, streaming_channel::Receiver< Bytes >) {
     let (tx, rx) = streaming_channel();
     let tx = ByteSender {
         buffer: Vec::new(),
-        tx
+        tx,
+        recording: None,
+        deadline: None,
+        timed_out: false
     };
 
     (tx, rx)
 }
 
 impl ByteSender {
+    /// Keeps a copy of everything which is sent and passes it to the `callback`
+    /// once the sender is dropped, unless it's bigger than `limit` or the sending fails.
+    pub fn record< F: FnOnce( Vec< u8 > ) + Send + 'static >( &mut self, limit: usize, callback: F ) {
+        self.recording = Some( Recording {
+            output: Vec::new(),
+            limit,
+            callback: Box::new( callback )
+        });
+    }
+
+    /// Makes every write after the `deadline` fail and aborts the whole response.
+    pub fn set_deadline( &mut self, deadline: Instant ) {
+        self.deadline = Some( deadline );
+    }
+
+    fn check_deadline( &mut self ) -> Result< (), () > {
+        if self.timed_out {
+            return Err(());
+        }
+
+        match self.deadline {
+            Some( deadline ) if Instant::now() >= deadline => {
+                self.timed_out = true;
+                self.recording = None;
+                self.buffer.clear();
+                self.tx.abort();
+                Err(())
+            },
+            _ => Ok(())
+        }
+    }
+
     fn write_buffer( &mut self, buffer: &[u8] ) -> Result< (), () > {
+        self.check_deadline()?;
+        if let Some( ref mut recording ) = self.recording {
+            if recording.output.len() + buffer.len() > recording.limit {
+                self.recording = None;
+            } else {
+                recording.output.extend_from_slice( buffer );
+            }
+        }
+
         self.buffer.extend_from_slice( buffer );
         if self.buffer.len() >= 128 * 1024 {
             self.flush_buffer()?;
@@ -31,19 +86,35 @@ impl ByteSender {
     }
 
     fn flush_buffer( &mut self ) -> Result< (), () >  {
+        self.check_deadline()?;
         if self.buffer.is_empty() {
             return Ok(());
         }
 
         let mut vec = Vec::with_capacity( self.buffer.capacity() );
         mem::swap( &mut vec, &mut self.buffer );
-        return self.tx.send( vec.into() );
+        let result = self.tx.send( vec.into() );
+        if result.is_err() {
+            self.recording = None;
+        }
+
+        result
     }
 }
 
 impl Drop for ByteSender {
     fn drop( &mut self ) {
+        // A response which was interrupted (e.g. because the query was aborted) shouldn't look complete.
+        if thread::panicking() {
+            self.tx.abort();
+            return;
+        }
+
         let _ = self.flush_buffer();
+
+        if let Some( recording ) = self.recording.take() {
+            (recording.callback)( recording.output );
+        }
     }
 }
 
@@ -0,0 +1,385 @@
+use std::cmp::Reverse;
+use std::hash::Hash;
+
+use ahash::{AHashMap as HashMap, AHashSet as HashSet};
+
+use cli_core::{
+    BacktraceId,
+    Data,
+    Operation,
+    SiteId,
+    format_size
+};
+
+use crate::protocol;
+use crate::get_frame;
+use crate::histograms::{bucket, bucket_range};
+use crate::regression::get_usage_by_backtrace;
+use crate::site_classification::{SAMPLE_COUNT, classify_curve};
+
+#[derive(Copy, Clone, Default)]
+struct SiteUsage {
+    peak: u64,
+    leaked: u64
+}
+
+#[derive(Copy, Clone, Default)]
+struct LibraryUsage {
+    allocated_count: u64,
+    allocated_size: u64
+}
+
+/// Everything from a single capture which is compared.
+struct Profile< 'a > {
+    data: &'a Data,
+    sites: Sites< SiteUsage >,
+    libraries: HashMap< &'a str, LibraryUsage >,
+    size_buckets: Vec< (u64, u64) >,
+    live_curve: Vec< u64 >,
+    shape: protocol::SiteClassification,
+    leaked: u64
+}
+
+fn file_name( path: &str ) -> &str {
+    path.rsplit( '/' ).next().unwrap_or( path )
+}
+
+fn format_delta( base: u64, target: u64 ) -> String {
+    let (verb, delta) = if target >= base { ("grew", target - base) } else { ("shrank", base - target) };
+    if base == 0 {
+        format!( "{} by {} (from nothing to {})", verb, format_size( delta ), format_size( target ) )
+    } else {
+        let percent = delta as f64 * 100.0 / base as f64;
+        format!( "{} by {} ({:.0}%, from {} to {})", verb, format_size( delta ), percent, format_size( base ), format_size( target ) )
+    }
+}
+
+fn shape_name( shape: protocol::SiteClassification ) -> &'static str {
+    match shape {
+        protocol::SiteClassification::SteadyGrowth => "steady growth",
+        protocol::SiteClassification::Plateau => "a plateau",
+        protocol::SiteClassification::Sawtooth => "a sawtooth",
+        protocol::SiteClassification::Startup => "startup only allocations",
+        protocol::SiteClassification::Other => "an irregular shape"
+    }
+}
+
+/// Returns the name of the innermost function of the backtrace, which is what people usually call a site by.
+pub fn site_name( data: &Data, backtrace_id: BacktraceId ) -> String {
+    let frames: Vec< _ > = data.get_backtrace( backtrace_id ).map( |(_, frame)| frame ).collect();
+    for frame in frames.iter().rev() {
+        if let Some( id ) = frame.any_function() {
+            return data.interner().resolve( id ).unwrap().to_owned();
+        }
+    }
+
+    match frames.last() {
+        Some( frame ) => format!( "0x{:016X}", frame.address().raw() ),
+        None => "<empty backtrace>".to_owned()
+    }
+}
+
+/// Samples the total amount of live memory at evenly spaced points over the whole runtime.
+fn total_live_curve( data: &Data ) -> Vec< u64 > {
+    let start = data.initial_timestamp().as_usecs();
+    let duration = std::cmp::max( data.last_timestamp().as_usecs().saturating_sub( start ), 1 );
+    let mut samples = Vec::with_capacity( SAMPLE_COUNT );
+    let mut live: u64 = 0;
+    for op in data.operations() {
+        let timestamp = match op {
+            Operation::Allocation { allocation, .. } => allocation.timestamp,
+            Operation::Deallocation { deallocation, .. } => deallocation.timestamp,
+            Operation::Reallocation { new_allocation, .. } => new_allocation.timestamp
+        };
+
+        while samples.len() < SAMPLE_COUNT && timestamp.as_usecs() > start + duration * (samples.len() as u64 + 1) / SAMPLE_COUNT as u64 {
+            samples.push( live );
+        }
+
+        match op {
+            Operation::Allocation { allocation, .. } => live += allocation.size,
+            Operation::Deallocation { allocation, .. } => live -= allocation.size,
+            Operation::Reallocation { new_allocation, old_allocation, .. } => {
+                live -= old_allocation.size;
+                live += new_allocation.size;
+            }
+        }
+    }
+
+    samples.resize( SAMPLE_COUNT, live );
+    samples
+}
+
+/// The usage of every site, along with one of the backtraces which belong to it.
+pub type Sites< U > = HashMap< SiteId, (BacktraceId, U) >;
+
+/// Groups the usage of the backtraces by their sites; the backtrace with the lowest ID is kept
+/// for every site, so that the same one is always picked no matter in which order they come.
+pub fn group_by_site< T, U: Default >(
+    data: &Data,
+    items: impl IntoIterator< Item = (BacktraceId, T) >,
+    mut add: impl FnMut( &mut U, T )
+) -> Sites< U > {
+    let mut site_by_backtrace: HashMap< BacktraceId, SiteId > = HashMap::new();
+    let mut sites: Sites< U > = HashMap::new();
+    for (backtrace_id, item) in items {
+        let site_id = *site_by_backtrace.entry( backtrace_id ).or_insert_with( || data.get_site_id( backtrace_id ) );
+        let site = sites.entry( site_id ).or_insert_with( || (backtrace_id, U::default()) );
+        site.0 = std::cmp::min( site.0, backtrace_id );
+        add( &mut site.1, item );
+    }
+
+    sites
+}
+
+/// Pairs up the entries of both captures by their keys, in the order of the keys.
+pub fn pair_by_key< K: Copy + Ord + Hash, V: Copy >( base: &HashMap< K, V >, target: &HashMap< K, V > ) -> Vec< (K, Option< V >, Option< V >) > {
+    let mut keys: Vec< K > = base.keys().chain( target.keys() ).copied().collect::< HashSet< _ > >().into_iter().collect();
+    keys.sort();
+    keys.into_iter().map( |key| (key, base.get( &key ).copied(), target.get( &key ).copied()) ).collect()
+}
+
+/// Returns the backtrace a site from either of the captures is shown with; the target's is preferred.
+pub fn site_backtrace< 'a >(
+    base: &'a Data,
+    base_backtrace: Option< BacktraceId >,
+    target: &'a Data,
+    target_backtrace: Option< BacktraceId >
+) -> (&'a Data, BacktraceId) {
+    match (target_backtrace, base_backtrace) {
+        (Some( backtrace_id ), _) => (target, backtrace_id),
+        (None, Some( backtrace_id )) => (base, backtrace_id),
+        (None, None) => unreachable!( "a site must be present in at least one of the captures" )
+    }
+}
+
+fn sorted_libraries< 'a >( profile: &Profile< 'a > ) -> Vec< (&'a str, LibraryUsage) > {
+    let mut libraries: Vec< _ > = profile.libraries.iter().map( |(&library, &usage)| (library, usage) ).collect();
+    libraries.sort_by_key( |&(library, _)| library );
+    libraries
+}
+
+fn build_profile( data: &Data ) -> Profile {
+    let mut leaked = 0;
+    let sites = group_by_site( data, get_usage_by_backtrace( data ), |site: &mut SiteUsage, usage| {
+        site.peak += usage.peak;
+        site.leaked += usage.leaked;
+        leaked += usage.leaked;
+    });
+
+    let mut libraries_by_backtrace: HashMap< BacktraceId, Vec< &str > > = HashMap::new();
+    let mut libraries: HashMap< &str, LibraryUsage > = HashMap::new();
+    let mut size_buckets = vec![ (0, 0); 65 ];
+    let mut last_allocation = data.initial_timestamp();
+    for allocation in data.allocations() {
+        let size_bucket = &mut size_buckets[ bucket( allocation.size ) ];
+        size_bucket.0 += 1;
+        size_bucket.1 += allocation.size;
+        last_allocation = std::cmp::max( last_allocation, allocation.timestamp );
+
+        let backtrace_libraries = libraries_by_backtrace.entry( allocation.backtrace ).or_insert_with( || {
+            let mut names: Vec< _ > = data.get_backtrace( allocation.backtrace )
+                .filter_map( |(_, frame)| frame.library() )
+                .map( |id| file_name( data.interner().resolve( id ).unwrap() ) )
+                .collect();
+            names.sort();
+            names.dedup();
+            names
+        });
+
+        for &library in backtrace_libraries.iter() {
+            let usage = libraries.entry( library ).or_insert_with( LibraryUsage::default );
+            usage.allocated_count += 1;
+            usage.allocated_size += allocation.size;
+        }
+    }
+
+    for event in data.library_events() {
+        libraries.entry( file_name( &event.library ) ).or_insert_with( LibraryUsage::default );
+    }
+
+    let start = data.initial_timestamp().as_usecs();
+    let duration = std::cmp::max( data.last_timestamp().as_usecs().saturating_sub( start ), 1 );
+    let last_allocation_at = (last_allocation.as_usecs().saturating_sub( start ) * SAMPLE_COUNT as u64 / duration) as usize;
+    let live_curve = total_live_curve( data );
+    let shape = classify_curve( &live_curve, last_allocation_at );
+
+    Profile {
+        data,
+        sites,
+        libraries,
+        size_buckets,
+        live_curve,
+        shape,
+        leaked
+    }
+}
+
+/// Compares two captures and summarizes what has changed between them as a list of changes
+/// ranked by how many bytes they affect, followed by the details the summary was built from.
+pub fn get_changes< 'a >(
+    base: &'a Data,
+    target: &'a Data,
+    backtrace_format: &protocol::BacktraceFormat,
+    params: &protocol::RequestChanges
+) -> protocol::ResponseChanges< 'a > {
+    let sites_count = params.sites_count.unwrap_or( 10 ) as usize;
+    let impact_min = params.impact_min.unwrap_or( 1 ) as u64;
+
+    let base = build_profile( base );
+    let target = build_profile( target );
+    let mut summary = Vec::new();
+
+    let base_peak = base.data.peak_allocated();
+    let target_peak = target.data.peak_allocated();
+    if base_peak != target_peak {
+        summary.push( protocol::Change {
+            kind: protocol::ChangeKind::Peak,
+            impact: (target_peak as i64 - base_peak as i64).abs() as u64,
+            description: format!( "Peak memory usage {}", format_delta( base_peak, target_peak ) )
+        });
+    }
+
+    if base.leaked != target.leaked {
+        summary.push( protocol::Change {
+            kind: protocol::ChangeKind::Leaked,
+            impact: (target.leaked as i64 - base.leaked as i64).abs() as u64,
+            description: format!( "Memory which was never freed {}", format_delta( base.leaked, target.leaked ) )
+        });
+    }
+
+    if base.shape != target.shape {
+        // This affects the whole process, so it's ranked as if it affected everything at the peak.
+        summary.push( protocol::Change {
+            kind: protocol::ChangeKind::TimelineShape,
+            impact: std::cmp::max( base_peak, target_peak ),
+            description: format!( "Memory usage over time changed from {} to {}", shape_name( base.shape ), shape_name( target.shape ) )
+        });
+    }
+
+    let mut sites = Vec::new();
+    for (site_id, base_site, target_site) in pair_by_key( &base.sites, &target.sites ) {
+        let base_usage = base_site.map( |(_, usage)| usage ).unwrap_or_default();
+        let target_usage = target_site.map( |(_, usage)| usage ).unwrap_or_default();
+        let leaked_delta = target_usage.leaked as i64 - base_usage.leaked as i64;
+        let peak_delta = target_usage.peak as i64 - base_usage.peak as i64;
+        let impact = std::cmp::max( leaked_delta.abs(), peak_delta.abs() ) as u64;
+        if impact == 0 {
+            continue;
+        }
+
+        let (data, backtrace_id) = site_backtrace( base.data, base_site.map( |(backtrace_id, _)| backtrace_id ), target.data, target_site.map( |(backtrace_id, _)| backtrace_id ) );
+
+        sites.push( (impact, site_id, data, backtrace_id, base_usage, target_usage) );
+    }
+
+    sites.sort_by_key( |&(impact, site_id, ..)| (Reverse( impact ), site_id) );
+    for &(impact, _, data, backtrace_id, base_usage, target_usage) in &sites {
+        let name = site_name( data, backtrace_id );
+        let description = if base_usage.peak == 0 {
+            format!( "New allocation site in `{}` peaked at {}", name, format_size( target_usage.peak ) )
+        } else if target_usage.peak == 0 {
+            format!( "Allocation site in `{}` which peaked at {} is gone", name, format_size( base_usage.peak ) )
+        } else if (target_usage.leaked as i64 - base_usage.leaked as i64).abs() as u64 == impact {
+            format!( "Memory never freed by `{}` {}", name, format_delta( base_usage.leaked, target_usage.leaked ) )
+        } else {
+            format!( "Peak memory usage of `{}` {}", name, format_delta( base_usage.peak, target_usage.peak ) )
+        };
+
+        summary.push( protocol::Change {
+            kind: protocol::ChangeKind::Site,
+            impact,
+            description
+        });
+    }
+
+    let mut new_libraries = Vec::new();
+    for (library, usage) in sorted_libraries( &target ) {
+        if !base.libraries.contains_key( library ) {
+            new_libraries.push( library.to_owned() );
+            summary.push( protocol::Change {
+                kind: protocol::ChangeKind::NewLibrary,
+                impact: usage.allocated_size,
+                description: format!( "New library `{}` made {} allocations totaling {}", library, usage.allocated_count, format_size( usage.allocated_size ) )
+            });
+        }
+    }
+
+    let mut removed_libraries = Vec::new();
+    for (library, usage) in sorted_libraries( &base ) {
+        if !target.libraries.contains_key( library ) {
+            removed_libraries.push( library.to_owned() );
+            summary.push( protocol::Change {
+                kind: protocol::ChangeKind::RemovedLibrary,
+                impact: usage.allocated_size,
+                description: format!( "Library `{}` which made {} allocations totaling {} is gone", library, usage.allocated_count, format_size( usage.allocated_size ) )
+            });
+        }
+    }
+
+    let mut size_distribution = Vec::new();
+    for (index, (&(base_count, base_size), &(target_count, target_size))) in base.size_buckets.iter().zip( target.size_buckets.iter() ).enumerate() {
+        if base_count == 0 && target_count == 0 {
+            continue;
+        }
+
+        let (min_size, max_size) = bucket_range( index );
+        size_distribution.push( protocol::SizeDistributionChange {
+            min_size,
+            max_size,
+            base_count,
+            base_size,
+            target_count,
+            target_size
+        });
+
+        // Only report the buckets whose allocation count changed by at least a quarter.
+        let count_delta = (target_count as i64 - base_count as i64).abs() as u64;
+        if count_delta * 4 >= std::cmp::max( base_count, 1 ) && count_delta != 0 {
+            let verb = if target_count > base_count { "grew" } else { "shrank" };
+            summary.push( protocol::Change {
+                kind: protocol::ChangeKind::SizeDistribution,
+                impact: (target_size as i64 - base_size as i64).abs() as u64,
+                description: format!( "The number of allocations between {} and {} {} from {} to {}", format_size( min_size ), format_size( max_size ), verb, base_count, target_count )
+            });
+        }
+    }
+
+    let threshold = std::cmp::max( base_peak, target_peak ) * impact_min / 100;
+    summary.retain( |change| change.impact > 0 && change.impact >= threshold );
+    // The sort is stable, so the changes with the same impact stay in the order in which they were found.
+    summary.sort_by_key( |change| Reverse( change.impact ) );
+
+    let sites = sites.into_iter().take( sites_count ).map( |(_, site_id, data, backtrace_id, base_usage, target_usage)| {
+        protocol::ChangedSite {
+            site_id: site_id.to_string(),
+            backtrace: data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, backtrace_format, frame ) ).collect(),
+            base_peak: base_usage.peak,
+            base_leaked: base_usage.leaked,
+            target_peak: target_usage.peak,
+            target_leaked: target_usage.leaked
+        }
+    }).collect();
+
+    protocol::ResponseChanges {
+        base: format!( "{}", base.data.id() ),
+        target: format!( "{}", target.data.id() ),
+        summary,
+        sites,
+        new_libraries,
+        removed_libraries,
+        size_distribution,
+        timeline: protocol::TimelineChange {
+            base_runtime: (base.data.last_timestamp() - base.data.initial_timestamp()).into(),
+            target_runtime: (target.data.last_timestamp() - target.data.initial_timestamp()).into(),
+            base_peak,
+            target_peak,
+            base_leaked: base.leaked,
+            target_leaked: target.leaked,
+            base_shape: base.shape,
+            target_shape: target.shape,
+            base_live: base.live_curve,
+            target_live: target.live_curve
+        }
+    }
+}
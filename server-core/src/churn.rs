@@ -0,0 +1,170 @@
+use std::cmp::{max, Ordering};
+use std::sync::Arc;
+
+use ahash::AHashMap as HashMap;
+use serde::Serialize;
+
+use cli_core::{
+    AllocationColumns,
+    BacktraceId,
+    Data,
+    Timestamp
+};
+
+use crate::protocol;
+use crate::filter::{AllocationMatcher, Filter};
+use crate::streaming_serializer::{StreamingSerializer, Rows};
+use crate::get_frame;
+
+#[derive(Default)]
+struct ChurnStats {
+    allocated_count: u64,
+    allocated_size: u64,
+    deallocated_count: u64,
+    deallocated_size: u64,
+    current_window: u64,
+    current_window_count: u64,
+    current_window_size: u64,
+    peak_window_count: u64,
+    peak_window_size: u64
+}
+
+impl ChurnStats {
+    fn flush_window( &mut self ) {
+        self.peak_window_count = max( self.peak_window_count, self.current_window_count );
+        self.peak_window_size = max( self.peak_window_size, self.current_window_size );
+        self.current_window_count = 0;
+        self.current_window_size = 0;
+    }
+}
+
+struct ChurnEntry {
+    backtrace_id: BacktraceId,
+    allocated_count: u64,
+    allocated_size: u64,
+    deallocated_count: u64,
+    deallocated_size: u64,
+    allocations_per_second: f64,
+    deallocations_per_second: f64,
+    bytes_per_second: f64,
+    peak_allocations_per_second: f64,
+    peak_bytes_per_second: f64
+}
+
+pub fn get_churn< 'a >(
+    data: &'a Data,
+    columns: Option< &AllocationColumns >,
+    backtrace_format: protocol::BacktraceFormat,
+    params: protocol::RequestChurn,
+    filter: Filter
+) -> protocol::ResponseChurn< impl Serialize + Rows + 'a > {
+    let remaining = params.count.unwrap_or( -1_i32 as _ ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+    let sort_by = params.sort_by.unwrap_or( protocol::ChurnSortBy::BytesPerSecond );
+    let order = params.order.unwrap_or( protocol::Order::Dsc );
+
+    let window = params.window.map( |window| window.0 ).unwrap_or( Timestamp::from_secs( 1 ) );
+    let window = max( window, Timestamp::eps() );
+    let range_start = filter.timestamp_start_opt().unwrap_or( data.initial_timestamp() );
+    let range_end = filter.timestamp_end_opt().unwrap_or( data.last_timestamp() );
+    let duration = if range_end > range_start { range_end - range_start } else { Timestamp::eps() };
+
+    let mut stats_by_backtrace: HashMap< BacktraceId, ChurnStats > = HashMap::new();
+    let matcher = AllocationMatcher::new( data, columns, &filter );
+    let iter = data.alloc_sorted_by_timestamp( filter.timestamp_start_opt(), filter.timestamp_end_opt() );
+    for (id, allocation) in iter {
+        if !matcher.matches( id, allocation ) {
+            continue;
+        }
+
+        let stats = stats_by_backtrace.entry( allocation.backtrace ).or_insert_with( ChurnStats::default );
+        let window_index = (allocation.timestamp - range_start).as_usecs() / window.as_usecs();
+        if window_index != stats.current_window {
+            stats.flush_window();
+            stats.current_window = window_index;
+        }
+
+        stats.allocated_count += 1;
+        stats.allocated_size += allocation.size;
+        stats.current_window_count += 1;
+        stats.current_window_size += allocation.size;
+
+        if let Some( ref deallocation ) = allocation.deallocation {
+            if deallocation.timestamp <= range_end {
+                stats.deallocated_count += 1;
+                stats.deallocated_size += allocation.size;
+            }
+        }
+    }
+
+    let seconds = duration.as_secs_f64();
+    let window_seconds = window.as_secs_f64();
+    let mut entries: Vec< ChurnEntry > = stats_by_backtrace.into_iter().map( |(backtrace_id, mut stats)| {
+        stats.flush_window();
+        ChurnEntry {
+            backtrace_id,
+            allocated_count: stats.allocated_count,
+            allocated_size: stats.allocated_size,
+            deallocated_count: stats.deallocated_count,
+            deallocated_size: stats.deallocated_size,
+            allocations_per_second: stats.allocated_count as f64 / seconds,
+            deallocations_per_second: stats.deallocated_count as f64 / seconds,
+            bytes_per_second: stats.allocated_size as f64 / seconds,
+            peak_allocations_per_second: stats.peak_window_count as f64 / window_seconds,
+            peak_bytes_per_second: stats.peak_window_size as f64 / window_seconds
+        }
+    }).collect();
+
+    fn key( entry: &ChurnEntry, sort_by: protocol::ChurnSortBy ) -> f64 {
+        match sort_by {
+            protocol::ChurnSortBy::AllocationsPerSecond => entry.allocations_per_second,
+            protocol::ChurnSortBy::DeallocationsPerSecond => entry.deallocations_per_second,
+            protocol::ChurnSortBy::BytesPerSecond => entry.bytes_per_second,
+            protocol::ChurnSortBy::PeakAllocationsPerSecond => entry.peak_allocations_per_second,
+            protocol::ChurnSortBy::PeakBytesPerSecond => entry.peak_bytes_per_second
+        }
+    }
+
+    entries.sort_by( |lhs, rhs| {
+        key( lhs, sort_by ).partial_cmp( &key( rhs, sort_by ) ).unwrap_or( Ordering::Equal )
+            .then_with( || lhs.backtrace_id.cmp( &rhs.backtrace_id ) )
+    });
+
+    if order == protocol::Order::Dsc {
+        entries.reverse();
+    }
+
+    let total_count = entries.len() as u64;
+    let entries = Arc::new( entries );
+    let groups = move || {
+        let backtrace_format = backtrace_format.clone();
+        let entries = entries.clone();
+        (0..entries.len())
+            .skip( skip )
+            .take( remaining )
+            .map( move |index| {
+                let entry = &entries[ index ];
+                let backtrace = data.get_backtrace( entry.backtrace_id ).map( |(_, frame)| get_frame( data, &backtrace_format, frame ) ).collect();
+                protocol::ChurnGroup {
+                    backtrace_id: entry.backtrace_id.raw(),
+                    backtrace,
+                    allocated_count: entry.allocated_count,
+                    allocated_size: entry.allocated_size,
+                    deallocated_count: entry.deallocated_count,
+                    deallocated_size: entry.deallocated_size,
+                    allocations_per_second: entry.allocations_per_second,
+                    deallocations_per_second: entry.deallocations_per_second,
+                    bytes_per_second: entry.bytes_per_second,
+                    peak_allocations_per_second: entry.peak_allocations_per_second,
+                    peak_bytes_per_second: entry.peak_bytes_per_second
+                }
+            })
+    };
+
+    protocol::ResponseChurn {
+        window: window.into(),
+        duration: duration.into(),
+        groups: StreamingSerializer::new( groups ),
+        total_count
+    }
+}
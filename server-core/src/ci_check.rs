@@ -0,0 +1,408 @@
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+
+use ahash::AHashMap as HashMap;
+
+use cli_core::{
+    BacktraceId,
+    Data,
+    FrameRules,
+    Loader,
+    SiteId,
+    SymbolSources,
+    format_size
+};
+
+use crate::changes::site_name;
+use crate::regression::get_usage;
+
+/*
+    The baseline is a JSON file which is meant to be committed alongside the code:
+
+        {
+            "version": 1,
+            "total": { "peak": 1000000, "leaked": 0, "max_peak": 1100000, "max_leaked": 0 },
+            "default": { "max_peak": 4096, "max_leaked": 0 },
+            "sites": [
+                { "site_id": "8c4f1e0a2b3d4c5e", "name": "foo::bar", "peak": 500000, "leaked": 0, "max_peak": 550000, "max_leaked": 0 }
+            ]
+        }
+
+    The `peak` and `leaked` are what was measured when the baseline was written and are only used
+    to show the deltas, while the `max_*` fields are the budgets which are actually enforced;
+    they can be edited by hand. The `default` budget applies to every site which isn't listed.
+*/
+
+const BASELINE_VERSION: u32 = 1;
+
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug, Serialize, Deserialize)]
+struct Usage {
+    peak: u64,
+    leaked: u64
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct Budget {
+    max_peak: u64,
+    max_leaked: u64
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct BaselineEntry {
+    #[serde(flatten)]
+    usage: Usage,
+    #[serde(flatten)]
+    budget: Budget
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct BaselineSite {
+    site_id: String,
+    name: String,
+    #[serde(flatten)]
+    entry: BaselineEntry
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct Baseline {
+    version: u32,
+    total: BaselineEntry,
+    default: Budget,
+    sites: Vec< BaselineSite >
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Ok,
+    OverBudget,
+    New,
+    Gone
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+struct SiteResult {
+    site_id: String,
+    name: String,
+    status: Status,
+    #[serde(flatten)]
+    usage: Usage,
+    baseline: Option< Usage >,
+    #[serde(flatten)]
+    budget: Budget,
+    backtrace_id: Option< u32 >,
+    backtrace_url: Option< String >,
+    #[serde(skip)]
+    frames: Vec< String >
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+struct CheckResult {
+    passed: bool,
+    data_id: String,
+    executable: String,
+    total: SiteResult,
+    sites: Vec< SiteResult >
+}
+
+pub struct CheckOptions {
+    /// The file with the per-site budgets.
+    pub baseline: PathBuf,
+    /// Writes a new baseline instead of checking against the existing one.
+    pub update_baseline: bool,
+    /// How many percent over the measured usage the budgets are when a new baseline is written.
+    pub tolerance: f64,
+    /// How many of the biggest sites are written into a new baseline.
+    pub baseline_sites: usize,
+    /// Where to write the results as JSON.
+    pub json_output: Option< PathBuf >,
+    /// Where to write the results as markdown; they're printed out if not specified.
+    pub markdown_output: Option< PathBuf >,
+    /// A server with the same data file loaded, to which the markdown will link.
+    pub server_url: Option< String >
+}
+
+fn with_tolerance( value: u64, tolerance: f64 ) -> u64 {
+    value + (value as f64 * tolerance / 100.0).ceil() as u64
+}
+
+fn check_budget( usage: Usage, budget: Budget ) -> bool {
+    usage.peak <= budget.max_peak && usage.leaked <= budget.max_leaked
+}
+
+fn format_frames( data: &Data, backtrace_id: BacktraceId ) -> Vec< String > {
+    let resolve = |id| data.interner().resolve( id ).unwrap();
+    let mut frames: Vec< String > = data.get_backtrace( backtrace_id ).map( |(_, frame)| {
+        let mut output = match frame.any_function() {
+            Some( function ) => resolve( function ).to_owned(),
+            None => format!( "0x{:016X}", frame.address().raw() )
+        };
+
+        if let (Some( source ), Some( line )) = (frame.source(), frame.line()) {
+            let _ = write!( output, " ({}:{})", resolve( source ), line );
+        } else if let Some( library ) = frame.library() {
+            let _ = write!( output, " [{}]", resolve( library ) );
+        }
+
+        output
+    }).collect();
+
+    frames.reverse();
+    frames
+}
+
+/// The `sites` are `(site ID, name, usage)`.
+fn build_baseline( total: Usage, sites: &[(String, String, Usage)], count: usize, tolerance: f64 ) -> Baseline {
+    let entry = |usage: Usage| BaselineEntry {
+        usage,
+        budget: Budget {
+            max_peak: with_tolerance( usage.peak, tolerance ),
+            max_leaked: with_tolerance( usage.leaked, tolerance )
+        }
+    };
+
+    // The biggest sites by their peak, and the biggest leakers.
+    let mut selected: Vec< usize > = (0..sites.len()).collect();
+    selected.sort_by_key( |&index| std::cmp::Reverse( sites[ index ].2.peak ) );
+    selected.truncate( count );
+
+    let mut leakers: Vec< usize > = (0..sites.len()).filter( |&index| sites[ index ].2.leaked > 0 ).collect();
+    leakers.sort_by_key( |&index| std::cmp::Reverse( sites[ index ].2.leaked ) );
+    leakers.truncate( count );
+    for index in leakers {
+        if !selected.contains( &index ) {
+            selected.push( index );
+        }
+    }
+
+    // Whatever isn't listed has to stay below the biggest of the sites which didn't make it into the list.
+    let unlisted = (0..sites.len()).filter( |index| !selected.contains( index ) );
+    let default = Budget {
+        max_peak: with_tolerance( unlisted.clone().map( |index| sites[ index ].2.peak ).max().unwrap_or( 0 ), tolerance ),
+        max_leaked: with_tolerance( unlisted.map( |index| sites[ index ].2.leaked ).max().unwrap_or( 0 ), tolerance )
+    };
+
+    Baseline {
+        version: BASELINE_VERSION,
+        total: entry( total ),
+        default,
+        sites: selected.into_iter().map( |index| {
+            let (ref site_id, ref name, usage) = sites[ index ];
+            BaselineSite {
+                site_id: site_id.clone(),
+                name: name.clone(),
+                entry: entry( usage )
+            }
+        }).collect()
+    }
+}
+
+fn compare( baseline: &Baseline, total: Usage, sites: &[(String, String, Usage)] ) -> (bool, SiteResult, Vec< SiteResult >) {
+    let result = |site_id: String, name: String, usage: Usage, baseline: Option< Usage >, budget: Budget, is_new: bool| {
+        let status = if !check_budget( usage, budget ) {
+            Status::OverBudget
+        } else if is_new {
+            Status::New
+        } else if baseline.is_some() && usage == Usage::default() {
+            Status::Gone
+        } else {
+            Status::Ok
+        };
+
+        SiteResult { site_id, name, status, usage, baseline, budget, backtrace_id: None, backtrace_url: None, frames: Vec::new() }
+    };
+
+    let total = result( String::new(), "total".to_owned(), total, Some( baseline.total.usage ), baseline.total.budget, false );
+    let baseline_sites: HashMap< &str, &BaselineSite > = baseline.sites.iter().map( |site| (site.site_id.as_str(), site) ).collect();
+    let mut seen = Vec::new();
+    let mut results = Vec::new();
+    for (site_id, name, usage) in sites {
+        match baseline_sites.get( site_id.as_str() ) {
+            Some( site ) => {
+                seen.push( site.site_id.as_str() );
+                results.push( result( site_id.clone(), name.clone(), *usage, Some( site.entry.usage ), site.entry.budget, false ) );
+            },
+            None => {
+                // There can be a lot of tiny sites which weren't worth putting into the baseline.
+                let result = result( site_id.clone(), name.clone(), *usage, None, baseline.default, true );
+                if result.status == Status::OverBudget {
+                    results.push( result );
+                }
+            }
+        }
+    }
+
+    for site in &baseline.sites {
+        if !seen.contains( &site.site_id.as_str() ) {
+            results.push( result( site.site_id.clone(), site.name.clone(), Usage::default(), Some( site.entry.usage ), site.entry.budget, false ) );
+        }
+    }
+
+    results.sort_by_key( |result| (result.status != Status::OverBudget, std::cmp::Reverse( result.usage.peak.max( result.baseline.map( |usage| usage.peak ).unwrap_or( 0 ) ) )) );
+    let passed = total.status != Status::OverBudget && results.iter().all( |result| result.status != Status::OverBudget );
+    (passed, total, results)
+}
+
+fn format_change( usage: u64, baseline: Option< u64 > ) -> String {
+    match baseline {
+        None => "new".to_owned(),
+        Some( baseline ) if usage >= baseline => format!( "+{}", format_size( usage - baseline ) ),
+        Some( baseline ) => format!( "-{}", format_size( baseline - usage ) )
+    }
+}
+
+fn render_markdown( result: &CheckResult ) -> String {
+    let mut output = String::new();
+    let _ = writeln!( output, "## Memory usage check {}", if result.passed { "passed" } else { "failed" } );
+    let _ = writeln!( output );
+    let _ = writeln!( output, "Data file `{}` of `{}`.", result.data_id, result.executable );
+    let _ = writeln!( output );
+    let _ = writeln!( output, "| Site | Status | Peak | Change | Budget | Leaked | Change | Budget |" );
+    let _ = writeln!( output, "|------|--------|-----:|-------:|-------:|-------:|-------:|-------:|" );
+    for site in std::iter::once( &result.total ).chain( result.sites.iter() ) {
+        let name = site.name.replace( '|', "\\|" ).replace( '`', "'" );
+        let name = match site.backtrace_url {
+            Some( ref url ) => format!( "[`{}`]({})", name, url ),
+            None => format!( "`{}`", name )
+        };
+
+        let status = match site.status {
+            Status::Ok => "ok",
+            Status::OverBudget => "**over budget**",
+            Status::New => "new",
+            Status::Gone => "gone"
+        };
+
+        let _ = writeln!(
+            output,
+            "| {} | {} | {} | {} | {} | {} | {} | {} |",
+            name,
+            status,
+            format_size( site.usage.peak ),
+            format_change( site.usage.peak, site.baseline.map( |usage| usage.peak ) ),
+            format_size( site.budget.max_peak ),
+            format_size( site.usage.leaked ),
+            format_change( site.usage.leaked, site.baseline.map( |usage| usage.leaked ) ),
+            format_size( site.budget.max_leaked )
+        );
+    }
+
+    for site in result.sites.iter().filter( |site| site.status == Status::OverBudget && !site.frames.is_empty() ) {
+        let _ = writeln!( output );
+        let _ = writeln!( output, "<details><summary>Backtrace of <code>{}</code> ({})</summary>", site.name.replace( '<', "&lt;" ), site.site_id );
+        let _ = writeln!( output );
+        let _ = writeln!( output, "```" );
+        for frame in &site.frames {
+            let _ = writeln!( output, "{}", frame );
+        }
+        let _ = writeln!( output, "```" );
+        let _ = writeln!( output, "</details>" );
+    }
+
+    output
+}
+
+/// Checks the memory usage of a data file against a baseline with per-site budgets.
+///
+/// Returns whether the check passed; a freshly written baseline always passes.
+pub fn check_main( input: PathBuf, symbol_sources: SymbolSources, frame_rules: Option< PathBuf >, options: CheckOptions ) -> Result< bool, Box< dyn Error > > {
+    let mut data = Loader::load_from_file( &input, &symbol_sources )?;
+    if let Some( path ) = frame_rules {
+        data.apply_frame_rules( &FrameRules::load( &path )? );
+    }
+
+    let mut site_by_backtrace: HashMap< BacktraceId, SiteId > = HashMap::new();
+    let mut backtrace_by_site: HashMap< SiteId, BacktraceId > = HashMap::new();
+    let usage_by_site = get_usage( &data, |backtrace_id| {
+        *site_by_backtrace.entry( backtrace_id ).or_insert_with( || {
+            let site_id = data.get_site_id( backtrace_id );
+            backtrace_by_site.entry( site_id ).or_insert( backtrace_id );
+            site_id
+        })
+    });
+
+    let usage = |usage: &crate::regression::BacktraceUsage| Usage { peak: usage.peak, leaked: usage.leaked };
+    let total = get_usage( &data, |_| () ).get( &() ).map( usage ).unwrap_or_default();
+    let mut sites: Vec< (String, String, Usage) > = usage_by_site.iter().map( |(&site_id, site_usage)| {
+        (site_id.to_string(), site_name( &data, backtrace_by_site[ &site_id ] ), usage( site_usage ))
+    }).collect();
+    sites.sort_by( |a, b| a.0.cmp( &b.0 ) );
+    let backtrace_by_site: HashMap< String, BacktraceId > = backtrace_by_site.into_iter().map( |(site_id, backtrace_id)| (site_id.to_string(), backtrace_id) ).collect();
+
+    if options.update_baseline {
+        let baseline = build_baseline( total, &sites, options.baseline_sites, options.tolerance );
+        fs::write( &options.baseline, serde_json::to_string_pretty( &baseline )? )?;
+        info!( "Written a baseline with {} sites to {:?}", baseline.sites.len(), options.baseline );
+        return Ok( true );
+    }
+
+    let baseline: Baseline = serde_json::from_str( &fs::read_to_string( &options.baseline )? )
+        .map_err( |error| format!( "failed to parse {:?}: {}", options.baseline, error ) )?;
+
+    if baseline.version != BASELINE_VERSION {
+        return Err( format!( "unsupported baseline version: {}", baseline.version ).into() );
+    }
+
+    let (passed, total, mut results) = compare( &baseline, total, &sites );
+    let server_url = options.server_url.as_ref().map( |url| url.trim_end_matches( '/' ) );
+    for result in &mut results {
+        if let Some( &backtrace_id ) = backtrace_by_site.get( &result.site_id ) {
+            result.backtrace_id = Some( backtrace_id.raw() );
+            result.backtrace_url = server_url.map( |url| format!( "{}/data/{}/backtrace/{}", url, data.id(), backtrace_id.raw() ) );
+            result.frames = format_frames( &data, backtrace_id );
+        }
+    }
+
+    let result = CheckResult {
+        passed,
+        data_id: data.id().to_string(),
+        executable: data.executable().to_owned(),
+        total,
+        sites: results
+    };
+
+    if let Some( path ) = options.json_output {
+        fs::write( path, serde_json::to_string_pretty( &result )? )?;
+    }
+
+    let markdown = render_markdown( &result );
+    match options.markdown_output {
+        Some( path ) => fs::write( path, markdown )?,
+        None => print!( "{}", markdown )
+    }
+
+    Ok( passed )
+}
+
+#[test]
+fn test_baseline_and_compare() {
+    let site = |id: u64, peak: u64, leaked: u64| (format!( "{:016x}", id ), format!( "site_{}", id ), Usage { peak, leaked });
+    let sites = vec![ site( 1, 1000, 0 ), site( 2, 500, 100 ), site( 3, 10, 0 ), site( 4, 5, 0 ) ];
+    let total = Usage { peak: 1200, leaked: 100 };
+
+    let baseline = build_baseline( total, &sites, 2, 10.0 );
+    assert_eq!( baseline.sites.iter().map( |site| site.name.as_str() ).collect::< Vec< _ > >(), vec![ "site_1", "site_2" ] );
+    assert_eq!( baseline.sites[ 0 ].entry.budget, Budget { max_peak: 1100, max_leaked: 0 } );
+    assert_eq!( baseline.default, Budget { max_peak: 11, max_leaked: 0 } );
+
+    let json = serde_json::to_string( &baseline ).unwrap();
+    assert_eq!( serde_json::from_str::< Baseline >( &json ).unwrap(), baseline );
+
+    let (passed, _, results) = compare( &baseline, total, &sites );
+    assert!( passed );
+    assert!( results.iter().all( |result| result.status == Status::Ok ) );
+
+    // The first site grew over its budget, the second one is gone and a new big one has appeared.
+    let sites = vec![ site( 1, 1200, 0 ), site( 3, 10, 0 ), site( 5, 100, 0 ) ];
+    let (passed, total, results) = compare( &baseline, Usage { peak: 1400, leaked: 0 }, &sites );
+    assert!( !passed );
+    assert_eq!( total.status, Status::OverBudget );
+    let statuses: Vec< _ > = results.iter().map( |result| (result.name.as_str(), result.status) ).collect();
+    assert_eq!( statuses, vec![ ("site_1", Status::OverBudget), ("site_5", Status::OverBudget), ("site_2", Status::Gone) ] );
+
+    let markdown = render_markdown( &CheckResult { passed, data_id: "id".to_owned(), executable: "foo".to_owned(), total, sites: results } );
+    assert!( markdown.starts_with( "## Memory usage check failed\n" ) );
+    assert!( markdown.contains( "| `site_1` | **over budget** | 1.2 KiB | +200 B | 1.1 KiB |" ) );
+}
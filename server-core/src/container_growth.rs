@@ -0,0 +1,137 @@
+use cli_core::Data;
+
+use crate::protocol;
+use crate::get_frame;
+use crate::site_classification::{SAMPLE_COUNT, live_curve};
+
+struct Growth {
+    regularity: f64
+}
+
+/// Checks whether the number of live allocations never goes down and keeps on growing in regular increments.
+fn detect_growth( counts: &[u64] ) -> Option< Growth > {
+    let first_nonzero = counts.iter().position( |&count| count != 0 )?;
+    let counts = &counts[ first_nonzero.. ];
+    if counts.len() < SAMPLE_COUNT / 4 {
+        return None;
+    }
+
+    let last = *counts.last().unwrap();
+    let tolerance = std::cmp::max( 1, last / 100 );
+    let mut increments = Vec::with_capacity( counts.len() - 1 );
+    for window in counts.windows( 2 ) {
+        if window[1] + tolerance < window[0] {
+            return None;
+        }
+
+        increments.push( window[1] as f64 - window[0] as f64 );
+    }
+
+    let growing_steps = increments.iter().filter( |&&increment| increment > 0.0 ).count();
+    if growing_steps * 4 < increments.len() * 3 {
+        return None;
+    }
+
+    let mean = increments.iter().sum::< f64 >() / increments.len() as f64;
+    if mean <= 0.0 {
+        return None;
+    }
+
+    let variance = increments.iter().map( |&increment| (increment - mean) * (increment - mean) ).sum::< f64 >() / increments.len() as f64;
+    let coefficient_of_variation = variance.sqrt() / mean;
+    if coefficient_of_variation > 1.0 {
+        return None;
+    }
+
+    Some( Growth {
+        regularity: 1.0 - coefficient_of_variation
+    })
+}
+
+#[test]
+fn test_detect_growth() {
+    let linear: Vec< u64 > = (0..64).map( |index| index * 10 ).collect();
+    assert_eq!( detect_growth( &linear ).unwrap().regularity, 1.0 );
+
+    let noisy: Vec< u64 > = (0..64).map( |index| index * 10 + index % 2 * 3 ).collect();
+    assert!( detect_growth( &noisy ).is_some() );
+
+    let shrinking: Vec< u64 > = (0..64).map( |index| if index == 40 { 0 } else { index * 10 } ).collect();
+    assert!( detect_growth( &shrinking ).is_none() );
+
+    let plateau: Vec< u64 > = (0..64).map( |index| std::cmp::min( index, 10 ) * 10 ).collect();
+    assert!( detect_growth( &plateau ).is_none() );
+
+    let steps: Vec< u64 > = (0..64).map( |index| if index < 60 { 10 } else { 1000 } ).collect();
+    assert!( detect_growth( &steps ).is_none() );
+
+    assert!( detect_growth( &[0; 64] ).is_none() );
+}
+
+/// Finds the call sites whose number of live allocations keeps on growing steadily over the whole runtime,
+/// which is what a container that's only ever appended to looks like.
+pub fn get_container_growth< 'a >(
+    data: &'a Data,
+    backtrace_format: &protocol::BacktraceFormat,
+    params: &protocol::RequestContainerGrowth
+) -> protocol::ResponseContainerGrowth< 'a > {
+    let remaining = params.count.unwrap_or( 20 ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+    let sort_by = params.sort_by.unwrap_or( protocol::ContainerGrowthSortBy::GrowthRate );
+    let live_count_min = params.live_count_min.unwrap_or( 64 );
+
+    let runtime = std::cmp::max( (data.last_timestamp() - data.initial_timestamp()).as_usecs(), 1 ) as f64 / 1_000_000.0;
+    let mut sites = Vec::new();
+    for (backtrace_id, _) in data.all_backtraces() {
+        if (data.get_allocation_ids_by_backtrace( backtrace_id ).len() as u64) < live_count_min {
+            continue;
+        }
+
+        let counts = live_curve( data, backtrace_id, |_| 1 );
+        let live_count = *counts.last().unwrap();
+        if live_count < live_count_min {
+            continue;
+        }
+
+        let growth = match detect_growth( &counts ) {
+            Some( growth ) => growth,
+            None => continue
+        };
+
+        let live_size = *live_curve( data, backtrace_id, |allocation| allocation.size ).last().unwrap();
+        let first_nonzero = counts.iter().position( |&count| count != 0 ).unwrap();
+        let growth_time = runtime * (SAMPLE_COUNT - first_nonzero) as f64 / SAMPLE_COUNT as f64;
+        let growth_rate = live_count.saturating_sub( counts[ first_nonzero ] ) as f64 / growth_time;
+        sites.push( (backtrace_id, counts, live_count, live_size, growth_rate, growth.regularity) );
+    }
+
+    sites.sort_by( |lhs, rhs| {
+        let ordering = match sort_by {
+            protocol::ContainerGrowthSortBy::GrowthRate => rhs.4.partial_cmp( &lhs.4 ).unwrap_or( std::cmp::Ordering::Equal ),
+            protocol::ContainerGrowthSortBy::LiveCount => rhs.2.cmp( &lhs.2 ),
+            protocol::ContainerGrowthSortBy::LiveSize => rhs.3.cmp( &lhs.3 )
+        };
+
+        ordering.then_with( || lhs.0.cmp( &rhs.0 ) )
+    });
+
+    let total_count = sites.len() as u64;
+    let sites = sites.into_iter().skip( skip ).take( remaining ).map( |(backtrace_id, counts, live_count, live_size, growth_rate, regularity)| {
+        let backtrace = data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, backtrace_format, frame ) ).collect();
+        protocol::ContainerGrowthSite {
+            backtrace_id: backtrace_id.raw(),
+            site_id: data.get_site_id( backtrace_id ).to_string(),
+            backtrace,
+            live_count,
+            live_size,
+            allocations_per_second: growth_rate,
+            regularity,
+            live_counts: counts
+        }
+    }).collect();
+
+    protocol::ResponseContainerGrowth {
+        sites,
+        total_count
+    }
+}
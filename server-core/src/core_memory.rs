@@ -0,0 +1,99 @@
+use std::cmp::min;
+
+use cli_core::{
+    AllocationId,
+    CoreDump,
+    Data,
+    LiveAllocations
+};
+
+use crate::protocol;
+
+/// How many bytes are returned by default if the address isn't within any allocation.
+const DEFAULT_LENGTH: u64 = 256;
+const MAXIMUM_LENGTH: u64 = 64 * 1024;
+
+/// A core dump of the profiled process, along with the allocations which were still alive at the end of the capture.
+pub struct CoreMemory {
+    core: CoreDump,
+    live: LiveAllocations
+}
+
+impl CoreMemory {
+    pub fn new( data: &Data, core: CoreDump ) -> Self {
+        CoreMemory { core, live: LiveAllocations::new( data ) }
+    }
+}
+
+fn to_core_allocation( data: &Data, (address, size, id): (u64, u64, AllocationId), pointer: u64 ) -> protocol::CoreAllocation {
+    let allocation = data.get_allocation( id );
+    protocol::CoreAllocation {
+        address,
+        address_s: format!( "{:016X}", address ),
+        size,
+        offset: pointer - address,
+        timestamp: allocation.timestamp.into(),
+        backtrace_id: allocation.backtrace.raw()
+    }
+}
+
+/// A printable representation of the bytes, with one character per byte.
+fn to_text( bytes: &[u8] ) -> String {
+    bytes.iter().map( |&byte| if byte >= 0x20 && byte < 0x7f { byte as char } else { '.' } ).collect()
+}
+
+pub fn get_core_summary( data: &Data, memory: &CoreMemory ) -> protocol::ResponseCoreSummary {
+    let dumped_count = memory.live.iter().filter( |&(address, size, _)| {
+        memory.core.read( address, size ).map( |bytes| bytes.len() as u64 == size ).unwrap_or( false )
+    }).count() as u64;
+
+    protocol::ResponseCoreSummary {
+        pid: memory.core.pid(),
+        capture_pid: data.pid(),
+        live_allocation_count: memory.live.len() as u64,
+        dumped_allocation_count: dumped_count
+    }
+}
+
+pub fn get_core_memory( data: &Data, memory: &CoreMemory, params: &protocol::RequestCoreMemory ) -> Option< protocol::ResponseCoreMemory > {
+    let allocation = memory.live.find( params.address );
+    let length = params.length.unwrap_or_else( || match allocation {
+        Some( (address, size, _) ) => address + size - params.address,
+        None => DEFAULT_LENGTH
+    });
+
+    let bytes = memory.core.read( params.address, min( length, MAXIMUM_LENGTH ) )?;
+
+    let pointer_size = if data.pointer_size() == 4 { 4 } else { 8 };
+    let misalignment = (params.address % pointer_size as u64) as usize;
+    let first = if misalignment == 0 { 0 } else { pointer_size - misalignment };
+    let pointers = (first..bytes.len()).step_by( pointer_size )
+        .filter( |&offset| offset + pointer_size <= bytes.len() )
+        .filter_map( |offset| {
+            let value = bytes[ offset..offset + pointer_size ].iter().rev().fold( 0, |value, &byte| (value << 8) | byte as u64 );
+            let target = memory.live.find( value )?;
+            Some( protocol::CorePointer {
+                offset: offset as u64,
+                value,
+                value_s: format!( "{:016X}", value ),
+                target: to_core_allocation( data, target, value )
+            })
+        })
+        .collect();
+
+    Some( protocol::ResponseCoreMemory {
+        address: params.address,
+        address_s: format!( "{:016X}", params.address ),
+        allocation: allocation.map( |entry| to_core_allocation( data, entry, params.address ) ),
+        length: bytes.len() as u64,
+        hex: bytes.iter().map( |byte| format!( "{:02x}", byte ) ).collect(),
+        text: to_text( bytes ),
+        utf8: String::from_utf8_lossy( bytes ).into_owned(),
+        pointers
+    })
+}
+
+#[test]
+fn test_to_text() {
+    assert_eq!( to_text( b"Hello\0\xff\n!" ), "Hello...!" );
+}
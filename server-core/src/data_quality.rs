@@ -0,0 +1,67 @@
+use cli_core::Data;
+
+use crate::protocol;
+
+/// Rates how much a capture can be trusted given the fraction of the deallocations which didn't match
+/// any tracked allocation, the fraction of the allocator's heap which wasn't tracked, if known,
+/// and the number of damaged chunks which had to be skipped when it was loaded.
+pub(crate) fn rate( unknown_deallocation_fraction: f64, untracked_heap_fraction: Option< f64 >, lost_chunk_count: u64 ) -> protocol::DataQualityRating {
+    let worst = untracked_heap_fraction.map( |fraction| fraction.max( unknown_deallocation_fraction ) ).unwrap_or( unknown_deallocation_fraction );
+    if worst < 0.01 && lost_chunk_count == 0 {
+        protocol::DataQualityRating::Good
+    } else if worst < 0.1 {
+        protocol::DataQualityRating::Fair
+    } else {
+        protocol::DataQualityRating::Poor
+    }
+}
+
+#[test]
+fn test_rate() {
+    assert_eq!( rate( 0.0, None, 0 ), protocol::DataQualityRating::Good );
+    assert_eq!( rate( 0.0, Some( 0.05 ), 0 ), protocol::DataQualityRating::Fair );
+    assert_eq!( rate( 0.5, Some( 0.0 ), 0 ), protocol::DataQualityRating::Poor );
+    assert_eq!( rate( 0.0, None, 1 ), protocol::DataQualityRating::Fair );
+}
+
+/// Quantifies how much of what the program did with the memory was missed by the profiler, e.g. because
+/// it was attached only after some allocations were already made or because some of the events were lost.
+pub fn get_data_quality( data: &Data ) -> protocol::ResponseDataQuality {
+    let unknown_deallocation_count = data.unknown_deallocation_count();
+    let deallocation_count = data.total_freed_count() + unknown_deallocation_count;
+    let unknown_deallocation_fraction = if deallocation_count == 0 {
+        0.0
+    } else {
+        unknown_deallocation_count as f64 / deallocation_count as f64
+    };
+
+    // The allocator knows how much memory is in use, so whatever we didn't see was allocated behind our back.
+    // Every chunk also carries a header and some padding which the allocator counts as used, so that's
+    // subtracted too as an estimate.
+    let untracked_heap = data.allocator_stats().last().and_then( |sample| {
+        let in_use = sample.heap_used + sample.mmaped;
+        if in_use == 0 {
+            // Not every allocator provides these statistics.
+            return None;
+        }
+
+        let tracked = sample.tracked_size + sample.tracked_count * 2 * data.pointer_size();
+        let untracked = in_use.saturating_sub( tracked );
+        Some( (untracked, untracked as f64 / in_use as f64) )
+    });
+
+    let untracked_heap_fraction = untracked_heap.map( |(_, fraction)| fraction );
+    let unknown_deallocation_range = data.unknown_deallocation_range();
+    protocol::ResponseDataQuality {
+        rating: rate( unknown_deallocation_fraction, untracked_heap_fraction, data.lost_chunk_count() ),
+        unknown_deallocation_count,
+        unknown_deallocation_fraction: unknown_deallocation_fraction as f32,
+        first_unknown_deallocation: unknown_deallocation_range.map( |(first, _)| (first - data.initial_timestamp()).into() ),
+        last_unknown_deallocation: unknown_deallocation_range.map( |(_, last)| (last - data.initial_timestamp()).into() ),
+        duplicate_allocation_count: data.duplicate_allocation_count(),
+        untracked_heap_size: untracked_heap.map( |(size, _)| size ),
+        untracked_heap_fraction: untracked_heap_fraction.map( |fraction| fraction as f32 ),
+        lost_chunk_count: data.lost_chunk_count(),
+        lost_event_count: data.lost_event_count()
+    }
+}
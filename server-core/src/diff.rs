@@ -0,0 +1,451 @@
+use std::cmp::Reverse;
+use std::error::Error;
+use std::fs;
+use std::hash::Hash;
+use std::path::PathBuf;
+
+use ahash::AHashMap as HashMap;
+
+use cli_core::{
+    Allocation,
+    Attribution,
+    BacktraceId,
+    Data,
+    FilteredData,
+    SiteId,
+    SymbolSources,
+    format_size,
+    table_to_string
+};
+
+use crate::protocol;
+use crate::get_frame;
+use crate::changes::{Sites, group_by_site, pair_by_key, site_backtrace, site_name};
+
+/*
+    The allocations of both captures are grouped by their site IDs (which, unlike the backtrace IDs,
+    are the same across runs and builds), and then the sites are compared one by one.
+    A site is `new` when it allocated nothing in the base capture, and `removed` when it
+    allocated nothing in the target capture.
+*/
+
+fn delta( base: &protocol::DiffUsage, target: &protocol::DiffUsage ) -> protocol::DiffDelta {
+    let delta = |base: u64, target: u64| target as i64 - base as i64;
+    protocol::DiffDelta {
+        allocated_count: delta( base.allocated_count, target.allocated_count ),
+        allocated_size: delta( base.allocated_size, target.allocated_size ),
+        freed_count: delta( base.freed_count, target.freed_count ),
+        freed_size: delta( base.freed_size, target.freed_size ),
+        leaked_count: delta( base.leaked_count, target.leaked_count ),
+        leaked_size: delta( base.leaked_size, target.leaked_size )
+    }
+}
+
+fn sort_key( delta: &protocol::DiffDelta, sort_by: protocol::DiffSortBy ) -> u64 {
+    let value = match sort_by {
+        protocol::DiffSortBy::AllocatedCount => delta.allocated_count,
+        protocol::DiffSortBy::AllocatedSize => delta.allocated_size,
+        protocol::DiffSortBy::FreedCount => delta.freed_count,
+        protocol::DiffSortBy::FreedSize => delta.freed_size,
+        protocol::DiffSortBy::LeakedCount => delta.leaked_count,
+        protocol::DiffSortBy::LeakedSize => delta.leaked_size
+    };
+
+    value.abs() as u64
+}
+
+fn add_allocation( usage: &mut protocol::DiffUsage, allocation: &Allocation ) {
+    usage.allocated_count += 1;
+    usage.allocated_size += allocation.size;
+    if allocation.was_deallocated() {
+        usage.freed_count += 1;
+        usage.freed_size += allocation.size;
+    } else {
+        usage.leaked_count += 1;
+        usage.leaked_size += allocation.size;
+    }
+}
+
+/// The usage of every site of a single capture.
+struct Usage {
+    total: protocol::DiffUsage,
+    sites: Sites< protocol::DiffUsage >
+}
+
+fn usage_by_site( data: &Data, filter: impl Fn( &Allocation ) -> bool ) -> Usage {
+    let mut total = protocol::DiffUsage::default();
+    let allocations = data.allocations().iter()
+        .filter( |allocation| filter( allocation ) )
+        .inspect( |allocation| add_allocation( &mut total, allocation ) )
+        .map( |allocation| (allocation.backtrace, allocation) );
+
+    let sites = group_by_site( data, allocations, add_allocation );
+    Usage { total, sites }
+}
+
+struct SiteDiff< K > {
+    key: K,
+    status: protocol::DiffStatus,
+    base: Option< (BacktraceId, protocol::DiffUsage) >,
+    target: Option< (BacktraceId, protocol::DiffUsage) >,
+    delta: protocol::DiffDelta
+}
+
+/// Pairs up the sites of both captures and sorts them by how much they've changed.
+fn compare< K: Copy + Ord + Hash >(
+    base: &HashMap< K, (BacktraceId, protocol::DiffUsage) >,
+    target: &HashMap< K, (BacktraceId, protocol::DiffUsage) >,
+    sort_by: protocol::DiffSortBy,
+    include_unchanged: bool
+) -> Vec< SiteDiff< K > > {
+    let mut output: Vec< _ > = pair_by_key( base, target ).into_iter().filter_map( |(key, base, target)| {
+        let base_usage = base.map( |(_, usage)| usage ).unwrap_or_default();
+        let target_usage = target.map( |(_, usage)| usage ).unwrap_or_default();
+        let status = if base_usage.allocated_count == 0 {
+            protocol::DiffStatus::New
+        } else if target_usage.allocated_count == 0 {
+            protocol::DiffStatus::Removed
+        } else if base_usage == target_usage {
+            protocol::DiffStatus::Unchanged
+        } else {
+            protocol::DiffStatus::Changed
+        };
+
+        if status == protocol::DiffStatus::Unchanged && !include_unchanged {
+            return None;
+        }
+
+        Some( SiteDiff { key, status, base, target, delta: delta( &base_usage, &target_usage ) } )
+    }).collect();
+
+    // The sort is stable and the pairs are already ordered by their keys.
+    output.sort_by_key( |site| Reverse( sort_key( &site.delta, sort_by ) ) );
+    output
+}
+
+struct Diff {
+    base_total: protocol::DiffUsage,
+    target_total: protocol::DiffUsage,
+    sites: Vec< SiteDiff< SiteId > >
+}
+
+impl SiteDiff< SiteId > {
+    fn backtrace< 'a >( &self, base: &'a Data, target: &'a Data ) -> (&'a Data, BacktraceId) {
+        site_backtrace( base, self.base.map( |(backtrace_id, _)| backtrace_id ), target, self.target.map( |(backtrace_id, _)| backtrace_id ) )
+    }
+}
+
+fn diff(
+    base: &Data,
+    base_filter: impl Fn( &Allocation ) -> bool,
+    target: &Data,
+    target_filter: impl Fn( &Allocation ) -> bool,
+    sort_by: protocol::DiffSortBy,
+    include_unchanged: bool
+) -> Diff {
+    let base = usage_by_site( base, base_filter );
+    let target = usage_by_site( target, target_filter );
+    Diff {
+        base_total: base.total,
+        target_total: target.total,
+        sites: compare( &base.sites, &target.sites, sort_by, include_unchanged )
+    }
+}
+
+fn to_response< 'a >(
+    base: &'a Data,
+    target: &'a Data,
+    result: &Diff,
+    backtrace_format: &protocol::BacktraceFormat,
+    skip: usize,
+    count: usize
+) -> protocol::ResponseDiff< 'a > {
+    let sites = result.sites.iter().skip( skip ).take( count ).map( |site| {
+        let (data, backtrace_id) = site.backtrace( base, target );
+        protocol::DiffSite {
+            site_id: site.key.to_string(),
+            status: site.status,
+            base_backtrace_id: site.base.map( |(backtrace_id, _)| backtrace_id.raw() ),
+            target_backtrace_id: site.target.map( |(backtrace_id, _)| backtrace_id.raw() ),
+            backtrace: data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, backtrace_format, frame ) ).collect(),
+            base: site.base.map( |(_, usage)| usage ).unwrap_or_default(),
+            target: site.target.map( |(_, usage)| usage ).unwrap_or_default(),
+            delta: site.delta
+        }
+    }).collect();
+
+    protocol::ResponseDiff {
+        base: format!( "{}", base.id() ),
+        target: format!( "{}", target.id() ),
+        delta: delta( &result.base_total, &result.target_total ),
+        base_total: result.base_total,
+        target_total: result.target_total,
+        sites,
+        total_count: result.sites.len() as u64
+    }
+}
+
+/// Compares the allocations of two captures site by site.
+pub fn get_diff< 'a >(
+    base: &'a Data,
+    base_filter: impl Fn( &Allocation ) -> bool,
+    target: &'a Data,
+    target_filter: impl Fn( &Allocation ) -> bool,
+    backtrace_format: &protocol::BacktraceFormat,
+    params: &protocol::RequestDiff
+) -> protocol::ResponseDiff< 'a > {
+    let sort_by = params.sort_by.unwrap_or( protocol::DiffSortBy::LeakedSize );
+    let result = diff( base, base_filter, target, target_filter, sort_by, params.include_unchanged.unwrap_or( false ) );
+    to_response( base, target, &result, backtrace_format, params.skip.unwrap_or( 0 ) as usize, params.count.unwrap_or( 50 ) as usize )
+}
+
+pub struct DiffOptions {
+    /// How many of the sites which have changed the most are printed out.
+    pub count: usize,
+    /// By which of the deltas the sites are sorted.
+    pub sort_by: protocol::DiffSortBy,
+    /// Whether the sites which haven't changed at all are also listed.
+    pub include_unchanged: bool,
+    /// Where to write the whole comparison as JSON.
+    pub json_output: Option< PathBuf >
+}
+
+fn format_signed_size( value: i64 ) -> String {
+    let sign = if value < 0 { "-" } else { "+" };
+    format!( "{}{}", sign, format_size( value.abs() as u64 ) )
+}
+
+fn format_signed_count( value: i64 ) -> String {
+    if value < 0 { value.to_string() } else { format!( "+{}", value ) }
+}
+
+fn status_name( status: protocol::DiffStatus ) -> &'static str {
+    match status {
+        protocol::DiffStatus::New => "new",
+        protocol::DiffStatus::Removed => "removed",
+        protocol::DiffStatus::Changed => "changed",
+        protocol::DiffStatus::Unchanged => "unchanged"
+    }
+}
+
+/// Compares two data files and prints out the sites which have changed the most.
+pub fn diff_main(
+    base: PathBuf,
+    target: PathBuf,
+    symbol_sources: SymbolSources,
+    frame_rules: Option< PathBuf >,
+    attribution: Attribution,
+    options: DiffOptions
+) -> Result< (), Box< dyn Error > > {
+    let load = |path: &PathBuf| {
+        FilteredData::load( path, &symbol_sources, frame_rules.as_deref(), &attribution, None, None ).map( |filtered| filtered.data )
+    };
+
+    let base = load( &base )?;
+    let target = load( &target )?;
+
+    let result = diff( &base, |_| true, &target, |_| true, options.sort_by, options.include_unchanged );
+    let mut table = vec![ vec![
+        "Status".to_owned(),
+        "Site".to_owned(),
+        "Leaked".to_owned(),
+        "Leaked (count)".to_owned(),
+        "Allocated".to_owned(),
+        "Allocated (count)".to_owned(),
+        "Freed".to_owned(),
+        "Freed (count)".to_owned()
+    ]];
+
+    let row = |status: &str, name: String, delta: &protocol::DiffDelta| vec![
+        status.to_owned(),
+        name,
+        format_signed_size( delta.leaked_size ),
+        format_signed_count( delta.leaked_count ),
+        format_signed_size( delta.allocated_size ),
+        format_signed_count( delta.allocated_count ),
+        format_signed_size( delta.freed_size ),
+        format_signed_count( delta.freed_count )
+    ];
+
+    table.push( row( "", "<total>".to_owned(), &delta( &result.base_total, &result.target_total ) ) );
+    for site in result.sites.iter().take( options.count ) {
+        let (data, backtrace_id) = site.backtrace( &base, &target );
+        let name = site_name( data, backtrace_id );
+        table.push( row( status_name( site.status ), name, &site.delta ) );
+    }
+
+    print!( "{}", table_to_string( &table ) );
+    if result.sites.len() > options.count {
+        println!( "... and {} more sites", result.sites.len() - options.count );
+    }
+
+    if let Some( path ) = options.json_output {
+        let backtrace_format = protocol::BacktraceFormat { strip_template_args: None };
+        let response = to_response( &base, &target, &result, &backtrace_format, 0, usize::MAX );
+        fs::write( path, serde_json::to_string_pretty( &response )? )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn usage( allocated_count: u64, allocated_size: u64, leaked_count: u64, leaked_size: u64 ) -> protocol::DiffUsage {
+    protocol::DiffUsage {
+        allocated_count,
+        allocated_size,
+        freed_count: allocated_count - leaked_count,
+        freed_size: allocated_size - leaked_size,
+        leaked_count,
+        leaked_size
+    }
+}
+
+/// Loads a capture with two sites: `main`, which allocates 32 bytes, and `malloc` called from `main`, which allocates 16 bytes.
+#[cfg(test)]
+fn test_data( allocations: &str ) -> Data {
+    let input = format!( "v 10100 2\nX ./a.out\ns libc.so.6\ns malloc\ns main\ni 1000 1 2\ni 2000 1 3\nt 2 0\nt 1 1\na 10 2\na 20 1\n{}", allocations );
+    let mut output = Vec::new();
+    cli_core::import_heaptrack( input.as_bytes(), &mut output, cli_core::Timestamp::from_secs( 100 ) ).unwrap();
+    cli_core::Loader::load_from_stream_without_debug_info( std::io::Cursor::new( output ) ).unwrap()
+}
+
+/// Two allocations from `malloc`, one of which is freed, and one from `main`.
+#[cfg(test)]
+const BASE_ALLOCATIONS: &str = "+ 0\n+ 1\n+ 0\n- 0\n";
+
+/// Two allocations from `malloc`, neither of which is freed.
+#[cfg(test)]
+const TARGET_ALLOCATIONS: &str = "+ 0\n+ 0\n";
+
+#[test]
+fn test_compare() {
+    let sites = |usages: Vec< (u32, protocol::DiffUsage) >| -> HashMap< u32, _ > {
+        usages.into_iter().map( |(key, usage)| (key, (BacktraceId::new( key ), usage)) ).collect()
+    };
+
+    let base = sites( vec![
+        (1, usage( 10, 1000, 1, 100 )),
+        (2, usage( 5, 500, 0, 0 )),
+        (3, usage( 1, 10, 1, 10 ))
+    ]);
+
+    let target = sites( vec![
+        (1, usage( 20, 2000, 5, 500 )),
+        (2, usage( 5, 500, 0, 0 )),
+        (4, usage( 1, 64, 1, 64 ))
+    ]);
+
+    let result = compare( &base, &target, protocol::DiffSortBy::LeakedSize, false );
+    let summary: Vec< _ > = result.iter().map( |site| (site.key, site.status, site.delta.leaked_size) ).collect();
+    assert_eq!( summary, vec![
+        (1, protocol::DiffStatus::Changed, 400),
+        (4, protocol::DiffStatus::New, 64),
+        (3, protocol::DiffStatus::Removed, -10)
+    ]);
+
+    let result = compare( &base, &target, protocol::DiffSortBy::AllocatedCount, true );
+    let keys: Vec< _ > = result.iter().map( |site| (site.key, site.status) ).collect();
+    assert_eq!( keys, vec![
+        (1, protocol::DiffStatus::Changed),
+        (3, protocol::DiffStatus::Removed),
+        (4, protocol::DiffStatus::New),
+        (2, protocol::DiffStatus::Unchanged)
+    ]);
+
+    assert_eq!( result[ 1 ].base.map( |(backtrace_id, _)| backtrace_id ), Some( BacktraceId::new( 3 ) ) );
+    assert_eq!( result[ 1 ].target, None );
+}
+
+#[test]
+fn test_usage_by_site() {
+    let data = test_data( BASE_ALLOCATIONS );
+    let result = usage_by_site( &data, |_| true );
+    assert_eq!( result.total, usage( 3, 64, 2, 48 ) );
+
+    let mut sites: Vec< _ > = result.sites.values().map( |&(_, usage)| usage ).collect();
+    sites.sort_by_key( |usage| usage.leaked_size );
+    assert_eq!( sites, vec![ usage( 2, 32, 1, 16 ), usage( 1, 32, 1, 32 ) ] );
+
+    let result = usage_by_site( &data, |allocation| allocation.size == 16 );
+    assert_eq!( result.total, usage( 2, 32, 1, 16 ) );
+    assert_eq!( result.sites.len(), 1 );
+}
+
+#[test]
+fn test_get_diff() {
+    let base = test_data( BASE_ALLOCATIONS );
+    let target = test_data( TARGET_ALLOCATIONS );
+    let backtrace_format = protocol::BacktraceFormat { strip_template_args: None };
+    let params = |skip, count| protocol::RequestDiff { skip, count, sort_by: None, include_unchanged: None };
+
+    let response = get_diff( &base, |_| true, &target, |_| true, &backtrace_format, &params( None, None ) );
+    assert_eq!( response.base, base.id().to_string() );
+    assert_eq!( response.target, target.id().to_string() );
+    assert_eq!( response.base_total, usage( 3, 64, 2, 48 ) );
+    assert_eq!( response.target_total, usage( 2, 32, 2, 32 ) );
+    assert_eq!( response.delta.leaked_size, -16 );
+    assert_eq!( response.total_count, 2 );
+
+    // The site from `main` is gone, and the one from `malloc` now leaks 16 more bytes.
+    let summary: Vec< _ > = response.sites.iter().map( |site| (site.status, site.delta.leaked_size) ).collect();
+    assert_eq!( summary, vec![
+        (protocol::DiffStatus::Removed, -32),
+        (protocol::DiffStatus::Changed, 16)
+    ]);
+
+    assert!( response.sites[ 0 ].base_backtrace_id.is_some() );
+    assert_eq!( response.sites[ 0 ].target_backtrace_id, None );
+    assert_eq!( response.sites[ 0 ].target, protocol::DiffUsage::default() );
+    assert!( !response.sites[ 0 ].backtrace.is_empty() );
+    assert!( response.sites[ 1 ].base_backtrace_id.is_some() && response.sites[ 1 ].target_backtrace_id.is_some() );
+
+    let response = get_diff( &base, |_| true, &target, |_| true, &backtrace_format, &params( Some( 1 ), Some( 1 ) ) );
+    assert_eq!( response.total_count, 2 );
+    assert_eq!( response.sites.len(), 1 );
+    assert_eq!( response.sites[ 0 ].status, protocol::DiffStatus::Changed );
+
+    let response = get_diff( &base, |allocation| allocation.size == 16, &target, |_| true, &backtrace_format, &params( None, None ) );
+    assert_eq!( response.total_count, 1 );
+    assert_eq!( response.base_total, usage( 2, 32, 1, 16 ) );
+}
+
+#[test]
+fn test_handler_diff_allocations() {
+    use std::sync::Arc;
+    use actix_web::body::{Body, ResponseBody};
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    let base = test_data( BASE_ALLOCATIONS );
+    let target = test_data( TARGET_ALLOCATIONS );
+
+    // The path parameters of a test request have to be static.
+    let base_id: &'static str = Box::leak( base.id().to_string().into_boxed_str() );
+    let mut state = crate::State::new( crate::QueryLimits::default(), None );
+    state.add_data( base );
+    state.add_data( target );
+    let state: crate::StateRef = Arc::new( state );
+
+    let request = |target_id: &'static str, query: &str| {
+        TestRequest::with_uri( &format!( "/?{}", query ) )
+            .data( state.clone() )
+            .param( "id", base_id )
+            .param( "target_id", target_id )
+            .to_http_request()
+    };
+
+    let response = crate::handler_diff_allocations( request( "last", "size_max=16" ) ).unwrap();
+    assert_eq!( response.status(), StatusCode::OK );
+    let body = match response.body() {
+        ResponseBody::Body( Body::Bytes( bytes ) ) => bytes.clone(),
+        _ => panic!( "unexpected body" )
+    };
+
+    let body: serde_json::Value = serde_json::from_slice( &body ).unwrap();
+    assert_eq!( body[ "total_count" ], 1 );
+    assert_eq!( body[ "base_total" ][ "allocated_size" ], 32 );
+    assert_eq!( body[ "sites" ][ 0 ][ "status" ], "changed" );
+
+    let error = crate::handler_diff_allocations( request( "0123456789abcdef0123456789abcdef", "" ) ).err().unwrap();
+    assert_eq!( error.as_response_error().error_response().status(), StatusCode::NOT_FOUND );
+}
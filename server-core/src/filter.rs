@@ -1,16 +1,26 @@
+use std::cmp::{min, max};
+
 use ahash::AHashMap as HashMap;
 use ahash::AHashSet as HashSet;
 
-use regex::{self, Regex};
+use regex::{self, Regex, RegexBuilder};
 
 use cli_core::{
     Allocation,
+    AllocationColumns,
+    AllocationId,
     BacktraceId,
+    ColumnFilter,
     Data,
-    Timestamp
+    Expression,
+    ExpressionError,
+    Selection,
+    Timestamp,
+    VirtualColumns
 };
 
 use crate::protocol;
+use crate::site_classification::classify_site;
 
 #[derive(Clone, Debug)]
 pub struct GroupFilter {
@@ -19,7 +29,11 @@ pub struct GroupFilter {
     pub leaked_allocations_min: Option< protocol::NumberOrPercentage >,
     pub leaked_allocations_max: Option< protocol::NumberOrPercentage >,
     pub allocations_min: usize,
-    pub allocations_max: usize
+    pub allocations_max: usize,
+    pub first_seen_min: Option< Timestamp >,
+    pub first_seen_max: Option< Timestamp >,
+    pub last_seen_min: Option< Timestamp >,
+    pub last_seen_max: Option< Timestamp >
 }
 
 #[derive(Clone, Debug)]
@@ -28,6 +42,7 @@ pub struct Filter {
     pub timestamp_start: Timestamp,
     pub timestamp_end_specified: bool,
     pub timestamp_end: Timestamp,
+    pub live_at: Option< Timestamp >,
     pub address_min: u64,
     pub address_max: u64,
     pub size_min_specified: bool,
@@ -43,7 +58,9 @@ pub struct Filter {
     pub arena: Option< protocol::ArenaFilter >,
     pub matched_backtraces: Option< HashSet< BacktraceId > >,
     pub marker: Option< u32 >,
-    pub group_filter: Option< GroupFilter >
+    pub group_filter: Option< GroupFilter >,
+    pub columns: VirtualColumns,
+    pub expression: Option< Expression >
 }
 
 #[derive(Clone, Debug)]
@@ -91,10 +108,13 @@ impl Filter {
 }
 
 pub enum PrepareFilterError {
-    InvalidRegex( &'static str, regex::Error )
+    InvalidRegex( &'static str, regex::Error ),
+    InvalidExpression( &'static str, ExpressionError ),
+    LiveAtBeforeStart,
+    LiveAtBeforeEnd
 }
 
-pub fn prepare_filter( data: &Data, filter: &protocol::AllocFilter ) -> Result< Filter, PrepareFilterError > {
+pub fn prepare_filter( data: &Data, filter: &protocol::AllocFilter, regex_size_limit: Option< usize > ) -> Result< Filter, PrepareFilterError > {
     let matched_backtraces_1;
     let matched_backtraces_2;
 
@@ -106,7 +126,7 @@ pub fn prepare_filter( data: &Data, filter: &protocol::AllocFilter ) -> Result<
             source_regex: filter.source_regex.clone(),
             negative_function_regex: filter.negative_function_regex.clone(),
             negative_source_regex: filter.negative_source_regex.clone()
-        })?;
+        }, regex_size_limit )?;
 
         let mut matched_backtraces = HashSet::new();
         let mut positive_cache = HashMap::new();
@@ -137,8 +157,19 @@ pub fn prepare_filter( data: &Data, filter: &protocol::AllocFilter ) -> Result<
         (Some( left ), Some( right )) => Some( left.intersection( &right ).cloned().collect() )
     };
 
+    let matched_backtraces = if let Some( classification ) = filter.group_classification {
+        let candidates = matched_backtraces.unwrap_or_else( || data.all_backtraces().map( |(backtrace_id, _)| backtrace_id ).collect() );
+        Some( candidates.into_iter().filter( |&backtrace_id| classify_site( data, backtrace_id ) == classification ).collect() )
+    } else {
+        matched_backtraces
+    };
+
     let group_interval_min = filter.group_interval_min.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) );
     let group_interval_max = filter.group_interval_max.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) );
+    let group_first_seen_min = filter.group_first_seen_min.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) );
+    let group_first_seen_max = filter.group_first_seen_max.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) );
+    let group_last_seen_min = filter.group_last_seen_min.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) );
+    let group_last_seen_max = filter.group_last_seen_max.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) );
 
     let has_group_filter =
         group_interval_min.is_some() ||
@@ -146,7 +177,11 @@ pub fn prepare_filter( data: &Data, filter: &protocol::AllocFilter ) -> Result<
         filter.group_leaked_allocations_min.is_some() ||
         filter.group_leaked_allocations_max.is_some() ||
         filter.group_allocations_min.is_some() ||
-        filter.group_allocations_max.is_some();
+        filter.group_allocations_max.is_some() ||
+        group_first_seen_min.is_some() ||
+        group_first_seen_max.is_some() ||
+        group_last_seen_min.is_some() ||
+        group_last_seen_max.is_some();
 
     let group_filter = if has_group_filter {
         let group_filter = GroupFilter {
@@ -156,17 +191,48 @@ pub fn prepare_filter( data: &Data, filter: &protocol::AllocFilter ) -> Result<
             leaked_allocations_max: filter.group_leaked_allocations_max,
             allocations_min: filter.group_allocations_min.map( |value| value as usize ).unwrap_or( 0 ),
             allocations_max: filter.group_allocations_max.map( |value| value as usize ).unwrap_or( -1_i32 as _ ),
+            first_seen_min: group_first_seen_min,
+            first_seen_max: group_first_seen_max,
+            last_seen_min: group_last_seen_min,
+            last_seen_max: group_last_seen_max
         };
         Some( group_filter )
     } else {
         None
     };
 
+    let timestamp_start = filter.from.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) );
+    let timestamp_end = filter.to.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) );
+    let live_at = filter.live_at.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) );
+    if let (Some( timestamp_start ), Some( live_at )) = (timestamp_start, live_at) {
+        if live_at < timestamp_start {
+            return Err( PrepareFilterError::LiveAtBeforeStart );
+        }
+    }
+
+    // The window has to be over before the point at which the allocations are supposed to still be alive.
+    if let (Some( timestamp_end ), Some( live_at )) = (timestamp_end, live_at) {
+        if live_at < timestamp_end {
+            return Err( PrepareFilterError::LiveAtBeforeEnd );
+        }
+    }
+
+    let columns = match filter.columns {
+        Some( ref columns ) => VirtualColumns::parse( columns, regex_size_limit ).map_err( |err| PrepareFilterError::InvalidExpression( "columns", err ) )?,
+        None => VirtualColumns::default()
+    };
+
+    let expression = match filter.expression {
+        Some( ref expression ) => Some( Expression::parse( expression, &columns, regex_size_limit ).map_err( |err| PrepareFilterError::InvalidExpression( "expression", err ) )? ),
+        None => None
+    };
+
     let filter = Filter {
         timestamp_start_specified: filter.from.is_some(),
-        timestamp_start: filter.from.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) ).unwrap_or( Timestamp::min() ),
+        timestamp_start: timestamp_start.unwrap_or( Timestamp::min() ),
         timestamp_end_specified: filter.to.is_some(),
-        timestamp_end: filter.to.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) ).unwrap_or( Timestamp::max() ),
+        timestamp_end: timestamp_end.unwrap_or( Timestamp::max() ),
+        live_at,
         address_min: filter.address_min.unwrap_or( 0 ),
         address_max: filter.address_max.unwrap_or( -1_i32 as _ ),
         size_min_specified: filter.size_min.is_some(),
@@ -182,33 +248,45 @@ pub fn prepare_filter( data: &Data, filter: &protocol::AllocFilter ) -> Result<
         arena: filter.arena,
         matched_backtraces,
         marker: filter.marker,
-        group_filter
+        group_filter,
+        columns,
+        expression
     };
 
     Ok( filter )
 }
 
-pub fn prepare_backtrace_filter( filter: &protocol::BacktraceFilter ) -> Result< BacktraceFilter, PrepareFilterError > {
+fn compile_regex( field: &'static str, pattern: &str, size_limit: Option< usize > ) -> Result< Regex, PrepareFilterError > {
+    let mut builder = RegexBuilder::new( pattern );
+    if let Some( size_limit ) = size_limit {
+        builder.size_limit( size_limit );
+        builder.dfa_size_limit( size_limit );
+    }
+
+    builder.build().map_err( |err| PrepareFilterError::InvalidRegex( field, err ) )
+}
+
+pub fn prepare_backtrace_filter( filter: &protocol::BacktraceFilter, regex_size_limit: Option< usize > ) -> Result< BacktraceFilter, PrepareFilterError > {
     let function_regex = if let Some( ref pattern ) = filter.function_regex {
-        Some( Regex::new( pattern ).map_err( |err| PrepareFilterError::InvalidRegex( "function_regex", err ) )? )
+        Some( compile_regex( "function_regex", pattern, regex_size_limit )? )
     } else {
         None
     };
 
     let source_regex = if let Some( ref pattern ) = filter.source_regex {
-        Some( Regex::new( pattern ).map_err( |err| PrepareFilterError::InvalidRegex( "source_regex", err ) )? )
+        Some( compile_regex( "source_regex", pattern, regex_size_limit )? )
     } else {
         None
     };
 
     let negative_function_regex = if let Some( ref pattern ) = filter.negative_function_regex {
-        Some( Regex::new( pattern ).map_err( |err| PrepareFilterError::InvalidRegex( "negative_function_regex", err ) )? )
+        Some( compile_regex( "negative_function_regex", pattern, regex_size_limit )? )
     } else {
         None
     };
 
     let negative_source_regex = if let Some( ref pattern ) = filter.negative_source_regex {
-        Some( Regex::new( pattern ).map_err( |err| PrepareFilterError::InvalidRegex( "negative_source_regex", err ) )? )
+        Some( compile_regex( "negative_source_regex", pattern, regex_size_limit )? )
     } else {
         None
     };
@@ -331,16 +409,100 @@ pub fn match_backtrace< 'a >(
     positive_matched && !negative_matched
 }
 
+/// Checks the parts of the filter which depend only on the allocation's backtrace.
+fn match_group( data: &Data, backtrace_id: BacktraceId, filter: &Filter ) -> bool {
+    let backtrace = data.get_backtrace( backtrace_id );
+    if backtrace.len() < filter.backtrace_depth_min || backtrace.len() > filter.backtrace_depth_max {
+        return false;
+    }
+
+    if let Some( ref matched_backtraces ) = filter.matched_backtraces {
+        if !matched_backtraces.contains( &backtrace_id ) {
+            return false;
+        }
+    }
+
+    if filter.lifetime == protocol::LifetimeFilter::OnlyWholeGroupLeaked {
+        let stats = data.get_group_statistics( backtrace_id );
+        if stats.free_count != 0 {
+            return false;
+        }
+    }
+
+    if let Some( ref group_filter ) = filter.group_filter {
+        let group_allocations = data.get_allocation_ids_by_backtrace( backtrace_id );
+        if group_allocations.len() < group_filter.allocations_min {
+            return false;
+        }
+
+        if group_allocations.len() > group_filter.allocations_max {
+            return false;
+        }
+
+        let first_timestamp = data.get_allocation( *group_allocations.first().unwrap() ).timestamp;
+        let last_timestamp = data.get_allocation( *group_allocations.last().unwrap() ).timestamp;
+        let interval = last_timestamp - first_timestamp;
+
+        if interval < group_filter.interval_min.unwrap_or( Timestamp::min() ) {
+            return false;
+        }
+
+        if interval > group_filter.interval_max.unwrap_or( Timestamp::max() ) {
+            return false;
+        }
+
+        if first_timestamp < group_filter.first_seen_min.unwrap_or( Timestamp::min() ) ||
+           first_timestamp > group_filter.first_seen_max.unwrap_or( Timestamp::max() ) {
+            return false;
+        }
+
+        if last_timestamp < group_filter.last_seen_min.unwrap_or( Timestamp::min() ) ||
+           last_timestamp > group_filter.last_seen_max.unwrap_or( Timestamp::max() ) {
+            return false;
+        }
+
+        let stats = data.get_group_statistics( backtrace_id );
+        let total_allocations = stats.alloc_count as u32;
+        let leaked = (stats.alloc_count - stats.free_count) as u32;
+
+        let leaked_min = group_filter.leaked_allocations_min.map( |threshold| threshold.get( total_allocations ) ).unwrap_or( 0 );
+        let leaked_max = group_filter.leaked_allocations_max.map( |threshold| threshold.get( total_allocations ) ).unwrap_or( -1_i32 as _ );
+
+        if leaked < leaked_min {
+            return false;
+        }
+
+        if leaked > leaked_max {
+            return false;
+        }
+    }
+
+    true
+}
+
 #[inline]
 pub fn match_allocation( data: &Data, allocation: &Allocation, filter: &Filter ) -> bool {
+    if !match_allocation_without_expression( data, allocation, filter ) {
+        return false;
+    }
+
+    match filter.expression {
+        Some( ref expression ) => expression.evaluate( data, allocation, &filter.columns.evaluate( data, allocation ) ).is_truthy(),
+        None => true
+    }
+}
+
+/// Same as `match_allocation`, except the filter's expression isn't checked; this is for the callers
+/// which need the values of the virtual columns themselves, so that they're only evaluated once.
+pub fn match_allocation_without_expression( data: &Data, allocation: &Allocation, filter: &Filter ) -> bool {
+    crate::query_limits::check_deadline();
+
     let timestamp_start = filter.timestamp_start;
     let timestamp_end = filter.timestamp_end;
     let size_min = filter.size_min;
     let size_max = filter.size_max;
     let lifetime_min = filter.lifetime_min;
     let lifetime_max = filter.lifetime_max;
-    let backtrace_depth_min = filter.backtrace_depth_min;
-    let backtrace_depth_max = filter.backtrace_depth_max;
 
     if allocation.pointer < filter.address_min {
         return false;
@@ -362,6 +524,18 @@ pub fn match_allocation( data: &Data, allocation: &Allocation, filter: &Filter )
         return false;
     }
 
+    if let Some( live_at ) = filter.live_at {
+        if allocation.timestamp > live_at {
+            return false;
+        }
+
+        if let Some( ref deallocation ) = allocation.deallocation {
+            if deallocation.timestamp <= live_at {
+                return false;
+            }
+        }
+    }
+
     match filter.lifetime {
         protocol::LifetimeFilter::All => {},
         protocol::LifetimeFilter::OnlyLeaked => {
@@ -394,16 +568,10 @@ pub fn match_allocation( data: &Data, allocation: &Allocation, filter: &Filter )
             if allocation.deallocation.is_some() {
                 return false;
             }
-
-            let stats = data.get_group_statistics( allocation.backtrace );
-            if stats.free_count != 0 {
-                return false;
-            }
         }
     }
 
-    let backtrace = data.get_backtrace( allocation.backtrace );
-    if backtrace.len() < backtrace_depth_min || backtrace.len() > backtrace_depth_max {
+    if !match_group( data, allocation.backtrace, filter ) {
         return false;
     }
 
@@ -450,49 +618,115 @@ pub fn match_allocation( data: &Data, allocation: &Allocation, filter: &Filter )
         }
     }
 
-    if let Some( ref matched_backtraces ) = filter.matched_backtraces {
-        if !matched_backtraces.contains( &allocation.backtrace ) {
-            return false;
-        }
+    true
+}
+
+/// Translates as much of the filter as possible into predicates over the allocation columns.
+fn to_column_filter( data: &Data, filter: &Filter ) -> ColumnFilter {
+    let mut timestamp = (filter.timestamp_start.as_usecs(), filter.timestamp_end.as_usecs());
+    let mut deallocation_timestamp = (0, u64::MAX);
+    if let Some( live_at ) = filter.live_at {
+        timestamp.1 = min( timestamp.1, live_at.as_usecs() );
+        deallocation_timestamp.0 = live_at.as_usecs().saturating_add( 1 );
     }
 
-    if let Some( ref group_filter ) = filter.group_filter {
-        let group_allocations = data.get_allocation_ids_by_backtrace( allocation.backtrace );
-        if group_allocations.len() < group_filter.allocations_min {
-            return false;
+    // The leaked allocations have their deallocation timestamp set to `u64::MAX`.
+    match filter.lifetime {
+        protocol::LifetimeFilter::All => {},
+        protocol::LifetimeFilter::OnlyLeaked | protocol::LifetimeFilter::OnlyWholeGroupLeaked => {
+            deallocation_timestamp.0 = u64::MAX;
+        },
+        protocol::LifetimeFilter::OnlyNotDeallocatedInCurrentRange => {
+            deallocation_timestamp.0 = max( deallocation_timestamp.0, filter.timestamp_end.as_usecs().saturating_add( 1 ) );
+        },
+        protocol::LifetimeFilter::OnlyDeallocatedInCurrentRange => {
+            deallocation_timestamp.1 = min( filter.timestamp_end.as_usecs(), u64::MAX - 1 );
+        },
+        protocol::LifetimeFilter::OnlyTemporary => {
+            deallocation_timestamp.1 = u64::MAX - 1;
         }
+    }
 
-        if group_allocations.len() > group_filter.allocations_max {
-            return false;
-        }
+    if filter.lifetime_max.is_some() {
+        deallocation_timestamp.1 = min( deallocation_timestamp.1, u64::MAX - 1 );
+    }
 
-        let first_timestamp = data.get_allocation( *group_allocations.first().unwrap() ).timestamp;
-        let last_timestamp = data.get_allocation( *group_allocations.last().unwrap() ).timestamp;
-        let interval = last_timestamp - first_timestamp;
+    let backtraces: Vec< bool > = (0..data.unique_backtrace_count())
+        .map( |index| match_group( data, BacktraceId::new( index as u32 ), filter ) )
+        .collect();
 
-        if interval < group_filter.interval_min.unwrap_or( Timestamp::min() ) {
-            return false;
-        }
+    ColumnFilter {
+        pointer: (filter.address_min, filter.address_max),
+        size: (filter.size_min, filter.size_max),
+        timestamp,
+        deallocation_timestamp,
+        lifetime: (filter.lifetime_min.0.as_usecs(), filter.lifetime_max.map( |lifetime| lifetime.0.as_usecs() ).unwrap_or( u64::MAX )),
+        marker: filter.marker,
+        backtraces: if backtraces.iter().all( |&is_matched| is_matched ) { None } else { Some( backtraces ) }
+    }
+}
 
-        if interval > group_filter.interval_max.unwrap_or( Timestamp::max() ) {
-            return false;
-        }
+/// Returns every allocation matched by the filter; this is equivalent to calling `match_allocation`
+/// for every allocation, but only the predicates which can't be checked on the columns are checked that way.
+pub fn select_allocations( data: &Data, columns: &AllocationColumns, filter: &Filter ) -> Selection {
+    select_allocations_impl( data, columns, filter, true )
+}
 
-        let stats = data.get_group_statistics( allocation.backtrace );
-        let total_allocations = stats.alloc_count as u32;
-        let leaked = (stats.alloc_count - stats.free_count) as u32;
+fn select_allocations_impl( data: &Data, columns: &AllocationColumns, filter: &Filter, with_expression: bool ) -> Selection {
+    let mut selection = columns.select( &to_column_filter( data, filter ) );
+    crate::query_limits::charge_memory( columns.len() / 8 );
+    let with_expression = with_expression && filter.expression.is_some();
+    if filter.mmaped.is_some() || filter.arena.is_some() || with_expression {
+        selection.retain( |id| {
+            let allocation = data.get_allocation( id );
+            if with_expression {
+                match_allocation( data, allocation, filter )
+            } else {
+                match_allocation_without_expression( data, allocation, filter )
+            }
+        });
+    }
 
-        let leaked_min = group_filter.leaked_allocations_min.map( |threshold| threshold.get( total_allocations ) ).unwrap_or( 0 );
-        let leaked_max = group_filter.leaked_allocations_max.map( |threshold| threshold.get( total_allocations ) ).unwrap_or( -1_i32 as _ );
+    selection
+}
 
-        if leaked < leaked_min {
-            return false;
-        }
+/// Checks which allocations are matched by a filter for the views which go over them in their own order;
+/// when the columns are available the filter is applied to all of the allocations up front.
+pub struct AllocationMatcher< 'a > {
+    data: &'a Data,
+    filter: &'a Filter,
+    selection: Option< Selection >,
+    with_expression: bool
+}
 
-        if leaked > leaked_max {
-            return false;
+impl< 'a > AllocationMatcher< 'a > {
+    pub fn new( data: &'a Data, columns: Option< &AllocationColumns >, filter: &'a Filter ) -> Self {
+        AllocationMatcher::new_impl( data, columns, filter, true )
+    }
+
+    /// Same as `new`, except the filter's expression isn't checked; see `match_allocation_without_expression`.
+    pub fn without_expression( data: &'a Data, columns: Option< &AllocationColumns >, filter: &'a Filter ) -> Self {
+        AllocationMatcher::new_impl( data, columns, filter, false )
+    }
+
+    fn new_impl( data: &'a Data, columns: Option< &AllocationColumns >, filter: &'a Filter, with_expression: bool ) -> Self {
+        AllocationMatcher {
+            data,
+            filter,
+            selection: columns.map( |columns| select_allocations_impl( data, columns, filter, with_expression ) ),
+            with_expression
         }
     }
 
-    true
+    #[inline]
+    pub fn matches( &self, id: AllocationId, allocation: &Allocation ) -> bool {
+        match self.selection {
+            Some( ref selection ) => {
+                crate::query_limits::check_deadline();
+                selection.contains( id )
+            },
+            None if self.with_expression => match_allocation( self.data, allocation, self.filter ),
+            None => match_allocation_without_expression( self.data, allocation, self.filter )
+        }
+    }
 }
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use ahash::AHashMap as HashMap;
+
+use cli_core::{
+    Allocation,
+    Data,
+    Operation
+};
+
+use crate::protocol;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RegionKey {
+    MainArena,
+    NonMainArenaHeap( u64 )
+}
+
+#[derive(Default)]
+struct Region {
+    address_map: BTreeMap< u64, u64 >,
+    used: u64
+}
+
+impl Region {
+    fn add( &mut self, range: &Range< u64 > ) {
+        *self.address_map.entry( range.start ).or_insert( 0 ) += 1;
+        *self.address_map.entry( range.end ).or_insert( 0 ) += 1;
+        self.used += range.end - range.start;
+    }
+
+    fn remove( &mut self, range: &Range< u64 > ) {
+        for address in &[range.start, range.end] {
+            let count = self.address_map.get_mut( address ).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.address_map.remove( address );
+            }
+        }
+
+        self.used -= range.end - range.start;
+    }
+
+    fn span( &self ) -> u64 {
+        let min = self.address_map.keys().next();
+        let max = self.address_map.keys().next_back();
+        match (min, max) {
+            (Some( min ), Some( max )) => max - min,
+            _ => 0
+        }
+    }
+
+    fn is_empty( &self ) -> bool {
+        self.address_map.is_empty()
+    }
+}
+
+#[derive(Default, Copy, Clone)]
+struct Sample {
+    main_arena_fragmentation: u64,
+    non_main_arena_fragmentation: u64,
+    span: u64,
+    used: u64
+}
+
+struct State< 'a > {
+    data: &'a Data,
+    heap_size: u64,
+    regions: HashMap< RegionKey, Region >,
+    current: Sample
+}
+
+/// Returns the region of the address space in which a given non-`mmap`ed allocation lives.
+pub fn region_key( allocation: &Allocation, range: &Range< u64 >, heap_size: u64 ) -> RegionKey {
+    if allocation.in_main_arena() {
+        RegionKey::MainArena
+    } else {
+        // Non-main arenas allocate from heaps which are always aligned to their maximum size.
+        RegionKey::NonMainArenaHeap( range.start & !(heap_size - 1) )
+    }
+}
+
+impl< 'a > State< 'a > {
+    fn update< F >( &mut self, allocation: &Allocation, callback: F ) where F: FnOnce( &mut Region, &Range< u64 > ) {
+        if allocation.is_mmaped() {
+            return;
+        }
+
+        let range = allocation.actual_range( self.data );
+        let key = region_key( allocation, &range, self.heap_size );
+        let region = self.regions.entry( key ).or_insert_with( Region::default );
+
+        let fragmentation = match key {
+            RegionKey::MainArena => &mut self.current.main_arena_fragmentation,
+            RegionKey::NonMainArenaHeap( _ ) => &mut self.current.non_main_arena_fragmentation
+        };
+
+        *fragmentation -= region.span() - region.used;
+        self.current.span -= region.span();
+        self.current.used -= region.used;
+
+        callback( region, &range );
+
+        *fragmentation += region.span() - region.used;
+        self.current.span += region.span();
+        self.current.used += region.used;
+
+        if region.is_empty() {
+            self.regions.remove( &key );
+        }
+    }
+}
+
+/// Returns the maximum size of a single heap used by glibc's non-main arenas (`HEAP_MAX_SIZE`).
+pub fn non_main_arena_heap_size( data: &Data ) -> u64 {
+    if data.pointer_size() == 8 { 64 * 1024 * 1024 } else { 1024 * 1024 }
+}
+
+pub fn get_fragmentation_timeline( data: &Data ) -> protocol::ResponseFragmentationTimeline {
+    let maximum_len = (data.last_timestamp().as_secs() - data.initial_timestamp().as_secs()) as usize;
+    let mut xs = Vec::with_capacity( maximum_len );
+    let mut samples: Vec< Sample > = Vec::with_capacity( maximum_len );
+    let mut x = (-1_i32) as u64;
+
+    let mut state = State {
+        data,
+        heap_size: non_main_arena_heap_size( data ),
+        regions: HashMap::new(),
+        current: Sample::default()
+    };
+
+    for op in data.operations() {
+        let timestamp = match op {
+            Operation::Allocation { allocation, .. } => {
+                if allocation.is_mmaped() {
+                    continue;
+                }
+
+                state.update( allocation, |region, range| region.add( range ) );
+                allocation.timestamp
+            },
+            Operation::Deallocation { allocation, deallocation, .. } => {
+                if allocation.is_mmaped() {
+                    continue;
+                }
+
+                state.update( allocation, |region, range| region.remove( range ) );
+                deallocation.timestamp
+            },
+            Operation::Reallocation { new_allocation, old_allocation, .. } => {
+                if new_allocation.is_mmaped() && old_allocation.is_mmaped() {
+                    continue;
+                }
+
+                state.update( old_allocation, |region, range| region.remove( range ) );
+                state.update( new_allocation, |region, range| region.add( range ) );
+                new_allocation.timestamp
+            }
+        };
+
+        let timestamp = timestamp.as_secs();
+        if timestamp != x {
+            if x != (-1_i32 as u64) && x + 1 != timestamp {
+                let last_sample = samples.last().cloned().unwrap();
+
+                xs.push( x + 1 );
+                samples.push( last_sample );
+
+                if x + 2 != timestamp {
+                    xs.push( timestamp - 1 );
+                    samples.push( last_sample );
+                }
+            }
+
+            x = timestamp;
+            xs.push( x );
+            samples.push( Sample::default() );
+        }
+
+        *samples.last_mut().unwrap() = state.current;
+    }
+
+    protocol::ResponseFragmentationTimeline {
+        xs,
+        fragmentation: samples.iter().map( |sample| sample.main_arena_fragmentation + sample.non_main_arena_fragmentation ).collect(),
+        main_arena_fragmentation: samples.iter().map( |sample| sample.main_arena_fragmentation ).collect(),
+        non_main_arena_fragmentation: samples.iter().map( |sample| sample.non_main_arena_fragmentation ).collect(),
+        span: samples.iter().map( |sample| sample.span ).collect(),
+        used: samples.iter().map( |sample| sample.used ).collect()
+    }
+}
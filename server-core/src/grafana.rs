@@ -0,0 +1,175 @@
+use std::cmp::Reverse;
+use std::sync::Arc;
+
+use cli_core::{
+    BacktraceId,
+    Data
+};
+
+use crate::protocol;
+use crate::changes::site_name;
+
+const TIMELINE_METRICS: &[&str] = &[
+    "allocated_size",
+    "allocated_count",
+    "leaked_size",
+    "leaked_count",
+    "size_delta",
+    "count_delta"
+];
+
+const TOP_SITES: &str = "top_sites";
+const TOP_SITES_COUNT: usize = 20;
+
+fn executable_name( data: &Data ) -> &str {
+    data.executable().rsplit( '/' ).next().unwrap_or( data.executable() )
+}
+
+/// Lists the metrics which can be queried; their values are `<data id>/<metric>`.
+pub fn search< 'a >( datasets: impl Iterator< Item = &'a Data >, query: &str ) -> Vec< protocol::GrafanaSearchResult > {
+    let mut results = Vec::new();
+    for data in datasets {
+        for &metric in TIMELINE_METRICS.iter().chain( std::iter::once( &TOP_SITES ) ) {
+            let text = format!( "{} ({}): {}", executable_name( data ), data.id(), metric );
+            if !text.contains( query ) {
+                continue;
+            }
+
+            results.push( protocol::GrafanaSearchResult {
+                text,
+                value: format!( "{}/{}", data.id(), metric )
+            });
+        }
+    }
+
+    results
+}
+
+fn parse_time( value: &str ) -> Result< u64, String > {
+    chrono::DateTime::parse_from_rfc3339( value )
+        .map( |timestamp| timestamp.timestamp_millis().max( 0 ) as u64 )
+        .map_err( |error| format!( "invalid time '{}': {}", value, error ) )
+}
+
+/// Picks the highest value out of every run of consecutive points so that the spikes survive the downsampling.
+fn downsample( points: Vec< (f64, u64) >, max_points: usize ) -> Vec< (f64, u64) > {
+    if max_points == 0 || points.len() <= max_points {
+        return points;
+    }
+
+    let run_length = (points.len() + max_points - 1) / max_points;
+    points.chunks( run_length ).map( |run| {
+        run.iter().cloned().fold( run[ 0 ], |highest, point| if point.0 > highest.0 { point } else { highest } )
+    }).collect()
+}
+
+fn get_time_series( timeline: &protocol::ResponseTimeline, metric: &str, from: u64, to: u64, max_points: usize ) -> Vec< (f64, u64) > {
+    let points = timeline.xs.iter().enumerate().filter_map( |(index, &secs)| {
+        let timestamp = secs * 1000;
+        if timestamp < from || timestamp > to {
+            return None;
+        }
+
+        let value = match metric {
+            "allocated_size" => timeline.allocated_size[ index ] as f64,
+            "allocated_count" => timeline.allocated_count[ index ] as f64,
+            "leaked_size" => timeline.leaked_size[ index ] as f64,
+            "leaked_count" => timeline.leaked_count[ index ] as f64,
+            "size_delta" => timeline.size_delta[ index ] as f64,
+            "count_delta" => timeline.count_delta[ index ] as f64,
+            _ => unreachable!()
+        };
+
+        Some( (value, timestamp) )
+    }).collect();
+
+    downsample( points, max_points )
+}
+
+fn get_top_sites_table( data: &Data ) -> protocol::GrafanaQueryResult {
+    let mut backtrace_ids: Vec< BacktraceId > = (0..data.unique_backtrace_count())
+        .map( |index| BacktraceId::new( index as u32 ) )
+        .filter( |&backtrace_id| data.get_group_statistics( backtrace_id ).alloc_count > 0 )
+        .collect();
+
+    backtrace_ids.sort_by_key( |&backtrace_id| (Reverse( data.get_group_statistics( backtrace_id ).alloc_size ), backtrace_id) );
+    let rows = backtrace_ids.into_iter().take( TOP_SITES_COUNT ).map( |backtrace_id| {
+        let stats = data.get_group_statistics( backtrace_id );
+        vec![
+            serde_json::Value::from( site_name( data, backtrace_id ) ),
+            serde_json::Value::from( stats.alloc_count ),
+            serde_json::Value::from( stats.alloc_size ),
+            serde_json::Value::from( stats.free_count ),
+            serde_json::Value::from( stats.free_size ),
+            serde_json::Value::from( stats.alloc_size.saturating_sub( stats.free_size ) )
+        ]
+    }).collect();
+
+    let column = |text, kind| protocol::GrafanaColumn { text, kind };
+    protocol::GrafanaQueryResult::Table {
+        kind: "table",
+        columns: vec![
+            column( "Site", "string" ),
+            column( "Allocated count", "number" ),
+            column( "Allocated size", "number" ),
+            column( "Freed count", "number" ),
+            column( "Freed size", "number" ),
+            column( "Leaked size", "number" )
+        ],
+        rows
+    }
+}
+
+/// Answers a query in the format expected by Grafana's JSON data source; `timeline_of` should return
+/// the precomputed timeline of a given data file, since the same ones are polled over and over again.
+pub fn query< 'a >(
+    datasets: impl Iterator< Item = &'a Data > + Clone,
+    timeline_of: impl Fn( &Data ) -> Arc< protocol::ResponseTimeline >,
+    request: &protocol::RequestGrafanaQuery
+) -> Result< Vec< protocol::GrafanaQueryResult >, String > {
+    let from = parse_time( &request.range.from )?;
+    let to = parse_time( &request.range.to )?;
+    let max_points = request.max_data_points.unwrap_or( 0 ) as usize;
+
+    let mut results = Vec::new();
+    for target in &request.targets {
+        let mut components = target.target.splitn( 2, '/' );
+        let id = components.next().unwrap();
+        let metric = components.next().ok_or_else( || format!( "invalid target '{}'", target.target ) )?;
+        let data = datasets.clone().find( |data| data.id().to_string() == id ).ok_or_else( || format!( "data not found: '{}'", id ) )?;
+
+        if metric == TOP_SITES {
+            results.push( get_top_sites_table( data ) );
+        } else if TIMELINE_METRICS.contains( &metric ) {
+            results.push( protocol::GrafanaQueryResult::TimeSeries {
+                target: format!( "{}: {}", executable_name( data ), metric ),
+                datapoints: get_time_series( &timeline_of( data ), metric, from, to, max_points )
+            });
+        } else {
+            return Err( format!( "unknown metric '{}'", metric ) );
+        }
+    }
+
+    Ok( results )
+}
+
+#[test]
+fn test_downsample() {
+    let points: Vec< _ > = [ 1.0, 5.0, 2.0, 3.0, 1.0, 4.0, 0.0 ].iter().enumerate().map( |(index, &value)| (value, index as u64) ).collect();
+    assert_eq!( downsample( points.clone(), 10 ), points );
+    assert_eq!( downsample( points.clone(), 3 ), vec![ (5.0, 1), (4.0, 5), (0.0, 6) ] );
+    assert_eq!( parse_time( "2016-10-31T06:33:44.866Z" ), Ok( 1477895624866 ) );
+}
+
+#[test]
+fn test_get_time_series() {
+    use common::timeline::{Timeline, TimelineOperation};
+
+    let mut timeline = Timeline::default();
+    timeline.add( 10, TimelineOperation::Allocation { size: 100, is_leaked: true } );
+    timeline.add( 11, TimelineOperation::Allocation { size: 20, is_leaked: false } );
+    timeline.add( 12, TimelineOperation::Deallocation { size: 20 } );
+
+    assert_eq!( get_time_series( &timeline, "allocated_size", 0, u64::MAX, 0 ), vec![ (100.0, 10000), (120.0, 11000), (100.0, 12000) ] );
+    assert_eq!( get_time_series( &timeline, "leaked_count", 11000, 11000, 0 ), vec![ (1.0, 11000) ] );
+}
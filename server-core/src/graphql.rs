@@ -0,0 +1,317 @@
+use ahash::AHashMap as HashMap;
+use actix_web::error::Error as ActixWebError;
+use juniper::{graphql_object, EmptyMutation, EmptySubscription, FieldResult, GraphQLObject, RootNode};
+
+use cli_core::{
+    AllocationId,
+    BacktraceId,
+    Data,
+    DataId
+};
+
+use crate::protocol;
+use crate::filter::{Filter, prepare_filter, match_allocation};
+use crate::site_classification::classify_site;
+use crate::{StateRef, get_frame, get_timeline};
+
+pub struct Context {
+    state: StateRef
+}
+
+impl juniper::Context for Context {}
+
+impl Context {
+    pub(crate) fn new( state: StateRef ) -> Self {
+        Context { state }
+    }
+
+    fn data( &self, id: DataId ) -> &Data {
+        self.state.data.get( &id ).unwrap()
+    }
+
+    fn filter( &self, data: &Data, filter: Option< String > ) -> FieldResult< Filter > {
+        let filter: protocol::AllocFilter = serde_urlencoded::from_str( filter.as_ref().map( |filter| filter.as_str() ).unwrap_or( "" ) )?;
+        let filter = prepare_filter( data, &filter, self.state.limits.memory_budget ).map_err( |error| ActixWebError::from( error ).to_string() )?;
+        Ok( filter )
+    }
+}
+
+fn relative_secs( data: &Data, timestamp: cli_core::Timestamp ) -> f64 {
+    (timestamp - data.initial_timestamp()).as_secs_f64()
+}
+
+fn page< T >( items: impl Iterator< Item = T >, skip: Option< i32 >, count: Option< i32 > ) -> Vec< T > {
+    let skip = skip.unwrap_or( 0 ).max( 0 ) as usize;
+    let count = count.map( |count| count.max( 0 ) as usize ).unwrap_or( -1_i32 as _ );
+    items.skip( skip ).take( count ).collect()
+}
+
+fn backtrace( data: &Data, backtrace_id: BacktraceId, strip_template_args: Option< bool > ) -> Vec< Frame > {
+    let format = protocol::BacktraceFormat { strip_template_args };
+    data.get_backtrace( backtrace_id ).map( |(_, frame)| {
+        let frame = get_frame( data, &format, frame );
+        Frame {
+            address: frame.address_s,
+            library: frame.library.map( |library| library.to_owned() ),
+            function: frame.function.map( |function| function.into_owned() ),
+            source: frame.source.map( |source| source.to_owned() ),
+            line: frame.line.map( |line| line as i32 ),
+            is_inline: frame.is_inline
+        }
+    }).collect()
+}
+
+#[derive(GraphQLObject)]
+pub struct Frame {
+    address: String,
+    library: Option< String >,
+    function: Option< String >,
+    source: Option< String >,
+    line: Option< i32 >,
+    is_inline: bool
+}
+
+/// The values are bucketed by second.
+#[derive(GraphQLObject)]
+pub struct Timeline {
+    xs: Vec< f64 >,
+    allocated_size: Vec< f64 >,
+    allocated_count: Vec< f64 >,
+    leaked_size: Vec< f64 >,
+    leaked_count: Vec< f64 >,
+    allocations: Vec< f64 >,
+    deallocations: Vec< f64 >
+}
+
+pub struct Allocation {
+    data_id: DataId,
+    id: AllocationId
+}
+
+#[graphql_object(context = Context)]
+impl Allocation {
+    fn address( &self, context: &Context ) -> String {
+        format!( "{:016X}", context.data( self.data_id ).get_allocation( self.id ).pointer )
+    }
+
+    /// Seconds since the start of the profiling.
+    fn timestamp( &self, context: &Context ) -> f64 {
+        let data = context.data( self.data_id );
+        relative_secs( data, data.get_allocation( self.id ).timestamp )
+    }
+
+    fn size( &self, context: &Context ) -> f64 {
+        context.data( self.data_id ).get_allocation( self.id ).size as f64
+    }
+
+    fn thread( &self, context: &Context ) -> i32 {
+        context.data( self.data_id ).get_allocation( self.id ).thread as i32
+    }
+
+    fn leaked( &self, context: &Context ) -> bool {
+        context.data( self.data_id ).get_allocation( self.id ).deallocation.is_none()
+    }
+
+    /// Seconds since the start of the profiling.
+    fn deallocation_timestamp( &self, context: &Context ) -> Option< f64 > {
+        let data = context.data( self.data_id );
+        data.get_allocation( self.id ).deallocation.as_ref().map( |deallocation| relative_secs( data, deallocation.timestamp ) )
+    }
+
+    fn backtrace_id( &self, context: &Context ) -> i32 {
+        context.data( self.data_id ).get_allocation( self.id ).backtrace.raw() as i32
+    }
+
+    fn backtrace( &self, context: &Context, strip_template_args: Option< bool > ) -> Vec< Frame > {
+        let data = context.data( self.data_id );
+        backtrace( data, data.get_allocation( self.id ).backtrace, strip_template_args )
+    }
+
+    /// Only available when the backtraces were gathered on `free`, or when the allocation was `realloc`'d.
+    fn deallocation_backtrace( &self, context: &Context, strip_template_args: Option< bool > ) -> Option< Vec< Frame > > {
+        let data = context.data( self.data_id );
+        let deallocation = data.get_allocation( self.id ).deallocation.as_ref()?;
+        deallocation.backtrace.map( |backtrace_id| backtrace( data, backtrace_id, strip_template_args ) )
+    }
+}
+
+pub struct AllocationGroup {
+    data_id: DataId,
+    backtrace_id: BacktraceId,
+    allocation_ids: Vec< AllocationId >,
+    size: u64,
+    leaked_count: u64
+}
+
+#[graphql_object(context = Context)]
+impl AllocationGroup {
+    fn backtrace_id( &self ) -> i32 {
+        self.backtrace_id.raw() as i32
+    }
+
+    fn site_id( &self, context: &Context ) -> String {
+        context.data( self.data_id ).get_site_id( self.backtrace_id ).to_string()
+    }
+
+    fn classification( &self, context: &Context ) -> String {
+        let name = match classify_site( context.data( self.data_id ), self.backtrace_id ) {
+            protocol::SiteClassification::SteadyGrowth => "steady_growth",
+            protocol::SiteClassification::Plateau => "plateau",
+            protocol::SiteClassification::Sawtooth => "sawtooth",
+            protocol::SiteClassification::Startup => "startup",
+            protocol::SiteClassification::Other => "other"
+        };
+
+        name.to_owned()
+    }
+
+    fn allocated_count( &self ) -> f64 {
+        self.allocation_ids.len() as f64
+    }
+
+    fn size( &self ) -> f64 {
+        self.size as f64
+    }
+
+    fn leaked_count( &self ) -> f64 {
+        self.leaked_count as f64
+    }
+
+    fn backtrace( &self, context: &Context, strip_template_args: Option< bool > ) -> Vec< Frame > {
+        backtrace( context.data( self.data_id ), self.backtrace_id, strip_template_args )
+    }
+
+    fn allocations( &self, skip: Option< i32 >, count: Option< i32 > ) -> Vec< Allocation > {
+        let data_id = self.data_id;
+        page( self.allocation_ids.iter().map( |&id| Allocation { data_id, id } ), skip, count )
+    }
+}
+
+pub struct Dataset {
+    id: DataId
+}
+
+#[graphql_object(context = Context)]
+impl Dataset {
+    fn id( &self ) -> String {
+        format!( "{}", self.id )
+    }
+
+    fn executable( &self, context: &Context ) -> String {
+        context.data( self.id ).executable().to_owned()
+    }
+
+    fn architecture( &self, context: &Context ) -> String {
+        context.data( self.id ).architecture().to_owned()
+    }
+
+    /// In seconds.
+    fn runtime( &self, context: &Context ) -> f64 {
+        let data = context.data( self.id );
+        relative_secs( data, data.last_timestamp() )
+    }
+
+    fn final_allocated( &self, context: &Context ) -> f64 {
+        let data = context.data( self.id );
+        (data.total_allocated() - data.total_freed()) as f64
+    }
+
+    fn final_allocated_count( &self, context: &Context ) -> f64 {
+        let data = context.data( self.id );
+        (data.total_allocated_count() - data.total_freed_count()) as f64
+    }
+
+    /// Allocations sorted by their timestamp; the `filter` takes the same parameters as the REST API, e.g. `lifetime=only_leaked&size_min=1024`.
+    fn allocations( &self, context: &Context, filter: Option< String >, skip: Option< i32 >, count: Option< i32 > ) -> FieldResult< Vec< Allocation > > {
+        let data = context.data( self.id );
+        let filter = context.filter( data, filter )?;
+        let data_id = self.id;
+        let iter = data.alloc_sorted_by_timestamp( filter.timestamp_start_opt(), filter.timestamp_end_opt() )
+            .filter( |(_, allocation)| match_allocation( data, allocation, &filter ) )
+            .map( |(id, _)| Allocation { data_id, id } );
+
+        Ok( page( iter, skip, count ) )
+    }
+
+    /// Allocations grouped by their backtrace and sorted by their total size.
+    fn groups( &self, context: &Context, filter: Option< String >, skip: Option< i32 >, count: Option< i32 > ) -> FieldResult< Vec< AllocationGroup > > {
+        let data = context.data( self.id );
+        let filter = context.filter( data, filter )?;
+        let mut groups: HashMap< BacktraceId, AllocationGroup > = HashMap::new();
+        let iter = data.alloc_sorted_by_timestamp( filter.timestamp_start_opt(), filter.timestamp_end_opt() );
+        for (id, allocation) in iter {
+            if !match_allocation( data, allocation, &filter ) {
+                continue;
+            }
+
+            let group = groups.entry( allocation.backtrace ).or_insert_with( || AllocationGroup {
+                data_id: data.id(),
+                backtrace_id: allocation.backtrace,
+                allocation_ids: Vec::new(),
+                size: 0,
+                leaked_count: 0
+            });
+
+            group.allocation_ids.push( id );
+            group.size += allocation.size;
+            if allocation.deallocation.is_none() {
+                group.leaked_count += 1;
+            }
+        }
+
+        let mut groups: Vec< _ > = groups.into_iter().map( |(_, group)| group ).collect();
+        groups.sort_by( |lhs, rhs| rhs.size.cmp( &lhs.size ).then_with( || lhs.backtrace_id.cmp( &rhs.backtrace_id ) ) );
+        Ok( page( groups.into_iter(), skip, count ) )
+    }
+
+    fn timeline( &self, context: &Context ) -> Timeline {
+        fn to_floats( values: Vec< u64 > ) -> Vec< f64 > {
+            values.into_iter().map( |value| value as f64 ).collect()
+        }
+
+        fn counts_to_floats( values: Vec< u32 > ) -> Vec< f64 > {
+            values.into_iter().map( f64::from ).collect()
+        }
+
+        let timeline = get_timeline( context.data( self.id ) );
+        Timeline {
+            xs: to_floats( timeline.xs ),
+            allocated_size: to_floats( timeline.allocated_size ),
+            allocated_count: to_floats( timeline.allocated_count ),
+            leaked_size: to_floats( timeline.leaked_size ),
+            leaked_count: to_floats( timeline.leaked_count ),
+            allocations: counts_to_floats( timeline.allocations ),
+            deallocations: counts_to_floats( timeline.deallocations )
+        }
+    }
+}
+
+pub struct Query;
+
+#[graphql_object(context = Context)]
+impl Query {
+    fn datasets( context: &Context ) -> Vec< Dataset > {
+        context.state.data_ids.iter().map( |&id| Dataset { id } ).collect()
+    }
+
+    /// The `id` can also be equal to `last`.
+    fn dataset( context: &Context, id: String ) -> Option< Dataset > {
+        let id = if id == "last" {
+            context.state.last_id()?
+        } else {
+            id.parse().ok()?
+        };
+
+        if context.state.data.contains_key( &id ) {
+            Some( Dataset { id } )
+        } else {
+            None
+        }
+    }
+}
+
+pub type Schema = RootNode< 'static, Query, EmptyMutation< Context >, EmptySubscription< Context > >;
+
+pub fn schema() -> Schema {
+    Schema::new( Query, EmptyMutation::new(), EmptySubscription::new() )
+}
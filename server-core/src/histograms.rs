@@ -0,0 +1,78 @@
+use cli_core::{AllocationColumns, AllocationId, Data};
+
+use crate::protocol;
+use crate::filter::{AllocationMatcher, Filter};
+
+/// Returns the index of the power-of-two bucket to which a given value belongs.
+pub fn bucket( value: u64 ) -> usize {
+    64 - value.leading_zeros() as usize
+}
+
+/// Returns the smallest and the largest value which belong to a given power-of-two bucket.
+pub fn bucket_range( index: usize ) -> (u64, u64) {
+    match index {
+        0 => (0, 0),
+        64 => (1 << 63, u64::MAX),
+        _ => (1 << (index - 1), (1 << index) - 1)
+    }
+}
+
+fn to_buckets( buckets: Vec< (u64, u64) > ) -> Vec< protocol::HistogramBucket > {
+    buckets.into_iter().enumerate().filter( |&(_, (count, _))| count != 0 ).map( |(index, (count, size))| {
+        let (min, max) = bucket_range( index );
+        protocol::HistogramBucket {
+            min,
+            max,
+            count,
+            size
+        }
+    }).collect()
+}
+
+/// Builds power-of-two histograms of the sizes and of the lifetimes (in microseconds) of the matched allocations.
+pub fn get_histograms( data: &Data, columns: Option< &AllocationColumns >, filter: &Filter ) -> protocol::ResponseHistograms {
+    let mut sizes = vec![ (0, 0); 65 ];
+    let mut lifetimes = vec![ (0, 0); 65 ];
+    let mut leaked_count = 0;
+    let mut leaked_size: u64 = 0;
+    let matcher = AllocationMatcher::new( data, columns, filter );
+    for (index, allocation) in data.allocations().iter().enumerate() {
+        if !matcher.matches( AllocationId::new( index as u64 ), allocation ) {
+            continue;
+        }
+
+        let bucket_by_size = &mut sizes[ bucket( allocation.size ) ];
+        bucket_by_size.0 += 1;
+        bucket_by_size.1 += allocation.size;
+
+        match allocation.deallocation {
+            Some( ref deallocation ) => {
+                let lifetime = deallocation.timestamp.as_usecs().saturating_sub( allocation.timestamp.as_usecs() );
+                let bucket_by_lifetime = &mut lifetimes[ bucket( lifetime ) ];
+                bucket_by_lifetime.0 += 1;
+                bucket_by_lifetime.1 += allocation.size;
+            },
+            None => {
+                leaked_count += 1;
+                leaked_size += allocation.size;
+            }
+        }
+    }
+
+    protocol::ResponseHistograms {
+        size: to_buckets( sizes ),
+        lifetime: to_buckets( lifetimes ),
+        leaked_count,
+        leaked_size
+    }
+}
+
+#[test]
+fn test_bucket() {
+    assert_eq!( bucket( 0 ), 0 );
+    assert_eq!( bucket( 1 ), 1 );
+    assert_eq!( bucket( 24 ), 5 );
+    assert_eq!( bucket_range( 5 ), (16, 31) );
+    assert_eq!( bucket( u64::MAX ), 64 );
+    assert_eq!( bucket_range( 64 ), (1 << 63, u64::MAX) );
+}
@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ahash::AHashMap as HashMap;
+use parking_lot::Mutex;
+
+use cli_core::{
+    Allocation,
+    Data,
+    IoAdapter,
+    export_as_flamegraph,
+    export_as_flamegraph_pl,
+    export_as_heaptrack,
+    export_as_pprof,
+    export_as_replay
+};
+
+use crate::byte_channel::ByteSender;
+use crate::filter::{Filter, match_allocation};
+use crate::protocol;
+
+/// How many jobs can run at the same time; the rest wait in a queue.
+const MAX_RUNNING_JOBS: usize = 2;
+/// How many jobs can wait in the queue before new ones are rejected.
+const MAX_QUEUED_JOBS: usize = 64;
+/// How long the jobs are kept around after they've finished.
+const JOB_TTL: Duration = Duration::from_secs( 60 * 60 );
+
+struct Job {
+    status: protocol::ResponseJob,
+    path: PathBuf,
+    finished_at: Option< Instant >
+}
+
+impl Drop for Job {
+    fn drop( &mut self ) {
+        let _ = fs::remove_file( &self.path );
+    }
+}
+
+type Task = Box< dyn FnOnce() + Send >;
+
+#[derive(Default)]
+struct Queue {
+    tasks: VecDeque< Task >,
+    worker_count: usize
+}
+
+/// The job queue is full.
+pub struct TooManyJobs;
+
+/// Runs heavy analyses in the background, independently of the client which requested them,
+/// on a small pool of worker threads; their results are kept in temporary files until the jobs
+/// are deleted or have expired.
+pub struct Jobs {
+    entries: Mutex< HashMap< String, Arc< Mutex< Job > > > >,
+    queue: Arc< Mutex< Queue > >,
+    counter: AtomicU64
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Jobs {
+            entries: Mutex::new( HashMap::new() ),
+            queue: Arc::new( Mutex::new( Queue::default() ) ),
+            counter: AtomicU64::new( 0 )
+        }
+    }
+
+    /// Queues a new job. Once it's its turn the `with_data` is called with a callback which runs it;
+    /// it should pass the data the job was submitted for to it, or `None` if that data is gone.
+    pub fn submit< F >( &self, data: &Data, kind: protocol::JobKind, filter: Filter, collapse_recursion: bool, with_data: F ) -> Result< protocol::ResponseJob, TooManyJobs >
+        where F: FnOnce( &dyn Fn( Option< &Data > ) ) + Send + 'static
+    {
+        self.remove_expired();
+
+        let mut queue = self.queue.lock();
+        if queue.tasks.len() >= MAX_QUEUED_JOBS {
+            return Err( TooManyJobs );
+        }
+
+        let id = format!( "{}", self.counter.fetch_add( 1, Ordering::SeqCst ) );
+        let path = env::temp_dir().join( format!( "memory-profiler-job-{}-{}", process::id(), id ) );
+        let status = protocol::ResponseJob {
+            id: id.clone(),
+            data_id: format!( "{}", data.id() ),
+            kind,
+            status: protocol::JobStatus::Queued,
+            error: None,
+            size: None
+        };
+
+        let job = Arc::new( Mutex::new( Job {
+            status: status.clone(),
+            path: path.clone(),
+            finished_at: None
+        }));
+
+        self.entries.lock().insert( id, job.clone() );
+        let callback = move |data: Option< &Data >| {
+            job.lock().status.status = protocol::JobStatus::Running;
+            let result = match data {
+                Some( data ) => run( data, kind, &filter, collapse_recursion, &path ),
+                None => Err( io::Error::new( io::ErrorKind::NotFound, "the data the job was submitted for is no longer loaded" ) )
+            };
+
+            let mut job = job.lock();
+            job.finished_at = Some( Instant::now() );
+            match result {
+                Ok( size ) => {
+                    job.status.status = protocol::JobStatus::Finished;
+                    job.status.size = Some( size );
+                },
+                Err( error ) => {
+                    warn!( "Job {} failed: {}", job.status.id, error );
+                    job.status.status = protocol::JobStatus::Failed;
+                    job.status.error = Some( error.to_string() );
+                }
+            }
+        };
+
+        queue.tasks.push_back( Box::new( move || with_data( &callback ) ) );
+        if queue.worker_count < MAX_RUNNING_JOBS {
+            queue.worker_count += 1;
+            let queue = self.queue.clone();
+            thread::spawn( move || run_worker( queue ) );
+        }
+
+        Ok( status )
+    }
+
+    pub fn get( &self, id: &str ) -> Option< protocol::ResponseJob > {
+        self.remove_expired();
+        self.entries.lock().get( id ).map( |job| job.lock().status.clone() )
+    }
+
+    /// Returns the job's status and, if it has finished, its result.
+    pub fn open_result( &self, id: &str ) -> Option< (protocol::ResponseJob, Option< io::Result< File > >) > {
+        self.remove_expired();
+        let job = self.entries.lock().get( id )?.clone();
+        let job = job.lock();
+        let result = if job.status.status == protocol::JobStatus::Finished {
+            Some( File::open( &job.path ) )
+        } else {
+            None
+        };
+
+        Some( (job.status.clone(), result) )
+    }
+
+    pub fn remove( &self, id: &str ) -> bool {
+        self.entries.lock().remove( id ).is_some()
+    }
+
+    fn remove_expired( &self ) {
+        self.entries.lock().retain( |_, job| {
+            job.lock().finished_at.map( |finished_at| finished_at.elapsed() < JOB_TTL ).unwrap_or( true )
+        });
+    }
+}
+
+fn run_worker( queue: Arc< Mutex< Queue > > ) {
+    loop {
+        let task = {
+            let mut queue = queue.lock();
+            match queue.tasks.pop_front() {
+                Some( task ) => task,
+                None => {
+                    queue.worker_count -= 1;
+                    return;
+                }
+            }
+        };
+
+        if panic::catch_unwind( AssertUnwindSafe( task ) ).is_err() {
+            error!( "A job has panicked" );
+        }
+    }
+}
+
+fn run( data: &Data, kind: protocol::JobKind, filter: &Filter, collapse_recursion: bool, path: &PathBuf ) -> io::Result< u64 > {
+    let mut fp = BufWriter::new( File::create( path )? );
+    let filter = |allocation: &Allocation| match_allocation( data, allocation, filter );
+    match kind {
+        protocol::JobKind::Flamegraph => export_as_flamegraph( data, IoAdapter::new( &mut fp ), filter, collapse_recursion ),
+        protocol::JobKind::FlamegraphPl => {
+            export_as_flamegraph_pl( data, IoAdapter::new( &mut fp ), filter, collapse_recursion )
+                .map_err( |_| io::Error::new( io::ErrorKind::Other, "failed to generate the flamegraph" ) )?;
+        },
+        protocol::JobKind::Heaptrack => export_as_heaptrack( data, &mut fp, filter )?,
+        protocol::JobKind::Pprof => export_as_pprof( data, &mut fp, filter )?,
+        protocol::JobKind::Replay => export_as_replay( data, &mut fp, filter )?
+    }
+
+    fp.flush()?;
+    Ok( fs::metadata( path )?.len() )
+}
+
+pub fn content_type( kind: protocol::JobKind ) -> &'static str {
+    match kind {
+        protocol::JobKind::Flamegraph => "image/svg+xml",
+        protocol::JobKind::FlamegraphPl | protocol::JobKind::Heaptrack | protocol::JobKind::Pprof | protocol::JobKind::Replay => "application/octet-stream"
+    }
+}
+
+pub fn send_file( mut fp: File, mut tx: ByteSender ) -> io::Result< () > {
+    let mut buffer = vec![ 0; 64 * 1024 ];
+    loop {
+        let count = fp.read( &mut buffer )?;
+        if count == 0 {
+            return Ok(());
+        }
+
+        tx.write_all( &buffer[ ..count ] )?;
+    }
+}
@@ -0,0 +1,69 @@
+use cli_core::{
+    Allocation,
+    Data
+};
+
+use crate::protocol;
+use crate::get_frame;
+
+fn get_kind( allocation: &Allocation ) -> protocol::AllocationKind {
+    if allocation.is_mmaped() {
+        protocol::AllocationKind::Mmap
+    } else if allocation.in_main_arena() {
+        protocol::AllocationKind::MainArena
+    } else {
+        protocol::AllocationKind::NonMainArena
+    }
+}
+
+fn get_region( data: &Data, allocation: &Allocation ) -> Option< protocol::MapRegion > {
+    let (range, region) = data.get_map_region( allocation.pointer )?;
+    let permissions = format!(
+        "{}{}{}{}",
+        if region.is_readable { 'r' } else { '-' },
+        if region.is_writable { 'w' } else { '-' },
+        if region.is_executable { 'x' } else { '-' },
+        if region.is_shared { 's' } else { 'p' }
+    );
+
+    Some( protocol::MapRegion {
+        address: range.start,
+        address_s: format!( "{:016X}", range.start ),
+        length: range.end - range.start,
+        permissions,
+        name: region.name.clone()
+    })
+}
+
+/// Returns the biggest allocations which were alive at a given point in time
+/// along with the region of the address space in which they've ended up.
+pub fn get_largest_allocations< 'a >(
+    data: &'a Data,
+    backtrace_format: &protocol::BacktraceFormat,
+    params: protocol::RequestLargestAllocations
+) -> Vec< protocol::LargestAllocation< 'a > > {
+    let at = params.at.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) ).unwrap_or( data.last_timestamp() );
+    let count = params.count.unwrap_or( 20 ) as usize;
+
+    data.alloc_sorted_by_size( None, None ).rev()
+        .map( |(_, allocation)| allocation )
+        .filter( |allocation| {
+            allocation.timestamp <= at &&
+            allocation.deallocation.as_ref().map( |deallocation| deallocation.timestamp > at ).unwrap_or( true )
+        })
+        .take( count )
+        .map( |allocation| {
+            protocol::LargestAllocation {
+                address: allocation.pointer,
+                address_s: format!( "{:016X}", allocation.pointer ),
+                size: allocation.size,
+                timestamp: allocation.timestamp.into(),
+                thread: allocation.thread,
+                kind: get_kind( allocation ),
+                region: get_region( data, allocation ),
+                backtrace_id: allocation.backtrace.raw(),
+                backtrace: data.get_backtrace( allocation.backtrace ).map( |(_, frame)| get_frame( data, backtrace_format, frame ) ).collect()
+            }
+        })
+        .collect()
+}
@@ -3,17 +3,19 @@ extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
+use std::cell::RefCell;
 use std::collections::BTreeMap;
-use std::fs::File;
 use std::error::Error;
 use std::sync::Arc;
-use std::ops::Bound::{self, Unbounded};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::time::{Duration, Instant};
 use std::fmt::{self, Write};
 use std::thread;
 use std::io;
+use std::mem;
 use std::borrow::Cow;
 use std::cmp::{min, max, Ordering};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use actix_web::{
     body::{
@@ -30,7 +32,8 @@ use actix_web::{
 
 use ahash::AHashMap as HashMap;
 
-use actix_web::error::{ErrorNotFound, ErrorBadRequest, ErrorInternalServerError};
+use actix_web::http::header;
+use actix_web::error::{ErrorNotFound, ErrorBadRequest, ErrorInternalServerError, ErrorServiceUnavailable, ErrorConflict};
 use actix_web::error::Error as ActixWebError;
 use actix_cors::Cors;
 use futures::Stream;
@@ -40,7 +43,12 @@ use lru::LruCache;
 use parking_lot::Mutex;
 
 use cli_core::{
+    CoreDump,
+    FrameRules,
+    Attribution,
     Loader,
+    Shard,
+    SymbolSources,
     Data,
     DataId,
     BacktraceId,
@@ -56,26 +64,90 @@ use cli_core::{
     MmapOperation,
     MemoryMap,
     MemoryUnmap,
+    MemoryRemap,
     CountAndSize,
+    Expression,
+    AllocationColumns,
+    Selection,
     export_as_replay,
     export_as_heaptrack,
     export_as_flamegraph,
     export_as_flamegraph_pl,
+    export_as_pprof,
     table_to_string
 };
 
 use common::Timestamp;
+use common::timeline::{Timeline, TimelineOperation};
 
 mod itertools;
 mod protocol;
 mod streaming_channel;
 mod byte_channel;
 mod streaming_serializer;
+mod row_format;
+mod response_cache;
 mod filter;
+mod churn;
+mod fragmentation;
+mod address_reuse;
+mod arenas;
+mod arena_trim;
+mod mapped_regions;
+mod overhead;
+mod size_classes;
+mod threads;
+mod regression;
+mod changes;
+mod diff;
+mod data_quality;
+mod top_sites;
+mod markers;
+mod sessions;
+mod jobs;
+mod allocator_stats;
+mod site_pairs;
+mod libraries;
+mod largest_allocations;
+mod site_classification;
+mod backtrace_clusters;
+mod container_growth;
+mod peaks;
+mod precompute;
+mod histograms;
+mod virtual_columns;
+mod shards;
+mod grafana;
+mod otlp;
+mod ci_check;
+mod core_memory;
+mod workspace;
+mod live;
+mod query_limits;
+pub mod plugin;
+#[cfg(feature = "scripting")]
+mod scripting;
+#[cfg(feature = "graphql")]
+mod graphql;
 
 use crate::byte_channel::byte_channel;
-use crate::streaming_serializer::StreamingSerializer;
-use crate::filter::{Filter, PrepareFilterError, prepare_filter, match_allocation};
+use crate::streaming_serializer::{StreamingSerializer, Rows};
+use crate::row_format::{RowFormat, write_response};
+use crate::sessions::Sessions;
+use crate::jobs::Jobs;
+use crate::precompute::Precomputed;
+use crate::core_memory::CoreMemory;
+use crate::workspace::{Inputs, Workspace};
+use crate::plugin::Plugins;
+use crate::response_cache::{ResponseCache, ResponseCacheKey, MAXIMUM_CACHED_RESPONSE_SIZE, is_not_modified};
+use crate::filter::{Filter, PrepareFilterError, prepare_filter, match_allocation, select_allocations};
+use crate::query_limits::QueryAborted;
+
+pub use crate::shards::coordinator_main;
+pub use crate::ci_check::{check_main, CheckOptions};
+pub use crate::diff::{diff_main, DiffOptions};
+pub use crate::otlp::push_main as push_otlp_main;
+pub use crate::protocol::DiffSortBy;
 
 struct AllocationGroups {
     allocations_by_backtrace: VecVec< BacktraceId, AllocationId >
@@ -85,6 +157,7 @@ impl AllocationGroups {
     fn new< 'a, T: IntoIterator< Item = (AllocationId, &'a Allocation) > >( iter: T ) -> Self {
         let mut grouped = HashMap::new();
         for (id, allocation) in iter {
+            query_limits::charge_memory( mem::size_of::< AllocationId >() );
             let allocations = grouped.entry( allocation.backtrace ).or_insert( Vec::new() );
             allocations.push( id );
         }
@@ -104,6 +177,18 @@ impl AllocationGroups {
         groups
     }
 
+    fn from_selection( columns: &AllocationColumns, selection: &Selection ) -> Self {
+        query_limits::charge_memory( selection.count() * mem::size_of::< AllocationId >() );
+        let mut allocations = VecVec::new();
+        for (backtrace_id, allocation_ids) in columns.group_by_backtrace( selection ) {
+            allocations.insert( backtrace_id, allocation_ids );
+        }
+
+        AllocationGroups {
+            allocations_by_backtrace: allocations
+        }
+    }
+
     fn len( &self ) -> usize {
         self.allocations_by_backtrace.len()
     }
@@ -120,15 +205,37 @@ struct AllocationGroupsKey {
 struct State {
     data: HashMap< DataId, Data >,
     data_ids: Vec< DataId >,
-    allocation_group_cache: Mutex< LruCache< AllocationGroupsKey, Arc< AllocationGroups > > >
+    allocation_group_cache: Mutex< LruCache< AllocationGroupsKey, Arc< AllocationGroups > > >,
+    precomputed: HashMap< DataId, Precomputed >,
+    plugins: Plugins,
+    response_cache: ResponseCache,
+    limits: QueryLimits,
+    running_queries: AtomicUsize,
+    sessions: Sessions,
+    jobs: Jobs,
+    cores: HashMap< DataId, CoreMemory >,
+    workspace: Workspace,
+    shard: Option< Shard >,
+    live: Option< live::LiveStreams >
 }
 
 impl State {
-    fn new() -> Self {
+    fn new( limits: QueryLimits, shard: Option< Shard > ) -> Self {
         State {
             data: HashMap::new(),
             data_ids: Vec::new(),
-            allocation_group_cache: Mutex::new( LruCache::new( 4 ) )
+            allocation_group_cache: Mutex::new( LruCache::new( 4 ) ),
+            precomputed: HashMap::new(),
+            plugins: Plugins::default(),
+            response_cache: ResponseCache::new( 16 ),
+            limits,
+            running_queries: AtomicUsize::new( 0 ),
+            sessions: Sessions::new( 1024 ),
+            jobs: Jobs::new(),
+            cores: HashMap::new(),
+            workspace: Workspace::default(),
+            shard,
+            live: None
         }
     }
 
@@ -138,16 +245,137 @@ impl State {
         }
 
         self.data_ids.push( data.id() );
+        self.precomputed.insert( data.id(), Precomputed::default() );
         self.data.insert( data.id(), data );
     }
 
     fn last_id( &self ) -> Option< DataId > {
         self.data_ids.last().cloned()
     }
+
+    fn save_workspace( &self ) {
+        if let Err( error ) = self.workspace.save( &self.sessions ) {
+            warn!( "Failed to save the workspace: {}", error );
+        }
+    }
+
+    /// Attaches a core dump to the data from the same process.
+    fn add_core( &mut self, path: &Path ) -> io::Result< () > {
+        let core = CoreDump::load( path )?;
+        let id = match core.pid() {
+            Some( pid ) => self.data.values().find( |data| data.pid() == pid ).map( |data| data.id() ),
+            None if self.data_ids.len() == 1 => self.last_id(),
+            None => None
+        };
+
+        let id = id.ok_or_else( || io::Error::new( io::ErrorKind::NotFound, format!( "none of the loaded data files matches the core dump {:?}", path ) ) )?;
+        if self.cores.contains_key( &id ) {
+            return Err( io::Error::new( io::ErrorKind::InvalidInput, format!( "more than one core dump matches the data '{}'", id ) ) );
+        }
+
+        info!( "Attached {:?} to the data '{}'", path, id );
+        let memory = CoreMemory::new( &self.data[ &id ], core );
+        self.cores.insert( id, memory );
+        Ok(())
+    }
 }
 
 type StateRef = Arc< State >;
 
+#[derive(Clone, Default, Debug)]
+pub struct QueryLimits {
+    /// The maximum number of queries which can run at the same time.
+    pub max_concurrent_queries: Option< usize >,
+    /// How long a single query can take before it's aborted.
+    pub timeout: Option< Duration >,
+    /// How much memory the regexes and the intermediate results of a single query can use.
+    pub memory_budget: Option< usize >
+}
+
+struct RunningQuery {
+    state: StateRef,
+    deadline: Option< Instant >
+}
+
+thread_local! {
+    /// The query started by `run_limited` on this thread, which `async_handler` can take over.
+    static CURRENT_QUERY: RefCell< Option< RunningQuery > > = RefCell::new( None );
+}
+
+impl RunningQuery {
+    fn start( state: &StateRef ) -> Result< Self > {
+        let count = state.running_queries.fetch_add( 1, AtomicOrdering::SeqCst ) + 1;
+        let query = RunningQuery {
+            state: state.clone(),
+            deadline: state.limits.timeout.map( |timeout| Instant::now() + timeout )
+        };
+
+        if let Some( max_concurrent_queries ) = state.limits.max_concurrent_queries {
+            if count > max_concurrent_queries {
+                return Err( ErrorServiceUnavailable( "too many queries are already running; try again later" ) );
+            }
+        }
+
+        Ok( query )
+    }
+
+    /// Takes over the query which is running on the current thread, so that it's still counted
+    /// after the handler returns; starts a new one if there's none.
+    fn take_or_start( state: &StateRef ) -> Result< Self > {
+        match CURRENT_QUERY.with( |current| current.borrow_mut().take() ) {
+            Some( query ) => Ok( query ),
+            None => RunningQuery::start( state )
+        }
+    }
+}
+
+impl Drop for RunningQuery {
+    fn drop( &mut self ) {
+        self.state.running_queries.fetch_sub( 1, AtomicOrdering::SeqCst );
+    }
+}
+
+fn aborted_query_error( reason: QueryAborted ) -> ActixWebError {
+    match reason {
+        QueryAborted::TimedOut => ErrorServiceUnavailable( "the query took too long and was aborted" ),
+        QueryAborted::OverMemoryBudget => ErrorServiceUnavailable( "the query needed too much memory and was aborted" )
+    }
+}
+
+/// Runs a query under the limits: it counts towards the maximum number of concurrent queries
+/// and is aborted once it runs out of time or memory.
+fn run_limited< F: FnOnce() -> Result< HttpResponse > >( req: &HttpRequest, callback: F ) -> Result< HttpResponse > {
+    let state = req.state();
+    let query = RunningQuery::start( state )?;
+    let deadline = query.deadline;
+    CURRENT_QUERY.with( |current| *current.borrow_mut() = Some( query ) );
+
+    struct Guard;
+    impl Drop for Guard {
+        fn drop( &mut self ) {
+            CURRENT_QUERY.with( |current| current.borrow_mut().take() );
+        }
+    }
+
+    let _guard = Guard;
+    let result = query_limits::run( deadline, state.limits.memory_budget, callback );
+
+    match result {
+        Ok( response ) => response,
+        Err( reason ) => Err( aborted_query_error( reason ) )
+    }
+}
+
+/// Wraps a handler so that it runs under the limits.
+fn limited< F >( handler: F ) -> impl Fn( HttpRequest ) -> Result< HttpResponse > + Clone + 'static
+    where F: Fn( HttpRequest ) -> Result< HttpResponse > + Clone + 'static
+{
+    move |req: HttpRequest| {
+        let request = req.clone();
+        run_limited( &request, || handler( req ) )
+    }
+}
+
 trait StateGetter {
     fn state( &self ) -> &StateRef;
 }
@@ -181,34 +409,105 @@ fn get_data( req: &HttpRequest ) -> Result< &Data > {
     req.state().data.get( &id ).ok_or_else( || ErrorNotFound( "data not found" ) )
 }
 
+/// Returns the columnar copy of the data's allocations if it was already built.
+fn get_precomputed_columns( req: &HttpRequest, data_id: DataId ) -> Option< Arc< AllocationColumns > > {
+    req.state().precomputed.get( &data_id ).and_then( |precomputed| precomputed.columns() )
+}
+
 impl From< PrepareFilterError > for ActixWebError {
     fn from( error: PrepareFilterError ) -> Self {
         match error {
             PrepareFilterError::InvalidRegex( field, inner_err ) => {
                 ErrorBadRequest( format!( "invalid '{}': {}", field, inner_err ) )
+            },
+            PrepareFilterError::InvalidExpression( field, inner_err ) => {
+                ErrorBadRequest( format!( "invalid '{}': {}", field, inner_err ) )
+            },
+            PrepareFilterError::LiveAtBeforeStart => {
+                ErrorBadRequest( "'live_at' cannot be earlier than 'from'" )
+            },
+            PrepareFilterError::LiveAtBeforeEnd => {
+                ErrorBadRequest( "'live_at' cannot be earlier than 'to'" )
             }
         }
     }
 }
 
-fn async_data_handler< F: FnOnce( &Data, byte_channel::ByteSender ) + Send + 'static >( req: &HttpRequest, callback: F ) -> Result< Body > {
-    let (tx, rx) = byte_channel();
+fn async_handler< F: FnOnce( byte_channel::ByteSender ) + Send + 'static >( req: &HttpRequest, callback: F ) -> Result< Body > {
+    let (mut tx, rx) = byte_channel();
     let rx = rx.map_err( |_| ErrorInternalServerError( "internal error" ) );
     let rx = BodyStream::new( rx );
     let body = Body::Message( Box::new( rx ) );
 
+    let state = req.state();
+    let query = RunningQuery::take_or_start( state )?;
+    let deadline = query.deadline;
+    let memory_budget = state.limits.memory_budget;
+    if let Some( deadline ) = deadline {
+        tx.set_deadline( deadline );
+    }
+
+    thread::spawn( move || {
+        let _query = query;
+        if let Err( reason ) = query_limits::run( deadline, memory_budget, move || callback( tx ) ) {
+            info!( "Aborted a query: {:?}", reason );
+        }
+    });
+
+    Ok( body )
+}
+
+fn async_data_handler< F: FnOnce( &Data, byte_channel::ByteSender ) + Send + 'static >( req: &HttpRequest, callback: F ) -> Result< Body > {
     let data_id = get_data_id( &req )?;
     let state = req.state().clone();
-    thread::spawn( move || {
+    async_handler( req, move |tx| {
         let data = match state.data.get( &data_id ) {
             Some( data ) => data,
             None => return
         };
 
         callback( data, tx );
-    });
+    })
+}
 
-    Ok( body )
+fn get_response_cache_key( req: &HttpRequest, content_type: &'static str ) -> Result< ResponseCacheKey > {
+    let data_id = get_data_id( req )?;
+    Ok( ResponseCacheKey::new( data_id, req, content_type ) )
+}
+
+fn cached_response( req: &HttpRequest, key: &ResponseCacheKey ) -> Option< HttpResponse > {
+    let etag = req.state().response_cache.etag( key );
+    if is_not_modified( req, &etag ) {
+        return Some( HttpResponse::NotModified().header( header::ETAG, etag ).finish() );
+    }
+
+    let body = req.state().response_cache.get( key )?;
+    Some( HttpResponse::Ok().content_type( key.content_type ).header( header::ETAG, etag ).body( body ) )
+}
+
+/// Serves a response which was precomputed in the background, with the same ETag as if it was computed on demand.
+fn precomputed_response< B: Into< Body > >( req: &HttpRequest, key: &ResponseCacheKey, body: B ) -> HttpResponse {
+    let etag = req.state().response_cache.etag( key );
+    if is_not_modified( req, &etag ) {
+        return HttpResponse::NotModified().header( header::ETAG, etag ).finish();
+    }
+
+    HttpResponse::Ok().content_type( key.content_type ).header( header::ETAG, etag ).body( body )
+}
+
+fn async_cached_data_handler< F: FnOnce( &Data, byte_channel::ByteSender ) + Send + 'static >( req: &HttpRequest, key: ResponseCacheKey, callback: F ) -> Result< HttpResponse > {
+    let etag = req.state().response_cache.etag( &key );
+    let content_type = key.content_type;
+    let state = req.state().clone();
+    let body = async_data_handler( req, move |data, mut tx| {
+        tx.record( MAXIMUM_CACHED_RESPONSE_SIZE, move |output| {
+            state.response_cache.insert( key, output.into() );
+        });
+
+        callback( data, tx );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( content_type ).header( header::ETAG, etag ).body( body ) )
 }
 
 fn strip_template( input: &str ) -> String {
@@ -276,7 +575,7 @@ fn get_frame< 'a >( data: &'a Data, format: &protocol::BacktraceFormat, frame: &
 }
 
 impl protocol::ResponseMetadata {
-    fn new( data: &Data ) -> Self {
+    fn new( data: &Data, shard: Option< Shard > ) -> Self {
         protocol::ResponseMetadata {
             id: format!( "{}", data.id() ),
             executable: data.executable().to_owned(),
@@ -286,335 +585,366 @@ impl protocol::ResponseMetadata {
             runtime: (data.last_timestamp() - data.initial_timestamp()).into(),
             unique_backtrace_count: data.unique_backtrace_count() as u64,
             maximum_backtrace_depth: data.maximum_backtrace_depth(),
-            timestamp: data.initial_timestamp().into()
+            timestamp: data.initial_timestamp().into(),
+            total_allocated: data.total_allocated(),
+            total_allocated_count: data.total_allocated_count(),
+            total_freed: data.total_freed(),
+            total_freed_count: data.total_freed_count(),
+            peak_allocated: data.peak_allocated(),
+            peak_allocated_timestamp: data.peak_allocated_timestamp().into(),
+            unknown_deallocation_count: data.unknown_deallocation_count(),
+            duplicate_allocation_count: data.duplicate_allocation_count(),
+            lost_chunk_count: data.lost_chunk_count(),
+            lost_event_count: data.lost_event_count(),
+            wall_clock_start: data.wall_clock_start().into(),
+            build_id: data.build_id().map( |build_id| build_id.to_owned() ),
+            allocator: data.allocator_info().map( |info| protocol::ResponseAllocatorInfo {
+                allocator: info.allocator.clone(),
+                allocator_version: info.allocator_version.clone(),
+                libc_version: info.libc_version.clone(),
+                tunables: info.tunables.iter().map( |tunable| protocol::ResponseAllocatorTunable {
+                    name: tunable.name.clone(),
+                    value: tunable.value.clone()
+                }).collect()
+            }),
+            metadata: data.metadata().iter().cloned().collect(),
+            data_quality: crate::data_quality::get_data_quality( data ),
+            shard: shard.map( |shard| shard.to_string() )
         }
     }
 }
 
 fn handler_list( req: HttpRequest ) -> HttpResponse {
     let list: Vec< _ > = req.state().data.values().map( |data| {
-        protocol::ResponseMetadata::new( data )
+        protocol::ResponseMetadata::new( data, req.state().shard )
     }).collect();
 
     HttpResponse::Ok().json( list )
 }
 
-fn get_fragmentation_timeline( data: &Data ) -> protocol::ResponseFragmentationTimeline {
-    #[inline(always)]
-    fn is_matched( allocation: &Allocation ) -> bool {
-        allocation.in_main_arena() && !allocation.is_mmaped()
-    }
+fn handler_live_list( req: HttpRequest ) -> HttpResponse {
+    let list = match req.state().live {
+        Some( ref live ) => live.list(),
+        None => Vec::new()
+    };
 
-    let maximum_len = (data.last_timestamp().as_secs() - data.initial_timestamp().as_secs()) as usize;
-    let mut xs = Vec::with_capacity( maximum_len );
-    let mut x = (-1_i32) as u64;
-
-    let mut current_used_address_space = 0;
-    let mut fragmentation = Vec::with_capacity( maximum_len );
-    let mut address_map: BTreeMap< u64, i64 > = BTreeMap::new();
-    let mut current_address_min = std::u64::MAX;
-    let mut current_address_max = 0;
-
-    fn trim_front( address_map: &mut BTreeMap< u64, i64 > ) {
-        while let Some( (&address, &count) ) = address_map.range( (Unbounded as Bound< u64 >, Unbounded) ).next() {
-            if count == 0 {
-                address_map.remove( &address );
-            } else {
-                break;
-            }
-        }
-    }
+    HttpResponse::Ok().json( list )
+}
 
-    fn trim_back( address_map: &mut BTreeMap< u64, i64 > ) {
-        while let Some( (&address, &count) ) = address_map.range( (Unbounded as Bound< u64 >, Unbounded) ).rev().next() {
-            if count == 0 {
-                address_map.remove( &address );
-            } else {
-                break;
-            }
-        }
-    }
+fn handler_live_summary( req: HttpRequest ) -> Result< HttpResponse > {
+    let live = req.state().live.as_ref().ok_or_else( || ErrorNotFound( "live stream not found" ) )?;
+    let id: DataId = req.match_info().get( "id" ).unwrap().parse().map_err( |_| ErrorNotFound( "live stream not found" ) )?;
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestLiveSummary = query( &req )?;
 
-    fn min( address_map: &BTreeMap< u64, i64 > ) -> u64 {
-        for (&address, &count) in address_map.range( (Unbounded as Bound< u64 >, Unbounded) ) {
-            if count == 0 {
-                continue;
-            }
+    let (stream, data) = live.snapshot( id ).ok_or_else( || ErrorNotFound( "live stream not found" ) )?;
+    let response = protocol::ResponseLiveSummary {
+        stream,
+        summary: data.as_ref().map( |data| crate::live::get_live_summary( data, &backtrace_format, &params ) )
+    };
+
+    Ok( HttpResponse::Ok().json( response ) )
+}
 
-            return address;
+fn handler_plugins( req: HttpRequest ) -> HttpResponse {
+    let list: Vec< _ > = req.state().plugins.iter().map( |plugin| {
+        protocol::ResponsePlugin {
+            name: plugin.name().to_owned(),
+            endpoints: plugin.endpoints()
         }
+    }).collect();
+
+    HttpResponse::Ok().json( list )
+}
 
-        std::u64::MAX
+fn handler_plugin( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let name = req.match_info().get( "plugin" ).unwrap();
+    let endpoint = req.match_info().get( "endpoint" ).unwrap();
+    let plugin = req.state().plugins.get( name ).ok_or_else( || ErrorNotFound( "plugin not found" ) )?;
+    if !plugin.endpoints().iter().any( |plugin_endpoint| plugin_endpoint == endpoint ) {
+        return Err( ErrorNotFound( "endpoint not found" ) );
     }
 
-    fn max( address_map: &BTreeMap< u64, i64 > ) -> u64 {
-        for (&address, &count) in address_map.range( (Unbounded as Bound< u64 >, Unbounded) ).rev() {
-            if count == 0 {
-                continue;
-            }
+    let response = plugin.handle( data, endpoint, req.query_string() ).map_err( ErrorBadRequest )?;
+    Ok( HttpResponse::Ok().content_type( response.content_type ).body( response.body ) )
+}
 
-            return address;
-        }
+fn get_session_id( req: &HttpRequest ) -> &str {
+    req.match_info().get( "session" ).unwrap()
+}
+
+fn handler_session_create( req: HttpRequest ) -> HttpResponse {
+    let id = req.state().sessions.create();
+    req.state().save_workspace();
+    HttpResponse::Ok().json( protocol::ResponseNewSession { id } )
+}
+
+fn handler_session_get( req: HttpRequest ) -> Result< HttpResponse > {
+    let session = req.state().sessions.get( get_session_id( &req ) ).ok_or_else( || ErrorNotFound( "session not found" ) )?;
+    Ok( HttpResponse::Ok().json( session ) )
+}
 
-        0
+fn handler_session_update( req: HttpRequest, session: web::Json< protocol::Session > ) -> Result< HttpResponse > {
+    if !req.state().sessions.update( get_session_id( &req ), session.into_inner() ) {
+        return Err( ErrorNotFound( "session not found" ) );
     }
 
-    for op in data.operations() {
-        let timestamp = match op {
-            Operation::Allocation { allocation, .. } => {
-                if !is_matched( allocation ) {
-                    continue;
-                }
+    req.state().save_workspace();
+    Ok( HttpResponse::Ok().finish() )
+}
 
-                let range = allocation.actual_range( &data );
-                *address_map.entry( range.start ).or_insert( 0 ) += 1;
-                *address_map.entry( range.end ).or_insert( 0 ) += 1;
-                current_used_address_space += range.end - range.start;
+fn handler_session_delete( req: HttpRequest ) -> Result< HttpResponse > {
+    if !req.state().sessions.remove( get_session_id( &req ) ) {
+        return Err( ErrorNotFound( "session not found" ) );
+    }
 
-                if range.start < current_address_min {
-                    current_address_min = range.start;
-                }
+    req.state().save_workspace();
+    Ok( HttpResponse::Ok().finish() )
+}
 
-                if range.end > current_address_max {
-                    current_address_max = range.end;
-                }
+fn handler_annotations( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    Ok( HttpResponse::Ok().json( req.state().workspace.annotations( data.id() ) ) )
+}
 
-                allocation.timestamp
-            },
-            Operation::Deallocation { allocation, deallocation, .. } => {
-                if !is_matched( allocation ) {
-                    continue;
-                }
+fn handler_annotation_add( req: HttpRequest, request: web::Json< protocol::RequestAnnotation > ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let annotation = req.state().workspace.add_annotation( data.id(), request.into_inner() );
+    req.state().save_workspace();
+    Ok( HttpResponse::Ok().json( annotation ) )
+}
+
+fn handler_annotation_delete( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let id: u64 = req.match_info().get( "annotation" ).unwrap().parse().map_err( |_| ErrorNotFound( "annotation not found" ) )?;
+    if !req.state().workspace.remove_annotation( data.id(), id ) {
+        return Err( ErrorNotFound( "annotation not found" ) );
+    }
 
-                let range = allocation.actual_range( &data );
-                *address_map.entry( range.start ).or_insert( 0 ) -= 1;
-                *address_map.entry( range.end ).or_insert( 0 ) -= 1;
-                current_used_address_space -= range.end - range.start;
+    req.state().save_workspace();
+    Ok( HttpResponse::Ok().finish() )
+}
 
-                if range.start == current_address_min {
-                    trim_front( &mut address_map );
-                    current_address_min = min( &address_map );
-                }
+fn get_job_id( req: &HttpRequest ) -> &str {
+    req.match_info().get( "job" ).unwrap()
+}
 
-                if range.end == current_address_max {
-                    trim_back( &mut address_map );
-                    current_address_max = max( &address_map );
-                }
+fn handler_job_submit( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let params: protocol::RequestJob = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
 
-                deallocation.timestamp
-            },
-            Operation::Reallocation { new_allocation, old_allocation, .. } => {
-                if !is_matched( new_allocation ) && !is_matched( old_allocation ) {
-                    continue;
-                }
+    let data_id = data.id();
+    let state = req.state().clone();
+    let response = req.state().jobs.submit( data, params.kind, filter, params.collapse_recursion.unwrap_or( false ), move |job| {
+        job( state.data.get( &data_id ) );
+    }).map_err( |_| ErrorServiceUnavailable( "too many jobs are already queued; try again later" ) )?;
 
-                if is_matched( old_allocation ) {
-                    let old_range = old_allocation.actual_range( &data );
-                    *address_map.entry( old_range.start ).or_insert( 0 ) -= 1;
-                    *address_map.entry( old_range.end ).or_insert( 0 ) -= 1;
+    Ok( HttpResponse::Accepted().json( response ) )
+}
 
-                    current_used_address_space -= old_range.end - old_range.start;
+fn handler_job_get( req: HttpRequest ) -> Result< HttpResponse > {
+    let job = req.state().jobs.get( get_job_id( &req ) ).ok_or_else( || ErrorNotFound( "job not found" ) )?;
+    Ok( HttpResponse::Ok().json( job ) )
+}
 
-                    if old_range.start == current_address_min {
-                        trim_front( &mut address_map );
-                        current_address_min = min( &address_map );
-                    }
+fn handler_job_result( req: HttpRequest ) -> Result< HttpResponse > {
+    let (job, result) = req.state().jobs.open_result( get_job_id( &req ) ).ok_or_else( || ErrorNotFound( "job not found" ) )?;
+    let fp = match result {
+        Some( result ) => result?,
+        None => return Err( ErrorConflict( "job hasn't finished successfully" ) )
+    };
 
-                    if old_range.end == current_address_max {
-                        trim_back( &mut address_map );
-                        current_address_max = max( &address_map );
-                    }
-                }
+    let (tx, rx) = byte_channel();
+    let rx = rx.map_err( |_| ErrorInternalServerError( "internal error" ) );
+    let body = Body::Message( Box::new( BodyStream::new( rx ) ) );
+    thread::spawn( move || {
+        let _ = crate::jobs::send_file( fp, tx );
+    });
 
-                if is_matched( new_allocation ) {
-                    let new_range = new_allocation.actual_range( &data );
-                    *address_map.entry( new_range.start ).or_insert( 0 ) += 1;
-                    *address_map.entry( new_range.end ).or_insert( 0 ) += 1;
-                    current_used_address_space += new_range.end - new_range.start;
+    Ok( HttpResponse::Ok().content_type( crate::jobs::content_type( job.kind ) ).body( body ) )
+}
 
-                    if new_range.start < current_address_min {
-                        current_address_min = new_range.start;
-                    }
+fn handler_job_delete( req: HttpRequest ) -> Result< HttpResponse > {
+    if !req.state().jobs.remove( get_job_id( &req ) ) {
+        return Err( ErrorNotFound( "job not found" ) );
+    }
 
-                    if new_range.end > current_address_max {
-                        current_address_max = new_range.end;
-                    }
-                }
+    Ok( HttpResponse::Ok().finish() )
+}
 
-                new_allocation.timestamp
-            }
+#[cfg(feature = "scripting")]
+fn handler_script( req: HttpRequest, script: String ) -> Result< HttpResponse > {
+    let data_id = get_data_id( &req )?;
+    let state = req.state().clone();
+    crate::scripting::check_script( &state, data_id, &script ).map_err( |error| ErrorBadRequest( format!( "invalid script: {}", error ) ) )?;
+
+    let deadline = state.limits.timeout.map( |timeout| Instant::now() + timeout );
+    let body = async_data_handler( &req, move |_, tx| {
+        let response = match crate::scripting::run_script( &state, data_id, deadline, &script ) {
+            Ok( result ) => protocol::ResponseScript { result: Some( result ), error: None },
+            Err( error ) => protocol::ResponseScript { result: None, error: Some( error ) }
         };
 
-        let timestamp = timestamp.as_secs();
-        if timestamp != x {
-            if x != (-1_i32 as u64) && x + 1 != timestamp {
-                let last_fragmentation = fragmentation.last().cloned().unwrap();
+        let _ = serde_json::to_writer( tx, &response );
+    })?;
 
-                xs.push( x + 1 );
-                fragmentation.push( last_fragmentation );
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
 
-                if x + 2 != timestamp {
-                    xs.push( timestamp - 1 );
-                    fragmentation.push( last_fragmentation );
-                }
-            }
+fn handler_grafana_test( _req: HttpRequest ) -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
 
-            x = timestamp;
-            xs.push( x );
-            fragmentation.push( 0 );
-        }
+fn handler_grafana_search( req: HttpRequest, request: web::Json< protocol::RequestGrafanaSearch > ) -> HttpResponse {
+    let results = crate::grafana::search( req.state().data.values(), &request.target );
+    HttpResponse::Ok().json( results )
+}
 
-        let range = if current_address_max == 0 {
-            0
-        } else {
-            current_address_max - current_address_min
+fn handler_grafana_query( req: HttpRequest, request: web::Json< protocol::RequestGrafanaQuery > ) -> Result< HttpResponse > {
+    run_limited( &req, || {
+        let timeline_of = |data: &Data| {
+            req.state().precomputed.get( &data.id() ).and_then( |precomputed| precomputed.timeline() )
+                .unwrap_or_else( || Arc::new( get_timeline( data ) ) )
         };
 
-        *fragmentation.last_mut().unwrap() = range - current_used_address_space;
-    }
+        let results = crate::grafana::query( req.state().data.values(), timeline_of, &request ).map_err( ErrorBadRequest )?;
+        Ok( HttpResponse::Ok().json( results ) )
+    })
+}
 
-    protocol::ResponseFragmentationTimeline {
-        xs,
-        fragmentation
-    }
+fn handler_grafana_annotations( _req: HttpRequest ) -> HttpResponse {
+    HttpResponse::Ok().json( Vec::< () >::new() )
+}
+
+#[cfg(feature = "graphql")]
+fn handler_graphql( req: HttpRequest, request: web::Json< juniper::http::GraphQLRequest > ) -> Result< HttpResponse > {
+    let state = req.state().clone();
+    let body = async_handler( &req, move |tx| {
+        let context = crate::graphql::Context::new( state );
+        let response = request.execute_sync( &crate::graphql::schema(), &context );
+        let _ = serde_json::to_writer( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
 }
 
 fn handler_fragmentation_timeline( req: HttpRequest ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
-    let response = get_fragmentation_timeline( data );
+    let response = crate::fragmentation::get_fragmentation_timeline( data );
     Ok( HttpResponse::Ok().json( response ) )
 }
 
-fn handler_timeline( req: HttpRequest ) -> Result< HttpResponse > {
+fn handler_address_reuse( req: HttpRequest ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestAddressReuse = query( &req )?;
 
-    let maximum_len = (data.last_timestamp().as_secs() - data.initial_timestamp().as_secs()) as usize;
-    let mut xs = Vec::with_capacity( maximum_len );
-    let mut size_delta = Vec::with_capacity( maximum_len );
-    let mut count_delta = Vec::with_capacity( maximum_len );
-    let mut allocated_size = Vec::with_capacity( maximum_len );
-    let mut allocated_count = Vec::with_capacity( maximum_len );
-    let mut leaked_size = Vec::with_capacity( maximum_len );
-    let mut leaked_count = Vec::with_capacity( maximum_len );
-    let mut allocations = Vec::with_capacity( maximum_len );
-    let mut deallocations = Vec::with_capacity( maximum_len );
-    let mut x = (-1_i32) as u64;
+    let response = crate::address_reuse::get_address_reuse( data, &backtrace_format, &params );
+    Ok( HttpResponse::Ok().json( response ) )
+}
 
-    for op in data.operations() {
-        let timestamp = match op {
-            Operation::Allocation { ref allocation, .. } => allocation.timestamp,
-            Operation::Deallocation { ref deallocation, .. } => deallocation.timestamp,
-            Operation::Reallocation { ref new_allocation, .. } => new_allocation.timestamp
-        };
+fn handler_allocator_stats_timeline( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let response = crate::allocator_stats::get_allocator_stats_timeline( data );
+    Ok( HttpResponse::Ok().json( response ) )
+}
 
-        let timestamp = timestamp.as_secs();
-        assert!( x == (-1_i32 as u64) || timestamp >= x );
-
-        let timestamp_changed = timestamp != x;
-
-        if timestamp_changed {
-            let mut last_allocated_size = 0;
-            let mut last_allocated_count = 0;
-            let mut last_leaked_size = 0;
-            let mut last_leaked_count = 0;
-
-            if x != (-1_i32 as u64) {
-                last_allocated_size = allocated_size.last().cloned().unwrap();
-                last_allocated_count = allocated_count.last().cloned().unwrap();
-                last_leaked_size = leaked_size.last().cloned().unwrap();
-                last_leaked_count = leaked_count.last().cloned().unwrap();
-
-                if x + 1 != timestamp {
-                    xs.push( x + 1 );
-                    size_delta.push( 0 );
-                    count_delta.push( 0 );
-                    allocated_size.push( last_allocated_size );
-                    allocated_count.push( last_allocated_count );
-                    leaked_size.push( last_leaked_size );
-                    leaked_count.push( last_leaked_count );
-                    allocations.push( 0 );
-                    deallocations.push( 0 );
-
-                    if x + 2 != timestamp {
-                        xs.push( timestamp - 1 );
-                        size_delta.push( 0 );
-                        count_delta.push( 0 );
-                        allocated_size.push( last_allocated_size );
-                        allocated_count.push( last_allocated_count );
-                        leaked_size.push( last_leaked_size );
-                        leaked_count.push( last_leaked_count );
-                        allocations.push( 0 );
-                        deallocations.push( 0 );
-                    }
-                }
-            }
+fn handler_resident_memory_timeline( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let response = crate::allocator_stats::get_resident_memory_timeline( data );
+    Ok( HttpResponse::Ok().json( response ) )
+}
 
-            x = timestamp;
-            xs.push( x );
-            size_delta.push( 0 );
-            count_delta.push( 0 );
-            allocated_size.push( last_allocated_size );
-            allocated_count.push( last_allocated_count );
-            leaked_size.push( last_leaked_size );
-            leaked_count.push( last_leaked_count );
-            allocations.push( 0 );
-            deallocations.push( 0 );
-        }
+fn handler_arena_timeline( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let response = crate::arenas::get_arena_timeline( data );
+    Ok( HttpResponse::Ok().json( response ) )
+}
 
-        let allocations = allocations.last_mut().unwrap();
-        let deallocations = deallocations.last_mut().unwrap();
-        let allocated_size = allocated_size.last_mut().unwrap();
-        let allocated_count = allocated_count.last_mut().unwrap();
-        let size_delta = size_delta.last_mut().unwrap();
-        let count_delta = count_delta.last_mut().unwrap();
-        let leaked_size = leaked_size.last_mut().unwrap();
-        let leaked_count = leaked_count.last_mut().unwrap();
+fn handler_arena_trim( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestArenaTrim = query( &req )?;
 
-        let (size_delta_v, count_delta_v) = match op {
-            Operation::Allocation { allocation, .. } => {
-                *allocations += 1;
-                if allocation.deallocation.is_none() {
-                    *leaked_size += allocation.size;
-                    *leaked_count += 1;
-                }
+    let response = crate::arena_trim::get_arena_trim( data, &backtrace_format, &params );
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_maps( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestMappedRegions = query( &req )?;
+
+    let response = crate::mapped_regions::get_mapped_regions( data, &backtrace_format, &params );
+    Ok( HttpResponse::Ok().json( response ) )
+}
 
-                (allocation.size as i64, 1)
+fn get_timeline( data: &Data ) -> protocol::ResponseTimeline {
+    let maximum_len = (data.last_timestamp().as_secs() - data.initial_timestamp().as_secs()) as usize;
+    let mut timeline = Timeline::with_capacity( maximum_len );
+    for op in data.operations() {
+        let (timestamp, operation) = match op {
+            Operation::Allocation { allocation, .. } => {
+                (allocation.timestamp, TimelineOperation::Allocation { size: allocation.size, is_leaked: allocation.deallocation.is_none() })
             },
-            Operation::Deallocation { allocation, .. } => {
-                *deallocations += 1;
-                (allocation.size as i64 * -1, -1)
+            Operation::Deallocation { allocation, deallocation, .. } => {
+                (deallocation.timestamp, TimelineOperation::Deallocation { size: allocation.size })
             },
             Operation::Reallocation { new_allocation, old_allocation, .. } => {
-                *allocations += 1;
-                *deallocations += 1;
-                if new_allocation.deallocation.is_none() {
-                    *leaked_size += new_allocation.size;
-                    *leaked_count += 1;
-                }
+                let operation = TimelineOperation::Reallocation {
+                    old_size: old_allocation.size,
+                    new_size: new_allocation.size,
+                    is_leaked: new_allocation.deallocation.is_none()
+                };
 
-                (new_allocation.size as i64 - old_allocation.size as i64, 0)
+                (new_allocation.timestamp, operation)
             }
         };
 
-        *allocated_size = (*allocated_size as i64 + size_delta_v) as _;
-        *allocated_count = (*allocated_count as i64 + count_delta_v) as _;
-        *size_delta = (*size_delta as i64 + size_delta_v) as _;
-        *count_delta = (*count_delta as i64 + count_delta_v) as _;
+        timeline.add( timestamp.as_secs(), operation );
     }
 
-    let timeline = protocol::ResponseTimeline {
-        xs,
-        size_delta,
-        count_delta,
-        allocated_size,
-        allocated_count,
-        leaked_size,
-        leaked_count,
-        allocations,
-        deallocations
-    };
+    timeline
+}
+
+fn handler_timeline( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let cache_key = get_response_cache_key( &req, "application/json" )?;
+    if let Some( timeline ) = req.state().precomputed.get( &data.id() ).and_then( |precomputed| precomputed.timeline_json() ) {
+        return Ok( precomputed_response( &req, &cache_key, (*timeline).clone() ) );
+    }
+
+    if let Some( response ) = cached_response( &req, &cache_key ) {
+        return Ok( response );
+    }
+
+    async_cached_data_handler( &req, cache_key, move |data, tx| {
+        let _ = serde_json::to_writer( tx, &get_timeline( data ) );
+    })
+}
+
+fn handler_histograms( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let cache_key = get_response_cache_key( &req, "application/json" )?;
+    if req.query_string().is_empty() {
+        if let Some( histograms ) = req.state().precomputed.get( &data.id() ).and_then( |precomputed| precomputed.histograms() ) {
+            return Ok( precomputed_response( &req, &cache_key, (*histograms).clone() ) );
+        }
+    }
+
+    if let Some( response ) = cached_response( &req, &cache_key ) {
+        return Ok( response );
+    }
 
-    Ok( HttpResponse::Ok().json( timeline ) )
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
+    let columns = get_precomputed_columns( &req, data.id() );
+    async_cached_data_handler( &req, cache_key, move |data, tx| {
+        let _ = serde_json::to_writer( tx, &crate::histograms::get_histograms( data, columns.as_ref().map( |columns| &**columns ), &filter ) );
+    })
 }
 
 fn allocations_iter< 'a >( data: &'a Data, sort_by: protocol::AllocSortBy, order: protocol::Order, filter: &Filter ) -> impl DoubleEndedIterator< Item = (AllocationId, &'a Allocation) > {
@@ -640,7 +970,7 @@ fn timestamp_to_fraction( data: &Data, timestamp: Timestamp ) -> f32 {
     (relative.as_usecs() as f64 / range.as_usecs() as f64) as f32
 }
 
-fn get_allocations< 'a >( data: &'a Data, backtrace_format: protocol::BacktraceFormat, params: protocol::RequestAllocations, filter: Filter ) -> protocol::ResponseAllocations< impl Serialize + 'a > {
+fn get_allocations< 'a >( data: &'a Data, backtrace_format: protocol::BacktraceFormat, params: protocol::RequestAllocations, filter: Filter ) -> protocol::ResponseAllocations< impl Serialize + Rows + 'a > {
     let remaining = params.count.unwrap_or( -1_i32 as _ ) as usize;
     let skip = params.skip.unwrap_or( 0 ) as usize;
     let sort_by = params.sort_by.unwrap_or( protocol::AllocSortBy::Timestamp );
@@ -655,12 +985,13 @@ fn get_allocations< 'a >( data: &'a Data, backtrace_format: protocol::BacktraceF
         let backtrace_format = backtrace_format.clone();
         let filter = filter.clone();
 
+        let column_filter = filter.clone();
+
         allocations_iter( data, sort_by, order, &filter )
-            .map( |(_, allocation)| allocation )
-            .filter( move |allocation| match_allocation( data, allocation, &filter ) )
+            .filter( move |(_, allocation)| match_allocation( data, allocation, &filter ) )
             .skip( skip )
             .take( remaining )
-            .map( move |allocation| {
+            .map( move |(allocation_id, allocation)| {
                 let backtrace = data.get_backtrace( allocation.backtrace ).map( |(_, frame)| get_frame( data, &backtrace_format, frame ) ).collect();
                 protocol::Allocation {
                     address: allocation.pointer,
@@ -674,13 +1005,22 @@ fn get_allocations< 'a >( data: &'a Data, backtrace_format: protocol::BacktraceF
                     deallocation: allocation.deallocation.as_ref().map( |deallocation| {
                         protocol::Deallocation {
                             timestamp: deallocation.timestamp.into(),
-                            thread: deallocation.thread
+                            thread: deallocation.thread,
+                            backtrace_id: deallocation.backtrace.map( |backtrace_id| backtrace_id.raw() ),
+                            backtrace: deallocation.backtrace.map( |backtrace_id| {
+                                data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, &backtrace_format, frame ) ).collect()
+                            })
                         }
                     }),
                     backtrace,
                     in_main_arena: !allocation.in_non_main_arena(),
                     is_mmaped: allocation.is_mmaped(),
-                    extra_space: allocation.extra_usable_space
+                    extra_space: allocation.extra_usable_space,
+                    jemalloc_arena: allocation.jemalloc_arena(),
+                    contents: data.get_allocation_contents( allocation_id ).map( |contents| {
+                        contents.iter().map( |byte| format!( "{:02x}", byte ) ).collect()
+                    }),
+                    columns: crate::virtual_columns::get_columns( data, allocation, &column_filter )
                 }
             })
     };
@@ -693,61 +1033,126 @@ fn get_allocations< 'a >( data: &'a Data, backtrace_format: protocol::BacktraceF
 
 fn handler_allocations( req: HttpRequest ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
+    let format = RowFormat::from_request( &req );
     let params: protocol::RequestAllocations = query( &req )?;
     let filter: protocol::AllocFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
     let backtrace_format: protocol::BacktraceFormat = query( &req )?;
 
     let body = async_data_handler( &req, move |data, tx| {
         let response = get_allocations( data, backtrace_format, params, filter );
-        let _ = serde_json::to_writer( tx, &response );
+        let _ = write_response( format, tx, &response, &response.allocations );
     })?;
 
-    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+    Ok( HttpResponse::Ok().content_type( format.content_type() ).body( body ) )
 }
 
-fn get_allocation_group_data< 'a, I >( data: &Data, iter: I ) -> protocol::AllocationGroupData
-    where I: IntoIterator< Item = &'a Allocation >, <I as IntoIterator>::IntoIter: ExactSizeIterator
-{
-    let iter = iter.into_iter();
-    assert_ne!( iter.len(), 0 );
-
-    let mut size_sum = 0;
-    let mut min_size = -1_i32 as _;
-    let mut max_size = 0;
-    let mut min_timestamp = Timestamp::max();
-    let mut max_timestamp = Timestamp::min();
-    let mut leaked_count = 0;
-    let mut allocated_count = 0;
-    for allocation in iter {
+/// Returns the 50th, 90th and 99th percentile of the given lifetimes, where `None` stands
+/// for an allocation which was never deallocated and is considered to live forever.
+fn lifetime_percentiles( mut lifetimes: Vec< Option< Timestamp > > ) -> [Option< Timestamp >; 3] {
+    lifetimes.sort_by_key( |lifetime| (lifetime.is_none(), *lifetime) );
+    let percentile = |percentile: usize| {
+        let rank = (lifetimes.len() * percentile + 99) / 100;
+        lifetimes.get( rank.saturating_sub( 1 ) ).cloned().unwrap_or( None )
+    };
+
+    [percentile( 50 ), percentile( 90 ), percentile( 99 )]
+}
+
+#[test]
+fn test_lifetime_percentiles() {
+    let lifetimes = |values: &[Option< u64 >]| values.iter().map( |value| value.map( Timestamp::from_usecs ) ).collect();
+    assert_eq!( lifetime_percentiles( lifetimes( &[Some( 1 )] ) ), [Some( Timestamp::from_usecs( 1 ) ); 3] );
+    assert_eq!(
+        lifetime_percentiles( lifetimes( &[None, Some( 3 ), Some( 1 ), Some( 2 ), Some( 4 ), Some( 5 ), Some( 6 ), Some( 7 ), Some( 8 ), Some( 9 )] ) ),
+        [Some( Timestamp::from_usecs( 5 ) ), Some( Timestamp::from_usecs( 9 ) ), None]
+    );
+    assert_eq!( lifetime_percentiles( Vec::new() ), [None, None, None] );
+}
+
+/// Accumulates the statistics of a group of allocations one allocation at a time.
+struct AllocationGroupDataBuilder {
+    size_sum: u64,
+    min_size: u64,
+    max_size: u64,
+    min_timestamp: Timestamp,
+    max_timestamp: Timestamp,
+    leaked_count: u64,
+    allocated_count: u64,
+    lifetimes: Vec< Option< Timestamp > >,
+    byte_usecs: u128
+}
+
+impl AllocationGroupDataBuilder {
+    fn new() -> Self {
+        AllocationGroupDataBuilder {
+            size_sum: 0,
+            min_size: -1_i32 as _,
+            max_size: 0,
+            min_timestamp: Timestamp::max(),
+            max_timestamp: Timestamp::min(),
+            leaked_count: 0,
+            allocated_count: 0,
+            lifetimes: Vec::new(),
+            byte_usecs: 0
+        }
+    }
+
+    fn add( &mut self, data: &Data, allocation: &Allocation ) {
+        self.byte_usecs += data.byte_microseconds( allocation );
+        self.lifetimes.push( allocation.deallocation.as_ref().map( |deallocation| deallocation.timestamp - allocation.timestamp ) );
         let size = allocation.size;
         let timestamp = allocation.timestamp;
-        size_sum += size;
-        min_size = min( min_size, size );
-        max_size = max( max_size, size );
-        min_timestamp = min( min_timestamp, timestamp );
-        max_timestamp = max( max_timestamp, timestamp );
+        self.size_sum += size;
+        self.min_size = min( self.min_size, size );
+        self.max_size = max( self.max_size, size );
+        self.min_timestamp = min( self.min_timestamp, timestamp );
+        self.max_timestamp = max( self.max_timestamp, timestamp );
 
-        allocated_count += 1;
+        self.allocated_count += 1;
         if allocation.deallocation.is_none() {
-            leaked_count += 1;
+            self.leaked_count += 1;
         }
     }
 
-    protocol::AllocationGroupData {
-        leaked_count,
-        allocated_count,
-        size: size_sum,
-        min_size,
-        max_size,
-        min_timestamp: min_timestamp.into(),
-        min_timestamp_relative: (min_timestamp - data.initial_timestamp()).into(),
-        min_timestamp_relative_p: timestamp_to_fraction( data, min_timestamp ),
-        max_timestamp: max_timestamp.into(),
-        max_timestamp_relative: (max_timestamp - data.initial_timestamp()).into(),
-        max_timestamp_relative_p: timestamp_to_fraction( data, max_timestamp ),
-        interval: (max_timestamp - min_timestamp).into()
+    fn build( self, data: &Data ) -> protocol::AllocationGroupData {
+        assert_ne!( self.allocated_count, 0 );
+
+        let [lifetime_p50, lifetime_p90, lifetime_p99] = lifetime_percentiles( self.lifetimes );
+        let byte_seconds = (self.byte_usecs / 1_000_000) as u64;
+        let (min_timestamp, max_timestamp) = (self.min_timestamp, self.max_timestamp);
+        protocol::AllocationGroupData {
+            leaked_count: self.leaked_count,
+            allocated_count: self.allocated_count,
+            size: self.size_sum,
+            min_size: self.min_size,
+            max_size: self.max_size,
+            min_timestamp: min_timestamp.into(),
+            min_timestamp_relative: (min_timestamp - data.initial_timestamp()).into(),
+            min_timestamp_relative_p: timestamp_to_fraction( data, min_timestamp ),
+            max_timestamp: max_timestamp.into(),
+            max_timestamp_relative: (max_timestamp - data.initial_timestamp()).into(),
+            max_timestamp_relative_p: timestamp_to_fraction( data, max_timestamp ),
+            interval: (max_timestamp - min_timestamp).into(),
+            lifetime_p50: lifetime_p50.map( |lifetime| lifetime.into() ),
+            lifetime_p90: lifetime_p90.map( |lifetime| lifetime.into() ),
+            lifetime_p99: lifetime_p99.map( |lifetime| lifetime.into() ),
+            byte_seconds
+        }
+    }
+}
+
+fn get_allocation_group_data< 'a, I >( data: &Data, iter: I ) -> protocol::AllocationGroupData
+    where I: IntoIterator< Item = &'a Allocation >, <I as IntoIterator>::IntoIter: ExactSizeIterator
+{
+    let iter = iter.into_iter();
+    let mut builder = AllocationGroupDataBuilder::new();
+    builder.lifetimes.reserve( iter.len() );
+    for allocation in iter {
+        builder.add( data, allocation );
     }
+
+    builder.build( data )
 }
 
 fn get_global_group_data( data: &Data, backtrace_id: BacktraceId ) -> protocol::AllocationGroupData {
@@ -761,6 +1166,12 @@ fn get_global_group_data( data: &Data, backtrace_id: BacktraceId ) -> protocol::
     let min_timestamp = stats.first_allocation;
     let max_timestamp = stats.last_allocation;
 
+    let lifetimes = data.get_allocations_by_backtrace( backtrace_id ).map( |(_, allocation)| {
+        allocation.deallocation.as_ref().map( |deallocation| deallocation.timestamp - allocation.timestamp )
+    }).collect();
+    let [lifetime_p50, lifetime_p90, lifetime_p99] = lifetime_percentiles( lifetimes );
+    let byte_seconds = data.byte_seconds( data.get_allocations_by_backtrace( backtrace_id ).map( |(_, allocation)| allocation ) );
+
     protocol::AllocationGroupData {
         leaked_count,
         allocated_count,
@@ -773,7 +1184,11 @@ fn get_global_group_data( data: &Data, backtrace_id: BacktraceId ) -> protocol::
         max_timestamp: max_timestamp.into(),
         max_timestamp_relative: (max_timestamp - data.initial_timestamp()).into(),
         max_timestamp_relative_p: timestamp_to_fraction( data, max_timestamp ),
-        interval: (max_timestamp - min_timestamp).into()
+        interval: (max_timestamp - min_timestamp).into(),
+        lifetime_p50: lifetime_p50.map( |lifetime| lifetime.into() ),
+        lifetime_p90: lifetime_p90.map( |lifetime| lifetime.into() ),
+        lifetime_p99: lifetime_p99.map( |lifetime| lifetime.into() ),
+        byte_seconds
     }
 }
 
@@ -782,7 +1197,7 @@ fn get_allocation_groups< 'a >(
     backtrace_format: protocol::BacktraceFormat,
     params: protocol::RequestAllocationGroups,
     allocation_groups: Arc< AllocationGroups >
-) -> protocol::ResponseAllocationGroups< impl Serialize + 'a > {
+) -> protocol::ResponseAllocationGroups< impl Serialize + Rows + 'a > {
     let remaining = params.count.unwrap_or( -1_i32 as _ ) as usize;
     let skip = params.skip.unwrap_or( 0 ) as usize;
 
@@ -802,6 +1217,8 @@ fn get_allocation_groups< 'a >(
                     all,
                     only_matched,
                     backtrace_id: backtrace_id.raw(),
+                    site_id: data.get_site_id( backtrace_id ).to_string(),
+                    classification: site_classification::classify_site( data, backtrace_id ),
                     backtrace
                 }
             })
@@ -812,156 +1229,343 @@ fn get_allocation_groups< 'a >(
         total_count: total_count as _
     };
 
-    response
+    response
+}
+
+#[derive(PartialEq, Eq)]
+struct Reverse< T >( T );
+
+impl< T > PartialOrd for Reverse< T > where T: PartialOrd {
+    #[inline]
+    fn partial_cmp( &self, rhs: &Reverse< T > ) -> Option< Ordering > {
+        self.0.partial_cmp( &rhs.0 ).map( Ordering::reverse )
+    }
+}
+
+impl< T > Ord for Reverse< T > where T: Ord {
+    #[inline]
+    fn cmp( &self, rhs: &Reverse< T > ) -> Ordering {
+        self.0.cmp( &rhs.0 ).reverse()
+    }
+}
+
+fn sort_groups< T, F >( data: &Data, groups: &mut AllocationGroups, order: protocol::Order, is_global: bool, callback: F )
+    where F: Fn( &protocol::AllocationGroupData ) -> T,
+          T: Ord
+{
+    // Computing the group data isn't cheap, so it's done only once per group.
+    groups.allocations_by_backtrace.sort_by_cached_key( |(&backtrace_id, ids)| {
+        let group_data = if is_global {
+            get_global_group_data( data, backtrace_id )
+        } else {
+            let allocations = ids.iter().map( |&id| data.get_allocation( id ) );
+            get_allocation_group_data( data, allocations )
+        };
+        callback( &group_data )
+    });
+
+    match order {
+        protocol::Order::Asc => {},
+        protocol::Order::Dsc => {
+            groups.allocations_by_backtrace.reverse();
+        }
+    }
+}
+
+fn build_allocation_groups( data: &Data, columns: Option< &AllocationColumns >, filter: &Filter, sort_by: protocol::AllocGroupsSortBy, order: protocol::Order ) -> AllocationGroups {
+    let mut groups = match columns {
+        Some( columns ) => AllocationGroups::from_selection( columns, &select_allocations( data, columns, filter ) ),
+        None => {
+            let iter =
+                allocations_iter( data, Default::default(), Default::default(), filter )
+                    .filter( move |(_, allocation)| match_allocation( data, allocation, filter ) );
+
+            AllocationGroups::new( iter )
+        }
+    };
+
+    match sort_by {
+        protocol::AllocGroupsSortBy::MinTimestamp => {
+            sort_groups( data, &mut groups, order, false, |group_data| group_data.min_timestamp.clone() );
+        },
+        protocol::AllocGroupsSortBy::MaxTimestamp => {
+            sort_groups( data, &mut groups, order, false, |group_data| group_data.max_timestamp.clone() );
+        },
+        protocol::AllocGroupsSortBy::Interval => {
+            sort_groups( data, &mut groups, order, false, |group_data| group_data.interval.clone() );
+        },
+        protocol::AllocGroupsSortBy::AllocatedCount => {
+            sort_groups( data, &mut groups, order, false, |group_data| group_data.allocated_count );
+        },
+        protocol::AllocGroupsSortBy::LeakedCount => {
+            sort_groups( data, &mut groups, order, false, |group_data| group_data.leaked_count );
+        },
+        protocol::AllocGroupsSortBy::Size => {
+            sort_groups( data, &mut groups, order, false, |group_data| group_data.size );
+        },
+        protocol::AllocGroupsSortBy::ByteSeconds => {
+            sort_groups( data, &mut groups, order, false, |group_data| group_data.byte_seconds );
+        },
+        protocol::AllocGroupsSortBy::GlobalMinTimestamp => {
+            sort_groups( data, &mut groups, order, true, |group_data| group_data.min_timestamp.clone() );
+        },
+        protocol::AllocGroupsSortBy::GlobalMaxTimestamp => {
+            sort_groups( data, &mut groups, order, true, |group_data| group_data.max_timestamp.clone() );
+        },
+        protocol::AllocGroupsSortBy::GlobalInterval => {
+            sort_groups( data, &mut groups, order, true, |group_data| group_data.interval.clone() );
+        },
+        protocol::AllocGroupsSortBy::GlobalAllocatedCount => {
+            sort_groups( data, &mut groups, order, true, |group_data| group_data.allocated_count );
+        },
+        protocol::AllocGroupsSortBy::GlobalLeakedCount => {
+            sort_groups( data, &mut groups, order, true, |group_data| group_data.leaked_count );
+        },
+        protocol::AllocGroupsSortBy::GlobalSize => {
+            sort_groups( data, &mut groups, order, true, |group_data| group_data.size );
+        },
+        protocol::AllocGroupsSortBy::GlobalByteSeconds => {
+            sort_groups( data, &mut groups, order, true, |group_data| group_data.byte_seconds );
+        }
+    }
+
+    groups
+}
+
+fn handler_allocation_groups( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let format = RowFormat::from_request( &req );
+    let cache_key = get_response_cache_key( &req, format.content_type() )?;
+    if let Some( response ) = cached_response( &req, &cache_key ) {
+        return Ok( response );
+    }
+
+    let filter_params: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter_params, req.state().limits.memory_budget )?;
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestAllocationGroups = query( &req )?;
+
+    let key = AllocationGroupsKey {
+        data_id: data.id(),
+        filter: filter_params,
+        sort_by: params.sort_by.unwrap_or( protocol::AllocGroupsSortBy::MinTimestamp ),
+        order: params.order.unwrap_or( protocol::Order::Asc )
+    };
+
+    let groups = req.state().allocation_group_cache.lock().get( &key ).cloned()
+        .or_else( || req.state().precomputed.get( &key.data_id ).and_then( |precomputed| precomputed.allocation_groups( &key ) ) );
+
+    let allocation_groups;
+    if let Some( groups ) = groups {
+        allocation_groups = groups;
+    } else {
+        let columns = get_precomputed_columns( &req, key.data_id );
+        let groups = build_allocation_groups( data, columns.as_ref().map( |columns| &**columns ), &filter, key.sort_by, key.order );
+        allocation_groups = Arc::new( groups );
+        req.state().allocation_group_cache.lock().put( key, allocation_groups.clone() );
+    }
+
+    async_cached_data_handler( &req, cache_key, move |data, tx| {
+        let response = get_allocation_groups( data, backtrace_format, params, allocation_groups );
+        let _ = write_response( format, tx, &response, &response.allocations );
+    })
+}
+
+fn handler_churn( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let format = RowFormat::from_request( &req );
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
+    let columns = get_precomputed_columns( &req, data.id() );
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestChurn = query( &req )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let response = crate::churn::get_churn( data, columns.as_ref().map( |columns| &**columns ), backtrace_format, params, filter );
+        let _ = write_response( format, tx, &response, &response.groups );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( format.content_type() ).body( body ) )
+}
+
+fn handler_column_groups( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
+    let columns = get_precomputed_columns( &req, data.id() );
+    let params: protocol::RequestColumnGroups = query( &req )?;
+    let group_by = Expression::parse( &params.group_by, &filter.columns, req.state().limits.memory_budget )
+        .map_err( |err| ErrorBadRequest( format!( "invalid 'group_by': {}", err ) ) )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let response = crate::virtual_columns::get_column_groups( data, columns.as_ref().map( |columns| &**columns ), &group_by, params, filter );
+        let _ = serde_json::to_writer( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
 }
 
-#[derive(PartialEq, Eq)]
-struct Reverse< T >( T );
+fn handler_overhead( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let format = RowFormat::from_request( &req );
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
+    let columns = get_precomputed_columns( &req, data.id() );
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestOverhead = query( &req )?;
 
-impl< T > PartialOrd for Reverse< T > where T: PartialOrd {
-    #[inline]
-    fn partial_cmp( &self, rhs: &Reverse< T > ) -> Option< Ordering > {
-        self.0.partial_cmp( &rhs.0 ).map( Ordering::reverse )
-    }
-}
+    let body = async_data_handler( &req, move |data, tx| {
+        let response = crate::overhead::get_overhead( data, columns.as_ref().map( |columns| &**columns ), backtrace_format, params, filter );
+        let _ = write_response( format, tx, &response, &response.groups );
+    })?;
 
-impl< T > Ord for Reverse< T > where T: Ord {
-    #[inline]
-    fn cmp( &self, rhs: &Reverse< T > ) -> Ordering {
-        self.0.cmp( &rhs.0 ).reverse()
-    }
+    Ok( HttpResponse::Ok().content_type( format.content_type() ).body( body ) )
 }
 
-fn handler_allocation_groups( req: HttpRequest ) -> Result< HttpResponse > {
+fn site_pairs_handler( req: HttpRequest, only_cross_thread: bool ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
-    let filter_params: protocol::AllocFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter_params )?;
+    let format = RowFormat::from_request( &req );
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
+    let columns = get_precomputed_columns( &req, data.id() );
     let backtrace_format: protocol::BacktraceFormat = query( &req )?;
-    let params: protocol::RequestAllocationGroups = query( &req )?;
+    let params: protocol::RequestSitePairs = query( &req )?;
 
-    let key = AllocationGroupsKey {
-        data_id: data.id(),
-        filter: filter_params,
-        sort_by: params.sort_by.unwrap_or( protocol::AllocGroupsSortBy::MinTimestamp ),
-        order: params.order.unwrap_or( protocol::Order::Asc )
-    };
+    let body = async_data_handler( &req, move |data, tx| {
+        let response = crate::site_pairs::get_site_pairs( data, columns.as_ref().map( |columns| &**columns ), backtrace_format, params, filter, only_cross_thread );
+        let _ = write_response( format, tx, &response, &response.groups );
+    })?;
 
-    let groups = req.state().allocation_group_cache.lock().get( &key ).cloned();
+    Ok( HttpResponse::Ok().content_type( format.content_type() ).body( body ) )
+}
 
-    fn sort_by< T, F >( data: &Data, groups: &mut AllocationGroups, order: protocol::Order, is_global: bool, callback: F )
-        where F: Fn( &protocol::AllocationGroupData ) -> T,
-              T: Ord
-    {
-        groups.allocations_by_backtrace.sort_by_key( |(&backtrace_id, ids)| {
-            let group_data = if is_global {
-                get_global_group_data( data, backtrace_id )
-            } else {
-                let allocations = ids.iter().map( |&id| data.get_allocation( id ) );
-                get_allocation_group_data( data, allocations )
-            };
-            callback( &group_data )
-        });
+fn handler_cross_thread_frees( req: HttpRequest ) -> Result< HttpResponse > {
+    site_pairs_handler( req, true )
+}
 
-        match order {
-            protocol::Order::Asc => {},
-            protocol::Order::Dsc => {
-                groups.allocations_by_backtrace.reverse();
-            }
-        }
-    }
+fn handler_site_pairs( req: HttpRequest ) -> Result< HttpResponse > {
+    site_pairs_handler( req, false )
+}
 
-    let allocation_groups;
-    if let Some( groups ) = groups {
-        allocation_groups = groups;
-    } else {
-        let iter =
-            allocations_iter( data, Default::default(), Default::default(), &filter )
-                .filter( move |(_, allocation)| match_allocation( data, allocation, &filter ) );
-
-        let mut groups = AllocationGroups::new( iter );
-        match key.sort_by {
-            protocol::AllocGroupsSortBy::MinTimestamp => {
-                sort_by( data, &mut groups, key.order, false, |group_data| group_data.min_timestamp.clone() );
-            },
-            protocol::AllocGroupsSortBy::MaxTimestamp => {
-                sort_by( data, &mut groups, key.order, false, |group_data| group_data.max_timestamp.clone() );
-            },
-            protocol::AllocGroupsSortBy::Interval => {
-                sort_by( data, &mut groups, key.order, false, |group_data| group_data.interval.clone() );
-            },
-            protocol::AllocGroupsSortBy::AllocatedCount => {
-                sort_by( data, &mut groups, key.order, false, |group_data| group_data.allocated_count );
-            },
-            protocol::AllocGroupsSortBy::LeakedCount => {
-                sort_by( data, &mut groups, key.order, false, |group_data| group_data.leaked_count );
-            },
-            protocol::AllocGroupsSortBy::Size => {
-                sort_by( data, &mut groups, key.order, false, |group_data| group_data.size );
-            },
-            protocol::AllocGroupsSortBy::GlobalMinTimestamp => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.min_timestamp.clone() );
-            },
-            protocol::AllocGroupsSortBy::GlobalMaxTimestamp => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.max_timestamp.clone() );
-            },
-            protocol::AllocGroupsSortBy::GlobalInterval => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.interval.clone() );
-            },
-            protocol::AllocGroupsSortBy::GlobalAllocatedCount => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.allocated_count );
-            },
-            protocol::AllocGroupsSortBy::GlobalLeakedCount => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.leaked_count );
-            },
-            protocol::AllocGroupsSortBy::GlobalSize => {
-                sort_by( data, &mut groups, key.order, true, |group_data| group_data.size );
-            }
-        }
+fn handler_backtrace_clusters( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let format = RowFormat::from_request( &req );
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
+    let columns = get_precomputed_columns( &req, data.id() );
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestBacktraceClusters = query( &req )?;
 
-        allocation_groups = Arc::new( groups );
-        req.state().allocation_group_cache.lock().put( key, allocation_groups.clone() );
-    }
+    let body = async_data_handler( &req, move |data, tx| {
+        let response = crate::backtrace_clusters::get_backtrace_clusters( data, columns.as_ref().map( |columns| &**columns ), backtrace_format, params, filter );
+        let _ = write_response( format, tx, &response, &response.clusters );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( format.content_type() ).body( body ) )
+}
+
+fn handler_size_class_waste( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let format = RowFormat::from_request( &req );
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
+    let columns = get_precomputed_columns( &req, data.id() );
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestSizeClassWaste = query( &req )?;
 
     let body = async_data_handler( &req, move |data, tx| {
-        let response = get_allocation_groups( data, backtrace_format, params, allocation_groups );
-        let _ = serde_json::to_writer( tx, &response );
+        let response = crate::size_classes::get_size_class_waste( data, columns.as_ref().map( |columns| &**columns ), backtrace_format, params, filter );
+        let _ = write_response( format, tx, &response, &response.groups );
     })?;
 
-    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+    Ok( HttpResponse::Ok().content_type( format.content_type() ).body( body ) )
 }
 
-fn handler_raw_allocations( req: HttpRequest ) -> Result< HttpResponse > {
+fn handler_top_sites( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestTopSites = query( &req )?;
+
+    let response = crate::top_sites::get_top_sites( data, &backtrace_format, &params );
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_container_growth( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestContainerGrowth = query( &req )?;
+
+    let response = crate::container_growth::get_container_growth( data, &backtrace_format, &params );
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn get_core( req: &HttpRequest ) -> Result< (&Data, &CoreMemory) > {
+    let id = get_data_id( req )?;
+    let memory = req.state().cores.get( &id ).ok_or_else( || ErrorNotFound( "no core dump was loaded for this data" ) )?;
+    Ok( (&req.state().data[ &id ], memory) )
+}
+
+fn handler_core_summary( req: HttpRequest ) -> Result< HttpResponse > {
+    let (data, memory) = get_core( &req )?;
+    let response = crate::core_memory::get_core_summary( data, memory );
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_core_memory( req: HttpRequest ) -> Result< HttpResponse > {
+    let (data, memory) = get_core( &req )?;
+    let params: protocol::RequestCoreMemory = query( &req )?;
+    let response = crate::core_memory::get_core_memory( data, memory, &params ).ok_or_else( || ErrorNotFound( "address not found in the core dump" ) )?;
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_peaks( req: HttpRequest ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
-    let iter = data.alloc_sorted_by_timestamp( None, None );
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestPeaks = query( &req )?;
+
+    let response = crate::peaks::get_peaks( data, &backtrace_format, &params );
+    Ok( HttpResponse::Ok().json( response ) )
+}
 
-    let mut output = String::new();
-    output.push_str( "[" );
+fn write_raw_allocations< T: fmt::Write >( data: &Data, output: &mut T ) -> fmt::Result {
+    output.write_str( "[" )?;
 
     let mut is_first = true;
-    for (_, allocation) in iter {
+    for (_, allocation) in data.alloc_sorted_by_timestamp( None, None ) {
         if !is_first {
-            output.push_str( "," );
+            output.write_str( "," )?;
         } else {
             is_first = false;
         }
 
-        output.push_str( "{\"backtrace\":[" );
+        output.write_str( "{\"backtrace\":[" )?;
         let mut is_first = true;
         for (_, frame) in data.get_backtrace( allocation.backtrace ) {
             if !is_first {
-                output.push_str( "," );
+                output.write_str( "," )?;
             } else {
                 is_first = false;
             }
 
             let address = frame.address().raw();
-            write!( output, "\"{:016X}\"", address ).unwrap();
+            write!( output, "\"{:016X}\"", address )?;
         }
-        output.push_str( "]}" );
+        output.write_str( "]}" )?;
     }
 
-    output.push_str( "]" );
-    Ok( HttpResponse::Ok().content_type( "application/json" ).body( output ) )
+    output.write_str( "]" )
+}
+
+fn handler_raw_allocations( req: HttpRequest ) -> Result< HttpResponse > {
+    get_data( &req )?;
+
+    // This can easily be bigger than the data itself, so it needs to be streamed
+    // instead of being built in memory in its entirety.
+    let body = async_data_handler( &req, move |data, mut tx| {
+        let _ = write_raw_allocations( data, &mut tx );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
 }
 
 fn dump_node< T: fmt::Write, K: PartialEq + Clone, V, F: Fn( &mut T, &V ) -> fmt::Result >(
@@ -975,6 +1579,8 @@ fn dump_node< T: fmt::Write, K: PartialEq + Clone, V, F: Fn( &mut T, &V ) -> fmt
     let node = tree.get_node( node_id );
     write!( output, "\"size\":{},", node.total_size )?;
     write!( output, "\"count\":{},", node.total_count )?;
+    write!( output, "\"self_size\":{},", node.self_size )?;
+    write!( output, "\"self_count\":{},", node.self_count )?;
     write!( output, "\"first\":{},", node.total_first_timestamp.as_secs() )?;
     write!( output, "\"last\":{},", node.total_last_timestamp.as_secs() )?;
     if let Some( value ) = node.value() {
@@ -1000,26 +1606,46 @@ fn dump_node< T: fmt::Write, K: PartialEq + Clone, V, F: Fn( &mut T, &V ) -> fmt
 fn handler_tree( req: HttpRequest ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
     let filter: protocol::AllocFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
     let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestTree = query( &req )?;
+    let direction = params.direction.unwrap_or( protocol::TreeDirection::TopDown );
+    let collapse_recursion = params.collapse_recursion.unwrap_or( false );
 
-    let body = async_data_handler( &req, move |data, mut tx| {
+    let cache_key = get_response_cache_key( &req, "application/json" )?;
+    if let Some( response ) = cached_response( &req, &cache_key ) {
+        return Ok( response );
+    }
+
+    async_cached_data_handler( &req, cache_key, move |data, mut tx| {
         let mut tree: Tree< FrameId, &Frame > = Tree::new();
+        let mut without_recursion = data.backtraces_without_recursion();
         for (allocation_id, allocation) in data.allocations_with_id() {
             if !match_allocation( data, allocation, &filter ) {
                 continue;
             }
 
-            tree.add_allocation( allocation, allocation_id, data.get_backtrace( allocation.backtrace ) );
+            match (collapse_recursion, direction) {
+                (false, protocol::TreeDirection::TopDown) => {
+                    tree.add_allocation( allocation, allocation_id, data.get_backtrace( allocation.backtrace ) )
+                },
+                (false, protocol::TreeDirection::BottomUp) => {
+                    tree.add_allocation( allocation, allocation_id, data.get_backtrace( allocation.backtrace ).rev() )
+                },
+                (true, protocol::TreeDirection::TopDown) => {
+                    tree.add_allocation( allocation, allocation_id, without_recursion.get( allocation.backtrace ).iter().cloned() )
+                },
+                (true, protocol::TreeDirection::BottomUp) => {
+                    tree.add_allocation( allocation, allocation_id, without_recursion.get( allocation.backtrace ).iter().cloned().rev() )
+                }
+            }
         }
 
-        dump_node( &tree, 0, &mut tx, &mut |output, frame| {
+        let _ = dump_node( &tree, 0, &mut tx, &mut |output, frame| {
             let frame = get_frame( data, &backtrace_format, frame );
             serde_json::to_writer( output, &frame ).map_err( |_| fmt::Error )
-        }).unwrap();
-    })?;
-
-    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+        });
+    })
 }
 
 fn handler_mmaps( req: HttpRequest ) -> Result< HttpResponse > {
@@ -1104,6 +1730,42 @@ fn handler_mmaps( req: HttpRequest ) -> Result< HttpResponse > {
                             backtrace_id: backtrace_id.raw(),
                             thread
                         })
+                    },
+                    MmapOperation::Mremap( MemoryRemap {
+                        timestamp,
+                        old_pointer,
+                        old_length,
+                        pointer,
+                        length,
+                        flags,
+                        backtrace: backtrace_id,
+                        thread
+                    }) => {
+                        if let Some( min ) = filter.size_min {
+                            if length < min {
+                                return None;
+                            }
+                        }
+                        if let Some( max ) = filter.size_max {
+                            if length > max {
+                                return None;
+                            }
+                        }
+                        let backtrace = data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, &backtrace_format, frame ) ).collect();
+                        Some( protocol::MmapOperation::Mremap {
+                            timestamp: timestamp.into(),
+                            old_pointer,
+                            old_pointer_s: format!( "{:016}", old_pointer ),
+                            old_length,
+                            pointer,
+                            pointer_s: format!( "{:016}", pointer ),
+                            length,
+                            backtrace,
+                            backtrace_id: backtrace_id.raw(),
+                            may_move: flags & 0x1 != 0,
+                            is_fixed: flags & 0x2 != 0,
+                            thread
+                        })
                     }
                 }
             })
@@ -1141,7 +1803,7 @@ fn handler_backtrace( req: HttpRequest ) -> Result< HttpResponse > {
 fn handler_backtraces( req: HttpRequest ) -> Result< HttpResponse > {
     let backtrace_format: protocol::BacktraceFormat = query( &req )?;
     let filter: protocol::BacktraceFilter = query( &req )?;
-    let filter = crate::filter::prepare_backtrace_filter( &filter )?;
+    let filter = crate::filter::prepare_backtrace_filter( &filter, req.state().limits.memory_budget )?;
     let body = async_data_handler( &req, move |data, tx| {
         let mut positive_cache = HashMap::new();
         let mut negative_cache = HashMap::new();
@@ -1227,7 +1889,7 @@ fn generate_regions< 'a, F: Fn( &Allocation ) -> bool + Clone + 'a >( data: &'a
 fn handler_regions( req: HttpRequest ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
     let filter: protocol::AllocFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
 
     let body = async_data_handler( &req, move |data, tx| {
         let response = generate_regions( data, |allocation| match_allocation( data, allocation, &filter ) );
@@ -1237,6 +1899,134 @@ fn handler_regions( req: HttpRequest ) -> Result< HttpResponse > {
     Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
 }
 
+fn handler_threads( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let response = crate::threads::get_threads( data );
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_largest_allocations( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestLargestAllocations = query( &req )?;
+    let response = crate::largest_allocations::get_largest_allocations( data, &backtrace_format, params );
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_thread_memory( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let params: protocol::RequestThreadMemory = query( &req )?;
+    let response = crate::threads::get_thread_memory( data, params );
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_markers( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
+    let columns = get_precomputed_columns( &req, data.id() );
+    let response = crate::markers::get_markers( data, columns.as_ref().map( |columns| &**columns ), filter );
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_libraries( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
+    let columns = get_precomputed_columns( &req, data.id() );
+    let response = crate::libraries::get_libraries( data, columns.as_ref().map( |columns| &**columns ), filter );
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_regressions( req: HttpRequest ) -> Result< HttpResponse > {
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestRegressions = query( &req )?;
+    let state = req.state();
+
+    let datasets: Vec< &Data > = match params.ids {
+        Some( ref ids ) => {
+            let mut datasets = Vec::new();
+            for id in ids.split( "," ) {
+                let id = if id == "last" {
+                    state.last_id()
+                } else {
+                    id.parse().ok()
+                };
+
+                let data = id.and_then( |id| state.data.get( &id ) ).ok_or_else( || ErrorNotFound( "data not found" ) )?;
+                datasets.push( data );
+            }
+            datasets
+        },
+        None => {
+            let mut datasets: Vec< _ > = state.data.values().collect();
+            datasets.sort_by_key( |data| data.initial_timestamp() );
+            datasets
+        }
+    };
+
+    let response = crate::regression::get_regressions( &datasets, &backtrace_format, &params );
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
+fn handler_changes( req: HttpRequest ) -> Result< HttpResponse > {
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestChanges = query( &req )?;
+    let state = req.state().clone();
+
+    let get_data_id = |id: &str| -> Result< DataId > {
+        let id = if id == "last" {
+            state.last_id()
+        } else {
+            id.parse().ok()
+        };
+
+        id.filter( |id| state.data.contains_key( id ) ).ok_or_else( || ErrorNotFound( "data not found" ) )
+    };
+
+    let base_id = get_data_id( &params.base )?;
+    let target_id = get_data_id( &params.target )?;
+    let body = async_handler( &req, move |tx| {
+        let (base, target) = match (state.data.get( &base_id ), state.data.get( &target_id )) {
+            (Some( base ), Some( target )) => (base, target),
+            _ => return
+        };
+
+        let response = crate::changes::get_changes( base, target, &backtrace_format, &params );
+        let _ = serde_json::to_writer( tx, &response );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
+fn handler_diff_allocations( req: HttpRequest ) -> Result< HttpResponse > {
+    let base = get_data( &req )?;
+    let target_id = req.match_info().get( "target_id" ).unwrap();
+    let target_id = if target_id == "last" {
+        req.state().last_id()
+    } else {
+        target_id.parse().ok()
+    };
+
+    let target = target_id.and_then( |id| req.state().data.get( &id ) ).ok_or_else( || ErrorNotFound( "data not found" ) )?;
+    let backtrace_format: protocol::BacktraceFormat = query( &req )?;
+    let params: protocol::RequestDiff = query( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let base_filter = prepare_filter( base, &filter, req.state().limits.memory_budget )?;
+    let target_filter = prepare_filter( target, &filter, req.state().limits.memory_budget )?;
+
+    let response = crate::diff::get_diff(
+        base,
+        |allocation| match_allocation( base, allocation, &base_filter ),
+        target,
+        |allocation| match_allocation( target, allocation, &target_filter ),
+        &backtrace_format,
+        &params
+    );
+
+    Ok( HttpResponse::Ok().json( response ) )
+}
+
 fn handler_mallopts( req: HttpRequest ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
     let backtrace_format: protocol::BacktraceFormat = query( &req )?;
@@ -1275,31 +2065,49 @@ fn handler_mallopts( req: HttpRequest ) -> Result< HttpResponse > {
 fn handler_export_flamegraph_pl( req: HttpRequest ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
     let filter: protocol::AllocFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
 
-    let body = async_data_handler( &req, move |data, tx| {
-        let _ = export_as_flamegraph_pl( data, tx, |allocation| match_allocation( data, allocation, &filter ) );
-    })?;
+    let params: protocol::RequestCollapseRecursion = query( &req )?;
+    let collapse_recursion = params.collapse_recursion.unwrap_or( false );
 
-    Ok( HttpResponse::Ok().content_type( "application/octet-stream" ).body( body ) )
+    let cache_key = get_response_cache_key( &req, "application/octet-stream" )?;
+    if let Some( response ) = cached_response( &req, &cache_key ) {
+        return Ok( response );
+    }
+
+    async_cached_data_handler( &req, cache_key, move |data, tx| {
+        let _ = export_as_flamegraph_pl( data, tx, |allocation| match_allocation( data, allocation, &filter ), collapse_recursion );
+    })
 }
 
 fn handler_export_flamegraph( req: HttpRequest ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
     let filter: protocol::AllocFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
 
-    let body = async_data_handler( &req, move |data, tx| {
-        let _ = export_as_flamegraph( data, tx, |allocation| match_allocation( data, allocation, &filter ) );
-    })?;
+    let params: protocol::RequestCollapseRecursion = query( &req )?;
+    let collapse_recursion = params.collapse_recursion.unwrap_or( false );
+
+    let cache_key = get_response_cache_key( &req, "image/svg+xml" )?;
+    if let Some( response ) = cached_response( &req, &cache_key ) {
+        return Ok( response );
+    }
+
+    if req.query_string().is_empty() {
+        if let Some( flamegraph ) = req.state().precomputed.get( &data.id() ).and_then( |precomputed| precomputed.flamegraph() ) {
+            return Ok( precomputed_response( &req, &cache_key, (*flamegraph).clone() ) );
+        }
+    }
 
-    Ok( HttpResponse::Ok().content_type( "image/svg+xml" ).body( body ) )
+    async_cached_data_handler( &req, cache_key, move |data, tx| {
+        let _ = export_as_flamegraph( data, tx, |allocation| match_allocation( data, allocation, &filter ), collapse_recursion );
+    })
 }
 
 fn handler_export_replay( req: HttpRequest ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
     let filter: protocol::AllocFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
 
     let body = async_data_handler( &req, move |data, tx| {
         let _ = export_as_replay( data, tx, |allocation| match_allocation( data, allocation, &filter ) );
@@ -1311,7 +2119,7 @@ fn handler_export_replay( req: HttpRequest ) -> Result< HttpResponse > {
 fn handler_export_heaptrack( req: HttpRequest ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
     let filter: protocol::AllocFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
 
     let body = async_data_handler( &req, move |data, tx| {
         let _ = export_as_heaptrack( data, tx, |allocation| match_allocation( data, allocation, &filter ) );
@@ -1320,13 +2128,45 @@ fn handler_export_heaptrack( req: HttpRequest ) -> Result< HttpResponse > {
     Ok( HttpResponse::Ok().content_type( "application/octet-stream" ).body( body ) )
 }
 
+fn handler_export_pprof( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let _ = export_as_pprof( data, tx, |allocation| match_allocation( data, allocation, &filter ) );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/octet-stream" ).body( body ) )
+}
+
+fn handler_export_otlp_metrics( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    Ok( HttpResponse::Ok().json( crate::otlp::get_metrics( data ) ) )
+}
+
+fn handler_export_otlp_profiles( req: HttpRequest ) -> Result< HttpResponse > {
+    let data = get_data( &req )?;
+    let filter: protocol::AllocFilter = query( &req )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
+
+    let body = async_data_handler( &req, move |data, tx| {
+        let _ = serde_json::to_writer( tx, &crate::otlp::get_profile( data, &filter ) );
+    })?;
+
+    Ok( HttpResponse::Ok().content_type( "application/json" ).body( body ) )
+}
+
 fn handler_allocation_ascii_tree( req: HttpRequest ) -> Result< HttpResponse > {
     let data = get_data( &req )?;
     let filter: protocol::AllocFilter = query( &req )?;
-    let filter = prepare_filter( data, &filter )?;
+    let filter = prepare_filter( data, &filter, req.state().limits.memory_budget )?;
+
+    let params: protocol::RequestCollapseRecursion = query( &req )?;
+    let collapse_recursion = params.collapse_recursion.unwrap_or( false );
 
     let body = async_data_handler( &req, move |data, mut tx| {
-        let tree = data.tree_by_source( |allocation| match_allocation( data, allocation, &filter ) );
+        let tree = data.tree_by_source( |allocation| match_allocation( data, allocation, &filter ), collapse_recursion );
         let table = data.dump_tree( &tree );
         let table = table_to_string( &table );
         let _ = writeln!( tx, "{}", table );
@@ -1467,24 +2307,64 @@ impl From< io::Error > for ServerError {
 
 impl Error for ServerError {}
 
-pub fn main( inputs: Vec< PathBuf >, debug_symbols: Vec< PathBuf >, load_in_parallel: bool, interface: &str, port: u16 ) -> Result< (), ServerError > {
-    let mut state = State::new();
+pub fn main( inputs: Vec< PathBuf >, symbol_sources: SymbolSources, frame_rules: Option< PathBuf >, attribution: Option< Attribution >, plugins: Vec< PathBuf >, cores: Vec< PathBuf >, workspace: Option< PathBuf >, limits: QueryLimits, load_in_parallel: bool, shard: Option< Shard >, interface: &str, port: u16, listen: Option< String >, listen_token_file: Option< PathBuf > ) -> Result< (), ServerError > {
+    let mut state = State::new( limits, shard );
+    let inputs = Inputs { data_files: inputs, cores, plugins, symbol_sources, frame_rules, attribution };
+    let Inputs { data_files: inputs, cores, plugins, symbol_sources, frame_rules, attribution } = match workspace {
+        Some( path ) => {
+            state.workspace = Workspace::open( path )?;
+            state.workspace.restore_sessions( &state.sessions );
+            state.workspace.merge( inputs )
+                .map_err( |error| io::Error::new( io::ErrorKind::InvalidData, error ) )?
+        },
+        None => inputs
+    };
+
+    let attribution = attribution.unwrap_or_default();
+
+    for path in plugins {
+        state.plugins.load( &path )?;
+    }
+
+    let frame_rules = match frame_rules {
+        Some( path ) => FrameRules::load( &path )?,
+        None => FrameRules::default()
+    };
+
+    let listen_token = match listen {
+        Some( ref address ) => common::request::read_token( address, listen_token_file.as_deref(), "--listen-token-file" )?,
+        None => None
+    };
+
+    if listen.is_some() {
+        state.live = Some( live::LiveStreams::new( symbol_sources.clone(), frame_rules.clone(), attribution.clone() ) );
+    }
 
     if !load_in_parallel {
         for filename in inputs {
             info!( "Trying to load {:?}...", filename );
-            let fp = File::open( filename )?;
-            let data = Loader::load_from_stream( fp, &debug_symbols )?;
+            let mut data = match shard {
+                Some( shard ) => Loader::load_shard_from_file( filename, &symbol_sources, shard )?,
+                None => Loader::load_from_file( filename, &symbol_sources )?
+            };
+            data.apply_frame_rules( &frame_rules );
+            data.apply_attribution( &attribution );
             state.add_data( data );
         }
     } else {
         let handles: Vec< thread::JoinHandle< io::Result< Data > > > = inputs.iter().map( move |filename| {
             let filename = filename.clone();
-            let debug_symbols = debug_symbols.clone();
+            let symbol_sources = symbol_sources.clone();
+            let frame_rules = frame_rules.clone();
+            let attribution = attribution.clone();
             thread::spawn( move || {
                 info!( "Trying to load {:?}...", filename );
-                let fp = File::open( filename )?;
-                let data = Loader::load_from_stream( fp, debug_symbols )?;
+                let mut data = match shard {
+                    Some( shard ) => Loader::load_shard_from_file( filename, &symbol_sources, shard )?,
+                    None => Loader::load_from_file( filename, &symbol_sources )?
+                };
+                data.apply_frame_rules( &frame_rules );
+                data.apply_attribution( &attribution );
                 Ok( data )
             })
         }).collect();
@@ -1496,11 +2376,24 @@ pub fn main( inputs: Vec< PathBuf >, debug_symbols: Vec< PathBuf >, load_in_para
         }
     }
 
+    for path in cores {
+        state.add_core( &path )?;
+    }
+
+    // Everything was loaded successfully, so it's safe to remember it.
+    state.save_workspace();
+
     for (key, bytes) in WEBUI_ASSETS {
         debug!( "Static asset: '{}', length = {}", key, bytes.len() );
     }
 
     let state = Arc::new( state );
+    precompute::spawn( &state );
+
+    if let Some( address ) = listen {
+        live::listen( &state, &address, listen_token )?;
+    }
+
     let sys = actix::System::new( "server" );
     actix_web::HttpServer::new( move || {
         App::new().data( state.clone() )
@@ -1508,34 +2401,99 @@ pub fn main( inputs: Vec< PathBuf >, debug_symbols: Vec< PathBuf >, load_in_para
             .configure( |app| {
                 app
                     .service( web::resource( "/list" ).route( web::get().to( handler_list ) ) )
-                    .service( web::resource( "/data/{id}/timeline" ).route( web::get().to( handler_timeline ) ) )
-                    .service( web::resource( "/data/{id}/fragmentation_timeline" ).route( web::get().to( handler_fragmentation_timeline ) ) )
-                    .service( web::resource( "/data/{id}/allocations" ).route( web::get().to( handler_allocations ) ) )
-                    .service( web::resource( "/data/{id}/allocation_groups" ).route( web::get().to( handler_allocation_groups ) ) )
-                    .service( web::resource( "/data/{id}/backtraces" ).route( web::get().to( handler_backtraces ) ) )
-                    .service( web::resource( "/data/{id}/raw_allocations" ).route( web::get().to( handler_raw_allocations ) ) )
-                    .service( web::resource( "/data/{id}/tree" ).route( web::get().to( handler_tree ) ) )
-                    .service( web::resource( "/data/{id}/mmaps" ).route( web::get().to( handler_mmaps ) ) )
-                    .service( web::resource( "/data/{id}/backtrace/{backtrace_id}" ).route( web::get().to( handler_backtrace ) ) )
-                    .service( web::resource( "/data/{id}/regions" ).route( web::get().to( handler_regions ) ) )
-                    .service( web::resource( "/data/{id}/mallopts" ).route( web::get().to( handler_mallopts ) ) )
-                    .service( web::resource( "/data/{id}/export/flamegraph" ).route( web::get().to( handler_export_flamegraph ) ) )
-                    .service( web::resource( "/data/{id}/export/flamegraph/{filename}" ).route( web::get().to( handler_export_flamegraph ) ) )
-                    .service( web::resource( "/data/{id}/export/flamegraph.pl" ).route( web::get().to( handler_export_flamegraph_pl ) ) )
-                    .service( web::resource( "/data/{id}/export/flamegraph.pl/{filename}" ).route( web::get().to( handler_export_flamegraph_pl ) ) )
-                    .service( web::resource( "/data/{id}/export/heaptrack" ).route( web::get().to( handler_export_heaptrack ) ) )
-                    .service( web::resource( "/data/{id}/export/heaptrack/{filename}" ).route( web::get().to( handler_export_heaptrack ) ) )
-                    .service( web::resource( "/data/{id}/export/replay" ).route( web::get().to( handler_export_replay ) ) )
-                    .service( web::resource( "/data/{id}/export/replay/{filename}" ).route( web::get().to( handler_export_replay ) ) )
-                    .service( web::resource( "/data/{id}/allocation_ascii_tree" ).route( web::get().to( handler_allocation_ascii_tree ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_constants" ).route( web::get().to( handler_dynamic_constants ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_constants/{filename}" ).route( web::get().to( handler_dynamic_constants ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_constants_ascii_tree" ).route( web::get().to( handler_dynamic_constants_ascii_tree ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_constants_ascii_tree/{filename}" ).route( web::get().to( handler_dynamic_constants_ascii_tree ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_statics" ).route( web::get().to( handler_dynamic_statics ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_statics/{filename}" ).route( web::get().to( handler_dynamic_statics ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_statics_ascii_tree" ).route( web::get().to( handler_dynamic_statics_ascii_tree ) ) )
-                    .service( web::resource( "/data/{id}/dynamic_statics_ascii_tree/{filename}" ).route( web::get().to( handler_dynamic_statics_ascii_tree ) ) );
+                    .service( web::resource( "/live" ).route( web::get().to( handler_live_list ) ) )
+                    .service( web::resource( "/live/{id}" ).route( web::get().to( limited( handler_live_summary ) ) ) )
+                    .service( web::resource( "/plugins" ).route( web::get().to( handler_plugins ) ) )
+                    .service( web::resource( "/regressions" ).route( web::get().to( limited( handler_regressions ) ) ) )
+                    .service( web::resource( "/changes" ).route( web::get().to( limited( handler_changes ) ) ) )
+                    .service( web::resource( "/sessions" ).route( web::post().to( handler_session_create ) ) )
+                    .service( web::resource( "/grafana" ).route( web::get().to( handler_grafana_test ) ) )
+                    .service( web::resource( "/grafana/search" ).route( web::post().to( handler_grafana_search ) ) )
+                    .service( web::resource( "/grafana/query" ).route( web::post().to( handler_grafana_query ) ) )
+                    .service( web::resource( "/grafana/annotations" ).route( web::post().to( handler_grafana_annotations ) ) )
+                    .service(
+                        web::resource( "/jobs/{job}" )
+                            .route( web::get().to( handler_job_get ) )
+                            .route( web::delete().to( handler_job_delete ) )
+                    )
+                    .service( web::resource( "/jobs/{job}/result" ).route( web::get().to( handler_job_result ) ) )
+                    .service(
+                        web::resource( "/sessions/{session}" )
+                            .route( web::get().to( handler_session_get ) )
+                            .route( web::put().to( handler_session_update ) )
+                            .route( web::delete().to( handler_session_delete ) )
+                    )
+                    .service( web::resource( "/data/{id}/timeline" ).route( web::get().to( limited( handler_timeline ) ) ) )
+                    .service( web::resource( "/data/{id}/histograms" ).route( web::get().to( limited( handler_histograms ) ) ) )
+                    .service( web::resource( "/data/{id}/fragmentation_timeline" ).route( web::get().to( limited( handler_fragmentation_timeline ) ) ) )
+                    .service( web::resource( "/data/{id}/address_reuse" ).route( web::get().to( limited( handler_address_reuse ) ) ) )
+                    .service( web::resource( "/data/{id}/allocator_stats_timeline" ).route( web::get().to( limited( handler_allocator_stats_timeline ) ) ) )
+                    .service( web::resource( "/data/{id}/resident_memory_timeline" ).route( web::get().to( limited( handler_resident_memory_timeline ) ) ) )
+                    .service( web::resource( "/data/{id}/arena_timeline" ).route( web::get().to( limited( handler_arena_timeline ) ) ) )
+                    .service( web::resource( "/data/{id}/arena_trim" ).route( web::get().to( limited( handler_arena_trim ) ) ) )
+                    .service( web::resource( "/data/{id}/allocations" ).route( web::get().to( limited( handler_allocations ) ) ) )
+                    .service( web::resource( "/data/{id}/diff/{target_id}/allocations" ).route( web::get().to( limited( handler_diff_allocations ) ) ) )
+                    .service( web::resource( "/data/{id}/largest_allocations" ).route( web::get().to( limited( handler_largest_allocations ) ) ) )
+                    .service( web::resource( "/data/{id}/allocation_groups" ).route( web::get().to( limited( handler_allocation_groups ) ) ) )
+                    .service( web::resource( "/data/{id}/column_groups" ).route( web::get().to( limited( handler_column_groups ) ) ) )
+                    .service( web::resource( "/data/{id}/churn" ).route( web::get().to( limited( handler_churn ) ) ) )
+                    .service( web::resource( "/data/{id}/overhead" ).route( web::get().to( limited( handler_overhead ) ) ) )
+                    .service( web::resource( "/data/{id}/cross_thread_frees" ).route( web::get().to( limited( handler_cross_thread_frees ) ) ) )
+                    .service( web::resource( "/data/{id}/site_pairs" ).route( web::get().to( limited( handler_site_pairs ) ) ) )
+                    .service( web::resource( "/data/{id}/backtrace_clusters" ).route( web::get().to( limited( handler_backtrace_clusters ) ) ) )
+                    .service( web::resource( "/data/{id}/size_class_waste" ).route( web::get().to( limited( handler_size_class_waste ) ) ) )
+                    .service( web::resource( "/data/{id}/top_sites" ).route( web::get().to( limited( handler_top_sites ) ) ) )
+                    .service( web::resource( "/data/{id}/container_growth" ).route( web::get().to( limited( handler_container_growth ) ) ) )
+                    .service( web::resource( "/data/{id}/peaks" ).route( web::get().to( limited( handler_peaks ) ) ) )
+                    .service( web::resource( "/data/{id}/core" ).route( web::get().to( limited( handler_core_summary ) ) ) )
+                    .service( web::resource( "/data/{id}/core/memory" ).route( web::get().to( limited( handler_core_memory ) ) ) )
+                    .service(
+                        web::resource( "/data/{id}/annotations" )
+                            .route( web::get().to( handler_annotations ) )
+                            .route( web::post().to( handler_annotation_add ) )
+                    )
+                    .service( web::resource( "/data/{id}/annotations/{annotation}" ).route( web::delete().to( handler_annotation_delete ) ) )
+                    .service( web::resource( "/data/{id}/plugins/{plugin}/{endpoint}" ).route( web::get().to( limited( handler_plugin ) ) ) )
+                    .service( web::resource( "/data/{id}/backtraces" ).route( web::get().to( limited( handler_backtraces ) ) ) )
+                    .service( web::resource( "/data/{id}/raw_allocations" ).route( web::get().to( limited( handler_raw_allocations ) ) ) )
+                    .service( web::resource( "/data/{id}/tree" ).route( web::get().to( limited( handler_tree ) ) ) )
+                    .service( web::resource( "/data/{id}/mmaps" ).route( web::get().to( limited( handler_mmaps ) ) ) )
+                    .service( web::resource( "/data/{id}/maps" ).route( web::get().to( limited( handler_maps ) ) ) )
+                    .service( web::resource( "/data/{id}/backtrace/{backtrace_id}" ).route( web::get().to( limited( handler_backtrace ) ) ) )
+                    .service( web::resource( "/data/{id}/regions" ).route( web::get().to( limited( handler_regions ) ) ) )
+                    .service( web::resource( "/data/{id}/mallopts" ).route( web::get().to( limited( handler_mallopts ) ) ) )
+                    .service( web::resource( "/data/{id}/threads" ).route( web::get().to( limited( handler_threads ) ) ) )
+                    .service( web::resource( "/data/{id}/thread_memory" ).route( web::get().to( limited( handler_thread_memory ) ) ) )
+                    .service( web::resource( "/data/{id}/jobs" ).route( web::post().to( handler_job_submit ) ) )
+                    .service( web::resource( "/data/{id}/markers" ).route( web::get().to( limited( handler_markers ) ) ) )
+                    .service( web::resource( "/data/{id}/libraries" ).route( web::get().to( limited( handler_libraries ) ) ) )
+                    .service( web::resource( "/data/{id}/export/flamegraph" ).route( web::get().to( limited( handler_export_flamegraph ) ) ) )
+                    .service( web::resource( "/data/{id}/export/flamegraph/{filename}" ).route( web::get().to( limited( handler_export_flamegraph ) ) ) )
+                    .service( web::resource( "/data/{id}/export/flamegraph.pl" ).route( web::get().to( limited( handler_export_flamegraph_pl ) ) ) )
+                    .service( web::resource( "/data/{id}/export/flamegraph.pl/{filename}" ).route( web::get().to( limited( handler_export_flamegraph_pl ) ) ) )
+                    .service( web::resource( "/data/{id}/export/heaptrack" ).route( web::get().to( limited( handler_export_heaptrack ) ) ) )
+                    .service( web::resource( "/data/{id}/export/heaptrack/{filename}" ).route( web::get().to( limited( handler_export_heaptrack ) ) ) )
+                    .service( web::resource( "/data/{id}/export/pprof" ).route( web::get().to( limited( handler_export_pprof ) ) ) )
+                    .service( web::resource( "/data/{id}/export/pprof/{filename}" ).route( web::get().to( limited( handler_export_pprof ) ) ) )
+                    .service( web::resource( "/data/{id}/export/otlp/metrics" ).route( web::get().to( limited( handler_export_otlp_metrics ) ) ) )
+                    .service( web::resource( "/data/{id}/export/otlp/profiles" ).route( web::get().to( limited( handler_export_otlp_profiles ) ) ) )
+                    .service( web::resource( "/data/{id}/export/replay" ).route( web::get().to( limited( handler_export_replay ) ) ) )
+                    .service( web::resource( "/data/{id}/export/replay/{filename}" ).route( web::get().to( limited( handler_export_replay ) ) ) )
+                    .service( web::resource( "/data/{id}/allocation_ascii_tree" ).route( web::get().to( limited( handler_allocation_ascii_tree ) ) ) )
+                    .service( web::resource( "/data/{id}/dynamic_constants" ).route( web::get().to( limited( handler_dynamic_constants ) ) ) )
+                    .service( web::resource( "/data/{id}/dynamic_constants/{filename}" ).route( web::get().to( limited( handler_dynamic_constants ) ) ) )
+                    .service( web::resource( "/data/{id}/dynamic_constants_ascii_tree" ).route( web::get().to( limited( handler_dynamic_constants_ascii_tree ) ) ) )
+                    .service( web::resource( "/data/{id}/dynamic_constants_ascii_tree/{filename}" ).route( web::get().to( limited( handler_dynamic_constants_ascii_tree ) ) ) )
+                    .service( web::resource( "/data/{id}/dynamic_statics" ).route( web::get().to( limited( handler_dynamic_statics ) ) ) )
+                    .service( web::resource( "/data/{id}/dynamic_statics/{filename}" ).route( web::get().to( limited( handler_dynamic_statics ) ) ) )
+                    .service( web::resource( "/data/{id}/dynamic_statics_ascii_tree" ).route( web::get().to( limited( handler_dynamic_statics_ascii_tree ) ) ) )
+                    .service( web::resource( "/data/{id}/dynamic_statics_ascii_tree/{filename}" ).route( web::get().to( limited( handler_dynamic_statics_ascii_tree ) ) ) );
+
+                #[cfg(feature = "scripting")]
+                app.service( web::resource( "/data/{id}/script" ).route( web::post().to( handler_script ) ) );
+
+                #[cfg(feature = "graphql")]
+                app.service( web::resource( "/graphql" ).route( web::post().to( handler_graphql ) ) );
 
                 for (key, bytes) in WEBUI_ASSETS {
                     app.service( web::resource( &format!( "/{}", key ) ).route( web::get().to( move || StaticResponse( key, bytes ) ) ) );
@@ -0,0 +1,86 @@
+use std::cmp::Reverse;
+
+use ahash::AHashMap as HashMap;
+
+use cli_core::{
+    AllocationColumns,
+    BacktraceId,
+    Data,
+    StringId,
+    Timestamp
+};
+
+use crate::protocol;
+use crate::filter::{AllocationMatcher, Filter};
+
+#[derive(Default)]
+struct LibraryStats {
+    first_loaded: Option< Timestamp >,
+    load_count: u64,
+    unload_count: u64,
+    allocated_count: u64,
+    allocated_size: u64,
+    leaked_count: u64,
+    leaked_size: u64
+}
+
+fn get_libraries_of_backtrace( data: &Data, backtrace_id: BacktraceId ) -> Vec< StringId > {
+    let mut libraries: Vec< _ > = data.get_backtrace( backtrace_id ).filter_map( |(_, frame)| frame.library() ).collect();
+    libraries.sort();
+    libraries.dedup();
+    libraries
+}
+
+/// Attributes the matched allocations to every library which had code on their backtrace,
+/// along with how many times each library was loaded and unloaded.
+pub fn get_libraries( data: &Data, columns: Option< &AllocationColumns >, filter: Filter ) -> Vec< protocol::Library > {
+    let mut stats_by_library: HashMap< &str, LibraryStats > = HashMap::new();
+    let initial_load = data.library_events().first().map( |event| event.timestamp );
+    for event in data.library_events() {
+        let stats = stats_by_library.entry( &event.library ).or_insert_with( LibraryStats::default );
+        if event.is_loaded {
+            stats.first_loaded = stats.first_loaded.or( Some( event.timestamp ) );
+            stats.load_count += 1;
+        } else {
+            stats.unload_count += 1;
+        }
+    }
+
+    let mut libraries_by_backtrace: HashMap< BacktraceId, Vec< StringId > > = HashMap::new();
+    let matcher = AllocationMatcher::new( data, columns, &filter );
+    let iter = data.alloc_sorted_by_timestamp( filter.timestamp_start_opt(), filter.timestamp_end_opt() );
+    for (id, allocation) in iter {
+        if !matcher.matches( id, allocation ) {
+            continue;
+        }
+
+        let libraries = libraries_by_backtrace.entry( allocation.backtrace ).or_insert_with( || get_libraries_of_backtrace( data, allocation.backtrace ) );
+        for &library in libraries.iter() {
+            let library = data.interner().resolve( library ).unwrap();
+            let stats = stats_by_library.entry( library ).or_insert_with( LibraryStats::default );
+            stats.allocated_count += 1;
+            stats.allocated_size += allocation.size;
+            if allocation.deallocation.is_none() {
+                stats.leaked_count += 1;
+                stats.leaked_size += allocation.size;
+            }
+        }
+    }
+
+    let mut libraries: Vec< _ > = stats_by_library.into_iter().map( |(library, stats)| {
+        protocol::Library {
+            library: library.to_owned(),
+            dynamically_loaded: stats.first_loaded.is_some() && stats.first_loaded != initial_load,
+            load_count: stats.load_count,
+            unload_count: stats.unload_count,
+            allocated_count: stats.allocated_count,
+            allocated_size: stats.allocated_size,
+            leaked_count: stats.leaked_count,
+            leaked_size: stats.leaked_size,
+            leaked_size_per_load: stats.leaked_size as f64 / std::cmp::max( stats.load_count, 1 ) as f64
+        }
+    }).collect();
+
+    libraries.sort_by( |lhs, rhs| (Reverse( lhs.leaked_size ), &lhs.library).cmp( &(Reverse( rhs.leaked_size ), &rhs.library) ) );
+    libraries
+}
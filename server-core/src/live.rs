@@ -0,0 +1,481 @@
+use std::cmp::Reverse;
+use std::io;
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ahash::AHashMap as HashMap;
+use parking_lot::{Mutex, MutexGuard};
+
+use cli_core::{
+    Attribution,
+    BacktraceId,
+    Data,
+    DataId,
+    FrameRules,
+    IncrementalLoader,
+    SymbolSources
+};
+
+use common::request::{BroadcastHeader, Response, is_token_valid};
+use common::speedy::Readable;
+
+use crate::protocol;
+use crate::{StateRef, get_frame, get_timeline};
+
+/*
+    Every stream is received on its own thread, and what's received is loaded right away. Taking a snapshot
+    clones the whole `Loader` though, and the profiled process writes to us synchronously, so the receiving
+    thread must never wait for a snapshot to finish; while one is being taken whatever arrives is just
+    appended to `pending`, and is loaded by whichever of the two gets to the loader next.
+*/
+
+/// How old the snapshot of a stream can get before it's taken again.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs( 1 );
+
+/// How long a stream is kept around after its process has disconnected.
+const FINISHED_STREAM_RETENTION: Duration = Duration::from_secs( 60 * 60 );
+
+/// How many streams can be received at the same time.
+const MAX_CONNECTIONS: usize = 64;
+
+/// How long a process which has just connected has to authenticate and send its header.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs( 30 );
+
+struct Status {
+    disconnected_at: Option< Instant >,
+    received_bytes: u64,
+    /// What was received while the loader was busy.
+    pending: Vec< u8 >
+}
+
+struct Loaded {
+    loader: IncrementalLoader,
+    /// Whether anything was loaded since the last snapshot was taken.
+    is_dirty: bool,
+    is_broken: bool,
+    snapshot: Option< (Instant, Arc< Data >) >
+}
+
+struct LiveStream {
+    header: BroadcastHeader,
+    status: Mutex< Status >,
+    loaded: Mutex< Loaded >
+}
+
+impl LiveStream {
+    fn info( &self ) -> protocol::LiveStream {
+        let status = self.status.lock();
+        protocol::LiveStream {
+            id: format!( "{}", self.header.id ),
+            executable: String::from_utf8_lossy( &self.header.executable ).into_owned(),
+            architecture: self.header.arch.clone(),
+            pid: self.header.pid,
+            is_connected: status.disconnected_at.is_none(),
+            received_bytes: status.received_bytes
+        }
+    }
+
+    fn load_pending( &self, loaded: &mut Loaded ) -> io::Result< () > {
+        if loaded.is_broken {
+            return Err( io::Error::new( io::ErrorKind::InvalidData, "the stream is broken" ) );
+        }
+
+        let pending = mem::take( &mut self.status.lock().pending );
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        loaded.is_dirty = true;
+        if let Err( error ) = loaded.loader.push( &pending ) {
+            loaded.is_broken = true;
+            return Err( error );
+        }
+
+        Ok(())
+    }
+
+    /// Loads the next piece of the stream, unless a snapshot is being taken right now.
+    fn receive( &self, data: &[u8] ) -> io::Result< () > {
+        {
+            let mut status = self.status.lock();
+            status.received_bytes += data.len() as u64;
+            status.pending.extend_from_slice( data );
+        }
+
+        match self.loaded.try_lock() {
+            Some( mut loaded ) => self.load_pending( &mut loaded ),
+            None => Ok(())
+        }
+    }
+
+    fn disconnect( &self ) {
+        if let Err( error ) = self.load_pending( &mut self.loaded.lock() ) {
+            warn!( "Failed to load the end of the live stream: {}", error );
+        }
+
+        self.status.lock().disconnected_at = Some( Instant::now() );
+    }
+}
+
+/// The data which the profiled processes started with `MEMORY_PROFILER_STREAM_TO` are streaming to us.
+///
+/// The streams are loaded as the data arrives, and are snapshotted whenever they're queried.
+pub(crate) struct LiveStreams {
+    symbol_sources: SymbolSources,
+    frame_rules: FrameRules,
+    attribution: Attribution,
+    streams: Mutex< Vec< Arc< LiveStream > > >
+}
+
+impl LiveStreams {
+    pub(crate) fn new( symbol_sources: SymbolSources, frame_rules: FrameRules, attribution: Attribution ) -> Self {
+        LiveStreams {
+            symbol_sources,
+            frame_rules,
+            attribution,
+            streams: Mutex::new( Vec::new() )
+        }
+    }
+
+    /// Returns the streams, after forgetting the ones which have finished a while ago.
+    fn streams( &self, now: Instant ) -> MutexGuard< Vec< Arc< LiveStream > > > {
+        let mut streams = self.streams.lock();
+        streams.retain( |stream| {
+            match stream.status.lock().disconnected_at {
+                Some( disconnected_at ) => now.saturating_duration_since( disconnected_at ) < FINISHED_STREAM_RETENTION,
+                None => true
+            }
+        });
+
+        streams
+    }
+
+    fn add( &self, header: BroadcastHeader ) -> Arc< LiveStream > {
+        let stream = Arc::new( LiveStream {
+            header,
+            status: Mutex::new( Status {
+                disconnected_at: None,
+                received_bytes: 0,
+                pending: Vec::new()
+            }),
+            loaded: Mutex::new( Loaded {
+                loader: IncrementalLoader::new( &self.symbol_sources ),
+                is_dirty: false,
+                is_broken: false,
+                snapshot: None
+            })
+        });
+
+        self.streams( Instant::now() ).push( stream.clone() );
+        stream
+    }
+
+    fn get( &self, id: DataId ) -> Option< Arc< LiveStream > > {
+        self.streams( Instant::now() ).iter().find( |stream| stream.header.id == id ).cloned()
+    }
+
+    pub(crate) fn list( &self ) -> Vec< protocol::LiveStream > {
+        self.streams( Instant::now() ).iter().map( |stream| stream.info() ).collect()
+    }
+
+    /// Returns the information about the stream and everything which was received through it so far;
+    /// the data is `None` if not even the header arrived yet.
+    pub(crate) fn snapshot( &self, id: DataId ) -> Option< (protocol::LiveStream, Option< Arc< Data > >) > {
+        let stream = self.get( id )?;
+        let mut loaded = stream.loaded.lock();
+        if let Err( error ) = stream.load_pending( &mut loaded ) {
+            warn!( "Failed to load the live stream {}: {}", stream.header.id, error );
+        }
+
+        let is_stale = loaded.snapshot.as_ref().map( |&(timestamp, _)| timestamp.elapsed() >= SNAPSHOT_INTERVAL ).unwrap_or( true );
+        if loaded.is_dirty && is_stale {
+            if let Some( mut data ) = loaded.loader.snapshot() {
+                data.apply_frame_rules( &self.frame_rules );
+                data.apply_attribution( &self.attribution );
+                loaded.snapshot = Some( (Instant::now(), Arc::new( data )) );
+                loaded.is_dirty = false;
+            }
+        }
+
+        let data = loaded.snapshot.as_ref().map( |(_, data)| data.clone() );
+        mem::drop( loaded );
+        Some( (stream.info(), data) )
+    }
+}
+
+fn receive( streams: &LiveStreams, socket: TcpStream, token: Option< &str > ) -> io::Result< () > {
+    let address = socket.peer_addr()?;
+    socket.set_read_timeout( Some( HANDSHAKE_TIMEOUT ) )?;
+
+    let mut message = Response::read_from_stream_unbuffered( &socket )?;
+    if let Some( token ) = token {
+        match message {
+            Response::Authenticate( ref given ) if is_token_valid( given, token ) => {},
+            _ => return Err( io::Error::new( io::ErrorKind::PermissionDenied, format!( "the live stream from {} wasn't authenticated", address ) ) )
+        }
+
+        message = Response::read_from_stream_unbuffered( &socket )?;
+    }
+
+    let header = match message {
+        Response::Start( header ) => header,
+        _ => return Err( io::Error::new( io::ErrorKind::InvalidData, "unexpected message" ) )
+    };
+
+    // The profiled process can go quiet for as long as it wants to.
+    socket.set_read_timeout( None )?;
+
+    info!( "Receiving a live stream from {}: PID {}, {}", address, header.pid, String::from_utf8_lossy( &header.executable ) );
+    let stream = streams.add( header );
+    let result = loop {
+        match Response::read_from_stream_unbuffered( &socket ) {
+            Ok( Response::Data( data ) ) => {
+                if let Err( error ) = stream.receive( &data ) {
+                    break Err( error );
+                }
+            },
+            Ok( Response::Finished ) => break Ok(()),
+            Ok( _ ) => {},
+            Err( error ) => {
+                let error: io::Error = error.into();
+                if error.kind() == io::ErrorKind::UnexpectedEof {
+                    break Ok(());
+                }
+
+                break Err( error );
+            }
+        }
+    };
+
+    stream.disconnect();
+    info!( "The live stream from {} has ended", address );
+    result
+}
+
+/// Starts accepting the streams from the profiled processes in the background.
+pub(crate) fn listen( state: &StateRef, address: &str, token: Option< String > ) -> io::Result< () > {
+    let listener = TcpListener::bind( address )?;
+    info!( "Listening for live streams on {}", listener.local_addr()? );
+
+    let state = state.clone();
+    let token = Arc::new( token );
+    let connections = Arc::new( AtomicUsize::new( 0 ) );
+    thread::spawn( move || {
+        for socket in listener.incoming() {
+            let socket = match socket {
+                Ok( socket ) => socket,
+                Err( error ) => {
+                    warn!( "Failed to accept a live stream: {}", error );
+                    continue;
+                }
+            };
+
+            if connections.fetch_add( 1, Ordering::SeqCst ) >= MAX_CONNECTIONS {
+                connections.fetch_sub( 1, Ordering::SeqCst );
+                warn!( "Rejecting a live stream from {:?}: there are already {} connected", socket.peer_addr().ok(), MAX_CONNECTIONS );
+                continue;
+            }
+
+            let state = state.clone();
+            let token = token.clone();
+            let connections = connections.clone();
+            thread::spawn( move || {
+                let streams = state.live.as_ref().unwrap();
+                if let Err( error ) = receive( streams, socket, (*token).as_deref() ) {
+                    warn!( "Failed to receive a live stream: {}", error );
+                }
+
+                connections.fetch_sub( 1, Ordering::SeqCst );
+            });
+        }
+    });
+
+    Ok(())
+}
+
+/// The current totals of a live stream, its timeline and the backtraces which hold the most memory right now.
+pub(crate) fn get_live_summary< 'a >(
+    data: &'a Data,
+    backtrace_format: &protocol::BacktraceFormat,
+    params: &protocol::RequestLiveSummary
+) -> protocol::LiveSummary< 'a > {
+    let mut by_backtrace: HashMap< BacktraceId, (u64, u64) > = HashMap::new();
+    for allocation in data.allocations().iter().filter( |allocation| !allocation.was_deallocated() ) {
+        let site = by_backtrace.entry( allocation.backtrace ).or_default();
+        site.0 += 1;
+        site.1 += allocation.size;
+    }
+
+    let live_count = by_backtrace.values().map( |&(count, _)| count ).sum();
+    let live_size = by_backtrace.values().map( |&(_, size)| size ).sum();
+
+    let mut sites: Vec< _ > = by_backtrace.into_iter().collect();
+    sites.sort_by_key( |&(backtrace_id, (_, size))| (Reverse( size ), backtrace_id.raw()) );
+    sites.truncate( params.count.unwrap_or( 20 ) as usize );
+
+    protocol::LiveSummary {
+        timestamp: data.last_timestamp().into(),
+        elapsed: (data.last_timestamp() - data.initial_timestamp()).into(),
+        live_count,
+        live_size,
+        total_allocated: data.total_allocated(),
+        total_allocated_count: data.total_allocated_count(),
+        total_freed: data.total_freed(),
+        total_freed_count: data.total_freed_count(),
+        top_sites: sites.into_iter().map( |(backtrace_id, (live_count, live_size))| protocol::LiveSite {
+            backtrace_id: backtrace_id.raw(),
+            backtrace: data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, backtrace_format, frame ) ).collect(),
+            live_count,
+            live_size
+        }).collect(),
+        timeline: get_timeline( data )
+    }
+}
+
+#[cfg(test)]
+fn test_streams() -> LiveStreams {
+    LiveStreams::new( SymbolSources::default(), FrameRules::default(), Attribution::default() )
+}
+
+#[cfg(test)]
+fn test_header( id: DataId ) -> BroadcastHeader {
+    BroadcastHeader {
+        id,
+        initial_timestamp: common::Timestamp::min(),
+        timestamp: common::Timestamp::min(),
+        wall_clock_secs: 0,
+        wall_clock_nsecs: 0,
+        pid: 123,
+        cmdline: b"./a.out\0".to_vec(),
+        executable: b"./a.out".to_vec(),
+        arch: "x86_64".to_owned(),
+        listener_port: 0,
+        protocol_version: common::request::PROTOCOL_VERSION
+    }
+}
+
+/// A native data stream with three allocations (16, 32 and 16 bytes) from two sites, one of which was freed.
+#[cfg(test)]
+fn test_data() -> Vec< u8 > {
+    let input = "\
+        v 10100 2\n\
+        X ./a.out\n\
+        s libc.so.6\n\
+        s malloc\n\
+        s main\n\
+        i 1000 1 2\n\
+        i 2000 1 3\n\
+        t 2 0\n\
+        t 1 1\n\
+        a 10 2\n\
+        a 20 1\n\
+        + 0\n\
+        + 1\n\
+        + 0\n\
+        - 0\n\
+    ";
+
+    let mut output = Vec::new();
+    cli_core::import_heaptrack( input.as_bytes(), &mut output, cli_core::Timestamp::from_secs( 100 ) ).unwrap();
+    output
+}
+
+#[test]
+fn test_receive() {
+    use common::speedy::Writable;
+
+    let streams = test_streams();
+    let listener = TcpListener::bind( "127.0.0.1:0" ).unwrap();
+    let address = listener.local_addr().unwrap();
+    let id = DataId::new( 1, 2 );
+    let data = test_data();
+    let length = data.len() as u64;
+    let client = thread::spawn( move || {
+        let mut socket = TcpStream::connect( address ).unwrap();
+        Response::Authenticate( "secret".into() ).write_to_stream( &mut socket ).unwrap();
+        Response::Start( test_header( id ) ).write_to_stream( &mut socket ).unwrap();
+        for piece in data.chunks( 7 ) {
+            Response::Data( piece.into() ).write_to_stream( &mut socket ).unwrap();
+        }
+        Response::Finished.write_to_stream( &mut socket ).unwrap();
+    });
+
+    let (socket, _) = listener.accept().unwrap();
+    receive( &streams, socket, Some( "secret" ) ).unwrap();
+    client.join().unwrap();
+
+    let (info, data) = streams.snapshot( id ).unwrap();
+    assert!( !info.is_connected );
+    assert_eq!( info.pid, 123 );
+    assert_eq!( info.received_bytes, length );
+
+    let data = data.unwrap();
+    assert_eq!( data.allocations().len(), 3 );
+
+    let format = protocol::BacktraceFormat { strip_template_args: None };
+    let summary = get_live_summary( &data, &format, &protocol::RequestLiveSummary { count: Some( 1 ) } );
+    assert_eq!( (summary.live_count, summary.live_size), (2, 48) );
+    assert_eq!( summary.top_sites.len(), 1 );
+    assert_eq!( (summary.top_sites[ 0 ].live_count, summary.top_sites[ 0 ].live_size), (1, 32) );
+    assert_eq!( summary.top_sites[ 0 ].backtrace[ 0 ].function.as_deref(), Some( "main" ) );
+}
+
+#[test]
+fn test_receive_without_the_token() {
+    use common::speedy::Writable;
+
+    let streams = test_streams();
+    let listener = TcpListener::bind( "127.0.0.1:0" ).unwrap();
+    let address = listener.local_addr().unwrap();
+    let client = thread::spawn( move || {
+        let mut socket = TcpStream::connect( address ).unwrap();
+        let _ = Response::Authenticate( "wrong".into() ).write_to_stream( &mut socket );
+        let _ = Response::Start( test_header( DataId::new( 1, 2 ) ) ).write_to_stream( &mut socket );
+    });
+
+    let (socket, _) = listener.accept().unwrap();
+    let error = receive( &streams, socket, Some( "secret" ) ).unwrap_err();
+    client.join().unwrap();
+
+    assert_eq!( error.kind(), io::ErrorKind::PermissionDenied );
+    assert!( streams.list().is_empty() );
+}
+
+#[test]
+fn test_receive_while_taking_a_snapshot() {
+    let streams = test_streams();
+    let id = DataId::new( 1, 2 );
+    let stream = streams.add( test_header( id ) );
+    let data = test_data();
+
+    {
+        // Receiving doesn't wait for the loader...
+        let _loaded = stream.loaded.lock();
+        stream.receive( &data ).unwrap();
+        assert_eq!( stream.status.lock().pending.len(), data.len() );
+    }
+
+    // ...and whatever was received in the meantime is loaded by the next snapshot.
+    let (info, snapshot) = streams.snapshot( id ).unwrap();
+    assert!( info.is_connected );
+    assert!( stream.status.lock().pending.is_empty() );
+    assert_eq!( snapshot.unwrap().allocations().len(), 3 );
+}
+
+#[test]
+fn test_finished_streams_are_evicted() {
+    let streams = test_streams();
+    let finished = streams.add( test_header( DataId::new( 1, 1 ) ) );
+    streams.add( test_header( DataId::new( 2, 2 ) ) );
+    finished.disconnect();
+
+    assert_eq!( streams.list().len(), 2 );
+    let later = Instant::now() + FINISHED_STREAM_RETENTION + Duration::from_secs( 1 );
+    let remaining: Vec< _ > = streams.streams( later ).iter().map( |stream| stream.header.id ).collect();
+    assert_eq!( remaining, vec![ DataId::new( 2, 2 ) ] );
+    assert!( streams.snapshot( DataId::new( 1, 1 ) ).is_none() );
+}
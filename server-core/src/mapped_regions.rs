@@ -0,0 +1,107 @@
+use std::cmp::Reverse;
+
+use ahash::AHashMap as HashMap;
+
+use cli_core::{
+    BacktraceId,
+    Data,
+    MappedRegion,
+    MappedRegions
+};
+
+use crate::protocol;
+use crate::get_frame;
+
+fn permissions( region: &MappedRegion ) -> Option< String > {
+    let (protection, flags) = (region.protection?, region.flags?);
+    let mut output = String::with_capacity( 4 );
+    output.push( if protection.is_readable() { 'r' } else { '-' } );
+    output.push( if protection.is_writable() { 'w' } else { '-' } );
+    output.push( if protection.is_executable() { 'x' } else { '-' } );
+    output.push( if flags.is_shared() { 's' } else { 'p' } );
+    Some( output )
+}
+
+/// How much memory was mapped through `mmap` over time, with one sample per second.
+fn get_timeline( data: &Data ) -> protocol::MappedTimeline {
+    let mut timeline = protocol::MappedTimeline {
+        xs: Vec::new(),
+        length: Vec::new(),
+        count: Vec::new()
+    };
+
+    let mut regions = MappedRegions::new();
+    for op in data.mmap_operations() {
+        regions.apply( op );
+
+        let x = op.timestamp().as_secs();
+        if timeline.xs.last() != Some( &x ) {
+            timeline.xs.push( x );
+            timeline.length.push( 0 );
+            timeline.count.push( 0 );
+        }
+
+        *timeline.length.last_mut().unwrap() = regions.total_length();
+        *timeline.count.last_mut().unwrap() = regions.len() as u64;
+    }
+
+    timeline
+}
+
+/// Returns the regions which were mapped at a given time, along with the backtraces
+/// which are responsible for most of them.
+pub fn get_mapped_regions< 'a >(
+    data: &'a Data,
+    backtrace_format: &protocol::BacktraceFormat,
+    params: &protocol::RequestMappedRegions
+) -> protocol::ResponseMappedRegions< 'a > {
+    let timestamp = params.at.map( |at| at.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) ).unwrap_or( data.last_timestamp() );
+    let mapped = MappedRegions::at( data, timestamp );
+    let size_min = params.size_min.unwrap_or( 0 );
+    let backtrace = |backtrace_id: BacktraceId| data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, backtrace_format, frame ) ).collect();
+
+    let mut by_site: HashMap< BacktraceId, (u64, u64) > = HashMap::new();
+    let mut regions = Vec::new();
+    for region in mapped.iter() {
+        let site = by_site.entry( region.backtrace ).or_default();
+        site.0 += 1;
+        site.1 += region.length;
+
+        if region.length < size_min {
+            continue;
+        }
+
+        regions.push( protocol::MappedRegion {
+            address: region.address,
+            address_s: format!( "{:016X}", region.address ),
+            length: region.length,
+            timestamp: region.timestamp.into(),
+            timestamp_relative: (region.timestamp - data.initial_timestamp()).into(),
+            backtrace_id: region.backtrace.raw(),
+            backtrace: backtrace( region.backtrace ),
+            thread: region.thread,
+            permissions: permissions( region ),
+            is_anonymous: region.flags.map( |flags| flags.is_anonymous() ),
+            file_descriptor: region.file_descriptor.map( |fd| fd as i32 ),
+            offset: region.offset,
+            was_remapped: region.was_remapped
+        });
+    }
+
+    let mut sites: Vec< _ > = by_site.into_iter().collect();
+    sites.sort_by_key( |&(backtrace_id, (_, length))| (Reverse( length ), backtrace_id.raw()) );
+
+    protocol::ResponseMappedRegions {
+        timestamp: timestamp.into(),
+        total_length: mapped.total_length(),
+        total_count: mapped.len() as u64,
+        regions,
+        sites: sites.into_iter().map( |(backtrace_id, (count, length))| protocol::MappedSite {
+            backtrace_id: backtrace_id.raw(),
+            backtrace: backtrace( backtrace_id ),
+            count,
+            length
+        }).collect(),
+        timeline: get_timeline( data )
+    }
+}
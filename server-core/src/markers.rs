@@ -0,0 +1,54 @@
+use ahash::AHashMap as HashMap;
+use ahash::AHashSet as HashSet;
+
+use cli_core::{
+    AllocationColumns,
+    BacktraceId,
+    Data
+};
+
+use crate::protocol;
+use crate::filter::{AllocationMatcher, Filter};
+
+#[derive(Default)]
+struct MarkerStats {
+    allocated_count: u64,
+    allocated_size: u64,
+    leaked_count: u64,
+    leaked_size: u64,
+    backtraces: HashSet< BacktraceId >
+}
+
+pub fn get_markers( data: &Data, columns: Option< &AllocationColumns >, filter: Filter ) -> Vec< protocol::Marker > {
+    let mut stats_by_marker: HashMap< u32, MarkerStats > = HashMap::new();
+    let matcher = AllocationMatcher::new( data, columns, &filter );
+    let iter = data.alloc_sorted_by_timestamp( filter.timestamp_start_opt(), filter.timestamp_end_opt() );
+    for (id, allocation) in iter {
+        if !matcher.matches( id, allocation ) {
+            continue;
+        }
+
+        let stats = stats_by_marker.entry( allocation.marker ).or_insert_with( MarkerStats::default );
+        stats.allocated_count += 1;
+        stats.allocated_size += allocation.size;
+        if allocation.deallocation.is_none() {
+            stats.leaked_count += 1;
+            stats.leaked_size += allocation.size;
+        }
+        stats.backtraces.insert( allocation.backtrace );
+    }
+
+    let mut markers: Vec< _ > = stats_by_marker.into_iter().map( |(marker, stats)| {
+        protocol::Marker {
+            marker,
+            allocated_count: stats.allocated_count,
+            allocated_size: stats.allocated_size,
+            leaked_count: stats.leaked_count,
+            leaked_size: stats.leaked_size,
+            backtrace_count: stats.backtraces.len() as u64
+        }
+    }).collect();
+
+    markers.sort_by_key( |marker| marker.marker );
+    markers
+}
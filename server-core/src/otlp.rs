@@ -0,0 +1,306 @@
+//! Exports in the OpenTelemetry protocol's JSON encoding, ready to be posted to a collector.
+
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use ahash::AHashMap as HashMap;
+use serde_json::{Value, json};
+
+use cli_core::{
+    BacktraceId,
+    Data,
+    FrameId,
+    FrameRules,
+    Loader,
+    SymbolSources
+};
+
+use crate::filter::{Filter, match_allocation, prepare_filter};
+use crate::get_timeline;
+use crate::protocol;
+
+const SCOPE_NAME: &str = "memory-profiler";
+const PUSH_TIMEOUT: Duration = Duration::from_secs( 60 );
+
+fn resource( data: &Data ) -> Value {
+    let executable = data.executable().rsplit( '/' ).next().unwrap_or( data.executable() );
+    json!({
+        "attributes": [
+            { "key": "service.name", "value": { "stringValue": executable } },
+            { "key": "process.executable.path", "value": { "stringValue": data.executable() } },
+            { "key": "memory_profiler.data_id", "value": { "stringValue": data.id().to_string() } }
+        ]
+    })
+}
+
+fn scope() -> Value {
+    json!({ "name": SCOPE_NAME, "version": env!( "CARGO_PKG_VERSION" ) })
+}
+
+fn nanos( secs: u64 ) -> String {
+    (secs as u128 * 1_000_000_000).to_string()
+}
+
+/// Returns an `ExportMetricsServiceRequest` with the memory usage of the whole capture, sampled every second.
+pub fn get_metrics( data: &Data ) -> Value {
+    let timeline = get_timeline( data );
+    let gauge = |name: &str, unit: &str, description: &str, values: &[u64]| {
+        let data_points: Vec< Value > = timeline.xs.iter().zip( values ).map( |(&secs, &value)| {
+            json!({ "timeUnixNano": nanos( secs ), "asInt": value.to_string() })
+        }).collect();
+
+        json!({
+            "name": name,
+            "unit": unit,
+            "description": description,
+            "gauge": { "dataPoints": data_points }
+        })
+    };
+
+    json!({
+        "resourceMetrics": [{
+            "resource": resource( data ),
+            "scopeMetrics": [{
+                "scope": scope(),
+                "metrics": [
+                    gauge( "memory_profiler.tracked.size", "By", "The total size of the live allocations", &timeline.allocated_size ),
+                    gauge( "memory_profiler.tracked.count", "{allocation}", "The number of live allocations", &timeline.allocated_count ),
+                    gauge( "memory_profiler.leaked.size", "By", "The total size of the allocations which are never freed", &timeline.leaked_size ),
+                    gauge( "memory_profiler.leaked.count", "{allocation}", "The number of allocations which are never freed", &timeline.leaked_count )
+                ]
+            }]
+        }]
+    })
+}
+
+#[derive(Default)]
+struct StringTable {
+    strings: Vec< String >,
+    index_by_string: HashMap< String, usize >
+}
+
+impl StringTable {
+    fn new() -> Self {
+        let mut table = StringTable::default();
+        // The first string has to always be empty.
+        table.get( "" );
+        table
+    }
+
+    fn get( &mut self, string: &str ) -> usize {
+        if let Some( &index ) = self.index_by_string.get( string ) {
+            return index;
+        }
+
+        let index = self.strings.len();
+        self.strings.push( string.to_owned() );
+        self.index_by_string.insert( string.to_owned(), index );
+        index
+    }
+}
+
+/// Returns the types of the values of every sample, along with the default one
+/// (which is an index into the string table, not into the sample types).
+fn get_sample_types( strings: &mut StringTable ) -> (Vec< Value >, usize) {
+    let mut value_type = |kind: &str, unit: &str| json!({ "type": strings.get( kind ).to_string(), "unit": strings.get( unit ).to_string() });
+    let sample_types = vec![
+        value_type( "alloc_objects", "count" ),
+        value_type( "alloc_space", "bytes" ),
+        value_type( "inuse_objects", "count" ),
+        value_type( "inuse_space", "bytes" )
+    ];
+
+    (sample_types, strings.get( "inuse_space" ))
+}
+
+/// Returns an `ExportProfilesServiceRequest` (as defined by the `v1experimental` version of the
+/// profiling signal) with the matched allocations aggregated by their backtraces.
+pub fn get_profile( data: &Data, filter: &Filter ) -> Value {
+    // alloc_objects, alloc_space, inuse_objects, inuse_space; same as in the Go's heap profiles.
+    let mut values_by_backtrace: HashMap< BacktraceId, [i64; 4] > = HashMap::new();
+    for allocation in data.allocations() {
+        if !match_allocation( data, allocation, filter ) {
+            continue;
+        }
+
+        let values = values_by_backtrace.entry( allocation.backtrace ).or_insert( [0; 4] );
+        values[ 0 ] += 1;
+        values[ 1 ] += allocation.size as i64;
+        if !allocation.was_deallocated() {
+            values[ 2 ] += 1;
+            values[ 3 ] += allocation.size as i64;
+        }
+    }
+
+    let mut backtraces: Vec< _ > = values_by_backtrace.into_iter().collect();
+    backtraces.sort_by_key( |&(backtrace_id, _)| backtrace_id );
+
+    let mut strings = StringTable::new();
+    let mut functions = Vec::new();
+    let mut function_index_by_name: HashMap< (usize, usize), usize > = HashMap::new();
+    let mut locations = Vec::new();
+    let mut location_index_by_frame: HashMap< FrameId, usize > = HashMap::new();
+    let mut location_indices = Vec::new();
+    let mut samples = Vec::new();
+
+    let resolve = |id| data.interner().resolve( id ).unwrap();
+    for (backtrace_id, values) in backtraces {
+        let start = location_indices.len();
+
+        // The innermost frame has to be first.
+        let frames: Vec< _ > = data.get_backtrace( backtrace_id ).collect();
+        for &(frame_id, frame) in frames.iter().rev() {
+            let location_index = match location_index_by_frame.get( &frame_id ) {
+                Some( &index ) => index,
+                None => {
+                    let mut line = Vec::new();
+                    if let Some( function ) = frame.any_function() {
+                        let name = strings.get( resolve( function ) );
+                        let filename = strings.get( frame.source().map( resolve ).unwrap_or( "" ) );
+                        let function_index = *function_index_by_name.entry( (name, filename) ).or_insert_with( || {
+                            functions.push( json!({
+                                "id": (functions.len() + 1).to_string(),
+                                "name": name.to_string(),
+                                "systemName": strings.get( frame.raw_function().map( resolve ).unwrap_or( "" ) ).to_string(),
+                                "filename": filename.to_string()
+                            }));
+                            functions.len() - 1
+                        });
+
+                        line.push( json!({
+                            "functionIndex": function_index.to_string(),
+                            "line": frame.line().unwrap_or( 0 ).to_string(),
+                            "column": frame.column().unwrap_or( 0 ).to_string()
+                        }));
+                    }
+
+                    locations.push( json!({
+                        "id": (locations.len() + 1).to_string(),
+                        "address": frame.address().raw().to_string(),
+                        "line": line,
+                        "isFolded": frame.is_inline()
+                    }));
+
+                    location_index_by_frame.insert( frame_id, locations.len() - 1 );
+                    locations.len() - 1
+                }
+            };
+
+            location_indices.push( location_index.to_string() );
+        }
+
+        samples.push( json!({
+            "locationsStartIndex": start.to_string(),
+            "locationsLength": (location_indices.len() - start).to_string(),
+            "value": values.iter().map( |value| value.to_string() ).collect::< Vec< _ > >()
+        }));
+    }
+
+    let (sample_types, default_sample_type) = get_sample_types( &mut strings );
+
+    let start = data.initial_timestamp().as_usecs() as u128 * 1000;
+    let end = data.last_timestamp().as_usecs() as u128 * 1000;
+    json!({
+        "resourceProfiles": [{
+            "resource": resource( data ),
+            "scopeProfiles": [{
+                "scope": scope(),
+                "profiles": [{
+                    "profileId": data.id().to_string(),
+                    "startTimeUnixNano": start.to_string(),
+                    "endTimeUnixNano": end.to_string(),
+                    "profile": {
+                        "sampleType": sample_types,
+                        "sample": samples,
+                        "location": locations,
+                        "locationIndices": location_indices,
+                        "function": functions,
+                        "stringTable": strings.strings,
+                        "timeNanos": start.to_string(),
+                        "durationNanos": (end - start).to_string(),
+                        "defaultSampleType": default_sample_type.to_string()
+                    }
+                }]
+            }]
+        }]
+    })
+}
+
+/// Posts a JSON body to `path` of an OTLP/HTTP collector, e.g. `http://localhost:4318`.
+fn post( collector: &str, path: &str, body: &Value ) -> io::Result< () > {
+    let rest = collector.strip_prefix( "http://" ).ok_or_else( || {
+        io::Error::new( io::ErrorKind::InvalidInput, format!( "unsupported collector URL '{}'; only 'http://' is supported", collector ) )
+    })?;
+
+    let (host, prefix) = match rest.find( '/' ) {
+        Some( index ) => (&rest[ ..index ], rest[ index.. ].trim_end_matches( '/' )),
+        None => (rest, "")
+    };
+
+    let body = serde_json::to_vec( body )?;
+    let address = host.to_socket_addrs()?.next().ok_or_else( || io::Error::new( io::ErrorKind::NotFound, format!( "failed to resolve '{}'", host ) ) )?;
+    let mut stream = TcpStream::connect_timeout( &address, PUSH_TIMEOUT )?;
+    stream.set_read_timeout( Some( PUSH_TIMEOUT ) )?;
+    stream.set_write_timeout( Some( PUSH_TIMEOUT ) )?;
+    write!(
+        stream,
+        "POST {}{} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        prefix,
+        path,
+        host,
+        body.len()
+    )?;
+    stream.write_all( &body )?;
+
+    let mut response = Vec::new();
+    stream.read_to_end( &mut response )?;
+    let status_line = String::from_utf8_lossy( &response ).lines().next().unwrap_or( "" ).to_owned();
+    match status_line.split_whitespace().nth( 1 ) {
+        Some( status ) if status.starts_with( '2' ) => Ok(()),
+        _ => Err( io::Error::new( io::ErrorKind::Other, format!( "the collector has responded to {} with {:?}", path, status_line ) ) )
+    }
+}
+
+/// Loads a data file and pushes its metrics and the profile of all of its allocations to an OTLP/HTTP collector.
+pub fn push_main( input: PathBuf, symbol_sources: SymbolSources, frame_rules: Option< PathBuf >, collector: &str ) -> Result< (), Box< dyn Error > > {
+    let mut data = Loader::load_from_file( &input, &symbol_sources )?;
+    if let Some( path ) = frame_rules {
+        data.apply_frame_rules( &FrameRules::load( &path )? );
+    }
+
+    let filter: protocol::AllocFilter = serde_urlencoded::from_str( "" ).unwrap();
+    let filter = match prepare_filter( &data, &filter, None ) {
+        Ok( filter ) => filter,
+        Err( _ ) => return Err( "failed to prepare an empty filter".into() )
+    };
+
+    post( collector, "/v1/metrics", &get_metrics( &data ) )?;
+    post( collector, "/v1/profiles", &get_profile( &data, &filter ) )?;
+    info!( "Pushed {} to {}", data.id(), collector );
+
+    Ok(())
+}
+
+#[test]
+fn test_sample_types() {
+    let mut strings = StringTable::new();
+    strings.get( "malloc" );
+
+    let (sample_types, default_sample_type) = get_sample_types( &mut strings );
+    assert_eq!( strings.strings[ default_sample_type ], "inuse_space" );
+    assert_eq!( sample_types[ 3 ][ "type" ], default_sample_type.to_string() );
+    assert_eq!( strings.strings[ sample_types[ 0 ][ "unit" ].as_str().unwrap().parse::< usize >().unwrap() ], "count" );
+}
+
+#[test]
+fn test_string_table() {
+    let mut strings = StringTable::new();
+    assert_eq!( strings.get( "malloc" ), 1 );
+    assert_eq!( strings.get( "main" ), 2 );
+    assert_eq!( strings.get( "malloc" ), 1 );
+    assert_eq!( strings.get( "" ), 0 );
+    assert_eq!( strings.strings, vec![ "", "malloc", "main" ] );
+}
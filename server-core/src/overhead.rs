@@ -0,0 +1,113 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use ahash::AHashMap as HashMap;
+use serde::Serialize;
+
+use cli_core::{
+    AllocationColumns,
+    BacktraceId,
+    Data
+};
+
+use crate::protocol;
+use crate::filter::{AllocationMatcher, Filter};
+use crate::streaming_serializer::{StreamingSerializer, Rows};
+use crate::get_frame;
+
+#[derive(Default)]
+struct OverheadEntry {
+    count: u64,
+    size: u64,
+    extra_space: u64
+}
+
+impl OverheadEntry {
+    fn overhead_ratio( &self ) -> f64 {
+        if self.size == 0 {
+            0.0
+        } else {
+            self.extra_space as f64 / self.size as f64
+        }
+    }
+}
+
+pub fn get_overhead< 'a >(
+    data: &'a Data,
+    columns: Option< &AllocationColumns >,
+    backtrace_format: protocol::BacktraceFormat,
+    params: protocol::RequestOverhead,
+    filter: Filter
+) -> protocol::ResponseOverhead< impl Serialize + Rows + 'a > {
+    let remaining = params.count.unwrap_or( -1_i32 as _ ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+    let sort_by = params.sort_by.unwrap_or( protocol::OverheadSortBy::ExtraSpace );
+    let order = params.order.unwrap_or( protocol::Order::Dsc );
+
+    let mut total = OverheadEntry::default();
+    let mut entry_by_backtrace: HashMap< BacktraceId, OverheadEntry > = HashMap::new();
+    let matcher = AllocationMatcher::new( data, columns, &filter );
+    let iter = data.alloc_sorted_by_timestamp( filter.timestamp_start_opt(), filter.timestamp_end_opt() );
+    for (id, allocation) in iter {
+        if !matcher.matches( id, allocation ) {
+            continue;
+        }
+
+        let extra_space = allocation.extra_usable_space as u64;
+        let entry = entry_by_backtrace.entry( allocation.backtrace ).or_insert_with( OverheadEntry::default );
+        entry.count += 1;
+        entry.size += allocation.size;
+        entry.extra_space += extra_space;
+
+        total.count += 1;
+        total.size += allocation.size;
+        total.extra_space += extra_space;
+    }
+
+    let mut entries: Vec< _ > = entry_by_backtrace.into_iter().collect();
+    entries.sort_by( |(lhs_id, lhs), (rhs_id, rhs)| {
+        let ordering = match sort_by {
+            protocol::OverheadSortBy::Count => lhs.count.cmp( &rhs.count ),
+            protocol::OverheadSortBy::Size => lhs.size.cmp( &rhs.size ),
+            protocol::OverheadSortBy::ExtraSpace => lhs.extra_space.cmp( &rhs.extra_space ),
+            protocol::OverheadSortBy::OverheadRatio => lhs.overhead_ratio().partial_cmp( &rhs.overhead_ratio() ).unwrap_or( Ordering::Equal )
+        };
+
+        ordering.then_with( || lhs_id.cmp( rhs_id ) )
+    });
+
+    if order == protocol::Order::Dsc {
+        entries.reverse();
+    }
+
+    let total_count = entries.len() as u64;
+    let entries = Arc::new( entries );
+    let groups = move || {
+        let backtrace_format = backtrace_format.clone();
+        let entries = entries.clone();
+        (0..entries.len())
+            .skip( skip )
+            .take( remaining )
+            .map( move |index| {
+                let (backtrace_id, ref entry) = entries[ index ];
+                let backtrace = data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, &backtrace_format, frame ) ).collect();
+                protocol::OverheadGroup {
+                    backtrace_id: backtrace_id.raw(),
+                    backtrace,
+                    count: entry.count,
+                    size: entry.size,
+                    extra_space: entry.extra_space,
+                    overhead_ratio: entry.overhead_ratio()
+                }
+            })
+    };
+
+    protocol::ResponseOverhead {
+        count: total.count,
+        size: total.size,
+        extra_space: total.extra_space,
+        overhead_ratio: total.overhead_ratio(),
+        groups: StreamingSerializer::new( groups ),
+        total_count
+    }
+}
@@ -0,0 +1,208 @@
+use ahash::AHashMap as HashMap;
+
+use cli_core::{
+    BacktraceId,
+    Data,
+    Operation,
+    Timestamp
+};
+
+use crate::protocol;
+use crate::get_frame;
+
+const BUCKET_COUNT: usize = 1024;
+
+#[derive(Default)]
+struct PeakSiteUsage {
+    live_count: u64,
+    live_size: u64,
+    rise_count: u64,
+    rise_size: u64
+}
+
+/// Returns the maximum amount of live memory within each of the evenly sized buckets
+/// spanning the whole runtime, along with when exactly it was reached.
+fn get_bucketed_timeline( data: &Data ) -> Vec< (Timestamp, u64) > {
+    let start = data.initial_timestamp().as_usecs();
+    let duration = std::cmp::max( data.last_timestamp().as_usecs().saturating_sub( start ), 1 );
+
+    let mut buckets = vec![ (data.initial_timestamp(), 0); BUCKET_COUNT ];
+    let mut live: u64 = 0;
+    let mut last_index = 0;
+    let mut last_timestamp = data.initial_timestamp();
+    for op in data.operations() {
+        let live_before = live;
+        let timestamp = match op {
+            Operation::Allocation { allocation, .. } => {
+                live = live.saturating_add( allocation.size );
+                allocation.timestamp
+            },
+            Operation::Deallocation { allocation, deallocation, .. } => {
+                live = live.saturating_sub( allocation.size );
+                deallocation.timestamp
+            },
+            Operation::Reallocation { new_allocation, old_allocation, .. } => {
+                live = live.saturating_sub( old_allocation.size ).saturating_add( new_allocation.size );
+                new_allocation.timestamp
+            }
+        };
+
+        let offset = timestamp.as_usecs().saturating_sub( start ) as u128;
+        let index = std::cmp::min( (offset * BUCKET_COUNT as u128 / duration as u128) as usize, BUCKET_COUNT - 1 );
+
+        if index > last_index {
+            // The buckets without any operations hold whatever was live before them.
+            for bucket in &mut buckets[ last_index + 1..index ] {
+                *bucket = (last_timestamp, live_before);
+            }
+
+            buckets[ index ] = (timestamp, live);
+            last_index = index;
+        } else if live > buckets[ last_index ].1 {
+            buckets[ last_index ] = (timestamp, live);
+        }
+
+        last_timestamp = timestamp;
+    }
+
+    for bucket in &mut buckets[ last_index + 1.. ] {
+        *bucket = (last_timestamp, live);
+    }
+
+    buckets
+}
+
+/// Finds the local maxima whose topographic prominence is at least `prominence_min`, and returns
+/// their indexes along with their prominence and the index of the lowest point on their left
+/// before reaching a higher peak, which is where the rise towards them has started.
+fn find_peaks( values: &[u64], prominence_min: u64 ) -> Vec< (usize, u64, usize) > {
+    let mut peaks = Vec::new();
+    let mut index = 0;
+    while index < values.len() {
+        let value = values[ index ];
+        let mut end = index;
+        while end + 1 < values.len() && values[ end + 1 ] == value {
+            end += 1;
+        }
+
+        let is_higher_than_left = index == 0 || values[ index - 1 ] < value;
+        let is_higher_than_right = end + 1 == values.len() || values[ end + 1 ] < value;
+        if is_higher_than_left && is_higher_than_right {
+            let mut left_base = index;
+            let mut left = index;
+            while left > 0 && values[ left - 1 ] <= value {
+                left -= 1;
+                if values[ left ] < values[ left_base ] {
+                    left_base = left;
+                }
+            }
+
+            let mut right_min = value;
+            let mut right = end;
+            while right + 1 < values.len() && values[ right + 1 ] <= value {
+                right += 1;
+                right_min = std::cmp::min( right_min, values[ right ] );
+            }
+
+            let prominence = value - std::cmp::max( values[ left_base ], right_min );
+            if prominence > 0 && prominence >= prominence_min {
+                peaks.push( (index, prominence, left_base) );
+            }
+        }
+
+        index = end + 1;
+    }
+
+    peaks
+}
+
+/// Converts the minimum prominence given as a percentage of the highest peak into an absolute value.
+fn prominence_threshold( peak_value: u64, prominence_min_percent: u32 ) -> u64 {
+    (peak_value as u128 * std::cmp::min( prominence_min_percent, 100 ) as u128 / 100) as u64
+}
+
+#[test]
+fn test_prominence_threshold() {
+    assert_eq!( prominence_threshold( 1000, 10 ), 100 );
+    assert_eq!( prominence_threshold( u64::MAX, 100 ), u64::MAX );
+    assert_eq!( prominence_threshold( u64::MAX, u32::MAX ), u64::MAX );
+}
+
+#[test]
+fn test_find_peaks() {
+    let values = [0, 10, 2, 2, 8, 8, 3, 20, 0];
+    assert_eq!( find_peaks( &values, 0 ), vec![ (1, 8, 0), (4, 5, 3), (7, 20, 0) ] );
+    assert_eq!( find_peaks( &values, 6 ), vec![ (1, 8, 0), (7, 20, 0) ] );
+    assert_eq!( find_peaks( &[5, 5, 5], 0 ), vec![] );
+    assert_eq!( find_peaks( &[], 0 ), vec![] );
+}
+
+/// Finds all of the significant peaks in the memory usage and breaks each of them down by call site.
+pub fn get_peaks< 'a >(
+    data: &'a Data,
+    backtrace_format: &protocol::BacktraceFormat,
+    params: &protocol::RequestPeaks
+) -> protocol::ResponsePeaks< 'a > {
+    let remaining = params.count.unwrap_or( 10 ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+    let sites_count = params.sites_count.unwrap_or( 10 ) as usize;
+    let prominence_min = params.prominence_min.unwrap_or( 10 );
+
+    let timeline = get_bucketed_timeline( data );
+    let values: Vec< u64 > = timeline.iter().map( |&(_, value)| value ).collect();
+    let peak_value = values.iter().copied().max().unwrap_or( 0 );
+    let mut peaks = find_peaks( &values, prominence_threshold( peak_value, prominence_min ) );
+    peaks.sort_by_key( |&(index, prominence, _)| (std::cmp::Reverse( prominence ), index) );
+
+    let total_count = peaks.len() as u64;
+    let peaks = peaks.into_iter().skip( skip ).take( remaining ).map( |(index, prominence, base_index)| {
+        let (timestamp, live_size) = timeline[ index ];
+        let (base_timestamp, base_size) = timeline[ base_index ];
+
+        let mut usage_by_backtrace: HashMap< BacktraceId, PeakSiteUsage > = HashMap::new();
+        for (_, allocation) in data.alloc_sorted_by_timestamp( None, Some( timestamp ) ) {
+            crate::query_limits::check_deadline();
+            if allocation.deallocation.as_ref().map( |deallocation| deallocation.timestamp <= timestamp ).unwrap_or( false ) {
+                continue;
+            }
+
+            let usage = usage_by_backtrace.entry( allocation.backtrace ).or_insert_with( PeakSiteUsage::default );
+            usage.live_count += 1;
+            usage.live_size = usage.live_size.saturating_add( allocation.size );
+            if allocation.timestamp > base_timestamp {
+                usage.rise_count += 1;
+                usage.rise_size = usage.rise_size.saturating_add( allocation.size );
+            }
+        }
+
+        let mut usage_by_backtrace: Vec< _ > = usage_by_backtrace.into_iter().collect();
+        usage_by_backtrace.sort_by_key( |(backtrace_id, usage)| (std::cmp::Reverse( usage.rise_size ), std::cmp::Reverse( usage.live_size ), *backtrace_id) );
+
+        let sites = usage_by_backtrace.into_iter().take( sites_count ).map( |(backtrace_id, usage)| {
+            protocol::PeakSite {
+                backtrace_id: backtrace_id.raw(),
+                site_id: data.get_site_id( backtrace_id ).to_string(),
+                backtrace: data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, backtrace_format, frame ) ).collect(),
+                live_count: usage.live_count,
+                live_size: usage.live_size,
+                rise_count: usage.rise_count,
+                rise_size: usage.rise_size
+            }
+        }).collect();
+
+        protocol::Peak {
+            timestamp: timestamp.into(),
+            timestamp_relative: (timestamp - data.initial_timestamp()).into(),
+            live_size,
+            prominence,
+            base_timestamp: base_timestamp.into(),
+            base_size,
+            sites
+        }
+    }).collect();
+
+    protocol::ResponsePeaks {
+        peaks,
+        total_count
+    }
+}
@@ -0,0 +1,175 @@
+//! Support for analyses which are shipped separately from the server and loaded at runtime.
+//!
+//! A plugin is a `cdylib` crate which depends on `server-core` and `cli-core`, implements
+//! the `Plugin` trait and exports it with the `declare_plugin!` macro:
+//!
+//! ```ignore
+//! use server_core::plugin::{Plugin, PluginResponse};
+//!
+//! struct MyPlugin;
+//!
+//! impl Plugin for MyPlugin {
+//!     fn name( &self ) -> &str { "my_plugin" }
+//!     fn endpoints( &self ) -> Vec< String > { vec![ "allocation_count".to_owned() ] }
+//!     fn handle( &self, data: &cli_core::Data, _endpoint: &str, _query: &str ) -> Result< PluginResponse, String > {
+//!         PluginResponse::json( &data.total_allocated_count() )
+//!     }
+//! }
+//!
+//! server_core::declare_plugin!( MyPlugin );
+//! ```
+//!
+//! Since Rust doesn't have a stable ABI the plugin has to be built with the same compiler
+//! and against the same build of this crate as the server which loads it. This is checked
+//! through `build_id`: cargo mixes the compiler version, the crate versions, their features
+//! and their dependencies into the hash of every crate it builds, and the type IDs
+//! of the types which cross the boundary are derived from it.
+
+use std::any::TypeId;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::os::raw::c_void;
+use std::path::Path;
+
+use libloading::Library;
+use serde::Serialize;
+
+use cli_core::Data;
+
+/// Bumped every time the `Plugin` trait changes in an incompatible way.
+pub const PLUGIN_API_VERSION: u32 = 2;
+
+/// Identifies the exact build of this crate and of `cli-core`; a plugin can only be loaded by a server with the same ID.
+pub fn build_id() -> u64 {
+    let mut hasher = DefaultHasher::new();
+    env!( "CARGO_PKG_VERSION" ).hash( &mut hasher );
+    TypeId::of::< dyn Plugin >().hash( &mut hasher );
+    TypeId::of::< PluginResponse >().hash( &mut hasher );
+    TypeId::of::< Data >().hash( &mut hasher );
+    hasher.finish()
+}
+
+pub struct PluginResponse {
+    pub content_type: String,
+    pub body: Vec< u8 >
+}
+
+impl PluginResponse {
+    pub fn json< T: Serialize >( value: &T ) -> Result< Self, String > {
+        let body = serde_json::to_vec( value ).map_err( |error| error.to_string() )?;
+        Ok( PluginResponse {
+            content_type: "application/json".to_owned(),
+            body
+        })
+    }
+}
+
+pub trait Plugin: Send + Sync {
+    /// The name under which the plugin's endpoints are exposed.
+    fn name( &self ) -> &str;
+
+    /// The endpoints which are exposed under `/data/<id>/plugins/<name>/<endpoint>`.
+    fn endpoints( &self ) -> Vec< String >;
+
+    /// Called in the background once for every data file after it was loaded, so that
+    /// the plugin can precompute whatever its endpoints need.
+    fn on_load( &self, _data: &Data ) {}
+
+    /// Handles a request to one of the plugin's endpoints; the query string is passed as-is.
+    fn handle( &self, data: &Data, endpoint: &str, query: &str ) -> Result< PluginResponse, String >;
+}
+
+/// Exports a plugin so that it can be loaded by the server.
+#[macro_export]
+macro_rules! declare_plugin {
+    ($constructor:expr) => {
+        #[no_mangle]
+        pub static MEMORY_PROFILER_PLUGIN_API_VERSION: u32 = $crate::plugin::PLUGIN_API_VERSION;
+
+        #[no_mangle]
+        pub extern "C" fn memory_profiler_plugin_build_id() -> u64 {
+            $crate::plugin::build_id()
+        }
+
+        /// Returns a null pointer if the plugin's constructor panics, since a panic can't unwind into the server.
+        #[no_mangle]
+        pub extern "C" fn memory_profiler_plugin_create() -> *mut std::os::raw::c_void {
+            let result = std::panic::catch_unwind( || {
+                let plugin: Box< dyn $crate::plugin::Plugin > = Box::new( $constructor );
+                plugin
+            });
+
+            match result {
+                Ok( plugin ) => Box::into_raw( Box::new( plugin ) ) as *mut std::os::raw::c_void,
+                Err( _ ) => std::ptr::null_mut()
+            }
+        }
+    };
+}
+
+struct LoadedPlugin {
+    // This has to be dropped before the library it came from.
+    plugin: Box< dyn Plugin >,
+    _library: Library
+}
+
+#[derive(Default)]
+pub(crate) struct Plugins {
+    plugins: Vec< LoadedPlugin >
+}
+
+fn load_error( path: &Path, message: String ) -> io::Error {
+    io::Error::new( io::ErrorKind::Other, format!( "failed to load plugin {:?}: {}", path, message ) )
+}
+
+impl Plugins {
+    pub(crate) fn load( &mut self, path: &Path ) -> io::Result< () > {
+        let plugin = unsafe {
+            let library = Library::new( path ).map_err( |error| load_error( path, error.to_string() ) )?;
+            let version = *library.get::< *const u32 >( b"MEMORY_PROFILER_PLUGIN_API_VERSION\0" )
+                .map_err( |error| load_error( path, error.to_string() ) )?;
+            if *version != PLUGIN_API_VERSION {
+                return Err( load_error( path, format!( "it was built for API version {} while version {} is required", *version, PLUGIN_API_VERSION ) ) );
+            }
+
+            let plugin_build_id = library.get::< extern "C" fn() -> u64 >( b"memory_profiler_plugin_build_id\0" )
+                .map_err( |error| load_error( path, error.to_string() ) )?;
+            if plugin_build_id() != build_id() {
+                return Err( load_error( path, "it was built with a different compiler or against a different build of the server".to_owned() ) );
+            }
+
+            let plugin = {
+                let create = library.get::< extern "C" fn() -> *mut c_void >( b"memory_profiler_plugin_create\0" )
+                    .map_err( |error| load_error( path, error.to_string() ) )?;
+                let plugin = create() as *mut Box< dyn Plugin >;
+                if plugin.is_null() {
+                    return Err( load_error( path, "its constructor has panicked".to_owned() ) );
+                }
+
+                *Box::from_raw( plugin )
+            };
+
+            LoadedPlugin {
+                plugin,
+                _library: library
+            }
+        };
+
+        if self.get( plugin.plugin.name() ).is_some() {
+            return Err( load_error( path, format!( "a plugin named '{}' was already loaded", plugin.plugin.name() ) ) );
+        }
+
+        info!( "Loaded plugin '{}' from {:?}", plugin.plugin.name(), path );
+        self.plugins.push( plugin );
+        Ok(())
+    }
+
+    pub(crate) fn get( &self, name: &str ) -> Option< &dyn Plugin > {
+        self.iter().find( |plugin| plugin.name() == name )
+    }
+
+    pub(crate) fn iter( &self ) -> impl Iterator< Item = &dyn Plugin > {
+        self.plugins.iter().map( |loaded| &*loaded.plugin )
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+
+use cli_core::{
+    AllocationColumns,
+    Data,
+    DataId,
+    export_as_flamegraph
+};
+
+use crate::filter::{match_allocation, prepare_filter};
+use crate::histograms::get_histograms;
+use crate::protocol;
+use crate::{
+    AllocationGroups,
+    AllocationGroupsKey,
+    StateRef,
+    build_allocation_groups,
+    get_timeline
+};
+
+/// The aggregates which are requested by pretty much every client right after a data file is opened,
+/// computed in the background right after loading so that they don't have to be computed on demand.
+#[derive(Default)]
+pub(crate) struct Precomputed {
+    timeline: Mutex< Option< Arc< protocol::ResponseTimeline > > >,
+    timeline_json: Mutex< Option< Arc< Vec< u8 > > > >,
+    histograms: Mutex< Option< Arc< Vec< u8 > > > >,
+    columns: Mutex< Option< Arc< AllocationColumns > > >,
+    allocation_groups: Mutex< Option< (AllocationGroupsKey, Arc< AllocationGroups >) > >,
+    flamegraph: Mutex< Option< Arc< String > > >
+}
+
+impl Precomputed {
+    pub(crate) fn timeline( &self ) -> Option< Arc< protocol::ResponseTimeline > > {
+        self.timeline.lock().clone()
+    }
+
+    /// The timeline, already serialized into JSON.
+    pub(crate) fn timeline_json( &self ) -> Option< Arc< Vec< u8 > > > {
+        self.timeline_json.lock().clone()
+    }
+
+    /// The unfiltered histograms, already serialized into JSON.
+    pub(crate) fn histograms( &self ) -> Option< Arc< Vec< u8 > > > {
+        self.histograms.lock().clone()
+    }
+
+    /// The columnar copy of the allocations used to speed up the filtering.
+    pub(crate) fn columns( &self ) -> Option< Arc< AllocationColumns > > {
+        self.columns.lock().clone()
+    }
+
+    pub(crate) fn allocation_groups( &self, key: &AllocationGroupsKey ) -> Option< Arc< AllocationGroups > > {
+        match *self.allocation_groups.lock() {
+            Some( (ref precomputed_key, ref groups) ) if precomputed_key == key => Some( groups.clone() ),
+            _ => None
+        }
+    }
+
+    /// The unfiltered flamegraph, as an SVG.
+    pub(crate) fn flamegraph( &self ) -> Option< Arc< String > > {
+        self.flamegraph.lock().clone()
+    }
+
+    fn compute( &self, data: &Data, memory_budget: Option< usize > ) {
+        let columns = Arc::new( AllocationColumns::new( data ) );
+        *self.columns.lock() = Some( columns.clone() );
+
+        let timeline = get_timeline( data );
+        match serde_json::to_vec( &timeline ) {
+            Ok( timeline ) => *self.timeline_json.lock() = Some( Arc::new( timeline ) ),
+            Err( error ) => warn!( "Failed to serialize the timeline of {}: {}", data.id(), error )
+        }
+
+        *self.timeline.lock() = Some( Arc::new( timeline ) );
+
+        // This is what the allocation groups are requested with when no filter is set.
+        let filter: protocol::AllocFilter = serde_urlencoded::from_str( "" ).unwrap();
+        match prepare_filter( data, &filter, memory_budget ) {
+            Ok( prepared_filter ) => {
+                let key = AllocationGroupsKey {
+                    data_id: data.id(),
+                    filter,
+                    sort_by: protocol::AllocGroupsSortBy::MinTimestamp,
+                    order: protocol::Order::Asc
+                };
+
+                let groups = build_allocation_groups( data, Some( &columns ), &prepared_filter, key.sort_by, key.order );
+                *self.allocation_groups.lock() = Some( (key, Arc::new( groups )) );
+
+                let histograms = get_histograms( data, Some( &columns ), &prepared_filter );
+                match serde_json::to_vec( &histograms ) {
+                    Ok( histograms ) => *self.histograms.lock() = Some( Arc::new( histograms ) ),
+                    Err( error ) => warn!( "Failed to serialize the histograms of {}: {}", data.id(), error )
+                }
+
+                let mut flamegraph = String::new();
+                export_as_flamegraph( data, &mut flamegraph, |allocation| match_allocation( data, allocation, &prepared_filter ), false );
+                *self.flamegraph.lock() = Some( Arc::new( flamegraph ) );
+            },
+            Err( _ ) => warn!( "Failed to prepare an empty filter for {}", data.id() )
+        }
+    }
+}
+
+/// How many data files can have their aggregates precomputed at the same time.
+const MAX_THREADS: usize = 4;
+
+fn precompute( state: &StateRef, data_id: DataId ) {
+    let data = state.data.get( &data_id ).unwrap();
+    let precomputed = state.precomputed.get( &data_id ).unwrap();
+
+    info!( "Precomputing the aggregates of {}...", data_id );
+    let started_at = Instant::now();
+    precomputed.compute( data, state.limits.memory_budget );
+    let elapsed = started_at.elapsed();
+    info!( "Precomputed the aggregates of {} in {}s {:03}", data_id, elapsed.as_secs(), elapsed.subsec_millis() );
+
+    for plugin in state.plugins.iter() {
+        if panic::catch_unwind( AssertUnwindSafe( || plugin.on_load( data ) ) ).is_err() {
+            error!( "Plugin '{}' has panicked while processing {}", plugin.name(), data_id );
+        }
+    }
+}
+
+/// Starts precomputing the common aggregates of every loaded data file on a few background threads,
+/// and then lets the plugins run their own passes over it.
+pub(crate) fn spawn( state: &StateRef ) {
+    let queue: Arc< Mutex< VecDeque< DataId > > > = Arc::new( Mutex::new( state.data_ids.iter().cloned().collect() ) );
+    let thread_count = std::cmp::min( state.data_ids.len(), MAX_THREADS );
+    for _ in 0..thread_count {
+        let state = state.clone();
+        let queue = queue.clone();
+        thread::spawn( move || {
+            loop {
+                let data_id = match queue.lock().pop_front() {
+                    Some( data_id ) => data_id,
+                    None => break
+                };
+
+                precompute( &state, data_id );
+            }
+        });
+    }
+}
@@ -2,6 +2,7 @@ use std::borrow::Cow;
 use std::marker::PhantomData;
 use std::str::FromStr;
 use std::fmt;
+use std::collections::BTreeMap;
 
 use serde::Serialize;
 use cli_core::Timestamp;
@@ -61,26 +62,175 @@ pub struct ResponseMetadata {
     pub runtime: Timeval,
     pub unique_backtrace_count: u64,
     pub maximum_backtrace_depth: u32,
-    pub timestamp: Timeval
+    pub timestamp: Timeval,
+    pub total_allocated: u64,
+    pub total_allocated_count: u64,
+    pub total_freed: u64,
+    pub total_freed_count: u64,
+    pub peak_allocated: u64,
+    pub peak_allocated_timestamp: Timeval,
+    pub unknown_deallocation_count: u64,
+    pub duplicate_allocation_count: u64,
+    pub lost_chunk_count: u64,
+    pub lost_event_count: u64,
+    pub wall_clock_start: Timeval,
+    pub build_id: Option< String >,
+    pub allocator: Option< ResponseAllocatorInfo >,
+    pub metadata: BTreeMap< String, String >,
+    pub data_quality: ResponseDataQuality,
+    pub shard: Option< String >
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Debug)]
+pub enum DataQualityRating {
+    #[serde(rename = "good")]
+    Good,
+    #[serde(rename = "fair")]
+    Fair,
+    #[serde(rename = "poor")]
+    Poor
+}
+
+#[derive(Serialize)]
+pub struct ResponseDataQuality {
+    pub rating: DataQualityRating,
+    pub unknown_deallocation_count: u64,
+    pub unknown_deallocation_fraction: f32,
+    pub first_unknown_deallocation: Option< Timeval >,
+    pub last_unknown_deallocation: Option< Timeval >,
+    pub duplicate_allocation_count: u64,
+    pub untracked_heap_size: Option< u64 >,
+    pub untracked_heap_fraction: Option< f32 >,
+    pub lost_chunk_count: u64,
+    pub lost_event_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponsePlugin {
+    pub name: String,
+    pub endpoints: Vec< String >
+}
+
+#[derive(Serialize)]
+pub struct ResponseAllocatorTunable {
+    pub name: String,
+    pub value: String
 }
 
 #[derive(Serialize)]
-pub struct ResponseTimeline {
+pub struct ResponseAllocatorInfo {
+    pub allocator: String,
+    pub allocator_version: String,
+    pub libc_version: String,
+    pub tunables: Vec< ResponseAllocatorTunable >
+}
+
+pub use common::timeline::Timeline as ResponseTimeline;
+
+#[derive(Serialize)]
+pub struct ResponseAllocatorStatsTimeline {
     pub xs: Vec< u64 >,
-    pub size_delta: Vec< i64 >,
-    pub count_delta: Vec< i64 >,
-    pub allocated_size: Vec< u64 >,
-    pub allocated_count: Vec< u64 >,
-    pub leaked_size: Vec< u64 >,
-    pub leaked_count: Vec< u64 >,
-    pub allocations: Vec< u32 >,
-    pub deallocations: Vec< u32 >
+    pub tracked_size: Vec< u64 >,
+    pub heap_size: Vec< u64 >,
+    pub heap_used: Vec< u64 >,
+    pub heap_free: Vec< u64 >,
+    pub mmaped: Vec< u64 >,
+    pub resident: Vec< u64 >
+}
+
+#[derive(Serialize)]
+pub struct ResponseResidentMemoryTimeline {
+    pub xs: Vec< u64 >,
+    pub resident: Vec< u64 >,
+    pub tracked_heap: Vec< u64 >,
+    pub allocator_overhead: Vec< u64 >,
+    pub mapped_files: Vec< Option< u64 > >,
+    pub unknown: Vec< u64 >
 }
 
 #[derive(Serialize)]
 pub struct ResponseFragmentationTimeline {
     pub xs: Vec< u64 >,
-    pub fragmentation: Vec< u64 >
+    pub fragmentation: Vec< u64 >,
+    pub main_arena_fragmentation: Vec< u64 >,
+    pub non_main_arena_fragmentation: Vec< u64 >,
+    pub span: Vec< u64 >,
+    pub used: Vec< u64 >
+}
+
+#[derive(Serialize)]
+pub struct AddressReuseSite< 'a > {
+    pub backtrace_id: u32,
+    pub site_id: String,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub allocated_count: u64,
+    pub allocated_size: u64,
+    pub reused_size: u64,
+    pub unfit_count: u64,
+    pub unfit_size: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseAddressReuse< 'a > {
+    pub xs: Vec< u64 >,
+    pub allocated_size: Vec< u64 >,
+    pub reused_size: Vec< u64 >,
+    pub hole_size: Vec< u64 >,
+    pub used: Vec< u64 >,
+    pub sites: Vec< AddressReuseSite< 'a > >,
+    pub total_count: u64
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Debug)]
+pub enum ArenaKind {
+    #[serde(rename = "main")]
+    Main,
+    #[serde(rename = "non_main")]
+    NonMain
+}
+
+#[derive(Serialize)]
+pub struct PinningAllocation< 'a > {
+    pub address: u64,
+    pub size: u64,
+    pub timestamp: Timeval,
+    pub timestamp_relative: Timeval,
+    pub backtrace_id: u32,
+    pub site_id: String,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub releasable_size: u64
+}
+
+#[derive(Serialize)]
+pub struct PinnedRegion< 'a > {
+    pub kind: ArenaKind,
+    pub start: u64,
+    pub top: u64,
+    pub peak_top: u64,
+    pub used: u64,
+    pub pinned_size: u64,
+    pub explanation: String,
+    pub pinning: Vec< PinningAllocation< 'a > >
+}
+
+#[derive(Serialize)]
+pub struct ResponseArenaTrim< 'a > {
+    pub timestamp: Timeval,
+    pub trim_threshold: u64,
+    pub total_pinned_size: u64,
+    pub regions: Vec< PinnedRegion< 'a > >
+}
+
+#[derive(Serialize)]
+pub struct ResponseArenaTimeline {
+    pub xs: Vec< u64 >,
+    pub main_arena_size: Vec< u64 >,
+    pub main_arena_count: Vec< u64 >,
+    pub non_main_arena_size: Vec< u64 >,
+    pub non_main_arena_count: Vec< u64 >,
+    pub non_main_arena_heap_count: Vec< u64 >,
+    pub mmaped_size: Vec< u64 >,
+    pub mmaped_count: Vec< u64 >
 }
 
 #[derive(Serialize)]
@@ -103,9 +253,11 @@ pub struct ResponseBacktrace< 'a > {
 }
 
 #[derive(Serialize)]
-pub struct Deallocation {
+pub struct Deallocation< 'a > {
     pub timestamp: Timeval,
-    pub thread: u32
+    pub thread: u32,
+    pub backtrace_id: Option< u32 >,
+    pub backtrace: Option< Vec< Frame< 'a > > >
 }
 
 #[derive(Serialize)]
@@ -118,11 +270,18 @@ pub struct Allocation< 'a > {
     pub thread: u32,
     pub size: u64,
     pub backtrace_id: u32,
-    pub deallocation: Option< Deallocation >,
+    pub deallocation: Option< Deallocation< 'a > >,
     pub backtrace: Vec< Frame< 'a > >,
     pub is_mmaped: bool,
     pub in_main_arena: bool,
-    pub extra_space: u32
+    pub extra_space: u32,
+    /// The jemalloc arena which was explicitly requested through `MALLOCX_ARENA`, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jemalloc_arena: Option< u32 >,
+    pub contents: Option< String >,
+    /// The values of the virtual columns, if any were defined.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub columns: Option< BTreeMap< String, serde_json::Value > >
 }
 
 #[derive(Serialize)]
@@ -138,83 +297,707 @@ pub struct AllocationGroupData {
     pub max_timestamp_relative_p: f32,
     pub interval: Timeval,
     pub leaked_count: u64,
-    pub allocated_count: u64
+    pub allocated_count: u64,
+    /// The percentiles of the allocations' lifetimes; `None` if they fall among the leaked allocations.
+    pub lifetime_p50: Option< Timeval >,
+    pub lifetime_p90: Option< Timeval >,
+    pub lifetime_p99: Option< Timeval >,
+    /// The sizes weighted by how long the allocations lived.
+    pub byte_seconds: u64
+}
+
+#[derive(Serialize)]
+pub struct AllocationGroup< 'a > {
+    pub all: AllocationGroupData,
+    pub only_matched: AllocationGroupData,
+    pub backtrace_id: u32,
+    pub site_id: String,
+    pub classification: SiteClassification,
+    pub backtrace: Vec< Frame< 'a > >
+}
+
+#[derive(Serialize)]
+pub struct ChurnGroup< 'a > {
+    pub backtrace_id: u32,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub allocated_count: u64,
+    pub allocated_size: u64,
+    pub deallocated_count: u64,
+    pub deallocated_size: u64,
+    pub allocations_per_second: f64,
+    pub deallocations_per_second: f64,
+    pub bytes_per_second: f64,
+    pub peak_allocations_per_second: f64,
+    pub peak_bytes_per_second: f64
+}
+
+#[derive(Serialize)]
+pub struct OverheadGroup< 'a > {
+    pub backtrace_id: u32,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub count: u64,
+    pub size: u64,
+    pub extra_space: u64,
+    pub overhead_ratio: f64
+}
+
+#[derive(Serialize)]
+pub struct SitePairGroup< 'a > {
+    pub allocation_backtrace_id: u32,
+    pub allocation_backtrace: Vec< Frame< 'a > >,
+    pub deallocation_backtrace_id: Option< u32 >,
+    pub deallocation_backtrace: Option< Vec< Frame< 'a > > >,
+    pub count: u64,
+    pub size: u64,
+    pub cross_thread_count: u64,
+    pub mean_lifetime: Timeval,
+    pub thread_pair_count: u64
+}
+
+#[derive(Serialize)]
+pub struct SizeClassWasteGroup< 'a > {
+    pub backtrace_id: u32,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub count: u64,
+    pub size: u64,
+    pub footprint: u64,
+    pub waste: u64,
+    pub waste_ratio: f64,
+    pub most_common_size: u64,
+    pub suggested_size: Option< u64 >,
+    pub suggested_saving_ratio: Option< f64 >
+}
+
+#[derive(Serialize)]
+pub struct Thread {
+    pub thread: u32,
+    pub name: Option< String >,
+    pub first_timestamp: Timeval,
+    pub last_timestamp: Timeval,
+    pub allocated_count: u64,
+    pub allocated_size: u64,
+    pub deallocated_count: u64,
+    pub deallocated_size: u64,
+    pub live_count: u64,
+    pub live_size: u64
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Debug, Hash)]
+pub enum AllocationKind {
+    #[serde(rename = "main_arena")]
+    MainArena,
+    #[serde(rename = "non_main_arena")]
+    NonMainArena,
+    #[serde(rename = "mmap")]
+    Mmap
+}
+
+#[derive(Serialize)]
+pub struct MapRegion {
+    pub address: u64,
+    pub address_s: String,
+    pub length: u64,
+    pub permissions: String,
+    pub name: String
+}
+
+#[derive(Serialize)]
+pub struct LargestAllocation< 'a > {
+    pub address: u64,
+    pub address_s: String,
+    pub size: u64,
+    pub timestamp: Timeval,
+    pub thread: u32,
+    pub kind: AllocationKind,
+    pub region: Option< MapRegion >,
+    pub backtrace_id: u32,
+    pub backtrace: Vec< Frame< 'a > >
+}
+
+#[derive(Serialize)]
+pub struct ThreadMemory {
+    pub thread: u32,
+    pub live_count: u64,
+    pub live_size: u64,
+    pub owned_count: u64,
+    pub owned_size: u64
+}
+
+#[derive(Serialize)]
+pub struct Marker {
+    pub marker: u32,
+    pub allocated_count: u64,
+    pub allocated_size: u64,
+    pub leaked_count: u64,
+    pub leaked_size: u64,
+    pub backtrace_count: u64
+}
+
+#[derive(Serialize)]
+pub struct Library {
+    pub library: String,
+    pub dynamically_loaded: bool,
+    pub load_count: u64,
+    pub unload_count: u64,
+    pub allocated_count: u64,
+    pub allocated_size: u64,
+    pub leaked_count: u64,
+    pub leaked_size: u64,
+    pub leaked_size_per_load: f64
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct Bookmark {
+    pub name: String,
+    pub url: String
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct Session {
+    /// Named allocation filters, each as a query string.
+    #[serde(default)]
+    pub filters: BTreeMap< String, String >,
+    /// The IDs of the datasets which are being compared.
+    #[serde(default)]
+    pub comparisons: Vec< String >,
+    #[serde(default)]
+    pub bookmarks: Vec< Bookmark >
+}
+
+/// A note left by a user on a dataset, optionally pointing to a backtrace or to a point in time.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Annotation {
+    pub id: u64,
+    pub data_id: String,
+    pub text: String,
+    #[serde(default)]
+    pub backtrace_id: Option< u32 >,
+    /// The number of seconds since the start of the profiling.
+    #[serde(default)]
+    pub at: Option< f64 >,
+    /// When the annotation was created, as a UNIX timestamp.
+    pub created: u64
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestAnnotation {
+    pub text: String,
+    pub backtrace_id: Option< u32 >,
+    pub at: Option< f64 >
+}
+
+#[cfg(feature = "scripting")]
+#[derive(Serialize)]
+pub struct ResponseScript {
+    pub result: Option< serde_json::Value >,
+    pub error: Option< String >
+}
+
+#[derive(Serialize)]
+pub struct ResponseNewSession {
+    pub id: String
+}
+
+#[derive(Serialize)]
+pub struct RegressionSite< 'a > {
+    pub site_id: String,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub leaked: Vec< u64 >,
+    pub peak: Vec< u64 >,
+    pub leaked_slope: f64,
+    pub peak_slope: f64
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Debug, Hash)]
+pub enum ChangeKind {
+    #[serde(rename = "peak")]
+    Peak,
+    #[serde(rename = "leaked")]
+    Leaked,
+    #[serde(rename = "timeline_shape")]
+    TimelineShape,
+    #[serde(rename = "site")]
+    Site,
+    #[serde(rename = "new_library")]
+    NewLibrary,
+    #[serde(rename = "removed_library")]
+    RemovedLibrary,
+    #[serde(rename = "size_distribution")]
+    SizeDistribution
+}
+
+#[derive(Serialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub impact: u64,
+    pub description: String
+}
+
+#[derive(Serialize)]
+pub struct ChangedSite< 'a > {
+    pub site_id: String,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub base_peak: u64,
+    pub base_leaked: u64,
+    pub target_peak: u64,
+    pub target_leaked: u64
+}
+
+#[derive(Serialize)]
+pub struct HistogramBucket {
+    pub min: u64,
+    pub max: u64,
+    pub count: u64,
+    pub size: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseHistograms {
+    pub size: Vec< HistogramBucket >,
+    pub lifetime: Vec< HistogramBucket >,
+    pub leaked_count: u64,
+    pub leaked_size: u64
+}
+
+#[derive(Serialize)]
+pub struct SizeDistributionChange {
+    pub min_size: u64,
+    pub max_size: u64,
+    pub base_count: u64,
+    pub base_size: u64,
+    pub target_count: u64,
+    pub target_size: u64
+}
+
+#[derive(Serialize)]
+pub struct TimelineChange {
+    pub base_runtime: Timeval,
+    pub target_runtime: Timeval,
+    pub base_peak: u64,
+    pub target_peak: u64,
+    pub base_leaked: u64,
+    pub target_leaked: u64,
+    pub base_shape: SiteClassification,
+    pub target_shape: SiteClassification,
+    pub base_live: Vec< u64 >,
+    pub target_live: Vec< u64 >
+}
+
+#[derive(Serialize)]
+pub struct ResponseChanges< 'a > {
+    pub base: String,
+    pub target: String,
+    pub summary: Vec< Change >,
+    pub sites: Vec< ChangedSite< 'a > >,
+    pub new_libraries: Vec< String >,
+    pub removed_libraries: Vec< String >,
+    pub size_distribution: Vec< SizeDistributionChange >,
+    pub timeline: TimelineChange
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Debug, Hash)]
+pub enum DiffStatus {
+    #[serde(rename = "new")]
+    New,
+    #[serde(rename = "removed")]
+    Removed,
+    #[serde(rename = "changed")]
+    Changed,
+    #[serde(rename = "unchanged")]
+    Unchanged
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Default, Serialize, Debug)]
+pub struct DiffUsage {
+    pub allocated_count: u64,
+    pub allocated_size: u64,
+    pub freed_count: u64,
+    pub freed_size: u64,
+    pub leaked_count: u64,
+    pub leaked_size: u64
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Debug)]
+pub struct DiffDelta {
+    pub allocated_count: i64,
+    pub allocated_size: i64,
+    pub freed_count: i64,
+    pub freed_size: i64,
+    pub leaked_count: i64,
+    pub leaked_size: i64
+}
+
+#[derive(Serialize)]
+pub struct DiffSite< 'a > {
+    pub site_id: String,
+    pub status: DiffStatus,
+    pub base_backtrace_id: Option< u32 >,
+    pub target_backtrace_id: Option< u32 >,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub base: DiffUsage,
+    pub target: DiffUsage,
+    pub delta: DiffDelta
+}
+
+#[derive(Serialize)]
+pub struct ResponseDiff< 'a > {
+    pub base: String,
+    pub target: String,
+    pub base_total: DiffUsage,
+    pub target_total: DiffUsage,
+    pub delta: DiffDelta,
+    pub sites: Vec< DiffSite< 'a > >,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct TopSite< 'a > {
+    pub backtrace_id: u32,
+    pub site_id: String,
+    pub classification: SiteClassification,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub allocated_count: u64,
+    pub allocated_size: u64,
+    pub freed_count: u64,
+    pub freed_size: u64,
+    pub min_size: u64,
+    pub max_size: u64,
+    pub first_allocation: Timeval,
+    pub last_allocation: Timeval
+}
+
+#[derive(Serialize)]
+pub struct Mallopt< 'a > {
+    pub timestamp: Timeval,
+    pub thread: u32,
+    pub backtrace_id: u32,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub raw_param: i32,
+    pub param: Option< String >,
+    pub value: i32,
+    pub result: i32
+}
+
+#[derive(Serialize)]
+pub enum MmapOperation< 'a > {
+    #[serde(rename = "mmap")]
+    Mmap {
+        timestamp: Timeval,
+        pointer: u64,
+        pointer_s: String,
+        length: u64,
+        backtrace_id: u32,
+        backtrace: Vec< Frame< 'a > >,
+        requested_address: u64,
+        requested_address_s: String,
+        is_readable: bool,
+        is_writable: bool,
+        is_executable: bool,
+        is_semaphore: bool,
+        grows_down: bool,
+        grows_up: bool,
+        is_shared: bool,
+        is_private: bool,
+        is_fixed: bool,
+        is_anonymous: bool,
+        is_uninitialized: bool,
+        offset: u64,
+        file_descriptor: i32,
+        thread: u32
+    },
+    #[serde(rename = "munmap")]
+    Munmap {
+        timestamp: Timeval,
+        pointer: u64,
+        pointer_s: String,
+        length: u64,
+        backtrace_id: u32,
+        backtrace: Vec< Frame< 'a > >,
+        thread: u32
+    },
+    #[serde(rename = "mremap")]
+    Mremap {
+        timestamp: Timeval,
+        old_pointer: u64,
+        old_pointer_s: String,
+        old_length: u64,
+        pointer: u64,
+        pointer_s: String,
+        length: u64,
+        backtrace_id: u32,
+        backtrace: Vec< Frame< 'a > >,
+        may_move: bool,
+        is_fixed: bool,
+        thread: u32
+    }
+}
+
+#[derive(Serialize)]
+pub struct ResponseAllocations< T: Serialize > {
+    pub allocations: T,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseAllocationGroups< T: Serialize > {
+    pub allocations: T,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ColumnGroup {
+    pub value: serde_json::Value,
+    pub data: AllocationGroupData
+}
+
+#[derive(Serialize)]
+pub struct ResponseColumnGroups {
+    pub groups: Vec< ColumnGroup >,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseChurn< T: Serialize > {
+    pub window: Timeval,
+    pub duration: Timeval,
+    pub groups: T,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct BacktraceCluster< 'a > {
+    pub backtrace_id: u32,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub backtrace_ids: Vec< u32 >,
+    pub count: u64,
+    pub size: u64,
+    pub leaked_count: u64,
+    pub leaked_size: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseBacktraceClusters< T: Serialize > {
+    pub clusters: T,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseSitePairs< T: Serialize > {
+    pub count: u64,
+    pub size: u64,
+    pub cross_thread_count: u64,
+    pub mean_lifetime: Timeval,
+    pub groups: T,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseOverhead< T: Serialize > {
+    pub count: u64,
+    pub size: u64,
+    pub extra_space: u64,
+    pub overhead_ratio: f64,
+    pub groups: T,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseSizeClassWaste< T: Serialize > {
+    pub count: u64,
+    pub size: u64,
+    pub footprint: u64,
+    pub waste: u64,
+    pub waste_ratio: f64,
+    pub groups: T,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseRegressions< 'a > {
+    pub datasets: Vec< String >,
+    pub sites: Vec< RegressionSite< 'a > >,
+    pub total_count: u64,
+    pub unmatched_backtrace_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ContainerGrowthSite< 'a > {
+    pub backtrace_id: u32,
+    pub site_id: String,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub live_count: u64,
+    pub live_size: u64,
+    pub allocations_per_second: f64,
+    pub regularity: f64,
+    pub live_counts: Vec< u64 >
+}
+
+#[derive(Serialize)]
+pub struct ResponseContainerGrowth< 'a > {
+    pub sites: Vec< ContainerGrowthSite< 'a > >,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct PeakSite< 'a > {
+    pub backtrace_id: u32,
+    pub site_id: String,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub live_count: u64,
+    pub live_size: u64,
+    pub rise_count: u64,
+    pub rise_size: u64
+}
+
+#[derive(Serialize)]
+pub struct Peak< 'a > {
+    pub timestamp: Timeval,
+    pub timestamp_relative: Timeval,
+    pub live_size: u64,
+    pub prominence: u64,
+    pub base_timestamp: Timeval,
+    pub base_size: u64,
+    pub sites: Vec< PeakSite< 'a > >
+}
+
+#[derive(Serialize)]
+pub struct ResponsePeaks< 'a > {
+    pub peaks: Vec< Peak< 'a > >,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseCoreSummary {
+    pub pid: Option< u32 >,
+    pub capture_pid: u32,
+    pub live_allocation_count: u64,
+    /// How many of the live allocations were fully dumped into the core.
+    pub dumped_allocation_count: u64
+}
+
+#[derive(Serialize)]
+pub struct CoreAllocation {
+    pub address: u64,
+    pub address_s: String,
+    pub size: u64,
+    /// The offset of the address which was looked up within the allocation.
+    pub offset: u64,
+    pub timestamp: Timeval,
+    pub backtrace_id: u32
+}
+
+#[derive(Serialize)]
+pub struct CorePointer {
+    pub offset: u64,
+    pub value: u64,
+    pub value_s: String,
+    pub target: CoreAllocation
+}
+
+#[derive(Serialize)]
+pub struct ResponseCoreMemory {
+    pub address: u64,
+    pub address_s: String,
+    pub allocation: Option< CoreAllocation >,
+    pub length: u64,
+    pub hex: String,
+    pub text: String,
+    pub utf8: String,
+    pub pointers: Vec< CorePointer >
+}
+
+#[derive(Serialize)]
+pub struct ResponseTopSites< 'a > {
+    pub sites: Vec< TopSite< 'a > >,
+    pub total_count: u64
+}
+
+#[derive(Serialize)]
+pub struct ResponseMmaps< T: Serialize > {
+    pub operations: T
+}
+
+#[derive(Serialize)]
+pub struct MappedRegion< 'a > {
+    pub address: u64,
+    pub address_s: String,
+    pub length: u64,
+    pub timestamp: Timeval,
+    pub timestamp_relative: Timeval,
+    pub backtrace_id: u32,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub thread: u32,
+    /// In the same format as in `/proc/self/maps`, e.g. `rw-p`; missing if the region wasn't mapped while profiling.
+    pub permissions: Option< String >,
+    pub is_anonymous: Option< bool >,
+    pub file_descriptor: Option< i32 >,
+    pub offset: u64,
+    pub was_remapped: bool
+}
+
+#[derive(Serialize)]
+pub struct MappedSite< 'a > {
+    pub backtrace_id: u32,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub count: u64,
+    pub length: u64
 }
 
 #[derive(Serialize)]
-pub struct AllocationGroup< 'a > {
-    pub all: AllocationGroupData,
-    pub only_matched: AllocationGroupData,
-    pub backtrace_id: u32,
-    pub backtrace: Vec< Frame< 'a > >
+pub struct MappedTimeline {
+    pub xs: Vec< u64 >,
+    pub length: Vec< u64 >,
+    pub count: Vec< u64 >
 }
 
 #[derive(Serialize)]
-pub struct Mallopt< 'a > {
+pub struct ResponseMappedRegions< 'a > {
     pub timestamp: Timeval,
-    pub thread: u32,
-    pub backtrace_id: u32,
-    pub backtrace: Vec< Frame< 'a > >,
-    pub raw_param: i32,
-    pub param: Option< String >,
-    pub value: i32,
-    pub result: i32
+    pub total_length: u64,
+    pub total_count: u64,
+    pub regions: Vec< MappedRegion< 'a > >,
+    pub sites: Vec< MappedSite< 'a > >,
+    pub timeline: MappedTimeline
 }
 
 #[derive(Serialize)]
-pub enum MmapOperation< 'a > {
-    #[serde(rename = "mmap")]
-    Mmap {
-        timestamp: Timeval,
-        pointer: u64,
-        pointer_s: String,
-        length: u64,
-        backtrace_id: u32,
-        backtrace: Vec< Frame< 'a > >,
-        requested_address: u64,
-        requested_address_s: String,
-        is_readable: bool,
-        is_writable: bool,
-        is_executable: bool,
-        is_semaphore: bool,
-        grows_down: bool,
-        grows_up: bool,
-        is_shared: bool,
-        is_private: bool,
-        is_fixed: bool,
-        is_anonymous: bool,
-        is_uninitialized: bool,
-        offset: u64,
-        file_descriptor: i32,
-        thread: u32
-    },
-    #[serde(rename = "munmap")]
-    Munmap {
-        timestamp: Timeval,
-        pointer: u64,
-        pointer_s: String,
-        length: u64,
-        backtrace_id: u32,
-        backtrace: Vec< Frame< 'a > >,
-        thread: u32
-    }
+pub struct LiveStream {
+    pub id: String,
+    pub executable: String,
+    pub architecture: String,
+    pub pid: u32,
+    /// Whether the profiled process is still streaming its data.
+    pub is_connected: bool,
+    pub received_bytes: u64
 }
 
 #[derive(Serialize)]
-pub struct ResponseAllocations< T: Serialize > {
-    pub allocations: T,
-    pub total_count: u64
+pub struct LiveSite< 'a > {
+    pub backtrace_id: u32,
+    pub backtrace: Vec< Frame< 'a > >,
+    pub live_count: u64,
+    pub live_size: u64
 }
 
 #[derive(Serialize)]
-pub struct ResponseAllocationGroups< T: Serialize > {
-    pub allocations: T,
-    pub total_count: u64
+pub struct LiveSummary< 'a > {
+    pub timestamp: Timeval,
+    pub elapsed: Timeval,
+    pub live_count: u64,
+    pub live_size: u64,
+    pub total_allocated: u64,
+    pub total_allocated_count: u64,
+    pub total_freed: u64,
+    pub total_freed_count: u64,
+    pub top_sites: Vec< LiveSite< 'a > >,
+    pub timeline: ResponseTimeline
 }
 
 #[derive(Serialize)]
-pub struct ResponseMmaps< T: Serialize > {
-    pub operations: T
+pub struct ResponseLiveSummary< 'a > {
+    pub stream: LiveStream,
+    /// `None` until the profiled process sends its first events.
+    pub summary: Option< LiveSummary< 'a > >
 }
 
 #[derive(Serialize)]
@@ -256,6 +1039,21 @@ pub enum MmapedFilter {
     No
 }
 
+/// How the memory held by a call site behaves over time.
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Debug, Hash)]
+pub enum SiteClassification {
+    #[serde(rename = "steady_growth")]
+    SteadyGrowth,
+    #[serde(rename = "plateau")]
+    Plateau,
+    #[serde(rename = "sawtooth")]
+    Sawtooth,
+    #[serde(rename = "startup")]
+    Startup,
+    #[serde(rename = "other")]
+    Other
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
 pub enum ArenaFilter {
     #[serde(rename = "main")]
@@ -288,6 +1086,8 @@ pub enum AllocGroupsSortBy {
     LeakedCount,
     #[serde(rename = "only_matched.size")]
     Size,
+    #[serde(rename = "only_matched.byte_seconds")]
+    ByteSeconds,
 
     #[serde(rename = "all.min_timestamp")]
     GlobalMinTimestamp,
@@ -300,7 +1100,145 @@ pub enum AllocGroupsSortBy {
     #[serde(rename = "all.leaked_count")]
     GlobalLeakedCount,
     #[serde(rename = "all.size")]
-    GlobalSize
+    GlobalSize,
+    #[serde(rename = "all.byte_seconds")]
+    GlobalByteSeconds
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum ChurnSortBy {
+    #[serde(rename = "allocations_per_second")]
+    AllocationsPerSecond,
+    #[serde(rename = "deallocations_per_second")]
+    DeallocationsPerSecond,
+    #[serde(rename = "bytes_per_second")]
+    BytesPerSecond,
+    #[serde(rename = "peak_allocations_per_second")]
+    PeakAllocationsPerSecond,
+    #[serde(rename = "peak_bytes_per_second")]
+    PeakBytesPerSecond
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum OverheadSortBy {
+    #[serde(rename = "count")]
+    Count,
+    #[serde(rename = "size")]
+    Size,
+    #[serde(rename = "extra_space")]
+    ExtraSpace,
+    #[serde(rename = "overhead_ratio")]
+    OverheadRatio
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum SitePairsSortBy {
+    #[serde(rename = "count")]
+    Count,
+    #[serde(rename = "size")]
+    Size,
+    #[serde(rename = "cross_thread_count")]
+    CrossThreadCount,
+    #[serde(rename = "mean_lifetime")]
+    MeanLifetime
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum BacktraceClustersSortBy {
+    #[serde(rename = "count")]
+    Count,
+    #[serde(rename = "size")]
+    Size,
+    #[serde(rename = "leaked_size")]
+    LeakedSize,
+    #[serde(rename = "backtrace_count")]
+    BacktraceCount
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum SizeClassWasteSortBy {
+    #[serde(rename = "count")]
+    Count,
+    #[serde(rename = "size")]
+    Size,
+    #[serde(rename = "waste")]
+    Waste,
+    #[serde(rename = "waste_ratio")]
+    WasteRatio
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum RegressionSortBy {
+    #[serde(rename = "leaked_slope")]
+    LeakedSlope,
+    #[serde(rename = "peak_slope")]
+    PeakSlope
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum DiffSortBy {
+    #[serde(rename = "allocated_count")]
+    AllocatedCount,
+    #[serde(rename = "allocated_size")]
+    AllocatedSize,
+    #[serde(rename = "freed_count")]
+    FreedCount,
+    #[serde(rename = "freed_size")]
+    FreedSize,
+    #[serde(rename = "leaked_count")]
+    LeakedCount,
+    #[serde(rename = "leaked_size")]
+    LeakedSize
+}
+
+impl FromStr for DiffSortBy {
+    type Err = String;
+
+    fn from_str( string: &str ) -> Result< Self, Self::Err > {
+        match string {
+            "allocated_count" => Ok( DiffSortBy::AllocatedCount ),
+            "allocated_size" => Ok( DiffSortBy::AllocatedSize ),
+            "freed_count" => Ok( DiffSortBy::FreedCount ),
+            "freed_size" => Ok( DiffSortBy::FreedSize ),
+            "leaked_count" => Ok( DiffSortBy::LeakedCount ),
+            "leaked_size" => Ok( DiffSortBy::LeakedSize ),
+            _ => Err( format!( "invalid sort order '{}'; expected one of 'allocated_count', 'allocated_size', 'freed_count', 'freed_size', 'leaked_count' or 'leaked_size'", string ) )
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum TopSitesSortBy {
+    #[serde(rename = "allocated_count")]
+    AllocatedCount,
+    #[serde(rename = "allocated_size")]
+    AllocatedSize
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum ContainerGrowthSortBy {
+    #[serde(rename = "growth_rate")]
+    GrowthRate,
+    #[serde(rename = "live_count")]
+    LiveCount,
+    #[serde(rename = "live_size")]
+    LiveSize
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum TreeDirection {
+    #[serde(rename = "top_down")]
+    TopDown,
+    #[serde(rename = "bottom_up")]
+    BottomUp
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
+pub enum AllocatorModel {
+    #[serde(rename = "glibc")]
+    Glibc,
+    #[serde(rename = "jemalloc")]
+    Jemalloc
 }
 
 impl Default for AllocSortBy {
@@ -309,6 +1247,102 @@ impl Default for AllocSortBy {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Debug, Hash)]
+pub enum JobKind {
+    #[serde(rename = "flamegraph")]
+    Flamegraph,
+    #[serde(rename = "flamegraph_pl")]
+    FlamegraphPl,
+    #[serde(rename = "heaptrack")]
+    Heaptrack,
+    #[serde(rename = "pprof")]
+    Pprof,
+    #[serde(rename = "replay")]
+    Replay
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestJob {
+    pub kind: JobKind,
+    pub collapse_recursion: Option< bool >
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Debug)]
+pub enum JobStatus {
+    #[serde(rename = "queued")]
+    Queued,
+    #[serde(rename = "running")]
+    Running,
+    #[serde(rename = "finished")]
+    Finished,
+    #[serde(rename = "failed")]
+    Failed
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct ResponseJob {
+    pub id: String,
+    pub data_id: String,
+    pub kind: JobKind,
+    pub status: JobStatus,
+    pub error: Option< String >,
+    pub size: Option< u64 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestGrafanaSearch {
+    #[serde(default)]
+    pub target: String
+}
+
+#[derive(Serialize)]
+pub struct GrafanaSearchResult {
+    pub text: String,
+    pub value: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GrafanaRange {
+    pub from: String,
+    pub to: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GrafanaTarget {
+    pub target: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestGrafanaQuery {
+    pub range: GrafanaRange,
+    pub targets: Vec< GrafanaTarget >,
+    #[serde(rename = "maxDataPoints")]
+    pub max_data_points: Option< u64 >
+}
+
+#[derive(Serialize)]
+pub struct GrafanaColumn {
+    pub text: &'static str,
+    #[serde(rename = "type")]
+    pub kind: &'static str
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum GrafanaQueryResult {
+    TimeSeries {
+        target: String,
+        /// Pairs of a value and a timestamp in milliseconds.
+        datapoints: Vec< (f64, u64) >
+    },
+    Table {
+        #[serde(rename = "type")]
+        kind: &'static str,
+        columns: Vec< GrafanaColumn >,
+        rows: Vec< Vec< serde_json::Value > >
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
 pub enum Order {
     #[serde(rename = "asc")]
@@ -580,6 +1614,7 @@ pub struct MmapFilter {
 pub struct AllocFilter {
     pub from: Option< TimestampFilter< TimestampMin > >,
     pub to: Option< TimestampFilter< TimestampMax > >,
+    pub live_at: Option< TimestampFilter< TimestampMin > >,
     pub lifetime: Option< LifetimeFilter >,
     pub address_min: Option< u64 >,
     pub address_max: Option< u64 >,
@@ -602,7 +1637,15 @@ pub struct AllocFilter {
     pub group_leaked_allocations_min: Option< NumberOrPercentage >,
     pub group_leaked_allocations_max: Option< NumberOrPercentage >,
     pub group_allocations_min: Option< u32 >,
-    pub group_allocations_max: Option< u32 >
+    pub group_allocations_max: Option< u32 >,
+    pub group_first_seen_min: Option< TimestampFilter< TimestampMin > >,
+    pub group_first_seen_max: Option< TimestampFilter< TimestampMax > >,
+    pub group_last_seen_min: Option< TimestampFilter< TimestampMin > >,
+    pub group_last_seen_max: Option< TimestampFilter< TimestampMax > >,
+    pub group_classification: Option< SiteClassification >,
+    pub columns: Option< String >,
+    #[serde(alias = "filter")]
+    pub expression: Option< String >
 }
 
 #[derive(Clone, PartialEq, Eq, Deserialize, Debug, Hash)]
@@ -629,6 +1672,13 @@ pub struct RequestAllocations {
     pub order: Option< Order >
 }
 
+#[derive(Deserialize, Debug)]
+pub struct RequestColumnGroups {
+    pub group_by: String,
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >
+}
+
 #[derive(Deserialize, Debug)]
 pub struct RequestAllocationGroups {
     pub skip: Option< u64 >,
@@ -637,3 +1687,159 @@ pub struct RequestAllocationGroups {
     pub sort_by: Option< AllocGroupsSortBy >,
     pub order: Option< Order >
 }
+
+#[derive(Deserialize, Debug)]
+pub struct RequestChurn {
+    pub window: Option< Interval >,
+
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >,
+
+    pub sort_by: Option< ChurnSortBy >,
+    pub order: Option< Order >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestOverhead {
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >,
+
+    pub sort_by: Option< OverheadSortBy >,
+    pub order: Option< Order >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestLargestAllocations {
+    pub at: Option< TimestampFilter< TimestampMin > >,
+    pub count: Option< u32 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestThreadMemory {
+    pub at: Option< TimestampFilter< TimestampMin > >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestSitePairs {
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >,
+
+    pub sort_by: Option< SitePairsSortBy >,
+    pub order: Option< Order >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestBacktraceClusters {
+    pub max_distance: Option< u32 >,
+
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >,
+
+    pub sort_by: Option< BacktraceClustersSortBy >,
+    pub order: Option< Order >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestSizeClassWaste {
+    pub allocator: Option< AllocatorModel >,
+
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >,
+
+    pub sort_by: Option< SizeClassWasteSortBy >,
+    pub order: Option< Order >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestRegressions {
+    pub ids: Option< String >,
+
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >,
+
+    pub sort_by: Option< RegressionSortBy >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestChanges {
+    pub base: String,
+    pub target: String,
+    pub impact_min: Option< u32 >,
+    pub sites_count: Option< u32 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestDiff {
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >,
+
+    pub sort_by: Option< DiffSortBy >,
+    pub include_unchanged: Option< bool >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestTopSites {
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >,
+
+    pub sort_by: Option< TopSitesSortBy >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestContainerGrowth {
+    pub live_count_min: Option< u64 >,
+
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >,
+
+    pub sort_by: Option< ContainerGrowthSortBy >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestAddressReuse {
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestMappedRegions {
+    pub at: Option< TimestampFilter< TimestampMin > >,
+    pub size_min: Option< u64 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestLiveSummary {
+    pub count: Option< u32 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestArenaTrim {
+    pub at: Option< TimestampFilter< TimestampMin > >,
+    pub pinning_count: Option< u32 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestCoreMemory {
+    pub address: u64,
+    pub length: Option< u64 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestPeaks {
+    pub prominence_min: Option< u32 >,
+    pub sites_count: Option< u32 >,
+
+    pub skip: Option< u64 >,
+    pub count: Option< u32 >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestTree {
+    pub direction: Option< TreeDirection >,
+    pub collapse_recursion: Option< bool >
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RequestCollapseRecursion {
+    pub collapse_recursion: Option< bool >
+}
@@ -0,0 +1,123 @@
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+
+/*
+    The limits are enforced cooperatively: a query runs with a budget installed for its thread,
+    the loops which go over the allocations periodically check whether there's still time left
+    and charge the memory they use for their intermediate results, and once the budget is exhausted
+    the stack is unwound (with `resume_unwind`, so no panic message is printed) back to `run`,
+    which turns it into an error.
+
+    Outside of `run` there's no budget, so checking it does nothing.
+*/
+
+/// How many times the deadline can be checked before the clock is actually read.
+const CLOCK_CHECK_INTERVAL: u32 = 4096;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum QueryAborted {
+    TimedOut,
+    OverMemoryBudget
+}
+
+struct Budget {
+    deadline: Option< Instant >,
+    memory_left: Option< usize >,
+    checks_until_clock: u32
+}
+
+thread_local! {
+    static BUDGET: RefCell< Option< Budget > > = RefCell::new( None );
+}
+
+/// Runs the `callback` with the given budget installed for the current thread.
+pub fn run< R, F: FnOnce() -> R >( deadline: Option< Instant >, memory_budget: Option< usize >, callback: F ) -> Result< R, QueryAborted > {
+    let budget = Budget {
+        deadline,
+        memory_left: memory_budget,
+        checks_until_clock: 0
+    };
+
+    let previous = BUDGET.with( |current| current.borrow_mut().replace( budget ) );
+    let result = panic::catch_unwind( AssertUnwindSafe( callback ) );
+    BUDGET.with( |current| *current.borrow_mut() = previous );
+
+    match result {
+        Ok( value ) => Ok( value ),
+        Err( payload ) => match payload.downcast::< QueryAborted >() {
+            Ok( reason ) => Err( *reason ),
+            Err( payload ) => panic::resume_unwind( payload )
+        }
+    }
+}
+
+fn abort( reason: QueryAborted ) -> ! {
+    panic::resume_unwind( Box::new( reason ) )
+}
+
+/// Aborts the current query if it's past its deadline.
+#[inline]
+pub fn check_deadline() {
+    let is_past_deadline = BUDGET.with( |current| {
+        let mut current = current.borrow_mut();
+        let budget = match *current {
+            Some( ref mut budget ) => budget,
+            None => return false
+        };
+
+        if budget.checks_until_clock > 0 {
+            budget.checks_until_clock -= 1;
+            return false;
+        }
+
+        budget.checks_until_clock = CLOCK_CHECK_INTERVAL;
+        budget.deadline.map( |deadline| Instant::now() >= deadline ).unwrap_or( false )
+    });
+
+    if is_past_deadline {
+        abort( QueryAborted::TimedOut );
+    }
+}
+
+/// Accounts for `bytes` of memory used by the current query; aborts it if that's over its budget.
+#[inline]
+pub fn charge_memory( bytes: usize ) {
+    let is_over_budget = BUDGET.with( |current| {
+        let mut current = current.borrow_mut();
+        let memory_left = match *current {
+            Some( Budget { memory_left: Some( ref mut memory_left ), .. } ) => memory_left,
+            _ => return false
+        };
+
+        match memory_left.checked_sub( bytes ) {
+            Some( value ) => {
+                *memory_left = value;
+                false
+            },
+            None => true
+        }
+    });
+
+    if is_over_budget {
+        abort( QueryAborted::OverMemoryBudget );
+    }
+}
+
+#[test]
+fn test_query_limits() {
+    use std::time::Duration;
+
+    assert_eq!( run( None, None, || { check_deadline(); charge_memory( 1 << 40 ); 1 } ), Ok( 1 ) );
+    assert_eq!( run( Some( Instant::now() - Duration::from_secs( 1 ) ), None, || check_deadline() ), Err( QueryAborted::TimedOut ) );
+    assert_eq!( run( Some( Instant::now() + Duration::from_secs( 3600 ) ), None, || check_deadline() ), Ok(()) );
+    assert_eq!( run( None, Some( 100 ), || { charge_memory( 60 ); charge_memory( 60 ); } ), Err( QueryAborted::OverMemoryBudget ) );
+    assert_eq!( run( None, Some( 100 ), || { charge_memory( 60 ); charge_memory( 40 ); } ), Ok(()) );
+
+    // The budget is only there while the query runs.
+    charge_memory( 1 << 40 );
+    check_deadline();
+
+    // Other panics are passed through.
+    assert!( panic::catch_unwind( || run( None, None, || panic!( "failure" ) ) ).is_err() );
+}
@@ -0,0 +1,175 @@
+use std::cmp::Ordering;
+use std::hash::Hash;
+
+use ahash::AHashMap as HashMap;
+
+use cli_core::{
+    BacktraceId,
+    Data,
+    Operation,
+    SiteId
+};
+
+use crate::protocol;
+use crate::get_frame;
+
+#[derive(Default)]
+pub struct BacktraceUsage {
+    live: u64,
+    pub peak: u64,
+    pub leaked: u64
+}
+
+struct Site {
+    leaked: Vec< u64 >,
+    peak: Vec< u64 >,
+    data_index: usize,
+    backtrace_id: BacktraceId
+}
+
+pub fn get_usage_by_backtrace( data: &Data ) -> HashMap< BacktraceId, BacktraceUsage > {
+    get_usage( data, |backtrace| backtrace )
+}
+
+/// Same as `get_usage_by_backtrace`, except the backtraces are grouped by the given key.
+pub fn get_usage< K: Copy + Eq + Hash >( data: &Data, mut key: impl FnMut( BacktraceId ) -> K ) -> HashMap< K, BacktraceUsage > {
+    let mut usage_by_key: HashMap< K, BacktraceUsage > = HashMap::new();
+    for op in data.operations() {
+        crate::query_limits::check_deadline();
+        match op {
+            Operation::Allocation { allocation, .. } => {
+                let usage = usage_by_key.entry( key( allocation.backtrace ) ).or_insert_with( BacktraceUsage::default );
+                usage.live += allocation.size;
+                usage.peak = std::cmp::max( usage.peak, usage.live );
+            },
+            Operation::Deallocation { allocation, .. } => {
+                let usage = usage_by_key.entry( key( allocation.backtrace ) ).or_insert_with( BacktraceUsage::default );
+                usage.live -= allocation.size;
+            },
+            Operation::Reallocation { new_allocation, old_allocation, .. } => {
+                usage_by_key.entry( key( old_allocation.backtrace ) ).or_insert_with( BacktraceUsage::default ).live -= old_allocation.size;
+
+                let usage = usage_by_key.entry( key( new_allocation.backtrace ) ).or_insert_with( BacktraceUsage::default );
+                usage.live += new_allocation.size;
+                usage.peak = std::cmp::max( usage.peak, usage.live );
+            }
+        }
+    }
+
+    for (_, allocation) in data.allocations_with_id() {
+        if allocation.deallocation.is_none() {
+            usage_by_key.get_mut( &key( allocation.backtrace ) ).unwrap().leaked += allocation.size;
+        }
+    }
+
+    usage_by_key
+}
+
+fn slope( values: &[u64] ) -> f64 {
+    let length = values.len() as f64;
+    if values.len() < 2 {
+        return 0.0;
+    }
+
+    let mean_x = (length - 1.0) / 2.0;
+    let mean_y = values.iter().map( |&value| value as f64 ).sum::< f64 >() / length;
+    let mut numerator = 0.0;
+    let mut denominator = 0.0;
+    for (x, &y) in values.iter().enumerate() {
+        let dx = x as f64 - mean_x;
+        numerator += dx * (y as f64 - mean_y);
+        denominator += dx * dx;
+    }
+
+    numerator / denominator
+}
+
+#[test]
+fn test_slope() {
+    assert_eq!( slope( &[] ), 0.0 );
+    assert_eq!( slope( &[10] ), 0.0 );
+    assert_eq!( slope( &[10, 20, 30] ), 10.0 );
+    assert_eq!( slope( &[30, 20, 10] ), -10.0 );
+    assert_eq!( slope( &[5, 5, 5, 5] ), 0.0 );
+}
+
+pub fn get_regressions< 'a >(
+    datasets: &[&'a Data],
+    backtrace_format: &protocol::BacktraceFormat,
+    params: &protocol::RequestRegressions
+) -> protocol::ResponseRegressions< 'a > {
+    let remaining = params.count.unwrap_or( -1_i32 as _ ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+    let sort_by = params.sort_by.unwrap_or( protocol::RegressionSortBy::LeakedSlope );
+
+    let mut sites: HashMap< SiteId, Site > = HashMap::new();
+    let mut unmatched_backtrace_count = 0;
+    for (data_index, &data) in datasets.iter().enumerate() {
+        for (backtrace_id, usage) in get_usage_by_backtrace( data ) {
+            // A site whose ID changes on every run can't be matched across the datasets;
+            // it'd only show up as many separate sites with a single data point each.
+            let site_id = match data.get_stable_site_id( backtrace_id ) {
+                Some( site_id ) => site_id,
+                None => {
+                    unmatched_backtrace_count += 1;
+                    continue;
+                }
+            };
+
+            let site = sites.entry( site_id ).or_insert_with( || Site {
+                leaked: vec![ 0; datasets.len() ],
+                peak: vec![ 0; datasets.len() ],
+                data_index,
+                backtrace_id
+            });
+
+            site.leaked[ data_index ] += usage.leaked;
+            site.peak[ data_index ] += usage.peak;
+
+            // Prefer to report the backtrace from the most recent dataset.
+            site.data_index = data_index;
+            site.backtrace_id = backtrace_id;
+        }
+    }
+
+    let mut sites: Vec< _ > = sites.into_iter().map( |(_, site)| {
+        let leaked_slope = slope( &site.leaked );
+        let peak_slope = slope( &site.peak );
+        (site, leaked_slope, peak_slope)
+    }).filter( |&(_, leaked_slope, peak_slope)| {
+        match sort_by {
+            protocol::RegressionSortBy::LeakedSlope => leaked_slope > 0.0,
+            protocol::RegressionSortBy::PeakSlope => peak_slope > 0.0
+        }
+    }).collect();
+
+    sites.sort_by( |&(_, lhs_leaked_slope, lhs_peak_slope), &(_, rhs_leaked_slope, rhs_peak_slope)| {
+        let (lhs, rhs) = match sort_by {
+            protocol::RegressionSortBy::LeakedSlope => (lhs_leaked_slope, rhs_leaked_slope),
+            protocol::RegressionSortBy::PeakSlope => (lhs_peak_slope, rhs_peak_slope)
+        };
+
+        rhs.partial_cmp( &lhs ).unwrap_or( Ordering::Equal )
+    });
+
+    let total_count = sites.len() as u64;
+    let sites = sites.into_iter().skip( skip ).take( remaining ).map( |(site, leaked_slope, peak_slope)| {
+        let data = datasets[ site.data_index ];
+        let backtrace = data.get_backtrace( site.backtrace_id ).map( |(_, frame)| get_frame( data, backtrace_format, frame ) ).collect();
+        protocol::RegressionSite {
+            site_id: data.get_site_id( site.backtrace_id ).to_string(),
+            backtrace,
+            leaked: site.leaked,
+            peak: site.peak,
+            leaked_slope,
+            peak_slope
+        }
+    }).collect();
+
+    protocol::ResponseRegressions {
+        datasets: datasets.iter().map( |data| format!( "{}", data.id() ) ).collect(),
+        sites,
+        total_count,
+        unmatched_backtrace_count
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+
+use actix_web::HttpRequest;
+use actix_web::http::header;
+use bytes::Bytes;
+use lru::LruCache;
+use parking_lot::Mutex;
+
+use cli_core::DataId;
+
+/// Responses bigger than this are never cached.
+pub const MAXIMUM_CACHED_RESPONSE_SIZE: usize = 32 * 1024 * 1024;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ResponseCacheKey {
+    data_id: DataId,
+    path: String,
+    query: String,
+    pub content_type: &'static str
+}
+
+impl ResponseCacheKey {
+    pub fn new( data_id: DataId, req: &HttpRequest, content_type: &'static str ) -> Self {
+        ResponseCacheKey {
+            data_id,
+            path: req.path().to_owned(),
+            query: req.query_string().to_owned(),
+            content_type
+        }
+    }
+}
+
+pub struct ResponseCache {
+    entries: Mutex< LruCache< ResponseCacheKey, Bytes > >,
+    /// Randomly seeded every time the server starts.
+    instance: RandomState
+}
+
+impl ResponseCache {
+    pub fn new( capacity: usize ) -> Self {
+        ResponseCache {
+            entries: Mutex::new( LruCache::new( capacity ) ),
+            instance: RandomState::new()
+        }
+    }
+
+    /// The loaded data never changes while the server is running, so the same request will always produce
+    /// the same response. The responses also depend on how the server was started (e.g. on the frame rules
+    /// or on where the symbols were looked for), so the ETags are only valid within a single run.
+    pub fn etag( &self, key: &ResponseCacheKey ) -> String {
+        let mut hasher = self.instance.build_hasher();
+        env!( "CARGO_PKG_VERSION" ).hash( &mut hasher );
+        key.hash( &mut hasher );
+        format!( "\"{:016x}\"", hasher.finish() )
+    }
+
+    pub fn get( &self, key: &ResponseCacheKey ) -> Option< Bytes > {
+        self.entries.lock().get( key ).cloned()
+    }
+
+    pub fn insert( &self, key: ResponseCacheKey, body: Bytes ) {
+        self.entries.lock().put( key, body );
+    }
+}
+
+pub fn is_not_modified( req: &HttpRequest, etag: &str ) -> bool {
+    let value = match req.headers().get( header::IF_NONE_MATCH ).and_then( |value| value.to_str().ok() ) {
+        Some( value ) => value,
+        None => return false
+    };
+
+    value.split( "," ).map( |tag| tag.trim() ).any( |tag| {
+        tag == "*" || tag == etag || (tag.starts_with( "W/" ) && &tag[ 2.. ] == etag)
+    })
+}
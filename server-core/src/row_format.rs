@@ -0,0 +1,141 @@
+use std::io::{self, Write};
+
+use actix_web::HttpRequest;
+use actix_web::http::header;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::streaming_serializer::Rows;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RowFormat {
+    Json,
+    Ndjson,
+    Csv
+}
+
+impl RowFormat {
+    pub fn from_request( req: &HttpRequest ) -> Self {
+        let accept = match req.headers().get( header::ACCEPT ).and_then( |value| value.to_str().ok() ) {
+            Some( accept ) => accept,
+            None => return RowFormat::Json
+        };
+
+        for media_type in accept.split( "," ) {
+            let media_type = media_type.split( ";" ).next().unwrap().trim();
+            match media_type {
+                "application/json" => return RowFormat::Json,
+                "application/x-ndjson" | "application/ndjson" => return RowFormat::Ndjson,
+                "text/csv" => return RowFormat::Csv,
+                _ => {}
+            }
+        }
+
+        RowFormat::Json
+    }
+
+    pub fn content_type( self ) -> &'static str {
+        match self {
+            RowFormat::Json => "application/json",
+            RowFormat::Ndjson => "application/x-ndjson",
+            RowFormat::Csv => "text/csv"
+        }
+    }
+}
+
+/// Writes out the whole `response` as JSON, or only its `rows`, one per line, as NDJSON or CSV.
+pub fn write_response< W, T, R >( format: RowFormat, mut output: W, response: &T, rows: &R ) -> io::Result< () >
+    where W: Write,
+          T: Serialize,
+          R: Rows
+{
+    match format {
+        RowFormat::Json => {
+            serde_json::to_writer( output, response )?;
+        },
+        RowFormat::Ndjson => {
+            for row in rows.rows() {
+                serde_json::to_writer( &mut output, &row )?;
+                output.write_all( b"\n" )?;
+            }
+        },
+        RowFormat::Csv => {
+            let mut columns: Option< Vec< String > > = None;
+            for row in rows.rows() {
+                let row = serde_json::to_value( &row )?;
+                if columns.is_none() {
+                    let mut list = Vec::new();
+                    csv_columns( "", &row, &mut list );
+
+                    let header: Vec< _ > = list.iter().map( |column| csv_escape( &column[ 1.. ].replace( "/", "." ) ) ).collect();
+                    writeln!( output, "{}", header.join( "," ) )?;
+                    columns = Some( list );
+                }
+
+                let columns = columns.as_ref().unwrap();
+                let cells: Vec< _ > = columns.iter().map( |column| csv_cell( row.pointer( column ) ) ).collect();
+                writeln!( output, "{}", cells.join( "," ) )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Nested objects are flattened into separate columns; anything which doesn't fit
+// into that shape in a given row (e.g. arrays, or an object where the first row
+// had a `null`) is written out as JSON into a single cell.
+fn csv_columns( prefix: &str, value: &Value, output: &mut Vec< String > ) {
+    match value {
+        Value::Object( map ) => {
+            for (key, value) in map {
+                csv_columns( &format!( "{}/{}", prefix, key ), value, output );
+            }
+        },
+        _ => output.push( prefix.to_owned() )
+    }
+}
+
+fn csv_cell( value: Option< &Value > ) -> String {
+    match value {
+        None | Some( Value::Null ) => String::new(),
+        Some( Value::String( value ) ) => csv_escape( value ),
+        Some( Value::Bool( value ) ) => value.to_string(),
+        Some( Value::Number( value ) ) => value.to_string(),
+        Some( value ) => csv_escape( &value.to_string() )
+    }
+}
+
+fn csv_escape( value: &str ) -> String {
+    if value.contains( |ch: char| ch == ',' || ch == '"' || ch == '\n' || ch == '\r' ) {
+        format!( "\"{}\"", value.replace( "\"", "\"\"" ) )
+    } else {
+        value.to_owned()
+    }
+}
+
+#[test]
+fn test_csv() {
+    use crate::streaming_serializer::StreamingSerializer;
+
+    let rows = vec![
+        serde_json::json!({ "size": 10, "deallocation": null, "timestamp": { "secs": 1, "fract_nsecs": 0 }, "backtrace": [ "a" ] }),
+        serde_json::json!({ "size": 20, "deallocation": { "thread": 2 }, "timestamp": { "secs": 3, "fract_nsecs": 4 }, "backtrace": [ "b, c" ] })
+    ];
+    let rows = StreamingSerializer::new( move || rows.clone().into_iter() );
+
+    let mut output = Vec::new();
+    write_response( RowFormat::Csv, &mut output, &(), &rows ).unwrap();
+    assert_eq!(
+        String::from_utf8( output ).unwrap(),
+        concat!(
+            "backtrace,deallocation,size,timestamp.fract_nsecs,timestamp.secs\n",
+            "\"[\"\"a\"\"]\",,10,0,1\n",
+            "\"[\"\"b, c\"\"]\",\"{\"\"thread\"\":2}\",20,4,3\n"
+        )
+    );
+
+    let mut output = Vec::new();
+    write_response( RowFormat::Ndjson, &mut output, &(), &rows ).unwrap();
+    assert_eq!( String::from_utf8( output ).unwrap().lines().count(), 2 );
+}
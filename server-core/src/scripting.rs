@@ -0,0 +1,135 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, ParseError, Scope};
+
+use cli_core::{
+    BacktraceId,
+    Data,
+    DataId
+};
+
+use crate::State;
+
+const MAXIMUM_OPERATIONS: u64 = 1_000_000_000;
+
+fn data_of( state: &State, data_id: DataId ) -> &Data {
+    state.data.get( &data_id ).unwrap()
+}
+
+fn allocation_to_map( data: &Data, index: i64 ) -> Result< Dynamic, Box< EvalAltResult > > {
+    let allocation = match data.allocations().get( index as usize ) {
+        Some( allocation ) if index >= 0 => allocation,
+        _ => return Err( format!( "allocation index out of range: {}", index ).into() )
+    };
+
+    let mut map = Map::new();
+    map.insert( "timestamp".into(), Dynamic::from( (allocation.timestamp - data.initial_timestamp()).as_secs_f64() ) );
+    map.insert( "size".into(), Dynamic::from( allocation.size as i64 ) );
+    map.insert( "thread".into(), Dynamic::from( allocation.thread as i64 ) );
+    map.insert( "backtrace".into(), Dynamic::from( allocation.backtrace.raw() as i64 ) );
+    map.insert( "marker".into(), Dynamic::from( allocation.marker as i64 ) );
+    map.insert( "is_mmaped".into(), Dynamic::from( allocation.is_mmaped() ) );
+    map.insert( "leaked".into(), Dynamic::from( allocation.deallocation.is_none() ) );
+    map.insert( "deallocation_timestamp".into(), match allocation.deallocation {
+        Some( ref deallocation ) => Dynamic::from( (deallocation.timestamp - data.initial_timestamp()).as_secs_f64() ),
+        None => Dynamic::UNIT
+    });
+
+    Ok( Dynamic::from( map ) )
+}
+
+fn backtrace_to_array( data: &Data, id: i64 ) -> Result< Dynamic, Box< EvalAltResult > > {
+    if id < 0 || id as u64 >= data.unique_backtrace_count() as u64 {
+        return Err( format!( "backtrace id out of range: {}", id ).into() );
+    }
+
+    let frames: Array = data.get_backtrace( BacktraceId::new( id as u32 ) ).map( |(_, frame)| {
+        match frame.function().or_else( || frame.raw_function() ) {
+            Some( function ) => Dynamic::from( data.interner().resolve( function ).unwrap().to_owned() ),
+            None => Dynamic::from( format!( "{:016X}", frame.address().raw() ) )
+        }
+    }).collect();
+
+    Ok( Dynamic::from( frames ) )
+}
+
+/// Creates a sandboxed engine with a small read-only API over a single dataset.
+///
+/// The scripts can't touch anything outside of the dataset, and the amount of work
+/// they can do is limited, both in the number of operations and in time.
+fn create_engine( state: &Arc< State >, data_id: DataId, deadline: Option< Instant > ) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations( MAXIMUM_OPERATIONS );
+    engine.set_max_call_levels( 32 );
+    engine.set_max_expr_depths( 64, 32 );
+    engine.set_max_string_size( 1024 * 1024 );
+    engine.set_max_array_size( 1024 * 1024 );
+    engine.set_max_map_size( 1024 * 1024 );
+    engine.disable_symbol( "eval" );
+    if let Some( deadline ) = deadline {
+        engine.on_progress( move |_| {
+            if Instant::now() >= deadline {
+                Some( "the script has timed out".into() )
+            } else {
+                None
+            }
+        });
+    }
+
+    let state_clone = state.clone();
+    engine.register_fn( "allocation_count", move || {
+        data_of( &state_clone, data_id ).allocations().len() as i64
+    });
+
+    let state_clone = state.clone();
+    engine.register_result_fn( "allocation", move |index: i64| {
+        allocation_to_map( data_of( &state_clone, data_id ), index )
+    });
+
+    let state_clone = state.clone();
+    engine.register_result_fn( "backtrace", move |id: i64| {
+        backtrace_to_array( data_of( &state_clone, data_id ), id )
+    });
+
+    let state_clone = state.clone();
+    engine.register_fn( "runtime", move || {
+        let data = data_of( &state_clone, data_id );
+        (data.last_timestamp() - data.initial_timestamp()).as_secs_f64()
+    });
+
+    engine
+}
+
+pub(crate) fn check_script( state: &Arc< State >, data_id: DataId, script: &str ) -> Result< (), ParseError > {
+    create_engine( state, data_id, None ).compile( script ).map( |_| () )
+}
+
+pub(crate) fn run_script( state: &Arc< State >, data_id: DataId, deadline: Option< Instant >, script: &str ) -> Result< serde_json::Value, String > {
+    let engine = create_engine( state, data_id, deadline );
+    let mut scope = Scope::new();
+    let result: Dynamic = engine.eval_with_scope( &mut scope, script ).map_err( |error| error.to_string() )?;
+    rhai::serde::from_dynamic( &result ).map_err( |error| error.to_string() )
+}
+
+#[test]
+fn test_backtrace_out_of_range() {
+    let input = "v 10100 2\nX ./a.out\ns main\ni 1000 0 1\nt 1 0\na 10 1\n+ 0\n";
+    let mut output = Vec::new();
+    cli_core::import_heaptrack( input.as_bytes(), &mut output, cli_core::Timestamp::from_secs( 100 ) ).unwrap();
+    let data = cli_core::Loader::load_from_stream_without_debug_info( std::io::Cursor::new( output ) ).unwrap();
+    let data_id = data.id();
+    let backtrace_count = data.unique_backtrace_count();
+
+    let mut state = State::new( crate::QueryLimits::default(), None );
+    state.add_data( data );
+    let state = Arc::new( state );
+
+    let result = run_script( &state, data_id, None, "backtrace( allocation( 0 ).backtrace )" ).unwrap();
+    assert_eq!( result, serde_json::json!( [ "main" ] ) );
+
+    for id in &[ -1, backtrace_count as i64, 999999999 ] {
+        let error = run_script( &state, data_id, None, &format!( "backtrace( {} )", id ) ).unwrap_err();
+        assert!( error.contains( "backtrace id out of range" ), "unexpected error: {}", error );
+    }
+}
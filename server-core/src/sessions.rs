@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use lru::LruCache;
+use parking_lot::Mutex;
+
+use crate::protocol;
+
+/// Keeps the working sets of the server's users, so that each one of them
+/// can have their own filters, comparisons and bookmarks.
+pub struct Sessions {
+    entries: Mutex< LruCache< String, protocol::Session > >,
+    hasher: RandomState,
+    counter: AtomicU64
+}
+
+impl Sessions {
+    pub fn new( capacity: usize ) -> Self {
+        Sessions {
+            entries: Mutex::new( LruCache::new( capacity ) ),
+            hasher: RandomState::new(),
+            counter: AtomicU64::new( 0 )
+        }
+    }
+
+    fn generate_id( &self ) -> String {
+        let counter = self.counter.fetch_add( 1, Ordering::SeqCst );
+        let mut hasher = self.hasher.build_hasher();
+        counter.hash( &mut hasher );
+        SystemTime::now().hash( &mut hasher );
+        let a = hasher.finish();
+        a.hash( &mut hasher );
+        let b = hasher.finish();
+        format!( "{:016x}{:016x}", a, b )
+    }
+
+    pub fn create( &self ) -> String {
+        let id = self.generate_id();
+        self.entries.lock().put( id.clone(), protocol::Session::default() );
+        id
+    }
+
+    pub fn get( &self, id: &str ) -> Option< protocol::Session > {
+        self.entries.lock().get( &id.to_owned() ).cloned()
+    }
+
+    pub fn update( &self, id: &str, session: protocol::Session ) -> bool {
+        let mut entries = self.entries.lock();
+        match entries.get_mut( &id.to_owned() ) {
+            Some( entry ) => {
+                *entry = session;
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn remove( &self, id: &str ) -> bool {
+        self.entries.lock().pop( &id.to_owned() ).is_some()
+    }
+
+    /// Brings back a session which was saved in a workspace, under its old ID.
+    pub fn restore( &self, id: String, session: protocol::Session ) {
+        self.entries.lock().put( id, session );
+    }
+
+    pub fn snapshot( &self ) -> BTreeMap< String, protocol::Session > {
+        self.entries.lock().iter().map( |(id, session)| (id.clone(), session.clone()) ).collect()
+    }
+}
@@ -0,0 +1,509 @@
+//! Support for analyzing captures which are too big to be loaded by a single process.
+//!
+//! Every worker is a normal server which was started with `--shard <index>/<count>` and loaded
+//! only the allocations from its share of the backtraces; the local workers started with `--shards`
+//! get a capture which was split up beforehand (see `split_into_shards`), so they don't even have
+//! to read the rest of it. The coordinator doesn't load anything itself; it forwards the requests
+//! to all of the workers and merges their responses.
+//!
+//! The rows from different workers are merged by their site IDs, so one row describes everything
+//! which was allocated from the same site in any of the shards, even if the capture recorded it
+//! under multiple backtraces; the sums are exact, but the lifetime percentiles are taken from
+//! the shard with most of the site's allocations. Only the endpoints routed in `coordinator_main`
+//! are supported; everything else (the trees, the flamegraphs, the filters by backtrace, the exports,
+//! the sessions, etc.) returns a `501 Not Implemented`.
+
+use std::cmp::{min, Ordering};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use actix_web::{web, App, HttpRequest, HttpResponse, Result};
+use actix_web::error::{ErrorBadGateway, ErrorBadRequest, ErrorNotFound, ErrorNotImplemented};
+use actix_web::error::Error as ActixWebError;
+use actix_cors::Cors;
+use ahash::AHashMap as HashMap;
+use serde_json::{json, Value};
+
+use crate::ServerError;
+use crate::protocol;
+
+/// How long to wait for a worker to accept a connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs( 10 );
+
+/// How long a worker can take to answer a single request; the queries themselves have their own limits.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs( 600 );
+
+/// How many times (once a second) to check whether a worker has finished loading before giving up.
+const MAX_READY_CHECKS: u32 = 3600;
+
+struct Workers {
+    addresses: Vec< String >
+}
+
+enum FetchError {
+    Status( u16, String ),
+    Other( String )
+}
+
+impl From< io::Error > for FetchError {
+    fn from( error: io::Error ) -> Self {
+        FetchError::Other( error.to_string() )
+    }
+}
+
+impl From< FetchError > for ActixWebError {
+    fn from( error: FetchError ) -> Self {
+        match error {
+            FetchError::Status( 400, message ) => ErrorBadRequest( message ),
+            FetchError::Status( 404, message ) => ErrorNotFound( message ),
+            FetchError::Status( status, message ) => ErrorBadGateway( format!( "a worker responded with {}: {}", status, message ) ),
+            FetchError::Other( message ) => ErrorBadGateway( format!( "failed to query a worker: {}", message ) )
+        }
+    }
+}
+
+fn decode_chunked( mut body: &[u8] ) -> Result< Vec< u8 >, FetchError > {
+    let invalid = || FetchError::Other( "invalid chunked encoding".to_owned() );
+    let mut output = Vec::new();
+    loop {
+        let line_end = body.windows( 2 ).position( |window| window == b"\r\n" ).ok_or_else( invalid )?;
+        let length = std::str::from_utf8( &body[ ..line_end ] ).ok()
+            .and_then( |line| usize::from_str_radix( line.split( ';' ).next().unwrap().trim(), 16 ).ok() )
+            .ok_or_else( invalid )?;
+
+        body = &body[ line_end + 2.. ];
+        if length == 0 {
+            return Ok( output );
+        }
+
+        if body.len() < length + 2 {
+            return Err( invalid() );
+        }
+
+        output.extend_from_slice( &body[ ..length ] );
+        body = &body[ length + 2.. ];
+    }
+}
+
+#[test]
+fn test_decode_chunked() {
+    assert_eq!( decode_chunked( b"3\r\nabc\r\na\r\n0123456789\r\n0\r\n\r\n" ).ok(), Some( b"abc0123456789".to_vec() ) );
+    assert!( decode_chunked( b"5\r\nabc\r\n" ).is_err() );
+}
+
+/// Does a plain HTTP GET request; the responses can be quite big, but there are only
+/// a handful of them per query, so there's no need for anything more sophisticated.
+fn http_get( address: &str, path: &str ) -> Result< Vec< u8 >, FetchError > {
+    let host = address.trim_start_matches( "http://" ).trim_end_matches( '/' );
+    let socket_address = host.to_socket_addrs()?.next()
+        .ok_or_else( || FetchError::Other( format!( "failed to resolve '{}'", host ) ) )?;
+    let mut stream = TcpStream::connect_timeout( &socket_address, CONNECT_TIMEOUT )?;
+    stream.set_read_timeout( Some( REQUEST_TIMEOUT ) )?;
+    stream.set_write_timeout( Some( REQUEST_TIMEOUT ) )?;
+    write!( stream, "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\nConnection: close\r\n\r\n", path, host )?;
+
+    let mut response = Vec::new();
+    stream.read_to_end( &mut response )?;
+
+    let header_end = response.windows( 4 ).position( |window| window == b"\r\n\r\n" )
+        .ok_or_else( || FetchError::Other( "malformed response".to_owned() ) )?;
+    let header = String::from_utf8_lossy( &response[ ..header_end ] ).to_lowercase();
+    let body = &response[ header_end + 4.. ];
+
+    let status: u16 = header.split_whitespace().nth( 1 ).and_then( |status| status.parse().ok() )
+        .ok_or_else( || FetchError::Other( "malformed status line".to_owned() ) )?;
+    let body = if header.lines().any( |line| line.starts_with( "transfer-encoding:" ) && line.contains( "chunked" ) ) {
+        decode_chunked( body )?
+    } else {
+        body.to_vec()
+    };
+
+    if status != 200 {
+        return Err( FetchError::Status( status, String::from_utf8_lossy( &body ).into_owned() ) );
+    }
+
+    Ok( body )
+}
+
+fn fetch_json( address: &str, path: &str ) -> Result< Value, FetchError > {
+    let body = http_get( address, path )?;
+    serde_json::from_slice( &body ).map_err( |error| FetchError::Other( format!( "invalid JSON from {}: {}", address, error ) ) )
+}
+
+/// Sends the same request to every worker in parallel.
+fn fetch_from_all( workers: &Workers, path: &str ) -> Result< Vec< Value >, FetchError > {
+    let handles: Vec< _ > = workers.addresses.iter().map( |address| {
+        let address = address.clone();
+        let path = path.to_owned();
+        thread::spawn( move || fetch_json( &address, &path ) )
+    }).collect();
+
+    handles.into_iter().map( |handle| handle.join().unwrap() ).collect()
+}
+
+/// Returns the request's path with its query string, with the given parameters replaced.
+fn worker_path( req: &HttpRequest, overrides: &[(&str, Option< u64 >)] ) -> Result< String > {
+    let mut pairs: Vec< (String, String) > = serde_urlencoded::from_str( req.query_string() ).map_err( |error| ErrorBadRequest( error.to_string() ) )?;
+    pairs.retain( |(key, _)| !overrides.iter().any( |(name, _)| name == key ) );
+    for &(name, value) in overrides {
+        if let Some( value ) = value {
+            pairs.push( (name.to_owned(), value.to_string()) );
+        }
+    }
+
+    let query = serde_urlencoded::to_string( &pairs ).unwrap();
+    Ok( format!( "{}?{}", req.path(), query ) )
+}
+
+fn query_value( req: &HttpRequest, name: &str ) -> Result< Option< String > > {
+    let pairs: Vec< (String, String) > = serde_urlencoded::from_str( req.query_string() ).map_err( |error| ErrorBadRequest( error.to_string() ) )?;
+    Ok( pairs.into_iter().find( |(key, _)| key == name ).map( |(_, value)| value ) )
+}
+
+fn timeval_to_usecs( value: &Value ) -> i64 {
+    value[ "secs" ].as_i64().unwrap_or( 0 ) * 1_000_000 + value[ "fract_nsecs" ].as_i64().unwrap_or( 0 ) / 1000
+}
+
+fn usecs_to_timeval( usecs: i64 ) -> Value {
+    json!({ "secs": usecs / 1_000_000, "fract_nsecs": (usecs % 1_000_000) * 1000 })
+}
+
+/// Compares two values of the same field; the timestamps are objects with `secs` and `fract_nsecs`.
+fn compare_values( lhs: &Value, rhs: &Value ) -> Ordering {
+    match (lhs, rhs) {
+        (&Value::Number( ref lhs ), &Value::Number( ref rhs )) => lhs.as_f64().partial_cmp( &rhs.as_f64() ).unwrap_or( Ordering::Equal ),
+        (&Value::Object( _ ), &Value::Object( _ )) => timeval_to_usecs( lhs ).cmp( &timeval_to_usecs( rhs ) ),
+        (&Value::String( ref lhs ), &Value::String( ref rhs )) => lhs.cmp( rhs ),
+        (&Value::Null, &Value::Null) => Ordering::Equal,
+        (&Value::Null, _) => Ordering::Less,
+        (_, &Value::Null) => Ordering::Greater,
+        _ => Ordering::Equal
+    }
+}
+
+fn sort_rows( rows: &mut [Value], pointer: &str, order: &str ) {
+    rows.sort_by( |lhs, rhs| {
+        let ordering = compare_values( lhs.pointer( pointer ).unwrap_or( &Value::Null ), rhs.pointer( pointer ).unwrap_or( &Value::Null ) );
+        if order == "dsc" { ordering.reverse() } else { ordering }
+    });
+}
+
+/// Merges the `AllocationGroupData` of the same group from two different shards.
+fn merge_group_data( target: &mut Value, other: &Value ) {
+    if other[ "allocated_count" ].as_u64() > target[ "allocated_count" ].as_u64() {
+        for key in &[ "lifetime_p50", "lifetime_p90", "lifetime_p99" ] {
+            target[ *key ] = other[ *key ].clone();
+        }
+    }
+
+    for key in &[ "size", "leaked_count", "allocated_count", "byte_seconds" ] {
+        target[ *key ] = json!( target[ *key ].as_u64().unwrap_or( 0 ) + other[ *key ].as_u64().unwrap_or( 0 ) );
+    }
+
+    for key in &[ "min_size", "min_timestamp", "min_timestamp_relative", "min_timestamp_relative_p" ] {
+        if compare_values( &other[ *key ], &target[ *key ] ) == Ordering::Less {
+            target[ *key ] = other[ *key ].clone();
+        }
+    }
+
+    for key in &[ "max_size", "max_timestamp", "max_timestamp_relative", "max_timestamp_relative_p" ] {
+        if compare_values( &other[ *key ], &target[ *key ] ) == Ordering::Greater {
+            target[ *key ] = other[ *key ].clone();
+        }
+    }
+
+    target[ "interval" ] = usecs_to_timeval( timeval_to_usecs( &target[ "max_timestamp" ] ) - timeval_to_usecs( &target[ "min_timestamp" ] ) );
+}
+
+#[test]
+fn test_merge_group_data() {
+    let mut target = json!({
+        "size": 10, "leaked_count": 1, "allocated_count": 2, "byte_seconds": 5, "min_size": 4, "max_size": 6,
+        "min_timestamp": { "secs": 10, "fract_nsecs": 0 }, "max_timestamp": { "secs": 12, "fract_nsecs": 0 },
+        "lifetime_p50": null
+    });
+
+    let other = json!({
+        "size": 20, "leaked_count": 0, "allocated_count": 3, "byte_seconds": 1, "min_size": 5, "max_size": 10,
+        "min_timestamp": { "secs": 11, "fract_nsecs": 0 }, "max_timestamp": { "secs": 15, "fract_nsecs": 500000000 },
+        "lifetime_p50": { "secs": 1, "fract_nsecs": 0 }
+    });
+
+    merge_group_data( &mut target, &other );
+    assert_eq!( target[ "size" ], json!( 30 ) );
+    assert_eq!( target[ "allocated_count" ], json!( 5 ) );
+    assert_eq!( target[ "min_size" ], json!( 4 ) );
+    assert_eq!( target[ "max_size" ], json!( 10 ) );
+    assert_eq!( target[ "interval" ], json!({ "secs": 5, "fract_nsecs": 500000000 }) );
+    assert_eq!( target[ "lifetime_p50" ], json!({ "secs": 1, "fract_nsecs": 0 }) );
+}
+
+/// Merges rows from different shards which describe the same thing, keyed by `key`.
+fn merge_rows( responses: Vec< Value >, rows_key: &str, key: &str, merge: impl Fn( &mut Value, &Value ) ) -> Vec< Value > {
+    let mut index_by_key: HashMap< String, usize > = HashMap::new();
+    let mut output: Vec< Value > = Vec::new();
+    for mut response in responses {
+        let rows = match response[ rows_key ].take() {
+            Value::Array( rows ) => rows,
+            _ => continue
+        };
+
+        for row in rows {
+            let row_key = row[ key ].to_string();
+            match index_by_key.get( &row_key ) {
+                Some( &index ) => merge( &mut output[ index ], &row ),
+                None => {
+                    index_by_key.insert( row_key, output.len() );
+                    output.push( row );
+                }
+            }
+        }
+    }
+
+    output
+}
+
+/// Merges the timelines of all of the shards; the live memory carries over
+/// from the last known point while the per-second counts don't.
+fn merge_timelines( timelines: &[Value] ) -> Value {
+    const LEVELS: &[&str] = &[ "allocated_size", "allocated_count", "leaked_size", "leaked_count" ];
+    const DELTAS: &[&str] = &[ "size_delta", "count_delta", "allocations", "deallocations" ];
+
+    let xs_of = |timeline: &Value| -> Vec< u64 > {
+        timeline[ "xs" ].as_array().map( |xs| xs.iter().filter_map( |x| x.as_u64() ).collect() ).unwrap_or_default()
+    };
+
+    let mut xs: Vec< u64 > = timelines.iter().flat_map( |timeline| xs_of( timeline ) ).collect();
+    xs.sort();
+    xs.dedup();
+
+    let mut output = json!({ "xs": xs.clone() });
+    for &key in LEVELS.iter().chain( DELTAS.iter() ) {
+        let is_level = LEVELS.contains( &key );
+        let mut merged = vec![ 0_i64; xs.len() ];
+        for timeline in timelines {
+            let timeline_xs = xs_of( timeline );
+            let values: Vec< i64 > = timeline[ key ].as_array().map( |values| values.iter().map( |value| value.as_i64().unwrap_or( 0 ) ).collect() ).unwrap_or_default();
+            let mut position = 0;
+            let mut last = 0;
+            for (index, &x) in xs.iter().enumerate() {
+                if position < timeline_xs.len() && timeline_xs[ position ] == x {
+                    last = values.get( position ).cloned().unwrap_or( 0 );
+                    merged[ index ] += last;
+                    position += 1;
+                } else if is_level {
+                    merged[ index ] += last;
+                }
+            }
+        }
+
+        output[ key ] = json!( merged );
+    }
+
+    output
+}
+
+#[test]
+fn test_merge_timelines() {
+    let timelines = [
+        json!({ "xs": [ 1, 2, 3 ], "allocated_size": [ 10, 20, 5 ], "allocations": [ 1, 1, 0 ] }),
+        json!({ "xs": [ 2, 4 ], "allocated_size": [ 100, 50 ], "allocations": [ 2, 3 ] })
+    ];
+
+    let merged = merge_timelines( &timelines );
+    assert_eq!( merged[ "xs" ], json!([ 1, 2, 3, 4 ]) );
+    assert_eq!( merged[ "allocated_size" ], json!([ 10, 120, 105, 55 ]) );
+    assert_eq!( merged[ "allocations" ], json!([ 1, 3, 0, 3 ]) );
+}
+
+fn workers( req: &HttpRequest ) -> &Workers {
+    req.app_data::< Arc< Workers > >().unwrap()
+}
+
+fn handler_list( req: HttpRequest ) -> Result< HttpResponse > {
+    let workers = workers( &req );
+    let lists = fetch_from_all( workers, "/list" )?;
+    let mut merged = merge_rows( lists.into_iter().map( |list| json!({ "list": list }) ).collect(), "list", "id", |target, other| {
+        for key in &[ "final_allocated", "final_allocated_count", "total_allocated", "total_allocated_count", "total_freed", "total_freed_count", "unknown_deallocation_count", "duplicate_allocation_count" ] {
+            target[ *key ] = json!( target[ *key ].as_u64().unwrap_or( 0 ) + other[ *key ].as_u64().unwrap_or( 0 ) );
+        }
+    });
+
+    for metadata in &mut merged {
+        // The peak of the whole capture is only known with the resolution of the timeline.
+        let timeline = merge_timelines( &fetch_from_all( workers, &format!( "/data/{}/timeline", metadata[ "id" ].as_str().unwrap_or( "" ) ) )? );
+        let peak = timeline[ "allocated_size" ].as_array().unwrap().iter().zip( timeline[ "xs" ].as_array().unwrap() )
+            .max_by_key( |(size, _)| size.as_i64().unwrap_or( 0 ) )
+            .map( |(size, x)| (size.clone(), usecs_to_timeval( x.as_i64().unwrap_or( 0 ) * 1_000_000 )) );
+        if let Some( (size, timestamp) ) = peak {
+            metadata[ "peak_allocated" ] = size;
+            metadata[ "peak_allocated_timestamp" ] = timestamp;
+        }
+
+        // The workers only know how much of the heap they've tracked themselves, so that part of the data quality is dropped.
+        let unknown_deallocation_count = metadata[ "unknown_deallocation_count" ].as_u64().unwrap_or( 0 );
+        let deallocation_count = metadata[ "total_freed_count" ].as_u64().unwrap_or( 0 ) + unknown_deallocation_count;
+        let fraction = if deallocation_count == 0 { 0.0 } else { unknown_deallocation_count as f64 / deallocation_count as f64 };
+        let lost_chunk_count = metadata[ "lost_chunk_count" ].as_u64().unwrap_or( 0 );
+        let duplicate_allocation_count = metadata[ "duplicate_allocation_count" ].clone();
+        let quality = &mut metadata[ "data_quality" ];
+        quality[ "rating" ] = serde_json::to_value( crate::data_quality::rate( fraction, None, lost_chunk_count ) ).unwrap();
+        quality[ "unknown_deallocation_count" ] = json!( unknown_deallocation_count );
+        quality[ "unknown_deallocation_fraction" ] = json!( fraction as f32 );
+        quality[ "duplicate_allocation_count" ] = duplicate_allocation_count;
+        quality[ "untracked_heap_size" ] = Value::Null;
+        quality[ "untracked_heap_fraction" ] = Value::Null;
+        metadata[ "shard" ] = Value::Null;
+    }
+
+    Ok( HttpResponse::Ok().json( merged ) )
+}
+
+fn handler_timeline( req: HttpRequest ) -> Result< HttpResponse > {
+    let timelines = fetch_from_all( workers( &req ), &worker_path( &req, &[] )? )?;
+    Ok( HttpResponse::Ok().json( merge_timelines( &timelines ) ) )
+}
+
+fn handler_allocations( req: HttpRequest ) -> Result< HttpResponse > {
+    let params: protocol::RequestAllocations = serde_urlencoded::from_str( req.query_string() ).map_err( |error| ErrorBadRequest( error.to_string() ) )?;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+    let count = params.count.map( |count| count as usize ).unwrap_or( std::usize::MAX );
+
+    // Every shard has to return everything up to the last requested row since we don't know which shard it'll come from.
+    let path = worker_path( &req, &[("skip", None), ("count", params.count.map( |count| min( skip as u64 + count as u64, std::u32::MAX as u64 ) ))] )?;
+    let responses = fetch_from_all( workers( &req ), &path )?;
+    let total_count: u64 = responses.iter().map( |response| response[ "total_count" ].as_u64().unwrap_or( 0 ) ).sum();
+    let mut rows: Vec< Value > = responses.into_iter().flat_map( |mut response| match response[ "allocations" ].take() {
+        Value::Array( rows ) => rows,
+        _ => Vec::new()
+    }).collect();
+
+    let sort_by = query_value( &req, "sort_by" )?.unwrap_or_else( || "timestamp".to_owned() );
+    let order = query_value( &req, "order" )?.unwrap_or_else( || "asc".to_owned() );
+    sort_rows( &mut rows, &format!( "/{}", sort_by ), &order );
+
+    let rows: Vec< _ > = rows.into_iter().skip( skip ).take( count ).collect();
+    Ok( HttpResponse::Ok().json( json!({ "allocations": rows, "total_count": total_count }) ) )
+}
+
+fn handler_allocation_groups( req: HttpRequest ) -> Result< HttpResponse > {
+    let params: protocol::RequestAllocationGroups = serde_urlencoded::from_str( req.query_string() ).map_err( |error| ErrorBadRequest( error.to_string() ) )?;
+    let path = worker_path( &req, &[("skip", None), ("count", None)] )?;
+    let responses = fetch_from_all( workers( &req ), &path )?;
+    let mut groups = merge_rows( responses, "allocations", "site_id", |target, other| {
+        merge_group_data( &mut target[ "all" ], &other[ "all" ] );
+        merge_group_data( &mut target[ "only_matched" ], &other[ "only_matched" ] );
+    });
+
+    let sort_by = query_value( &req, "sort_by" )?.unwrap_or_else( || "only_matched.min_timestamp".to_owned() );
+    let order = query_value( &req, "order" )?.unwrap_or_else( || "asc".to_owned() );
+    sort_rows( &mut groups, &format!( "/{}", sort_by.replace( '.', "/" ) ), &order );
+
+    let total_count = groups.len();
+    let groups: Vec< _ > = groups.into_iter()
+        .skip( params.skip.unwrap_or( 0 ) as usize )
+        .take( params.count.map( |count| count as usize ).unwrap_or( std::usize::MAX ) )
+        .collect();
+
+    Ok( HttpResponse::Ok().json( json!({ "allocations": groups, "total_count": total_count }) ) )
+}
+
+fn handler_column_groups( req: HttpRequest ) -> Result< HttpResponse > {
+    let params: protocol::RequestColumnGroups = serde_urlencoded::from_str( req.query_string() ).map_err( |error| ErrorBadRequest( error.to_string() ) )?;
+    let path = worker_path( &req, &[("skip", None), ("count", Some( std::u32::MAX as u64 ))] )?;
+    let responses = fetch_from_all( workers( &req ), &path )?;
+    let mut groups = merge_rows( responses, "groups", "value", |target, other| {
+        merge_group_data( &mut target[ "data" ], &other[ "data" ] );
+    });
+
+    sort_rows( &mut groups, "/data/size", "dsc" );
+    let total_count = groups.len();
+    let groups: Vec< _ > = groups.into_iter()
+        .skip( params.skip.unwrap_or( 0 ) as usize )
+        .take( params.count.unwrap_or( 10 ) as usize )
+        .collect();
+
+    Ok( HttpResponse::Ok().json( json!({ "groups": groups, "total_count": total_count }) ) )
+}
+
+fn handler_unsupported( req: HttpRequest ) -> HttpResponse {
+    HttpResponse::from_error( ErrorNotImplemented( format!( "'{}' isn't supported when the analysis is sharded", req.path() ) ) )
+}
+
+/// Waits until every worker has finished loading its shard and checks that together they cover the whole capture.
+fn wait_for_workers( workers: &Workers ) -> Result< (), ServerError > {
+    let mut shards = Vec::new();
+    for address in &workers.addresses {
+        let mut checks = 0;
+        let list = loop {
+            match fetch_json( address, "/list" ) {
+                Ok( list ) => break list,
+                Err( error ) => {
+                    checks += 1;
+                    if checks >= MAX_READY_CHECKS {
+                        let reason = match error {
+                            FetchError::Status( status, message ) => format!( "{}: {}", status, message ),
+                            FetchError::Other( message ) => message
+                        };
+
+                        let message = format!( "worker {} isn't ready after {} attempts: {}", address, checks, reason );
+                        return Err( ServerError::Other( io::Error::new( io::ErrorKind::Other, message ) ) );
+                    }
+
+                    debug!( "Worker {} isn't ready yet", address );
+                    thread::sleep( Duration::from_secs( 1 ) );
+                }
+            }
+        };
+
+        info!( "Worker {} is ready", address );
+        for metadata in list.as_array().cloned().unwrap_or_default() {
+            match metadata[ "shard" ].as_str() {
+                Some( shard ) => shards.push( shard.to_owned() ),
+                None => {
+                    let message = format!( "worker {} has loaded its data without the `--shard` option", address );
+                    return Err( ServerError::Other( io::Error::new( io::ErrorKind::Other, message ) ) );
+                }
+            }
+        }
+    }
+
+    shards.sort();
+    shards.dedup();
+    let count = workers.addresses.len();
+    let expected: Vec< _ > = (0..count).map( |index| format!( "{}/{}", index, count ) ).collect();
+    let mut expected_sorted = expected.clone();
+    expected_sorted.sort();
+    if shards != expected_sorted {
+        let message = format!( "the workers have loaded the shards {:?} while {:?} were expected", shards, expected );
+        return Err( ServerError::Other( io::Error::new( io::ErrorKind::Other, message ) ) );
+    }
+
+    Ok(())
+}
+
+/// Starts a server which merges the results from the given workers instead of loading the data itself.
+pub fn coordinator_main( workers: Vec< String >, interface: &str, port: u16 ) -> Result< (), ServerError > {
+    let workers = Arc::new( Workers { addresses: workers } );
+    wait_for_workers( &workers )?;
+
+    let sys = actix::System::new( "server" );
+    actix_web::HttpServer::new( move || {
+        App::new().data( workers.clone() )
+            .wrap( Cors::new() )
+            .service( web::resource( "/list" ).route( web::get().to( handler_list ) ) )
+            .service( web::resource( "/data/{id}/timeline" ).route( web::get().to( handler_timeline ) ) )
+            .service( web::resource( "/data/{id}/allocations" ).route( web::get().to( handler_allocations ) ) )
+            .service( web::resource( "/data/{id}/allocation_groups" ).route( web::get().to( handler_allocation_groups ) ) )
+            .service( web::resource( "/data/{id}/column_groups" ).route( web::get().to( handler_column_groups ) ) )
+            .default_service( web::route().to( handler_unsupported ) )
+    }).bind( &format!( "{}:{}", interface, port ) ).map_err( |err| ServerError::BindFailed( err ) )?
+        .shutdown_timeout( 1 )
+        .start();
+
+    let _ = sys.run();
+    Ok(())
+}
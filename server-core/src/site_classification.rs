@@ -0,0 +1,136 @@
+use cli_core::{
+    Allocation,
+    BacktraceId,
+    Data,
+    Timestamp
+};
+
+use crate::protocol::SiteClassification;
+
+pub const SAMPLE_COUNT: usize = 64;
+
+/// Samples the sum of `weight` of the live allocations from a given call site
+/// at evenly spaced points over the whole runtime.
+pub fn live_curve< F >( data: &Data, backtrace_id: BacktraceId, weight: F ) -> Vec< u64 > where F: Fn( &Allocation ) -> u64 {
+    let mut changes: Vec< (Timestamp, i64) > = Vec::new();
+    for &allocation_id in data.get_allocation_ids_by_backtrace( backtrace_id ) {
+        let allocation = data.get_allocation( allocation_id );
+        let weight = weight( allocation ) as i64;
+        changes.push( (allocation.timestamp, weight) );
+        if let Some( ref deallocation ) = allocation.deallocation {
+            changes.push( (deallocation.timestamp, -weight) );
+        }
+    }
+
+    changes.sort_by_key( |&(timestamp, _)| timestamp );
+
+    let start = data.initial_timestamp().as_usecs();
+    let duration = std::cmp::max( data.last_timestamp().as_usecs().saturating_sub( start ), 1 );
+    let mut samples = Vec::with_capacity( SAMPLE_COUNT );
+    let mut changes = changes.into_iter().peekable();
+    let mut live: i64 = 0;
+    for index in 0..SAMPLE_COUNT {
+        let sample_at = start + duration * (index as u64 + 1) / SAMPLE_COUNT as u64;
+        while let Some( &(timestamp, change) ) = changes.peek() {
+            if timestamp.as_usecs() > sample_at {
+                break;
+            }
+
+            live += change;
+            changes.next();
+        }
+
+        samples.push( std::cmp::max( live, 0 ) as u64 );
+    }
+
+    samples
+}
+
+/// Classifies the shape of the live bytes curve, sampled at evenly spaced points over the whole runtime.
+///
+/// `last_allocation_at` is the index of the sample during which the last allocation was made.
+pub fn classify_curve( samples: &[u64], last_allocation_at: usize ) -> SiteClassification {
+    let peak = samples.iter().copied().max().unwrap_or( 0 );
+    if peak == 0 {
+        return SiteClassification::Other;
+    }
+
+    let last = *samples.last().unwrap();
+    if last_allocation_at < samples.len() / 10 && last >= peak - peak / 10 {
+        return SiteClassification::Startup;
+    }
+
+    let first_nonzero = samples.iter().position( |&value| value != 0 ).unwrap();
+    let steps = samples.len() - 1 - first_nonzero;
+    let mut increases = 0;
+    let mut small_decreases = 0;
+    let mut big_decreases = 0;
+    for window in samples[ first_nonzero.. ].windows( 2 ) {
+        if window[1] > window[0] {
+            increases += 1;
+        } else if window[0] - window[1] > peak / 4 {
+            big_decreases += 1;
+        } else if window[0] - window[1] > peak / 20 {
+            small_decreases += 1;
+        }
+    }
+
+    let middle = samples[ samples.len() / 2 ];
+    let is_growing =
+        last >= peak - peak / 10 &&
+        middle < last - last / 4 &&
+        steps >= samples.len() / 4 &&
+        increases * 2 >= steps &&
+        big_decreases == 0 &&
+        small_decreases <= steps / 10;
+
+    if is_growing {
+        return SiteClassification::SteadyGrowth;
+    }
+
+    if big_decreases >= 2 {
+        return SiteClassification::Sawtooth;
+    }
+
+    let second_half = &samples[ samples.len() / 2.. ];
+    let min = second_half.iter().copied().min().unwrap();
+    let max = second_half.iter().copied().max().unwrap();
+    if min >= peak / 2 && max - min <= peak / 10 {
+        return SiteClassification::Plateau;
+    }
+
+    SiteClassification::Other
+}
+
+/// Classifies how the memory held by a given call site behaves over time.
+pub fn classify_site( data: &Data, backtrace_id: BacktraceId ) -> SiteClassification {
+    let allocation_ids = data.get_allocation_ids_by_backtrace( backtrace_id );
+    let last_allocation = match allocation_ids.last() {
+        Some( &allocation_id ) => data.get_allocation( allocation_id ).timestamp,
+        None => return SiteClassification::Other
+    };
+
+    let start = data.initial_timestamp().as_usecs();
+    let duration = std::cmp::max( data.last_timestamp().as_usecs().saturating_sub( start ), 1 );
+    let last_allocation_at = (last_allocation.as_usecs().saturating_sub( start ) * SAMPLE_COUNT as u64 / duration) as usize;
+
+    classify_curve( &live_curve( data, backtrace_id, |allocation| allocation.size ), last_allocation_at )
+}
+
+#[test]
+fn test_classify_curve() {
+    let growth: Vec< u64 > = (0..64).map( |index| index * 100 ).collect();
+    assert_eq!( classify_curve( &growth, 63 ), SiteClassification::SteadyGrowth );
+
+    let mut startup = vec![ 1000; 64 ];
+    startup[ 0 ] = 500;
+    assert_eq!( classify_curve( &startup, 1 ), SiteClassification::Startup );
+
+    let plateau: Vec< u64 > = (0..64).map( |index| std::cmp::min( index * 100, 1000 ) + index % 2 * 10 ).collect();
+    assert_eq!( classify_curve( &plateau, 63 ), SiteClassification::Plateau );
+
+    let sawtooth: Vec< u64 > = (0..64).map( |index| (index % 8) * 100 ).collect();
+    assert_eq!( classify_curve( &sawtooth, 63 ), SiteClassification::Sawtooth );
+
+    assert_eq!( classify_curve( &[0; 64], 63 ), SiteClassification::Other );
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use ahash::AHashMap as HashMap;
+use ahash::AHashSet as HashSet;
+use serde::Serialize;
+
+use cli_core::{
+    AllocationColumns,
+    BacktraceId,
+    Data,
+    Timestamp
+};
+
+use crate::protocol;
+use crate::filter::{AllocationMatcher, Filter};
+use crate::streaming_serializer::{StreamingSerializer, Rows};
+use crate::get_frame;
+
+#[derive(Default)]
+struct SitePairEntry {
+    count: u64,
+    size: u64,
+    cross_thread_count: u64,
+    lifetime: u64,
+    thread_pairs: HashSet< (u32, u32) >
+}
+
+impl SitePairEntry {
+    fn mean_lifetime( &self ) -> Timestamp {
+        Timestamp::from_usecs( self.lifetime / std::cmp::max( self.count, 1 ) )
+    }
+}
+
+/// Groups the matched deallocated allocations by their allocation and deallocation backtraces,
+/// optionally only taking into account those which were freed by a different thread.
+pub fn get_site_pairs< 'a >(
+    data: &'a Data,
+    columns: Option< &AllocationColumns >,
+    backtrace_format: protocol::BacktraceFormat,
+    params: protocol::RequestSitePairs,
+    filter: Filter,
+    only_cross_thread: bool
+) -> protocol::ResponseSitePairs< impl Serialize + Rows + 'a > {
+    let remaining = params.count.unwrap_or( -1_i32 as _ ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+    let sort_by = params.sort_by.unwrap_or( protocol::SitePairsSortBy::Count );
+    let order = params.order.unwrap_or( protocol::Order::Dsc );
+
+    let mut total = SitePairEntry::default();
+    let mut entry_by_sites: HashMap< (BacktraceId, Option< BacktraceId >), SitePairEntry > = HashMap::new();
+    let matcher = AllocationMatcher::new( data, columns, &filter );
+    let iter = data.alloc_sorted_by_timestamp( filter.timestamp_start_opt(), filter.timestamp_end_opt() );
+    for (id, allocation) in iter {
+        let deallocation = match allocation.deallocation {
+            Some( ref deallocation ) if !only_cross_thread || deallocation.thread != allocation.thread => deallocation,
+            _ => continue
+        };
+
+        if !matcher.matches( id, allocation ) {
+            continue;
+        }
+
+        // The deallocation backtraces are only available when `MEMORY_PROFILER_GRAB_BACKTRACES_ON_FREE` was set.
+        let is_cross_thread = deallocation.thread != allocation.thread;
+        let lifetime = (deallocation.timestamp - allocation.timestamp).as_usecs();
+        let entry = entry_by_sites.entry( (allocation.backtrace, deallocation.backtrace) ).or_insert_with( SitePairEntry::default );
+        entry.count += 1;
+        entry.size += allocation.size;
+        entry.cross_thread_count += is_cross_thread as u64;
+        entry.lifetime += lifetime;
+        entry.thread_pairs.insert( (allocation.thread, deallocation.thread) );
+
+        total.count += 1;
+        total.size += allocation.size;
+        total.cross_thread_count += is_cross_thread as u64;
+        total.lifetime += lifetime;
+    }
+
+    let mut entries: Vec< _ > = entry_by_sites.into_iter().collect();
+    entries.sort_by( |(lhs_key, lhs), (rhs_key, rhs)| {
+        let ordering = match sort_by {
+            protocol::SitePairsSortBy::Count => lhs.count.cmp( &rhs.count ),
+            protocol::SitePairsSortBy::Size => lhs.size.cmp( &rhs.size ),
+            protocol::SitePairsSortBy::CrossThreadCount => lhs.cross_thread_count.cmp( &rhs.cross_thread_count ),
+            protocol::SitePairsSortBy::MeanLifetime => lhs.mean_lifetime().cmp( &rhs.mean_lifetime() )
+        };
+
+        ordering.then_with( || lhs_key.cmp( rhs_key ) )
+    });
+
+    if order == protocol::Order::Dsc {
+        entries.reverse();
+    }
+
+    let total_count = entries.len() as u64;
+    let entries = Arc::new( entries );
+    let groups = move || {
+        let backtrace_format = backtrace_format.clone();
+        let entries = entries.clone();
+        (0..entries.len())
+            .skip( skip )
+            .take( remaining )
+            .map( move |index| {
+                let ((allocation_backtrace_id, deallocation_backtrace_id), ref entry) = entries[ index ];
+                let allocation_backtrace = data.get_backtrace( allocation_backtrace_id ).map( |(_, frame)| get_frame( data, &backtrace_format, frame ) ).collect();
+                let deallocation_backtrace = deallocation_backtrace_id.map( |backtrace_id| {
+                    data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, &backtrace_format, frame ) ).collect()
+                });
+
+                protocol::SitePairGroup {
+                    allocation_backtrace_id: allocation_backtrace_id.raw(),
+                    allocation_backtrace,
+                    deallocation_backtrace_id: deallocation_backtrace_id.map( |backtrace_id| backtrace_id.raw() ),
+                    deallocation_backtrace,
+                    count: entry.count,
+                    size: entry.size,
+                    cross_thread_count: entry.cross_thread_count,
+                    mean_lifetime: entry.mean_lifetime().into(),
+                    thread_pair_count: entry.thread_pairs.len() as u64
+                }
+            })
+    };
+
+    protocol::ResponseSitePairs {
+        count: total.count,
+        size: total.size,
+        cross_thread_count: total.cross_thread_count,
+        mean_lifetime: total.mean_lifetime().into(),
+        groups: StreamingSerializer::new( groups ),
+        total_count
+    }
+}
@@ -0,0 +1,261 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use ahash::AHashMap as HashMap;
+use serde::Serialize;
+
+use cli_core::{
+    AllocationColumns,
+    BacktraceId,
+    Data
+};
+
+use crate::protocol;
+use crate::filter::{AllocationMatcher, Filter};
+use crate::streaming_serializer::{StreamingSerializer, Rows};
+use crate::get_frame;
+
+const PAGE_SIZE: u64 = 4096;
+
+#[derive(Copy, Clone)]
+struct Model {
+    allocator: protocol::AllocatorModel,
+    pointer_size: u64
+}
+
+impl Model {
+    /// Returns how much memory the allocator will actually consume to satisfy an allocation of a given size.
+    fn footprint( &self, size: u64, is_mmaped: bool ) -> u64 {
+        match self.allocator {
+            protocol::AllocatorModel::Glibc => {
+                if is_mmaped {
+                    round_up( size + self.pointer_size * 2, PAGE_SIZE )
+                } else {
+                    let alignment = self.pointer_size * 2;
+                    let min_size = self.pointer_size * 4;
+                    std::cmp::max( min_size, round_up( size + self.pointer_size, alignment ) )
+                }
+            },
+            protocol::AllocatorModel::Jemalloc => jemalloc_size_class( size )
+        }
+    }
+
+    /// Returns the usable size of an allocation with the given footprint.
+    fn usable_size( &self, footprint: u64, is_mmaped: bool ) -> u64 {
+        match self.allocator {
+            protocol::AllocatorModel::Glibc => {
+                if is_mmaped {
+                    footprint - self.pointer_size * 2
+                } else {
+                    footprint - self.pointer_size
+                }
+            },
+            protocol::AllocatorModel::Jemalloc => footprint
+        }
+    }
+
+    /// Returns the biggest size which fits into the next smaller size class.
+    fn suggested_size( &self, size: u64, is_mmaped: bool ) -> Option< u64 > {
+        let footprint = self.footprint( size, is_mmaped );
+        let smaller_footprint = match self.allocator {
+            protocol::AllocatorModel::Glibc => {
+                let step = if is_mmaped { PAGE_SIZE } else { self.pointer_size * 2 };
+                let min_size = if is_mmaped { PAGE_SIZE } else { self.pointer_size * 4 };
+                if footprint < min_size + step {
+                    return None;
+                }
+                footprint - step
+            },
+            protocol::AllocatorModel::Jemalloc => {
+                if footprint <= 8 {
+                    return None;
+                }
+                footprint - jemalloc_size_class_delta( footprint )
+            }
+        };
+
+        Some( self.usable_size( smaller_footprint, is_mmaped ) )
+    }
+}
+
+#[inline]
+fn round_up( value: u64, alignment: u64 ) -> u64 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+#[inline]
+fn floor_log2( value: u64 ) -> u32 {
+    63 - value.leading_zeros()
+}
+
+fn jemalloc_size_class_delta( size: u64 ) -> u64 {
+    if size <= 16 {
+        8
+    } else if size <= 128 {
+        16
+    } else {
+        1 << (floor_log2( size - 1 ) - 2)
+    }
+}
+
+fn jemalloc_size_class( size: u64 ) -> u64 {
+    if size <= 8 {
+        8
+    } else {
+        round_up( size, jemalloc_size_class_delta( size ) )
+    }
+}
+
+#[test]
+fn test_jemalloc_size_class() {
+    assert_eq!( jemalloc_size_class( 0 ), 8 );
+    assert_eq!( jemalloc_size_class( 9 ), 16 );
+    assert_eq!( jemalloc_size_class( 17 ), 32 );
+    assert_eq!( jemalloc_size_class( 113 ), 128 );
+    assert_eq!( jemalloc_size_class( 129 ), 160 );
+    assert_eq!( jemalloc_size_class( 256 ), 256 );
+    assert_eq!( jemalloc_size_class( 257 ), 320 );
+    assert_eq!( jemalloc_size_class( 4096 ), 4096 );
+    assert_eq!( jemalloc_size_class( 4100 ), 5120 );
+}
+
+#[test]
+fn test_suggested_size() {
+    let jemalloc = Model { allocator: protocol::AllocatorModel::Jemalloc, pointer_size: 8 };
+    assert_eq!( jemalloc.suggested_size( 4100, false ), Some( 4096 ) );
+    assert_eq!( jemalloc.suggested_size( 4096, false ), Some( 3584 ) );
+    assert_eq!( jemalloc.suggested_size( 16, false ), Some( 8 ) );
+    assert_eq!( jemalloc.suggested_size( 8, false ), None );
+
+    let glibc = Model { allocator: protocol::AllocatorModel::Glibc, pointer_size: 8 };
+    assert_eq!( glibc.footprint( 4100, false ), 4112 );
+    assert_eq!( glibc.usable_size( 4112, false ), 4104 );
+    assert_eq!( glibc.suggested_size( 4100, false ), Some( 4088 ) );
+    assert_eq!( glibc.suggested_size( 24, false ), None );
+    assert_eq!( glibc.footprint( 200000, true ), 200704 );
+}
+
+#[derive(Default)]
+struct WasteEntry {
+    count: u64,
+    size: u64,
+    footprint: u64,
+    waste: u64,
+    count_by_size: HashMap< (u64, bool), u64 >
+}
+
+impl WasteEntry {
+    fn waste_ratio( &self ) -> f64 {
+        if self.footprint == 0 {
+            0.0
+        } else {
+            self.waste as f64 / self.footprint as f64
+        }
+    }
+}
+
+pub fn get_size_class_waste< 'a >(
+    data: &'a Data,
+    columns: Option< &AllocationColumns >,
+    backtrace_format: protocol::BacktraceFormat,
+    params: protocol::RequestSizeClassWaste,
+    filter: Filter
+) -> protocol::ResponseSizeClassWaste< impl Serialize + Rows + 'a > {
+    let remaining = params.count.unwrap_or( -1_i32 as _ ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+    let sort_by = params.sort_by.unwrap_or( protocol::SizeClassWasteSortBy::Waste );
+    let order = params.order.unwrap_or( protocol::Order::Dsc );
+    let model = Model {
+        allocator: params.allocator.unwrap_or( protocol::AllocatorModel::Glibc ),
+        pointer_size: data.pointer_size()
+    };
+
+    let mut total = WasteEntry::default();
+    let mut entry_by_backtrace: HashMap< BacktraceId, WasteEntry > = HashMap::new();
+    let matcher = AllocationMatcher::new( data, columns, &filter );
+    let iter = data.alloc_sorted_by_timestamp( filter.timestamp_start_opt(), filter.timestamp_end_opt() );
+    for (id, allocation) in iter {
+        if !matcher.matches( id, allocation ) {
+            continue;
+        }
+
+        let is_mmaped = allocation.is_mmaped();
+        let footprint = model.footprint( allocation.size, is_mmaped );
+        let waste = model.usable_size( footprint, is_mmaped ) - allocation.size;
+
+        let entry = entry_by_backtrace.entry( allocation.backtrace ).or_insert_with( WasteEntry::default );
+        entry.count += 1;
+        entry.size += allocation.size;
+        entry.footprint += footprint;
+        entry.waste += waste;
+        *entry.count_by_size.entry( (allocation.size, is_mmaped) ).or_insert( 0 ) += 1;
+
+        total.count += 1;
+        total.size += allocation.size;
+        total.footprint += footprint;
+        total.waste += waste;
+    }
+
+    let mut entries: Vec< _ > = entry_by_backtrace.into_iter().collect();
+    entries.sort_by( |(lhs_id, lhs), (rhs_id, rhs)| {
+        let ordering = match sort_by {
+            protocol::SizeClassWasteSortBy::Count => lhs.count.cmp( &rhs.count ),
+            protocol::SizeClassWasteSortBy::Size => lhs.size.cmp( &rhs.size ),
+            protocol::SizeClassWasteSortBy::Waste => lhs.waste.cmp( &rhs.waste ),
+            protocol::SizeClassWasteSortBy::WasteRatio => lhs.waste_ratio().partial_cmp( &rhs.waste_ratio() ).unwrap_or( Ordering::Equal )
+        };
+
+        ordering.then_with( || lhs_id.cmp( rhs_id ) )
+    });
+
+    if order == protocol::Order::Dsc {
+        entries.reverse();
+    }
+
+    let total_count = entries.len() as u64;
+    let entries = Arc::new( entries );
+    let groups = move || {
+        let backtrace_format = backtrace_format.clone();
+        let entries = entries.clone();
+        (0..entries.len())
+            .skip( skip )
+            .take( remaining )
+            .map( move |index| {
+                let (backtrace_id, ref entry) = entries[ index ];
+                let backtrace = data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, &backtrace_format, frame ) ).collect();
+                let (&(most_common_size, is_mmaped), _) = entry.count_by_size.iter()
+                    .max_by_key( |&(&(size, _), &count)| (count, size) )
+                    .unwrap();
+
+                let footprint = model.footprint( most_common_size, is_mmaped );
+                let suggested_size = model.suggested_size( most_common_size, is_mmaped );
+                let suggested_saving_ratio = suggested_size.map( |suggested_size| {
+                    let suggested_footprint = model.footprint( suggested_size, is_mmaped );
+                    (footprint - suggested_footprint) as f64 / footprint as f64
+                });
+
+                protocol::SizeClassWasteGroup {
+                    backtrace_id: backtrace_id.raw(),
+                    backtrace,
+                    count: entry.count,
+                    size: entry.size,
+                    footprint: entry.footprint,
+                    waste: entry.waste,
+                    waste_ratio: entry.waste_ratio(),
+                    most_common_size,
+                    suggested_size,
+                    suggested_saving_ratio
+                }
+            })
+    };
+
+    protocol::ResponseSizeClassWaste {
+        count: total.count,
+        size: total.size,
+        footprint: total.footprint,
+        waste: total.waste,
+        waste_ratio: total.waste_ratio(),
+        groups: StreamingSerializer::new( groups ),
+        total_count
+    }
+}
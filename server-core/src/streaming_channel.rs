@@ -7,7 +7,8 @@ struct Inner< T > {
     buffer: VecDeque< T >,
     task: Option< futures::task::Task >,
     sender_closed: bool,
-    receiver_closed: bool
+    receiver_closed: bool,
+    aborted: bool
 }
 
 pub struct Sender< T >( Arc< (Condvar, Mutex< Inner< T > >) > );
@@ -36,6 +37,16 @@ impl< T > Sender< T > {
 
         Ok(())
     }
+
+    /// Makes the receiving end fail instead of finishing normally.
+    pub fn abort( &mut self ) {
+        let mut inner = (self.0).1.lock().unwrap();
+        inner.aborted = true;
+        inner.buffer.clear();
+        if let Some( ref mut task ) = inner.task {
+            task.notify();
+        }
+    }
 }
 
 impl< T > Drop for Sender< T > {
@@ -56,6 +67,10 @@ impl< T > futures::Stream for Receiver< T > {
 
     fn poll( &mut self ) -> futures::Poll< Option< Self::Item >, Self::Error > {
         let mut inner = (self.0).1.lock().unwrap();
+        if inner.aborted {
+            return Err(());
+        }
+
         match inner.buffer.pop_front() {
             Some( value ) => {
                 (self.0).0.notify_all();
@@ -86,7 +101,8 @@ pub fn streaming_channel< T >() -> (Sender< T >, Receiver< T >) {
         buffer: VecDeque::new(),
         task: None,
         sender_closed: false,
-        receiver_closed: false
+        receiver_closed: false,
+        aborted: false
     };
 
     let condvar = Condvar::new();
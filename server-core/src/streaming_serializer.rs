@@ -34,3 +34,24 @@ impl< F, R, T > Serialize for StreamingSerializer< F, R, T >
         seq.end()
     }
 }
+
+/// Gives access to the individual elements of a streamed sequence.
+pub trait Rows {
+    type Row: Serialize;
+    type Iter: Iterator< Item = Self::Row >;
+
+    fn rows( &self ) -> Self::Iter;
+}
+
+impl< F, R, T > Rows for StreamingSerializer< F, R, T >
+    where F: Fn() -> R,
+          R: Iterator< Item = T >,
+          T: Serialize
+{
+    type Row = T;
+    type Iter = R;
+
+    fn rows( &self ) -> Self::Iter {
+        (self.callback)()
+    }
+}
@@ -0,0 +1,130 @@
+use std::cmp::{min, max};
+
+use ahash::AHashMap as HashMap;
+
+use cli_core::{
+    Data,
+    Timestamp
+};
+
+use crate::protocol;
+
+struct ThreadStats {
+    first_timestamp: Timestamp,
+    last_timestamp: Timestamp,
+    allocated_count: u64,
+    allocated_size: u64,
+    deallocated_count: u64,
+    deallocated_size: u64,
+    live_count: u64,
+    live_size: u64
+}
+
+impl ThreadStats {
+    fn new( timestamp: Timestamp ) -> Self {
+        ThreadStats {
+            first_timestamp: timestamp,
+            last_timestamp: timestamp,
+            allocated_count: 0,
+            allocated_size: 0,
+            deallocated_count: 0,
+            deallocated_size: 0,
+            live_count: 0,
+            live_size: 0
+        }
+    }
+
+    fn touch( &mut self, timestamp: Timestamp ) {
+        self.first_timestamp = min( self.first_timestamp, timestamp );
+        self.last_timestamp = max( self.last_timestamp, timestamp );
+    }
+}
+
+pub fn get_threads( data: &Data ) -> Vec< protocol::Thread > {
+    let mut stats_by_thread: HashMap< u32, ThreadStats > = HashMap::new();
+    for (_, allocation) in data.allocations_with_id() {
+        let stats = stats_by_thread.entry( allocation.thread ).or_insert_with( || ThreadStats::new( allocation.timestamp ) );
+        stats.touch( allocation.timestamp );
+        stats.allocated_count += 1;
+        stats.allocated_size += allocation.size;
+
+        match allocation.deallocation {
+            Some( ref deallocation ) => {
+                let stats = stats_by_thread.entry( deallocation.thread ).or_insert_with( || ThreadStats::new( deallocation.timestamp ) );
+                stats.touch( deallocation.timestamp );
+                stats.deallocated_count += 1;
+                stats.deallocated_size += allocation.size;
+            },
+            None => {
+                stats.live_count += 1;
+                stats.live_size += allocation.size;
+            }
+        }
+    }
+
+    let mut threads: Vec< _ > = stats_by_thread.into_iter().map( |(thread, stats)| {
+        protocol::Thread {
+            thread,
+            // Thread names are not recorded yet.
+            name: None,
+            first_timestamp: stats.first_timestamp.into(),
+            last_timestamp: stats.last_timestamp.into(),
+            allocated_count: stats.allocated_count,
+            allocated_size: stats.allocated_size,
+            deallocated_count: stats.deallocated_count,
+            deallocated_size: stats.deallocated_size,
+            live_count: stats.live_count,
+            live_size: stats.live_size
+        }
+    }).collect();
+
+    threads.sort_by_key( |thread| thread.thread );
+    threads
+}
+
+#[derive(Default)]
+struct ThreadMemory {
+    live_count: u64,
+    live_size: u64,
+    owned_count: u64,
+    owned_size: u64
+}
+
+/// Returns how much memory was held by each thread at a given point in time.
+///
+/// The live memory is attributed to the thread which allocated it, while the owned memory
+/// is attributed to the thread which will eventually free it (or to the allocating thread
+/// if it was never freed).
+pub fn get_thread_memory( data: &Data, params: protocol::RequestThreadMemory ) -> Vec< protocol::ThreadMemory > {
+    let at = params.at.map( |ts| ts.to_timestamp( data.initial_timestamp(), data.last_timestamp() ) ).unwrap_or( data.last_timestamp() );
+
+    let mut memory_by_thread: HashMap< u32, ThreadMemory > = HashMap::new();
+    for (_, allocation) in data.alloc_sorted_by_timestamp( None, Some( at ) ) {
+        let owner = match allocation.deallocation {
+            Some( ref deallocation ) if deallocation.timestamp <= at => continue,
+            Some( ref deallocation ) => deallocation.thread,
+            None => allocation.thread
+        };
+
+        let memory = memory_by_thread.entry( allocation.thread ).or_insert_with( ThreadMemory::default );
+        memory.live_count += 1;
+        memory.live_size += allocation.size;
+
+        let memory = memory_by_thread.entry( owner ).or_insert_with( ThreadMemory::default );
+        memory.owned_count += 1;
+        memory.owned_size += allocation.size;
+    }
+
+    let mut threads: Vec< _ > = memory_by_thread.into_iter().map( |(thread, memory)| {
+        protocol::ThreadMemory {
+            thread,
+            live_count: memory.live_count,
+            live_size: memory.live_size,
+            owned_count: memory.owned_count,
+            owned_size: memory.owned_size
+        }
+    }).collect();
+
+    threads.sort_by( |lhs, rhs| rhs.live_size.cmp( &lhs.live_size ).then_with( || lhs.thread.cmp( &rhs.thread ) ) );
+    threads
+}
@@ -0,0 +1,59 @@
+use cli_core::{
+    BacktraceId,
+    Data
+};
+
+use crate::protocol;
+use crate::get_frame;
+use crate::site_classification::classify_site;
+
+pub fn get_top_sites< 'a >(
+    data: &'a Data,
+    backtrace_format: &protocol::BacktraceFormat,
+    params: &protocol::RequestTopSites
+) -> protocol::ResponseTopSites< 'a > {
+    let remaining = params.count.unwrap_or( 20 ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+    let sort_by = params.sort_by.unwrap_or( protocol::TopSitesSortBy::AllocatedCount );
+
+    // The group statistics also include the temporary allocations which were culled at runtime.
+    let mut backtrace_ids: Vec< BacktraceId > = (0..data.unique_backtrace_count())
+        .map( |index| BacktraceId::new( index as u32 ) )
+        .filter( |&backtrace_id| data.get_group_statistics( backtrace_id ).alloc_count > 0 )
+        .collect();
+
+    backtrace_ids.sort_by_key( |&backtrace_id| {
+        let stats = data.get_group_statistics( backtrace_id );
+        let key = match sort_by {
+            protocol::TopSitesSortBy::AllocatedCount => stats.alloc_count,
+            protocol::TopSitesSortBy::AllocatedSize => stats.alloc_size
+        };
+
+        (std::cmp::Reverse( key ), backtrace_id)
+    });
+
+    let total_count = backtrace_ids.len() as u64;
+    let sites = backtrace_ids.into_iter().skip( skip ).take( remaining ).map( |backtrace_id| {
+        let stats = data.get_group_statistics( backtrace_id );
+        let backtrace = data.get_backtrace( backtrace_id ).map( |(_, frame)| get_frame( data, backtrace_format, frame ) ).collect();
+        protocol::TopSite {
+            backtrace_id: backtrace_id.raw(),
+            site_id: data.get_site_id( backtrace_id ).to_string(),
+            classification: classify_site( data, backtrace_id ),
+            backtrace,
+            allocated_count: stats.alloc_count,
+            allocated_size: stats.alloc_size,
+            freed_count: stats.free_count,
+            freed_size: stats.free_size,
+            min_size: stats.min_size,
+            max_size: stats.max_size,
+            first_allocation: stats.first_allocation.into(),
+            last_allocation: stats.last_allocation.into()
+        }
+    }).collect();
+
+    protocol::ResponseTopSites {
+        sites,
+        total_count
+    }
+}
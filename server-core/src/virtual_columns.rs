@@ -0,0 +1,102 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use ahash::AHashMap as HashMap;
+
+use cli_core::{
+    Allocation,
+    AllocationColumns,
+    AllocationId,
+    ColumnValue,
+    Data,
+    Expression,
+    Timestamp
+};
+
+use crate::protocol;
+use crate::filter::{AllocationMatcher, Filter};
+use crate::query_limits::charge_memory;
+use crate::AllocationGroupDataBuilder;
+
+pub fn to_json( value: ColumnValue ) -> serde_json::Value {
+    match value {
+        ColumnValue::Null => serde_json::Value::Null,
+        ColumnValue::Bool( value ) => serde_json::Value::Bool( value ),
+        ColumnValue::Number( value ) if value.fract() == 0.0 && value.abs() < 9.0e15 => serde_json::Value::from( value as i64 ),
+        ColumnValue::Number( value ) => serde_json::Number::from_f64( value ).map( serde_json::Value::Number ).unwrap_or( serde_json::Value::Null ),
+        ColumnValue::String( value ) => serde_json::Value::String( value )
+    }
+}
+
+/// Returns the values of the filter's virtual columns for a given allocation, keyed by their names.
+pub fn get_columns( data: &Data, allocation: &Allocation, filter: &Filter ) -> Option< BTreeMap< String, serde_json::Value > > {
+    if filter.columns.is_empty() {
+        return None;
+    }
+
+    let values = filter.columns.evaluate( data, allocation );
+    Some( filter.columns.names().map( |name| name.to_owned() ).zip( values.into_iter().map( to_json ) ).collect() )
+}
+
+#[test]
+fn test_to_json() {
+    assert_eq!( to_json( ColumnValue::Number( 3.0 ) ), serde_json::json!( 3 ) );
+    assert_eq!( to_json( ColumnValue::Number( 0.5 ) ), serde_json::json!( 0.5 ) );
+    assert_eq!( to_json( ColumnValue::Number( std::f64::NAN ) ), serde_json::Value::Null );
+    assert_eq!( to_json( ColumnValue::String( "a".to_owned() ) ), serde_json::json!( "a" ) );
+}
+
+/// Groups the matched allocations by the value of an arbitrary expression, which can refer to the filter's virtual columns.
+pub fn get_column_groups( data: &Data, columns: Option< &AllocationColumns >, group_by: &Expression, params: protocol::RequestColumnGroups, filter: Filter ) -> protocol::ResponseColumnGroups {
+    let remaining = params.count.unwrap_or( 10 ) as usize;
+    let skip = params.skip.unwrap_or( 0 ) as usize;
+
+    // The virtual columns are evaluated only once per allocation, and every allocation
+    // goes straight into its group's statistics instead of being collected first.
+    let mut index_by_key: HashMap< String, usize > = HashMap::new();
+    let mut groups: Vec< (ColumnValue, AllocationGroupDataBuilder) > = Vec::new();
+    let matcher = AllocationMatcher::without_expression( data, columns, &filter );
+    for (index, allocation) in data.allocations().iter().enumerate() {
+        if !matcher.matches( AllocationId::new( index as u64 ), allocation ) {
+            continue;
+        }
+
+        let columns = filter.columns.evaluate( data, allocation );
+        if let Some( ref expression ) = filter.expression {
+            if !expression.evaluate( data, allocation, &columns ).is_truthy() {
+                continue;
+            }
+        }
+
+        let value = group_by.evaluate( data, allocation, &columns );
+
+        // Floats aren't hashable, so the groups are keyed by the value's debug representation instead.
+        let key = format!( "{:?}", value );
+        let index = match index_by_key.get( &key ) {
+            Some( &index ) => index,
+            None => {
+                charge_memory( key.len() + std::mem::size_of::< (ColumnValue, AllocationGroupDataBuilder) >() );
+                index_by_key.insert( key, groups.len() );
+                groups.push( (value, AllocationGroupDataBuilder::new()) );
+                groups.len() - 1
+            }
+        };
+
+        charge_memory( std::mem::size_of::< Option< Timestamp > >() );
+        groups[ index ].1.add( data, allocation );
+    }
+
+    let total_count = groups.len() as u64;
+    let mut groups: Vec< _ > = groups.into_iter().map( |(value, builder)| {
+        protocol::ColumnGroup {
+            value: to_json( value ),
+            data: builder.build( data )
+        }
+    }).collect();
+
+    groups.sort_by_key( |group| (Reverse( group.data.size ), Reverse( group.data.allocated_count )) );
+    protocol::ResponseColumnGroups {
+        groups: groups.into_iter().skip( skip ).take( remaining ).collect(),
+        total_count
+    }
+}
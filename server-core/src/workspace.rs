@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use cli_core::{Attribution, DataId, SymbolSources};
+use parking_lot::Mutex;
+
+use crate::protocol;
+use crate::sessions::Sessions;
+
+/*
+    A workspace file is a JSON file with everything which is needed to get the server back
+    into the same state after a restart:
+
+        {
+            "inputs": ["/captures/memory-profiling_app_1.dat", ...],
+            "cores": ["/captures/core.1234", ...],
+            "plugins": ["/home/user/libmy_analysis.so", ...],
+            "debug_symbols": [...],
+            "sysroots": [...],
+            "symbol_paths": [...],
+            "frame_rules": "/home/user/frame-rules.txt",
+            "attribute_to": "outermost-non-inline",
+            "sessions": { "<session ID>": { "filters": {...}, "comparisons": [...], "bookmarks": [...] } },
+            "annotations": [{ "id": 1, "data_id": "...", "text": "...", "backtrace_id": 12, "at": 5.0, "created": 1600000000 }]
+        }
+*/
+
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+struct WorkspaceFile {
+    #[serde(default)]
+    inputs: Vec< PathBuf >,
+    #[serde(default)]
+    cores: Vec< PathBuf >,
+    #[serde(default)]
+    plugins: Vec< PathBuf >,
+    #[serde(default)]
+    debug_symbols: Vec< PathBuf >,
+    #[serde(default)]
+    sysroots: Vec< PathBuf >,
+    #[serde(default)]
+    symbol_paths: Vec< PathBuf >,
+    #[serde(default)]
+    frame_rules: Option< PathBuf >,
+    #[serde(default)]
+    attribute_to: Option< String >,
+    #[serde(default)]
+    sessions: BTreeMap< String, protocol::Session >,
+    #[serde(default)]
+    annotations: Vec< protocol::Annotation >
+}
+
+/// Everything the server loads on startup.
+#[derive(Default)]
+pub struct Inputs {
+    pub data_files: Vec< PathBuf >,
+    pub cores: Vec< PathBuf >,
+    pub plugins: Vec< PathBuf >,
+    pub symbol_sources: SymbolSources,
+    pub frame_rules: Option< PathBuf >,
+    pub attribution: Option< Attribution >
+}
+
+/// The persistent part of the server's state; without a path nothing is saved.
+#[derive(Default)]
+pub struct Workspace {
+    path: Option< PathBuf >,
+    file: Mutex< WorkspaceFile >
+}
+
+/// The paths are stored as absolute, so that the server can be restarted from any directory.
+fn absolute( path: PathBuf ) -> PathBuf {
+    fs::canonicalize( &path ).unwrap_or( path )
+}
+
+fn extend_unique( target: &mut Vec< PathBuf >, paths: Vec< PathBuf > ) {
+    for path in paths {
+        let path = absolute( path );
+        if !target.contains( &path ) {
+            target.push( path );
+        }
+    }
+}
+
+fn exists( path: &Path ) -> bool {
+    if path.exists() {
+        return true;
+    }
+
+    warn!( "Skipping {:?} from the workspace since it doesn't exist anymore", path );
+    false
+}
+
+/// Adds the paths from the command line to the ones from the workspace. The paths from the workspace which
+/// don't exist anymore are skipped (but are kept in the workspace, in case they come back), while the ones
+/// from the command line are always returned, so that a typo there is still reported.
+fn merge_paths( target: &mut Vec< PathBuf >, paths: Vec< PathBuf > ) -> Vec< PathBuf > {
+    let paths: Vec< _ > = paths.into_iter().map( absolute ).collect();
+    let mut output: Vec< _ > = target.iter().filter( |path| paths.contains( path ) || exists( path ) ).cloned().collect();
+    for path in paths {
+        if !output.contains( &path ) {
+            output.push( path.clone() );
+        }
+
+        if !target.contains( &path ) {
+            target.push( path );
+        }
+    }
+
+    output
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since( UNIX_EPOCH ).map( |duration| duration.as_secs() ).unwrap_or( 0 )
+}
+
+impl Workspace {
+    /// Opens a workspace file, or starts a new one if it doesn't exist yet.
+    pub fn open( path: PathBuf ) -> io::Result< Self > {
+        let file = match fs::read( &path ) {
+            Ok( contents ) => {
+                info!( "Restoring the workspace from {:?}...", path );
+                serde_json::from_slice( &contents ).map_err( |error| {
+                    io::Error::new( io::ErrorKind::InvalidData, format!( "failed to parse the workspace {:?}: {}", path, error ) )
+                })?
+            },
+            Err( ref error ) if error.kind() == io::ErrorKind::NotFound => WorkspaceFile::default(),
+            Err( error ) => return Err( error )
+        };
+
+        Ok( Workspace { path: Some( path ), file: Mutex::new( file ) } )
+    }
+
+    /// Adds what was given on the command line to what's already in the workspace, and returns the combination
+    /// of the two; the frame rules and the attribution from the command line replace the ones from the workspace.
+    pub fn merge( &self, inputs: Inputs ) -> Result< Inputs, String > {
+        let mut file = self.file.lock();
+        let data_files = merge_paths( &mut file.inputs, inputs.data_files );
+        let cores = merge_paths( &mut file.cores, inputs.cores );
+        let plugins = merge_paths( &mut file.plugins, inputs.plugins );
+        extend_unique( &mut file.debug_symbols, inputs.symbol_sources.debug_symbols );
+        extend_unique( &mut file.sysroots, inputs.symbol_sources.sysroots );
+        extend_unique( &mut file.symbol_paths, inputs.symbol_sources.symbol_paths );
+
+        let frame_rules = match inputs.frame_rules {
+            Some( frame_rules ) => {
+                let frame_rules = absolute( frame_rules );
+                file.frame_rules = Some( frame_rules.clone() );
+                Some( frame_rules )
+            },
+            None => file.frame_rules.clone().filter( |path| exists( path ) )
+        };
+
+        if let Some( attribution ) = inputs.attribution {
+            file.attribute_to = Some( attribution.to_string() );
+        }
+
+        let attribution = match file.attribute_to {
+            Some( ref attribution ) => attribution.parse()?,
+            None => Attribution::default()
+        };
+
+        let symbol_sources = SymbolSources {
+            debug_symbols: file.debug_symbols.clone(),
+            sysroots: file.sysroots.clone(),
+            symbol_paths: file.symbol_paths.clone()
+        };
+
+        Ok( Inputs {
+            data_files,
+            cores,
+            plugins,
+            symbol_sources,
+            frame_rules,
+            attribution: Some( attribution )
+        })
+    }
+
+    pub fn restore_sessions( &self, sessions: &Sessions ) {
+        for (id, session) in self.file.lock().sessions.clone() {
+            sessions.restore( id, session );
+        }
+    }
+
+    pub fn annotations( &self, data_id: DataId ) -> Vec< protocol::Annotation > {
+        let data_id = format!( "{}", data_id );
+        self.file.lock().annotations.iter().filter( |annotation| annotation.data_id == data_id ).cloned().collect()
+    }
+
+    pub fn add_annotation( &self, data_id: DataId, request: protocol::RequestAnnotation ) -> protocol::Annotation {
+        let mut file = self.file.lock();
+        let annotation = protocol::Annotation {
+            id: file.annotations.iter().map( |annotation| annotation.id + 1 ).max().unwrap_or( 1 ),
+            data_id: format!( "{}", data_id ),
+            text: request.text,
+            backtrace_id: request.backtrace_id,
+            at: request.at,
+            created: now()
+        };
+
+        file.annotations.push( annotation.clone() );
+        annotation
+    }
+
+    pub fn remove_annotation( &self, data_id: DataId, id: u64 ) -> bool {
+        let data_id = format!( "{}", data_id );
+        let mut file = self.file.lock();
+        let length = file.annotations.len();
+        file.annotations.retain( |annotation| !(annotation.id == id && annotation.data_id == data_id) );
+        file.annotations.len() != length
+    }
+
+    /// Writes the workspace back into its file, along with the current sessions.
+    pub fn save( &self, sessions: &Sessions ) -> io::Result< () > {
+        let path = match self.path {
+            Some( ref path ) => path,
+            None => return Ok(())
+        };
+
+        let mut file = self.file.lock();
+        file.sessions = sessions.snapshot();
+
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push( ".tmp" );
+        let temporary_path: PathBuf = temporary_path.into();
+
+        fs::write( &temporary_path, serde_json::to_vec_pretty( &*file )? )?;
+        fs::rename( &temporary_path, path )
+    }
+}
+
+#[test]
+fn test_merge() {
+    let workspace = Workspace::default();
+    {
+        let mut file = workspace.file.lock();
+        file.inputs.push( "/captures/a.dat".into() );
+        file.attribute_to = Some( "outermost-non-inline".to_owned() );
+    }
+
+    let symbol_sources = SymbolSources { sysroots: vec![ "/nonexistent/sysroot".into() ], .. SymbolSources::default() };
+    let inputs = workspace.merge( Inputs {
+        data_files: vec![ "/captures/a.dat".into(), "/captures/b.dat".into() ],
+        symbol_sources,
+        .. Inputs::default()
+    }).unwrap();
+
+    assert_eq!( inputs.data_files, vec![ PathBuf::from( "/captures/a.dat" ), PathBuf::from( "/captures/b.dat" ) ] );
+    assert_eq!( inputs.symbol_sources.sysroots, vec![ PathBuf::from( "/nonexistent/sysroot" ) ] );
+    assert_eq!( inputs.frame_rules, None );
+    assert_eq!( inputs.attribution, Some( Attribution::OutermostNonInline ) );
+
+    let inputs = workspace.merge( Inputs { attribution: Some( Attribution::InnermostInline ), .. Inputs::default() } ).unwrap();
+    assert_eq!( inputs.attribution, Some( Attribution::InnermostInline ) );
+}
+
+#[test]
+fn test_merge_skips_missing_inputs_from_the_workspace() {
+    let directory = std::env::temp_dir().join( format!( "memory-profiler-workspace-test-{}", std::process::id() ) );
+    fs::create_dir_all( &directory ).unwrap();
+    let existing = absolute( directory.clone() );
+
+    let workspace = Workspace::default();
+    {
+        let mut file = workspace.file.lock();
+        file.inputs.push( "/nonexistent/moved.dat".into() );
+        file.inputs.push( existing.clone() );
+        file.cores.push( "/nonexistent/core.1".into() );
+        file.plugins.push( "/nonexistent/libplugin.so".into() );
+        file.frame_rules = Some( "/nonexistent/frame-rules.txt".into() );
+    }
+
+    let inputs = workspace.merge( Inputs {
+        data_files: vec![ "/nonexistent/typo.dat".into() ],
+        .. Inputs::default()
+    }).unwrap();
+
+    assert_eq!( inputs.data_files, vec![ existing, PathBuf::from( "/nonexistent/typo.dat" ) ] );
+    assert!( inputs.cores.is_empty() );
+    assert!( inputs.plugins.is_empty() );
+    assert_eq!( inputs.frame_rules, None );
+
+    // They're still kept in the workspace.
+    assert_eq!( workspace.file.lock().inputs.len(), 3 );
+    assert_eq!( workspace.file.lock().cores.len(), 1 );
+
+    fs::remove_dir_all( &directory ).unwrap();
+}
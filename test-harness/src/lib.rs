@@ -0,0 +1,356 @@
+//! Assertions on the heap allocations made by a piece of code, for use in tests and benchmarks.
+//!
+//! The [`TrackingAllocator`] has to be installed as the global allocator of the test binary:
+//!
+//! ```rust,ignore
+//! #[global_allocator]
+//! static ALLOCATOR: memory_profiler_test::TrackingAllocator< std::alloc::System > =
+//!     memory_profiler_test::TrackingAllocator::new( std::alloc::System );
+//!
+//! #[test]
+//! fn parsing_does_not_allocate() {
+//!     let (_, measurement) = memory_profiler_test::measure( || parse( "1234" ) );
+//!     assert_eq!( measurement.allocations, 0 );
+//! }
+//! ```
+//!
+//! When the test binary is run with `libmemory_profiler.so` preloaded the measurements
+//! are made by the profiler instead: they then include every thread of the process and every
+//! allocation made through `malloc` (e.g. by C libraries), and the [`TrackingAllocator`] isn't needed.
+//! Since other tests running at the same time are counted too such tests should be run
+//! with `--test-threads=1`.
+//!
+//! Otherwise only the allocations made on the thread which calls [`measure`] are counted, so tests
+//! running in parallel don't affect each other; anything done on other threads is ignored.
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::Cell;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use libc::{c_char, c_int};
+
+/// What was allocated while the closure passed to [`measure`] was running.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Measurement {
+    /// The number of new allocations (not counting reallocations).
+    pub allocations: u64,
+    /// The number of reallocations.
+    pub reallocations: u64,
+    /// The number of deallocations; this includes memory which was allocated before the measurement started.
+    pub deallocations: u64,
+    /// The total number of bytes allocated, including the growth of the reallocated memory.
+    pub allocated_bytes: u64,
+    /// The total number of bytes deallocated, including the shrinkage of the reallocated memory.
+    ///
+    /// When measured by the profiler only the memory which was allocated during the measurement is counted.
+    pub deallocated_bytes: u64,
+    /// The highest amount of memory in use at any point, relative to what was in use when the measurement started.
+    pub peak_bytes: u64
+}
+
+impl Measurement {
+    /// The number of bytes which were allocated and are still in use, minus what was freed from before the measurement.
+    pub fn net_bytes( &self ) -> i64 {
+        self.allocated_bytes as i64 - self.deallocated_bytes as i64
+    }
+
+    /// Whether there were any allocations or reallocations at all.
+    pub fn has_allocated( &self ) -> bool {
+        self.allocations != 0 || self.reallocations != 0
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct State {
+    is_active: bool,
+    measurement: Measurement,
+    current_bytes: i64
+}
+
+thread_local! {
+    static STATE: Cell< State > = Cell::new( State::default() );
+}
+
+fn update( callback: impl FnOnce( &mut Measurement, &mut i64 ) ) {
+    // This can fail if we're called while the thread is being torn down.
+    let _ = STATE.try_with( |cell| {
+        let mut state = cell.get();
+        if !state.is_active {
+            return;
+        }
+
+        callback( &mut state.measurement, &mut state.current_bytes );
+        if state.current_bytes > state.measurement.peak_bytes as i64 {
+            state.measurement.peak_bytes = state.current_bytes as u64;
+        }
+
+        cell.set( state );
+    });
+}
+
+/// A global allocator which keeps track of what's being allocated during [`measure`].
+///
+/// Every allocation is forwarded to the wrapped allocator.
+pub struct TrackingAllocator< A > {
+    inner: A
+}
+
+impl< A > TrackingAllocator< A > {
+    pub const fn new( inner: A ) -> Self {
+        TrackingAllocator { inner }
+    }
+}
+
+unsafe impl< A: GlobalAlloc > GlobalAlloc for TrackingAllocator< A > {
+    unsafe fn alloc( &self, layout: Layout ) -> *mut u8 {
+        let pointer = self.inner.alloc( layout );
+        if !pointer.is_null() {
+            update( |measurement, current_bytes| {
+                measurement.allocations += 1;
+                measurement.allocated_bytes += layout.size() as u64;
+                *current_bytes += layout.size() as i64;
+            });
+        }
+
+        pointer
+    }
+
+    unsafe fn alloc_zeroed( &self, layout: Layout ) -> *mut u8 {
+        let pointer = self.inner.alloc_zeroed( layout );
+        if !pointer.is_null() {
+            update( |measurement, current_bytes| {
+                measurement.allocations += 1;
+                measurement.allocated_bytes += layout.size() as u64;
+                *current_bytes += layout.size() as i64;
+            });
+        }
+
+        pointer
+    }
+
+    unsafe fn dealloc( &self, pointer: *mut u8, layout: Layout ) {
+        self.inner.dealloc( pointer, layout );
+        update( |measurement, current_bytes| {
+            measurement.deallocations += 1;
+            measurement.deallocated_bytes += layout.size() as u64;
+            *current_bytes -= layout.size() as i64;
+        });
+    }
+
+    unsafe fn realloc( &self, pointer: *mut u8, layout: Layout, new_size: usize ) -> *mut u8 {
+        let new_pointer = self.inner.realloc( pointer, layout, new_size );
+        if !new_pointer.is_null() {
+            update( |measurement, current_bytes| {
+                measurement.reallocations += 1;
+                if new_size > layout.size() {
+                    measurement.allocated_bytes += (new_size - layout.size()) as u64;
+                } else {
+                    measurement.deallocated_bytes += (layout.size() - new_size) as u64;
+                }
+
+                *current_bytes += new_size as i64 - layout.size() as i64;
+            });
+        }
+
+        new_pointer
+    }
+}
+
+fn set_active( is_active: bool ) -> State {
+    STATE.with( |cell| {
+        let previous = cell.get();
+        cell.set( State { is_active, .. State::default() } );
+        previous
+    })
+}
+
+/// The counterpart of the profiler's `Measurement` (see `preload/src/measurement.rs`).
+#[repr(C)]
+#[derive(Default)]
+struct RawMeasurement {
+    allocations: u64,
+    reallocations: u64,
+    deallocations: u64,
+    allocated_bytes: u64,
+    deallocated_bytes: u64,
+    peak_bytes: u64
+}
+
+struct Profiler {
+    start: unsafe extern "C" fn() -> c_int,
+    stop: unsafe extern "C" fn( *mut RawMeasurement ) -> c_int
+}
+
+fn profiler() -> Option< Profiler > {
+    unsafe {
+        let start = libc::dlsym( libc::RTLD_DEFAULT, b"memory_profiler_start_measurement\0".as_ptr() as *const c_char );
+        let stop = libc::dlsym( libc::RTLD_DEFAULT, b"memory_profiler_stop_measurement\0".as_ptr() as *const c_char );
+        if start.is_null() || stop.is_null() {
+            return None;
+        }
+
+        Some( Profiler {
+            start: mem::transmute( start ),
+            stop: mem::transmute( stop )
+        })
+    }
+}
+
+/// Whether `libmemory_profiler.so` is preloaded, in which case [`measure`] uses it.
+pub fn is_profiler_loaded() -> bool {
+    profiler().is_some()
+}
+
+thread_local! {
+    static IS_MEASURING_WITH_PROFILER: Cell< bool > = Cell::new( false );
+}
+
+/// Only a single measurement can be made by the profiler at a time.
+static PROFILER_LOCK: AtomicBool = AtomicBool::new( false );
+
+fn measure_with_profiler< R >( profiler: &Profiler, callback: impl FnOnce() -> R ) -> Option< (R, Measurement) > {
+    assert!( !IS_MEASURING_WITH_PROFILER.with( |flag| flag.get() ), "`measure` can't be called recursively" );
+    while PROFILER_LOCK.compare_exchange( false, true, Ordering::Acquire, Ordering::Relaxed ).is_err() {
+        std::thread::sleep( std::time::Duration::from_millis( 1 ) );
+    }
+
+    struct Unlock;
+    impl Drop for Unlock {
+        fn drop( &mut self ) {
+            IS_MEASURING_WITH_PROFILER.with( |flag| flag.set( false ) );
+            PROFILER_LOCK.store( false, Ordering::Release );
+        }
+    }
+
+    IS_MEASURING_WITH_PROFILER.with( |flag| flag.set( true ) );
+    let _unlock = Unlock;
+    if unsafe { (profiler.start)() } != 0 {
+        // The profiler is loaded, but it isn't running.
+        return None;
+    }
+
+    // Makes sure that the measurement is stopped even if the callback panics.
+    struct Stop< 'a >( &'a Profiler, bool );
+    impl< 'a > Drop for Stop< 'a > {
+        fn drop( &mut self ) {
+            if !self.1 {
+                unsafe { ((self.0).stop)( std::ptr::null_mut() ) };
+            }
+        }
+    }
+
+    let mut stop = Stop( profiler, false );
+    let result = callback();
+    stop.1 = true;
+
+    let mut raw = RawMeasurement::default();
+    assert_eq!( unsafe { (profiler.stop)( &mut raw ) }, 0, "the profiler didn't finish the measurement" );
+    Some( (result, Measurement {
+        allocations: raw.allocations,
+        reallocations: raw.reallocations,
+        deallocations: raw.deallocations,
+        allocated_bytes: raw.allocated_bytes,
+        deallocated_bytes: raw.deallocated_bytes,
+        peak_bytes: raw.peak_bytes
+    }))
+}
+
+/// Runs the `callback` and returns its result along with what it has allocated.
+///
+/// If the profiler is preloaded and running it measures what every thread has allocated;
+/// otherwise this is the same as [`measure_current_thread`].
+pub fn measure< R >( callback: impl FnOnce() -> R ) -> (R, Measurement) {
+    if let Some( profiler ) = profiler() {
+        // The callback can only be called once, so whether the profiler is running is checked first.
+        let mut callback = Some( callback );
+        if let Some( (result, measurement) ) = measure_with_profiler( &profiler, || (callback.take().unwrap())() ) {
+            return (result, measurement);
+        }
+
+        return measure_current_thread( callback.take().unwrap() );
+    }
+
+    measure_current_thread( callback )
+}
+
+/// Runs the `callback` and returns its result along with what it has allocated on the current thread.
+///
+/// Panics if the [`TrackingAllocator`] isn't installed as the global allocator, or when called recursively.
+pub fn measure_current_thread< R >( callback: impl FnOnce() -> R ) -> (R, Measurement) {
+    assert!( !STATE.with( |cell| cell.get().is_active ), "`measure` can't be called recursively" );
+
+    // Make sure we're actually installed; otherwise everything would trivially pass.
+    set_active( true );
+    let probe = Box::new( 0_u64 );
+    // The read makes sure the allocation isn't optimized out.
+    unsafe { std::ptr::read_volatile( &*probe ) };
+    drop( probe );
+    let probe = set_active( false );
+    assert!(
+        probe.measurement.allocations > 0,
+        "the `TrackingAllocator` isn't installed as the global allocator; use `#[global_allocator]` to install it"
+    );
+
+    // Makes sure that a panicking callback (e.g. a failed assertion) doesn't break the next measurement.
+    struct Deactivate;
+    impl Drop for Deactivate {
+        fn drop( &mut self ) {
+            set_active( false );
+        }
+    }
+
+    let _guard = Deactivate;
+    set_active( true );
+    let result = callback();
+    let state = set_active( false );
+    (result, state.measurement)
+}
+
+/// Runs the `callback` and panics if it has allocated anything (see [`measure`]).
+pub fn assert_no_allocations< R >( callback: impl FnOnce() -> R ) -> R {
+    let (result, measurement) = measure( callback );
+    assert!( !measurement.has_allocated(), "expected no allocations, got {:?}", measurement );
+    result
+}
+
+/// Runs the `callback` and panics if at any point it had more than `limit` bytes allocated (see [`measure`]).
+pub fn assert_peak_below< R >( limit: u64, callback: impl FnOnce() -> R ) -> R {
+    let (result, measurement) = measure( callback );
+    assert!( measurement.peak_bytes < limit, "expected the peak to be below {} bytes, got {:?}", limit, measurement );
+    result
+}
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator< std::alloc::System > = TrackingAllocator::new( std::alloc::System );
+
+#[test]
+fn test_measure() {
+    let (sum, measurement) = measure( || {
+        let mut vec: Vec< u8 > = Vec::with_capacity( 100 );
+        vec.extend( 0..100 );
+        vec.reserve_exact( 200 );
+        let buffer = vec![ 0_u8; 1000 ];
+        drop( buffer );
+        vec.iter().map( |&value| value as u64 ).sum::< u64 >()
+    });
+
+    assert_eq!( sum, 4950 );
+    assert_eq!( measurement.allocations, 2 );
+    assert_eq!( measurement.reallocations, 1 );
+    assert_eq!( measurement.deallocations, 2 );
+    assert_eq!( measurement.allocated_bytes, 1300 );
+    assert_eq!( measurement.peak_bytes, 1300 );
+    assert_eq!( measurement.net_bytes(), 0 );
+}
+
+#[test]
+fn test_assertions() {
+    let value = assert_no_allocations( || (0..10).sum::< u32 >() );
+    assert_eq!( value, 45 );
+
+    assert_peak_below( 2000, || vec![ 0_u8; 1000 ] );
+    let result = std::panic::catch_unwind( || assert_peak_below( 500, || vec![ 0_u8; 1000 ] ) );
+    assert!( result.is_err() );
+}
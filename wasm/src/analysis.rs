@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::io;
+
+use common::Timestamp;
+use common::timeline::{Timeline, TimelineOperation};
+use memory_profiler_capture::{Capture, Event, Header};
+
+use crate::protocol;
+
+struct Allocation {
+    timestamp: u64,
+    size: u64,
+    deallocation: Option< u64 >
+}
+
+enum Operation {
+    Allocation( usize ),
+    Deallocation( usize ),
+    Reallocation { old: usize, new: usize }
+}
+
+/// The aggregates of a single capture, computed without any of the server's indexes
+/// so that they can be cheaply built in the browser.
+pub struct Analysis {
+    header: Header,
+    timestamp_to_wall_clock: u64,
+    allocations: Vec< Allocation >,
+    operations: Vec< Operation >,
+    allocation_by_pointer: HashMap< u64, usize >,
+    unique_backtrace_count: u64,
+    maximum_backtrace_depth: u32,
+    last_timestamp: u64,
+    lost_chunk_count: u64,
+    lost_event_count: u64
+}
+
+impl Analysis {
+    pub fn new( header: Header ) -> Self {
+        let mut analysis = Analysis {
+            timestamp_to_wall_clock: 0,
+            allocations: Vec::new(),
+            operations: Vec::new(),
+            allocation_by_pointer: HashMap::new(),
+            unique_backtrace_count: 0,
+            maximum_backtrace_depth: 0,
+            last_timestamp: header.initial_timestamp,
+            lost_chunk_count: 0,
+            lost_event_count: 0,
+            header
+        };
+
+        analysis.update_wall_clock( analysis.header.timestamp, analysis.header.wall_clock_secs, analysis.header.wall_clock_nsecs );
+        analysis
+    }
+
+    pub fn load( mut capture: Capture ) -> io::Result< Self > {
+        let mut analysis = Analysis::new( capture.header().clone() );
+        for event in &mut capture {
+            analysis.add_event( event? );
+        }
+
+        analysis.lost_chunk_count = capture.lost_chunk_count();
+        analysis.lost_event_count = capture.lost_event_count();
+        Ok( analysis )
+    }
+
+    pub fn id( &self ) -> &str {
+        &self.header.id
+    }
+
+    fn update_wall_clock( &mut self, timestamp: u64, secs: u64, nsecs: u64 ) {
+        self.timestamp_to_wall_clock = Timestamp::from_timespec( secs, nsecs ).as_usecs().wrapping_sub( timestamp );
+    }
+
+    fn to_wall_clock( &self, timestamp: u64 ) -> Timestamp {
+        Timestamp::from_usecs( timestamp.wrapping_add( self.timestamp_to_wall_clock ) )
+    }
+
+    fn add_allocation( &mut self, timestamp: u64, pointer: u64, size: u64 ) -> usize {
+        let index = self.allocations.len();
+        self.allocations.push( Allocation { timestamp, size, deallocation: None } );
+        self.allocation_by_pointer.insert( pointer, index );
+        index
+    }
+
+    pub fn add_event( &mut self, event: Event ) {
+        match event {
+            Event::Allocation { timestamp, pointer, size, .. } => {
+                self.last_timestamp = timestamp;
+                let index = self.add_allocation( timestamp, pointer, size );
+                self.operations.push( Operation::Allocation( index ) );
+            },
+            Event::Reallocation { timestamp, old_pointer, pointer, size, .. } => {
+                self.last_timestamp = timestamp;
+                let old = self.allocation_by_pointer.remove( &old_pointer );
+                let new = self.add_allocation( timestamp, pointer, size );
+                if let Some( old ) = old {
+                    self.allocations[ old ].deallocation = Some( timestamp );
+                    self.operations.push( Operation::Reallocation { old, new } );
+                } else {
+                    self.operations.push( Operation::Allocation( new ) );
+                }
+            },
+            Event::Deallocation { timestamp, pointer, .. } => {
+                self.last_timestamp = timestamp;
+                if let Some( index ) = self.allocation_by_pointer.remove( &pointer ) {
+                    self.allocations[ index ].deallocation = Some( timestamp );
+                    self.operations.push( Operation::Deallocation( index ) );
+                }
+            },
+            Event::Backtrace { addresses, .. } => {
+                self.unique_backtrace_count += 1;
+                self.maximum_backtrace_depth = self.maximum_backtrace_depth.max( addresses.len() as u32 );
+            },
+            Event::WallClock { timestamp, secs, nsecs } => {
+                self.update_wall_clock( timestamp, secs, nsecs );
+            },
+            _ => {}
+        }
+    }
+
+    fn operation_timestamp( &self, operation: &Operation ) -> u64 {
+        match *operation {
+            Operation::Allocation( index ) => self.allocations[ index ].timestamp,
+            Operation::Deallocation( index ) => self.allocations[ index ].deallocation.unwrap(),
+            Operation::Reallocation { new, .. } => self.allocations[ new ].timestamp
+        }
+    }
+
+    pub fn metadata( &self ) -> protocol::ResponseMetadata {
+        let mut total_allocated = 0;
+        let mut total_allocated_count = 0;
+        let mut total_freed = 0;
+        let mut total_freed_count = 0;
+        let mut current = 0;
+        let mut peak_allocated = 0;
+        let mut peak_allocated_timestamp = self.header.initial_timestamp;
+        for operation in &self.operations {
+            match *operation {
+                Operation::Allocation( index ) => {
+                    total_allocated += self.allocations[ index ].size;
+                    total_allocated_count += 1;
+                },
+                Operation::Deallocation( index ) => {
+                    total_freed += self.allocations[ index ].size;
+                    total_freed_count += 1;
+                },
+                Operation::Reallocation { old, new } => {
+                    total_allocated += self.allocations[ new ].size;
+                    total_allocated_count += 1;
+                    total_freed += self.allocations[ old ].size;
+                    total_freed_count += 1;
+                }
+            }
+
+            current = total_allocated - total_freed;
+            if current > peak_allocated {
+                peak_allocated = current;
+                peak_allocated_timestamp = self.operation_timestamp( operation );
+            }
+        }
+
+        protocol::ResponseMetadata {
+            id: self.header.id.clone(),
+            executable: self.header.executable.clone(),
+            architecture: self.header.architecture.clone(),
+            final_allocated: current,
+            final_allocated_count: total_allocated_count - total_freed_count,
+            runtime: Timestamp::from_usecs( self.last_timestamp.saturating_sub( self.header.initial_timestamp ) ).into(),
+            unique_backtrace_count: self.unique_backtrace_count,
+            maximum_backtrace_depth: self.maximum_backtrace_depth,
+            timestamp: self.to_wall_clock( self.header.initial_timestamp ).into(),
+            total_allocated,
+            total_allocated_count,
+            total_freed,
+            total_freed_count,
+            peak_allocated,
+            peak_allocated_timestamp: self.to_wall_clock( peak_allocated_timestamp ).into(),
+            lost_chunk_count: self.lost_chunk_count,
+            lost_event_count: self.lost_event_count
+        }
+    }
+
+    /// Builds the same per-second timeline as the server does.
+    pub fn timeline( &self ) -> Timeline {
+        let mut timeline = Timeline::default();
+        for operation in &self.operations {
+            let secs = self.to_wall_clock( self.operation_timestamp( operation ) ).as_secs();
+            let operation = match *operation {
+                Operation::Allocation( index ) => {
+                    let allocation = &self.allocations[ index ];
+                    TimelineOperation::Allocation { size: allocation.size, is_leaked: allocation.deallocation.is_none() }
+                },
+                Operation::Deallocation( index ) => {
+                    TimelineOperation::Deallocation { size: self.allocations[ index ].size }
+                },
+                Operation::Reallocation { old, new } => {
+                    let new = &self.allocations[ new ];
+                    TimelineOperation::Reallocation { old_size: self.allocations[ old ].size, new_size: new.size, is_leaked: new.deallocation.is_none() }
+                }
+            };
+
+            timeline.add( secs, operation );
+        }
+
+        timeline
+    }
+}
+
+#[test]
+fn test_timeline() {
+    let mut analysis = Analysis::new( Header {
+        id: "0".to_owned(),
+        pid: 1,
+        executable: "a.out".to_owned(),
+        cmdline: Vec::new(),
+        architecture: "amd64".to_owned(),
+        pointer_size: 8,
+        is_little_endian: true,
+        initial_timestamp: 0,
+        timestamp: 0,
+        wall_clock_secs: 1000,
+        wall_clock_nsecs: 0
+    });
+
+    let allocation = |timestamp: u64, pointer: u64, size: u64| Event::Allocation { timestamp, thread: 1, pointer, size, backtrace: 0, flags: 0 };
+    analysis.add_event( allocation( 100_000, 0x1000, 10 ) );
+    analysis.add_event( allocation( 200_000, 0x2000, 20 ) );
+    analysis.add_event( Event::Deallocation { timestamp: 1_500_000, thread: 1, pointer: 0x1000, backtrace: None } );
+    analysis.add_event( Event::Reallocation { timestamp: 5_000_000, thread: 1, old_pointer: 0x2000, pointer: 0x3000, size: 50, backtrace: 0, flags: 0 } );
+    // An unknown deallocation shouldn't affect anything.
+    analysis.add_event( Event::Deallocation { timestamp: 5_000_000, thread: 1, pointer: 0x4000, backtrace: None } );
+
+    let timeline = analysis.timeline();
+    assert_eq!( timeline.xs, vec![ 1000, 1001, 1002, 1004, 1005 ] );
+    assert_eq!( timeline.allocated_size, vec![ 30, 20, 20, 20, 50 ] );
+    assert_eq!( timeline.allocated_count, vec![ 2, 1, 1, 1, 1 ] );
+    assert_eq!( timeline.size_delta, vec![ 30, -10, 0, 0, 30 ] );
+    assert_eq!( timeline.leaked_size, vec![ 0, 0, 0, 0, 50 ] );
+    assert_eq!( timeline.allocations, vec![ 2, 0, 0, 0, 1 ] );
+    assert_eq!( timeline.deallocations, vec![ 0, 1, 0, 0, 1 ] );
+
+    let metadata = analysis.metadata();
+    assert_eq!( metadata.total_allocated, 80 );
+    assert_eq!( metadata.total_freed, 30 );
+    assert_eq!( metadata.final_allocated, 50 );
+    assert_eq!( metadata.final_allocated_count, 1 );
+    assert_eq!( metadata.peak_allocated, 50 );
+}
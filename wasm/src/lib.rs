@@ -0,0 +1,54 @@
+//! The parts of the analysis which can run entirely in the browser, so that the web UI
+//! can open a local data file without a server.
+
+#[macro_use]
+extern crate serde_derive;
+
+mod analysis;
+mod protocol;
+
+use std::io;
+
+use wasm_bindgen::prelude::*;
+
+use memory_profiler_capture::Capture;
+
+use crate::analysis::Analysis;
+
+#[wasm_bindgen]
+pub struct LocalCapture {
+    analysis: Analysis
+}
+
+#[wasm_bindgen]
+impl LocalCapture {
+    #[wasm_bindgen(constructor)]
+    pub fn new( contents: Vec< u8 > ) -> Result< LocalCapture, JsValue > {
+        let capture = Capture::from_reader( io::Cursor::new( contents ) ).map_err( |error| JsValue::from_str( &error.to_string() ) )?;
+        let analysis = Analysis::load( capture ).map_err( |error| JsValue::from_str( &error.to_string() ) )?;
+        Ok( LocalCapture { analysis } )
+    }
+
+    pub fn id( &self ) -> String {
+        self.analysis.id().to_owned()
+    }
+
+    /// Answers a request to the server's REST API with a JSON response,
+    /// or returns `undefined` if the endpoint isn't supported in the browser.
+    pub fn request( &self, path: &str ) -> Option< String > {
+        let path = path.split( '?' ).next().unwrap();
+        if path == "/list" {
+            return serde_json::to_string( &[ self.analysis.metadata() ] ).ok();
+        }
+
+        let mut components = path.trim_start_matches( '/' ).split( '/' );
+        if components.next() != Some( "data" ) || components.next() != Some( self.analysis.id() ) {
+            return None;
+        }
+
+        match (components.next(), components.next()) {
+            (Some( "timeline" ), None) => serde_json::to_string( &self.analysis.timeline() ).ok(),
+            _ => None
+        }
+    }
+}
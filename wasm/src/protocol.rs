@@ -0,0 +1,40 @@
+//! The subset of the server's metadata which can be computed in the browser; it has to be
+//! kept in sync with `server-core/src/protocol.rs`. The timeline is shared through `common`.
+
+use common::Timestamp;
+
+#[derive(Serialize)]
+pub struct Timeval {
+    pub secs: u64,
+    pub fract_nsecs: u32
+}
+
+impl From< Timestamp > for Timeval {
+    fn from( value: Timestamp ) -> Self {
+        Timeval {
+            secs: value.as_secs(),
+            fract_nsecs: value.fract_nsecs() as _
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ResponseMetadata {
+    pub id: String,
+    pub executable: String,
+    pub architecture: String,
+    pub final_allocated: u64,
+    pub final_allocated_count: u64,
+    pub runtime: Timeval,
+    pub unique_backtrace_count: u64,
+    pub maximum_backtrace_depth: u32,
+    pub timestamp: Timeval,
+    pub total_allocated: u64,
+    pub total_allocated_count: u64,
+    pub total_freed: u64,
+    pub total_freed_count: u64,
+    pub peak_allocated: u64,
+    pub peak_allocated_timestamp: Timeval,
+    pub lost_chunk_count: u64,
+    pub lost_event_count: u64
+}
@@ -6,6 +6,8 @@ import PageDataList from "./PageDataList.js";
 import PageDataOverview from "./PageDataOverview.js";
 import PageDataAllocations from "./PageDataAllocations.js";
 import PageDataAddressSpace from "./PageDataAddressSpace.js";
+import PageDataMaps from "./PageDataMaps.js";
+import PageLive from "./PageLive.js";
 
 export default class App extends React.Component {
     render() {
@@ -21,6 +23,15 @@ export default class App extends React.Component {
                     <Route exact path="/address_space/:id" render={ ({ match, location, history }) => {
                         return <PageDataAddressSpace key="address_space" location={location} history={history} sourceUrl={this.props.sourceUrl} id={match.params.id} />;
                     }} />
+                    <Route exact path="/maps/:id" render={ ({ match, location, history }) => {
+                        return <PageDataMaps key="maps" location={location} sourceUrl={this.props.sourceUrl} id={match.params.id} />;
+                    }} />
+                    <Route exact path="/live" render={ ({ location }) => {
+                        return <PageLive key="live" location={location} sourceUrl={this.props.sourceUrl} />;
+                    }} />
+                    <Route exact path="/live/:id" render={ ({ match, location }) => {
+                        return <PageLive key="live_stream" location={location} sourceUrl={this.props.sourceUrl} id={match.params.id} />;
+                    }} />
                     <Route exact path="/" render={ () => {
                         return <PageDataList key="list" sourceUrl={this.props.sourceUrl} />;
                     }} />
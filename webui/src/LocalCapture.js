@@ -0,0 +1,48 @@
+// Lets the UI open a data file without a server; the WebAssembly module built
+// from `wasm/` loads it and answers the API requests for it in the browser.
+
+let wasm_module = null;
+let local_capture = null;
+
+function load_wasm_module() {
+    if( wasm_module === null ) {
+        wasm_module = new Promise( (resolve, reject) => {
+            const script = document.createElement( "script" );
+            script.src = "wasm/memory_profiler_wasm.js";
+            script.onload = () => {
+                window.wasm_bindgen( "wasm/memory_profiler_wasm_bg.wasm" ).then( () => resolve( window.wasm_bindgen ), reject );
+            };
+            script.onerror = () => {
+                wasm_module = null;
+                reject( new Error( "The WebAssembly module is missing; see the README on how to build it." ) );
+            };
+            document.head.appendChild( script );
+        });
+    }
+
+    return wasm_module;
+}
+
+export function open_local_capture( file ) {
+    return Promise.all( [load_wasm_module(), file.arrayBuffer()] ).then( ([wasm, contents]) => {
+        local_capture = new wasm.LocalCapture( new Uint8Array( contents ) );
+        return local_capture.id();
+    });
+}
+
+const original_fetch = window.fetch.bind( window );
+window.fetch = ( url, options ) => {
+    if( local_capture !== null && typeof url === "string" ) {
+        const path = url.replace( /^[a-z]+:\/\/[^/]+/, "" );
+        if( path === "/list" || path.startsWith( "/data/" + local_capture.id() + "/" ) ) {
+            const body = local_capture.request( path );
+            if( body === undefined ) {
+                return Promise.resolve( new Response( "", { status: 501 } ) );
+            }
+
+            return Promise.resolve( new Response( body, { status: 200, headers: { "Content-Type": "application/json" } } ) );
+        }
+    }
+
+    return original_fetch( url, options );
+};
@@ -189,6 +189,14 @@ const REGEX_FIELD = {
     parse: identity
 };
 
+// The expressions are only validated by the server.
+const EXPRESSION_FIELD = {
+    kind: "entry",
+    validate: function( value ) { return value.trim() !== ""; },
+    format: identity,
+    parse: identity
+};
+
 function fmt_or_percent( formatter ) {
     return function( value ) {
         if( value.endsWith( "%" ) ) {
@@ -260,6 +268,11 @@ const FIELDS = {
         label: "Negative source file regex",
         badge: value => "Sources NOT matching /" + value + "/"
     },
+    filter: {
+        ...EXPRESSION_FIELD,
+        label: "Expression, e.g. size > 4K && lifetime < 1s && function ~ \"parse_\"",
+        badge: value => "Matching " + value
+    },
     backtraces: {
         label: "Backtrace",
         badge: value => "Matching backtrace with ID " + value
@@ -284,6 +297,26 @@ const FIELDS = {
         label: "Max allocations",
         badge: value => "At most " + value + " allocations"
     },
+    group_first_seen_min: {
+        ...DATE_OR_PERCENTAGE_FIELD,
+        label: "First seen after",
+        badge: value => "Group first seen after " + (fmt_date_unix( value ) || value)
+    },
+    group_first_seen_max: {
+        ...DATE_OR_PERCENTAGE_FIELD,
+        label: "First seen before",
+        badge: value => "Group first seen before " + (fmt_date_unix( value ) || value)
+    },
+    group_last_seen_min: {
+        ...DATE_OR_PERCENTAGE_FIELD,
+        label: "Last seen after",
+        badge: value => "Group last seen after " + (fmt_date_unix( value ) || value)
+    },
+    group_last_seen_max: {
+        ...DATE_OR_PERCENTAGE_FIELD,
+        label: "Last seen before",
+        badge: value => "Group last seen before " + (fmt_date_unix( value ) || value)
+    },
     group_leaked_allocations_min: {
         ...POSITIVE_INTEGER_OR_PERCENTAGE_FIELD,
         label: "Min leaked allocations",
@@ -457,8 +490,20 @@ class FilterEditor extends React.Component {
                         <div className="px-2" />
                         {this.field("group_interval_max")}
                     </div>
+                    <div className="d-flex flex-row">
+                        {this.field("group_first_seen_min")}
+                        <div className="px-2" />
+                        {this.field("group_first_seen_max")}
+                        <div className="px-2" />
+                        {this.field("group_last_seen_min")}
+                        <div className="px-2" />
+                        {this.field("group_last_seen_max")}
+                    </div>
 
                 </div>
+                <div title="By expression" className="d-flex">
+                    {this.field("filter")}
+                </div>
                 <div title="Misc" className="d-flex">
                     {this.field("mmaped")}
                     <div className="px-2" />
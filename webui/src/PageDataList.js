@@ -3,9 +3,10 @@ import ReactTable from "react-table";
 import { Button } from "reactstrap";
 import { Link } from "react-router-dom";
 import { fmt_uptime, fmt_size, fmt_date_unix } from "./utils.js";
+import { open_local_capture } from "./LocalCapture.js";
 
 export default class PageDataList extends React.Component {
-    state = { datasets: [] }
+    state = { datasets: [], local_error: null }
 
     componentDidMount() {
         this.updateDatasetList();
@@ -67,13 +68,18 @@ export default class PageDataList extends React.Component {
 
         const data = this.state.datasets;
         return (
-            <div className="PageDataList">
+            <div className="PageDataList" onDragOver={event => event.preventDefault()} onDrop={this.onDrop.bind( this )}>
                 <div className="navbar shadow w-100 px-3 py-2">
                     <div className="w-100 text-center">
                         Currently loaded data
                     </div>
                 </div>
                 <div className="px-4 pt-4">
+                    <p className="text-muted text-center">
+                        Drop a data file here to open it in your browser without a server,
+                        or see the processes which are <Link to="/live">streaming to this server</Link>.
+                    </p>
+                    {this.state.local_error && <p className="text-danger text-center">{this.state.local_error}</p>}
                     <ReactTable
                         columns={columns}
                         data={this.state.datasets}
@@ -93,6 +99,19 @@ export default class PageDataList extends React.Component {
         });
     }
 
+    onDrop( event ) {
+        event.preventDefault();
+        const file = event.dataTransfer.files[ 0 ];
+        if( !file ) {
+            return;
+        }
+
+        this.setState( { local_error: null } );
+        open_local_capture( file )
+            .then( id => { window.location.hash = "#/overview/" + id; } )
+            .catch( error => this.setState( { local_error: "Failed to open " + file.name + ": " + error } ) );
+    }
+
     updateDatasetList() {
         fetch( this.props.sourceUrl + "/list" )
             .then( response => response.json() )
@@ -0,0 +1,78 @@
+import React from "react";
+import Graph from "./Graph.js";
+import { extract_query, create_query, fmt_hex16, fmt_size, fmt_full_size, fmt_uptime_timeval, fmt_date_timeval, format_frame } from "./utils.js";
+
+export default class PageDataMaps extends React.Component {
+    state = {}
+
+    componentDidMount() {
+        const params = extract_query( this.props.location.search );
+        const encoded_body = create_query( params ).toString();
+        const url = (this.props.sourceUrl || "") + "/data/" + this.props.id + "/maps?" + encoded_body;
+        fetch( url )
+            .then( rsp => rsp.json() )
+            .then( json => this.setState( {data: json} ) );
+    }
+
+    renderBacktrace( backtrace ) {
+        return backtrace.map( (frame, index) => format_frame( index, frame ) );
+    }
+
+    render() {
+        const data = this.state.data;
+        if( !data ) {
+            return <div />;
+        }
+
+        const sites = data.sites.map( site => (
+            <tr key={"site_" + site.backtrace_id}>
+                <td title={fmt_full_size( site.length )}>{fmt_size( site.length )}</td>
+                <td>{site.count}</td>
+                <td className="text-monospace small">{this.renderBacktrace( site.backtrace )}</td>
+            </tr>
+        ));
+
+        const regions = data.regions.map( region => (
+            <tr key={"region_" + region.address}>
+                <td className="text-monospace">0x{fmt_hex16( region.address )}</td>
+                <td title={fmt_full_size( region.length )}>{fmt_size( region.length )}</td>
+                <td className="text-monospace">{region.permissions || "?"}{region.was_remapped ? " (remapped)" : ""}</td>
+                <td>{region.is_anonymous === false ? "fd " + region.file_descriptor + " @ " + region.offset : "anonymous"}</td>
+                <td>{fmt_uptime_timeval( region.timestamp_relative )}</td>
+                <td className="text-monospace small">{this.renderBacktrace( region.backtrace )}</td>
+            </tr>
+        ));
+
+        return (
+            <div className="PageDataMaps pt-3 px-4">
+                <h1 className="h2">Memory maps</h1>
+                <p>
+                    {data.total_count} regions ({fmt_size( data.total_length )}) were mapped through <code>mmap</code> at {fmt_date_timeval( data.timestamp )}.
+                </p>
+                <Graph
+                    key="mapped"
+                    title="Mapped memory"
+                    data={data.timeline}
+                    y_accessor="length"
+                    y_label=""
+                    fill={true}
+                    xUnit="unix_timestamp"
+                />
+                <h2 className="h3 pt-3">By backtrace</h2>
+                <table className="table table-sm">
+                    <thead>
+                        <tr><th>Size</th><th>Regions</th><th>Backtrace</th></tr>
+                    </thead>
+                    <tbody>{sites}</tbody>
+                </table>
+                <h2 className="h3 pt-3">Regions</h2>
+                <table className="table table-sm">
+                    <thead>
+                        <tr><th>Address</th><th>Size</th><th>Permissions</th><th>Backing</th><th>Mapped at</th><th>Backtrace</th></tr>
+                    </thead>
+                    <tbody>{regions}</tbody>
+                </table>
+            </div>
+        );
+    }
+}
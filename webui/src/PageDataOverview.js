@@ -99,6 +99,19 @@ export default class PageDataOverview extends React.Component {
                                 <td>Maximum backtrace depth</td>
                                 <td>{this.state.general.maximum_backtrace_depth}</td>
                             </tr>
+                            <tr>
+                                <td>Data quality</td>
+                                <td>{this.state.general.data_quality.rating}</td>
+                            </tr>
+                            {this.state.general.lost_chunk_count > 0 &&
+                                <tr className="text-danger">
+                                    <td>Lost data</td>
+                                    <td>
+                                        The data file is damaged; {this.state.general.lost_chunk_count} chunks
+                                        with {this.state.general.lost_event_count} events were skipped
+                                    </td>
+                                </tr>
+                            }
                         </tbody>
                     </table>
                 </div>
@@ -274,6 +287,7 @@ export default class PageDataOverview extends React.Component {
                             &nbsp;(<a href={prefix + "/export/flamegraph/flame.svg?lifetime=only_leaked"}>flamegraph</a>)
                         </li>
                         <li><Link to={"/address_space/" + this.props.id + "?lifetime=only_not_deallocated_in_current_range&mmaped=no"}>Address space fragmentation</Link></li>
+                        <li><Link to={"/maps/" + this.props.id}>Memory maps</Link></li>
                         <li><a href={(this.props.sourceUrl || "") + "/data/" + this.props.id + "/dynamic_constants_ascii_tree/dynamic_constants_" + this.props.id + ".txt"}>Dynamically allocated constants (as ASCII tree)</a></li>
                         <li><a href={(this.props.sourceUrl || "") + "/data/" + this.props.id + "/dynamic_statics_ascii_tree/dynamic_statics_" + this.props.id + ".txt"}>Dynamically allocated statics (as ASCII tree)</a></li>
                         <li><a href={(this.props.sourceUrl || "") + "/data/" + this.props.id + "/dynamic_constants/dynamic_constants_" + this.props.id + ".json"}>Download dynamically constants (as JSON)</a></li>
@@ -0,0 +1,120 @@
+import React from "react";
+import { Link } from "react-router-dom";
+import Graph from "./Graph.js";
+import { fmt_size, fmt_full_size, fmt_uptime_timeval, fmt_date_timeval, format_frame } from "./utils.js";
+
+const POLL_INTERVAL = 2000;
+
+export default class PageLive extends React.Component {
+    state = {}
+
+    componentDidMount() {
+        this.refresh();
+        this.timer = setInterval( () => this.refresh(), POLL_INTERVAL );
+    }
+
+    componentWillUnmount() {
+        clearInterval( this.timer );
+    }
+
+    componentDidUpdate( prevProps ) {
+        if( prevProps.id !== this.props.id ) {
+            this.setState( {data: null} );
+            this.refresh();
+        }
+    }
+
+    refresh() {
+        const base = (this.props.sourceUrl || "") + "/live";
+        const url = this.props.id ? base + "/" + this.props.id : base;
+        fetch( url )
+            .then( rsp => rsp.json() )
+            .then( json => this.setState( {data: json} ) )
+            .catch( () => {} );
+    }
+
+    renderList( streams ) {
+        const rows = streams.map( stream => (
+            <tr key={"stream_" + stream.id}>
+                <td>{stream.executable.match( /[^/]*$/ )[ 0 ]}</td>
+                <td>{stream.pid}</td>
+                <td>{stream.architecture}</td>
+                <td title={fmt_full_size( stream.received_bytes )}>{fmt_size( stream.received_bytes )}B</td>
+                <td>{stream.is_connected ? "Connected" : "Disconnected"}</td>
+                <td><Link to={"/live/" + stream.id}>Watch</Link></td>
+            </tr>
+        ));
+
+        return (
+            <div className="PageLive pt-3 px-4">
+                <h1 className="h2">Live streams</h1>
+                {streams.length === 0 && <p className="text-muted">No process is streaming to this server; start one with <code>MEMORY_PROFILER_STREAM_TO</code>.</p>}
+                <table className="table table-sm">
+                    <thead>
+                        <tr><th>Binary</th><th>PID</th><th>Architecture</th><th>Received</th><th>Status</th><th /></tr>
+                    </thead>
+                    <tbody>{rows}</tbody>
+                </table>
+            </div>
+        );
+    }
+
+    render() {
+        const data = this.state.data;
+        if( !data ) {
+            return <div />;
+        }
+
+        if( !this.props.id ) {
+            return this.renderList( data );
+        }
+
+        const stream = data.stream;
+        const summary = data.summary;
+        const title = stream.executable.match( /[^/]*$/ )[ 0 ] + " (PID " + stream.pid + ")" + (stream.is_connected ? "" : ", disconnected");
+        if( !summary ) {
+            return (
+                <div className="PageLive pt-3 px-4">
+                    <h1 className="h2">{title}</h1>
+                    <p className="text-muted">Waiting for the data...</p>
+                </div>
+            );
+        }
+
+        const sites = summary.top_sites.map( site => (
+            <tr key={"site_" + site.backtrace_id}>
+                <td title={fmt_full_size( site.live_size )}>{fmt_size( site.live_size )}B</td>
+                <td>{site.live_count}</td>
+                <td className="text-monospace small">{site.backtrace.map( (frame, index) => format_frame( index, frame ) )}</td>
+            </tr>
+        ));
+
+        return (
+            <div className="PageLive pt-3 px-4">
+                <h1 className="h2">{title}</h1>
+                <p>
+                    {summary.live_count} allocations ({fmt_size( summary.live_size )}B) were alive
+                    at {fmt_date_timeval( summary.timestamp )}, after {fmt_uptime_timeval( summary.elapsed )}.
+                    In total {summary.total_allocated_count} allocations ({fmt_size( summary.total_allocated )}B) were made
+                    and {summary.total_freed_count} ({fmt_size( summary.total_freed )}B) were freed.
+                </p>
+                <Graph
+                    key="memory"
+                    title="Memory usage"
+                    data={summary.timeline}
+                    y_accessor="allocated_size"
+                    y_label=""
+                    fill={true}
+                    xUnit="unix_timestamp"
+                />
+                <h2 className="h3 pt-3">Top sites</h2>
+                <table className="table table-sm">
+                    <thead>
+                        <tr><th>Live size</th><th>Live allocations</th><th>Backtrace</th></tr>
+                    </thead>
+                    <tbody>{sites}</tbody>
+                </table>
+            </div>
+        );
+    }
+}